@@ -0,0 +1,78 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Benchmark of midstate computation implementations for the number of midstates supported by
+//! the S9 hash chain (versions rolled in one work).
+
+use bosminer::work::midstate::{self, Compute};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Version bits rolled by the S9 backend
+const VERSION_MASK: u32 = 0x1fffe000;
+/// Midstate counts supported by BM1387 chips
+const MIDSTATE_COUNTS: [usize; 3] = [1, 2, 4];
+
+fn prepare_versions(count: usize) -> Vec<u32> {
+    let base = 0x20000000;
+    let shift = VERSION_MASK.trailing_zeros();
+    (0..count as u32).map(|i| base | (i << shift)).collect()
+}
+
+fn bench_midstate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("midstate");
+    let header = ii_bitcoin::BlockHeader {
+        version: 0x20000000,
+        previous_hash: [0x5a; 32],
+        merkle_root: [0xa5; 32],
+        time: 0x5d8a8a1b,
+        bits: 0x1715b23e,
+        nonce: 0,
+    };
+
+    let implementations: Vec<midstate::DynCompute> = vec![
+        std::sync::Arc::new(midstate::Generic),
+        std::sync::Arc::new(midstate::Interleaved),
+    ];
+    for midstate_count in MIDSTATE_COUNTS.iter().copied() {
+        let versions = prepare_versions(midstate_count);
+        let mut midstates = Vec::with_capacity(midstate_count);
+        for compute in implementations.iter() {
+            group.bench_with_input(
+                BenchmarkId::new(compute.name(), midstate_count),
+                &versions,
+                |b, versions| {
+                    b.iter(|| {
+                        midstates.clear();
+                        compute.compute(black_box(&header), versions, &mut midstates);
+                        midstates.len()
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_midstate);
+criterion_main!(benches);
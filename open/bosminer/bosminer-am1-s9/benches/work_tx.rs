@@ -0,0 +1,124 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Benchmark of the work submission path: works are stored to the work registry and serialized
+//! into (emulated) work TX FIFO in batches that fit into the FIFO after one wait for room.
+
+use bosminer::work;
+use bosminer_am1_s9::bm1387::MidstateCount;
+use bosminer_am1_s9::io::WorkTx;
+use bosminer_am1_s9::null_work;
+use bosminer_am1_s9::registry::WorkRegistry;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+/// Number of works which fit into work TX FIFO with one midstate
+const BATCH_SIZE: usize = 16;
+/// Size of work TX FIFO in words (see `WorkTxFifo::BIGGEST_WORK`)
+const FIFO_SIZE: usize = 200;
+/// Size of `work_id` range for one midstate
+const WORK_ID_COUNT: usize = 65536;
+
+fn prepare_batch() -> Vec<work::Assignment> {
+    (0..BATCH_SIZE as u64).map(null_work::prepare).collect()
+}
+
+/// Emulation of work TX FIFO register
+struct Fifo {
+    words: [u32; FIFO_SIZE],
+    len: usize,
+}
+
+impl Fifo {
+    fn new() -> Self {
+        Self {
+            words: [0; FIFO_SIZE],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, item: u32) {
+        self.words[self.len] = black_box(item);
+        self.len += 1;
+    }
+}
+
+/// Works are cloned to the registry and serialized into temporary buffer which is then written
+/// to the FIFO
+fn submit_cloned(registry: &WorkRegistry, works: Vec<work::Assignment>) -> usize {
+    let midstate_count = MidstateCount::new(1);
+    let mut fifo = Fifo::new();
+    let mut buffer = Vec::new();
+    for work in works {
+        let work_id = registry.store_work(work.clone(), false);
+        WorkTx::serialize_work(midstate_count, &work, work_id, |item| buffer.push(item));
+    }
+    for item in buffer {
+        fifo.write(item);
+    }
+    fifo.len
+}
+
+/// Works are moved to the registry and serialized in place directly into the FIFO
+fn submit_in_place(
+    registry: &WorkRegistry,
+    work_ids: &mut Vec<usize>,
+    mut works: Vec<work::Assignment>,
+) -> usize {
+    let midstate_count = MidstateCount::new(1);
+    let mut fifo = Fifo::new();
+    work_ids.extend(works.drain(..).map(|work| registry.store_work(work, false)));
+    for work_id in work_ids.drain(..) {
+        let work = registry.get_work(work_id).expect("BUG: work not stored");
+        WorkTx::serialize_work(midstate_count, &work, work_id, |item| fifo.write(item));
+    }
+    fifo.len
+}
+
+fn bench_work_tx(c: &mut Criterion) {
+    let mut group = c.benchmark_group("work_tx_batch");
+
+    let registry = WorkRegistry::new(WORK_ID_COUNT);
+    group.bench_function("cloned", |b| {
+        b.iter_batched(
+            prepare_batch,
+            |works| submit_cloned(&registry, works),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let registry = WorkRegistry::new(WORK_ID_COUNT);
+    let mut work_ids = Vec::with_capacity(BATCH_SIZE);
+    group.bench_function("in_place", |b| {
+        b.iter_batched(
+            prepare_batch,
+            |works| submit_in_place(&registry, &mut work_ids, works),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_work_tx);
+criterion_main!(benches);
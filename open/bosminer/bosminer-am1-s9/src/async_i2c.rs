@@ -35,8 +35,8 @@ use tokio::task;
 use embedded_hal::blocking::i2c::{Read, Write};
 use linux_embedded_hal::I2cdev;
 
-use crate::error::{self, ErrorKind};
-use failure::ResultExt;
+use crate::error::{self, ErrorKind, ResultExt};
+use async_trait::async_trait;
 
 use std::convert::AsRef;
 use std::path::Path;
@@ -98,14 +98,21 @@ fn serve_requests(
     Ok(())
 }
 
+/// Raw (non-register based) async I2C transfers. Devices like the voltage controller PIC don't
+/// expose registers, they just consume and produce byte streams.
+#[async_trait]
+pub trait AsyncRawBus: Send + Sync {
+    async fn read(&self, address: u8, num_bytes: usize) -> error::Result<Vec<u8>>;
+
+    async fn write(&self, address: u8, bytes: Vec<u8>) -> error::Result<()>;
+}
+
 /// Clonable async I2C device. I2cDevice is closed when last sender channel is dropped.
 pub struct AsyncI2cDev {
     request_tx: mpsc::UnboundedSender<Request>,
 }
 
-/// TODO: Make this into a trait, then implement different backends.
-/// TODO: Write tests for this and for power controller (fake async I2C with power controller,
-/// check power initialization goes as expected etc., maybe reuse I2C bus from sensors?).
+/// TODO: Write tests for this.
 /// TODO: Reuse traits from `i2c/i2c.rs`
 impl AsyncI2cDev {
     /// Open I2C device
@@ -153,4 +160,15 @@ impl AsyncI2cDev {
     }
 }
 
+#[async_trait]
+impl AsyncRawBus for AsyncI2cDev {
+    async fn read(&self, address: u8, num_bytes: usize) -> error::Result<Vec<u8>> {
+        AsyncI2cDev::read(self, address, num_bytes).await
+    }
+
+    async fn write(&self, address: u8, bytes: Vec<u8>) -> error::Result<()> {
+        AsyncI2cDev::write(self, address, bytes).await
+    }
+}
+
 // Please somebody write tests here
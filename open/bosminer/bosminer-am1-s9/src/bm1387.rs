@@ -37,9 +37,6 @@ use std::default::Default;
 use std::fmt::Debug;
 use std::mem::size_of;
 
-#[allow(dead_code)]
-pub const HASH_COUNTING_REG: u8 = 0x14;
-
 /// Maximum supported baud rate clock divisor
 const MAX_BAUD_CLOCK_DIV: usize = 26;
 
@@ -383,6 +380,29 @@ impl Register for GetAddressReg {
     const REG_NUM: u8 = 0x00;
 }
 
+/// Hash counting number register
+///
+/// The chip counts hashes computed by its cores and the counter overflows when it reaches this
+/// number. The register has been identified in bmminer-mix sources where it is set with respect
+/// to the number of chips on the chain, its exact behavior hasn't been verified.
+#[derive(PackedStruct, Debug, Clone, PartialEq)]
+#[packed_struct(endian = "msb", size_bytes = "4")]
+pub struct HashCountingReg {
+    pub hash_counting_number: u32,
+}
+
+impl HashCountingReg {
+    pub fn new(hash_counting_number: u32) -> Self {
+        Self {
+            hash_counting_number,
+        }
+    }
+}
+
+impl Register for HashCountingReg {
+    const REG_NUM: u8 = 0x14;
+}
+
 /// Describes recognized chip revisions
 #[derive(PrimitiveEnum_u16, Clone, Copy, Debug, PartialEq)]
 pub enum ChipRev {
@@ -619,6 +639,10 @@ impl Register for PllReg {
     const REG_NUM: u8 = 0x0c;
 }
 
+/// Mask of PLL register bits that can be compared after readback. When PLL register is read back,
+/// it is or-ed with 0x8000_0000, not sure why.
+pub const PLL_READBACK_MASK: u32 = 0x7fff_ffff;
+
 // TODO: how to initialize with custom XTAL frequency?
 pub static PRECOMPUTED_PLL: Lazy<Vec<PllFrequency>> =
     Lazy::new(|| PllFrequency::precompute_pll_table(crate::CHIP_OSC_CLK_HZ));
@@ -978,6 +1002,41 @@ mod test {
         assert_eq!(reg.hashrate(), 0x23000000);
     }
 
+    #[test]
+    fn test_hash_counting_reg() {
+        let reg = HashCountingReg::new(0x0012_3456);
+
+        assert_eq!(reg.pack(), [0x00, 0x12, 0x34, 0x56]);
+        assert_eq!(reg.to_reg(), 0x0012_3456);
+        assert_eq!(HashCountingReg::from_reg(0x0012_3456), reg);
+    }
+
+    /// Test that registers survive serialization to register format and back
+    fn check_reg_round_trip<T: Register>(reg: T) {
+        assert_eq!(T::from_reg(reg.to_reg()), reg);
+    }
+
+    #[test]
+    fn test_reg_round_trip() {
+        check_reg_round_trip(HashrateReg { hashrate24: 0x1234 });
+        check_reg_round_trip(HashCountingReg::new(0xdead_beef));
+        check_reg_round_trip(TicketMaskReg::new(256).expect("Cannot build difficulty register"));
+        check_reg_round_trip(
+            MiscCtrlReg::new(false, true, 13, false, true).expect("Cannot build misc register"),
+        );
+        check_reg_round_trip(GetAddressReg {
+            chip_rev: CHIP_REV_BM1387,
+            _reserved1: 0,
+            addr: 0x20,
+        });
+        check_reg_round_trip(PllReg {
+            fbdiv: 0x78,
+            refdiv: 2,
+            postdiv1: 4,
+            postdiv2: 1,
+        });
+    }
+
     /// Test serialization and evaluation of PLL divider
     fn try_one_divider(freq: usize, reg: u32, fbdiv: u8, refdiv: u8, postdiv1: u8, postdiv2: u8) {
         let pll = PllReg {
@@ -24,15 +24,13 @@ use crate::bm1387::{self, ChipAddress, Register};
 use crate::command::Interface as CommandInterface;
 use crate::i2c;
 
-use crate::error::{self, ErrorKind};
-use failure::ResultExt;
-
+use crate::error::{self, ErrorKind, ResultExt};
 use ii_logging::macros::*;
 
 use std::time::Duration;
 
 use ii_async_compat::tokio;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 /// Represents I2C bus that is implemented by sending chip commands
 /// to a particular chip on hashchain.
@@ -87,7 +85,7 @@ impl<T: CommandInterface> Bus<T> {
                 // `&& reg.to_reg() != 0`
                 return Ok(reg);
             }
-            delay_for(Self::BUSY_WAIT_DELAY).await;
+            sleep(Self::BUSY_WAIT_DELAY).await;
         }
         Err(ErrorKind::I2cHashchip(
             "timeout when waiting for I2C response".to_string(),
@@ -101,13 +99,10 @@ impl<T: CommandInterface> Bus<T> {
     /// re-set the `MMEN` flag on chip with temp sensor (thus disabling it
     /// because all work on the chain was with multiple midstates)).
     async fn start(&mut self) -> error::Result<()> {
-        let mut misc = self
-            .command_context
-            .read_one_register::<bm1387::MiscCtrlReg>(self.chip_address)
-            .await?;
-        misc.set_i2c(Some(bm1387::I2cBusSelect::Bottom));
         self.command_context
-            .write_register_readback(self.chip_address, &misc)
+            .modify_register(self.chip_address, |misc: &mut bm1387::MiscCtrlReg| {
+                misc.set_i2c(Some(bm1387::I2cBusSelect::Bottom))
+            })
             .await?;
         self.wait_busy()
             .await
@@ -161,7 +156,7 @@ impl<T: CommandInterface> i2c::AsyncBus for Bus<T> {
                 // looks legit
                 return Ok(cmd_reply.data);
             }
-            delay_for(Bus::<T>::FAIL_TRY_DELAY).await;
+            sleep(Bus::<T>::FAIL_TRY_DELAY).await;
         }
         Err(ErrorKind::I2cHashchip(
             "Hashchip I2C controller keeps reading wrong address/register".to_string(),
@@ -0,0 +1,117 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Description of S9 hardware for the shared Antminer infrastructure and variants of hashboards
+//! compatible with S9 control board
+
+use crate::config;
+
+use bosminer_antminer::board::Model;
+use bosminer_antminer::gpio::{PinInName, PinOutName};
+
+use bosminer_config::schema;
+use bosminer_macros::Schema;
+
+use serde::{Deserialize, Serialize};
+
+/// Antminer S9 with Zynq based control board
+pub struct S9;
+
+impl Model for S9 {
+    const NAME: &'static str = "Antminer S9";
+
+    const HASH_CHAIN_INDEX_MIN: usize = 6;
+    const HASH_CHAIN_INDEX_MAX: usize = 8;
+
+    const CHIPS_ON_CHAIN: usize = 63;
+
+    const FAN_COUNT: usize = 4;
+
+    fn pin_out(name: PinOutName) -> u64 {
+        match name {
+            PinOutName::LEDFrontRed => 943,
+            PinOutName::LEDFrontGreen => 944,
+            PinOutName::Buzzer => 945,
+            PinOutName::Rst(i) => {
+                assert!(i > 0 && i <= 8, "Rst pin {} is out of range", i);
+                888 + (i as u64 - 1)
+            }
+        }
+    }
+
+    fn pin_in(name: PinInName) -> u64 {
+        match name {
+            PinInName::ResetButton => 953,
+            PinInName::IPSelect => 957,
+            PinInName::Plug(i) => {
+                assert!(i > 0 && i <= 8, "Plug pin {} is out of range", i);
+                897 + (i as u64 - 1)
+            }
+        }
+    }
+}
+
+/// Hashboards with BM1387 chips which differ only in chip count and default operating point
+#[derive(Serialize, Deserialize, Schema, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    S9,
+    T9Plus,
+    R4,
+}
+
+impl Variant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::S9 => "S9",
+            Self::T9Plus => "T9+",
+            Self::R4 => "R4",
+        }
+    }
+
+    /// Number of chips on fully working hash chain
+    pub fn chips_on_chain(&self) -> usize {
+        match self {
+            Self::S9 => S9::CHIPS_ON_CHAIN,
+            Self::T9Plus => 54,
+            Self::R4 => 63,
+        }
+    }
+
+    /// Default PLL frequency in MHz
+    pub fn default_frequency(&self) -> f64 {
+        match self {
+            Self::S9 => config::DEFAULT_FREQUENCY_MHZ,
+            Self::T9Plus => 600.0,
+            Self::R4 => 550.0,
+        }
+    }
+
+    /// Default voltage in volts
+    pub fn default_voltage(&self) -> f64 {
+        match self {
+            Self::S9 => config::DEFAULT_VOLTAGE_V,
+            Self::T9Plus => 9.0,
+            Self::R4 => 8.6,
+        }
+    }
+}
@@ -20,20 +20,52 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use ii_cgminer_api::command::{DEVDETAILS, FANS, TEMPCTRL, TEMPS};
+use ii_cgminer_api::command::{
+    ASIC_BOOST, CHIPS, DEVDETAILS, EDIT_POOL, FANS, IDENT, IDENTIFY, LAST_SHUTDOWN, NOTIFY,
+    RESTART_CHAIN, SELF_CHECK, TEMPCTRL, TEMPS, TUNE,
+};
+use ii_cgminer_api::parameter::Parameters;
 use ii_cgminer_api::{command, commands, response};
 
+use ii_async_compat::task;
+use ii_logging::macros::*;
+
+use bosminer::client;
+use bosminer::node::WorkSolver as _;
+use bosminer_config::{ClientDescriptor, ClientUserInfo};
+
 use serde::Serialize;
+use serde_json as json;
 
+use std::fs;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::bm1387::MidstateCount;
+use crate::config;
+use crate::health;
+use crate::led;
 use crate::monitor;
+use crate::power;
+use crate::selfcheck;
 use crate::sensor;
+use crate::shutdown;
+
+/// Name of the driver reported by `devdetails` command
+const DRIVER: &str = "bm1387";
+/// Source of kernel release reported by `devdetails` command
+const KERNEL_RELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+/// Source of host name reported by `ident` command
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+/// Source of MAC address reported by `ident` command
+const MAC_ADDRESS_PATH: &str = "/sys/class/net/eth0/address";
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 #[repr(u32)]
 pub enum StatusCode {
     NotReady = 1,
+    LedUnavailable = 2,
+    ConfigNotSaved = 3,
 }
 
 impl From<StatusCode> for u32 {
@@ -44,12 +76,22 @@ impl From<StatusCode> for u32 {
 
 pub enum ErrorCode {
     NotReady,
+    LedUnavailable,
+    ConfigNotSaved(String),
 }
 
 impl From<ErrorCode> for response::Error {
     fn from(code: ErrorCode) -> Self {
         let (code, msg) = match code {
             ErrorCode::NotReady => (StatusCode::NotReady, "Not ready".to_string()),
+            ErrorCode::LedUnavailable => (
+                StatusCode::LedUnavailable,
+                "Identification LED is not available".to_string(),
+            ),
+            ErrorCode::ConfigNotSaved(reason) => (
+                StatusCode::ConfigNotSaved,
+                format!("Configuration not saved: {}", reason),
+            ),
         };
 
         Self::from_custom_error(code, msg)
@@ -66,6 +108,103 @@ pub struct DevDetailInfo {
     pub chips: u32,
     #[serde(rename = "Cores")]
     pub cores: u32,
+    #[serde(rename = "Stale Solutions")]
+    pub stale_solutions: u32,
+    #[serde(rename = "Max Stale Age")]
+    pub max_stale_age: u32,
+    /// Nonces that couldn't be mapped to any enumerated chip
+    #[serde(rename = "Unknown Chip Nonces")]
+    pub unknown_chip_nonces: u32,
+    /// Solutions received more than once for the same work
+    #[serde(rename = "Duplicate Solutions")]
+    pub duplicate_solutions: u32,
+    /// Number of switches to a new block
+    #[serde(rename = "Job Switches")]
+    pub job_switches: u32,
+    /// Works for old block purged on job switches
+    #[serde(rename = "Purged Works")]
+    pub purged_works: u32,
+    /// Seconds from sending the first work of the last new block to receiving its first solution
+    #[serde(rename = "Job Switch Latency")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_switch_latency: Option<f64>,
+    #[serde(rename = "Max Job Switch Latency")]
+    pub max_job_switch_latency: f64,
+    /// Number of recent solutions of each work kept for duplicate detection (0 means unlimited)
+    #[serde(rename = "Duplicate Window")]
+    pub duplicate_window: u32,
+    /// Corrupted or missing responses on the command bus
+    #[serde(rename = "Command Bus Errors")]
+    pub command_bus_errors: u64,
+    /// Commands retried due to command bus errors
+    #[serde(rename = "Command Retries")]
+    pub command_retries: u64,
+    /// Commands that failed even after retries
+    #[serde(rename = "Command Failures")]
+    pub command_failures: u64,
+    /// Percentage of time the command bus has been busy recently
+    #[serde(rename = "Command Bus Utilization")]
+    pub command_bus_utilization: f64,
+    /// Sensor commands deferred in favour of work commands
+    #[serde(rename = "Deferred Sensor Commands")]
+    pub deferred_sensor_commands: u64,
+    /// Total time in seconds the sensor commands waited for the bus
+    #[serde(rename = "Sensor Wait Time")]
+    pub sensor_wait_time: f64,
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct ChipInfo {
+    #[serde(rename = "Valid")]
+    pub valid: u32,
+    #[serde(rename = "Errors")]
+    pub errors: u32,
+    /// Duplicate solutions (included in errors)
+    #[serde(rename = "Duplicates")]
+    pub duplicates: u32,
+    /// Number of core addresses the chip produced valid nonces from
+    #[serde(rename = "Active Cores")]
+    pub active_cores: u32,
+    /// Nonces don't fit the expected core address range of the chip
+    #[serde(rename = "Address Mismatch")]
+    pub address_mismatch: bool,
+    /// Number of cores that haven't returned any solution since start
+    #[serde(rename = "Silent Cores")]
+    pub silent_cores: u32,
+    /// Hashrate reported by hashrate register of the chip in GH/s
+    #[serde(rename = "Register GHS")]
+    pub register_ghs: Option<f64>,
+    /// Hashrate derived from nonces returned by the chip in GH/s
+    #[serde(rename = "Nonce GHS")]
+    pub nonce_ghs: Option<f64>,
+    /// Nominal hashrate given by chip frequency in GH/s
+    #[serde(rename = "Expected GHS")]
+    pub expected_ghs: Option<f64>,
+    /// Reconciled hashrate of the chip in GH/s
+    #[serde(rename = "Effective GHS")]
+    pub effective_ghs: Option<f64>,
+    /// Register-reported hashrate diverges from the nominal hashrate
+    #[serde(rename = "Hashrate Diverging")]
+    pub hashrate_diverging: bool,
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct SensorInfo {
+    /// Local (PCB) temperature, `None` when the readout failed
+    #[serde(rename = "Local")]
+    pub local: Option<f64>,
+    /// Remote (chip) temperature, `None` when the readout failed
+    #[serde(rename = "Remote")]
+    pub remote: Option<f64>,
+}
+
+impl From<sensor::Temperature> for SensorInfo {
+    fn from(temp: sensor::Temperature) -> Self {
+        Self {
+            local: Option::<f32>::from(temp.local).map(|t| t as f64),
+            remote: Option::<f32>::from(temp.remote).map(|t| t as f64),
+        }
+    }
 }
 
 #[derive(Serialize, PartialEq, Clone, Debug)]
@@ -74,27 +213,155 @@ pub struct TempInfo {
     pub board: f64,
     #[serde(rename = "Chip")]
     pub chip: f64,
+    /// Chip temperature aggregated from all sensors by monitor
+    #[serde(rename = "Chain")]
+    pub chain: Option<f64>,
+    /// Rate of change of chain temperature in degrees Celsius per minute
+    #[serde(rename = "Chain Slope")]
+    pub chain_slope: Option<f64>,
+    #[serde(rename = "Sensors")]
+    pub sensors: Vec<SensorInfo>,
+}
+
+/// Hashchain that can be restarted through the API
+trait RestartableChain {
+    fn hashboard_idx(&self) -> usize;
+    fn request_restart(&self) -> bool;
+    fn restart_status(&self) -> crate::RestartStatus;
+}
+
+impl RestartableChain for crate::Manager {
+    fn hashboard_idx(&self) -> usize {
+        self.hashboard_idx
+    }
+
+    fn request_restart(&self) -> bool {
+        crate::Manager::request_restart(self)
+    }
+
+    fn restart_status(&self) -> crate::RestartStatus {
+        crate::Manager::restart_status(self)
+    }
+}
+
+/// Trigger restart of hashchain selected by `parameter` (if any) and report restart progress
+/// of all `chains`
+fn restart_chain<C: RestartableChain>(
+    chains: &[Arc<C>],
+    chain_statuses: &[monitor::ChainStatus],
+    parameter: Option<&json::Value>,
+) -> command::Result<response::ext::RestartChain> {
+    let parameters = Parameters::new(parameter);
+    let chain = parameters.get_opt::<i32>(0, "chain")?;
+
+    let mut triggered_idx = None;
+    if let Some(chain) = chain {
+        let chain = chains
+            .iter()
+            .find(|candidate| candidate.hashboard_idx() as i32 == chain)
+            .ok_or_else(|| {
+                response::ErrorCode::InvalidParameter("chain".to_string(), chain.to_string())
+            })?;
+        if chain.request_restart() {
+            triggered_idx = Some(chain.hashboard_idx());
+        }
+    }
+
+    Ok(response::ext::RestartChain {
+        list: chains
+            .iter()
+            .enumerate()
+            .map(|(idx, chain)| {
+                let hashboard_idx = chain.hashboard_idx();
+                let status = chain.restart_status();
+                let broken_restarts = chain_statuses
+                    .iter()
+                    .find(|chain| chain.hashboard_idx == hashboard_idx)
+                    .map_or(0, |chain| chain.restart_count as u32);
+                response::ext::ChainRestart {
+                    idx: idx as i32,
+                    id: hashboard_idx as i32,
+                    status: status.to_string(),
+                    triggered: (triggered_idx == Some(hashboard_idx)).into(),
+                    broken_restarts,
+                    error: match status {
+                        crate::RestartStatus::Failed(error) => Some(error),
+                        _ => None,
+                    },
+                }
+            })
+            .collect(),
+    })
+}
+
+/// Miner state served by the custom CGMiner API commands
+pub struct Context {
+    /// Hardware ID of the control board
+    pub serial: String,
+    pub managers: Vec<Arc<crate::Manager>>,
+    pub monitor: Arc<monitor::Monitor>,
+    pub identify_led: Option<Arc<led::IdentifyLed>>,
+    /// Record of the previous termination of the miner
+    pub last_shutdown: Option<shutdown::Record>,
+    pub self_check: Arc<selfcheck::Tracker>,
+    pub client_manager: client::Manager,
+    /// Configuration file where pool changes are persisted
+    pub config_path: Option<String>,
 }
 
 pub struct Handler {
     model: String,
+    kernel: String,
+    /// Hardware ID of the control board
+    serial: String,
     managers: Vec<Arc<crate::Manager>>,
     monitor: Arc<monitor::Monitor>,
+    identify_led: Option<Arc<led::IdentifyLed>>,
+    /// Record of the previous termination of the miner
+    last_shutdown: Option<shutdown::Record>,
+    self_check: Arc<selfcheck::Tracker>,
+    client_manager: client::Manager,
+    /// Configuration file where pool changes are persisted
+    config_path: Option<String>,
 }
 
 impl Handler {
-    pub fn new(
-        model: String,
-        managers: Vec<Arc<crate::Manager>>,
-        monitor: Arc<monitor::Monitor>,
-    ) -> Self {
+    pub fn new(model: String, context: Context) -> Self {
+        let Context {
+            serial,
+            managers,
+            monitor,
+            identify_led,
+            last_shutdown,
+            self_check,
+            client_manager,
+            config_path,
+        } = context;
+        // kernel release is not available e.g. when running outside of Linux
+        let kernel = fs::read_to_string(KERNEL_RELEASE_PATH)
+            .map(|release| release.trim().to_string())
+            .unwrap_or_default();
         Self {
             model,
+            kernel,
+            serial,
             managers,
             monitor,
+            identify_led,
+            last_shutdown,
+            self_check,
+            client_manager,
+            config_path,
         }
     }
 
+    /// Convert optional time of event to CGMiner API time (0 means that event never occurred)
+    fn to_api_time(time: Option<SystemTime>) -> response::Time {
+        time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as response::Time)
+            .unwrap_or(0)
+    }
+
     fn get_monitor_status(&self) -> command::Result<monitor::Status> {
         match self.monitor.status_receiver.borrow().clone() {
             Some(status) => Ok(status),
@@ -109,17 +376,41 @@ impl Handler {
             let mut chip_count = 0;
             let mut voltage = 0.0;
             let mut frequency = 0;
+            let mut stale_solutions = 0;
+            let mut max_stale_age = 0;
+            let mut unknown_chip_nonces = 0;
+            let mut duplicate_solutions = 0;
+            let mut job_switches = 0;
+            let mut purged_works = 0;
+            let mut job_switch_latency = None;
+            let mut max_job_switch_latency = 0.0;
+            let mut command_stats = crate::command::Stats::default();
+            let mut bus_stats = None;
             if let Some(hash_chain) = inner.hash_chain.as_ref() {
                 chip_count = hash_chain.chip_count;
                 voltage = hash_chain.get_voltage().await.as_volts() as f64;
                 frequency = hash_chain.get_frequency().await.avg() as u32;
+                let counter = hash_chain.snapshot_counter().await;
+                stale_solutions = counter.stale_solutions as u32;
+                max_stale_age = counter.max_stale_age as u32;
+                unknown_chip_nonces = counter.unknown_chip_nonces as u32;
+                duplicate_solutions = counter.duplicates as u32;
+                job_switches = counter.job_switches as u32;
+                purged_works = counter.purged_works as u32;
+                job_switch_latency = counter
+                    .job_switch_latency
+                    .map(|latency| latency.as_secs_f64());
+                max_job_switch_latency = counter.max_job_switch_latency.as_secs_f64();
+                command_stats = hash_chain.command_context.stats().await;
+                bus_stats = Some(hash_chain.command_context.bus_stats().await);
             }
+            let duplicate_window = manager.chain_config.duplicate_window.unwrap_or_default() as u32;
             list.push(response::DevDetail {
                 idx: list.len() as i32,
                 name: manager.to_string(),
                 id: manager.hashboard_idx as i32,
-                driver: "".to_string(),
-                kernel: "".to_string(),
+                driver: DRIVER.to_string(),
+                kernel: self.kernel.clone(),
                 model: self.model.clone(),
                 device_path: "".to_string(),
                 info: DevDetailInfo {
@@ -127,6 +418,26 @@ impl Handler {
                     frequency,
                     chips: chip_count as u32,
                     cores: (chip_count * crate::bm1387::NUM_CORES_ON_CHIP) as u32,
+                    stale_solutions,
+                    max_stale_age,
+                    unknown_chip_nonces,
+                    duplicate_solutions,
+                    job_switches,
+                    purged_works,
+                    job_switch_latency,
+                    max_job_switch_latency,
+                    duplicate_window,
+                    command_bus_errors: command_stats.corrupted_responses
+                        + command_stats.missing_responses,
+                    command_retries: command_stats.retries,
+                    command_failures: command_stats.failures,
+                    command_bus_utilization: bus_stats.map_or(0.0, |bus_stats| {
+                        bus_stats.utilization(Instant::now()) * 100.0
+                    }),
+                    deferred_sensor_commands: bus_stats
+                        .map_or(0, |bus_stats| bus_stats.deferred_sensor_commands),
+                    sensor_wait_time: bus_stats
+                        .map_or(0.0, |bus_stats| bus_stats.sensor_wait_time.as_secs_f64()),
                 },
             });
         }
@@ -134,6 +445,44 @@ impl Handler {
         Ok(response::DevDetails { list })
     }
 
+    async fn handle_notify(&self) -> command::Result<response::Notify> {
+        let list = self
+            .managers
+            .iter()
+            .enumerate()
+            .map(|(idx, manager)| {
+                let health = manager.health.snapshot();
+                let reason_not_well = match health.reason {
+                    None => response::NotifyReason::None,
+                    Some(health::Reason::InitFailed) => response::NotifyReason::ThreadFailInit,
+                    Some(health::Reason::CommsError) | Some(health::Reason::CommandBusErrors) => {
+                        response::NotifyReason::DevCommsError
+                    }
+                };
+                response::NotifyInfo {
+                    idx: idx as i32,
+                    name: manager.to_string(),
+                    id: manager.hashboard_idx as i32,
+                    last_well: Self::to_api_time(health.last_well),
+                    last_not_well: Self::to_api_time(health.last_not_well),
+                    reason_not_well,
+                    thread_fail_init: health.init_failures,
+                    thread_zero_hash: 0,
+                    thread_fail_queue: 0,
+                    dev_sick_idle_60s: 0,
+                    dev_dead_idle_600s: 0,
+                    dev_nostart: 0,
+                    dev_over_heat: 0,
+                    dev_thermal_cutoff: 0,
+                    dev_comms_error: health.comms_errors,
+                    dev_throttle: 0,
+                }
+            })
+            .collect();
+
+        Ok(response::Notify { list })
+    }
+
     async fn handle_temp_ctrl(&self) -> command::Result<response::ext::TempCtrl> {
         let config = self.get_monitor_status()?.config;
 
@@ -163,6 +512,14 @@ impl Handler {
     }
 
     async fn handle_temps(&self) -> command::Result<response::ext::Temps<TempInfo>> {
+        // monitor may not have collected the chains yet, raw temperatures are available anyway
+        let chain_statuses = self
+            .monitor
+            .status_receiver
+            .borrow()
+            .as_ref()
+            .map(|status| status.chains.clone())
+            .unwrap_or_default();
         let mut list = vec![];
         for manager in self.managers.iter() {
             let inner = manager.inner.lock().await;
@@ -170,12 +527,35 @@ impl Handler {
                 if let Some(sensor::Temperature { local, remote }) =
                     hash_chain.current_temperature()
                 {
+                    let chain_status = chain_statuses
+                        .iter()
+                        .find(|chain| chain.hashboard_idx == manager.hashboard_idx);
+                    let chain = chain_status.and_then(|chain| match chain.temperature {
+                        monitor::ChainTemperature::Ok(t) => Some(t as f64),
+                        _ => None,
+                    });
+                    let chain_slope = chain_status
+                        .and_then(|chain| chain.temperature_slope)
+                        .map(|slope| slope as f64);
+                    let sensors = chain_status
+                        .map(|chain| {
+                            chain
+                                .sensors
+                                .iter()
+                                .cloned()
+                                .map(SensorInfo::from)
+                                .collect()
+                        })
+                        .unwrap_or_default();
                     list.push(response::ext::Temp {
                         idx: list.len() as i32,
                         id: manager.hashboard_idx as i32,
                         info: TempInfo {
                             board: Option::from(local).unwrap_or(0.0) as f64,
                             chip: Option::from(remote).unwrap_or(0.0) as f64,
+                            chain,
+                            chain_slope,
+                            sensors,
                         },
                     });
                 }
@@ -184,6 +564,399 @@ impl Handler {
         Ok(response::ext::Temps { list: list })
     }
 
+    async fn handle_chips(&self) -> command::Result<response::ext::Chips<ChipInfo>> {
+        let mut list = vec![];
+        for manager in self.managers.iter() {
+            let inner = manager.inner.lock().await;
+            if let Some(hash_chain) = inner.hash_chain.as_ref() {
+                let counter = hash_chain.snapshot_counter().await;
+                let silent_cores = hash_chain.silent_cores();
+                for (chip_idx, chip) in counter.chip.iter().enumerate() {
+                    let hashrate = hash_chain.chip_hashrate(chip_idx);
+                    list.push(response::ext::Chip {
+                        idx: list.len() as i32,
+                        id: manager.hashboard_idx as i32,
+                        chip: chip_idx as i32,
+                        info: ChipInfo {
+                            valid: chip.valid as u32,
+                            errors: chip.errors as u32,
+                            duplicates: chip.duplicates as u32,
+                            active_cores: chip.active_cores() as u32,
+                            address_mismatch: chip.address_mismatch(),
+                            silent_cores: silent_cores
+                                .iter()
+                                .find(|silent| silent.chip == chip_idx)
+                                .map_or(0, |silent| silent.cores.len() as u32),
+                            register_ghs: hashrate
+                                .as_ref()
+                                .and_then(|hashrate| hashrate.register)
+                                .map(|hashrate| hashrate / 1e9),
+                            nonce_ghs: hashrate.as_ref().map(|hashrate| hashrate.nonce / 1e9),
+                            expected_ghs: hashrate.as_ref().map(|hashrate| hashrate.expected / 1e9),
+                            effective_ghs: hashrate
+                                .as_ref()
+                                .map(|hashrate| hashrate.effective / 1e9),
+                            hashrate_diverging: hashrate
+                                .as_ref()
+                                .map_or(false, |hashrate| hashrate.diverging),
+                        },
+                    });
+                }
+            }
+        }
+        Ok(response::ext::Chips { list })
+    }
+
+    /// Request new frequency (in MHz) and/or voltage (in volts) of one hash chain or of all
+    /// hash chains when no chain is specified
+    async fn handle_tune(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::Tune> {
+        let parameters = Parameters::new(parameter);
+        let chain = parameters.get_opt::<i32>(0, "chain")?;
+        let frequency = parameters.get_opt::<f64>(1, "frequency")?;
+        let voltage = parameters.get_opt::<f64>(2, "voltage")?;
+
+        if frequency.is_none() && voltage.is_none() {
+            return Err(response::ErrorCode::MissingParameter("frequency".to_string()).into());
+        }
+        if let Some(frequency) = frequency {
+            if frequency < config::FREQUENCY_MHZ_MIN || frequency > config::FREQUENCY_MHZ_MAX {
+                return Err(response::ErrorCode::InvalidParameter(
+                    "frequency".to_string(),
+                    frequency.to_string(),
+                )
+                .into());
+            }
+        }
+        let voltage_setting = match voltage {
+            Some(voltage) => {
+                if voltage < config::VOLTAGE_V_MIN || voltage > config::VOLTAGE_V_MAX {
+                    return Err(response::ErrorCode::InvalidParameter(
+                        "voltage".to_string(),
+                        voltage.to_string(),
+                    )
+                    .into());
+                }
+                Some(power::Voltage::from_volts(voltage as f32).map_err(|_| {
+                    response::ErrorCode::InvalidParameter(
+                        "voltage".to_string(),
+                        voltage.to_string(),
+                    )
+                })?)
+            }
+            None => None,
+        };
+
+        let managers: Vec<_> = self
+            .managers
+            .iter()
+            .filter(|manager| match chain {
+                Some(chain) => manager.hashboard_idx as i32 == chain,
+                None => true,
+            })
+            .collect();
+        if let Some(chain) = chain {
+            if managers.is_empty() {
+                return Err(response::ErrorCode::InvalidParameter(
+                    "chain".to_string(),
+                    chain.to_string(),
+                )
+                .into());
+            }
+        }
+
+        let mut list = vec![];
+        for manager in managers {
+            manager.request_tuning(crate::TuningRequest {
+                frequency: frequency.map(|frequency| {
+                    crate::FrequencySettings::from_frequency((frequency * 1_000_000.0) as usize)
+                }),
+                voltage: voltage_setting,
+            });
+            list.push(response::ext::ChainTuning {
+                idx: list.len() as i32,
+                id: manager.hashboard_idx as i32,
+                frequency,
+                voltage,
+            });
+        }
+        Ok(response::ext::Tune { list })
+    }
+
+    /// Trigger restart of the specified hash chain and report restart progress of all hash
+    /// chains. Without parameter, only the progress is reported.
+    async fn handle_restart_chain(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::RestartChain> {
+        let chain_statuses = self
+            .monitor
+            .status_receiver
+            .borrow()
+            .as_ref()
+            .map(|status| status.chains.clone())
+            .unwrap_or_default();
+        restart_chain(&self.managers, &chain_statuses, parameter)
+    }
+
+    fn identify_remaining(&self) -> f64 {
+        self.identify_led
+            .as_ref()
+            .and_then(|led| led.remaining())
+            .map_or(0.0, |remaining| remaining.as_secs_f64())
+    }
+
+    /// Blink identification LED for specified number of seconds (zero stops blinking)
+    async fn handle_identify(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::Identify> {
+        let parameters = Parameters::new(parameter);
+        let duration = match parameters.get_opt::<f64>(0, "duration")? {
+            Some(duration) => {
+                if !(0.0..=led::MAX_IDENTIFY_TIME.as_secs_f64()).contains(&duration) {
+                    return Err(response::ErrorCode::InvalidParameter(
+                        "duration".to_string(),
+                        duration.to_string(),
+                    )
+                    .into());
+                }
+                Duration::from_secs_f64(duration)
+            }
+            None => led::DEFAULT_IDENTIFY_TIME,
+        };
+        let identify_led = match self.identify_led.as_ref() {
+            Some(identify_led) => identify_led,
+            None => return Err(ErrorCode::LedUnavailable.into()),
+        };
+
+        identify_led.identify(duration);
+        Ok(response::ext::Identify {
+            remaining: self.identify_remaining(),
+        })
+    }
+
+    /// Switch number of midstates (1, 2 or 4) of all hash chains. Hash chains are restarted in
+    /// the background, so the switch takes effect later. Without parameter, only the current
+    /// number of midstates is reported.
+    async fn handle_asic_boost(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::AsicBoost> {
+        let parameters = Parameters::new(parameter);
+        // the new number of midstates is reported when the switch has been triggered
+        let mut reported_count = self.client_manager.midstate_count();
+
+        let mut switched = false;
+        if let Some(count) = parameters.get_opt::<u32>(0, "midstate_count")? {
+            let midstate_count = match count {
+                1 | 2 | 4 => MidstateCount::new(count as usize),
+                _ => {
+                    return Err(response::ErrorCode::InvalidParameter(
+                        "midstate_count".to_string(),
+                        count.to_string(),
+                    )
+                    .into())
+                }
+            };
+            if midstate_count.to_count() != reported_count {
+                let managers = self.managers.clone();
+                let client_manager = self.client_manager.clone();
+                task::spawn_named("asicboost", async move {
+                    if let Err(e) =
+                        crate::switch_midstate_count(&managers, &client_manager, midstate_count)
+                            .await
+                    {
+                        error!("AsicBoost: switch failed: {}", e);
+                    }
+                });
+                reported_count = midstate_count.to_count();
+                switched = true;
+            }
+        }
+
+        Ok(response::ext::AsicBoost {
+            info: response::ext::AsicBoostInfo {
+                midstate_count: reported_count as u32,
+                switched: switched.into(),
+            },
+        })
+    }
+
+    async fn handle_ident(&self) -> command::Result<response::ext::Ident> {
+        let read_trimmed = |path| {
+            fs::read_to_string(path)
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        Ok(response::ext::Ident {
+            info: response::ext::IdentInfo {
+                hostname: read_trimmed(HOSTNAME_PATH),
+                model: self.model.clone(),
+                serial: self.serial.clone(),
+                mac: read_trimmed(MAC_ADDRESS_PATH),
+                hashboard_serials: self
+                    .managers
+                    .iter()
+                    .filter_map(|manager| manager.get_serial())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                identifying: self.identify_remaining(),
+            },
+        })
+    }
+
+    async fn handle_last_shutdown(&self) -> command::Result<response::ext::LastShutdown> {
+        Ok(response::ext::LastShutdown {
+            list: self
+                .last_shutdown
+                .iter()
+                .map(|record| response::ext::ShutdownInfo {
+                    when: record.timestamp as response::Time,
+                    reason: record.reason.code().to_string(),
+                    exit_code: record.reason.exit_code(),
+                    msg: record.message.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    async fn handle_self_check(&self) -> command::Result<response::ext::SelfCheck> {
+        let report = self.self_check.report();
+        Ok(response::ext::SelfCheck {
+            fans: report.running_fans(),
+            list: report
+                .chains
+                .iter()
+                .enumerate()
+                .map(|(idx, chain)| response::ext::SelfCheckInfo {
+                    idx: idx as i32,
+                    id: chain.hashboard_idx as i32,
+                    serial: chain.serial.clone().unwrap_or_default(),
+                    status: chain.status.to_string(),
+                    chips: chain.chips.unwrap_or(0) as u32,
+                    expected_chips: chain.expected_chips.unwrap_or(0) as u32,
+                    sensors: chain.sensors.unwrap_or(0) as u32,
+                    pic_version: chain
+                        .pic_version
+                        .map(|version| format!("{:#04x}", version))
+                        .unwrap_or_default(),
+                    fpga_version: chain.fpga_version.clone().unwrap_or_default(),
+                    msg: chain.problems().join(", "),
+                })
+                .collect(),
+        })
+    }
+
+    /// Write connection details of the pool which has been created from `original` descriptor to
+    /// the configuration file
+    fn persist_pool(
+        &self,
+        original: &ClientDescriptor,
+        url: Option<String>,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> command::Result<()> {
+        let config_path = self.config_path.as_ref().ok_or_else(|| {
+            ErrorCode::ConfigNotSaved("configuration file is not known".to_string())
+        })?;
+        config::api::Handler::new(config_path)
+            .update::<config::Backend, _>(|backend_config| {
+                let pool = backend_config
+                    .find_pool_mut(original)
+                    .ok_or_else(|| "pool is not present in configuration file".to_string())?;
+                if let Some(url) = url {
+                    pool.url = url;
+                }
+                if let Some(user) = user {
+                    pool.user = user;
+                }
+                if password.is_some() {
+                    pool.password = password;
+                }
+                Ok(())
+            })
+            .map_err(|e| ErrorCode::ConfigNotSaved(e))?;
+        Ok(())
+    }
+
+    /// Change URL, user or password of a pool at runtime. The change is written to the
+    /// configuration file as well when `persist` is set so that it survives restart.
+    async fn handle_edit_pool(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::EditPool> {
+        let parameters = Parameters::new(parameter);
+        let idx = parameters.get::<i32>(0, "pool")?;
+        // empty values passed in CGMiner compatible string keep the current ones
+        let get_value = |position, name| -> command::Result<Option<String>> {
+            Ok(parameters
+                .get_opt::<String>(position, name)?
+                .filter(|value| !value.is_empty()))
+        };
+        let url = get_value(1, "url")?;
+        let user = get_value(2, "user")?;
+        let password = get_value(3, "password")?;
+        let persist = parameters.get_opt::<bool>(4, "persist")?.unwrap_or(false);
+
+        // pools are indexed across all groups in the same way as in `pools` command
+        let mut clients = vec![];
+        for group in self.client_manager.get_groups().await {
+            clients.extend(group.get_clients().await.into_iter());
+        }
+        let client = clients
+            .get(idx as usize)
+            .cloned()
+            .ok_or_else(|| response::ErrorCode::InvalidPoolId(idx, clients.len() as i32 - 1))?;
+
+        let original = client.descriptor().await;
+        let mut descriptor = original.clone();
+        if let Some(url) = url.as_ref() {
+            let edited = ClientDescriptor::create(
+                url.as_str(),
+                &ClientUserInfo::new(descriptor.user.as_str(), None),
+                descriptor.enabled,
+            )
+            .map_err(|_| response::ErrorCode::InvalidParameter("url".to_string(), url.clone()))?;
+            // the protocol determines type of the client which cannot be changed at runtime
+            if edited.protocol.scheme() != original.protocol.scheme() {
+                Err(response::ErrorCode::InvalidParameter(
+                    "url".to_string(),
+                    url.clone(),
+                ))?;
+            }
+            descriptor.protocol = edited.protocol;
+            descriptor.host = edited.host;
+            descriptor.port = edited.port;
+            descriptor.fragment = edited.fragment;
+        }
+        if let Some(user) = user.as_ref() {
+            descriptor.user = user.clone();
+        }
+        if password.is_some() {
+            descriptor.password = password.clone();
+        }
+
+        // save the configuration first so that nothing is changed when it fails
+        if persist {
+            self.persist_pool(&original, url, user, password)?;
+        }
+        let url = descriptor.get_url(true, true, false);
+        client.change_descriptor(descriptor).await;
+        if client.is_enabled() {
+            // reconnect with new connection details
+            let _ = client.try_restart(true);
+        }
+
+        Ok(response::ext::EditPool {
+            idx: idx as usize,
+            url,
+            persisted: persist,
+        })
+    }
+
     async fn handle_fans(&self) -> command::Result<response::ext::Fans> {
         let status = self.get_monitor_status()?;
         let speed = status.fan_speed.map(|speed| speed.to_pwm()).unwrap_or(0);
@@ -206,17 +979,136 @@ impl Handler {
 
 pub fn create_custom_commands(
     backend: Arc<crate::Backend>,
-    managers: Vec<Arc<crate::Manager>>,
-    monitor: Arc<monitor::Monitor>,
+    context: Context,
 ) -> Option<command::Map> {
-    let handler = Arc::new(Handler::new(backend.to_string(), managers, monitor));
+    let handler = Arc::new(Handler::new(backend.to_string(), context));
 
     let custom_commands = commands![
         (DEVDETAILS: ParameterLess -> handler.handle_dev_details),
+        (NOTIFY: ParameterLess -> handler.handle_notify),
         (TEMPCTRL: ParameterLess -> handler.handle_temp_ctrl),
         (TEMPS: ParameterLess -> handler.handle_temps),
-        (FANS: ParameterLess -> handler.handle_fans)
+        (FANS: ParameterLess -> handler.handle_fans),
+        (CHIPS: ParameterLess -> handler.handle_chips),
+        (TUNE: Parameter(None) -> handler.handle_tune; "chain,frequency,voltage"),
+        (RESTART_CHAIN: Parameter(None) -> handler.handle_restart_chain; "chain"),
+        (IDENTIFY: Parameter(None) -> handler.handle_identify; "duration"),
+        (IDENT: ParameterLess -> handler.handle_ident),
+        (LAST_SHUTDOWN: ParameterLess -> handler.handle_last_shutdown),
+        (SELF_CHECK: ParameterLess -> handler.handle_self_check),
+        (EDIT_POOL: Parameter(None) -> handler.handle_edit_pool; "pool,url,user,password,persist"),
+        (ASIC_BOOST: Parameter(None) -> handler.handle_asic_boost; "midstate_count")
     ];
 
     Some(custom_commands)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex as StdMutex;
+
+    /// Hashchain accepting restart requests without any hardware
+    struct TestChain {
+        hashboard_idx: usize,
+        restart_status: StdMutex<crate::RestartStatus>,
+    }
+
+    impl TestChain {
+        fn new(hashboard_idx: usize) -> Arc<Self> {
+            Arc::new(Self {
+                hashboard_idx,
+                restart_status: StdMutex::new(crate::RestartStatus::Idle),
+            })
+        }
+    }
+
+    impl RestartableChain for TestChain {
+        fn hashboard_idx(&self) -> usize {
+            self.hashboard_idx
+        }
+
+        fn request_restart(&self) -> bool {
+            let mut restart_status = self.restart_status.lock().expect("BUG: lock failed");
+            if restart_status.in_progress() {
+                return false;
+            }
+            *restart_status = crate::RestartStatus::Stopping;
+            true
+        }
+
+        fn restart_status(&self) -> crate::RestartStatus {
+            self.restart_status
+                .lock()
+                .expect("BUG: lock failed")
+                .clone()
+        }
+    }
+
+    fn chain_status(hashboard_idx: usize, restart_count: usize) -> monitor::ChainStatus {
+        monitor::ChainStatus {
+            hashboard_idx,
+            start_pending: false,
+            sensors: vec![],
+            temperature: monitor::ChainTemperature::Unknown,
+            restart_count,
+            temperature_slope: None,
+        }
+    }
+
+    #[test]
+    fn test_restart_chain() {
+        let chains = vec![TestChain::new(6), TestChain::new(8)];
+        let chain_statuses = vec![chain_status(8, 2)];
+
+        let response = restart_chain(&chains, &chain_statuses, Some(&json::json!("8")))
+            .expect("BUG: restart failed");
+        assert_eq!(response.list.len(), 2);
+        assert_eq!(response.list[0].id, 6);
+        assert_eq!(response.list[0].status, "Idle");
+        assert_eq!(response.list[0].triggered, response::Bool::N);
+        assert_eq!(response.list[0].broken_restarts, 0);
+        assert_eq!(response.list[1].id, 8);
+        assert_eq!(response.list[1].status, "Stopping");
+        assert_eq!(response.list[1].triggered, response::Bool::Y);
+        assert_eq!(response.list[1].broken_restarts, 2);
+
+        // restart in progress is not triggered again
+        let response = restart_chain(&chains, &chain_statuses, Some(&json::json!(8)))
+            .expect("BUG: restart failed");
+        assert_eq!(response.list[1].triggered, response::Bool::N);
+
+        // failed restart is reported with its error
+        *chains[0].restart_status.lock().expect("BUG: lock failed") =
+            crate::RestartStatus::Failed("no chips".to_string());
+        let response = restart_chain(&chains, &chain_statuses, None).expect("BUG: restart failed");
+        assert_eq!(response.list[0].status, "Failed");
+        assert_eq!(response.list[0].error, Some("no chips".to_string()));
+    }
+
+    #[test]
+    fn test_restart_invalid_chain() {
+        let chains = vec![TestChain::new(6)];
+
+        assert!(restart_chain(&chains, &[], Some(&json::json!("7"))).is_err());
+        assert!(restart_chain(&chains, &[], Some(&json::json!("chain"))).is_err());
+        assert_eq!(chains[0].restart_status(), crate::RestartStatus::Idle);
+    }
+
+    #[test]
+    fn test_restart_missing_chain() {
+        let chains = vec![TestChain::new(6), TestChain::new(7)];
+
+        // only the progress is reported without the parameter
+        for parameter in &[None, Some(json::json!("")), Some(json::Value::Null)] {
+            let response =
+                restart_chain(&chains, &[], parameter.as_ref()).expect("BUG: restart failed");
+            assert_eq!(response.list.len(), 2);
+            assert!(response
+                .list
+                .iter()
+                .all(|chain| chain.triggered == response::Bool::N && chain.status == "Idle"));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Estimation of chip hashrate from hashrate registers of chips
+//!
+//! Every chip reports the rate at which it computes hashes in its hashrate register. The
+//! register is sampled periodically and reconciled with hashrate derived from nonces that
+//! the chip returned in the same period. Chips whose register-reported hashrate diverges from
+//! the nominal hashrate given by their frequency are flagged.
+
+use crate::bm1387;
+
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+/// Period of sampling of hashrate registers
+pub const SAMPLE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Chip is flagged when its register-reported hashrate differs from its nominal hashrate by more
+/// than this percentage
+pub const DIVERGENCE_THRESHOLD: f64 = 20.0;
+
+/// Number of standard deviations of nonce count for which the register-reported hashrate is
+/// still considered consistent with the returned nonces
+const NONCE_TOLERANCE_SIGMA: f64 = 3.0;
+
+/// Number of hashes needed to find one nonce of difficulty 1
+const HASHES_PER_SHARE: f64 = 4_294_967_296.0;
+
+/// Hashrate of one chip in hashes per second
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chip {
+    /// Hashrate reported by hashrate register, `None` when the register readout failed
+    pub register: Option<f64>,
+    /// Hashrate derived from valid nonces returned in the sample period
+    pub nonce: f64,
+    /// Nominal hashrate given by chip frequency
+    pub expected: f64,
+    /// Hashrate that is considered to be the real hashrate of the chip
+    pub effective: f64,
+    /// Register-reported hashrate diverges from the nominal hashrate
+    pub diverging: bool,
+}
+
+impl Chip {
+    /// Reconcile `register` hashrate with `shares` (in difficulty 1 units) found with
+    /// `asic_difficulty` during `period` by chip running at `frequency`
+    pub fn new(
+        register: Option<u64>,
+        shares: usize,
+        asic_difficulty: usize,
+        period: Duration,
+        frequency: usize,
+    ) -> Self {
+        let period = period.as_secs_f64();
+        let register = register.map(|hashrate| hashrate as f64);
+        let nonce = if period > 0.0 {
+            shares as f64 * HASHES_PER_SHARE / period
+        } else {
+            0.0
+        };
+        let expected = (frequency * bm1387::NUM_CORES_ON_CHIP) as f64;
+
+        // The register counts every hash so it is more precise than the statistical estimate
+        // from nonces. Nonces are preferred only when they don't correspond to the register
+        // (e.g. the chip is hashing but its results are lost or wrong).
+        let asic_difficulty = asic_difficulty.max(1) as f64;
+        let effective = match register {
+            Some(register) => {
+                let expected_nonces = register * period / (HASHES_PER_SHARE * asic_difficulty);
+                let nonces = shares as f64 / asic_difficulty;
+                if (nonces - expected_nonces).abs()
+                    <= NONCE_TOLERANCE_SIGMA * expected_nonces.sqrt() + 1.0
+                {
+                    register
+                } else {
+                    nonce
+                }
+            }
+            None => nonce,
+        };
+        let diverging = match register {
+            Some(register) if expected > 0.0 => {
+                (register - expected).abs() * 100.0 / expected > DIVERGENCE_THRESHOLD
+            }
+            _ => false,
+        };
+
+        Self {
+            register,
+            nonce,
+            expected,
+            effective,
+            diverging,
+        }
+    }
+}
+
+/// The most recent hashrate sample of all chips on hash chain
+#[derive(Debug)]
+pub struct Tracker {
+    chips: StdMutex<Vec<Chip>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self {
+            chips: StdMutex::new(vec![]),
+        }
+    }
+
+    /// Replace the previous sample with `chips` and return indices of chips that have just
+    /// started to diverge
+    pub fn update(&self, chips: Vec<Chip>) -> Vec<usize> {
+        let mut last_chips = self.chips.lock().expect("BUG: lock poisoned");
+        let newly_diverging = chips
+            .iter()
+            .enumerate()
+            .filter(|(idx, chip)| {
+                chip.diverging && !last_chips.get(*idx).map_or(false, |last| last.diverging)
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        *last_chips = chips;
+        newly_diverging
+    }
+
+    /// Hashrate of chip `idx` from the last sample, `None` when it hasn't been sampled yet
+    pub fn get(&self, idx: usize) -> Option<Chip> {
+        self.chips
+            .lock()
+            .expect("BUG: lock poisoned")
+            .get(idx)
+            .cloned()
+    }
+
+    /// Total effective hashrate of all chips from the last sample
+    pub fn total_effective(&self) -> f64 {
+        self.chips
+            .lock()
+            .expect("BUG: lock poisoned")
+            .iter()
+            .map(|chip| chip.effective)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FREQUENCY: usize = 650_000_000;
+    const DIFFICULTY: usize = 64;
+    /// Period long enough to tell apart hashrates from nonces
+    const PERIOD: Duration = Duration::from_secs(3600);
+
+    /// Shares in difficulty 1 units found by `hashrate` during `period`
+    fn shares(hashrate: f64, period: Duration) -> usize {
+        let nonces = (hashrate * period.as_secs_f64() / HASHES_PER_SHARE / DIFFICULTY as f64)
+            .round() as usize;
+        nonces * DIFFICULTY
+    }
+
+    #[test]
+    fn test_chip_reconcile() {
+        let expected = (FREQUENCY * bm1387::NUM_CORES_ON_CHIP) as f64;
+
+        // register corresponds to nonces
+        let chip = Chip::new(
+            Some(expected as u64),
+            shares(expected, PERIOD),
+            DIFFICULTY,
+            PERIOD,
+            FREQUENCY,
+        );
+        assert_eq!(chip.effective, expected);
+        assert!(!chip.diverging);
+        assert!((chip.nonce - expected).abs() / expected < 0.05);
+
+        // chip hashes but returns just half of nonces
+        let chip = Chip::new(
+            Some(expected as u64),
+            shares(expected / 2.0, PERIOD),
+            DIFFICULTY,
+            PERIOD,
+            FREQUENCY,
+        );
+        assert_eq!(chip.effective, chip.nonce);
+        assert!(!chip.diverging);
+
+        // register reports much lower hashrate than expected
+        let chip = Chip::new(
+            Some((expected / 2.0) as u64),
+            shares(expected / 2.0, PERIOD),
+            DIFFICULTY,
+            PERIOD,
+            FREQUENCY,
+        );
+        assert_eq!(chip.effective, expected / 2.0);
+        assert!(chip.diverging);
+
+        // failed register readout
+        let chip = Chip::new(
+            None,
+            shares(expected, PERIOD),
+            DIFFICULTY,
+            PERIOD,
+            FREQUENCY,
+        );
+        assert_eq!(chip.effective, chip.nonce);
+        assert!(!chip.diverging);
+    }
+
+    #[test]
+    fn test_tracker_divergence() {
+        let tracker = Tracker::new();
+        assert_eq!(tracker.get(0), None);
+
+        let ok = Chip::new(Some(100), 0, DIFFICULTY, SAMPLE_PERIOD, 1);
+        let diverging = Chip::new(Some(1), 0, DIFFICULTY, SAMPLE_PERIOD, 1);
+        assert!(!ok.diverging);
+        assert!(diverging.diverging);
+
+        assert_eq!(tracker.update(vec![ok.clone(), diverging.clone()]), vec![1]);
+        // divergence is reported only once
+        assert_eq!(
+            tracker.update(vec![diverging.clone(), diverging.clone()]),
+            vec![0]
+        );
+        assert!(tracker
+            .update(vec![diverging.clone(), diverging.clone()])
+            .is_empty());
+        assert_eq!(tracker.get(1), Some(diverging));
+    }
+}
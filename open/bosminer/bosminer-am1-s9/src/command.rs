@@ -36,12 +36,21 @@ use std::time::Duration;
 
 use packed_struct::{PackedStruct, PackedStructSlice};
 
-use futures::lock::Mutex;
+use futures::lock::{Mutex, MutexGuard};
 use ii_async_compat::futures;
+use ii_async_compat::tokio;
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::error::{self, ErrorKind};
-use failure::ResultExt;
+use crate::error::{self, ErrorKind, ResultExt};
+
+/// Delay between reads of individual chips when verifying register of the whole chain. It limits
+/// the load of the command bus which is shared with other tasks (e.g. temperature readout).
+const VERIFY_READ_DELAY: Duration = Duration::from_millis(1);
+
+/// Half-life of busy time accounted into utilization of the command bus
+const UTILIZATION_HALF_LIFE: Duration = Duration::from_secs(60);
 
 /// Interface definition for command-stack API - reading and writing of registers
 ///
@@ -78,6 +87,66 @@ pub trait Interface: Send + Sync {
         return Ok(responses.remove(0));
     }
 
+    /// Alter register of one chip in a read-modify-write cycle so that the settings the caller
+    /// doesn't care about stay intact. The register is read back to verify it was written
+    /// correctly and the new value is returned.
+    ///
+    /// * `chip_address` can be only unicast
+    async fn modify_register<T, F>(&self, chip_address: ChipAddress, modify: F) -> error::Result<T>
+    where
+        T: bm1387::Register,
+        F: FnOnce(&mut T) + Send,
+    {
+        let mut value = self.read_one_register::<T>(chip_address).await?;
+        modify(&mut value);
+        self.write_register_readback(chip_address, &value).await?;
+        Ok(value)
+    }
+
+    /// Read register from each of `chip_count` chips one by one and return addresses of chips
+    /// whose register doesn't match `expected` value (only bits in `mask` are compared) or which
+    /// didn't respond at all.
+    ///
+    /// Broadcast readback cannot tell which chip holds the wrong value (it relies on the order
+    /// of responses) so this is the way to find chips which diverged after a broadcast write.
+    async fn find_diverging_chips<'a, T: bm1387::Register>(
+        &'a self,
+        chip_count: usize,
+        expected: &'a T,
+        mask: u32,
+    ) -> DivergingChips {
+        let expected_value = expected.to_reg() & mask;
+        let mut diverging = DivergingChips::default();
+        for chip in 0..chip_count {
+            if chip > 0 {
+                tokio::time::sleep(VERIFY_READ_DELAY).await;
+            }
+            match self.read_one_register::<T>(ChipAddress::One(chip)).await {
+                Ok(value) if value.to_reg() & mask == expected_value => {}
+                Ok(value) => {
+                    debug!(
+                        "chip {} has diverged value of register {:#x}: {:#x?} instead of {:#x?}",
+                        chip,
+                        T::REG_NUM,
+                        value,
+                        expected
+                    );
+                    diverging.mismatched.push(chip);
+                }
+                Err(e) => {
+                    debug!(
+                        "chip {} failed to read register {:#x}: {}",
+                        chip,
+                        T::REG_NUM,
+                        e
+                    );
+                    diverging.unresponsive.push(chip);
+                }
+            }
+        }
+        diverging
+    }
+
     /// Write register(s) and read it/them back to verify they were written correctly
     /// Same as `write_register`, but followed by `read_register` on the same register.
     async fn write_register_readback<'a, T: bm1387::Register>(
@@ -105,33 +174,186 @@ pub trait Interface: Send + Sync {
     }
 }
 
+/// Chips whose register doesn't hold the expected value
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DivergingChips {
+    /// Chips that responded with a different value
+    pub mismatched: Vec<usize>,
+    /// Chips that haven't responded (the value is unknown)
+    pub unresponsive: Vec<usize>,
+}
+
+impl DivergingChips {
+    pub fn is_empty(&self) -> bool {
+        self.mismatched.is_empty() && self.unresponsive.is_empty()
+    }
+
+    /// All diverging chips in ascending order
+    pub fn all(&self) -> Vec<usize> {
+        let mut chips: Vec<_> = self
+            .mismatched
+            .iter()
+            .chain(self.unresponsive.iter())
+            .cloned()
+            .collect();
+        chips.sort_unstable();
+        chips
+    }
+}
+
+/// Statistics of errors on the command bus (UART between FPGA and chips). Long or damaged cables
+/// tend to cause intermittent corruption of command responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Responses that were malformed (framing error or response that cannot be unpacked)
+    pub corrupted_responses: u64,
+    /// Read commands that haven't received response from all addressed chips
+    pub missing_responses: u64,
+    /// Read commands retried due to one of the errors above
+    pub retries: u64,
+    /// Read commands that failed even after all retries
+    pub failures: u64,
+}
+
+/// Priority of commands sharing the command bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Commands controlling hashing (chip configuration, job switching, hashrate readout, ...)
+    Work,
+    /// Transactions on I2C bus of temperature sensors which is tunneled through chip registers.
+    /// These wait for work commands pending at the time they are issued.
+    Sensor,
+}
+
+/// Utilization of the command bus and accounting of its arbitration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusStats {
+    /// Commands issued with `Priority::Work`
+    pub work_commands: u64,
+    /// Commands issued with `Priority::Sensor`
+    pub sensor_commands: u64,
+    /// Sensor commands that had to wait for pending work commands
+    pub deferred_sensor_commands: u64,
+    /// Total time sensor commands waited for pending work commands
+    pub sensor_wait_time: Duration,
+    /// Total time the bus has been used by any command
+    pub busy_time: Duration,
+    /// Busy time in seconds decayed with `UTILIZATION_HALF_LIFE`
+    recent_busy: f64,
+    /// Elapsed time in seconds decayed with `UTILIZATION_HALF_LIFE`
+    recent_elapsed: f64,
+    /// Time when the recent values were updated for the last time
+    last_update: Instant,
+}
+
+impl BusStats {
+    fn new() -> Self {
+        Self {
+            work_commands: 0,
+            sensor_commands: 0,
+            deferred_sensor_commands: 0,
+            sensor_wait_time: Duration::from_secs(0),
+            busy_time: Duration::from_secs(0),
+            recent_busy: 0.0,
+            recent_elapsed: 0.0,
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Factor of decay of recent values after `elapsed` time
+    fn decay(elapsed: Duration) -> f64 {
+        0.5f64.powf(elapsed.as_secs_f64() / UTILIZATION_HALF_LIFE.as_secs_f64())
+    }
+
+    /// Account the bus held from `acquired` till `now`
+    fn account_busy(&mut self, acquired: Instant, now: Instant) {
+        let busy = now.saturating_duration_since(acquired);
+        let elapsed = now.saturating_duration_since(self.last_update);
+        let decay = Self::decay(elapsed);
+        self.busy_time += busy;
+        self.recent_busy = self.recent_busy * decay + busy.as_secs_f64();
+        self.recent_elapsed = self.recent_elapsed * decay + elapsed.as_secs_f64();
+        self.last_update = now;
+    }
+
+    /// Ratio of time the bus has been busy recently (0.0 - 1.0). Older busy time is weighted
+    /// exponentially less with half-life `UTILIZATION_HALF_LIFE`.
+    pub fn utilization(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        let decay = Self::decay(elapsed);
+        let recent_elapsed = self.recent_elapsed * decay + elapsed.as_secs_f64();
+        if recent_elapsed > 0.0 {
+            (self.recent_busy * decay / recent_elapsed).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
 /// `InnerContext` holds FPGA registers with command FIFO and implements on top
 /// of them functions to issue commands to chip registers (via `send_raw_command`)
 /// or to read/write chip registers (via `Interface` interface).
 ///
 /// No locking for sharing is provided.
-pub struct InnerContext {
+pub struct InnerContext<F = io::CommandRxTxFifos> {
     /// s9-io FPGA registers
-    command_io: io::CommandRxTx,
+    command_io: io::CommandRxTx<F>,
     /// Number of chips on chain - used to verify all replies have been received.
     /// If `chip_count` is `None`, number of chips haven't been determined yet so
     /// skip the check.
     chip_count: Option<usize>,
+    stats: Stats,
+    bus_stats: BusStats,
 }
 
 /// Interface to access chip registers via series of commands
-impl InnerContext {
+impl<F: io::CommandFifoIo> InnerContext<F> {
     /// Timeout for waiting for command
     const COMMAND_READ_TIMEOUT: Duration = Duration::from_millis(100);
 
     /// How long to wait for command RX queue flush
     const COMMAND_FLUSH_TIMEOUT: Duration = Duration::from_micros(5);
 
+    /// Maximal number of attempts to read register when the response is corrupted or missing
+    const MAX_READ_ATTEMPTS: usize = 3;
+
     /// Read register(s)
     ///
+    /// The command is retried (up to `MAX_READ_ATTEMPTS`) when the responses are corrupted or
+    /// some of them are missing. The last error is returned when all attempts fail.
+    async fn read_register<T: bm1387::Register>(
+        &mut self,
+        chip_address: ChipAddress,
+    ) -> error::Result<Vec<T>> {
+        let mut attempt = 1;
+        loop {
+            match self.try_read_register(chip_address).await {
+                Ok(registers) => return Ok(registers),
+                Err(e) if attempt < Self::MAX_READ_ATTEMPTS => {
+                    warn!(
+                        "Retrying read of register {:#x} (attempt {}): {}",
+                        T::REG_NUM,
+                        attempt,
+                        e
+                    );
+                    self.stats.retries += 1;
+                    attempt += 1;
+                    // drop any leftovers of the failed command before the next attempt
+                    self.flush_command_rx().await?;
+                }
+                Err(e) => {
+                    self.stats.failures += 1;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Read register(s) in one attempt
+    ///
     /// Throw an error if unexpected number of replies have been received.
     /// (expected number is one reply per chip)
-    async fn read_register<T: bm1387::Register>(
+    async fn try_read_register<T: bm1387::Register>(
         &mut self,
         chip_address: ChipAddress,
     ) -> error::Result<Vec<T>> {
@@ -144,13 +366,21 @@ impl InnerContext {
         // wait for all responses and collect them
         let mut responses = Vec::new();
         loop {
-            match self
+            let response = self
                 .command_io
                 .recv_response(Self::COMMAND_READ_TIMEOUT)
-                .await?
-            {
+                .await
+                .map_err(|e| {
+                    self.stats.corrupted_responses += 1;
+                    e
+                })?;
+            match response {
                 Some(one_response) => {
                     let one_response = bm1387::CmdResponse::unpack_from_slice(&one_response)
+                        .map_err(|e| {
+                            self.stats.corrupted_responses += 1;
+                            e
+                        })
                         .context(format!("response unpacking failed"))?;
                     responses.push(one_response.value);
                     // exit early if we expect just one response
@@ -168,6 +398,7 @@ impl InnerContext {
             if let Some(chip_count) = self.chip_count {
                 // for broadcast we expect chip_count responses
                 if chip_count != responses.len() {
+                    self.stats.missing_responses += 1;
                     Err(ErrorKind::Hashchip(format!(
                         "Number of responses {} of GetStatusCmd(reg={:#x}) doesn't match chip count {}",
                         responses.len(),
@@ -178,6 +409,7 @@ impl InnerContext {
             }
         } else {
             if responses.len() != 1 {
+                self.stats.missing_responses += 1;
                 Err(ErrorKind::Hashchip(format!(
                     "No response for GetStatusCmd(reg={:#x}) from chip {:?}",
                     T::REG_NUM,
@@ -236,27 +468,83 @@ impl InnerContext {
         self.chip_count = Some(chip_count);
     }
 
-    pub fn new(command_io: io::CommandRxTx) -> Self {
+    pub fn new(command_io: io::CommandRxTx<F>) -> Self {
         Self {
             command_io,
             chip_count: None,
+            stats: Default::default(),
+            bus_stats: BusStats::new(),
         }
     }
 }
 
+/// Admission to the command bus granted according to the priority of the command
+enum Admission<'a> {
+    Work { _guard: RwLockReadGuard<'a, ()> },
+    Sensor { _guard: RwLockWriteGuard<'a, ()> },
+}
+
+/// Exclusive access to the command bus. The time the bus is held is accounted as busy time.
+/// The admission is released after the bus when the guard is dropped.
+struct BusGuard<'a, F> {
+    inner: MutexGuard<'a, InnerContext<F>>,
+    _admission: Admission<'a>,
+    acquired: Instant,
+}
+
+impl<'a, F> std::ops::Deref for BusGuard<'a, F> {
+    type Target = InnerContext<F>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<'a, F> std::ops::DerefMut for BusGuard<'a, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<'a, F> Drop for BusGuard<'a, F> {
+    fn drop(&mut self) {
+        let acquired = self.acquired;
+        self.inner.bus_stats.account_busy(acquired, Instant::now());
+    }
+}
+
 /// Locking wrapper on InnerContext. Implements Interface.
-#[derive(Clone)]
-pub struct Context {
-    inner: Arc<Mutex<InnerContext>>,
+///
+/// Access to the bus is arbitrated by command priority. Work commands are admitted as shared
+/// (read) and sensor commands as exclusive (write) holders of a fair queue. A sensor command
+/// thus waits until all work commands issued before it are done and work commands issued after
+/// it are queued behind it so the temperature readout cannot be starved.
+pub struct Context<F = io::CommandRxTxFifos> {
+    inner: Arc<Mutex<InnerContext<F>>>,
+    /// Queue of commands waiting for admission to the bus
+    admission: Arc<RwLock<()>>,
+    /// Priority of commands issued through this instance of context
+    priority: Priority,
+}
+
+// Implemented manually because derive would require the FIFO to be `Clone`
+impl<F> Clone for Context<F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            admission: self.admission.clone(),
+            priority: self.priority,
+        }
+    }
 }
 
 #[async_trait]
-impl Interface for Context {
+impl<F: io::CommandFifoIo> Interface for Context<F> {
     async fn read_register<T: bm1387::Register>(
         &self,
         chip_address: ChipAddress,
     ) -> error::Result<Vec<T>> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
         inner.read_register::<T>(chip_address).await
     }
 
@@ -265,14 +553,58 @@ impl Interface for Context {
         chip_address: ChipAddress,
         value: &'a T,
     ) -> error::Result<()> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
         inner.write_register(chip_address, value).await
     }
 }
 
-impl Context {
+impl<F: io::CommandFifoIo> Context<F> {
+    /// Acquire the bus with priority of this context
+    async fn lock(&self) -> BusGuard<'_, F> {
+        match self.priority {
+            Priority::Work => {
+                let admission = Admission::Work {
+                    _guard: self.admission.read().await,
+                };
+                let mut inner = self.inner.lock().await;
+                inner.bus_stats.work_commands += 1;
+                BusGuard {
+                    inner,
+                    _admission: admission,
+                    acquired: Instant::now(),
+                }
+            }
+            Priority::Sensor => {
+                let start = Instant::now();
+                let (admission, deferred) = match self.admission.try_write() {
+                    Ok(admission) => (admission, false),
+                    Err(_) => (self.admission.write().await, true),
+                };
+                let mut inner = self.inner.lock().await;
+                inner.bus_stats.sensor_commands += 1;
+                if deferred {
+                    inner.bus_stats.deferred_sensor_commands += 1;
+                    inner.bus_stats.sensor_wait_time += start.elapsed();
+                }
+                BusGuard {
+                    inner,
+                    _admission: Admission::Sensor { _guard: admission },
+                    acquired: Instant::now(),
+                }
+            }
+        }
+    }
+
+    /// Context sharing the same bus which issues commands with sensor priority
+    pub fn sensor_context(&self) -> Self {
+        Self {
+            priority: Priority::Sensor,
+            ..self.clone()
+        }
+    }
+
     pub async fn send_raw_command(&self, cmd: Vec<u8>, wait: bool) {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
         inner.send_raw_command(cmd, wait).await
     }
 
@@ -281,9 +613,209 @@ impl Context {
         inner.set_chip_count(chip_count);
     }
 
-    pub fn new(command_io: io::CommandRxTx) -> Self {
+    /// Statistics of errors on the command bus
+    pub async fn stats(&self) -> Stats {
+        self.inner.lock().await.stats
+    }
+
+    /// Utilization of the command bus
+    pub async fn bus_stats(&self) -> BusStats {
+        self.inner.lock().await.bus_stats
+    }
+
+    pub fn new(command_io: io::CommandRxTx<F>) -> Self {
         Self {
             inner: Arc::new(Mutex::new(InnerContext::new(command_io))),
+            admission: Arc::new(RwLock::new(())),
+            priority: Priority::Work,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Chain of chips emulating just one register
+    struct Chain {
+        /// Register value of each chip, `None` for chip that doesn't respond
+        regs: Vec<Option<u32>>,
+    }
+
+    #[async_trait]
+    impl Interface for Chain {
+        async fn read_register<T: bm1387::Register>(
+            &self,
+            chip_address: ChipAddress,
+        ) -> error::Result<Vec<T>> {
+            match chip_address {
+                ChipAddress::One(chip) => match self.regs[chip] {
+                    Some(value) => Ok(vec![T::from_reg(value)]),
+                    None => Err(ErrorKind::Hashchip("no response".to_string()))?,
+                },
+                ChipAddress::All => panic!("broadcast read shouldn't be used"),
+            }
         }
+
+        async fn write_register<'a, T: bm1387::Register>(
+            &'a self,
+            _chip_address: ChipAddress,
+            _value: &'a T,
+        ) -> error::Result<()> {
+            panic!("register shouldn't be written")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_diverging_chips() {
+        let expected = bm1387::HashrateReg { hashrate24: 0x10 };
+        let chain = Chain {
+            regs: vec![Some(0x10), Some(0x11), None, Some(0x8000_0010)],
+        };
+
+        let diverging = chain.find_diverging_chips(4, &expected, !0).await;
+        assert_eq!(diverging.mismatched, vec![1, 3]);
+        assert_eq!(diverging.unresponsive, vec![2]);
+        assert_eq!(diverging.all(), vec![1, 2, 3]);
+        assert_eq!(
+            chain
+                .find_diverging_chips(4, &expected, bm1387::PLL_READBACK_MASK)
+                .await,
+            DivergingChips {
+                mismatched: vec![1],
+                unresponsive: vec![2],
+            }
+        );
+        assert!(chain
+            .find_diverging_chips(1, &expected, !0)
+            .await
+            .is_empty());
+    }
+
+    /// Build command context for emulated chain with `chip_count` chips
+    async fn mock_context(chip_count: usize) -> (io::mock::Chain, Context<io::mock::Fifo>) {
+        let chain = io::mock::Chain::new(chip_count);
+        let (command_io, _, _) = chain.split(0, bm1387::MidstateCount::new(1));
+        let context = Context::new(command_io);
+        context.set_chip_count(chip_count).await;
+        (chain, context)
+    }
+
+    #[tokio::test]
+    async fn test_read_register_retry() {
+        let (chain, context) = mock_context(4).await;
+
+        // lost response is detected and the read is retried
+        chain.inject_crc_errors(1);
+        let responses = context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .expect("read failed");
+        assert_eq!(responses.len(), 4);
+        assert_eq!(
+            context.stats().await,
+            Stats {
+                missing_responses: 1,
+                retries: 1,
+                ..Default::default()
+            }
+        );
+
+        // truncated response is detected and the read is retried
+        chain.inject_framing_errors(1);
+        context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .expect("read failed");
+        let stats = context.stats().await;
+        assert_eq!(stats.corrupted_responses, 1);
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_register_failure() {
+        let (chain, context) = mock_context(4).await;
+
+        // missing chip
+        chain.set_responding(2, false);
+        assert!(context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .is_err());
+        chain.set_responding(2, true);
+
+        // stalled FIFO
+        chain.set_stalled(true);
+        assert!(context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .is_err());
+
+        assert_eq!(
+            context.stats().await,
+            Stats {
+                corrupted_responses: 0,
+                missing_responses: 6,
+                retries: 4,
+                failures: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bus_arbitration() {
+        let (_chain, context) = mock_context(4).await;
+        let sensor_context = context.sensor_context();
+
+        context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .expect("read failed");
+        sensor_context
+            .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
+            .await
+            .expect("read failed");
+        let bus_stats = context.bus_stats().await;
+        assert_eq!(bus_stats.work_commands, 1);
+        assert_eq!(bus_stats.sensor_commands, 1);
+        assert_eq!(bus_stats.deferred_sensor_commands, 0);
+
+        // sensor command waits for pending work command
+        let pending_work = context.admission.read().await;
+        let hold_time = Duration::from_millis(50);
+        let sensor_read = sensor_context.read_register::<bm1387::GetAddressReg>(ChipAddress::All);
+        let release_work = async move {
+            tokio::time::sleep(hold_time).await;
+            drop(pending_work);
+        };
+        let (result, _) = futures::join!(sensor_read, release_work);
+        result.expect("read failed");
+        let bus_stats = context.bus_stats().await;
+        assert_eq!(bus_stats.sensor_commands, 2);
+        assert_eq!(bus_stats.deferred_sensor_commands, 1);
+        assert!(bus_stats.sensor_wait_time >= hold_time);
+        assert!(bus_stats.utilization(Instant::now()) <= 1.0);
+    }
+
+    #[test]
+    fn test_bus_utilization() {
+        let mut bus_stats = BusStats::new();
+        let start = bus_stats.last_update;
+        let second = Duration::from_secs(1);
+
+        // busy half of the time
+        for i in 0..60 {
+            let acquired = start + second * (2 * i + 1);
+            bus_stats.account_busy(acquired, acquired + second);
+        }
+        let now = start + second * 120;
+        assert!((bus_stats.utilization(now) - 0.5).abs() < 0.01);
+
+        // utilization drops when the bus is idle (unlike the average over the whole lifetime)
+        let now = now + UTILIZATION_HALF_LIFE * 2;
+        let utilization = bus_stats.utilization(now);
+        assert!(utilization > 0.0 && utilization < 0.25);
+        assert_eq!(bus_stats.busy_time, second * 60);
     }
 }
@@ -30,22 +30,34 @@
 use ii_logging::macros::*;
 
 pub mod api;
+pub mod check;
 mod metadata;
+pub mod migration;
 pub mod support;
 
 use crate::bm1387::MidstateCount;
+use crate::board;
+use crate::derate;
 use crate::fan;
+use crate::front_panel;
 use crate::hooks;
 use crate::monitor;
+use crate::open_core;
 use crate::power;
+use crate::sensor;
 use crate::FrequencySettings;
 
 use support::OptionDefault;
 
 use bosminer::client;
+use bosminer::events;
 use bosminer::hal::{self, BackendConfig as _};
+use bosminer::schedule;
 
-use bosminer_config::{ClientDescriptor, ClientUserInfo};
+use bosminer_antminer::board::Model as _;
+use bosminer_config::schema::{self, Schema};
+use bosminer_config::{ClientDescriptor, ClientProtocol, ClientUserInfo};
+use bosminer_macros::Schema;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
@@ -56,10 +68,10 @@ use std::sync::Arc;
 use std::time::Duration;
 
 /// Hardware revision
-pub const HW_MODEL: &'static str = "Antminer S9";
+pub const HW_MODEL: &'static str = board::S9::NAME;
 
 /// Expected configuration version
-const FORMAT_VERSION: &'static str = "1.0";
+const FORMAT_VERSION: &'static str = "1.1";
 
 /// Expected configuration model
 pub const FORMAT_MODEL: &'static str = HW_MODEL;
@@ -74,6 +86,28 @@ pub const DEFAULT_CONFIG_PATH: &'static str = "/etc/bosminer.toml";
 /// Default Hardware ID path
 pub const DEFAULT_HW_ID_PATH: &'static str = "/tmp/miner_hwid";
 
+/// Default compatibility of CGMiner API
+pub const DEFAULT_LEGACY_CGMINER: bool = false;
+
+/// Default log of found blocks
+pub const DEFAULT_BLOCK_LOG_PATH: &'static str = "/var/log/bosminer-blocks.log";
+
+/// Record of the last miner shutdown that is reported after restart (kept in RAM as it is
+/// written on every start)
+pub const DEFAULT_SHUTDOWN_STATE_PATH: &'static str = "/var/run/bosminer-shutdown.json";
+
+/// Hardware self-check report of the running miner
+pub const DEFAULT_SELFCHECK_PATH: &'static str = "/tmp/bosminer-selfcheck.json";
+
+/// Default settings of monitor status history (kept in RAM to spare the flash)
+pub const DEFAULT_MONITOR_HISTORY_PATH: &'static str = "/var/log/bosminer-monitor.log";
+pub const DEFAULT_MONITOR_HISTORY_INTERVAL_S: f64 = 60.0;
+pub const DEFAULT_MONITOR_HISTORY_MAX_SIZE_KB: usize = 256;
+pub const DEFAULT_MONITOR_HISTORY_MAX_FILES: usize = 2;
+
+/// Default value for front panel status LEDs flag
+pub const DEFAULT_FRONT_PANEL_STATUS_LEDS: bool = true;
+
 /// Default value for hash chain enabled flag
 pub const DEFAULT_HASH_CHAIN_ENABLED: bool = true;
 
@@ -92,6 +126,35 @@ pub const DEFAULT_FREQUENCY_MHZ: f64 = 650.0;
 /// Default voltage
 pub const DEFAULT_VOLTAGE_V: f64 = 8.8;
 
+/// Default delay between starts of individual hash chains in seconds
+pub const DEFAULT_CHAIN_START_GAP_S: f64 = 5.0;
+
+/// Default number of restarts of broken hash chain (0 shuts down the miner immediately)
+pub const DEFAULT_BROKEN_CHAIN_RESTARTS: usize = 0;
+
+/// Default minimal delay between restarts of broken hash chain in seconds
+pub const DEFAULT_BROKEN_CHAIN_COOLDOWN_S: f64 = 60.0;
+
+/// Default delay between consecutive open-core works in seconds
+pub const DEFAULT_OPEN_CORE_DELAY_S: f64 = 0.0;
+
+/// Default time in seconds the open-core voltage is held after the last open-core work
+pub const DEFAULT_OPEN_CORE_HOLD_S: f64 = 0.5;
+
+/// Default time in seconds after start when cores without any solution are reported. With
+/// default ASIC difficulty one core finds a nonce every ~2.5 minutes on average so the time has
+/// to be long enough to avoid reporting healthy cores.
+pub const DEFAULT_CORE_CHECK_TIME_S: f64 = 1800.0;
+
+/// Default parameters of hash chain derating on bursts of hardware errors. Error counters are
+/// sampled every minute, three bad samples in a row derate the hash chain and after half an hour
+/// without errors it is probed upward again.
+pub const DEFAULT_DERATE_INTERVAL_S: f64 = 60.0;
+pub const DEFAULT_DERATE_SUSTAIN: usize = 3;
+pub const DEFAULT_DERATE_RECOVERY: usize = 30;
+pub const DEFAULT_DERATE_FREQUENCY_STEP_MHZ: f64 = 25.0;
+pub const DEFAULT_DERATE_VOLTAGE_STEP_V: f64 = 0.1;
+
 /// Default temperature control mode
 pub const DEFAULT_TEMP_CONTROL_MODE: TempControlMode = TempControlMode::Auto;
 
@@ -110,8 +173,13 @@ pub const DEFAULT_MIN_FANS: usize = 1;
 pub const S9_HASHBOARD_INDEX: usize = 8;
 
 /// Range of hash chain index
-pub const HASH_CHAIN_INDEX_MIN: usize = 6;
-pub const HASH_CHAIN_INDEX_MAX: usize = 8;
+pub const HASH_CHAIN_INDEX_MIN: usize = board::S9::HASH_CHAIN_INDEX_MIN;
+pub const HASH_CHAIN_INDEX_MAX: usize = board::S9::HASH_CHAIN_INDEX_MAX;
+
+/// Range of expected number of chips on hash chain (enumeration fails when all addresses are
+/// taken)
+pub const CHIP_COUNT_MIN: usize = 1;
+pub const CHIP_COUNT_MAX: usize = crate::MAX_CHIPS_ON_CHAIN - 1;
 
 /// Range of PLL frequency for clocking the chips in MHz
 pub const FREQUENCY_MHZ_MIN: f64 = 200.0;
@@ -131,7 +199,23 @@ pub const FAN_SPEED_MAX: usize = 100;
 
 /// Range of possible fans
 pub const FANS_MIN: usize = 0;
-pub const FANS_MAX: usize = 4;
+pub const FANS_MAX: usize = board::S9::FAN_COUNT;
+
+/// Range of fan index (fan number)
+pub const FAN_INDEX_MIN: usize = 1;
+pub const FAN_INDEX_MAX: usize = FANS_MAX;
+
+/// Range of target fan RPM (0 excludes the fan from RPM control)
+pub const FAN_RPM_MIN: usize = 0;
+pub const FAN_RPM_MAX: usize = 12000;
+
+/// Range of monitor status history settings
+pub const MONITOR_HISTORY_INTERVAL_S_MIN: f64 = 5.0;
+pub const MONITOR_HISTORY_INTERVAL_S_MAX: f64 = 3600.0;
+pub const MONITOR_HISTORY_MAX_SIZE_KB_MIN: usize = 1;
+pub const MONITOR_HISTORY_MAX_SIZE_KB_MAX: usize = 4096;
+pub const MONITOR_HISTORY_MAX_FILES_MIN: usize = 1;
+pub const MONITOR_HISTORY_MAX_FILES_MAX: usize = 10;
 
 /// Default ASIC difficulty
 pub const DEFAULT_ASIC_DIFFICULTY: usize = 64;
@@ -146,10 +230,56 @@ pub struct ResolvedChainConfig {
     pub midstate_count: MidstateCount,
     pub frequency: FrequencySettings,
     pub voltage: power::Voltage,
+    /// Frequency is set by user and not taken from defaults
+    pub frequency_configured: bool,
+    /// Voltage is set by user and not taken from defaults
+    pub voltage_configured: bool,
     pub enabled: bool,
+    /// Number of recent work items kept in work registry, `None` selects the default based on
+    /// the `work_id` range (which is given by midstate count)
+    pub work_registry_depth: Option<usize>,
+    /// Number of most recent solutions of each work item kept for duplicate detection, `None`
+    /// keeps all solutions of active work
+    pub duplicate_window: Option<usize>,
+    /// Derating of the hashchain on sustained bursts of hardware errors, `None` disables it
+    pub derate: Option<derate::Config>,
+    /// Opening of chip cores and verification of their activity
+    pub open_core: open_core::Config,
+    /// Variant of the hashboard, `None` when it hasn't been configured nor detected yet
+    pub variant: Option<board::Variant>,
+    /// Number of chips expected on the hash chain set by user
+    pub chip_count: Option<usize>,
+    /// Temperature reported by emulated sensor instead of probing sensor chips
+    pub sensor_emulation: Option<sensor::emulated::Trajectory>,
+    /// Serial number of the hashboard set by user
+    pub serial: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+impl ResolvedChainConfig {
+    /// Replace default frequency and voltage with defaults of hashboard `variant`. Values
+    /// configured by user are kept.
+    pub fn apply_variant(&mut self, variant: board::Variant) {
+        self.variant = Some(variant);
+        if !self.frequency_configured {
+            self.frequency = FrequencySettings::from_frequency(
+                (variant.default_frequency() * 1_000_000.0) as usize,
+            );
+        }
+        if !self.voltage_configured {
+            self.voltage = power::Voltage::from_volts(variant.default_voltage() as f32)
+                .expect("BUG: invalid default voltage");
+        }
+    }
+
+    /// Exact number of chips expected on the hash chain. Hashboard variant which hasn't been
+    /// configured is expected to be S9 (variants cannot be told apart by their chip count).
+    pub fn expected_chip_count(&self) -> usize {
+        self.chip_count
+            .unwrap_or_else(|| self.variant.unwrap_or(board::Variant::S9).chips_on_chain())
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Copy, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum TempControlMode {
     Auto,
@@ -167,7 +297,7 @@ impl std::string::ToString for TempControlMode {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
 pub struct Format {
     pub version: String,
     pub model: String,
@@ -177,55 +307,336 @@ pub struct Format {
     pub timestamp: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct HashChainGlobal {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_ASIC_BOOST)]
     pub asic_boost: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_registry_depth: Option<usize>,
+    /// Number of most recent solutions of each work kept for detection of duplicate solutions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_window: Option<usize>,
+    /// Delay between starts of individual hash chains in seconds (0 starts all at once)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_CHAIN_START_GAP_S, minimum = 0)]
+    pub start_gap: Option<f64>,
+    /// Rate of hardware errors in percent which derates the hash chain when it's sustained
+    /// (derating is disabled when not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = 0, maximum = 100)]
+    pub derate_error_rate: Option<f64>,
+    /// Voltage in volts up to which the voltage of derated hash chain can be raised when its
+    /// frequency is already at minimum (voltage is never raised when not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = VOLTAGE_V_MIN, maximum = VOLTAGE_V_MAX)]
+    pub derate_max_voltage: Option<f64>,
+    /// Number of attempts to restart a broken hash chain before the whole miner is shut down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_BROKEN_CHAIN_RESTARTS)]
+    pub broken_chain_restarts: Option<usize>,
+    /// Minimal delay in seconds between restarts of a broken hash chain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_BROKEN_CHAIN_COOLDOWN_S, minimum = 0)]
+    pub broken_chain_cooldown: Option<f64>,
+    /// Number of open-core works sent to chips during initialization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_core_count: Option<usize>,
+    /// Voltage in volts used while chip cores are being opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = VOLTAGE_V_MIN, maximum = VOLTAGE_V_MAX)]
+    pub open_core_voltage: Option<f64>,
+    /// Delay in seconds between consecutive open-core works
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_OPEN_CORE_DELAY_S, minimum = 0)]
+    pub open_core_delay: Option<f64>,
+    /// Time in seconds the open-core voltage is held after the last open-core work
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_OPEN_CORE_HOLD_S, minimum = 0)]
+    pub open_core_hold: Option<f64>,
+    /// Time in seconds after start when cores that haven't returned any solution are reported
+    /// (0 disables the check)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_CORE_CHECK_TIME_S, minimum = 0)]
+    pub core_check_time: Option<f64>,
+    /// Variant of hashboards determining their chip count and default operating point
+    /// (S9 when not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<board::Variant>,
     #[serde(flatten)]
     pub overridable: Option<HashChain>,
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct HashChain {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_HASH_CHAIN_ENABLED)]
     pub enabled: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_FREQUENCY_MHZ,
+        minimum = FREQUENCY_MHZ_MIN,
+        maximum = FREQUENCY_MHZ_MAX
+    )]
     pub frequency: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_VOLTAGE_V, minimum = VOLTAGE_V_MIN, maximum = VOLTAGE_V_MAX)]
     pub voltage: Option<f64>,
+    /// Number of chips expected on the hash chain (given by hashboard variant by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = CHIP_COUNT_MIN, maximum = CHIP_COUNT_MAX)]
+    pub chip_count: Option<usize>,
+    /// Emulate temperature sensor on development boards without sensor chips. Temperature
+    /// follows trajectory given by points `SECONDS:CELSIUS` delimited by commas (e.g.
+    /// `0:40,600:90`). Emulation is refused when a real sensor is found on the hashboard.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensor_emulation: Option<String>,
+    /// Serial number of the hashboard reported by the API (it cannot be read from the hashboard
+    /// itself). It can be set only for individual hash chains.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+}
+
+impl HashChain {
+    fn check(&self, key: &str, diagnostics: &mut check::Diagnostics) {
+        diagnostics.check_range(
+            format!("{}.frequency", key),
+            "frequency",
+            self.frequency,
+            FREQUENCY_MHZ_MIN,
+            FREQUENCY_MHZ_MAX,
+        );
+        diagnostics.check_range(
+            format!("{}.voltage", key),
+            "voltage",
+            self.voltage,
+            VOLTAGE_V_MIN,
+            VOLTAGE_V_MAX,
+        );
+        diagnostics.check_range(
+            format!("{}.chip_count", key),
+            "chip count",
+            self.chip_count,
+            CHIP_COUNT_MIN,
+            CHIP_COUNT_MAX,
+        );
+        if let Some(Err(e)) = self
+            .sensor_emulation
+            .as_ref()
+            .map(|spec| sensor::emulated::Trajectory::parse(spec))
+        {
+            diagnostics.error(
+                format!("{}.sensor_emulation", key),
+                format!("invalid sensor emulation: {}", e),
+            );
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TempSlopeAction {
+    /// Run fans at full speed (hashchains are not throttled)
+    Fans,
+    /// Shutdown the whole miner
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TempControl {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_TEMP_CONTROL_MODE.to_string())]
     mode: Option<TempControlMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_TARGET_TEMP_C,
+        minimum = TEMPERATURE_C_MIN,
+        maximum = TEMPERATURE_C_MAX
+    )]
     target_temp: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_HOT_TEMP_C,
+        minimum = TEMPERATURE_C_MIN,
+        maximum = TEMPERATURE_C_MAX
+    )]
     hot_temp: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_DANGEROUS_TEMP_C,
+        minimum = TEMPERATURE_C_MIN,
+        maximum = TEMPERATURE_C_MAX
+    )]
     dangerous_temp: Option<f64>,
+    /// Maximal rise of chip temperature in degrees Celsius per minute (detection of failed
+    /// cooling is disabled when not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_temp_slope: Option<f64>,
+    /// What to do when temperature rises faster than `max_temp_slope`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temp_slope_action: Option<TempSlopeAction>,
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct FanControl {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_FAN_SPEED, minimum = FAN_SPEED_MIN, maximum = FAN_SPEED_MAX)]
     speed: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_MIN_FANS, minimum = FANS_MIN, maximum = FANS_MAX)]
     min_fans: Option<usize>,
+    /// Target RPM of all connected fans. Fan speed is then controlled by RPM feedback instead of
+    /// being fixed to `speed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = FAN_RPM_MIN, maximum = FAN_RPM_MAX)]
+    target_rpm: Option<usize>,
+    /// Per-fan overrides indexed by fan number
+    #[serde(rename = "fan")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fans: Option<BTreeMap<String, Fan>>,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+impl FanControl {
+    /// Target RPM of each fan (indexed from 0) or `None` when RPM control is not configured
+    fn rpm_targets(&self) -> Option<fan::rpm::Targets> {
+        let targets: fan::rpm::Targets = (FAN_INDEX_MIN..=FAN_INDEX_MAX)
+            .map(|fan_idx| {
+                self.fans
+                    .as_ref()
+                    .and_then(|m| m.get(&fan_idx.to_string()))
+                    .and_then(|fan| fan.target_rpm)
+                    .or(self.target_rpm)
+                    .filter(|target_rpm| *target_rpm > 0)
+            })
+            .collect();
+        if targets.iter().any(Option::is_some) {
+            Some(targets)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Fan {
+    /// Target RPM of this fan (0 excludes the fan from RPM control)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = FAN_RPM_MIN, maximum = FAN_RPM_MAX)]
+    target_rpm: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Schema, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorHistoryFormat {
+    Csv,
+    Json,
+}
+
+/// Periodic recording of temperatures, fan speed and control decisions into a rotating file
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorHistory {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_MONITOR_HISTORY_PATH)]
+    path: Option<String>,
+    /// Format of records (`csv` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<MonitorHistoryFormat>,
+    /// Minimal time in seconds between two records (warnings and shutdowns are recorded
+    /// immediately)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_MONITOR_HISTORY_INTERVAL_S,
+        minimum = MONITOR_HISTORY_INTERVAL_S_MIN,
+        maximum = MONITOR_HISTORY_INTERVAL_S_MAX
+    )]
+    interval: Option<f64>,
+    /// Size of the file in KiB after which it is rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_MONITOR_HISTORY_MAX_SIZE_KB,
+        minimum = MONITOR_HISTORY_MAX_SIZE_KB_MIN,
+        maximum = MONITOR_HISTORY_MAX_SIZE_KB_MAX
+    )]
+    max_size: Option<usize>,
+    /// Number of kept files including the current one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = DEFAULT_MONITOR_HISTORY_MAX_FILES,
+        minimum = MONITOR_HISTORY_MAX_FILES_MIN,
+        maximum = MONITOR_HISTORY_MAX_FILES_MAX
+    )]
+    max_files: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Schema, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    None,
+    Identify,
+    RestartPools,
+    Restart,
+}
+
+impl From<ButtonAction> for front_panel::Action {
+    fn from(action: ButtonAction) -> Self {
+        match action {
+            ButtonAction::None => Self::None,
+            ButtonAction::Identify => Self::Identify,
+            ButtonAction::RestartPools => Self::RestartPools,
+            ButtonAction::Restart => Self::Restart,
+        }
+    }
+}
+
+/// LEDs and buttons on the front panel of the control board
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FrontPanel {
+    /// Reflect the state of the miner with front LEDs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_FRONT_PANEL_STATUS_LEDS)]
+    status_leds: Option<bool>,
+    /// Action triggered by the reset button (`none` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reset_button: Option<ButtonAction>,
+    /// Action triggered by the IP report button (`none` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_report_button: Option<ButtonAction>,
+}
+
+#[derive(Serialize, Deserialize, Schema, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Api {
+    /// Render timestamps and status fields exactly as the legacy CGMiner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = DEFAULT_LEGACY_CGMINER)]
+    pub legacy_cgminer: Option<bool>,
+    /// Origins of web pages (e.g. `http://dashboard.local:8080`) allowed to use the WebSocket
+    /// API besides pages served by the miner itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub websocket_origins: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Schema, Default, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Backend {
     #[serde(skip)]
     pub info: hal::BackendInfo,
     #[serde(skip)]
     pub client_manager: Option<client::Manager>,
+    #[serde(skip)]
+    pub event_bus: Option<Arc<events::Bus>>,
+    #[serde(skip)]
+    pub scheduler: Option<Arc<schedule::Scheduler>>,
+    /// Path of the configuration file the backend has been loaded from
+    #[serde(skip)]
+    pub config_path: Option<String>,
     // TODO: merge pools and clients
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hash_chain_global: Option<HashChainGlobal>,
@@ -238,6 +649,28 @@ pub struct Backend {
     temp_control: Option<TempControl>,
     #[serde(skip_serializing_if = "Option::is_none")]
     fan_control: Option<FanControl>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monitor_history: Option<MonitorHistory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    front_panel: Option<FrontPanel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_found: Option<bosminer_config::BlockFoundConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pool_health: Option<bosminer_config::PoolHealthConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    share_watchdog: Option<bosminer_config::ShareWatchdogConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<bosminer_config::EventsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<bosminer_config::ScheduleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee: Option<bosminer_config::FeeConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    telemetry: Option<bosminer_config::TelemetryConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug: Option<bosminer_config::DebugConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api: Option<Api>,
     #[serde(rename = "group")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groups: Option<Vec<bosminer_config::GroupConfig>>,
@@ -249,7 +682,7 @@ pub struct Backend {
 
 pub trait ConfigBody
 where
-    Self: Serialize + DeserializeOwned + Default + fmt::Debug,
+    Self: Serialize + DeserializeOwned + Schema + Default + fmt::Debug,
 {
     fn model() -> String;
 
@@ -257,7 +690,17 @@ where
 
     fn version_is_supported(version: &str) -> bool;
 
-    fn sanity_check(&self) -> Result<(), String>;
+    /// Migrations upgrading older configuration formats to the current `version`
+    fn migrations() -> &'static [migration::Migration];
+
+    /// Report all problems found in the configuration
+    fn check(&self, diagnostics: &mut check::Diagnostics);
+
+    fn sanity_check(&self) -> Result<(), String> {
+        let mut diagnostics = check::Diagnostics::new();
+        self.check(&mut diagnostics);
+        diagnostics.into_result()
+    }
 
     fn metadata() -> serde_json::Value;
 
@@ -286,7 +729,7 @@ impl<B> fmt::Display for FormatWrapperError<B> {
 
 impl<B: fmt::Debug> std::error::Error for FormatWrapperError<B> {}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Schema, Debug)]
 pub struct FormatWrapper<B> {
     format: Format,
     #[serde(flatten)]
@@ -319,16 +762,64 @@ where
         Ok(())
     }
 
+    /// Deserialize configuration layers without any sanity check
+    pub fn deserialize(layers: bosminer_config::Layers) -> Result<Self, FormatWrapperError<B>> {
+        layers
+            .try_into()
+            .map_err(|msg| FormatWrapperError::ParsingError(msg))
+    }
+
+    /// Validate whole configuration and report all problems found
+    pub fn check(&self) -> check::Diagnostics {
+        let mut diagnostics = check::Diagnostics::new();
+        if self.format.model != B::model() {
+            diagnostics.error(
+                "format.model",
+                format!("incompatible format model '{}'", self.format.model),
+            );
+        }
+        if !B::version_is_supported(&self.format.version) {
+            diagnostics.error(
+                "format.version",
+                format!("incompatible format version '{}'", self.format.version),
+            );
+        }
+        self.body.check(&mut diagnostics);
+        diagnostics
+    }
+
     pub fn metadata() -> serde_json::Value {
         // TODO: format-related metadata are for now stored within backend metadata, so move them
         // here and just prepend them to whatever backend returns us
         B::metadata()
     }
 
+    /// JSON schema of the whole configuration file generated from configuration structures
+    pub fn json_schema() -> serde_json::Value {
+        let mut schema = <Self as Schema>::schema();
+        schema["$schema"] = schema::DRAFT.into();
+        schema["title"] = format!("{} configuration", B::model()).into();
+        schema
+    }
+
+    /// Read config file and upgrade it to the current format version
+    pub fn load(config_path: &str) -> Result<migration::Migrated, FormatWrapperError<B>> {
+        migration::Migrated::load(config_path, B::migrations())
+            .map_err(|msg| FormatWrapperError::ParsingError(msg))
+    }
+
     pub fn parse(config_path: &str) -> Result<Self, FormatWrapperError<B>> {
         // Parse config file - either user specified or the default one
-        let mut config: Self = bosminer_config::parse(config_path)
+        let layers = Self::load(config_path)?
+            .layers()
             .map_err(|msg| FormatWrapperError::ParsingError(msg))?;
+        Self::from_layers(layers)
+    }
+
+    /// Build effective configuration from configuration file merged with environment variables
+    /// and command line overrides
+    pub fn from_layers(layers: bosminer_config::Layers) -> Result<Self, FormatWrapperError<B>> {
+        let mut config = Self::deserialize(layers)?;
 
         match config.sanity_check() {
             Ok(_) => Ok(config),
@@ -345,6 +836,29 @@ impl Backend {
         self.groups.as_ref().map(|v| !v.is_empty()).unwrap_or(false)
     }
 
+    /// Find configuration of the pool which has been used to create the client with
+    /// `descriptor`
+    pub fn find_pool_mut(
+        &mut self,
+        descriptor: &ClientDescriptor,
+    ) -> Option<&mut bosminer_config::PoolConfig> {
+        let full_url = descriptor.get_full_url();
+        self.groups
+            .iter_mut()
+            .flatten()
+            .filter_map(|group| group.pools.as_mut())
+            .flatten()
+            .find(|pool| {
+                ClientDescriptor::create(
+                    pool.url.as_str(),
+                    &ClientUserInfo::new(pool.user.as_str(), None),
+                    true,
+                )
+                .map(|pool_descriptor| pool_descriptor.get_full_url() == full_url)
+                .unwrap_or(false)
+            })
+    }
+
     pub fn has_pools(&self) -> bool {
         match &self.groups {
             Some(groups) => groups
@@ -368,7 +882,12 @@ impl Backend {
             overridable.as_ref().and_then(|v| v.voltage),
             DEFAULT_VOLTAGE_V,
         );
+        let mut chip_count = overridable.as_ref().and_then(|v| v.chip_count);
+        let mut sensor_emulation = overridable
+            .as_ref()
+            .and_then(|v| v.sensor_emulation.clone());
         let mut enabled = DEFAULT_HASH_CHAIN_ENABLED;
+        let mut serial = None;
 
         // If there's a per-chain override then apply it
         if let Some(hash_chain) = self
@@ -385,17 +904,92 @@ impl Backend {
                 .voltage
                 .map(|v| OptionDefault::Some(v))
                 .unwrap_or(voltage);
+            chip_count = hash_chain.chip_count.or(chip_count);
+            sensor_emulation = hash_chain.sensor_emulation.clone().or(sensor_emulation);
+            serial = hash_chain.serial.clone();
         }
 
         // Computed s9-specific values
-        ResolvedChainConfig {
+        let mut chain_config = ResolvedChainConfig {
             midstate_count: MidstateCount::new(self.midstate_count()),
             frequency: FrequencySettings::from_frequency((*frequency * 1_000_000.0) as usize),
             // TODO: handle config errors
             voltage: power::Voltage::from_volts(*voltage as f32)
                 .expect("TODO: bad voltage requested"),
+            frequency_configured: frequency.is_some(),
+            voltage_configured: voltage.is_some(),
             enabled,
+            work_registry_depth: self
+                .hash_chain_global
+                .as_ref()
+                .and_then(|v| v.work_registry_depth),
+            duplicate_window: self
+                .hash_chain_global
+                .as_ref()
+                .and_then(|v| v.duplicate_window),
+            derate: self.hash_chain_global.as_ref().and_then(|v| {
+                v.derate_error_rate.map(|error_rate| {
+                    derate::Config::new(error_rate, v.derate_max_voltage.map(|v| v as f32))
+                })
+            }),
+            open_core: self.resolve_open_core_config(),
+            variant: None,
+            chip_count,
+            sensor_emulation: sensor_emulation.and_then(|spec| {
+                sensor::emulated::Trajectory::parse(&spec)
+                    .map_err(|e| {
+                        error!(
+                            "Hash chain {}: sensor emulation is ignored: {}",
+                            hash_chain_idx, e
+                        )
+                    })
+                    .ok()
+            }),
+            serial,
+        };
+        // Configured variant replaces defaults of S9
+        if let Some(variant) = self.hash_chain_global.as_ref().and_then(|v| v.variant) {
+            chain_config.apply_variant(variant);
         }
+        chain_config
+    }
+
+    pub fn resolve_open_core_config(&self) -> open_core::Config {
+        let mut open_core = open_core::Config::default();
+        let hash_chain_global = match &self.hash_chain_global {
+            Some(value) => value,
+            None => return open_core,
+        };
+        if let Some(count) = hash_chain_global.open_core_count {
+            open_core.count = count;
+        }
+        if let Some(voltage) = hash_chain_global.open_core_voltage {
+            open_core.voltage = power::Voltage::from_volts(voltage as f32)
+                .expect("BUG: open-core voltage has not been checked");
+        }
+        if let Some(delay) = hash_chain_global.open_core_delay {
+            open_core.delay = Duration::from_secs_f64(delay);
+        }
+        if let Some(hold) = hash_chain_global.open_core_hold {
+            open_core.hold = Duration::from_secs_f64(hold);
+        }
+        if let Some(check_time) = hash_chain_global.core_check_time {
+            open_core.check_time = if check_time > 0.0 {
+                Some(Duration::from_secs_f64(check_time))
+            } else {
+                None
+            };
+        }
+        open_core
+    }
+
+    pub fn resolve_chain_start_gap(&self) -> Duration {
+        let start_gap = self
+            .hash_chain_global
+            .as_ref()
+            .and_then(|v| v.start_gap)
+            .unwrap_or(DEFAULT_CHAIN_START_GAP_S);
+        Duration::from_secs_f64(start_gap)
     }
 
     pub fn resolve_monitor_config(&self) -> monitor::Config {
@@ -426,6 +1020,7 @@ impl Backend {
             self.fan_control.as_ref().and_then(|v| v.min_fans),
             DEFAULT_MIN_FANS,
         );
+        let rpm_targets = self.fan_control.as_ref().and_then(|v| v.rpm_targets());
 
         let temp_config;
         let fan_config;
@@ -470,11 +1065,28 @@ impl Backend {
                         *fan_speed
                     );
                 }
+                if let Some(rpm_targets) = &rpm_targets {
+                    warn!(
+                        "Unused fan 'target_rpm' ({:?}) because 'auto' mode is set",
+                        rpm_targets
+                    );
+                }
             }
             TempControlMode::Manual | TempControlMode::Disabled => {
                 fan_config = if fan_speed.eq_some(&0) && min_fans.eq_some(&0) {
                     // completely disable fan controller when all settings are set to 0
                     None
+                } else if let Some(rpm_targets) = rpm_targets {
+                    if fan_speed.is_some() {
+                        warn!(
+                            "Unused fan 'speed' ({}) because 'target_rpm' is set",
+                            *fan_speed
+                        );
+                    }
+                    Some(monitor::FanControlConfig {
+                        mode: monitor::FanControlMode::TargetRpm(rpm_targets),
+                        min_fans: *min_fans,
+                    })
                 } else {
                     Some(monitor::FanControlConfig {
                         mode: monitor::FanControlMode::FixedSpeed(fan::Speed::new(*fan_speed)),
@@ -491,10 +1103,94 @@ impl Backend {
             }
         };
 
+        // Configure detection of thermal runaway
+        let thermal_runaway = self
+            .temp_control
+            .as_ref()
+            .and_then(|v| v.max_temp_slope)
+            .map(|max_slope| monitor::ThermalRunawayConfig {
+                max_slope: max_slope as f32,
+                action: match self.temp_control.as_ref().and_then(|v| v.temp_slope_action) {
+                    Some(TempSlopeAction::Fans) => monitor::ThermalRunawayAction::FullSpeedFans,
+                    Some(TempSlopeAction::Shutdown) | None => {
+                        monitor::ThermalRunawayAction::Shutdown
+                    }
+                },
+            });
+
+        // Configure handling of broken hash chains
+        let max_restarts = self
+            .hash_chain_global
+            .as_ref()
+            .and_then(|v| v.broken_chain_restarts)
+            .unwrap_or(DEFAULT_BROKEN_CHAIN_RESTARTS);
+        let cooldown = self
+            .hash_chain_global
+            .as_ref()
+            .and_then(|v| v.broken_chain_cooldown)
+            .unwrap_or(DEFAULT_BROKEN_CHAIN_COOLDOWN_S);
+        let broken_chain_policy = if max_restarts > 0 {
+            monitor::BrokenChainPolicy::Restart {
+                max_restarts,
+                cooldown: Duration::from_secs_f64(cooldown),
+            }
+        } else {
+            monitor::BrokenChainPolicy::Shutdown
+        };
+
         monitor::Config {
             temp_config,
             fan_config,
             fans_on_while_warming_up: self.fans_on_while_warming_up.unwrap_or(true),
+            broken_chain_policy,
+            thermal_runaway,
+        }
+    }
+
+    /// Monitor status history is recorded only when it is configured
+    pub fn resolve_monitor_history_config(&self) -> Option<monitor::history::Config> {
+        self.monitor_history
+            .as_ref()
+            .map(|monitor_history| monitor::history::Config {
+                path: monitor_history
+                    .path
+                    .as_deref()
+                    .unwrap_or(DEFAULT_MONITOR_HISTORY_PATH)
+                    .into(),
+                format: match monitor_history.format {
+                    Some(MonitorHistoryFormat::Csv) | None => monitor::history::Format::Csv,
+                    Some(MonitorHistoryFormat::Json) => monitor::history::Format::Json,
+                },
+                interval: Duration::from_secs_f64(
+                    monitor_history
+                        .interval
+                        .unwrap_or(DEFAULT_MONITOR_HISTORY_INTERVAL_S),
+                ),
+                max_size: monitor_history
+                    .max_size
+                    .unwrap_or(DEFAULT_MONITOR_HISTORY_MAX_SIZE_KB)
+                    as u64
+                    * 1024,
+                max_files: monitor_history
+                    .max_files
+                    .unwrap_or(DEFAULT_MONITOR_HISTORY_MAX_FILES),
+            })
+    }
+
+    pub fn resolve_front_panel_config(&self) -> front_panel::Config {
+        let front_panel = self.front_panel.clone().unwrap_or_default();
+        front_panel::Config {
+            status_leds: front_panel
+                .status_leds
+                .unwrap_or(DEFAULT_FRONT_PANEL_STATUS_LEDS),
+            reset_action: front_panel
+                .reset_button
+                .unwrap_or(ButtonAction::None)
+                .into(),
+            ip_report_action: front_panel
+                .ip_report_button
+                .unwrap_or(ButtonAction::None)
+                .into(),
         }
     }
 
@@ -522,23 +1218,228 @@ impl ConfigBody for Backend {
         version == FORMAT_VERSION
     }
 
-    fn sanity_check(&self) -> Result<(), String> {
+    fn migrations() -> &'static [migration::Migration] {
+        migration::MIGRATIONS
+    }
+
+    fn check(&self, diagnostics: &mut check::Diagnostics) {
         // Check if all hash chain keys have meaningful name
         if let Some(hash_chains) = &self.hash_chains {
-            for idx in hash_chains.keys() {
-                let _ = idx
-                    .parse::<usize>()
-                    .map_err(|_| format!("hash chain index '{}' is not number", idx))
-                    .and_then(|idx| {
-                        if (HASH_CHAIN_INDEX_MIN..=HASH_CHAIN_INDEX_MAX).contains(&idx) {
-                            Ok(idx)
-                        } else {
-                            Err(format!(
-                                "hash chain index '{}' is out of range '{}..{}'",
-                                idx, HASH_CHAIN_INDEX_MIN, HASH_CHAIN_INDEX_MAX
-                            ))
+            for (idx, hash_chain) in hash_chains {
+                let key = format!("hash_chain.{}", idx);
+                match idx.parse::<usize>() {
+                    Ok(idx) => {
+                        if !(HASH_CHAIN_INDEX_MIN..=HASH_CHAIN_INDEX_MAX).contains(&idx) {
+                            diagnostics.error(
+                                key.as_str(),
+                                format!(
+                                    "hash chain index '{}' is out of range '{}..{}'",
+                                    idx, HASH_CHAIN_INDEX_MIN, HASH_CHAIN_INDEX_MAX
+                                ),
+                            );
                         }
-                    })?;
+                    }
+                    Err(_) => diagnostics.error(
+                        key.as_str(),
+                        format!("hash chain index '{}' is not number", idx),
+                    ),
+                }
+                hash_chain.check(&key, diagnostics);
+            }
+        }
+
+        if let Some(hash_chain_global) = &self.hash_chain_global {
+            if hash_chain_global.work_registry_depth == Some(0) {
+                diagnostics.error(
+                    "hash_chain_global.work_registry_depth",
+                    "work registry depth has to be greater than zero".to_string(),
+                );
+            }
+            if hash_chain_global.duplicate_window == Some(0) {
+                diagnostics.error(
+                    "hash_chain_global.duplicate_window",
+                    "duplicate window has to be greater than zero".to_string(),
+                );
+            }
+
+            if let Some(start_gap) = hash_chain_global.start_gap {
+                if !(start_gap >= 0.0 && start_gap.is_finite()) {
+                    diagnostics.error(
+                        "hash_chain_global.start_gap",
+                        format!("hash chain start gap '{}' is not valid", start_gap),
+                    );
+                }
+            }
+
+            if let Some(error_rate) = hash_chain_global.derate_error_rate {
+                if !(error_rate > 0.0 && error_rate < 100.0) {
+                    diagnostics.error(
+                        "hash_chain_global.derate_error_rate",
+                        format!(
+                            "derate error rate '{}' is out of range '0..100'",
+                            error_rate
+                        ),
+                    );
+                }
+            }
+            diagnostics.check_range(
+                "hash_chain_global.derate_max_voltage",
+                "derate max voltage",
+                hash_chain_global.derate_max_voltage,
+                VOLTAGE_V_MIN,
+                VOLTAGE_V_MAX,
+            );
+
+            if let Some(cooldown) = hash_chain_global.broken_chain_cooldown {
+                if !(cooldown >= 0.0 && cooldown.is_finite()) {
+                    diagnostics.error(
+                        "hash_chain_global.broken_chain_cooldown",
+                        format!("broken chain cooldown '{}' is not valid", cooldown),
+                    );
+                }
+            }
+
+            if hash_chain_global.open_core_count == Some(0) {
+                diagnostics.error(
+                    "hash_chain_global.open_core_count",
+                    "open-core work count has to be greater than zero".to_string(),
+                );
+            }
+            diagnostics.check_range(
+                "hash_chain_global.open_core_voltage",
+                "voltage",
+                hash_chain_global.open_core_voltage,
+                VOLTAGE_V_MIN,
+                VOLTAGE_V_MAX,
+            );
+            for (name, value) in &[
+                ("open_core_delay", hash_chain_global.open_core_delay),
+                ("open_core_hold", hash_chain_global.open_core_hold),
+                ("core_check_time", hash_chain_global.core_check_time),
+            ] {
+                if let Some(value) = value {
+                    if !(*value >= 0.0 && value.is_finite()) {
+                        diagnostics.error(
+                            format!("hash_chain_global.{}", name),
+                            format!("time '{}' is not valid", value),
+                        );
+                    }
+                }
+            }
+
+            if let Some(overridable) = &hash_chain_global.overridable {
+                overridable.check("hash_chain_global", diagnostics);
+                if overridable.serial.is_some() {
+                    diagnostics.error(
+                        "hash_chain_global.serial",
+                        "serial number can be set only for individual hash chain".to_string(),
+                    );
+                }
+            }
+        }
+
+        if let Some(temp_control) = &self.temp_control {
+            for (name, value) in &[
+                ("target_temp", temp_control.target_temp),
+                ("hot_temp", temp_control.hot_temp),
+                ("dangerous_temp", temp_control.dangerous_temp),
+            ] {
+                diagnostics.check_range(
+                    format!("temp_control.{}", name),
+                    "temperature",
+                    *value,
+                    TEMPERATURE_C_MIN,
+                    TEMPERATURE_C_MAX,
+                );
+            }
+            if let Some(max_temp_slope) = temp_control.max_temp_slope {
+                if !(max_temp_slope > 0.0 && max_temp_slope.is_finite()) {
+                    diagnostics.error(
+                        "temp_control.max_temp_slope",
+                        format!("temperature slope '{}' is not valid", max_temp_slope),
+                    );
+                }
+            }
+        }
+
+        if let Some(fan_control) = &self.fan_control {
+            diagnostics.check_range(
+                "fan_control.speed",
+                "fan speed",
+                fan_control.speed,
+                FAN_SPEED_MIN,
+                FAN_SPEED_MAX,
+            );
+            diagnostics.check_range(
+                "fan_control.min_fans",
+                "number of fans",
+                fan_control.min_fans,
+                FANS_MIN,
+                FANS_MAX,
+            );
+            diagnostics.check_range(
+                "fan_control.target_rpm",
+                "fan RPM",
+                fan_control.target_rpm,
+                FAN_RPM_MIN,
+                FAN_RPM_MAX,
+            );
+            if let Some(fans) = &fan_control.fans {
+                for (idx, fan) in fans {
+                    let key = format!("fan_control.fan.{}", idx);
+                    match idx.parse::<usize>() {
+                        Ok(idx) => {
+                            if !(FAN_INDEX_MIN..=FAN_INDEX_MAX).contains(&idx) {
+                                diagnostics.error(
+                                    key.as_str(),
+                                    format!(
+                                        "fan index '{}' is out of range '{}..{}'",
+                                        idx, FAN_INDEX_MIN, FAN_INDEX_MAX
+                                    ),
+                                );
+                            }
+                        }
+                        Err(_) => diagnostics
+                            .error(key.as_str(), format!("fan index '{}' is not number", idx)),
+                    }
+                    diagnostics.check_range(
+                        format!("{}.target_rpm", key),
+                        "fan RPM",
+                        fan.target_rpm,
+                        FAN_RPM_MIN,
+                        FAN_RPM_MAX,
+                    );
+                }
+            }
+        }
+
+        if let Some(pool_health) = &self.pool_health {
+            diagnostics.check_range(
+                "pool_health.threshold",
+                "pool health threshold",
+                pool_health.threshold,
+                0.0,
+                100.0,
+            );
+            if let Some(period) = pool_health.period {
+                if !(period >= 0.0 && period.is_finite()) {
+                    diagnostics.error(
+                        "pool_health.period",
+                        format!("pool health period '{}' is not valid", period),
+                    );
+                }
+            }
+        }
+
+        if let Some(share_watchdog) = &self.share_watchdog {
+            if let Err(e) = share_watchdog.timeout() {
+                diagnostics.error("share_watchdog.timeout", e.to_string());
+            }
+        }
+
+        if let Some(telemetry) = &self.telemetry {
+            if let Err(e) = telemetry.interval() {
+                diagnostics.error("telemetry.interval", e.to_string());
             }
         }
 
@@ -547,26 +1448,198 @@ impl ConfigBody for Backend {
         // Don't worry if is this section missing, maybe there are some pools on command line
         if let Some(groups) = &self.groups {
             let mut group_names = HashSet::with_capacity(groups.len());
-            for group in groups {
+            let mut total_quota = 0;
+            let mut total_fixed_share_ratio = 0.0;
+            for (i, group) in groups.iter().enumerate() {
+                let group_key = format!("group[{}]", i);
                 if let Some(name) = group_names.replace(&group.descriptor.name) {
-                    Err(format!("group with name '{}' already defined", name))?;
+                    diagnostics.error(
+                        format!("{}.name", group_key),
+                        format!("group with name '{}' already defined", name),
+                    );
+                }
+                // Follow the rules used by client manager when the groups are created
+                match group.descriptor.strategy() {
+                    bosminer_config::LoadBalanceStrategy::Quota(quota) => total_quota += quota,
+                    bosminer_config::LoadBalanceStrategy::FixedShareRatio(ratio) => {
+                        let key = format!("{}.fixed_share_ratio", group_key);
+                        if !(ratio >= 0.0 && ratio < 1.0) {
+                            diagnostics.error(
+                                key,
+                                format!("fixed share ratio '{}' is out of range '0..1'", ratio),
+                            );
+                        } else if i == 0 {
+                            diagnostics.error(
+                                key,
+                                "the first group cannot have fixed share ratio".to_string(),
+                            );
+                        } else if total_fixed_share_ratio + ratio >= 1.0 {
+                            diagnostics.error(
+                                key,
+                                "total fixed share ratio is greater than or equal to 1.0"
+                                    .to_string(),
+                            );
+                        }
+                        total_fixed_share_ratio += ratio;
+                    }
                 }
                 if let Some(pools) = &group.pools {
-                    for pool in pools {
-                        let _ = ClientDescriptor::create(
+                    for (j, pool) in pools.iter().enumerate() {
+                        let pool_key = format!("{}.pool[{}]", group_key, j);
+                        match ClientDescriptor::create(
                             pool.url.as_str(),
                             &ClientUserInfo::new(pool.user.as_str(), pool.password.as_deref()),
                             pool.enabled.unwrap_or(DEFAULT_POOL_ENABLED),
-                        )
-                        .map_err(|e| {
-                            format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user)
-                        })?;
+                        ) {
+                            Ok(descriptor) => match descriptor.protocol {
+                                ClientProtocol::Drain | ClientProtocol::Bitcoind(_)
+                                    if pool.endpoints.as_ref().map_or(false, |e| !e.is_empty()) =>
+                                {
+                                    diagnostics.error(
+                                        format!("{}.endpoints", pool_key),
+                                        format!(
+                                            "endpoints are supported only by stratum protocols \
+                                             in pool '{}@{}'",
+                                            pool.url, pool.user
+                                        ),
+                                    );
+                                }
+                                _ => {}
+                            },
+                            Err(e) => {
+                                diagnostics.error(
+                                    format!("{}.url", pool_key),
+                                    format!(
+                                        "{} in pool '{}@{}'",
+                                        e.to_string(),
+                                        pool.url,
+                                        pool.user
+                                    ),
+                                );
+                            }
+                        }
+                        if let Err(e) = pool.retry_schedule() {
+                            diagnostics.error(
+                                format!("{}.retry_delay", pool_key),
+                                format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user),
+                            );
+                        }
+                        if let Err(e) = pool.weight() {
+                            diagnostics.error(
+                                format!("{}.weight", pool_key),
+                                format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user),
+                            );
+                        }
+                        if let Err(e) = pool.extranonce_partition() {
+                            diagnostics.error(
+                                format!("{}.extranonce_partition", pool_key),
+                                format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user),
+                            );
+                        }
+                        if let Err(e) = pool.channel_count() {
+                            diagnostics.error(
+                                format!("{}.channels", pool_key),
+                                format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user),
+                            );
+                        }
+                        if let Err(e) = pool.endpoints() {
+                            diagnostics.error(
+                                format!("{}.endpoints", pool_key),
+                                format!("{} in pool '{}@{}'", e.to_string(), pool.url, pool.user),
+                            );
+                        }
                     }
                 }
             }
+            if !groups.is_empty()
+                && total_quota == 0
+                && groups
+                    .iter()
+                    .any(|group| group.descriptor.get_quota().is_some())
+            {
+                diagnostics.error(
+                    "group",
+                    "total quota of all groups has to be greater than zero".to_string(),
+                );
+            }
         }
 
-        Ok(())
+        if let Some(monitor_history) = &self.monitor_history {
+            diagnostics.check_range(
+                "monitor_history.interval",
+                "interval",
+                monitor_history.interval,
+                MONITOR_HISTORY_INTERVAL_S_MIN,
+                MONITOR_HISTORY_INTERVAL_S_MAX,
+            );
+            diagnostics.check_range(
+                "monitor_history.max_size",
+                "maximal size",
+                monitor_history.max_size,
+                MONITOR_HISTORY_MAX_SIZE_KB_MIN,
+                MONITOR_HISTORY_MAX_SIZE_KB_MAX,
+            );
+            diagnostics.check_range(
+                "monitor_history.max_files",
+                "maximal number of files",
+                monitor_history.max_files,
+                MONITOR_HISTORY_MAX_FILES_MIN,
+                MONITOR_HISTORY_MAX_FILES_MAX,
+            );
+        }
+
+        if let Some(fee) = &self.fee {
+            if let Err(e) = ClientDescriptor::create(
+                fee.url.as_str(),
+                &ClientUserInfo::new(fee.user.as_str(), fee.password.as_deref()),
+                true,
+            ) {
+                diagnostics.error(
+                    "fee.url",
+                    format!("{} in pool '{}@{}'", e.to_string(), fee.url, fee.user),
+                );
+            }
+            if let Err(e) = fee.share_ratio() {
+                diagnostics.error("fee.percent", e.to_string());
+            }
+        }
+
+        diagnostics.check_range(
+            "schedule.utc_offset",
+            "UTC offset",
+            self.schedule.as_ref().and_then(|v| v.utc_offset),
+            schedule::UTC_OFFSET_MIN,
+            schedule::UTC_OFFSET_MAX,
+        );
+        if let Some(profiles) = self.schedule.as_ref().and_then(|v| v.profiles.as_ref()) {
+            let mut names = HashSet::new();
+            for (i, profile) in profiles.iter().enumerate() {
+                let key = format!("schedule.profile.{}", i);
+                if let Err(e) = schedule::Profile::from_config(profile) {
+                    diagnostics.error(key.as_str(), e);
+                }
+                if !names.insert(profile.name.as_str()) {
+                    diagnostics.error(
+                        format!("{}.name", key),
+                        format!("duplicate profile '{}'", profile.name),
+                    );
+                }
+                diagnostics.check_range(
+                    format!("{}.frequency", key),
+                    "frequency",
+                    profile.frequency,
+                    FREQUENCY_MHZ_MIN,
+                    FREQUENCY_MHZ_MAX,
+                );
+                diagnostics.check_range(
+                    format!("{}.voltage", key),
+                    "voltage",
+                    profile.voltage,
+                    VOLTAGE_V_MIN,
+                    VOLTAGE_V_MAX,
+                );
+            }
+        }
     }
 
     fn metadata() -> serde_json::Value {
@@ -593,10 +1666,74 @@ impl hal::BackendConfig for Backend {
         }
     }
 
+    fn block_found(&self) -> bosminer_config::BlockFoundConfig {
+        let mut block_found = self.block_found.clone().unwrap_or_default();
+        if block_found.log_path.is_none() {
+            block_found.log_path = Some(DEFAULT_BLOCK_LOG_PATH.to_string());
+        }
+        block_found
+    }
+
+    fn pool_health(&self) -> bosminer_config::PoolHealthConfig {
+        self.pool_health.clone().unwrap_or_default()
+    }
+
+    fn events(&self) -> bosminer_config::EventsConfig {
+        self.events.clone().unwrap_or_default()
+    }
+
+    fn fee(&self) -> Option<bosminer_config::FeeConfig> {
+        self.fee.clone()
+    }
+
+    fn schedule(&self) -> bosminer_config::ScheduleConfig {
+        self.schedule.clone().unwrap_or_default()
+    }
+
+    fn share_watchdog(&self) -> bosminer_config::ShareWatchdogConfig {
+        self.share_watchdog.clone().unwrap_or_default()
+    }
+
+    fn telemetry(&self) -> bosminer_config::TelemetryConfig {
+        self.telemetry.clone().unwrap_or_default()
+    }
+
+    fn debug(&self) -> bosminer_config::DebugConfig {
+        self.debug.clone().unwrap_or_default()
+    }
+
+    fn cgminer_compatibility(&self) -> ii_cgminer_api::support::Compatibility {
+        if self
+            .api
+            .as_ref()
+            .and_then(|v| v.legacy_cgminer)
+            .unwrap_or(DEFAULT_LEGACY_CGMINER)
+        {
+            ii_cgminer_api::support::Compatibility::Legacy
+        } else {
+            ii_cgminer_api::support::Compatibility::Native
+        }
+    }
+
+    fn websocket_origins(&self) -> Vec<String> {
+        self.api
+            .as_ref()
+            .and_then(|v| v.websocket_origins.clone())
+            .unwrap_or_default()
+    }
+
     fn set_client_manager(&mut self, client_manager: client::Manager) {
         self.client_manager.replace(client_manager);
     }
 
+    fn set_event_bus(&mut self, event_bus: Arc<events::Bus>) {
+        self.event_bus.replace(event_bus);
+    }
+
+    fn set_scheduler(&mut self, scheduler: Arc<schedule::Scheduler>) {
+        self.scheduler.replace(scheduler);
+    }
+
     fn info(&self) -> Option<hal::BackendInfo> {
         Some(self.info.clone())
     }
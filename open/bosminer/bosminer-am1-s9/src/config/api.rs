@@ -206,6 +206,16 @@ impl<'a> Handler<'a> {
         self.send_response(response);
     }
 
+    /// Machine-readable JSON schema of the configuration generated from configuration structures
+    pub fn handle_schema<B: ConfigBody>(self) {
+        let response = MetadataResponse {
+            status: Status::new::<_, B>(StatusCode::Success, None),
+            data: FormatWrapper::<B>::json_schema(),
+        };
+
+        self.send_response(response);
+    }
+
     pub fn handle_data<B: ConfigBody>(self) {
         let response = match FormatWrapper::<B>::parse(self.config_path) {
             // TODO: Improve error handling
@@ -248,33 +258,101 @@ impl<'a> Handler<'a> {
             serde_json::from_value(request.data).expect("TODO: deserialize Backend");
         config.sanity_check().expect("TODO: invalid configuration");
 
+        let response = SaveResponse {
+            status: Status::new::<_, B>(StatusCode::Success, None),
+            data: Some(self.write_config(config)),
+        };
+
+        self.send_response(response);
+    }
+
+    /// Upgrade configuration file to the current format version. The upgraded configuration is
+    /// written to stdout unless `in_place` is set, then it's written back to the file and the
+    /// result of the save is written to stdout.
+    pub fn handle_migrate<B: ConfigBody>(self, in_place: bool) {
+        let config = FormatWrapper::<B>::load(self.config_path).and_then(|migrated| {
+            let layers = migrated
+                .layers()
+                .map_err(|msg| crate::config::FormatWrapperError::ParsingError(msg))?;
+            FormatWrapper::<B>::from_layers(layers)
+        });
+
+        let response = match config {
+            Ok(mut config) => {
+                config.format.generator = generator_string::<B>().into();
+                config.format.timestamp = UnixTime::now().into();
+                if !in_place {
+                    print!(
+                        "{}",
+                        toml::to_string_pretty(&config)
+                            .expect("BUG: cannot serialize migrated configuration")
+                    );
+                    return;
+                }
+                SaveResponse {
+                    status: Status::new::<_, B>(StatusCode::Success, None),
+                    data: Some(self.write_config(config)),
+                }
+            }
+            Err(e @ crate::config::FormatWrapperError::IncompatibleVersion(..)) => SaveResponse {
+                status: Status::new::<_, B>(
+                    StatusCode::IncompatibleFormatVersion,
+                    format!("{}", e),
+                ),
+                data: None,
+            },
+            Err(e) => SaveResponse {
+                status: Status::new::<_, B>(StatusCode::InvalidFormat, format!("{}", e)),
+                data: None,
+            },
+        };
+
+        self.send_response(response);
+    }
+
+    /// Apply `update` to the configuration file and write it back. Unlike the other handlers,
+    /// this one is used by the running miner so errors are returned instead of being written to
+    /// stdout. Returns path of the written file.
+    pub fn update<B: ConfigBody, F>(&self, update: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut B) -> Result<(), String>,
+    {
+        let mut config = FormatWrapper::<B>::parse(self.config_path).map_err(|e| e.to_string())?;
+        update(&mut config.body)?;
+        config.sanity_check().map_err(|e| e.to_string())?;
+
+        config.format.generator = generator_string::<B>().into();
+        config.format.timestamp = UnixTime::now().into();
+        self.try_write_config(config)
+            .map(|success| success.path)
+            .map_err(|e| e.to_string())
+    }
+
+    fn write_config<B: ConfigBody>(&self, config: FormatWrapper<B>) -> SaveSuccess {
+        self.try_write_config(config).expect("TODO: write config")
+    }
+
+    fn try_write_config<B: ConfigBody>(&self, config: FormatWrapper<B>) -> io::Result<SaveSuccess> {
         let config_path = Path::new(self.config_path);
         let config_tmp_path = config_path.with_extension(Self::CONFIG_TMP_EXTENSION);
 
-        let mut file = FileGuard::create(&config_tmp_path).expect("TODO: File::create");
+        let mut file = FileGuard::create(&config_tmp_path)?;
 
         file.write_all(
             toml::to_string_pretty(&config)
-                .expect("TODO: toml::to_string_pretty")
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
                 .as_bytes(),
-        )
-        .expect("TODO: file.write_all");
-
-        file.persist(config_path).expect("TODO: file.persist");
+        )?;
 
-        let response = SaveResponse {
-            status: Status::new::<_, B>(StatusCode::Success, None),
-            data: Some(SaveSuccess {
-                path: config_path
-                    .canonicalize()
-                    .expect("TODO: path.canonicalize")
-                    .into_os_string()
-                    .into_string()
-                    .expect("TODO: into_os_string"),
-                format: config.format,
-            }),
-        };
+        file.persist(config_path)?;
 
-        self.send_response(response);
+        Ok(SaveSuccess {
+            path: config_path
+                .canonicalize()?
+                .into_os_string()
+                .into_string()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid path"))?,
+            format: config.format,
+        })
     }
 }
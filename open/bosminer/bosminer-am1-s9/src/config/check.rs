@@ -0,0 +1,268 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Validation of configuration which collects all problems found instead of stopping at the
+//! first one. Every problem is tied to a key in configuration file so it can be easily located.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Single problem found in configuration
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// Path of the offending key e.g. `group[0].pool[1].url`
+    pub key: Option<String>,
+    /// Line in configuration file where the key is located
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "'{}': ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report problem related to particular `key`
+    pub fn error<K: Into<String>>(&mut self, key: K, message: String) {
+        self.items.push(Diagnostic {
+            key: Some(key.into()),
+            line: None,
+            message,
+        });
+    }
+
+    /// Report problem which cannot be tied to any key
+    pub fn general(&mut self, message: String) {
+        self.items.push(Diagnostic {
+            key: None,
+            line: None,
+            message,
+        });
+    }
+
+    /// Report `value` of `key` when it does not fit into the range `min..max`
+    pub fn check_range<K, T>(&mut self, key: K, name: &str, value: Option<T>, min: T, max: T)
+    where
+        K: Into<String>,
+        T: PartialOrd + fmt::Display,
+    {
+        if let Some(value) = value {
+            if !(value >= min && value <= max) {
+                self.error(
+                    key,
+                    format!("{} '{}' is out of range '{}..{}'", name, value, min, max),
+                );
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    /// Fill lines of all diagnostics from configuration file `content`
+    pub fn locate(&mut self, content: &str) {
+        let index = LineIndex::new(content);
+        for item in self.items.iter_mut() {
+            item.line = item.key.as_ref().and_then(|key| index.find(key));
+        }
+    }
+
+    /// Convert diagnostics to the error of the first problem found
+    pub fn into_result(self) -> Result<(), String> {
+        match self.items.into_iter().next() {
+            Some(item) => Err(item.message),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lines of all keys and sections of TOML configuration file
+struct LineIndex {
+    lines: HashMap<String, usize>,
+}
+
+impl LineIndex {
+    fn new(content: &str) -> Self {
+        let mut lines = HashMap::new();
+        // Number of already seen items of each array of tables
+        let mut array_lengths: HashMap<String, usize> = HashMap::new();
+        let mut section = String::new();
+
+        for (number, line) in content.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let header = line.split('#').next().unwrap_or_default().trim();
+                let name = header.trim_start_matches('[').trim_end_matches(']');
+                let path = Self::resolve(&array_lengths, name);
+                section = if header.starts_with("[[") {
+                    let length = array_lengths.entry(path.clone()).or_insert(0);
+                    *length += 1;
+                    format!("{}[{}]", path, *length - 1)
+                } else {
+                    path
+                };
+                lines.entry(section.clone()).or_insert(number);
+            } else if let Some(position) = line.find('=') {
+                let key = line[..position]
+                    .trim()
+                    .trim_matches(|c| c == '"' || c == '\'');
+                let key = if section.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", section, key)
+                };
+                lines.entry(key).or_insert(number);
+            }
+        }
+
+        Self { lines }
+    }
+
+    /// Convert dotted table name to the path where all parent arrays of tables are referenced by
+    /// their current (last) item
+    fn resolve(array_lengths: &HashMap<String, usize>, name: &str) -> String {
+        let parts: Vec<_> = name
+            .split('.')
+            .map(|part| part.trim().trim_matches(|c| c == '"' || c == '\''))
+            .collect();
+        let mut path = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                path.push('.');
+            }
+            path.push_str(part);
+            if i + 1 < parts.len() {
+                if let Some(length) = array_lengths.get(&path) {
+                    path = format!("{}[{}]", path, length.saturating_sub(1));
+                }
+            }
+        }
+        path
+    }
+
+    /// Find line of `key` or the nearest parent section
+    fn find(&self, key: &str) -> Option<usize> {
+        let mut key = key;
+        loop {
+            if let Some(line) = self.lines.get(key) {
+                return Some(*line);
+            }
+            key = &key[..key.rfind(|c| c == '.' || c == '[')?];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"[format]
+version = '1.1'
+
+[hash_chain_global]
+frequency = 950.0
+
+[hash_chain.6]
+voltage = 8.8
+
+[[group]]
+name = 'Default'
+
+[[group.pool]]
+url = 'stratum2+tcp://v2.stratum.slushpool.com:3336'
+
+[[group.pool]]
+url = 'invalid'
+
+[[group]]
+name = 'Backup'
+"#;
+
+    #[test]
+    fn test_line_index() {
+        let index = LineIndex::new(CONFIG);
+
+        assert_eq!(index.find("format.version"), Some(2));
+        assert_eq!(index.find("hash_chain_global.frequency"), Some(5));
+        assert_eq!(index.find("hash_chain.6.voltage"), Some(8));
+        // Missing key is located at its section
+        assert_eq!(index.find("hash_chain.6.frequency"), Some(7));
+        assert_eq!(index.find("group[0].pool[0].url"), Some(14));
+        assert_eq!(index.find("group[0].pool[1].url"), Some(17));
+        assert_eq!(index.find("group[1].name"), Some(20));
+        assert_eq!(index.find("temp_control.mode"), None);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.check_range(
+            "hash_chain_global.frequency",
+            "frequency",
+            Some(950.0),
+            200.0,
+            900.0,
+        );
+        diagnostics.check_range("hash_chain.6.voltage", "voltage", Some(8.8), 7.95, 9.4);
+        diagnostics.check_range("hash_chain.7.voltage", "voltage", None, 7.95, 9.4);
+        diagnostics.locate(CONFIG);
+
+        let items: Vec<_> = diagnostics.iter().map(|item| item.to_string()).collect();
+        assert_eq!(
+            items,
+            vec![
+                "line 5: 'hash_chain_global.frequency': frequency '950' is out of range '200..900'"
+            ]
+        );
+        assert_eq!(
+            diagnostics.into_result(),
+            Err("frequency '950' is out of range '200..900'".to_string())
+        );
+    }
+}
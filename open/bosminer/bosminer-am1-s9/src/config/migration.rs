@@ -0,0 +1,207 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Upgrade of configuration files written in older format versions. Every migration converts
+//! raw TOML of one format version to the next one so older configurations can be chained through
+//! all migrations up to the current version.
+
+use std::fs;
+
+/// Raw configuration as read from TOML file
+pub type Table = toml::value::Table;
+
+/// Single step of configuration upgrade
+pub struct Migration {
+    /// Format version of configuration the migration can be applied to
+    pub from: &'static str,
+    /// Format version of migrated configuration
+    pub to: &'static str,
+    /// Upgrade raw configuration in place
+    pub upgrade: fn(&mut Table) -> Result<(), String>,
+}
+
+/// Migrations of S9 configuration format sorted by version
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: "1.0",
+    to: "1.1",
+    upgrade: upgrade_1_0,
+}];
+
+/// Version 1.1 no longer silently ignores legacy top-level `[[pool]]` section. The pools are
+/// moved to the default group unless there are groups already.
+fn upgrade_1_0(table: &mut Table) -> Result<(), String> {
+    let pools = match table.remove("pool") {
+        Some(pools) => pools,
+        None => return Ok(()),
+    };
+    if table.contains_key("group") {
+        return Err("cannot move legacy 'pool' section because 'group' already exists".into());
+    }
+
+    let mut group = Table::new();
+    group.insert(
+        "name".to_string(),
+        bosminer_config::GroupDescriptor::DEFAULT_NAME.into(),
+    );
+    group.insert("pool".to_string(), pools);
+    table.insert("group".to_string(), vec![toml::Value::Table(group)].into());
+
+    Ok(())
+}
+
+fn format_version(table: &Table) -> Option<&str> {
+    table
+        .get("format")
+        .and_then(|format| format.get("version"))
+        .and_then(|version| version.as_str())
+}
+
+fn set_format_version(table: &mut Table, version: &str) {
+    if let Some(format) = table.get_mut("format").and_then(|v| v.as_table_mut()) {
+        format.insert("version".to_string(), version.into());
+    }
+}
+
+/// Apply all `migrations` following the format version of `table`. Returns the original version
+/// when the configuration has been upgraded.
+pub fn migrate(table: &mut Table, migrations: &[Migration]) -> Result<Option<String>, String> {
+    let original_version = match format_version(table) {
+        Some(version) => version.to_string(),
+        // Missing version is reported by sanity check of the configuration
+        None => return Ok(None),
+    };
+
+    let mut version = original_version.clone();
+    while let Some(migration) = migrations.iter().find(|m| m.from == version) {
+        (migration.upgrade)(table).map_err(|e| {
+            format!(
+                "cannot migrate configuration from version '{}' to '{}': {}",
+                migration.from, migration.to, e
+            )
+        })?;
+        set_format_version(table, migration.to);
+        version = migration.to.to_string();
+    }
+
+    Ok(if version != original_version {
+        Some(original_version)
+    } else {
+        None
+    })
+}
+
+/// Configuration file upgraded to the latest known format version
+pub struct Migrated {
+    pub table: Table,
+    /// Original format version when some migration has been applied
+    pub original_version: Option<String>,
+}
+
+impl Migrated {
+    /// Read configuration file from `config_path` and apply all `migrations`
+    pub fn load(config_path: &str, migrations: &[Migration]) -> Result<Self, String> {
+        let content = fs::read_to_string(config_path)
+            .map_err(|e| format!("cannot read configuration file: {}", e))?;
+        let mut table: Table = toml::from_str(&content).map_err(|e| format!("{}", e))?;
+        let original_version = migrate(&mut table, migrations)?;
+
+        Ok(Self {
+            table,
+            original_version,
+        })
+    }
+
+    /// Use the migrated configuration as the first configuration layer
+    pub fn layers(&self) -> Result<bosminer_config::Layers, String> {
+        bosminer_config::Layers::from_toml(&self.to_toml())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(&self.table).expect("BUG: cannot serialize migrated configuration")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(content: &str) -> Table {
+        toml::from_str(content).expect("BUG: invalid test configuration")
+    }
+
+    #[test]
+    fn test_migrate_legacy_pools() {
+        let mut table = parse(
+            r#"
+            [format]
+            version = '1.0'
+            model = 'Antminer S9'
+
+            [[pool]]
+            url = 'stratum2+tcp://v2.stratum.slushpool.com:3336'
+            user = 'braiins.worker'
+            "#,
+        );
+
+        assert_eq!(migrate(&mut table, MIGRATIONS), Ok(Some("1.0".to_string())));
+        assert_eq!(format_version(&table), Some("1.1"));
+        assert!(table.get("pool").is_none());
+
+        let group = &table["group"][0];
+        assert_eq!(
+            group["name"].as_str(),
+            Some(bosminer_config::GroupDescriptor::DEFAULT_NAME)
+        );
+        assert_eq!(group["pool"][0]["user"].as_str(), Some("braiins.worker"));
+    }
+
+    #[test]
+    fn test_migrate_conflict() {
+        let mut table = parse(
+            r#"
+            [format]
+            version = '1.0'
+
+            [[pool]]
+            url = 'stratum2+tcp://v2.stratum.slushpool.com:3336'
+
+            [[group]]
+            name = 'Default'
+            "#,
+        );
+
+        assert!(migrate(&mut table, MIGRATIONS).is_err());
+    }
+
+    #[test]
+    fn test_migrate_current() {
+        let mut table = parse(
+            r#"
+            [format]
+            version = '1.1'
+            "#,
+        );
+
+        assert_eq!(migrate(&mut table, MIGRATIONS), Ok(None));
+        assert_eq!(format_version(&table), Some("1.1"));
+    }
+}
@@ -54,6 +54,8 @@ pub struct Chip {
     pub core: [Core; super::CORE_ADR_SPACE_SIZE],
     pub valid: usize,
     pub errors: usize,
+    /// Duplicate solutions (also included in `errors`)
+    pub duplicates: usize,
 }
 
 impl Chip {
@@ -61,6 +63,7 @@ impl Chip {
         Self {
             valid: 0,
             errors: 0,
+            duplicates: 0,
             core: [Core::new(); super::CORE_ADR_SPACE_SIZE],
         }
     }
@@ -68,10 +71,22 @@ impl Chip {
     pub fn reset(&mut self) {
         self.valid = 0;
         self.errors = 0;
+        self.duplicates = 0;
         for core in self.core.iter_mut() {
             core.reset();
         }
     }
+
+    /// Number of core addresses that produced at least one valid nonce
+    pub fn active_cores(&self) -> usize {
+        self.core.iter().filter(|core| core.valid > 0).count()
+    }
+
+    /// Valid nonces are spread over more core addresses than there are cores on chip, so
+    /// the conjectured mapping of nonces to chip/core addresses doesn't hold for this chip
+    pub fn address_mismatch(&self) -> bool {
+        self.active_cores() > bm1387::NUM_CORES_ON_CHIP
+    }
 }
 
 #[derive(Clone)]
@@ -79,6 +94,23 @@ pub struct HashChain {
     pub chip: Vec<Chip>,
     pub valid: usize,
     pub errors: usize,
+    /// Duplicate solutions (also included in `errors`)
+    pub duplicates: usize,
+    /// Solutions for work that has already been retired from work registry
+    pub stale_solutions: usize,
+    /// The longest observed delay of a stale solution in number of work items (see
+    /// `registry::Stats`)
+    pub max_stale_age: usize,
+    /// Nonces that map to a chip address that hasn't been enumerated
+    pub unknown_chip_nonces: usize,
+    /// Number of switches to a new block
+    pub job_switches: usize,
+    /// Work items purged from the chain on job switches
+    pub purged_works: usize,
+    /// Time from sending the first work of a new block to receiving its first solution
+    pub job_switch_latency: Option<Duration>,
+    /// The longest observed job switch latency
+    pub max_job_switch_latency: Duration,
     pub started: Instant,
     pub stopped: Option<Instant>,
     pub asic_difficulty: usize,
@@ -89,6 +121,14 @@ impl HashChain {
         Self {
             valid: 0,
             errors: 0,
+            duplicates: 0,
+            stale_solutions: 0,
+            max_stale_age: 0,
+            unknown_chip_nonces: 0,
+            job_switches: 0,
+            purged_works: 0,
+            job_switch_latency: None,
+            max_job_switch_latency: Duration::from_secs(0),
             started: Instant::now(),
             stopped: None,
             chip: vec![Chip::new(); chip_count],
@@ -99,6 +139,14 @@ impl HashChain {
     pub fn reset(&mut self) {
         self.valid = 0;
         self.errors = 0;
+        self.duplicates = 0;
+        self.stale_solutions = 0;
+        self.max_stale_age = 0;
+        self.unknown_chip_nonces = 0;
+        self.job_switches = 0;
+        self.purged_works = 0;
+        self.job_switch_latency = None;
+        self.max_job_switch_latency = Duration::from_secs(0);
         for chip in self.chip.iter_mut() {
             chip.reset();
         }
@@ -123,7 +171,7 @@ impl HashChain {
     pub fn add_valid(&mut self, addr: bm1387::CoreAddress) {
         if addr.chip >= self.chip.len() {
             // nonce from non-existent chip
-            // TODO: what to do?
+            self.unknown_chip_nonces += 1;
             return;
         }
         self.valid += self.asic_difficulty;
@@ -134,7 +182,7 @@ impl HashChain {
     pub fn add_error(&mut self, addr: bm1387::CoreAddress) {
         if addr.chip >= self.chip.len() {
             // nonce from non-existent chip
-            // TODO: what to do?
+            self.unknown_chip_nonces += 1;
             return;
         }
         self.errors += 1;
@@ -142,6 +190,35 @@ impl HashChain {
         self.chip[addr.chip].core[addr.core].errors += 1;
     }
 
+    /// Account solution which has already been received for the same work. Duplicates are
+    /// accounted as errors as well so they still degrade the error rate of the chip.
+    pub fn add_duplicate(&mut self, addr: bm1387::CoreAddress) {
+        self.add_error(addr);
+        if addr.chip < self.chip.len() {
+            self.duplicates += 1;
+            self.chip[addr.chip].duplicates += 1;
+        }
+    }
+
+    /// Account solution for retired work, `age` is the number of work items stored since its
+    /// retirement
+    pub fn add_stale(&mut self, age: usize) {
+        self.stale_solutions += 1;
+        self.max_stale_age = self.max_stale_age.max(age);
+    }
+
+    /// Account switch to a new block which purged `purged_works` from the chain
+    pub fn add_job_switch(&mut self, purged_works: usize) {
+        self.job_switches += 1;
+        self.purged_works += purged_works;
+    }
+
+    /// Account time needed by the chain to return the first solution of a new block
+    pub fn set_job_switch_latency(&mut self, latency: Duration) {
+        self.job_switch_latency = Some(latency);
+        self.max_job_switch_latency = self.max_job_switch_latency.max(latency);
+    }
+
     pub fn set_chip_count(&mut self, chip_count: usize) {
         self.chip.resize(chip_count, Chip::new());
     }
@@ -150,3 +227,70 @@ impl HashChain {
         self.chip.len()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_address_mismatch() {
+        let mut counter = HashChain::new(2, 1);
+        counter.add_valid(bm1387::CoreAddress { chip: 1, core: 3 });
+        counter.add_valid(bm1387::CoreAddress { chip: 1, core: 3 });
+        counter.add_error(bm1387::CoreAddress { chip: 1, core: 4 });
+        counter.add_valid(bm1387::CoreAddress { chip: 2, core: 3 });
+        counter.add_error(bm1387::CoreAddress { chip: 5, core: 3 });
+
+        assert_eq!(counter.unknown_chip_nonces, 2);
+        assert_eq!(counter.chip[0].active_cores(), 0);
+        assert_eq!(counter.chip[1].active_cores(), 1);
+        assert!(!counter.chip[1].address_mismatch());
+
+        for core in 0..crate::CORE_ADR_SPACE_SIZE {
+            counter.add_valid(bm1387::CoreAddress { chip: 0, core });
+        }
+        assert!(counter.chip[0].address_mismatch());
+
+        counter.reset();
+        assert_eq!(counter.unknown_chip_nonces, 0);
+        assert_eq!(counter.chip[0].active_cores(), 0);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut counter = HashChain::new(2, 1);
+        counter.add_duplicate(bm1387::CoreAddress { chip: 1, core: 3 });
+        counter.add_duplicate(bm1387::CoreAddress { chip: 1, core: 4 });
+        counter.add_error(bm1387::CoreAddress { chip: 0, core: 3 });
+        counter.add_duplicate(bm1387::CoreAddress { chip: 5, core: 3 });
+
+        assert_eq!(counter.duplicates, 2);
+        assert_eq!(counter.errors, 3);
+        assert_eq!(counter.chip[0].duplicates, 0);
+        assert_eq!(counter.chip[1].duplicates, 2);
+        assert_eq!(counter.chip[1].errors, 2);
+        assert_eq!(counter.unknown_chip_nonces, 1);
+
+        counter.reset();
+        assert_eq!(counter.duplicates, 0);
+        assert_eq!(counter.chip[1].duplicates, 0);
+    }
+
+    #[test]
+    fn test_job_switch() {
+        let mut counter = HashChain::new(2, 1);
+        counter.add_job_switch(10);
+        counter.set_job_switch_latency(Duration::from_millis(30));
+        counter.add_job_switch(5);
+        counter.set_job_switch_latency(Duration::from_millis(20));
+
+        assert_eq!(counter.job_switches, 2);
+        assert_eq!(counter.purged_works, 15);
+        assert_eq!(counter.job_switch_latency, Some(Duration::from_millis(20)));
+        assert_eq!(counter.max_job_switch_latency, Duration::from_millis(30));
+
+        counter.reset();
+        assert_eq!(counter.job_switches, 0);
+        assert_eq!(counter.job_switch_latency, None);
+    }
+}
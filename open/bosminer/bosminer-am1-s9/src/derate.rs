@@ -0,0 +1,220 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Derating of hash chain operating point on sustained bursts of hardware errors
+//!
+//! Error counters of a running hash chain are sampled periodically. When the ratio of hardware
+//! errors stays above the threshold for several consecutive samples, the frequency is lowered by
+//! one step (or the voltage is raised within a limit once the frequency is at its minimum). After
+//! the hash chain has been stable for a while, it is probed upward by one step back towards the
+//! operating point it has been running at before it was derated.
+
+use crate::config;
+
+use std::time::Duration;
+
+/// Samples with fewer solutions (valid or not) are too noisy to make any decision
+const MIN_SAMPLE_SOLUTIONS: usize = 32;
+
+/// Parameters of the derating controller
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Ratio of hardware errors to all solutions (`0..1`) which is considered as a burst
+    pub error_ratio: f64,
+    /// Period of sampling of error counters
+    pub interval: Duration,
+    /// Number of consecutive samples above `error_ratio` that trigger derating
+    pub sustain: usize,
+    /// Number of consecutive stable samples after which the operating point is probed upward
+    pub recovery: usize,
+    /// Frequency step in Hz by which each chip is lowered
+    pub frequency_step: usize,
+    /// Voltage step in volts used when the frequency cannot be lowered anymore
+    pub voltage_step: f32,
+    /// Voltage is never raised above this limit (in volts), `None` disables raising of voltage
+    pub max_voltage: Option<f32>,
+}
+
+impl Config {
+    pub fn new(error_rate: f64, max_voltage: Option<f32>) -> Self {
+        Self {
+            error_ratio: error_rate / 100.0,
+            interval: Duration::from_secs_f64(config::DEFAULT_DERATE_INTERVAL_S),
+            sustain: config::DEFAULT_DERATE_SUSTAIN,
+            recovery: config::DEFAULT_DERATE_RECOVERY,
+            frequency_step: (config::DEFAULT_DERATE_FREQUENCY_STEP_MHZ * 1_000_000.0) as usize,
+            voltage_step: config::DEFAULT_DERATE_VOLTAGE_STEP_V as f32,
+            max_voltage,
+        }
+    }
+}
+
+/// Change of the operating point decided by the controller
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Hash chain has produced too many hardware errors
+    Down,
+    /// Hash chain has been stable long enough to restore one derate step
+    Up,
+}
+
+/// Decides when the hash chain should be derated from samples of its error counters. The
+/// operating points themselves are managed by the caller.
+#[derive(Debug)]
+pub struct Controller {
+    config: Config,
+    /// Cumulative counters of valid solutions and hardware errors from the previous sample
+    last_counters: Option<(usize, usize)>,
+    /// Ratio of hardware errors in the last sample with enough solutions
+    error_ratio: Option<f64>,
+    bad_samples: usize,
+    good_samples: usize,
+}
+
+impl Controller {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            last_counters: None,
+            error_ratio: None,
+            bad_samples: 0,
+            good_samples: 0,
+        }
+    }
+
+    /// Forget all samples (e.g. after the hash chain has been restarted)
+    pub fn reset(&mut self) {
+        self.last_counters = None;
+        self.error_ratio = None;
+        self.bad_samples = 0;
+        self.good_samples = 0;
+    }
+
+    /// Ratio of hardware errors in the last sample with enough solutions
+    pub fn error_ratio(&self) -> Option<f64> {
+        self.error_ratio
+    }
+
+    /// Feed cumulative counters of the hash chain. `derated` tells whether there is any derate
+    /// step which can be restored.
+    pub fn sample(&mut self, valid: usize, errors: usize, derated: bool) -> Option<Step> {
+        let (last_valid, last_errors) = match self.last_counters.replace((valid, errors)) {
+            Some(last_counters) => last_counters,
+            // the first sample is just a baseline
+            None => return None,
+        };
+        if valid < last_valid || errors < last_errors {
+            // counters have been reset in the meantime
+            return None;
+        }
+        let solutions = (valid - last_valid) + (errors - last_errors);
+        if solutions < MIN_SAMPLE_SOLUTIONS {
+            return None;
+        }
+        let error_ratio = (errors - last_errors) as f64 / solutions as f64;
+        self.error_ratio = Some(error_ratio);
+
+        if error_ratio > self.config.error_ratio {
+            self.good_samples = 0;
+            self.bad_samples += 1;
+            if self.bad_samples >= self.config.sustain {
+                self.bad_samples = 0;
+                return Some(Step::Down);
+            }
+        } else {
+            self.bad_samples = 0;
+            if derated {
+                self.good_samples += 1;
+                if self.good_samples >= self.config.recovery {
+                    self.good_samples = 0;
+                    return Some(Step::Up);
+                }
+            } else {
+                self.good_samples = 0;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_config() -> Config {
+        Config {
+            error_ratio: 0.1,
+            interval: Duration::from_secs(1),
+            sustain: 2,
+            recovery: 3,
+            frequency_step: 25_000_000,
+            voltage_step: 0.1,
+            max_voltage: None,
+        }
+    }
+
+    #[test]
+    fn test_sustained_burst() {
+        let mut controller = Controller::new(test_config());
+        assert_eq!(controller.sample(0, 0, false), None);
+        // single burst is tolerated
+        assert_eq!(controller.sample(80, 20, false), None);
+        assert_eq!(controller.error_ratio(), Some(0.2));
+        assert_eq!(controller.sample(180, 20, false), None);
+        assert_eq!(controller.sample(260, 40, false), None);
+        assert_eq!(controller.sample(340, 60, false), Some(Step::Down));
+        // counting starts again after derating
+        assert_eq!(controller.sample(420, 80, true), None);
+        assert_eq!(controller.sample(500, 100, true), Some(Step::Down));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut controller = Controller::new(test_config());
+        assert_eq!(controller.sample(0, 0, true), None);
+        assert_eq!(controller.sample(100, 0, true), None);
+        assert_eq!(controller.sample(200, 0, true), None);
+        // burst interrupts the stable period
+        assert_eq!(controller.sample(280, 20, true), None);
+        assert_eq!(controller.sample(380, 20, true), None);
+        assert_eq!(controller.sample(480, 20, true), None);
+        assert_eq!(controller.sample(580, 20, true), Some(Step::Up));
+        // nothing to restore
+        for i in 0..5 {
+            assert_eq!(controller.sample(680 + i * 100, 20, false), None);
+        }
+    }
+
+    #[test]
+    fn test_insufficient_sample() {
+        let mut controller = Controller::new(test_config());
+        assert_eq!(controller.sample(0, 0, false), None);
+        for i in 1..10 {
+            assert_eq!(controller.sample(0, i * 10, false), None);
+        }
+        assert_eq!(controller.error_ratio(), None);
+        // reset of counters is not an error burst
+        assert_eq!(controller.sample(0, 0, false), None);
+        assert_eq!(controller.sample(0, 100, false), None);
+        assert_eq!(controller.sample(0, 200, false), Some(Step::Down));
+    }
+}
@@ -0,0 +1,514 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Diagnostics and frequency tuning of hashboards operated without pools and temperature
+//! control (used by `diag` and `tune` subcommands). The miner must not be running at the same
+//! time.
+
+use ii_logging::macros::*;
+
+use crate::bm1387::{self, ChipAddress};
+use crate::chip_hashrate;
+use crate::config;
+use crate::error;
+use crate::fan::{self, Driver as _};
+use crate::gpio;
+use crate::monitor;
+use crate::null_work;
+use crate::power;
+use crate::registry;
+use crate::sensor;
+use crate::{Backend, FrequencySettings, HashChain, PlugPin, ResetPin, TEMP_CHIPS};
+
+use futures::channel::mpsc;
+use ii_async_compat::futures;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default time of hashing at each frequency of the sweep
+pub const DEFAULT_SAMPLE_TIME: Duration = Duration::from_secs(60);
+/// Default range of the sweep in MHz
+pub const DEFAULT_MIN_FREQUENCY_MHZ: f64 = 500.0;
+pub const DEFAULT_MAX_FREQUENCY_MHZ: f64 = 800.0;
+/// Default step of the sweep in MHz
+pub const DEFAULT_STEP_MHZ: f64 = 25.0;
+
+/// The sweep is stopped when any sensor reports temperature above this limit
+const MAX_TEMPERATURE: f32 = 90.0;
+
+/// Result of hardware check of one hashboard
+#[derive(Debug, Clone)]
+pub struct BoardReport {
+    pub hashboard_idx: usize,
+    pub expected_chip_count: Option<usize>,
+    /// Number of enumerated chips, `None` when the hashboard hasn't been initialized
+    pub chip_count: Option<usize>,
+    /// Voltage setpoint reported back by the voltage controller
+    pub voltage: Option<power::Voltage>,
+    /// Readings of all temperature sensors that have been found
+    pub temperatures: Vec<sensor::Temperature>,
+    pub errors: Vec<String>,
+}
+
+impl BoardReport {
+    fn new(hashboard_idx: usize) -> Self {
+        Self {
+            hashboard_idx,
+            expected_chip_count: None,
+            chip_count: None,
+            voltage: None,
+            temperatures: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// All checks have passed
+    pub fn is_ok(&self) -> bool {
+        let chips_ok = match (self.chip_count, self.expected_chip_count) {
+            (Some(chip_count), Some(expected_chip_count)) => chip_count == expected_chip_count,
+            (chip_count, None) => chip_count.is_some(),
+            (None, _) => false,
+        };
+        chips_ok && self.errors.is_empty()
+    }
+}
+
+fn format_measurement(measurement: &sensor::Measurement) -> String {
+    match measurement {
+        sensor::Measurement::Ok(temperature) => format!("{:.1} °C", temperature),
+        measurement => format!("{:?}", measurement),
+    }
+}
+
+impl fmt::Display for BoardReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Hashboard {}: {}",
+            self.hashboard_idx,
+            if self.is_ok() { "OK" } else { "FAILED" }
+        )?;
+        match (self.chip_count, self.expected_chip_count) {
+            (Some(chip_count), Some(expected_chip_count)) => {
+                writeln!(f, "  chips: {}/{}", chip_count, expected_chip_count)?
+            }
+            (Some(chip_count), None) => writeln!(f, "  chips: {}", chip_count)?,
+            (None, _) => writeln!(f, "  chips: not enumerated")?,
+        }
+        if let Some(voltage) = self.voltage {
+            writeln!(f, "  voltage: {}", voltage)?;
+        }
+        for temperature in self.temperatures.iter() {
+            writeln!(
+                f,
+                "  temperature: local {}, remote {}",
+                format_measurement(&temperature.local),
+                format_measurement(&temperature.remote)
+            )?;
+        }
+        for error in self.errors.iter() {
+            writeln!(f, "  error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters of frequency sweep (frequencies in MHz)
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    /// Time of hashing at each frequency
+    pub sample_time: Duration,
+}
+
+impl Sweep {
+    /// All frequencies of the sweep in ascending order
+    pub fn frequencies(&self) -> Vec<f64> {
+        let mut frequencies = vec![];
+        let mut frequency = self.start;
+        while frequency <= self.end && self.step > 0.0 {
+            frequencies.push(frequency);
+            frequency += self.step;
+        }
+        frequencies
+    }
+}
+
+/// Measurement at one frequency of the sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepStep {
+    /// Frequency in MHz
+    pub frequency: f64,
+    pub chip_count: usize,
+    /// Sum of hashrate registers of all chips in hashes per second
+    pub hashrate: f64,
+    /// Nominal hashrate of all expected chips at this frequency
+    pub expected_hashrate: f64,
+}
+
+impl SweepStep {
+    /// No chip has dropped out and the hashrate doesn't diverge from nominal hashrate
+    pub fn passed(&self, expected_chip_count: usize) -> bool {
+        self.chip_count == expected_chip_count
+            && self.hashrate * 100.0
+                >= self.expected_hashrate * (100.0 - chip_hashrate::DIVERGENCE_THRESHOLD)
+    }
+}
+
+/// Result of frequency sweep of one hashboard
+#[derive(Debug, Clone)]
+pub struct TuneResult {
+    pub hashboard_idx: usize,
+    pub voltage: power::Voltage,
+    pub steps: Vec<SweepStep>,
+    /// The highest frequency that passed, `None` when no frequency passed
+    pub frequency: Option<f64>,
+    /// Reason why the sweep has been stopped early
+    pub error: Option<String>,
+}
+
+impl fmt::Display for TuneResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.frequency {
+            Some(frequency) => writeln!(
+                f,
+                "Hashboard {}: {} MHz at {}",
+                self.hashboard_idx, frequency, self.voltage
+            )?,
+            None => writeln!(f, "Hashboard {}: no usable frequency", self.hashboard_idx)?,
+        }
+        for step in self.steps.iter() {
+            writeln!(
+                f,
+                "  {} MHz: {} chips, {:.2}/{:.2} TH/s",
+                step.frequency,
+                step.chip_count,
+                step.hashrate / 1e12,
+                step.expected_hashrate / 1e12
+            )?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "  error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Per-chain configuration found by tuning in the format of configuration file
+#[derive(Serialize, Debug)]
+struct Profiles {
+    hash_chain: BTreeMap<String, config::HashChain>,
+}
+
+/// Write frequencies found by tuning as `[hash_chain.N]` sections of configuration file
+pub fn write_profiles(path: &str, results: &[TuneResult]) -> Result<(), String> {
+    let profiles = Profiles {
+        hash_chain: results
+            .iter()
+            .filter_map(|result| {
+                result.frequency.map(|frequency| {
+                    (
+                        result.hashboard_idx.to_string(),
+                        config::HashChain {
+                            frequency: Some(frequency),
+                            voltage: Some(
+                                (result.voltage.as_volts() as f64 * 100.0).round() / 100.0,
+                            ),
+                            ..Default::default()
+                        },
+                    )
+                })
+            })
+            .collect(),
+    };
+    let content = toml::to_string_pretty(&profiles).map_err(|e| e.to_string())?;
+    fs::write(path, content).map_err(|e| format!("cannot write '{}': {}", path, e))
+}
+
+/// Hashboards operated directly, without pools and temperature control
+pub struct Bench {
+    gpio_mgr: gpio::ControlPinManager,
+    voltage_ctrl_backend: Arc<power::I2cBackend>,
+    backend_config: config::Backend,
+    /// Fans are kept at full speed because there's no temperature control
+    _fan_control: fan::Control,
+    /// Messages for monitor are not processed, the receiver only keeps the channel open
+    monitor_tx: mpsc::UnboundedSender<monitor::Message>,
+    _monitor_rx: mpsc::UnboundedReceiver<monitor::Message>,
+}
+
+impl Bench {
+    pub fn new(backend_config: config::Backend) -> error::Result<Self> {
+        let fan_control = fan::Control::new()?;
+        fan_control.set_speed(fan::Speed::FULL_SPEED);
+        let (monitor_tx, monitor_rx) = mpsc::unbounded();
+        Ok(Self {
+            gpio_mgr: gpio::ControlPinManager::new(),
+            voltage_ctrl_backend: Arc::new(power::I2cBackend::new(0)),
+            backend_config,
+            _fan_control: fan_control,
+            monitor_tx,
+            _monitor_rx: monitor_rx,
+        })
+    }
+
+    /// Indices of present hashboards which are enabled in configuration
+    pub fn hashboards(&self) -> error::Result<Vec<usize>> {
+        Ok(Backend::detect_hashboards(&self.gpio_mgr)?
+            .into_iter()
+            .filter(|idx| self.backend_config.resolve_chain_config(*idx).enabled)
+            .collect())
+    }
+
+    /// Power on and initialize hashboard. Chip cores are opened only when `open_cores` is set.
+    async fn power_on(
+        &self,
+        hashboard_idx: usize,
+        chain_config: &config::ResolvedChainConfig,
+        frequency: &FrequencySettings,
+        open_cores: bool,
+    ) -> error::Result<(HashChain, Arc<registry::WorkRegistry>)> {
+        let mut hash_chain = HashChain::new(
+            ResetPin::open(&self.gpio_mgr, hashboard_idx)?,
+            PlugPin::open(&self.gpio_mgr, hashboard_idx)?,
+            self.voltage_ctrl_backend.clone(),
+            hashboard_idx,
+            chain_config.midstate_count,
+            config::DEFAULT_ASIC_DIFFICULTY,
+            self.monitor_tx.clone(),
+        )?;
+        hash_chain.expected_chip_count = chain_config.expected_chip_count();
+        hash_chain.open_core = chain_config.open_core.clone();
+        hash_chain.disable_init_work = !open_cores;
+        match hash_chain.init(frequency, chain_config.voltage, true).await {
+            Ok(work_registry) => Ok((hash_chain, work_registry)),
+            Err(e) => {
+                Self::power_off(&hash_chain).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn power_off(hash_chain: &HashChain) {
+        // halt is required to stop voltage heart-beat task
+        hash_chain
+            .halt_sender
+            .clone()
+            .send_halt_with_reason("diagnostics done".into())
+            .await;
+        if let Err(e) = hash_chain.shutdown().await {
+            error!("Chain {} shutdown failed: {}", hash_chain.hashboard_idx, e);
+        }
+    }
+
+    /// Read all temperature sensors of initialized hashboard
+    async fn read_temperatures(hash_chain: &HashChain) -> error::Result<Vec<sensor::Temperature>> {
+        let mut temperatures = vec![];
+        for temp_chip in TEMP_CHIPS.iter() {
+            let mut sensor =
+                HashChain::try_to_initialize_sensor(hash_chain.command_context.clone(), *temp_chip)
+                    .await?;
+            temperatures.push(sensor.read_temperature().await?);
+        }
+        Ok(temperatures)
+    }
+
+    /// Check hashboard without mining: enumerate chips, probe temperature sensors
+    /// and read back voltage
+    pub async fn diagnose(&self, hashboard_idx: usize) -> BoardReport {
+        let mut report = BoardReport::new(hashboard_idx);
+        let chain_config = self.backend_config.resolve_chain_config(hashboard_idx);
+        report.expected_chip_count = Some(chain_config.expected_chip_count());
+
+        let (hash_chain, _) = match self
+            .power_on(hashboard_idx, &chain_config, &chain_config.frequency, false)
+            .await
+        {
+            Ok(hash_chain) => hash_chain,
+            Err(e) => {
+                report.errors.push(format!("initialization failed: {}", e));
+                return report;
+            }
+        };
+        report.chip_count = Some(hash_chain.chip_count);
+        match hash_chain.voltage_ctrl.read_voltage().await {
+            Ok(voltage) => report.voltage = Some(voltage),
+            Err(e) => report
+                .errors
+                .push(format!("voltage readback failed: {}", e)),
+        }
+        match Self::read_temperatures(&hash_chain).await {
+            Ok(temperatures) => report.temperatures = temperatures,
+            Err(e) => report.errors.push(format!("sensor probing failed: {}", e)),
+        }
+        Self::power_off(&hash_chain).await;
+        report
+    }
+
+    /// Keep initialized hashboard busy with null work for `sample_time` and read hashrate
+    /// registers of all chips
+    async fn measure(
+        hash_chain: &HashChain,
+        work_registry: Arc<registry::WorkRegistry>,
+        sample_time: Duration,
+    ) -> error::Result<f64> {
+        // solutions of null work are just drained, the task is dropped when the chain is halted
+        let mut rx_fifo = hash_chain.take_work_rx_io().await;
+        hash_chain
+            .halt_receiver
+            .register_client(format!("chain {} diag work-rx", hash_chain.hashboard_idx))
+            .await
+            .spawn(async move {
+                while let Ok((fifo, _)) = rx_fifo.recv_solution().await {
+                    rx_fifo = fifo;
+                }
+            });
+
+        let mut tx_fifo = hash_chain.take_work_tx_io().await;
+        let midstate_count = hash_chain.midstate_count.to_count();
+        let start = Instant::now();
+        let mut seq_num = 0u32;
+        while start.elapsed() < sample_time {
+            tx_fifo.wait_for_room().await?;
+            let work = null_work::prepare_opencore(true, midstate_count, seq_num);
+            let work_id = work_registry.store_work(work, true);
+            let work = work_registry
+                .get_work(work_id)
+                .expect("BUG: work not stored");
+            tx_fifo.send_work(&work, work_id)?;
+            seq_num = seq_num.wrapping_add(1);
+        }
+
+        let registers = hash_chain
+            .command_context
+            .read_register::<bm1387::HashrateReg>(ChipAddress::All)
+            .await?;
+        Ok(registers
+            .iter()
+            .map(|register| register.hashrate() as f64)
+            .sum())
+    }
+
+    /// Find the highest frequency from `sweep` at which the hashboard hashes at its nominal
+    /// hashrate. The sweep stops at the first frequency that doesn't pass.
+    pub async fn tune(&self, hashboard_idx: usize, sweep: &Sweep) -> TuneResult {
+        let chain_config = self.backend_config.resolve_chain_config(hashboard_idx);
+        let mut result = TuneResult {
+            hashboard_idx,
+            voltage: chain_config.voltage,
+            steps: vec![],
+            frequency: None,
+            error: None,
+        };
+        let expected_chip_count = chain_config.expected_chip_count();
+
+        for frequency in sweep.frequencies() {
+            info!("Tune: hashboard {} at {} MHz", hashboard_idx, frequency);
+            let frequency_settings =
+                FrequencySettings::from_frequency((frequency * 1_000_000.0) as usize);
+            let (hash_chain, work_registry) = match self
+                .power_on(hashboard_idx, &chain_config, &frequency_settings, true)
+                .await
+            {
+                Ok(hash_chain) => hash_chain,
+                Err(e) => {
+                    result.error = Some(format!("initialization failed: {}", e));
+                    break;
+                }
+            };
+            let hashrate = Self::measure(&hash_chain, work_registry, sweep.sample_time).await;
+            let temperatures = Self::read_temperatures(&hash_chain).await;
+            Self::power_off(&hash_chain).await;
+
+            let step = match hashrate {
+                Ok(hashrate) => SweepStep {
+                    frequency,
+                    chip_count: hash_chain.chip_count,
+                    hashrate,
+                    expected_hashrate: frequency
+                        * 1_000_000.0
+                        * (bm1387::NUM_CORES_ON_CHIP * expected_chip_count) as f64,
+                },
+                Err(e) => {
+                    result.error = Some(format!("measurement failed: {}", e));
+                    break;
+                }
+            };
+            let passed = step.passed(expected_chip_count);
+            result.steps.push(step);
+            if !passed {
+                break;
+            }
+            result.frequency = Some(frequency);
+
+            let too_hot = temperatures.map_or(true, |temperatures| {
+                temperatures
+                    .iter()
+                    .filter_map(|temperature| Option::<f32>::from(temperature.local.clone()))
+                    .any(|temperature| temperature > MAX_TEMPERATURE)
+            });
+            if too_hot {
+                result.error = Some("temperature cannot be verified or is too high".into());
+                break;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let sweep = Sweep {
+            start: 600.0,
+            end: 700.0,
+            step: 25.0,
+            sample_time: DEFAULT_SAMPLE_TIME,
+        };
+        assert_eq!(sweep.frequencies(), vec![600.0, 625.0, 650.0, 675.0, 700.0]);
+
+        let step = SweepStep {
+            frequency: 650.0,
+            chip_count: 63,
+            hashrate: 12.0e12,
+            expected_hashrate: 13.5e12,
+        };
+        assert!(step.passed(63));
+        assert!(!step.passed(62));
+        assert!(!SweepStep {
+            hashrate: 10.0e12,
+            ..step
+        }
+        .passed(63));
+    }
+}
@@ -22,155 +22,159 @@
 
 //! The Antminer S9 errors
 
-use failure::{Backtrace, Context, Fail};
-use std::fmt::{self, Debug, Display};
+use thiserror::Error;
+
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
 
 use std::io;
 use sysfs_gpio;
 use uio_async;
 
+/// Underlying error that caused `Error`
+pub type Source = Box<dyn StdError + Send + Sync + 'static>;
+
+/// Error is a pair of `ErrorKind` that gives the context (e.g. hashboard index) and an optional
+/// underlying error (available via `std::error::Error::source`)
+#[derive(Debug)]
 pub struct Error {
-    inner: Context<ErrorKind>,
+    kind: ErrorKind,
+    source: Option<Source>,
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum ErrorKind {
     /// General error used for more specific input/output error.
-    #[fail(display = "{}", _0)]
+    #[error("{0}")]
     General(String),
 
     /// Standard input/output error.
-    #[fail(display = "IO: {}", _0)]
+    #[error("IO: {0}")]
     Io(String),
 
     /// Error tied to a particular UIO device
-    #[fail(display = "UIO device {}: {}", _0, _1)]
+    #[error("UIO device {0}: {1}")]
     UioDevice(String, String),
 
     /// Generic UIO error
-    #[fail(display = "UIO: {}", _0)]
+    #[error("UIO: {0}")]
     Uio(String),
 
     /// Unexpected version of something.
-    #[fail(display = "Unexpected {} version: {}, expected: {}", _0, _1, _2)]
+    #[error("Unexpected {0} version: {1}, expected: {2}")]
     UnexpectedVersion(String, String, String),
 
+    /// FPGA bitstream version not supported by the driver
+    #[error("Hashboard {0}: incompatible s9-io bitstream {1}: {2}")]
+    IncompatibleBitstream(usize, String, String),
+
     /// Error concerning hashboard with specific index.
-    #[fail(display = "Hashboard {}: {}", _0, _1)]
+    #[error("Hashboard {0}: {1}")]
     Hashboard(usize, String),
 
     /// Error concerning hashchip.
-    #[fail(display = "Hashchip: {}", _0)]
+    #[error("Hashchip: {0}")]
     Hashchip(String),
 
     /// Error concerning hashchip enumeration.
-    #[fail(display = "Enumeration: {}", _0)]
+    #[error("Enumeration: {0}")]
     ChipEnumeration(String),
 
     /// Error concerning I2C on hashchip.
-    #[fail(display = "I2C hashchip: {}", _0)]
+    #[error("I2C hashchip: {0}")]
     I2cHashchip(String),
 
     /// Work or command FIFO timeout.
-    #[fail(display = "FIFO: {}: {}", _0, _1)]
+    #[error("FIFO: {0}: {1}")]
     Fifo(Fifo, String),
 
     /// Baud rate errors.
-    #[fail(display = "Baud rate: {}", _0)]
+    #[error("Baud rate: {0}")]
     BaudRate(String),
 
     /// GPIO errors.
-    #[fail(display = "GPIO: {}", _0)]
+    #[error("GPIO: {0}")]
     Gpio(String),
 
     /// I2C errors.
-    #[fail(display = "I2C: {}", _0)]
+    #[error("I2C: {0}")]
     I2c(String),
 
     /// Power controller errors.
-    #[fail(display = "Power: {}", _0)]
+    #[error("Power: {0}")]
     Power(String),
 
     /// PLL conversion error
-    #[fail(display = "PLL: {}", _0)]
+    #[error("PLL: {0}")]
     PLL(String),
 
     /// Error from hashchain manager.
-    #[fail(display = "HashChain Manager: {}", _0)]
+    #[error("HashChain Manager: {0}")]
     HashChainManager(HashChainManager),
 
     /// Error when halting.
-    #[fail(display = "Halt: {}", _0)]
+    #[error("Halt: {0}")]
     Halt(String),
 
     /// Error when dealing with sensors.
-    #[fail(display = "Sensors: {}", _0)]
+    #[error("Sensors: {0}")]
     Sensors(String),
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum HashChainManager {
-    #[fail(display = "HashChain parameters not set")]
+    #[error("HashChain parameters not set")]
     ParamsNotSet,
+    #[error("HashChain {0} is owned by {1}")]
+    Busy(usize, String),
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum Fifo {
-    #[fail(display = "timed out")]
+    #[error("timed out")]
     TimedOut,
 }
 
-/// Implement Fail trait instead of use Derive to get more control over custom type.
-/// The main advantage is customization of Context type which allows conversion of
-/// any error types to this custom error with general error kind by calling context
-/// method on any result type.
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
+impl Error {
+    /// Create error of `kind` caused by `source`
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: Into<Source>,
+    {
+        Self {
+            kind,
+            source: Some(source.into()),
+        }
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+    pub fn kind(&self) -> ErrorKind {
+        self.kind.clone()
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
-    }
-}
-
-impl Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Debug::fmt(&self.inner, f)
+        Display::fmt(&self.kind, f)
     }
 }
 
-impl Error {
-    pub fn kind(&self) -> ErrorKind {
-        self.inner.get_context().clone()
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn StdError + 'static))
     }
 }
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
-        Self {
-            inner: Context::new(kind),
-        }
+        Self { kind, source: None }
     }
 }
 
-impl From<Context<ErrorKind>> for Error {
-    fn from(inner: Context<ErrorKind>) -> Self {
-        Self { inner }
-    }
-}
-
-impl From<Context<String>> for Error {
-    fn from(context: Context<String>) -> Self {
-        Self {
-            inner: context.map(|info| ErrorKind::General(info)),
-        }
+impl From<String> for ErrorKind {
+    fn from(msg: String) -> Self {
+        ErrorKind::General(msg)
     }
 }
 
@@ -183,38 +187,96 @@ impl From<String> for Error {
 impl From<std::num::ParseIntError> for Error {
     fn from(e: std::num::ParseIntError) -> Self {
         let msg = e.to_string();
-        Self {
-            inner: e.context(ErrorKind::General(msg)),
-        }
+        Self::with_source(ErrorKind::General(msg), e)
     }
 }
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         let msg = e.to_string();
-        Self {
-            inner: e.context(ErrorKind::Io(msg)),
-        }
+        Self::with_source(ErrorKind::Io(msg), e)
     }
 }
 
 impl From<uio_async::UioError> for Error {
     fn from(uio_error: uio_async::UioError) -> Self {
         let msg = uio_error.to_string();
-        Self {
-            inner: uio_error.context(ErrorKind::Uio(msg)),
-        }
+        Self::with_source(ErrorKind::Uio(msg), uio_error)
     }
 }
 
 impl From<sysfs_gpio::Error> for Error {
     fn from(gpio_error: sysfs_gpio::Error) -> Self {
         let msg = gpio_error.to_string();
-        Self {
-            inner: gpio_error.context(ErrorKind::Gpio(msg)),
-        }
+        Self::with_source(ErrorKind::Gpio(msg), gpio_error)
+    }
+}
+
+/// Attach context (anything convertible to `ErrorKind`) to an error while keeping the original
+/// error as a source
+pub trait ResultExt<T, E> {
+    fn context<K>(self, kind: K) -> Result<T>
+    where
+        K: Into<ErrorKind>;
+
+    fn with_context<F, K>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>;
+}
+
+impl<T, E> ResultExt<T, E> for std::result::Result<T, E>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn context<K>(self, kind: K) -> Result<T>
+    where
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|e| Error::with_source(kind.into(), e))
+    }
+
+    fn with_context<F, K>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|e| {
+            let kind = f(&e).into();
+            Error::with_source(kind, e)
+        })
     }
 }
 
 /// A specialized `Result` type bound to [`Error`].
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_context_keeps_hashboard_idx() {
+        let result: std::result::Result<(), io::Error> =
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such pin"));
+        let error = result
+            .context(ErrorKind::Hashboard(
+                8,
+                "failed to initialize plug pin".into(),
+            ))
+            .unwrap_err();
+
+        assert_eq!(
+            error.kind(),
+            ErrorKind::Hashboard(8, "failed to initialize plug pin".into())
+        );
+        assert_eq!(
+            error.to_string(),
+            "Hashboard 8: failed to initialize plug pin"
+        );
+        assert_eq!(
+            error.source().expect("missing source").to_string(),
+            "no such pin"
+        );
+    }
+}
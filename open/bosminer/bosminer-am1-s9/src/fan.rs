@@ -22,44 +22,11 @@
 
 //! This module is responsible for reading fan feedback and setting fan PWM in FPGA controller.
 
-pub mod pid;
-
-use crate::error::{self, ErrorKind};
-use failure::ResultExt;
+pub use bosminer_antminer::fan::{pid, rpm, Driver, Feedback, Speed};
 
+use crate::error::{self, ErrorKind, ResultExt};
 use uio_async;
 
-/// Structure representing PWM of fan
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Speed(usize);
-
-impl Speed {
-    pub const FULL_SPEED: Self = Self(100);
-    pub const STOPPED: Self = Self(0);
-
-    pub fn new(speed: usize) -> Self {
-        assert!(speed <= 100);
-
-        Speed(speed)
-    }
-
-    pub fn to_pwm(&self) -> usize {
-        self.0
-    }
-}
-
-/// Speed of fans read from feedback pins
-#[derive(Debug, Clone)]
-pub struct Feedback {
-    pub rpm: Vec<usize>,
-}
-
-impl Feedback {
-    pub fn num_fans_running(&self) -> usize {
-        self.rpm.iter().filter(|rpm| **rpm > 0).count()
-    }
-}
-
 /// Memory-mapped fan controller
 pub struct Control {
     regs: uio_async::UioTypedMapping<ii_fpga_io_am1_s9::fan_ctrl::RegisterBlock>,
@@ -79,9 +46,11 @@ impl Control {
             regs: map.into_typed(),
         })
     }
+}
 
+impl Driver for Control {
     /// Read feedback registers and convert them to RPM
-    pub fn read_feedback(&self) -> Feedback {
+    fn read_feedback(&self) -> Feedback {
         Feedback {
             rpm: self
                 .regs
@@ -92,50 +61,11 @@ impl Control {
         }
     }
 
-    /// Set PWM for fans in percent (0 means fans stopped, 100 means fans on full)
-    pub fn set_speed(&self, speed: Speed) {
+    fn set_speed(&self, speed: Speed) {
         // Only lower 8 bits of FAN_PWM register are considered, so writing 256 would stop fans,
         // hence the assert.
-        assert!(speed.0 <= 100);
-        self.regs
-            .fan_pwm
-            .write(|w| unsafe { w.bits(speed.0 as u8) })
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_fan_speed() {
-        assert_eq!(Speed::STOPPED.0, 0);
-        assert_eq!(Speed::FULL_SPEED.0, 100);
-        assert_eq!(Speed::new(70).0, 70);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_fan_speed_fail() {
-        Speed::new(101);
-    }
-
-    #[test]
-    fn test_feedback_fan_count() {
-        assert_eq!(
-            Feedback {
-                rpm: vec![50, 0, 11, 0, 0]
-            }
-            .num_fans_running(),
-            2
-        );
-        assert_eq!(
-            Feedback {
-                rpm: vec![0, 0, 0, 0, 0]
-            }
-            .num_fans_running(),
-            0
-        );
-        assert_eq!(Feedback { rpm: Vec::new() }.num_fans_running(), 0);
+        let pwm = speed.to_pwm();
+        assert!(pwm <= 100);
+        self.regs.fan_pwm.write(|w| unsafe { w.bits(pwm as u8) })
     }
 }
@@ -0,0 +1,484 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Front panel of the control board. Green and red LEDs reflect the state of the miner which is
+//! derived from events published on the event bus. Presses of the reset and IP report buttons
+//! are published on the event bus as well and they trigger configurable actions.
+//!
+//! The red LED is shared with the identification (see `led::IdentifyLed`) which takes precedence
+//! while the miner is being identified.
+
+use ii_logging::macros::*;
+
+use crate::error;
+use crate::gpio;
+use crate::halt;
+use crate::led;
+use crate::shutdown;
+
+use bosminer::client;
+use bosminer::events;
+use bosminer::schedule;
+
+use embedded_hal::digital::v2::OutputPin;
+
+use futures::stream::StreamExt;
+use ii_async_compat::{futures, tokio};
+use tokio::sync::{broadcast, watch};
+use tokio::time::sleep;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Period of LED updates which is also the half period of LED blinking
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The miner is considered degraded for this time after the last fault event
+const FAULT_HOLD_TIME: Duration = Duration::from_secs(300);
+
+/// Button presses closer to each other are considered as bouncing of the button contacts
+const DEBOUNCE_TIME: Duration = Duration::from_millis(200);
+
+/// State of the miner signalled with the front LEDs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum State {
+    /// No hash chain has been started yet (green LED blinks)
+    Initializing,
+    /// Mining has been paused by user or by scheduled profile (green LED blinks as well)
+    Paused,
+    /// All hash chains are mining (green LED is on)
+    Mining,
+    /// Some hash chains are not mining or a fault occurred recently (green LED is on and red LED
+    /// blinks)
+    Degraded,
+    /// No hash chain is mining or the miner has been shut down (red LED is on)
+    Failure,
+}
+
+impl State {
+    /// Values of green and red LED in the `phase` of blinking
+    fn leds(&self, phase: bool) -> (bool, bool) {
+        match self {
+            Self::Initializing | Self::Paused => (phase, false),
+            Self::Mining => (true, false),
+            Self::Degraded => (true, phase),
+            Self::Failure => (false, true),
+        }
+    }
+}
+
+/// Derives the state of the miner from events
+#[derive(Debug)]
+struct Tracker {
+    /// Number of hash chains which are enabled
+    expected_chains: usize,
+    running_chains: HashSet<usize>,
+    started: bool,
+    shutdown: bool,
+    /// Mining has been paused by user
+    paused: bool,
+    /// Hash chains have been stopped by scheduled profile
+    paused_by_schedule: bool,
+    last_fault: Option<Instant>,
+}
+
+impl Tracker {
+    fn new(expected_chains: usize) -> Self {
+        Self {
+            expected_chains,
+            running_chains: HashSet::new(),
+            started: false,
+            shutdown: false,
+            paused: false,
+            paused_by_schedule: false,
+            last_fault: None,
+        }
+    }
+
+    /// Update pause by the active scheduled `profile`. Hash chains are started again when the
+    /// pause ends so the miner is initializing until the first of them is running.
+    fn set_profile(&mut self, profile: Option<&schedule::Profile>) {
+        let paused = profile.map_or(false, |profile| profile.paused);
+        if self.paused_by_schedule && !paused {
+            self.started = false;
+        }
+        self.paused_by_schedule = paused;
+    }
+
+    fn handle(&mut self, kind: &events::Kind, now: Instant) {
+        match kind {
+            events::Kind::ChainStarted { hashboard_idx } => {
+                self.started = true;
+                self.running_chains.insert(*hashboard_idx);
+            }
+            events::Kind::ChainStopped { hashboard_idx }
+            | events::Kind::ChainBroken { hashboard_idx, .. } => {
+                self.running_chains.remove(hashboard_idx);
+            }
+            events::Kind::ThermalShutdown { .. } => self.shutdown = true,
+            events::Kind::MiningPaused => self.paused = true,
+            events::Kind::MiningResumed => self.paused = false,
+            _ => {}
+        }
+        if kind.is_fault() {
+            self.last_fault = Some(now);
+        }
+    }
+
+    fn state(&self, now: Instant) -> State {
+        let recent_fault = self
+            .last_fault
+            .map_or(false, |time| now.duration_since(time) < FAULT_HOLD_TIME);
+        if self.shutdown {
+            State::Failure
+        } else if self.paused || self.paused_by_schedule {
+            State::Paused
+        } else if !self.started {
+            State::Initializing
+        } else if self.running_chains.is_empty() {
+            State::Failure
+        } else if self.running_chains.len() < self.expected_chains || recent_fault {
+            State::Degraded
+        } else {
+            State::Mining
+        }
+    }
+}
+
+/// Buttons on the front panel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Button {
+    Reset,
+    IpReport,
+}
+
+impl Button {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Reset => "reset",
+            Self::IpReport => "ip_report",
+        }
+    }
+
+    fn pin_name(&self) -> gpio::PinInName {
+        match self {
+            Self::Reset => gpio::PinInName::ResetButton,
+            Self::IpReport => gpio::PinInName::IPSelect,
+        }
+    }
+}
+
+/// Action triggered by pressing a button
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    /// The press is only published as an event
+    None,
+    /// Start identification of the miner or stop it when it is running
+    Identify,
+    /// Reconnect all enabled pools
+    RestartPools,
+    /// Terminate the miner so that it is started again by the service manager
+    Restart,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Reflect the state of the miner with front LEDs
+    pub status_leds: bool,
+    pub reset_action: Action,
+    pub ip_report_action: Action,
+}
+
+pub struct FrontPanel {
+    config: Config,
+    event_bus: Arc<events::Bus>,
+    identify_led: Option<Arc<led::IdentifyLed>>,
+    client_manager: client::Manager,
+    app_halt_sender: Arc<halt::Sender>,
+}
+
+impl FrontPanel {
+    pub fn new(
+        config: Config,
+        event_bus: Arc<events::Bus>,
+        identify_led: Option<Arc<led::IdentifyLed>>,
+        client_manager: client::Manager,
+        app_halt_sender: Arc<halt::Sender>,
+    ) -> Self {
+        Self {
+            config,
+            event_bus,
+            identify_led,
+            client_manager,
+            app_halt_sender,
+        }
+    }
+
+    /// Start LED and button tasks. It has to be called before hash chains are started so that
+    /// no event is missed. Unavailable LEDs or buttons are reported and skipped.
+    /// `expected_chains` is the number of enabled hash chains and `profile_receiver` tracks
+    /// the scheduled profile which may pause them.
+    pub fn start(
+        self: Arc<Self>,
+        gpio_mgr: &gpio::ControlPinManager,
+        expected_chains: usize,
+        profile_receiver: watch::Receiver<Option<schedule::Profile>>,
+    ) {
+        if self.config.status_leds {
+            match Self::open_leds(gpio_mgr) {
+                Ok((green, red)) => {
+                    tokio::spawn(self.clone().led_task(
+                        green,
+                        red,
+                        Tracker::new(expected_chains),
+                        self.event_bus.subscribe(),
+                        profile_receiver,
+                    ));
+                }
+                Err(e) => warn!("Front panel: status LEDs are not available: {}", e),
+            }
+        }
+        for button in [Button::Reset, Button::IpReport].iter() {
+            match gpio_mgr
+                .get_pin_in(button.pin_name())
+                .and_then(|pin| pin.events(gpio::Edge::Falling))
+            {
+                Ok(events) => {
+                    tokio::spawn(self.clone().button_task(*button, events));
+                }
+                Err(e) => warn!(
+                    "Front panel: button '{}' is not available: {}",
+                    button.name(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn open_leds(
+        gpio_mgr: &gpio::ControlPinManager,
+    ) -> error::Result<(gpio::PinOut, gpio::PinOut)> {
+        Ok((
+            gpio_mgr.get_pin_out(gpio::PinOutName::LEDFrontGreen)?,
+            gpio_mgr.get_pin_out(gpio::PinOutName::LEDFrontRed)?,
+        ))
+    }
+
+    fn set_led(pin: &mut gpio::PinOut, on: bool) -> error::Result<()> {
+        if on {
+            pin.set_high()?;
+        } else {
+            pin.set_low()?;
+        }
+        Ok(())
+    }
+
+    fn identifying(&self) -> bool {
+        self.identify_led
+            .as_ref()
+            .map_or(false, |identify_led| identify_led.remaining().is_some())
+    }
+
+    async fn led_task(
+        self: Arc<Self>,
+        mut green: gpio::PinOut,
+        mut red: gpio::PinOut,
+        mut tracker: Tracker,
+        mut receiver: broadcast::Receiver<events::Event>,
+        profile_receiver: watch::Receiver<Option<schedule::Profile>>,
+    ) {
+        let mut phase = false;
+        let mut last_state = None;
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(event) => tracker.handle(&event.kind, Instant::now()),
+                    Err(broadcast::error::TryRecvError::Lagged(count)) => {
+                        warn!("Front panel: missed {} event(s)", count)
+                    }
+                    Err(broadcast::error::TryRecvError::Empty) => break,
+                    Err(broadcast::error::TryRecvError::Closed) => return,
+                }
+            }
+
+            tracker.set_profile(profile_receiver.borrow().as_ref());
+            let state = tracker.state(Instant::now());
+            if last_state != Some(state) {
+                debug!("Front panel: miner state {:?}", state);
+                last_state = Some(state);
+            }
+            phase = !phase;
+            let (green_on, red_on) = state.leds(phase);
+            let mut result = Self::set_led(&mut green, green_on);
+            if !self.identifying() {
+                result = result.and(Self::set_led(&mut red, red_on));
+            }
+            if let Err(e) = result {
+                warn!("Front panel: cannot set status LEDs: {}", e);
+            }
+            sleep(TICK_INTERVAL).await;
+        }
+    }
+
+    fn action(&self, button: Button) -> Action {
+        match button {
+            Button::Reset => self.config.reset_action,
+            Button::IpReport => self.config.ip_report_action,
+        }
+    }
+
+    async fn button_task(self: Arc<Self>, button: Button, mut events: gpio::PinEvents) {
+        let mut last_press: Option<Instant> = None;
+        while let Some(value) = events.next().await {
+            // Buttons are active low
+            if value {
+                continue;
+            }
+            let now = Instant::now();
+            if last_press.map_or(false, |time| now.duration_since(time) < DEBOUNCE_TIME) {
+                continue;
+            }
+            last_press = Some(now);
+
+            self.event_bus.publish(events::Kind::ButtonPressed {
+                button: button.name().to_string(),
+            });
+            self.run_action(self.action(button)).await;
+        }
+    }
+
+    async fn run_action(&self, action: Action) {
+        match action {
+            Action::None => {}
+            Action::Identify => match &self.identify_led {
+                Some(identify_led) => identify_led.identify(if self.identifying() {
+                    Duration::from_secs(0)
+                } else {
+                    led::DEFAULT_IDENTIFY_TIME
+                }),
+                None => warn!("Front panel: identification LED is not available"),
+            },
+            Action::RestartPools => {
+                info!("Front panel: restarting pools");
+                self.client_manager.restart_clients().await;
+            }
+            Action::Restart => {
+                info!("Front panel: restarting miner");
+                self.app_halt_sender
+                    .clone()
+                    .send_shutdown(
+                        shutdown::Reason::UserRequest,
+                        "front panel: restart requested".to_string(),
+                    )
+                    .await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(2);
+        assert_eq!(tracker.state(start), State::Initializing);
+
+        tracker.handle(&events::Kind::ChainStarted { hashboard_idx: 6 }, start);
+        assert_eq!(tracker.state(start), State::Degraded);
+        tracker.handle(&events::Kind::ChainStarted { hashboard_idx: 7 }, start);
+        assert_eq!(tracker.state(start), State::Mining);
+
+        // Fault keeps the miner degraded for a while
+        let fault = events::Kind::SensorFailed {
+            hashboard_idx: 6,
+            reason: "timeout".into(),
+        };
+        tracker.handle(&fault, start);
+        assert_eq!(tracker.state(start), State::Degraded);
+        assert_eq!(tracker.state(start + FAULT_HOLD_TIME), State::Mining);
+
+        tracker.handle(&events::Kind::ChainStopped { hashboard_idx: 6 }, start);
+        tracker.handle(
+            &events::Kind::ChainBroken {
+                hashboard_idx: 7,
+                reason: "hashboard unplugged".into(),
+            },
+            start,
+        );
+        assert_eq!(tracker.state(start + FAULT_HOLD_TIME), State::Failure);
+    }
+
+    #[test]
+    fn test_thermal_shutdown() {
+        let now = Instant::now();
+        let mut tracker = Tracker::new(1);
+        tracker.handle(&events::Kind::ChainStarted { hashboard_idx: 6 }, now);
+        tracker.handle(
+            &events::Kind::ThermalShutdown {
+                reason: "temperature above DANGEROUS".into(),
+            },
+            now,
+        );
+        assert_eq!(tracker.state(now), State::Failure);
+    }
+
+    #[test]
+    fn test_paused() {
+        let now = Instant::now();
+        let mut tracker = Tracker::new(1);
+        tracker.handle(&events::Kind::ChainStarted { hashboard_idx: 6 }, now);
+        tracker.handle(&events::Kind::MiningPaused, now);
+        assert_eq!(tracker.state(now), State::Paused);
+        tracker.handle(&events::Kind::MiningResumed, now);
+        assert_eq!(tracker.state(now), State::Mining);
+
+        // chains stopped by schedule don't mean failure
+        let profile = schedule::Profile::from_config(&bosminer_config::ProfileConfig {
+            name: "night".into(),
+            paused: Some(true),
+            ..Default::default()
+        })
+        .expect("invalid profile");
+        tracker.set_profile(Some(&profile));
+        tracker.handle(&events::Kind::ChainStopped { hashboard_idx: 6 }, now);
+        assert_eq!(tracker.state(now), State::Paused);
+        // chains are initializing again when the pause ends
+        tracker.set_profile(None);
+        assert_eq!(tracker.state(now), State::Initializing);
+        tracker.handle(&events::Kind::ChainStarted { hashboard_idx: 6 }, now);
+        assert_eq!(tracker.state(now), State::Mining);
+    }
+
+    #[test]
+    fn test_leds() {
+        assert_eq!(State::Mining.leds(false), (true, false));
+        assert_eq!(State::Initializing.leds(true), (true, false));
+        assert_eq!(State::Initializing.leds(false), (false, false));
+        assert_eq!(State::Paused.leds(true), (true, false));
+        assert_eq!(State::Degraded.leds(true), (true, true));
+        assert_eq!(State::Failure.leds(false), (false, true));
+    }
+}
@@ -28,24 +28,50 @@
 //!
 //! Termination context means that task is run `select`-ed on termination condition, and when
 //! that condition is signaled, select returns and the task is dropped.
+//!
+//! Contexts form a hierarchy: a child context is halted together with its parent and the reason
+//! of the halt is propagated to all clients. The state of all registered clients can be obtained
+//! with `Sender::status` which helps to find out which client blocks the halt.
+
+use ii_logging::macros::*;
 
+use std::fmt;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex, Weak};
 use std::time::Duration;
 
 use crate::error;
+use crate::shutdown;
 use error::ErrorKind;
 
 use futures::channel::mpsc;
 use futures::future::{select, Either};
 use futures::lock::Mutex;
 use ii_async_compat::prelude::*;
+use ii_async_compat::task::spawn_named;
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 use tokio::task;
 
+/// Name of contexts created with `make_pair`
+pub const ROOT_NAME: &str = "root";
+
+/// Reason used when halt is issued without specifying it
+pub const UNSPECIFIED_REASON: &str = "unspecified";
+
 /// Token sent by halted task to confirm that halting is done
 struct Done;
 
+/// Error returned by `Receiver::sleep` when the wait has been interrupted by halt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Halted;
+
+impl fmt::Display for Halted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "halted")
+    }
+}
+
 /// Receiver side of "halt done" confirmation
 struct DoneReceiver {
     done_rx: mpsc::UnboundedReceiver<Done>,
@@ -54,9 +80,15 @@ struct DoneReceiver {
 /// Sender side of "halt done" confirmation
 pub struct DoneSender {
     done_tx: mpsc::UnboundedSender<Done>,
+    reason: Arc<String>,
 }
 
 impl DoneSender {
+    /// Why the context has been halted
+    pub fn reason(&self) -> &str {
+        self.reason.as_str()
+    }
+
     /// Confirm halt has been done
     pub fn confirm(self) {
         self.done_tx
@@ -65,16 +97,18 @@ impl DoneSender {
     }
 }
 
-fn make_done_pair() -> (DoneSender, DoneReceiver) {
+fn make_done_pair(reason: Arc<String>) -> (DoneSender, DoneReceiver) {
     let (done_tx, done_rx) = mpsc::unbounded();
 
-    (DoneSender { done_tx }, DoneReceiver { done_rx })
+    (DoneSender { done_tx, reason }, DoneReceiver { done_rx })
 }
 
 /// One (non-clonable) instance of receiver
 /// In case the sender ends, the `recv` part of channel receives `None` as EOF
 pub struct NotifyReceiver {
     notify_rx: mpsc::UnboundedReceiver<DoneSender>,
+    /// Name under which the spawned task is registered in the task registry
+    name: String,
 }
 
 impl NotifyReceiver {
@@ -88,7 +122,8 @@ impl NotifyReceiver {
     where
         F: Future<Output = ()> + 'static + Send,
     {
-        tokio::spawn(async move {
+        let name = self.name.clone();
+        spawn_named(name, async move {
             if let Some(done_sender) = self.wait_for_halt().await {
                 f.await;
                 done_sender.confirm();
@@ -101,7 +136,8 @@ impl NotifyReceiver {
     where
         F: Future<Output = ()> + 'static + Send,
     {
-        tokio::spawn(async move {
+        let name = self.name.clone();
+        spawn_named(name, async move {
             match select(f.boxed(), self.wait_for_halt().boxed()).await {
                 // in case we received halt notification, reply and exit
                 Either::Right((halt_result, _)) => {
@@ -120,10 +156,83 @@ impl NotifyReceiver {
     }
 }
 
+/// State of a client as seen by the halt sender
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientState {
+    /// Waiting for halt
+    Registered,
+    /// Halt has been sent and the sender waits for confirmation
+    Halting,
+    /// Client confirmed the halt
+    Halted,
+    /// Client ended before halt was sent
+    Ended,
+    /// Client dropped the halt handle without confirmation
+    Dropped,
+    /// Client didn't confirm the halt in time
+    TimedOut,
+}
+
+impl fmt::Display for ClientState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::Registered => "registered",
+            Self::Halting => "halting",
+            Self::Halted => "halted",
+            Self::Ended => "ended",
+            Self::Dropped => "dropped",
+            Self::TimedOut => "timed out",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientStatus {
+    pub name: String,
+    pub state: ClientState,
+}
+
+/// Snapshot of one halt context including all its children
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub name: String,
+    /// Reason of the halt when it has already been issued
+    pub reason: Option<String>,
+    pub clients: Vec<ClientStatus>,
+    pub children: Vec<Status>,
+}
+
+impl Status {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        write!(f, "{}context '{}'", indent, self.name)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " (halted: {})", reason)?;
+        }
+        writeln!(f)?;
+        for client in self.clients.iter() {
+            writeln!(f, "{}  client '{}': {}", indent, client.name, client.state)?;
+        }
+        for child in self.children.iter() {
+            child.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
 /// One halt receiver as seen by halt sender
 struct NotifySender {
     notify_tx: mpsc::UnboundedSender<DoneSender>,
     name: String,
+    /// Index of client state in `Sender::client_states`
+    id: usize,
 }
 
 impl NotifySender {
@@ -132,8 +241,8 @@ impl NotifySender {
     /// the "halted" section exited by itself).
     /// Return of `Some(done)` means the other side received the notification and will report
     /// back via `done` channel.
-    pub fn send_halt(&self) -> Option<DoneReceiver> {
-        let (done_sender, done_receiver) = make_done_pair();
+    pub fn send_halt(&self, reason: Arc<String>) -> Option<DoneReceiver> {
+        let (done_sender, done_receiver) = make_done_pair(reason);
 
         if self.notify_tx.unbounded_send(done_sender).is_ok() {
             Some(done_receiver)
@@ -143,12 +252,16 @@ impl NotifySender {
     }
 }
 
-fn make_notify_pair(name: String) -> (NotifySender, NotifyReceiver) {
+fn make_notify_pair(name: String, id: usize) -> (NotifySender, NotifyReceiver) {
     let (notify_tx, notify_rx) = mpsc::unbounded();
 
     (
-        NotifySender { notify_tx, name },
-        NotifyReceiver { notify_rx },
+        NotifySender {
+            notify_tx,
+            name: name.clone(),
+            id,
+        },
+        NotifyReceiver { notify_rx, name },
     )
 }
 
@@ -165,11 +278,71 @@ impl Receiver {
     pub async fn register_client(&self, name: String) -> NotifyReceiver {
         self.sender.clone().register_client(name).await
     }
+
+    /// Whether halt of this context (or any of its parents) has been issued
+    pub fn is_halted(&self) -> bool {
+        *self.sender.halted.borrow()
+    }
+
+    /// Sleep for `duration` unless the context is halted. Halt interrupts the sleep immediately
+    /// (even before the halt is delivered to the client) so retry and periodic loops don't
+    /// delay the shutdown.
+    pub async fn sleep(&self, duration: Duration) -> Result<(), Halted> {
+        let mut halted = self.sender.halted.subscribe();
+        if *halted.borrow_and_update() {
+            return Err(Halted);
+        }
+        match select(
+            tokio::time::sleep(duration).boxed(),
+            halted.wait_for(|halted| *halted).boxed(),
+        )
+        .await
+        {
+            Either::Left(_) => Ok(()),
+            Either::Right(_) => Err(Halted),
+        }
+    }
+
+    /// Create a child context which is halted together with this one (with the same reason).
+    /// The child context can also be halted on its own.
+    pub async fn make_child(&self, name: String) -> (Arc<Sender>, Receiver) {
+        let (child_sender, child_receiver) =
+            make_named_pair(name.clone(), self.sender.halt_timeout);
+        self.sender
+            .children
+            .lock()
+            .expect("BUG: lock halt children")
+            .push(Arc::downgrade(&child_sender));
+
+        let notify_receiver = self.register_client(name.clone()).await;
+        let sender = child_sender.clone();
+        spawn_named(name, async move {
+            if let Some(done_sender) = notify_receiver.wait_for_halt().await {
+                sender
+                    .send_halt_with_reason(done_sender.reason().to_string())
+                    .await;
+                done_sender.confirm();
+            }
+        });
+
+        (child_sender, child_receiver)
+    }
 }
 
 /// One halt context capable of notifying all of registered `clients`
 pub struct Sender {
+    name: String,
     clients: Mutex<Vec<NotifySender>>,
+    /// States of all clients ever registered indexed by `NotifySender::id`
+    client_states: StdMutex<Vec<ClientStatus>>,
+    /// Child contexts which are halted together with this one
+    children: StdMutex<Vec<Weak<Sender>>>,
+    /// Reason of the first halt issued on this context
+    reason: StdMutex<Option<Arc<String>>>,
+    /// Structured reason of the first shutdown issued on this context
+    shutdown_reason: StdMutex<Option<shutdown::Reason>>,
+    /// Flag set as soon as the halt is issued to interrupt `Receiver::sleep`
+    halted: watch::Sender<bool>,
     exit_hooks: Mutex<Vec<Pin<Box<dyn Future<Output = ()> + 'static + Send>>>>,
     /// How long to wait for client to finish
     halt_timeout: Duration,
@@ -177,9 +350,15 @@ pub struct Sender {
 
 impl Sender {
     /// Create new Sender
-    fn new(halt_timeout: Duration) -> Arc<Self> {
+    fn new(name: String, halt_timeout: Duration) -> Arc<Self> {
         Arc::new(Self {
+            name,
             clients: Mutex::new(Vec::new()),
+            client_states: StdMutex::new(Vec::new()),
+            children: StdMutex::new(Vec::new()),
+            reason: StdMutex::new(None),
+            shutdown_reason: StdMutex::new(None),
+            halted: watch::channel(false).0,
             halt_timeout,
             exit_hooks: Mutex::new(Vec::new()),
         })
@@ -187,11 +366,72 @@ impl Sender {
 
     /// Register one client. Available only through `Receiver` API
     async fn register_client(self: Arc<Self>, name: String) -> NotifyReceiver {
-        let (notify_sender, notify_receiver) = make_notify_pair(name);
+        let id = {
+            let mut client_states = self.client_states.lock().expect("BUG: lock halt states");
+            client_states.push(ClientStatus {
+                name: name.clone(),
+                state: ClientState::Registered,
+            });
+            client_states.len() - 1
+        };
+        let (notify_sender, notify_receiver) = make_notify_pair(name, id);
         self.clients.lock().await.push(notify_sender);
         notify_receiver
     }
 
+    /// Interrupt sleeping clients of this context and all child contexts
+    fn mark_halted(&self) {
+        self.halted.send_replace(true);
+        for child in self
+            .children
+            .lock()
+            .expect("BUG: lock halt children")
+            .iter()
+            .filter_map(Weak::upgrade)
+        {
+            child.mark_halted();
+        }
+    }
+
+    fn set_client_state(&self, id: usize, state: ClientState) {
+        self.client_states.lock().expect("BUG: lock halt states")[id].state = state;
+    }
+
+    /// Snapshot of this context, its clients and all (still existing) child contexts
+    pub fn status(&self) -> Status {
+        let children: Vec<_> = self
+            .children
+            .lock()
+            .expect("BUG: lock halt children")
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        Status {
+            name: self.name.clone(),
+            reason: self
+                .reason
+                .lock()
+                .expect("BUG: lock halt reason")
+                .as_ref()
+                .map(|reason| reason.to_string()),
+            clients: self
+                .client_states
+                .lock()
+                .expect("BUG: lock halt states")
+                .clone(),
+            children: children.iter().map(|child| child.status()).collect(),
+        }
+    }
+
+    /// Structured reason of the shutdown or `None` when the context has been halted with
+    /// `send_halt` only
+    pub fn shutdown_reason(&self) -> Option<shutdown::Reason> {
+        *self
+            .shutdown_reason
+            .lock()
+            .expect("BUG: lock halt shutdown reason")
+    }
+
     /// Register hook that is to be executed after all futures terminated
     pub async fn add_exit_hook<F>(&self, f: F)
     where
@@ -205,31 +445,50 @@ impl Sender {
     /// tasks was halted (we send them channel to reply back) and one of them would be dropped
     /// before it had a chance to run (ie. as a result of another task that is being terminated
     /// dropping it in termination handler) it wouldn't respond with "termination successful".
-    async fn send_halt_internal(self: Arc<Self>) -> error::Result<()> {
+    async fn send_halt_internal(self: Arc<Self>, reason: String) -> error::Result<()> {
+        // the first reason wins when the context is halted multiple times
+        let reason = self
+            .reason
+            .lock()
+            .expect("BUG: lock halt reason")
+            .get_or_insert_with(|| Arc::new(reason))
+            .clone();
+        self.mark_halted();
+
         // take the list of clients
         let mut clients: Vec<_> = self.clients.lock().await.drain(..).collect();
 
         // notify clients one-by-one
         for client in clients.drain(..) {
             // try to halt them
-            let mut done_wait = match client.send_halt() {
+            let mut done_wait = match client.send_halt(reason.clone()) {
                 // client has already ended
-                None => continue,
+                None => {
+                    self.set_client_state(client.id, ClientState::Ended);
+                    continue;
+                }
                 // extract handle, wait on it later
                 Some(handle) => handle,
             };
+            self.set_client_state(client.id, ClientState::Halting);
             match done_wait.done_rx.next().timeout(self.halt_timeout).await {
                 Ok(confirm) => match confirm {
-                    Some(_) => (),
-                    None => Err(ErrorKind::Halt(format!(
-                        "failed to halt client {}: dropped handle",
-                        client.name
-                    )))?,
+                    Some(_) => self.set_client_state(client.id, ClientState::Halted),
+                    None => {
+                        self.set_client_state(client.id, ClientState::Dropped);
+                        Err(ErrorKind::Halt(format!(
+                            "failed to halt client {} of context {} ({}): dropped handle",
+                            client.name, self.name, reason
+                        )))?
+                    }
                 },
-                Err(_) => Err(ErrorKind::Halt(format!(
-                    "failed to halt client {}: timeout",
-                    client.name
-                )))?,
+                Err(_) => {
+                    self.set_client_state(client.id, ClientState::TimedOut);
+                    Err(ErrorKind::Halt(format!(
+                        "failed to halt client {} of context {} ({}): timeout",
+                        client.name, self.name, reason
+                    )))?
+                }
             }
         }
 
@@ -244,43 +503,70 @@ impl Sender {
     /// additional threads.
     pub fn hook_termination_signals(self: Arc<Self>) {
         // Hook `SIGINT`, `SIGHUP` and `SIGTERM`
-        for signal_type in vec![
-            SignalKind::interrupt(),
-            SignalKind::hangup(),
-            SignalKind::terminate(),
+        for (signal_type, signal_name) in vec![
+            (SignalKind::interrupt(), "SIGINT"),
+            (SignalKind::hangup(), "SIGHUP"),
+            (SignalKind::terminate(), "SIGTERM"),
         ] {
             let halt_sender = self.clone();
             tokio::spawn(async move {
                 if let Some(_) = signal(signal_type)
                     .expect("BUG: failed hooking signal")
-                    .next()
+                    .recv()
                     .await
                 {
                     // Exit after receiving signal
-                    halt_sender.send_halt().await;
+                    halt_sender
+                        .send_shutdown(
+                            shutdown::Reason::UserRequest,
+                            format!("received {}", signal_name),
+                        )
+                        .await;
                 }
             });
         }
     }
 
+    /// Halt the context with a structured `shutdown_reason` which determines the exit code of
+    /// the miner. The first reason wins when the context is shut down multiple times.
+    pub async fn send_shutdown(self: Arc<Self>, shutdown_reason: shutdown::Reason, reason: String) {
+        self.shutdown_reason
+            .lock()
+            .expect("BUG: lock halt shutdown reason")
+            .get_or_insert(shutdown_reason);
+        self.send_halt_with_reason(reason).await
+    }
+
     pub async fn send_halt(self: Arc<Self>) {
+        self.send_halt_with_reason(UNSPECIFIED_REASON.to_string())
+            .await
+    }
+
+    /// Halt all clients and child contexts and pass them the `reason` of the halt
+    pub async fn send_halt_with_reason(self: Arc<Self>, reason: String) {
         let (finish_tx, mut finish_rx) = mpsc::unbounded();
+        let sender = self.clone();
         let handle: task::JoinHandle<error::Result<()>> = tokio::spawn(async move {
-            self.send_halt_internal().await?;
+            sender.send_halt_internal(reason).await?;
             let _result = finish_tx.unbounded_send(());
             Ok(())
         });
         finish_rx.next().await;
-        handle
-            .await
-            .expect("halt task has panicked")
-            .expect("halt failed");
+        if let Err(e) = handle.await.expect("halt task has panicked") {
+            error!("Halt failed: {}\n{}", e, self.status());
+            panic!("halt failed: {}", e);
+        }
     }
 }
 
 /// Build a halt sender/receiver pair
 pub fn make_pair(halt_timeout: Duration) -> (Arc<Sender>, Receiver) {
-    let sender = Sender::new(halt_timeout);
+    make_named_pair(ROOT_NAME.to_string(), halt_timeout)
+}
+
+/// Build a halt sender/receiver pair of context named `name`
+pub fn make_named_pair(name: String, halt_timeout: Duration) -> (Arc<Sender>, Receiver) {
+    let sender = Sender::new(name, halt_timeout);
     let receiver = Receiver {
         sender: sender.clone(),
     };
@@ -292,7 +578,7 @@ pub fn make_pair(halt_timeout: Duration) -> (Arc<Sender>, Receiver) {
 mod test {
     use super::*;
     use std::sync::atomic::{AtomicBool, Ordering};
-    use tokio::time::delay_for;
+    use tokio::time::sleep;
 
     // Test that if cleanup after halt takes too long, halter will panic
     #[tokio::test]
@@ -304,7 +590,7 @@ mod test {
         tokio::spawn(async move {
             if let Some(done) = notify_receiver.wait_for_halt().await {
                 // do a long halt cleanup
-                delay_for(Duration::from_secs(100)).await;
+                sleep(Duration::from_secs(100)).await;
                 done.confirm();
             }
         });
@@ -380,7 +666,7 @@ mod test {
 
         // Task 1: started in termination context, issues halt
         notify_receiver1.spawn(async move {
-            delay_for(Duration::from_millis(5)).await;
+            sleep(Duration::from_millis(5)).await;
             sender.send_halt().await;
         });
         // Task 2: started in normal context, waits for halt
@@ -396,10 +682,88 @@ mod test {
         if let Some(done) = main_receiver.wait_for_halt().await {
             done.confirm();
             // Wait for task 2 to run
-            delay_for(Duration::from_millis(50)).await;
+            sleep(Duration::from_millis(50)).await;
             assert_eq!(halted_flag.load(Ordering::Relaxed), true);
         } else {
             panic!("no halt received!");
         }
     }
+
+    // Test that halting parent halts children and passes them the reason
+    #[tokio::test]
+    async fn test_halt_child() {
+        let (sender, receiver) = make_pair(Duration::from_millis(50));
+        let (child_sender, child_receiver) = receiver.make_child("child".into()).await;
+        let notify_receiver = child_receiver.register_client("task".into()).await;
+        let (reason_tx, mut reason_rx) = mpsc::unbounded();
+
+        tokio::spawn(async move {
+            if let Some(done) = notify_receiver.wait_for_halt().await {
+                reason_tx.unbounded_send(done.reason().to_string()).unwrap();
+                done.confirm();
+            }
+        });
+
+        sender.clone().send_halt_with_reason("test".into()).await;
+        assert_eq!(reason_rx.next().await, Some("test".to_string()));
+
+        let status = sender.status();
+        assert_eq!(status.reason, Some("test".to_string()));
+        assert_eq!(
+            status.clients,
+            vec![ClientStatus {
+                name: "child".into(),
+                state: ClientState::Halted,
+            }]
+        );
+        assert_eq!(status.children, vec![child_sender.status()]);
+        assert_eq!(status.children[0].clients[0].state, ClientState::Halted);
+    }
+
+    // Test that halting child doesn't halt its parent
+    #[tokio::test]
+    async fn test_halt_child_alone() {
+        let (sender, receiver) = make_pair(Duration::from_millis(50));
+        let (child_sender, _child_receiver) = receiver.make_child("child".into()).await;
+        let _notify_receiver = receiver.register_client("task".into()).await;
+
+        child_sender.clone().send_halt().await;
+        assert_eq!(
+            child_sender.status().reason,
+            Some(UNSPECIFIED_REASON.into())
+        );
+
+        let status = sender.status();
+        assert_eq!(status.reason, None);
+        assert!(status
+            .clients
+            .iter()
+            .all(|client| client.state == ClientState::Registered));
+    }
+
+    // Test that halt interrupts sleep of the context and its children
+    #[tokio::test]
+    async fn test_halt_sleep() {
+        let (sender, receiver) = make_pair(Duration::from_millis(50));
+        let (_child_sender, child_receiver) = receiver.make_child("child".into()).await;
+
+        assert_eq!(receiver.sleep(Duration::from_millis(1)).await, Ok(()));
+        let sleeping = tokio::spawn(async move {
+            let result = child_receiver.sleep(Duration::from_secs(100)).await;
+            (result, child_receiver.is_halted())
+        });
+
+        sender.clone().send_halt().await;
+        let (result, halted) = sleeping
+            .timeout(Duration::from_secs(1))
+            .await
+            .expect("sleep hasn't been interrupted")
+            .expect("BUG: sleeping task panicked");
+        assert_eq!(result, Err(Halted));
+        assert!(halted);
+
+        // sleep in halted context returns immediately
+        assert!(receiver.is_halted());
+        assert_eq!(receiver.sleep(Duration::from_secs(100)).await, Err(Halted));
+    }
 }
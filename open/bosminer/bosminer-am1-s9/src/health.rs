@@ -0,0 +1,105 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Tracking of hashchain health that is reported via CGMiner `notify` command
+
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Reason why the hashchain has been considered not well
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// Hashchain initialization failed
+    InitFailed,
+    /// Communication with hashchain (e.g. temperature sensor) failed
+    CommsError,
+    /// Commands sent to chips failed even after retries (usually due to damaged cable)
+    CommandBusErrors,
+}
+
+/// Snapshot of the hashchain health
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Health {
+    pub last_well: Option<SystemTime>,
+    pub last_not_well: Option<SystemTime>,
+    pub reason: Option<Reason>,
+    pub init_failures: u32,
+    pub comms_errors: u32,
+    pub command_bus_errors: u32,
+}
+
+/// Shared health tracker that is kept across hashchain restarts
+#[derive(Debug, Default)]
+pub struct Tracker {
+    inner: Mutex<Health>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record that the hashchain is working as expected
+    pub fn report_well(&self) {
+        self.inner.lock().expect("BUG: lock failed").last_well = Some(SystemTime::now());
+    }
+
+    /// Record a failure of the hashchain
+    pub fn report_not_well(&self, reason: Reason) {
+        let mut inner = self.inner.lock().expect("BUG: lock failed");
+        inner.last_not_well = Some(SystemTime::now());
+        inner.reason = Some(reason);
+        match reason {
+            Reason::InitFailed => inner.init_failures += 1,
+            Reason::CommsError => inner.comms_errors += 1,
+            Reason::CommandBusErrors => inner.command_bus_errors += 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> Health {
+        self.inner.lock().expect("BUG: lock failed").clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report_not_well() {
+        let tracker = Tracker::new();
+        assert_eq!(tracker.snapshot(), Health::default());
+
+        tracker.report_well();
+        tracker.report_not_well(Reason::CommsError);
+        tracker.report_not_well(Reason::CommandBusErrors);
+        tracker.report_not_well(Reason::InitFailed);
+
+        let health = tracker.snapshot();
+        assert!(health.last_well.is_some());
+        assert!(health.last_not_well.is_some());
+        assert_eq!(health.reason, Some(Reason::InitFailed));
+        assert_eq!(health.init_failures, 1);
+        assert_eq!(health.comms_errors, 1);
+        assert_eq!(health.command_bus_errors, 1);
+    }
+}
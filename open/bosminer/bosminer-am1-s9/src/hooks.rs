@@ -20,17 +20,52 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use ii_logging::macros::*;
+
+use crate::counters;
 use crate::halt;
 use crate::monitor;
+use crate::power;
+use crate::sensor;
+use crate::FrequencySettings;
 use crate::Manager;
 
 use bosminer::client;
 
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 
+/// Change of hash chain operating point requested through `RunningChain`
+#[derive(Clone)]
+pub enum TuningEvent {
+    Frequency(FrequencySettings),
+    Voltage(power::Voltage),
+}
+
+/// State of one running hash chain at the time the telemetry snapshot was taken
+#[derive(Clone)]
+pub struct ChainTelemetry {
+    pub hashboard_idx: usize,
+    pub chip_count: usize,
+    pub frequency: FrequencySettings,
+    pub voltage: power::Voltage,
+    pub temperature: Option<sensor::Temperature>,
+    pub counter: counters::HashChain,
+}
+
+/// Periodic snapshot of the whole miner
+#[derive(Clone)]
+pub struct Telemetry {
+    pub timestamp: SystemTime,
+    /// Last status reported by `Monitor` (if any)
+    pub monitor_status: Option<monitor::Status>,
+    /// Only running hash chains are present
+    pub chains: Vec<ChainTelemetry>,
+}
+
 /// Trait to be implemented by external creates wishing extending functionality of the bare miner
 #[async_trait]
 pub trait Hooks: Send + Sync + Debug {
@@ -60,6 +95,24 @@ pub trait Hooks: Send + Sync + Debug {
 
     /// Called after all groups with clients has been loaded
     async fn clients_loaded(&self, _client_manager: client::Manager) {}
+
+    /// Called after hash chain has been successfully started
+    async fn chain_started(&self, _manager: Arc<Manager>) {}
+
+    /// Called after hash chain has been stopped (either explicitly or by miner termination)
+    async fn chain_stopped(&self, _manager: Arc<Manager>) {}
+
+    /// Called after frequency or voltage of running hash chain has been successfully changed
+    async fn chain_tuned(&self, _manager: Arc<Manager>, _event: TuningEvent) {}
+
+    /// Interval of telemetry snapshots passed to `telemetry`.
+    /// Return value: `None` disables telemetry completely (default).
+    fn telemetry_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called periodically with the current state of the miner
+    async fn telemetry(&self, _telemetry: Telemetry) {}
 }
 
 /// NoHooks uses default implementation of all hooks
@@ -67,3 +120,61 @@ pub trait Hooks: Send + Sync + Debug {
 pub struct NoHooks;
 
 impl Hooks for NoHooks {}
+
+/// Example hooks that just log all events and telemetry snapshots, it can be used as a starting
+/// point for site-specific extensions
+#[derive(Debug)]
+pub struct LogHooks {
+    pub telemetry_interval: Duration,
+}
+
+impl LogHooks {
+    pub fn new(telemetry_interval: Duration) -> Self {
+        Self { telemetry_interval }
+    }
+}
+
+#[async_trait]
+impl Hooks for LogHooks {
+    async fn chain_started(&self, manager: Arc<Manager>) {
+        info!("Hooks: chain {} started", manager.hashboard_idx);
+    }
+
+    async fn chain_stopped(&self, manager: Arc<Manager>) {
+        info!("Hooks: chain {} stopped", manager.hashboard_idx);
+    }
+
+    async fn chain_tuned(&self, manager: Arc<Manager>, event: TuningEvent) {
+        match event {
+            TuningEvent::Frequency(frequency) => info!(
+                "Hooks: chain {} frequency set to {}",
+                manager.hashboard_idx, frequency
+            ),
+            TuningEvent::Voltage(voltage) => info!(
+                "Hooks: chain {} voltage set to {:.2} V",
+                manager.hashboard_idx,
+                voltage.as_volts()
+            ),
+        }
+    }
+
+    fn telemetry_interval(&self) -> Option<Duration> {
+        Some(self.telemetry_interval)
+    }
+
+    async fn telemetry(&self, telemetry: Telemetry) {
+        for chain in telemetry.chains.iter() {
+            info!(
+                "Hooks: chain {}: {} chips, {}, {:.2} V, {:?}, valid {}, errors {}, stale {}",
+                chain.hashboard_idx,
+                chain.chip_count,
+                chain.frequency,
+                chain.voltage.as_volts(),
+                chain.temperature,
+                chain.counter.valid,
+                chain.counter.errors,
+                chain.counter.stale_solutions
+            );
+        }
+    }
+}
@@ -20,11 +20,15 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use crate::async_i2c::AsyncRawBus;
 use crate::error::{self, ErrorKind};
 use crate::i2c::{self, Address};
 
 use async_trait::async_trait;
 
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
 /// Register, Value
 pub struct InitReg(pub u8, pub u8);
 
@@ -105,3 +109,174 @@ impl i2c::AsyncBus for FakeI2cBus {
         Ok(())
     }
 }
+
+/// State of the fake PIC command parser
+#[derive(Clone, Copy, PartialEq)]
+enum PicParserState {
+    /// Waiting for the first magic byte (`0x55`)
+    Magic1,
+    /// Waiting for the second magic byte (`0xaa`)
+    Magic2,
+    /// Waiting for command byte
+    Command,
+    /// Collecting arguments for command
+    Arguments(u8, usize),
+}
+
+struct FakePicState {
+    parser: PicParserState,
+    arguments: Vec<u8>,
+    /// Bytes that are to be returned by subsequent single-byte reads
+    reply: VecDeque<u8>,
+    flash_pointer: u16,
+    version: u8,
+    voltage: u8,
+    voltage_enabled: bool,
+    heart_beats: usize,
+}
+
+/// FakePicBus emulates the I2C byte protocol of the S9 voltage controller (PIC) as seen by
+/// `power::I2cBackend`. Flash is always read as zeroes (i.e. invalid) and writes to it are ignored.
+#[derive(Clone)]
+pub struct FakePicBus {
+    /// Which I2C address to respond on
+    respond_addr: u8,
+    state: Arc<Mutex<FakePicState>>,
+}
+
+impl FakePicBus {
+    // Subset of PIC commands that are interpreted (see `power` module)
+    const SET_PIC_FLASH_POINTER: u8 = 0x01;
+    const SEND_DATA_TO_IIC: u8 = 0x02;
+    const READ_DATA_FROM_IIC: u8 = 0x03;
+    const WRITE_DATA_INTO_PIC: u8 = 0x05;
+    const GET_PIC_FLASH_POINTER: u8 = 0x08;
+    const SET_VOLTAGE: u8 = 0x10;
+    const ENABLE_VOLTAGE: u8 = 0x15;
+    const SEND_HEART_BEAT: u8 = 0x16;
+    const GET_PIC_SOFTWARE_VERSION: u8 = 0x17;
+    const GET_VOLTAGE: u8 = 0x18;
+
+    /// Number of bytes transferred to/from flash in one command
+    const FLASH_XFER_BLOCK_SIZE_BYTES: usize = 16;
+
+    /// Constructs fake PIC.
+    ///
+    /// * `respond_addr` - which I2C address to respond on
+    /// * `version` - firmware version reported by the PIC
+    pub fn new(respond_addr: u8, version: u8) -> Self {
+        Self {
+            respond_addr,
+            state: Arc::new(Mutex::new(FakePicState {
+                parser: PicParserState::Magic1,
+                arguments: vec![],
+                reply: VecDeque::new(),
+                flash_pointer: 0,
+                version,
+                voltage: 0,
+                voltage_enabled: false,
+                heart_beats: 0,
+            })),
+        }
+    }
+
+    pub fn voltage_enabled(&self) -> bool {
+        self.state.lock().expect("BUG: lock failed").voltage_enabled
+    }
+
+    pub fn voltage(&self) -> u8 {
+        self.state.lock().expect("BUG: lock failed").voltage
+    }
+
+    pub fn heart_beats(&self) -> usize {
+        self.state.lock().expect("BUG: lock failed").heart_beats
+    }
+
+    fn argument_count(command: u8) -> usize {
+        match command {
+            Self::SET_PIC_FLASH_POINTER => 2,
+            Self::SEND_DATA_TO_IIC => Self::FLASH_XFER_BLOCK_SIZE_BYTES,
+            Self::SET_VOLTAGE | Self::ENABLE_VOLTAGE => 1,
+            _ => 0,
+        }
+    }
+
+    fn execute(state: &mut FakePicState, command: u8, arguments: &[u8]) {
+        let flash_block_words = (Self::FLASH_XFER_BLOCK_SIZE_BYTES / 2) as u16;
+        match command {
+            Self::SET_PIC_FLASH_POINTER => {
+                state.flash_pointer = u16::from_be_bytes([arguments[0], arguments[1]])
+            }
+            Self::READ_DATA_FROM_IIC => {
+                state
+                    .reply
+                    .extend([0u8; Self::FLASH_XFER_BLOCK_SIZE_BYTES].iter());
+                state.flash_pointer += flash_block_words;
+            }
+            Self::WRITE_DATA_INTO_PIC => state.flash_pointer += flash_block_words,
+            Self::GET_PIC_FLASH_POINTER => {
+                state.reply.extend(state.flash_pointer.to_be_bytes().iter())
+            }
+            Self::SET_VOLTAGE => state.voltage = arguments[0],
+            Self::ENABLE_VOLTAGE => state.voltage_enabled = arguments[0] != 0,
+            Self::SEND_HEART_BEAT => state.heart_beats += 1,
+            Self::GET_PIC_SOFTWARE_VERSION => state.reply.push_back(state.version),
+            Self::GET_VOLTAGE => state.reply.push_back(state.voltage),
+            // Reset, jump to application etc. have no observable effect
+            _ => (),
+        }
+    }
+
+    fn feed(state: &mut FakePicState, byte: u8) {
+        state.parser = match state.parser {
+            PicParserState::Magic1 if byte == 0x55 => PicParserState::Magic2,
+            PicParserState::Magic2 if byte == 0xaa => PicParserState::Command,
+            PicParserState::Magic1 | PicParserState::Magic2 => PicParserState::Magic1,
+            PicParserState::Command => {
+                state.arguments.clear();
+                PicParserState::Arguments(byte, Self::argument_count(byte))
+            }
+            PicParserState::Arguments(command, remaining) => {
+                state.arguments.push(byte);
+                PicParserState::Arguments(command, remaining - 1)
+            }
+        };
+        // Execute command once all arguments have been received
+        if let PicParserState::Arguments(command, 0) = state.parser {
+            let arguments = state.arguments.clone();
+            Self::execute(state, command, &arguments);
+            state.parser = PicParserState::Magic1;
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncRawBus for FakePicBus {
+    /// PIC replies with one valid byte per I2C transaction, the rest is garbage
+    async fn read(&self, address: u8, num_bytes: usize) -> error::Result<Vec<u8>> {
+        if address != self.respond_addr {
+            Err(ErrorKind::I2c(format!(
+                "Nothing present on I2C address {:#x}!",
+                address
+            )))?
+        }
+        let mut state = self.state.lock().expect("BUG: lock failed");
+        let mut reply = vec![0; num_bytes];
+        reply[0] = state.reply.pop_front().unwrap_or(0xff);
+        Ok(reply)
+    }
+
+    async fn write(&self, address: u8, bytes: Vec<u8>) -> error::Result<()> {
+        if address != self.respond_addr {
+            Err(ErrorKind::I2c(format!(
+                "Nothing present on I2C address {:#x}!",
+                address
+            )))?
+        }
+        let mut state = self.state.lock().expect("BUG: lock failed");
+        for byte in bytes.into_iter() {
+            Self::feed(&mut state, byte);
+        }
+        Ok(())
+    }
+}
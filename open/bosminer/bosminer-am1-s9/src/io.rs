@@ -27,8 +27,13 @@
 //!     API to wait for events (via interrupts)
 //!   * `Control` layer knows about chip configuration (number of midstates)
 //!     and implements few higher-level functions to read/write work
+//!
+//! The `Control` layer is generic over `*FifoIo` traits (statically dispatched) so that the FPGA
+//! can be replaced with `mock` implementation in tests.
 
 mod ext_work_id;
+#[cfg(test)]
+pub mod mock;
 mod uio;
 
 use crate::error::{self, ErrorKind};
@@ -38,26 +43,26 @@ use ext_work_id::ExtWorkId;
 use bosminer::work;
 use std::convert::TryInto;
 use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
 
 use chrono::prelude::DateTime;
 use chrono::Utc;
 use std::time::{Duration, UNIX_EPOCH};
 
 use ii_async_compat::prelude::*;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 use ii_fpga_io_am1_s9::{self, common::version::MINER_TYPE_A, generic::Variant};
 
 use ii_logging::macros::*;
 
-/// We fail the initialization unless we find this s9-io of this version
-const EXPECTED_S9IO_VERSION: Version = Version {
-    miner_type: MinerType::Known(MINER_TYPE_A::ANTMINER),
-    model: 9,
-    major: 1,
-    minor: 0,
-    patch: 0,
-};
+/// We fail the initialization unless we find s9-io for this miner
+const S9IO_MINER_TYPE: MinerType = MinerType::Known(MINER_TYPE_A::ANTMINER);
+const S9IO_MODEL: usize = 9;
+/// Range of s9-io versions (major, minor, patch) the driver has been verified with
+const MIN_S9IO_VERSION: (usize, usize, usize) = (1, 0, 0);
+const MAX_S9IO_VERSION: (usize, usize, usize) = (1, 0, 0);
 
 /// Base clock speed of the IP core running in the FPGA
 pub const F_CLK_SPEED_HZ: usize = 50_000_000;
@@ -102,6 +107,34 @@ struct Version {
     patch: usize,
 }
 
+impl Version {
+    fn number(&self) -> (usize, usize, usize) {
+        (self.major, self.minor, self.patch)
+    }
+
+    /// Check that the driver supports bitstream with this version
+    fn check_supported(&self) -> Result<(), String> {
+        if self.miner_type != S9IO_MINER_TYPE || self.model != S9IO_MODEL {
+            return Err(format!("bitstream is not for Antminer S{}", S9IO_MODEL));
+        }
+        let (major, minor, patch) = MIN_S9IO_VERSION;
+        if self.number() < MIN_S9IO_VERSION {
+            return Err(format!(
+                "bitstream is too old, the oldest supported version is {}.{}.{}",
+                major, minor, patch
+            ));
+        }
+        let (major, minor, patch) = MAX_S9IO_VERSION;
+        if self.number() > MAX_S9IO_VERSION {
+            return Err(format!(
+                "bitstream is too new, the newest supported version is {}.{}.{}",
+                major, minor, patch
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let model;
@@ -118,6 +151,61 @@ impl fmt::Display for Version {
     }
 }
 
+/// Access to FIFO with solutions received from chips
+pub trait WorkRxFifoIo: Send + Sync + 'static {
+    fn init(&mut self) -> error::Result<()>;
+
+    /// Read one word from the FIFO (waits until there's any)
+    fn read(&mut self) -> impl Future<Output = error::Result<u32>> + Send;
+
+    /// The FIFO is full and further solutions from chips may be lost
+    fn is_full(&self) -> bool;
+}
+
+/// Access to FIFO with work sent to chips
+pub trait WorkTxFifoIo: Send + Sync + 'static {
+    fn init(&mut self) -> error::Result<()>;
+
+    /// Write one word to the FIFO (blocks while the FIFO is full)
+    fn write(&mut self, item: u32) -> error::Result<()>;
+
+    /// Write one word to the FIFO without checking whether the FIFO is full
+    fn write_unchecked(&mut self, item: u32);
+
+    /// Wait for the FIFO to make room for one work
+    fn wait_for_room(&self) -> impl Future<Output = error::Result<()>> + Send;
+
+    /// There's room for one work in the FIFO (`wait_for_room` won't block)
+    fn has_room(&self) -> bool;
+
+    /// Discard all work waiting in the FIFO.
+    /// Returns: `false` when the FIFO doesn't support flushing
+    fn flush(&mut self) -> error::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Access to FIFOs with commands sent to chips and their responses
+pub trait CommandFifoIo: Send + Sync + 'static {
+    fn init(&mut self) -> error::Result<()>;
+
+    /// Write one word of command to command TX FIFO
+    fn write(&self, item: u32) -> impl Future<Output = ()> + Send;
+
+    /// Wait for command TX FIFO to become empty
+    fn wait_tx_empty(&self) -> impl Future<Output = ()> + Send;
+
+    /// Read one word of response from command RX FIFO
+    /// Returns:
+    ///     * `Ok(None)` on timeout
+    ///     * `Ok(Some(_))` if something was received
+    ///     * `Err(_)` if error occured
+    fn read_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Future<Output = error::Result<Option<u32>>> + Send;
+}
+
 #[derive(Clone, Debug)]
 pub struct Solution {
     /// Actual nonce
@@ -130,7 +218,8 @@ pub struct Solution {
     pub hardware_id: u32,
 }
 
-struct WorkRxFifo {
+/// Solution FIFO of s9-io IP core
+pub struct WorkRxFifo {
     regs: uio_async::UioTypedMapping<ii_fpga_io_am1_s9::workrx::RegisterBlock>,
     uio: uio_async::UioDevice,
 }
@@ -151,15 +240,17 @@ impl WorkRxFifo {
         Ok(got_irq.and_then(|_| Some(self.regs.work_rx_fifo.read().bits())))
     }
 
-    /// Try to read from work rx fifo.
-    /// Async variant. Uses IRQ.
-    pub async fn async_read(&mut self) -> error::Result<u32> {
-        let cond = || !self.is_empty();
-        self.uio.async_irq_wait_cond(cond).await?;
-        Ok(self.regs.work_rx_fifo.read().bits())
+    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
+        let uio = uio::Device::open(hashboard_idx, uio::Type::WorkRx)?;
+        Ok(Self {
+            regs: uio.map()?,
+            uio: uio.uio,
+        })
     }
+}
 
-    pub fn init(&mut self) -> error::Result<()> {
+impl WorkRxFifoIo for WorkRxFifo {
+    fn init(&mut self) -> error::Result<()> {
         // reset input FIFO
         self.regs
             .work_rx_ctrl_reg
@@ -171,16 +262,22 @@ impl WorkRxFifo {
         Ok(())
     }
 
-    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
-        let uio = uio::Device::open(hashboard_idx, uio::Type::WorkRx)?;
-        Ok(Self {
-            regs: uio.map()?,
-            uio: uio.uio,
-        })
+    /// Try to read from work rx fifo.
+    /// Async variant. Uses IRQ.
+    async fn read(&mut self) -> error::Result<u32> {
+        let cond = || !self.is_empty();
+        self.uio.async_irq_wait_cond(cond).await?;
+        Ok(self.regs.work_rx_fifo.read().bits())
+    }
+
+    #[inline]
+    fn is_full(&self) -> bool {
+        self.regs.work_rx_stat_reg.read().rx_full().bit()
     }
 }
 
-struct WorkTxFifo {
+/// Work FIFO of s9-io IP core
+pub struct WorkTxFifo {
     regs: uio_async::UioTypedMapping<ii_fpga_io_am1_s9::worktx::RegisterBlock>,
     uio: uio_async::UioDevice,
 }
@@ -213,48 +310,71 @@ impl WorkTxFifo {
         self.regs.work_tx_last_id.read().bits()
     }
 
+    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
+        let uio = uio::Device::open(hashboard_idx, uio::Type::WorkTx)?;
+        Ok(Self {
+            regs: uio.map()?,
+            uio: uio.uio,
+        })
+    }
+}
+
+impl WorkTxFifoIo for WorkTxFifo {
+    fn init(&mut self) -> error::Result<()> {
+        // Set threshold for work TX so that there's space for
+        // at least one job.
+        self.regs
+            .work_tx_irq_thr
+            .write(|w| unsafe { w.bits(Self::FIFO_THRESHOLD) });
+        // reset output FIFO
+        self.regs
+            .work_tx_ctrl_reg
+            .modify(|_, w| w.rst_tx_fifo().set_bit());
+        // enable IRQ_WORK_TX interrupt
+        self.regs
+            .work_tx_ctrl_reg
+            .modify(|_, w| w.irq_en().set_bit());
+        Ok(())
+    }
+
     /// Try to write work item to work TX FIFO.
     /// Performs blocking write without timeout. Uses IRQ.
     /// The idea is that you don't call this function until you are sure you
     /// can fit in all the entries you want.
     #[inline]
-    pub fn write(&mut self, item: u32) -> error::Result<()> {
+    fn write(&mut self, item: u32) -> error::Result<()> {
         let cond = || !self.is_full();
         self.uio.irq_wait_cond(cond, None)?;
         self.regs.work_tx_fifo.write(|w| unsafe { w.bits(item) });
         Ok(())
     }
 
+    /// Write work item to work TX FIFO without checking whether the FIFO is full.
+    /// The caller has to guarantee there's room for the item (see `wait_for_room`)
+    /// which saves reading of status register (and possibly waiting for IRQ) for each item.
+    #[inline]
+    fn write_unchecked(&mut self, item: u32) {
+        self.regs.work_tx_fifo.write(|w| unsafe { w.bits(item) });
+    }
+
     /// Wait for output FIFO to make room for one work
-    pub async fn async_wait_for_room(&self) -> error::Result<()> {
+    async fn wait_for_room(&self) -> error::Result<()> {
         let cond = || self.has_space_for_one_job();
         self.uio.async_irq_wait_cond(cond).await?;
         Ok(())
     }
 
-    pub fn init(&mut self) -> error::Result<()> {
-        // Set threshold for work TX so that there's space for
-        // at least one job.
-        self.regs
-            .work_tx_irq_thr
-            .write(|w| unsafe { w.bits(Self::FIFO_THRESHOLD) });
-        // reset output FIFO
+    #[inline]
+    fn has_room(&self) -> bool {
+        self.has_space_for_one_job()
+    }
+
+    /// Reset the FIFO. Work which is already being sent to chips is not affected.
+    fn flush(&mut self) -> error::Result<bool> {
         self.regs
             .work_tx_ctrl_reg
             .modify(|_, w| w.rst_tx_fifo().set_bit());
-        // enable IRQ_WORK_TX interrupt
-        self.regs
-            .work_tx_ctrl_reg
-            .modify(|_, w| w.irq_en().set_bit());
-        Ok(())
-    }
-
-    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
-        let uio = uio::Device::open(hashboard_idx, uio::Type::WorkTx)?;
-        Ok(Self {
-            regs: uio.map()?,
-            uio: uio.uio,
-        })
+        Ok(true)
     }
 }
 
@@ -288,40 +408,56 @@ impl CommandRxTxFifos {
         self.regs.cmd_stat_reg.read().tx_full().bit()
     }
 
-    /// Wait for command FIFO to become empty
-    /// Uses timed polling
-    pub async fn wait_tx_empty(&self) {
-        while !self.is_tx_empty() {
-            delay_for(Duration::from_millis(1)).await;
-        }
+    /// Read command from cmd rx fifo
+    /// Async variant. Uses IRQ.
+    pub async fn read(&mut self) -> error::Result<u32> {
+        let cond = || !self.is_rx_empty();
+        self.uio.async_irq_wait_cond(cond).await?;
+        Ok(self.regs.cmd_rx_fifo.read().bits())
+    }
+
+    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
+        let uio = uio::Device::open(hashboard_idx, uio::Type::Command)?;
+        Ok(Self {
+            regs: uio.map()?,
+            uio: uio.uio,
+        })
+    }
+}
+
+impl CommandFifoIo for CommandRxTxFifos {
+    fn init(&mut self) -> error::Result<()> {
+        // reset input FIFO
+        self.regs
+            .cmd_ctrl_reg
+            .modify(|_, w| w.rst_rx_fifo().set_bit().rst_tx_fifo().set_bit());
+        // enable IRQ_CMD_RX interrupt
+        self.regs.cmd_ctrl_reg.modify(|_, w| w.irq_en().set_bit());
+        Ok(())
     }
 
     /// Write command to cmd tx fifo.
     /// Uses timed polling
-    pub async fn write(&self, item: u32) {
+    async fn write(&self, item: u32) {
         // wait for space in queue
         while self.is_tx_full() {
-            delay_for(Duration::from_millis(1)).await;
+            sleep(Duration::from_millis(1)).await;
         }
         // write command word
         self.regs.cmd_tx_fifo.write(|w| unsafe { w.bits(item) });
     }
 
-    /// Read command from cmd rx fifo
-    /// Async variant. Uses IRQ.
-    pub async fn read(&mut self) -> error::Result<u32> {
-        let cond = || !self.is_rx_empty();
-        self.uio.async_irq_wait_cond(cond).await?;
-        Ok(self.regs.cmd_rx_fifo.read().bits())
+    /// Wait for command FIFO to become empty
+    /// Uses timed polling
+    async fn wait_tx_empty(&self) {
+        while !self.is_tx_empty() {
+            sleep(Duration::from_millis(1)).await;
+        }
     }
 
     /// Read command from cmd rx fifo with timeout
     /// Async variant. Uses IRQ.
-    /// Returns:
-    ///     * `Ok(None)` on timeout
-    ///     * `Ok(Some(_))` if something was received
-    ///     * `Err(_)` if error occured
-    pub async fn read_with_timeout(&mut self, timeout: Duration) -> error::Result<Option<u32>> {
+    async fn read_with_timeout(&mut self, timeout: Duration) -> error::Result<Option<u32>> {
         match self.read().timeout(timeout).await {
             Ok(Ok(word)) => Ok(Some(word)), // Read complete on time
             Ok(Err(err)) => Err(err),       // Read I/O error
@@ -336,24 +472,6 @@ impl CommandRxTxFifos {
             }
         }
     }
-
-    pub fn init(&mut self) -> error::Result<()> {
-        // reset input FIFO
-        self.regs
-            .cmd_ctrl_reg
-            .modify(|_, w| w.rst_rx_fifo().set_bit().rst_tx_fifo().set_bit());
-        // enable IRQ_CMD_RX interrupt
-        self.regs.cmd_ctrl_reg.modify(|_, w| w.irq_en().set_bit());
-        Ok(())
-    }
-
-    pub fn new(hashboard_idx: usize) -> error::Result<Self> {
-        let uio = uio::Device::open(hashboard_idx, uio::Type::Command)?;
-        Ok(Self {
-            regs: uio.map()?,
-            uio: uio.uio,
-        })
-    }
 }
 
 /// This structure represents mining solution response as read from
@@ -383,15 +501,15 @@ impl WorkRxResponse {
     }
 }
 
-pub struct WorkRx {
-    fifo: WorkRxFifo,
+pub struct WorkRx<F = WorkRxFifo> {
+    fifo: F,
     midstate_count: MidstateCount,
 }
 
-impl WorkRx {
-    pub async fn recv_solution(mut self) -> Result<(Self, Solution), failure::Error> {
-        let word1 = self.fifo.async_read().await?;
-        let word2 = self.fifo.async_read().await?;
+impl<F: WorkRxFifoIo> WorkRx<F> {
+    pub async fn recv_solution(mut self) -> error::Result<(Self, Solution)> {
+        let word1 = self.fifo.read().await?;
+        let word2 = self.fifo.read().await?;
         let resp = WorkRxResponse::from_hw(self.midstate_count, word1, word2);
 
         let solution = Solution {
@@ -404,58 +522,93 @@ impl WorkRx {
         Ok((self, solution))
     }
 
+    /// Solution FIFO is full and solutions found by chips may be lost
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.fifo.is_full()
+    }
+
     fn init(&mut self) -> error::Result<()> {
         self.fifo.init()
     }
 
+    /// Build work RX on top of any FIFO implementation
+    pub fn with_fifo(fifo: F, midstate_count: MidstateCount) -> Self {
+        Self {
+            fifo,
+            midstate_count,
+        }
+    }
+}
+
+impl WorkRx {
     fn new(hashboard_idx: usize, midstate_count: MidstateCount) -> error::Result<Self> {
-        Ok(Self {
-            fifo: WorkRxFifo::new(hashboard_idx)?,
+        Ok(Self::with_fifo(
+            WorkRxFifo::new(hashboard_idx)?,
             midstate_count,
-        })
+        ))
     }
 }
 
-pub struct WorkTx {
-    fifo: WorkTxFifo,
+pub struct WorkTx<F = WorkTxFifo> {
+    fifo: F,
     midstate_count: MidstateCount,
 }
 
-impl WorkTx {
+impl<F: WorkTxFifoIo> WorkTx<F> {
     pub async fn wait_for_room(&self) -> error::Result<()> {
-        self.fifo.async_wait_for_room().await
+        self.fifo.wait_for_room().await
     }
 
-    pub fn assert_midstate_count(&self, expected_midstate_count: usize) {
-        assert_eq!(
-            expected_midstate_count,
-            self.midstate_count.to_count(),
-            "Outgoing work has {} midstates, but miner is configured for {} midstates!",
-            expected_midstate_count,
-            self.midstate_count.to_count(),
-        );
+    /// There's room for at least one work in the FIFO
+    #[inline]
+    pub fn has_room(&self) -> bool {
+        self.fifo.has_room()
     }
 
-    pub fn send_work(
-        &mut self,
-        work: &work::Assignment,
-        work_id: usize,
-    ) -> Result<(), failure::Error> {
-        self.assert_midstate_count(work.midstates.len());
-        let ext_work_id = ExtWorkId::new(work_id, 0);
-
-        self.fifo
-            .write(ext_work_id.to_hw(self.midstate_count).to_le())?;
-        self.fifo.write(work.bits().to_le())?;
-        self.fifo.write(work.ntime.to_le())?;
-        self.fifo.write(work.merkle_root_tail().to_le())?;
+    /// Discard all work waiting in the FIFO (e.g. after new block).
+    /// Returns: `false` when the FIFO doesn't support flushing
+    pub fn flush(&mut self) -> error::Result<bool> {
+        self.fifo.flush()
+    }
 
-        for mid in work.midstates.iter() {
-            for midstate_word in mid.state.words::<u32>().rev() {
-                self.fifo.write(midstate_word.to_be())?;
+    pub fn send_work(&mut self, work: &work::Assignment, work_id: usize) -> error::Result<()> {
+        let fifo = &mut self.fifo;
+        let mut result = Ok(());
+        WorkTx::serialize_work(self.midstate_count, work, work_id, |item| {
+            if result.is_ok() {
+                result = fifo.write(item);
             }
+        });
+        result
+    }
+
+    /// Maximal number of works which can be sent with `send_work_batch` after one
+    /// `wait_for_room`
+    pub fn batch_size(&self) -> usize {
+        WorkTxFifo::BIGGEST_WORK as usize / WorkTx::work_size(self.midstate_count)
+    }
+
+    /// Send a batch of works (with their `work_id`s) in one burst. It must be called only after
+    /// `wait_for_room` and the batch must not be bigger than `batch_size`. The works are
+    /// serialized in place so they can be borrowed directly from the work registry.
+    pub fn send_work_batch<I, W>(&mut self, works: I)
+    where
+        I: IntoIterator<Item = (W, usize)>,
+        W: Deref<Target = work::Assignment>,
+    {
+        let batch_size = self.batch_size();
+        for (i, (work, work_id)) in works.into_iter().enumerate() {
+            assert!(
+                i < batch_size,
+                "BUG: batch exceeds batch size {}",
+                batch_size
+            );
+            let fifo = &mut self.fifo;
+            WorkTx::serialize_work(self.midstate_count, &work, work_id, |item| {
+                fifo.write_unchecked(item)
+            });
         }
-        Ok(())
     }
 
     /// Return upper bound for `work_id`
@@ -468,20 +621,77 @@ impl WorkTx {
         self.fifo.init()
     }
 
+    /// Build work TX on top of any FIFO implementation
+    pub fn with_fifo(fifo: F, midstate_count: MidstateCount) -> Self {
+        Self {
+            fifo,
+            midstate_count,
+        }
+    }
+}
+
+/// Format of work doesn't depend on the FIFO implementation
+impl WorkTx {
+    /// Number of u32 words of work header (ext. work ID, nbits, ntime and merkle root tail)
+    const WORK_HEADER_SIZE: usize = 4;
+    /// Number of u32 words of one midstate
+    const MIDSTATE_SIZE: usize = 8;
+
+    /// Size of one work in FIFO (in u32 words)
+    fn work_size(midstate_count: MidstateCount) -> usize {
+        Self::WORK_HEADER_SIZE + midstate_count.to_count() * Self::MIDSTATE_SIZE
+    }
+
+    /// Serialize work in format of work TX FIFO word by word into `emit`. The work is written
+    /// directly to its destination so no intermediate buffer has to be allocated.
+    ///
+    /// Work with fewer midstates than `midstate_count` (the job doesn't allow rolling enough
+    /// version bits) is padded with its midstates repeated, solutions of the padding are dropped
+    /// by the receiver. Work with more midstates (generated before the number of midstates has
+    /// been switched) is truncated.
+    #[inline]
+    pub fn serialize_work<F>(
+        midstate_count: MidstateCount,
+        work: &work::Assignment,
+        work_id: usize,
+        mut emit: F,
+    ) where
+        F: FnMut(u32),
+    {
+        assert!(!work.midstates.is_empty(), "BUG: work without midstates");
+        let ext_work_id = ExtWorkId::new(work_id, 0);
+
+        emit(ext_work_id.to_hw(midstate_count).to_le());
+        emit(work.bits().to_le());
+        emit(work.ntime.to_le());
+        emit(work.merkle_root_tail().to_le());
+
+        for mid in work
+            .midstates
+            .iter()
+            .cycle()
+            .take(midstate_count.to_count())
+        {
+            for midstate_word in mid.state.words::<u32>().rev() {
+                emit(midstate_word.to_be());
+            }
+        }
+    }
+
     fn new(hashboard_idx: usize, midstate_count: MidstateCount) -> error::Result<Self> {
-        Ok(Self {
-            fifo: WorkTxFifo::new(hashboard_idx)?,
+        Ok(Self::with_fifo(
+            WorkTxFifo::new(hashboard_idx)?,
             midstate_count,
-        })
+        ))
     }
 }
 
-pub struct CommandRxTx {
-    fifo: CommandRxTxFifos,
+pub struct CommandRxTx<F = CommandRxTxFifos> {
+    fifo: F,
     pub hashboard_idx: usize,
 }
 
-impl CommandRxTx {
+impl<F: CommandFifoIo> CommandRxTx<F> {
     /// Serializes command into 32-bit words and submits it to the command TX FIFO
     ///
     /// * `wait` - when true, wait until all commands are sent
@@ -541,11 +751,21 @@ impl CommandRxTx {
         self.fifo.init()
     }
 
+    /// Build command interface on top of any FIFO implementation
+    pub fn with_fifo(fifo: F, hashboard_idx: usize) -> Self {
+        Self {
+            fifo,
+            hashboard_idx,
+        }
+    }
+}
+
+impl CommandRxTx {
     fn new(hashboard_idx: usize) -> error::Result<Self> {
-        Ok(Self {
-            fifo: CommandRxTxFifos::new(hashboard_idx)?,
+        Ok(Self::with_fifo(
+            CommandRxTxFifos::new(hashboard_idx)?,
             hashboard_idx,
-        })
+        ))
     }
 }
 
@@ -584,6 +804,11 @@ impl Common {
         }
     }
 
+    /// Return description of FPGA bitstream (version and build time)
+    pub fn get_bitstream_info(&mut self) -> String {
+        format!("{} built on {}", self.get_version(), self.get_build_id())
+    }
+
     #[inline]
     pub fn enable_ip_core(&self) {
         self.regs.ctrl_reg.modify(|_, w| w.enable().bit(true));
@@ -637,12 +862,12 @@ impl Common {
             self.hashboard_idx, version, build_id
         );
 
-        // check it's the exact version
-        if version != EXPECTED_S9IO_VERSION {
-            Err(ErrorKind::UnexpectedVersion(
-                "s9-io bitstream".to_string(),
+        // check that the bitstream version is in the supported range
+        if let Err(reason) = version.check_supported() {
+            Err(ErrorKind::IncompatibleBitstream(
+                self.hashboard_idx,
                 version.to_string(),
-                EXPECTED_S9IO_VERSION.to_string(),
+                reason,
             ))?
         }
         Ok(())
@@ -713,6 +938,8 @@ impl Core {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::sync::Arc;
+
     /// Index of chain for testing (must exist and be defined in DTS)
     const TEST_CHAIN_INDEX: usize = 8;
 
@@ -787,6 +1014,130 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_work_tx_serialization() {
+        let time = 0xffffffff;
+        let job = Arc::new(crate::null_work::NullJob::new(time, 0xffff_ffff, 0));
+        let midstate = work::Midstate {
+            version: 0,
+            state: [0u8; 32].into(),
+        };
+
+        // (midstate count, number of works fitting into FIFO after one wait for room)
+        for &(count, batch_size) in [(1, 16), (2, 10), (4, 5)].iter() {
+            let midstate_count = MidstateCount::new(count);
+            let work = work::Assignment::new(job.clone(), vec![midstate.clone(); count], time);
+            let mut buffer = Vec::new();
+
+            WorkTx::serialize_work(midstate_count, &work, 3, |item| buffer.push(item));
+            WorkTx::serialize_work(midstate_count, &work, 4, |item| buffer.push(item));
+            let work_size = WorkTx::work_size(midstate_count);
+            assert_eq!(buffer.len(), 2 * work_size);
+            assert_eq!(
+                buffer[work_size],
+                ExtWorkId::new(4, 0).to_hw(midstate_count).to_le()
+            );
+            assert_eq!(WorkTxFifo::BIGGEST_WORK as usize / work_size, batch_size);
+        }
+
+        // work with different number of midstates than the FIFO is padded or truncated
+        let midstates: Vec<_> = (1..=2u8)
+            .map(|i| work::Midstate {
+                version: i as u32,
+                state: [i; 32].into(),
+            })
+            .collect();
+        let work = work::Assignment::new(job.clone(), midstates, time);
+        let header_size = WorkTx::WORK_HEADER_SIZE;
+        let midstate_size = WorkTx::MIDSTATE_SIZE;
+
+        let mut buffer = Vec::new();
+        WorkTx::serialize_work(MidstateCount::new(4), &work, 3, |item| buffer.push(item));
+        assert_eq!(buffer.len(), WorkTx::work_size(MidstateCount::new(4)));
+        let padded_midstates = &buffer[header_size..];
+        assert_ne!(
+            padded_midstates[..midstate_size],
+            padded_midstates[midstate_size..2 * midstate_size]
+        );
+        assert_eq!(
+            padded_midstates[..2 * midstate_size],
+            padded_midstates[2 * midstate_size..]
+        );
+
+        let mut truncated = Vec::new();
+        WorkTx::serialize_work(MidstateCount::new(1), &work, 3, |item| truncated.push(item));
+        assert_eq!(truncated.len(), WorkTx::work_size(MidstateCount::new(1)));
+        assert_eq!(
+            truncated[header_size..],
+            buffer[header_size..header_size + midstate_size]
+        );
+    }
+
+    /// Verify that work and solutions pass through FIFOs (emulated by mock)
+    #[tokio::test]
+    async fn test_work_mock_fifo() {
+        let midstate_count = MidstateCount::new(4);
+        let chain = mock::Chain::new(1);
+        let (_, work_rx, mut work_tx) = chain.split(TEST_CHAIN_INDEX, midstate_count);
+
+        let job = Arc::new(crate::null_work::NullJob::new(0, 0xffff_ffff, 0));
+        let midstate = work::Midstate {
+            version: 0,
+            state: [0u8; 32].into(),
+        };
+        let work = work::Assignment::new(job, vec![midstate; 4], 0);
+        work_tx.wait_for_room().await.expect("wait for room failed");
+        work_tx.send_work_batch(vec![(&work, 5), (&work, 6)]);
+        let mut expected = Vec::new();
+        WorkTx::serialize_work(midstate_count, &work, 5, |item| expected.push(item));
+        WorkTx::serialize_work(midstate_count, &work, 6, |item| expected.push(item));
+        assert_eq!(chain.take_work(), expected);
+
+        chain.send_solution(midstate_count, 0xdead0666, 6, 3, 2);
+        let (_, solution) = work_rx
+            .recv_solution()
+            .await
+            .expect("solution receive failed");
+        assert_eq!(solution.nonce, 0xdead0666);
+        assert_eq!(solution.hardware_id, 6);
+        assert_eq!(solution.midstate_idx, 3);
+        assert_eq!(solution.solution_idx, 2);
+
+        // stalled FIFO never makes room for work
+        chain.set_stalled(true);
+        assert!(!work_tx.has_room());
+        assert!(work_tx
+            .wait_for_room()
+            .timeout(Duration::from_millis(50))
+            .await
+            .is_err());
+    }
+
+    /// Verify that full solution FIFO (emulated by mock) drops solutions
+    #[tokio::test]
+    async fn test_work_mock_rx_fifo_full() {
+        let midstate_count = MidstateCount::new(4);
+        let chain = mock::Chain::new(1);
+        let (_, work_rx, _) = chain.split(TEST_CHAIN_INDEX, midstate_count);
+
+        // every solution takes two words
+        let capacity = mock::WORK_RX_FIFO_SIZE / 2;
+        for nonce in 0..capacity as u32 {
+            assert!(!work_rx.is_full());
+            chain.send_solution(midstate_count, nonce, 1, 0, 0);
+        }
+        assert!(work_rx.is_full());
+        // solution is lost
+        chain.send_solution(midstate_count, 0xdead0666, 1, 0, 0);
+
+        let (work_rx, solution) = work_rx
+            .recv_solution()
+            .await
+            .expect("solution receive failed");
+        assert_eq!(solution.nonce, 0);
+        assert!(!work_rx.is_full());
+    }
+
     #[test]
     fn test_version_display() {
         let version = Version {
@@ -808,6 +1159,27 @@ mod test {
         assert_eq!(version.to_string(), "1.2.3 for Unknown[10, 19]");
     }
 
+    #[test]
+    fn test_version_supported() {
+        let version = |miner_type, model, major, minor, patch| Version {
+            miner_type,
+            model,
+            major,
+            minor,
+            patch,
+        };
+        let antminer = || MinerType::Known(MINER_TYPE_A::ANTMINER);
+
+        assert!(version(antminer(), 9, 1, 0, 0).check_supported().is_ok());
+        assert!(version(antminer(), 9, 1, 0, 1).check_supported().is_err());
+        assert!(version(antminer(), 9, 0, 9, 0).check_supported().is_err());
+        assert!(version(antminer(), 9, 2, 0, 0).check_supported().is_err());
+        assert!(version(antminer(), 17, 1, 0, 0).check_supported().is_err());
+        assert!(version(MinerType::Unknown(10), 9, 1, 0, 0)
+            .check_supported()
+            .is_err());
+    }
+
     #[test]
     fn test_build_id_display() {
         let build_id = BuildId(0x5D8255F0);
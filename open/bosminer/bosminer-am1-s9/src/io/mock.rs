@@ -0,0 +1,373 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Mock of s9-io FPGA IP core with emulated chain of BM1387 chips. It allows to test hash chain
+//! initialization, command bus error handling and solution processing without hardware.
+//!
+//! The emulated chips understand just the commands used during chain initialization (read and
+//! write of registers, address assignment) and the chain can be instructed to misbehave:
+//! chips can stop responding, responses can be lost or truncated and the FIFOs can stall.
+
+use super::*;
+
+use crate::bm1387::{self, Register as _};
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex};
+
+/// How often stalled FIFOs are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Size of work RX FIFO (in u32 words), solutions are lost when it's full
+pub const WORK_RX_FIFO_SIZE: usize = 1024;
+
+/// Emulated BM1387 chip
+#[derive(Debug, Clone)]
+struct Chip {
+    /// Address assigned by `SetChipAddress` command
+    address: Option<u8>,
+    /// Revision reported in the address register
+    chip_rev: u16,
+    /// Registers written by `SetConfig` command
+    registers: HashMap<u8, u32>,
+    /// Chip neither responds nor takes an address
+    responding: bool,
+}
+
+impl Chip {
+    fn new() -> Self {
+        Self {
+            address: None,
+            chip_rev: bm1387::ChipRev::Bm1387 as u16,
+            registers: HashMap::new(),
+            responding: true,
+        }
+    }
+
+    fn read_register(&self, register: u8) -> u32 {
+        if register == bm1387::GetAddressReg::REG_NUM {
+            ((self.chip_rev as u32) << 16) | self.address.unwrap_or(0) as u32
+        } else {
+            self.registers.get(&register).cloned().unwrap_or(0)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    chips: Vec<Chip>,
+    /// Bytes of partially received command
+    command: Vec<u8>,
+    /// Words in command RX FIFO
+    responses: VecDeque<u32>,
+    /// Words in work RX FIFO
+    solutions: VecDeque<u32>,
+    /// Words written to work TX FIFO
+    work: Vec<u32>,
+    /// Number of following responses that are dropped (as if they had wrong CRC)
+    crc_errors: usize,
+    /// Number of following commands whose last response is truncated to one word
+    framing_errors: usize,
+    /// FIFOs neither deliver responses and solutions nor make room for work
+    stalled: bool,
+}
+
+impl State {
+    /// Decode command from bytes received so far and execute it once it's complete
+    fn process_command(&mut self) {
+        if self.command.len() < 2 {
+            return;
+        }
+        // length in header accounts for CRC5 which is appended by the IP core
+        let length = self.command[1] as usize - 1;
+        if self.command.len() < length {
+            return;
+        }
+        let command: Vec<u8> = self.command.drain(..).collect();
+
+        let code = command[0] & 0x0f;
+        let to_all = command[0] & 0x10 != 0;
+        let hw_addr = command[2];
+        let mut responses = Vec::new();
+        match code {
+            // GetStatus
+            0x04 => {
+                let register = command[3];
+                for chip in self.chips.iter().filter(|chip| chip.responding) {
+                    if to_all || chip.address == Some(hw_addr) {
+                        responses.push(chip.read_register(register));
+                    }
+                }
+            }
+            // SetConfig
+            0x08 => {
+                let register = command[3];
+                let value = u32::from_be_bytes(
+                    command[4..8]
+                        .try_into()
+                        .expect("slice with incorrect length"),
+                );
+                for chip in self.chips.iter_mut().filter(|chip| chip.responding) {
+                    if to_all || chip.address == Some(hw_addr) {
+                        chip.registers.insert(register, value);
+                    }
+                }
+            }
+            // InactivateFromChain
+            0x05 => {
+                for chip in self.chips.iter_mut() {
+                    chip.address = None;
+                }
+            }
+            // SetChipAddress
+            0x01 => {
+                if let Some(chip) = self
+                    .chips
+                    .iter_mut()
+                    .find(|chip| chip.responding && chip.address.is_none())
+                {
+                    chip.address = Some(hw_addr);
+                }
+            }
+            _ => panic!("unsupported command {:#x?}", command),
+        }
+
+        let truncate_last = self.framing_errors > 0 && !responses.is_empty();
+        if truncate_last {
+            self.framing_errors -= 1;
+        }
+        let response_count = responses.len();
+        for (i, value) in responses.into_iter().enumerate() {
+            if self.crc_errors > 0 {
+                self.crc_errors -= 1;
+                continue;
+            }
+            // response is 7 bytes long: 4 bytes of value, 2 zero bytes and CRC
+            let mut bytes = [0u8; 8];
+            bytes[..4].copy_from_slice(&value.to_be_bytes());
+            self.responses
+                .push_back(u32::from_le_bytes(bytes[..4].try_into().unwrap()));
+            if !(truncate_last && i == response_count - 1) {
+                self.responses
+                    .push_back(u32::from_le_bytes(bytes[4..].try_into().unwrap()));
+            }
+        }
+    }
+}
+
+/// Emulated chain of chips connected to mock FIFOs
+#[derive(Debug, Clone)]
+pub struct Chain {
+    state: Arc<StdMutex<State>>,
+}
+
+impl Chain {
+    pub fn new(chip_count: usize) -> Self {
+        Self {
+            state: Arc::new(StdMutex::new(State {
+                chips: vec![Chip::new(); chip_count],
+                command: Vec::new(),
+                responses: VecDeque::new(),
+                solutions: VecDeque::new(),
+                work: Vec::new(),
+                crc_errors: 0,
+                framing_errors: 0,
+                stalled: false,
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("BUG: lock poisoned")
+    }
+
+    /// Build IO blocks connected to this chain
+    pub fn split(
+        &self,
+        hashboard_idx: usize,
+        midstate_count: MidstateCount,
+    ) -> (CommandRxTx<Fifo>, WorkRx<Fifo>, WorkTx<Fifo>) {
+        (
+            CommandRxTx::with_fifo(Fifo(self.clone()), hashboard_idx),
+            WorkRx::with_fifo(Fifo(self.clone()), midstate_count),
+            WorkTx::with_fifo(Fifo(self.clone()), midstate_count),
+        )
+    }
+
+    /// Make chip (not) respond to commands
+    pub fn set_responding(&self, chip: usize, responding: bool) {
+        self.lock().chips[chip].responding = responding;
+    }
+
+    pub fn set_chip_rev(&self, chip: usize, chip_rev: u16) {
+        self.lock().chips[chip].chip_rev = chip_rev;
+    }
+
+    /// Address assigned to chip during enumeration
+    pub fn chip_address(&self, chip: usize) -> Option<u8> {
+        self.lock().chips[chip].address
+    }
+
+    /// Value written to chip register
+    pub fn register(&self, chip: usize, register: u8) -> Option<u32> {
+        self.lock().chips[chip].registers.get(&register).cloned()
+    }
+
+    /// Drop following `count` responses
+    pub fn inject_crc_errors(&self, count: usize) {
+        self.lock().crc_errors += count;
+    }
+
+    /// Truncate last response of following `count` commands
+    pub fn inject_framing_errors(&self, count: usize) {
+        self.lock().framing_errors += count;
+    }
+
+    /// Stall all FIFOs
+    pub fn set_stalled(&self, stalled: bool) {
+        self.lock().stalled = stalled;
+    }
+
+    /// Emulate solution found by a chip
+    pub fn send_solution(
+        &self,
+        midstate_count: MidstateCount,
+        nonce: u32,
+        work_id: usize,
+        midstate_idx: usize,
+        solution_idx: usize,
+    ) {
+        let ext_work_id = ExtWorkId::new(work_id, midstate_idx).to_hw(midstate_count);
+        let mut state = self.lock();
+        if state.solutions.len() >= WORK_RX_FIFO_SIZE {
+            return;
+        }
+        state.solutions.push_back(nonce);
+        state
+            .solutions
+            .push_back((ext_work_id << 8) | solution_idx as u32);
+    }
+
+    /// Take all words written to work TX FIFO
+    pub fn take_work(&self) -> Vec<u32> {
+        self.lock().work.drain(..).collect()
+    }
+}
+
+/// Mock of all FIFOs sharing the emulated chain
+pub struct Fifo(Chain);
+
+impl Fifo {
+    /// Pop word from FIFO selected by `queue` unless FIFOs are stalled
+    fn pop<F>(&self, queue: F) -> Option<u32>
+    where
+        F: FnOnce(&mut State) -> &mut VecDeque<u32>,
+    {
+        let mut state = self.0.lock();
+        if state.stalled {
+            None
+        } else {
+            queue(&mut state).pop_front()
+        }
+    }
+
+    fn is_stalled(&self) -> bool {
+        self.0.lock().stalled
+    }
+}
+
+impl CommandFifoIo for Fifo {
+    fn init(&mut self) -> error::Result<()> {
+        let mut state = self.0.lock();
+        state.command.clear();
+        state.responses.clear();
+        Ok(())
+    }
+
+    async fn write(&self, item: u32) {
+        let mut state = self.0.lock();
+        state.command.extend_from_slice(&item.to_le_bytes());
+        state.process_command();
+    }
+
+    async fn wait_tx_empty(&self) {}
+
+    async fn read_with_timeout(&mut self, timeout: Duration) -> error::Result<Option<u32>> {
+        if let Some(word) = self.pop(|state| &mut state.responses) {
+            return Ok(Some(word));
+        }
+        sleep(timeout).await;
+        Ok(self.pop(|state| &mut state.responses))
+    }
+}
+
+impl WorkRxFifoIo for Fifo {
+    fn init(&mut self) -> error::Result<()> {
+        self.0.lock().solutions.clear();
+        Ok(())
+    }
+
+    async fn read(&mut self) -> error::Result<u32> {
+        loop {
+            if let Some(word) = self.pop(|state| &mut state.solutions) {
+                return Ok(word);
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.0.lock().solutions.len() >= WORK_RX_FIFO_SIZE
+    }
+}
+
+impl WorkTxFifoIo for Fifo {
+    fn init(&mut self) -> error::Result<()> {
+        self.0.lock().work.clear();
+        Ok(())
+    }
+
+    fn write(&mut self, item: u32) -> error::Result<()> {
+        self.write_unchecked(item);
+        Ok(())
+    }
+
+    fn write_unchecked(&mut self, item: u32) {
+        self.0.lock().work.push(item);
+    }
+
+    async fn wait_for_room(&self) -> error::Result<()> {
+        while self.is_stalled() {
+            sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    fn has_room(&self) -> bool {
+        !self.is_stalled()
+    }
+
+    fn flush(&mut self) -> error::Result<bool> {
+        self.0.lock().work.clear();
+        Ok(true)
+    }
+}
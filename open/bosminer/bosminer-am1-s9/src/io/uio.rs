@@ -22,8 +22,7 @@
 
 //! Simple wrapper around UIO device
 
-use crate::error::{self, ErrorKind};
-use failure::ResultExt;
+use crate::error::{self, ErrorKind, ResultExt};
 use uio_async;
 
 pub struct Device {
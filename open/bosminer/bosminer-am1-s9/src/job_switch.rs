@@ -0,0 +1,198 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Tracking of job switches caused by a new previous block hash. Each client (job origin) is
+//! tracked separately so that mining jobs of multiple pools (possibly on different blocks) don't
+//! look like switches. When a client switches to a new block, its in-flight work for the old
+//! block is useless so it is purged and the time the chain needs to switch to the new block
+//! (until the first solution of new work is received) is measured.
+
+use bosminer::work;
+
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+/// Key identifying origin (client) of the work
+fn origin_key(work: &work::Assignment) -> usize {
+    work.origin().as_ptr() as *const () as usize
+}
+
+/// Block currently mined for one client
+#[derive(Debug)]
+struct ClientBlock {
+    /// Previous block hash of the most recently sent work of the client
+    previous_hash: ii_bitcoin::DHash,
+    /// Time when the first work of a new block has been sent and no solution for the new block
+    /// has been received yet
+    switch_start: Option<Instant>,
+}
+
+/// Switch of one client to a new block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Switch {
+    origin: usize,
+    previous_hash: ii_bitcoin::DHash,
+}
+
+impl Switch {
+    /// Check if `work` belongs to the old block of the client which has switched
+    pub fn is_stale(&self, work: &work::Assignment) -> bool {
+        origin_key(work) == self.origin && *work.previous_hash() != self.previous_hash
+    }
+}
+
+/// Job switch tracker shared between work TX and solution RX tasks of one hash chain
+#[derive(Debug, Default)]
+pub struct Tracker {
+    /// Current block of each client indexed by its origin key
+    clients: StdMutex<HashMap<usize, ClientBlock>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<HashMap<usize, ClientBlock>> {
+        self.clients
+            .lock()
+            .expect("BUG: cannot lock job switch tracker")
+    }
+
+    /// Account `works` about to be sent to the chain.
+    /// Returns: clients which have been switched to a new block by the works
+    pub fn works_sent(&self, works: &[work::Assignment]) -> Vec<Switch> {
+        let mut clients = self.lock();
+        let mut switches = vec![];
+        for work in works {
+            let origin = origin_key(work);
+            let previous_hash = *work.previous_hash();
+            match clients.get_mut(&origin) {
+                // the very first work of the client is not a switch
+                None => {
+                    clients.insert(
+                        origin,
+                        ClientBlock {
+                            previous_hash,
+                            switch_start: None,
+                        },
+                    );
+                }
+                Some(block) if block.previous_hash != previous_hash => {
+                    block.previous_hash = previous_hash;
+                    block.switch_start = Some(Instant::now());
+                    switches.retain(|switch: &Switch| switch.origin != origin);
+                    switches.push(Switch {
+                        origin,
+                        previous_hash,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        switches
+    }
+
+    /// Account solution received for `work`.
+    /// Returns: latency of job switch when this is the first solution for the new block of the
+    /// client
+    pub fn solution_received(&self, work: &work::Assignment) -> Option<Duration> {
+        let mut clients = self.lock();
+        match clients.get_mut(&origin_key(work)) {
+            Some(block) if block.previous_hash == *work.previous_hash() => {
+                block.switch_start.take().map(|start| start.elapsed())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::null_work;
+
+    use bosminer::job;
+    use bosminer::test_utils;
+    use ii_bitcoin::HashTrait as _;
+
+    use std::sync::Arc;
+
+    fn prepare_work(hash_byte: u8) -> work::Assignment {
+        let mut job = null_work::NullJob::new(0, 0xffff_ffff, 0);
+        job.set_previous_hash(ii_bitcoin::DHash::from_slice(&[hash_byte; 32]).unwrap());
+        work::Assignment::new(Arc::new(job) as Arc<dyn job::Bitcoin>, vec![], 0)
+    }
+
+    fn prepare_other_client_work() -> work::Assignment {
+        let job = Arc::new(test_utils::TEST_BLOCKS[0]);
+        work::Assignment::new(job as Arc<dyn job::Bitcoin>, vec![], 0)
+    }
+
+    #[test]
+    fn test_job_switch() {
+        let tracker = Tracker::new();
+        let old_work = prepare_work(1);
+        let new_work = prepare_work(2);
+
+        // the first work doesn't switch the chain
+        assert!(tracker.works_sent(&[old_work.clone()]).is_empty());
+        assert_eq!(tracker.solution_received(&old_work), None);
+        assert!(tracker.works_sent(&[old_work.clone()]).is_empty());
+
+        let switches = tracker.works_sent(&[old_work.clone(), new_work.clone()]);
+        assert_eq!(switches.len(), 1);
+        assert!(switches[0].is_stale(&old_work));
+        assert!(!switches[0].is_stale(&new_work));
+        // solutions of old work don't finish the switch
+        assert_eq!(tracker.solution_received(&old_work), None);
+        assert!(tracker.solution_received(&new_work).is_some());
+        // latency is measured only once
+        assert_eq!(tracker.solution_received(&new_work), None);
+    }
+
+    #[test]
+    fn test_job_switch_clients() {
+        let tracker = Tracker::new();
+        let work = prepare_work(1);
+        let other_work = prepare_other_client_work();
+        assert_ne!(work.previous_hash(), other_work.previous_hash());
+
+        // alternating clients mining different blocks don't switch the chain
+        for _ in 0..3 {
+            assert!(tracker.works_sent(&[work.clone()]).is_empty());
+            assert!(tracker
+                .works_sent(&[other_work.clone(), work.clone()])
+                .is_empty());
+        }
+
+        // switch of one client doesn't affect work of the other one
+        let new_work = prepare_work(2);
+        let switches = tracker.works_sent(&[new_work.clone(), other_work.clone()]);
+        assert_eq!(switches.len(), 1);
+        assert!(switches[0].is_stale(&work));
+        assert!(!switches[0].is_stale(&other_work));
+        assert_eq!(tracker.solution_received(&other_work), None);
+        assert!(tracker.solution_received(&new_work).is_some());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Front panel LED used for physical identification of the miner (e.g. in a rack)
+
+use ii_logging::macros::*;
+
+use crate::error;
+use crate::gpio;
+
+use embedded_hal::digital::v2::OutputPin;
+
+use ii_async_compat::tokio;
+use tokio::time::sleep;
+
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+/// Identification time used when the operator doesn't specify any
+pub const DEFAULT_IDENTIFY_TIME: Duration = Duration::from_secs(60);
+/// Longest identification time that can be requested
+pub const MAX_IDENTIFY_TIME: Duration = Duration::from_secs(3600);
+
+/// Half period of LED blinking
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Red front LED blinking on request
+pub struct IdentifyLed {
+    pin: StdMutex<gpio::PinOut>,
+    /// Time when blinking stops, `None` when the LED is not blinking
+    deadline: StdMutex<Option<Instant>>,
+}
+
+impl IdentifyLed {
+    pub fn open(gpio_mgr: &gpio::ControlPinManager) -> error::Result<Self> {
+        let led = Self {
+            pin: StdMutex::new(gpio_mgr.get_pin_out(gpio::PinOutName::LEDFrontRed)?),
+            deadline: StdMutex::new(None),
+        };
+        led.set(false)?;
+        Ok(led)
+    }
+
+    fn set(&self, on: bool) -> error::Result<()> {
+        let mut pin = self.pin.lock().expect("BUG: lock failed");
+        if on {
+            pin.set_high()?;
+        } else {
+            pin.set_low()?;
+        }
+        Ok(())
+    }
+
+    /// Blink the LED for `duration`. When the LED is already blinking only the time when the
+    /// blinking stops is changed so zero `duration` stops the identification.
+    pub fn identify(self: &Arc<Self>, duration: Duration) {
+        let mut deadline = self.deadline.lock().expect("BUG: lock failed");
+        let blinking = deadline.is_some();
+        if !blinking && duration == Duration::from_secs(0) {
+            return;
+        }
+        deadline.replace(Instant::now() + duration);
+        if !blinking {
+            tokio::spawn(self.clone().blink_task());
+        }
+    }
+
+    /// Remaining time of identification, `None` when the LED is not blinking
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .lock()
+            .expect("BUG: lock failed")
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    async fn blink_task(self: Arc<Self>) {
+        info!("Identify: blinking front LED");
+        let mut on = false;
+        loop {
+            {
+                let mut deadline = self.deadline.lock().expect("BUG: lock failed");
+                if deadline.map_or(true, |deadline| Instant::now() >= deadline) {
+                    deadline.take();
+                    break;
+                }
+            }
+            on = !on;
+            if let Err(e) = self.set(on) {
+                warn!("Identify: cannot set front LED: {}", e);
+            }
+            sleep(BLINK_INTERVAL).await;
+        }
+        if let Err(e) = self.set(false) {
+            warn!("Identify: cannot turn front LED off: {}", e);
+        }
+        info!("Identify: finished");
+    }
+}
@@ -23,22 +23,34 @@
 
 mod async_i2c;
 pub mod bm1387;
+pub mod board;
 mod cgminer;
+pub mod chip_hashrate;
 pub mod command;
 pub mod config;
 pub mod counters;
+pub mod derate;
+pub mod diag;
 pub mod error;
 pub mod fan;
+pub mod front_panel;
 pub mod gpio;
 pub mod halt;
+pub mod health;
 pub mod hooks;
 pub mod i2c;
 pub mod io;
+pub mod job_switch;
+pub mod led;
 pub mod monitor;
 pub mod null_work;
+pub mod open_core;
 pub mod power;
 pub mod registry;
+pub mod selfcheck;
 pub mod sensor;
+pub mod shutdown;
+pub mod supervisor;
 pub mod utils;
 
 #[cfg(test)]
@@ -47,21 +59,25 @@ pub mod test;
 use ii_logging::macros::*;
 
 use bosminer::async_trait;
+use bosminer::events;
 use bosminer::hal::{self, BackendConfig as _};
 use bosminer::node;
+use bosminer::schedule;
 use bosminer::stats;
 use bosminer::work;
 
+use bosminer_antminer::board::Model as _;
 use bosminer_macros::WorkSolverNode;
 
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex as StdMutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use error::ErrorKind;
-use failure::ResultExt;
+use error::{ErrorKind, ResultExt};
 
 use futures::channel::mpsc;
+use futures::future::FutureExt;
 use futures::lock::{Mutex, MutexGuard};
 use futures::stream::StreamExt;
 use ii_async_compat::futures;
@@ -78,7 +94,7 @@ use ii_bitcoin::MeetsTarget;
 
 use ii_async_compat::tokio;
 use tokio::sync::watch;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 /// Timing constants
 const INACTIVATE_FROM_CHAIN_DELAY: Duration = Duration::from_millis(100);
@@ -93,7 +109,7 @@ const ENUM_RETRY_COUNT: usize = 10;
 /// addresses to the chips need to be assigned with step of 4 (e.g. 0, 4, 8, etc.)
 pub const MAX_CHIPS_ON_CHAIN: usize = 64;
 /// Number of chips to consider OK for initialization
-pub const EXPECTED_CHIPS_ON_CHAIN: usize = 63;
+pub const EXPECTED_CHIPS_ON_CHAIN: usize = board::S9::CHIPS_ON_CHAIN;
 
 /// Oscillator speed for all chips on S9 hash boards
 pub const CHIP_OSC_CLK_HZ: usize = 25_000_000;
@@ -103,12 +119,30 @@ const INIT_CHIP_BAUD_RATE: usize = 115740;
 /// Exact desired target baud rate when hashing at full speed (matches the divisor, too)
 const TARGET_CHIP_BAUD_RATE: usize = 1562500;
 
-/// Address of chip with connected temp sensor
-const TEMP_CHIP: ChipAddress = ChipAddress::One(61);
+/// Addresses of chips with connected temp sensors. Sensors behind all of them are probed and
+/// their readings are aggregated by monitor.
+const TEMP_CHIPS: [ChipAddress; 1] = [ChipAddress::One(61)];
 
 /// Timeout for completion of haschain halt
 const HALT_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How long to wait on exit for submission and acknowledgement of solutions found before all
+/// hashchains have been halted
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The biggest change of chip frequency in one step when frequency of running hashchain changes
+const FREQUENCY_RAMP_STEP: usize = 25_000_000;
+/// How long to stay at each frequency step before making the next one
+const FREQUENCY_RAMP_DWELL: Duration = Duration::from_secs(1);
+/// How long to wait before another attempt to tune hashchain which is owned by someone else
+const TUNING_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How many times a failed hashchain task is spawned again before the hashchain is restarted
+const MAX_TASK_RESTARTS: usize = 3;
+
+/// How long the plug pin has to keep its value before hashboard presence change is accepted
+const PLUG_DEBOUNCE_TIME: Duration = Duration::from_millis(500);
+
 /// Core address space size (it should be 114, but the addresses are non-consecutive)
 const CORE_ADR_SPACE_SIZE: usize = 128;
 
@@ -137,6 +171,11 @@ impl PlugPin {
     pub fn hashboard_present(&self) -> error::Result<bool> {
         Ok(self.pin.is_high()?)
     }
+
+    /// Stream of hashboard presence changes triggered by plug pin interrupts
+    pub fn presence_events(&self) -> error::Result<gpio::PinEvents> {
+        Ok(self.pin.events(gpio::Edge::Both)?)
+    }
 }
 
 /// Type representing reset pin
@@ -175,10 +214,13 @@ impl ResetPin {
 /// - memory mapping of the FPGA control interface
 /// - mining work submission and solution processing
 ///
-/// TODO: disable voltage controller via async `Drop` trait (which doesn't exist yet)
+/// There's no async `Drop`, the hashboard has to be powered down explicitly via `shutdown`
+/// once the hashchain is halted.
 pub struct HashChain {
     /// Number of chips that have been detected
     chip_count: usize,
+    /// Exact number of chips expected on the hash chain
+    expected_chip_count: usize,
     /// Eliminates the need to query the IP core about the current number of configured midstates
     midstate_count: MidstateCount,
     /// ASIC difficulty
@@ -198,6 +240,27 @@ pub struct HashChain {
     /// Do not send open-core work if this is true (some tests that test chip initialization may
     /// want to do this).
     disable_init_work: bool,
+    /// Parameters of core opening and verification
+    open_core: open_core::Config,
+    /// Cores that returned at least one solution since start
+    core_tracker: open_core::Tracker,
+    /// Hashrate of chips sampled from their hashrate registers
+    chip_hashrate: chip_hashrate::Tracker,
+    /// Number of recent work items kept in work registry, `None` means registry default
+    work_registry_depth: Option<usize>,
+    /// Number of recent solutions of each work kept for duplicate detection, `None` means
+    /// registry default
+    duplicate_window: Option<usize>,
+    /// Health tracker shared with the hashchain manager
+    health: Arc<health::Tracker>,
+    /// Handlers of failed tasks that cannot be restarted
+    escalation: supervisor::Escalation,
+    /// Version of FPGA bitstream read when the IP core has been initialized
+    fpga_version: String,
+    /// Hardware self-check where found sensors are recorded
+    self_check: Arc<selfcheck::Tracker>,
+    /// Temperature trajectory of emulated sensor used instead of probing sensor chips
+    sensor_emulation: Option<sensor::emulated::Trajectory>,
     /// channels through which temperature status is sent
     temperature_sender: Mutex<Option<watch::Sender<Option<sensor::Temperature>>>>,
     temperature_receiver: watch::Receiver<Option<sensor::Temperature>>,
@@ -231,7 +294,8 @@ impl HashChain {
     ) -> error::Result<Self> {
         let core = io::Core::new(hashboard_idx, midstate_count)?;
         // Unfortunately, we have to do IP core re-init here (but it should be OK, it's synchronous)
-        let (common_io, command_io, work_rx_io, work_tx_io) = core.init_and_split()?;
+        let (mut common_io, command_io, work_rx_io, work_tx_io) = core.init_and_split()?;
+        let fpga_version = common_io.get_bitstream_info();
 
         // check that the board is present
         if !plug_pin.hashboard_present()? {
@@ -245,10 +309,12 @@ impl HashChain {
         let (temperature_sender, temperature_receiver) = watch::channel(None);
 
         // create halt notification channel
-        let (halt_sender, halt_receiver) = halt::make_pair(HALT_TIMEOUT);
+        let (halt_sender, halt_receiver) =
+            halt::make_named_pair(format!("chain {}", hashboard_idx), HALT_TIMEOUT);
 
         Ok(Self {
             chip_count: 0,
+            expected_chip_count: EXPECTED_CHIPS_ON_CHAIN,
             midstate_count,
             asic_difficulty,
             asic_target: ii_bitcoin::Target::from_pool_difficulty(asic_difficulty),
@@ -261,6 +327,16 @@ impl HashChain {
             work_tx_io: Mutex::new(Some(work_tx_io)),
             monitor_tx,
             disable_init_work: false,
+            open_core: Default::default(),
+            core_tracker: open_core::Tracker::new(),
+            chip_hashrate: chip_hashrate::Tracker::new(),
+            work_registry_depth: None,
+            duplicate_window: None,
+            health: Arc::new(health::Tracker::new()),
+            escalation: Default::default(),
+            fpga_version,
+            self_check: Default::default(),
+            sensor_emulation: None,
             temperature_sender: Mutex::new(Some(temperature_sender)),
             temperature_receiver,
             counter: Arc::new(Mutex::new(counters::HashChain::new(
@@ -338,6 +414,19 @@ impl HashChain {
         Ok(())
     }
 
+    /// Powers down the hashboard: turns off the IP core, parks the chips in reset and disables
+    /// the voltage controller.
+    ///
+    /// This is meant to be called after the hashchain has been halted (all tasks including the
+    /// voltage controller heart beat are gone), otherwise they would keep talking to dead chips.
+    pub async fn shutdown(&self) -> error::Result<()> {
+        info!("Hashboard {}: shutting down", self.hashboard_idx);
+        self.common_io.disable_ip_core();
+        // reset pin is just a handle to GPIO, so it's fine to use a copy of it
+        self.reset_pin.clone().enter_reset()?;
+        self.voltage_ctrl.shutdown().await
+    }
+
     /// Configures difficulty globally on all chips within the hashchain
     async fn set_asic_diff(&mut self, difficulty: usize) -> error::Result<()> {
         let tm_reg = bm1387::TicketMaskReg::new(difficulty as u32)?;
@@ -347,8 +436,31 @@ impl HashChain {
             tm_reg
         );
         self.command_context
-            .write_register_readback(ChipAddress::All, &tm_reg)
+            .write_register(ChipAddress::All, &tm_reg)
             .await?;
+
+        // verify each chip separately to find out which chips (if any) ignored the broadcast
+        let diverging = self
+            .command_context
+            .find_diverging_chips(self.chip_count, &tm_reg, !0)
+            .await;
+        if !diverging.mismatched.is_empty() {
+            Err(ErrorKind::Hashchip(format!(
+                "ticket mask register diverged on chips {:?}",
+                diverging.mismatched
+            )))?
+        }
+        // a chip that fails to respond is not worth failing the whole hashchain
+        if !diverging.unresponsive.is_empty() {
+            warn!(
+                "Chain {}: chips {:?} haven't responded to ticket mask readback",
+                self.hashboard_idx, diverging.unresponsive
+            );
+            let unresponsive = diverging.unresponsive;
+            self.self_check.update_chain(self.hashboard_idx, |chain| {
+                chain.unresponsive_chips = unresponsive
+            });
+        }
         Ok(())
     }
 
@@ -364,11 +476,11 @@ impl HashChain {
         info!("Resetting hash board");
         self.enter_reset()?;
         self.voltage_ctrl.disable_voltage().await?;
-        delay_for(INIT_DELAY).await;
+        sleep(INIT_DELAY).await;
         self.voltage_ctrl.enable_voltage().await?;
-        delay_for(INIT_DELAY * 2).await;
+        sleep(INIT_DELAY * 2).await;
         self.exit_reset()?;
-        delay_for(INIT_DELAY).await;
+        sleep(INIT_DELAY).await;
 
         // Enumerate chips
         info!("Starting chip enumeration");
@@ -379,17 +491,25 @@ impl HashChain {
         self.command_context.set_chip_count(self.chip_count).await;
         self.counter.lock().await.set_chip_count(self.chip_count);
         self.frequency.lock().await.set_chip_count(self.chip_count);
+        self.core_tracker.reset(self.chip_count);
 
+        // More chips than expected means that the hashboard variant is misconfigured
+        if self.chip_count > self.expected_chip_count {
+            Err(ErrorKind::ChipEnumeration(format!(
+                "Too many chips on chain ({} found, {} expected)",
+                self.chip_count, self.expected_chip_count
+            )))?;
+        }
         // If we don't have full number of chips and we do not want incomplete chain, then raise
         // an error
-        if self.chip_count < EXPECTED_CHIPS_ON_CHAIN && !accept_less_chips {
+        if self.chip_count < self.expected_chip_count && !accept_less_chips {
             Err(ErrorKind::ChipEnumeration(
                 "Not enough chips on chain".into(),
             ))?;
         }
 
         // set PLL
-        self.set_pll(initial_frequency).await?;
+        self.set_pll(initial_frequency, None).await?;
 
         // configure the hashing chain to operate at desired baud rate. Note that gate block is
         // enabled to allow continuous start of chips in the chain
@@ -406,13 +526,13 @@ impl HashChain {
     ///
     /// * if enumeration fails (for enumeration-related reason), try to retry
     ///   it up to pre-defined number of times
-    /// * if less than 63 chips is found, retry the enumeration
+    /// * if less than expected number of chips is found, retry the enumeration
     async fn init(
         &mut self,
         initial_frequency: &FrequencySettings,
         initial_voltage: power::Voltage,
         accept_less_chips: bool,
-    ) -> error::Result<Arc<Mutex<registry::WorkRegistry>>> {
+    ) -> error::Result<Arc<registry::WorkRegistry>> {
         info!("Hashboard IP core initialized");
         self.voltage_ctrl
             .clone()
@@ -430,19 +550,35 @@ impl HashChain {
             .await?;
 
         // Build shared work registry
-        // TX fifo determines the size of work registry
-        let work_registry = Arc::new(Mutex::new(registry::WorkRegistry::new(
-            self.work_tx_io
-                .lock()
-                .await
-                .as_ref()
-                .expect("work-tx io missing")
-                .work_id_count(),
-        )));
+        // TX fifo determines the size of work registry (the `work_id` range depends on midstate
+        // count)
+        let registry_size = self
+            .work_tx_io
+            .lock()
+            .await
+            .as_ref()
+            .expect("work-tx io missing")
+            .work_id_count();
+        let registry_depth = match self.work_registry_depth {
+            Some(depth) if depth < registry_size => depth,
+            Some(depth) => {
+                warn!(
+                    "Work registry depth {} exceeds registry size {}, using default",
+                    depth, registry_size
+                );
+                registry::WorkRegistry::default_depth(registry_size)
+            }
+            None => registry::WorkRegistry::default_depth(registry_size),
+        };
+        let mut work_registry = registry::WorkRegistry::with_depth(registry_size, registry_depth);
+        if let Some(duplicate_window) = self.duplicate_window {
+            work_registry.set_duplicate_window(duplicate_window);
+        }
+        let work_registry = Arc::new(work_registry);
 
         // send opencore work (at high voltage) unless someone disabled it
         if !self.disable_init_work {
-            self.send_init_work(work_registry.clone()).await;
+            self.send_init_work(work_registry.clone()).await?;
         }
 
         // lower voltage to working level
@@ -457,14 +593,22 @@ impl HashChain {
 
     /// Detects the number of chips on the hashing chain and assigns an address to each chip
     async fn enumerate_chips(&mut self) -> error::Result<()> {
+        // Reset chip count (we might get called multiple times)
+        self.chip_count = 0;
+        self.chip_count = Self::enumerate_chain(&self.command_context).await?;
+        Ok(())
+    }
+
+    /// Detect chips on chain and assign addresses to them. Returns number of detected chips.
+    async fn enumerate_chain<F: io::CommandFifoIo>(
+        command_context: &command::Context<F>,
+    ) -> error::Result<usize> {
         // Enumerate all chips (broadcast read address register request)
-        let responses = self
-            .command_context
+        let responses = command_context
             .read_register::<bm1387::GetAddressReg>(ChipAddress::All)
             .await?;
 
-        // Reset chip count (we might get called multiple times)
-        self.chip_count = 0;
+        let mut chip_count = 0;
         // Check if are responses meaningful
         for (address, addr_reg) in responses.iter().enumerate() {
             if addr_reg.chip_rev != bm1387::CHIP_REV_BM1387 {
@@ -475,16 +619,16 @@ impl HashChain {
                     addr_reg.chip_rev,
                 )))?
             }
-            self.chip_count += 1;
+            chip_count += 1;
         }
-        if self.chip_count >= MAX_CHIPS_ON_CHAIN {
+        if chip_count >= MAX_CHIPS_ON_CHAIN {
             Err(ErrorKind::ChipEnumeration(format!(
                 "detected {} chips, expected less than {} chips on one chain. Possibly a hardware issue?",
-                self.chip_count,
+                chip_count,
                 MAX_CHIPS_ON_CHAIN,
             )))?
         }
-        if self.chip_count == 0 {
+        if chip_count == 0 {
             Err(ErrorKind::ChipEnumeration(
                 "no chips detected on the current chain".to_string(),
             ))?
@@ -495,21 +639,21 @@ impl HashChain {
         let inactivate_from_chain_cmd = bm1387::InactivateFromChainCmd::new().pack();
         // make sure all chips receive inactivation request
         for _ in 0..3 {
-            self.command_context
+            command_context
                 .send_raw_command(inactivate_from_chain_cmd.to_vec(), false)
                 .await;
-            delay_for(INACTIVATE_FROM_CHAIN_DELAY).await;
+            sleep(INACTIVATE_FROM_CHAIN_DELAY).await;
         }
 
         // Assign address to each chip
-        for i in 0..self.chip_count {
+        for i in 0..chip_count {
             let cmd = bm1387::SetChipAddressCmd::new(ChipAddress::One(i));
-            self.command_context
+            command_context
                 .send_raw_command(cmd.pack().to_vec(), false)
                 .await;
         }
 
-        Ok(())
+        Ok(chip_count)
     }
 
     /// Loads PLL register with a starting value
@@ -528,7 +672,7 @@ impl HashChain {
         );
 
         // NOTE: When PLL register is read back, it is or-ed with 0x8000_0000, not sure why.
-        //  Avoid reading it back to prevent disappointment.
+        //  Avoid reading it back to prevent disappointment (see `PLL_READBACK_MASK`).
         self.command_context
             .write_register(chip_addr, &pll.reg)
             .await?;
@@ -538,8 +682,40 @@ impl HashChain {
 
     /// Load PLL register of all chips
     ///
+    /// With `ramp` the frequency is changed gradually: chips are moved towards the target
+    /// frequency in steps and the hashchain dwells at each step for a while. Without `ramp` the
+    /// target frequency is set at once (e.g. during initialization).
+    ///
     /// Takes care of adjusting `work_time`
-    pub async fn set_pll(&self, frequency: &FrequencySettings) -> error::Result<()> {
+    pub async fn set_pll(
+        &self,
+        frequency: &FrequencySettings,
+        ramp: Option<FrequencyRamp>,
+    ) -> error::Result<()> {
+        let ramp = match ramp {
+            Some(ramp) => ramp,
+            None => return self.load_pll(frequency).await,
+        };
+        loop {
+            let next_frequency = self
+                .get_frequency()
+                .await
+                .step_towards(frequency, ramp.step);
+            self.load_pll(&next_frequency).await?;
+            if next_frequency.reached(frequency) {
+                return Ok(());
+            }
+            trace!(
+                "chain {}: frequency ramped to {}",
+                self.hashboard_idx,
+                next_frequency
+            );
+            sleep(ramp.dwell).await;
+        }
+    }
+
+    /// Load PLL register of all chips at once
+    async fn load_pll(&self, frequency: &FrequencySettings) -> error::Result<()> {
         // TODO: find a better way - how to communicate with frequency setter how many chips we have?
         assert!(frequency.chip.len() >= self.chip_count);
 
@@ -548,11 +724,29 @@ impl HashChain {
             // Update them in one go
             self.set_chip_pll(ChipAddress::All, frequency.chip[0])
                 .await?;
+
+            // verify each chip separately and retry the chips that ignored the broadcast
+            let pll = bm1387::PllFrequency::lookup_freq(frequency.chip[0])?;
+            let diverging = self
+                .command_context
+                .find_diverging_chips(self.chip_count, &pll.reg, bm1387::PLL_READBACK_MASK)
+                .await;
+            if !diverging.is_empty() {
+                warn!(
+                    "chain {}: PLL register diverged on chips {:?}, setting them one by one",
+                    self.hashboard_idx,
+                    diverging.all()
+                );
+                for chip in diverging.all() {
+                    self.set_chip_pll(ChipAddress::One(chip), frequency.chip[0])
+                        .await?;
+                }
+            }
         } else {
             // Update chips one-by-one
             for i in 0..self.chip_count {
-                let new_freq = self.frequency.lock().await.chip[i];
-                if new_freq != frequency.chip[i] {
+                let new_freq = frequency.chip[i];
+                if new_freq != self.frequency.lock().await.chip[i] {
                     self.set_chip_pll(ChipAddress::One(i), new_freq).await?;
                 }
             }
@@ -628,49 +822,105 @@ impl HashChain {
         self.chip_count
     }
 
-    /// Initialize cores by sending open-core work with correct nbits to each core
-    async fn send_init_work(&mut self, work_registry: Arc<Mutex<registry::WorkRegistry>>) {
-        // Each core gets one work
-        const NUM_WORK: usize = bm1387::NUM_CORES_ON_CHIP;
+    /// Initialize cores by sending a sequence of open-core works with correct nbits at open-core
+    /// voltage. The voltage is held until chips process the whole sequence.
+    async fn send_init_work(
+        &mut self,
+        work_registry: Arc<registry::WorkRegistry>,
+    ) -> error::Result<()> {
+        let open_core = self.open_core.clone();
         trace!(
             "Sending out {} pieces of dummy work to initialize chips",
-            NUM_WORK
+            open_core.count
         );
+        self.voltage_ctrl.set_voltage(open_core.voltage).await?;
+
         let midstate_count = self.midstate_count.to_count();
         let mut work_tx_io = self.work_tx_io.lock().await;
         let tx_fifo = work_tx_io.as_mut().expect("tx fifo missing");
-        for _ in 0..NUM_WORK {
-            let work = &null_work::prepare_opencore(true, midstate_count);
+        for i in 0..open_core.count {
+            if i > 0 && open_core.delay > Duration::from_secs(0) {
+                sleep(open_core.delay).await;
+            }
+            // each work of the sequence is unique so the chips don't treat it as a repeated one
+            let work = null_work::prepare_opencore(true, midstate_count, i as u32);
+            tx_fifo.wait_for_room().await.expect("wait for tx room");
             // store work to registry as "initial work" so that later we can properly ignore
             // solutions
-            let work_id = work_registry.lock().await.store_work(work.clone(), true);
-            tx_fifo.wait_for_room().await.expect("wait for tx room");
+            let work_id = work_registry.store_work(work, true);
+            let work = work_registry
+                .get_work(work_id)
+                .expect("BUG: work not stored");
             tx_fifo.send_work(&work, work_id).expect("send work");
         }
+        drop(work_tx_io);
+
+        // the last works may still be in the FIFO, the voltage cannot be lowered yet
+        sleep(open_core.hold).await;
+        Ok(())
     }
 
-    /// This task picks up work from frontend (via generator), saves it to
-    /// registry (to pair with `Assignment` later) and sends it out to hw.
-    /// It makes sure that TX fifo is empty before requesting work from
-    /// generator.
+    /// This task picks up work from frontend (via prefetch buffer filled by generator), saves
+    /// it to registry (to pair with `Assignment` later) and sends it out to hw.
+    /// It makes sure that TX fifo is empty before taking work from the buffer.
+    /// When the works switch the chain to a new block, work for the old block is flushed from
+    /// the FIFO and purged from the registry so its solutions are dropped early.
     /// It exits when generator returns `None`.
     async fn work_tx_task(
-        work_registry: Arc<Mutex<registry::WorkRegistry>>,
+        hashboard_idx: usize,
+        work_registry: Arc<registry::WorkRegistry>,
         mut tx_fifo: io::WorkTx,
-        mut work_generator: work::Generator,
+        work_generator: work::Generator,
+        job_switch: Arc<job_switch::Tracker>,
+        counter: Arc<Mutex<counters::HashChain>>,
     ) {
+        // fill the FIFO with as many works as fit into it after one wait for room
+        let batch_size = tx_fifo.batch_size();
+        // works are generated ahead of time so that the FIFO doesn't run empty at high
+        // frequencies while the generator is scheduled
+        let mut prefetch = work::prefetch::Prefetch::new(
+            work_generator,
+            batch_size,
+            work::prefetch::DEFAULT_MAX_DEPTH.max(batch_size),
+        );
+        // buffers are reused for all batches to avoid allocation for each work
+        let mut works = Vec::with_capacity(batch_size);
+        let mut work_ids = Vec::with_capacity(batch_size);
         loop {
+            if !tx_fifo.has_room() {
+                prefetch.pipeline_stats().account_tx_fifo_full();
+            }
             tx_fifo.wait_for_room().await.expect("wait for tx room");
-            let work = work_generator.generate().await;
-            match work {
-                None => return,
-                Some(work) => {
-                    // assign `work_id` to `work`
-                    let work_id = work_registry.lock().await.store_work(work.clone(), false);
-                    // send work is synchronous
-                    tx_fifo.send_work(&work, work_id).expect("send work");
-                }
+            prefetch.next_batch(batch_size, &mut works).await;
+            if works.is_empty() {
+                return;
+            }
+            let switches = job_switch.works_sent(&works);
+            if !switches.is_empty() {
+                let flushed = tx_fifo.flush().expect("flush tx fifo");
+                let purged =
+                    work_registry.purge(|work| switches.iter().any(|switch| switch.is_stale(work)));
+                counter.lock().await.add_job_switch(purged);
+                debug!(
+                    "Chain {}: switched to new block, {} works purged (FIFO flushed: {})",
+                    hashboard_idx, purged, flushed
+                );
             }
+            // move works to the registry to assign `work_id` to them
+            work_ids.extend(
+                works
+                    .drain(..)
+                    .map(|work| work_registry.store_work(work, false)),
+            );
+            // send work is synchronous and serializes the works directly from the registry
+            tx_fifo.send_work_batch(work_ids.drain(..).map(|work_id| {
+                (
+                    work_registry
+                        .get_work(work_id)
+                        .expect("BUG: work not stored"),
+                    work_id,
+                )
+            }));
         }
     }
 
@@ -683,65 +933,110 @@ impl HashChain {
     /// TODO: figure out when and how to stop this task
     async fn solution_rx_task(
         self: Arc<Self>,
-        work_registry: Arc<Mutex<registry::WorkRegistry>>,
+        work_registry: Arc<registry::WorkRegistry>,
         mut rx_fifo: io::WorkRx,
         solution_sender: work::SolutionSender,
+        job_switch: Arc<job_switch::Tracker>,
         counter: Arc<Mutex<counters::HashChain>>,
     ) {
+        // full FIFO is accounted only once until it's drained
+        let mut rx_fifo_full = false;
         // solution receiving/filtering part
         loop {
+            if rx_fifo.is_full() {
+                if !rx_fifo_full {
+                    solution_sender.pipeline_stats().account_rx_fifo_overflow();
+                    rx_fifo_full = true;
+                }
+            } else {
+                rx_fifo_full = false;
+            }
             let (rx_fifo_out, hw_solution) =
                 rx_fifo.recv_solution().await.expect("recv solution failed");
             rx_fifo = rx_fifo_out;
             let work_id = hw_solution.hardware_id;
             let solution = Solution::from_hw_solution(&hw_solution, self.asic_target);
-            let mut work_registry = work_registry.lock().await;
+            let core_addr = bm1387::CoreAddress::new(solution.nonce);
 
-            let work = work_registry.find_work(work_id as usize);
-            match work {
-                Some(work_item) => {
-                    // ignore solutions coming from initial work
+            // the registry slot is locked while the work is looked up so it has to be released
+            // before any `await` (stale solution is returned with its age as an error)
+            let status = match work_registry.lookup_solution_work(work_id as usize) {
+                registry::Lookup::Active(mut work_item) => {
+                    // ignore solutions coming from initial work (they just prove the core is open)
                     if work_item.initial_work {
+                        self.core_tracker.account(core_addr);
                         continue;
                     }
-                    let core_addr = bm1387::CoreAddress::new(solution.nonce);
-                    let status = work_item.insert_solution(solution);
-
-                    // work item detected a new unique solution, we will push it for further processing
-                    if let Some(unique_solution) = status.unique_solution {
-                        if !status.duplicate {
-                            let hash = unique_solution.hash();
-                            if !hash.meets(unique_solution.backend_target()) {
-                                info!("Solution from hashchain not hitting ASIC target; {}", hash);
-                                counter.lock().await.add_error(core_addr);
-                            } else {
-                                counter.lock().await.add_valid(core_addr);
-                            }
-                            solution_sender.send(unique_solution);
-                        }
-                    }
-                    if status.duplicate {
-                        counter.lock().await.add_error(core_addr);
-                    }
-                    if status.mismatched_nonce {
-                        counter.lock().await.add_error(core_addr);
+                    // work with fewer midstates has been padded with its own midstates when it
+                    // was sent (see `io::WorkTx::serialize_work`), the same solution is
+                    // reported for the original midstate
+                    if solution.midstate_idx >= work_item.work().midstates.len() {
+                        continue;
                     }
+                    let job_switch_latency = job_switch.solution_received(work_item.work());
+                    Ok((work_item.insert_solution(solution), job_switch_latency))
+                }
+                registry::Lookup::Retired { age } => {
+                    debug!(
+                        "Stale solution (work retired {} work items ago), ID:{:#x} {:#010x?}",
+                        age, work_id, solution
+                    );
+                    Err(age)
                 }
-                None => {
+                registry::Lookup::Unknown => {
                     info!(
                         "No work present for solution, ID:{:#x} {:#010x?}",
                         work_id, solution
                     );
+                    continue;
+                }
+            };
+            let status = match status {
+                Ok((status, job_switch_latency)) => {
+                    if let Some(latency) = job_switch_latency {
+                        debug!(
+                            "Chain {}: job switch latency {:?}",
+                            self.hashboard_idx, latency
+                        );
+                        counter.lock().await.set_job_switch_latency(latency);
+                    }
+                    status
                 }
+                Err(age) => {
+                    counter.lock().await.add_stale(age);
+                    continue;
+                }
+            };
+
+            // work item detected a new unique solution, we will push it for further processing
+            if let Some(unique_solution) = status.unique_solution {
+                if !status.duplicate {
+                    let hash = unique_solution.hash();
+                    if !hash.meets(unique_solution.backend_target()) {
+                        info!("Solution from hashchain not hitting ASIC target; {}", hash);
+                        counter.lock().await.add_error(core_addr);
+                    } else {
+                        counter.lock().await.add_valid(core_addr);
+                        self.core_tracker.account(core_addr);
+                    }
+                    solution_sender.send(unique_solution);
+                }
+            }
+            if status.duplicate {
+                counter.lock().await.add_duplicate(core_addr);
+            }
+            if status.mismatched_nonce {
+                counter.lock().await.add_error(core_addr);
             }
         }
     }
 
     async fn try_to_initialize_sensor(
         command_context: command::Context,
+        temp_chip: ChipAddress,
     ) -> error::Result<Box<dyn sensor::Sensor>> {
-        // construct I2C bus via command interface
-        let i2c_bus = bm1387::i2c::Bus::new_and_init(command_context, TEMP_CHIP)
+        // construct I2C bus via command interface, sensor commands give way to work commands
+        let i2c_bus = bm1387::i2c::Bus::new_and_init(command_context.sensor_context(), temp_chip)
             .await
             .with_context(|_| ErrorKind::Sensors("bus construction failed".into()))?;
 
@@ -785,90 +1080,188 @@ impl HashChain {
 
         // Wait some time before trying to initialize temperature controller
         // (Otherwise RX queue might be clogged with initial work and we will not get any replies)
-        //
-        // TODO: we should implement a more robust mechanism that controls access to the I2C bus of
-        // a hashing chip only if the hashchain allows it (hashchain is in operation etc.)
-        delay_for(Duration::from_secs(5)).await;
-
-        // Try to probe sensor
-        // This may fail - in which case we put `None` into `sensor`
-        let mut sensor = match Self::try_to_initialize_sensor(self.command_context.clone())
-            .await
-            .with_context(|_| ErrorKind::Hashboard(self.hashboard_idx, "sensor error".into()))
-            .map_err(|e| e.into())
-        {
-            error::Result::Err(e) => {
-                error!("Sensor probing failed: {}", e);
-                None
+        // Afterwards the sensor transactions are deferred while the hashchain issues its own
+        // commands (see `command::Priority`).
+        sleep(Duration::from_secs(5)).await;
+
+        // Try to probe sensors
+        // Probing may fail - in which case the sensor is left out
+        let mut sensors = Vec::new();
+        for temp_chip in TEMP_CHIPS.iter() {
+            match Self::try_to_initialize_sensor(self.command_context.clone(), *temp_chip)
+                .await
+                .with_context(|_| ErrorKind::Hashboard(self.hashboard_idx, "sensor error".into()))
+            {
+                error::Result::Err(e) => {
+                    error!("Sensor probing on chip {:?} failed: {}", temp_chip, e);
+                }
+                error::Result::Ok(sensor) => sensors.push(sensor),
             }
-            error::Result::Ok(sensor) => Some(sensor),
-        };
+        }
+        // Emulation is meant only for boards without sensor chips
+        if let Some(trajectory) = self.sensor_emulation.clone() {
+            if sensors.is_empty() {
+                warn!(
+                    "Hash chain {}: temperature sensor is emulated",
+                    self.hashboard_idx
+                );
+                let mut sensor = sensor::emulated::Emulated::new(trajectory);
+                sensor
+                    .init()
+                    .await
+                    .expect("BUG: emulated sensor initialization failed");
+                sensors.push(sensor);
+            } else {
+                error!(
+                    "Hash chain {}: sensor emulation refused because real sensor is present",
+                    self.hashboard_idx
+                );
+            }
+        }
+        let sensor_count = sensors.len();
+        self.self_check.update_chain(self.hashboard_idx, |chain| {
+            chain.sensors = Some(sensor_count)
+        });
+
+        // Number of failed commands seen in the previous iteration of the loop
+        let mut command_failures = self.command_context.stats().await.failures;
 
         // "Watchdog" loop that pings monitor every some seconds
         loop {
-            // If we have temperature sensor, try to read it
-            let temp = if let Some(sensor) = sensor.as_mut() {
-                match sensor
-                    .read_temperature()
-                    .await
-                    .with_context(|_| {
-                        ErrorKind::Hashboard(self.hashboard_idx, "temperature read fail".into())
-                    })
-                    .map_err(|e| e.into())
-                {
+            // Commands that failed even after retries indicate persistent problem with the
+            // command bus (intermittent errors are handled by retries)
+            let command_stats = self.command_context.stats().await;
+            if command_stats.failures > command_failures {
+                warn!(
+                    "Hash chain {}: {} commands failed (command bus stats: {:?})",
+                    self.hashboard_idx,
+                    command_stats.failures - command_failures,
+                    command_stats
+                );
+                self.health
+                    .report_not_well(health::Reason::CommandBusErrors);
+                command_failures = command_stats.failures;
+            }
+
+            // Try to read all temperature sensors we have
+            let mut temps = Vec::with_capacity(sensors.len());
+            for sensor in sensors.iter_mut() {
+                let temp = match sensor.read_temperature().await.with_context(|_| {
+                    ErrorKind::Hashboard(self.hashboard_idx, "temperature read fail".into())
+                }) {
                     error::Result::Ok(temp) => {
                         info!("Measured temperature: {:?}", temp);
+                        self.health.report_well();
                         temp
                     }
                     error::Result::Err(e) => {
                         error!("Sensor temperature read failed: {}", e);
+                        self.health.report_not_well(health::Reason::CommsError);
                         sensor::INVALID_TEMPERATURE_READING
                     }
-                }
-            } else {
-                // Otherwise just make empty temperature reading
-                sensor::INVALID_TEMPERATURE_READING
-            };
+                };
+                temps.push(temp);
+            }
 
             // Broadcast
             temperature_sender
-                .broadcast(Some(temp.clone()))
+                .send(Some(sensor::Temperature::primary(&temps)))
                 .expect("temp broadcast failed");
 
             // Send heartbeat to monitor
             self.monitor_tx
-                .unbounded_send(monitor::Message::Running(temp))
+                .unbounded_send(monitor::Message::Running(temps))
                 .expect("send failed");
 
             // TODO: sync this delay with monitor task
-            delay_for(Duration::from_secs(5)).await;
+            sleep(Duration::from_secs(5)).await;
         }
     }
 
     /// Hashrate monitor task
-    /// Fetch perodically information about hashrate
-    #[allow(dead_code)]
+    /// Sample periodically hashrate registers of all chips and reconcile them with hashrate
+    /// derived from nonces found in the same period
     async fn hashrate_monitor_task(self: Arc<Self>) {
-        info!("Hashrate monitor task started");
+        info!("Chain {}: hashrate monitor started", self.hashboard_idx);
+        let mut last_counter = self.snapshot_counter().await;
         loop {
-            delay_for(Duration::from_secs(5)).await;
+            sleep(chip_hashrate::SAMPLE_PERIOD).await;
 
-            let responses = self
+            let registers = match self
                 .command_context
                 .read_register::<bm1387::HashrateReg>(ChipAddress::All)
                 .await
-                .expect("reading hashrate_reg failed");
+            {
+                Ok(responses) => responses
+                    .iter()
+                    .map(|hashrate_reg| Some(hashrate_reg.hashrate()))
+                    .collect(),
+                Err(e) => {
+                    warn!(
+                        "Chain {}: reading of chip hashrate failed: {}",
+                        self.hashboard_idx, e
+                    );
+                    vec![None; self.chip_count]
+                }
+            };
+            let counter = self.snapshot_counter().await;
+            let frequency = self.get_frequency().await;
+
+            // Counters may have been reset in the meantime
+            let since_last = counter.started == last_counter.started;
+            let period = if since_last {
+                counter
+                    .duration()
+                    .checked_sub(last_counter.duration())
+                    .unwrap_or_default()
+            } else {
+                counter.duration()
+            };
+            let chips = registers
+                .into_iter()
+                .zip(frequency.chip.iter())
+                .enumerate()
+                .map(|(idx, (register, frequency))| {
+                    let valid = counter.chip.get(idx).map_or(0, |chip| chip.valid);
+                    let last_valid = match last_counter.chip.get(idx) {
+                        Some(chip) if since_last => chip.valid,
+                        _ => 0,
+                    };
+                    chip_hashrate::Chip::new(
+                        register,
+                        valid.saturating_sub(last_valid),
+                        counter.asic_difficulty,
+                        period,
+                        *frequency,
+                    )
+                })
+                .collect::<Vec<_>>();
+            last_counter = counter;
 
-            let mut sum = 0;
-            for (chip_address, hashrate_reg) in responses.iter().enumerate() {
+            for (idx, chip) in chips.iter().enumerate() {
                 trace!(
-                    "chip {} hashrate {} GHash/s",
-                    chip_address,
-                    hashrate_reg.hashrate() as f64 / 1e9
+                    "Chain {}: chip {} hashrate register={:?} nonce={} expected={} GH/s",
+                    self.hashboard_idx,
+                    idx,
+                    chip.register.map(|hashrate| hashrate / 1e9),
+                    chip.nonce / 1e9,
+                    chip.expected / 1e9
+                );
+            }
+            for idx in self.chip_hashrate.update(chips) {
+                warn!(
+                    "Chain {}: hashrate of chip {} reported by the chip diverges from \
+                     the expected hashrate by more than {}%",
+                    self.hashboard_idx,
+                    idx,
+                    chip_hashrate::DIVERGENCE_THRESHOLD
                 );
-                sum += hashrate_reg.hashrate() as u128;
             }
-            info!("Total chip hashrate {} GH/s", sum as f64 / 1e9);
+            info!(
+                "Chain {}: effective chip hashrate {:.2} GH/s",
+                self.hashboard_idx,
+                self.chip_hashrate.total_effective() / 1e9
+            );
         }
     }
 
@@ -876,46 +1269,126 @@ impl HashChain {
         self: Arc<Self>,
         work_generator: work::Generator,
         solution_sender: work::SolutionSender,
-        work_registry: Arc<Mutex<registry::WorkRegistry>>,
+        work_registry: Arc<registry::WorkRegistry>,
     ) {
-        // spawn tx task
-        let tx_fifo = self.take_work_tx_io().await;
-        self.halt_receiver
-            .register_client("work-tx".into())
-            .await
-            .spawn(Self::work_tx_task(
-                work_registry.clone(),
-                tx_fifo,
-                work_generator,
-            ));
+        // job switches are detected by tx task and finished by rx task
+        let job_switch = Arc::new(job_switch::Tracker::new());
+
+        // unexpected termination of these tasks would leave the hashchain idle
+        let supervisor = supervisor::Supervisor::new(
+            self.halt_receiver.clone(),
+            Some(self.hashboard_idx),
+            self.escalation.clone(),
+        );
+
+        // spawn tx task (FIFOs cannot be taken twice, the whole chain has to be restarted)
+        let mut tx_task = Some(Self::work_tx_task(
+            self.hashboard_idx,
+            work_registry.clone(),
+            self.take_work_tx_io().await,
+            work_generator,
+            job_switch.clone(),
+            self.counter.clone(),
+        ));
+        supervisor
+            .spawn(
+                format!("chain {} work-tx", self.hashboard_idx),
+                supervisor::Policy::RestartChain,
+                move || tx_task.take().expect("BUG: work-tx task restarted"),
+            )
+            .await;
 
         // spawn rx task
-        let rx_fifo = self.take_work_rx_io().await;
-        self.halt_receiver
-            .register_client("work-rx".into())
-            .await
-            .spawn(Self::solution_rx_task(
-                self.clone(),
-                work_registry.clone(),
-                rx_fifo,
-                solution_sender,
-                self.counter.clone(),
-            ));
+        let mut rx_task = Some(Self::solution_rx_task(
+            self.clone(),
+            work_registry.clone(),
+            self.take_work_rx_io().await,
+            solution_sender,
+            job_switch,
+            self.counter.clone(),
+        ));
+        supervisor
+            .spawn(
+                format!("chain {} work-rx", self.hashboard_idx),
+                supervisor::Policy::RestartChain,
+                move || rx_task.take().expect("BUG: work-rx task restarted"),
+            )
+            .await;
 
         // spawn hashrate monitor
-        // Disabled until we found a use for this
-        /*
-        self.halt_receiver
-            .register_client("hashrate monitor".into())
-            .await
-            .spawn(Self::hashrate_monitor_task(self.clone()));
-        */
+        let hash_chain = self.clone();
+        supervisor
+            .spawn(
+                format!("chain {} hashrate monitor", self.hashboard_idx),
+                supervisor::Policy::RestartTask {
+                    max_restarts: MAX_TASK_RESTARTS,
+                },
+                move || Self::hashrate_monitor_task(hash_chain.clone()),
+            )
+            .await;
 
-        // spawn temperature monitor
-        self.halt_receiver
-            .register_client("temperature monitor".into())
-            .await
-            .spawn(Self::monitor_watchdog_temp_task(self.clone()));
+        // spawn temperature monitor (temperature sender cannot be taken twice)
+        let mut temperature_task = Some(Self::monitor_watchdog_temp_task(self.clone()));
+        supervisor
+            .spawn(
+                format!("chain {} temperature monitor", self.hashboard_idx),
+                supervisor::Policy::RestartChain,
+                move || {
+                    temperature_task
+                        .take()
+                        .expect("BUG: temperature monitor restarted")
+                },
+            )
+            .await;
+
+        // spawn verification of opened cores
+        if let Some(check_time) = self.open_core.check_time {
+            self.halt_receiver
+                .register_client(format!("chain {} core check", self.hashboard_idx))
+                .await
+                .spawn(Self::core_check_task(self.clone(), check_time));
+        }
+    }
+
+    /// Report cores that haven't returned any solution within `check_time` after start. Such
+    /// cores either haven't been opened or are dead.
+    async fn core_check_task(self: Arc<Self>, check_time: Duration) {
+        sleep(check_time).await;
+        let silent_cores = self.core_tracker.silent_cores();
+        if silent_cores.is_empty() {
+            info!("Chain {}: all cores are hashing", self.hashboard_idx);
+            return;
+        }
+        let core_count: usize = silent_cores.iter().map(|chip| chip.cores.len()).sum();
+        warn!(
+            "Chain {}: {} core(s) haven't returned any solution in {} s",
+            self.hashboard_idx,
+            core_count,
+            check_time.as_secs()
+        );
+        for chip in silent_cores {
+            if chip.whole_chip() {
+                warn!(
+                    "Chain {}: no core of chip {} responds",
+                    self.hashboard_idx, chip.chip
+                );
+            } else {
+                warn!(
+                    "Chain {}: chip {} cores {:?} don't respond",
+                    self.hashboard_idx, chip.chip, chip.cores
+                );
+            }
+        }
+    }
+
+    /// Cores that haven't returned any solution since start
+    pub fn silent_cores(&self) -> Vec<open_core::SilentCores> {
+        self.core_tracker.silent_cores()
+    }
+
+    /// Hashrate of chip `chip_idx` from the last sample of hashrate registers
+    pub fn chip_hashrate(&self, chip_idx: usize) -> Option<chip_hashrate::Chip> {
+        self.chip_hashrate.get(chip_idx)
     }
 
     pub async fn reset_counter(&self) {
@@ -979,7 +1452,6 @@ impl FrequencySettings {
         *self.chip.iter().min().expect("BUG: no chips on chain")
     }
 
-    #[allow(dead_code)]
     pub fn max(&self) -> usize {
         *self.chip.iter().max().expect("BUG: no chips on chain")
     }
@@ -990,6 +1462,44 @@ impl FrequencySettings {
         (sum / self.chip.len() as u64) as usize
     }
 
+    /// Return settings where frequency of each chip is moved towards `target` frequency by at
+    /// most `step`
+    pub fn step_towards(&self, target: &Self, step: Frequency) -> Self {
+        Self {
+            chip: self
+                .chip
+                .iter()
+                .zip(target.chip.iter())
+                .map(|(&current, &target)| {
+                    if current < target {
+                        target.min(current.saturating_add(step))
+                    } else {
+                        target.max(current.saturating_sub(step))
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Return settings where frequency of each chip is lowered by `step` but not below `min`
+    pub fn lowered_by(&self, step: Frequency, min: Frequency) -> Self {
+        Self {
+            chip: self
+                .chip
+                .iter()
+                .map(|&frequency| frequency.saturating_sub(step).max(min))
+                .collect(),
+        }
+    }
+
+    /// Check that all chips run at `target` frequency
+    pub fn reached(&self, target: &Self) -> bool {
+        self.chip
+            .iter()
+            .zip(target.chip.iter())
+            .all(|(current, target)| current == target)
+    }
+
     fn pretty_frequency(freq: usize) -> String {
         format!("{:.01} MHz", (freq as f32) / 1_000_000.0)
     }
@@ -1013,6 +1523,67 @@ impl fmt::Display for FrequencySettings {
     }
 }
 
+/// Gradual change of hashchain frequency (see `HashChain::set_pll`)
+#[derive(Debug, Clone, Copy)]
+pub struct FrequencyRamp {
+    /// The biggest change of chip frequency in one step
+    pub step: Frequency,
+    /// How long to stay at each step
+    pub dwell: Duration,
+}
+
+impl Default for FrequencyRamp {
+    fn default() -> Self {
+        Self {
+            step: FREQUENCY_RAMP_STEP,
+            dwell: FREQUENCY_RAMP_DWELL,
+        }
+    }
+}
+
+/// Request to change operating point of running hashchain
+#[derive(Clone)]
+pub struct TuningRequest {
+    pub frequency: Option<FrequencySettings>,
+    pub voltage: Option<power::Voltage>,
+}
+
+/// Progress of hashchain restart requested by operator
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestartStatus {
+    /// No restart has been requested yet
+    Idle,
+    /// Restart is waiting for hashchain ownership or the hashchain is being stopped
+    Stopping,
+    /// Hashchain is being initialized again
+    Starting,
+    /// The last restart finished successfully
+    Done,
+    /// The last restart failed with an error
+    Failed(String),
+}
+
+impl RestartStatus {
+    pub fn in_progress(&self) -> bool {
+        match self {
+            Self::Stopping | Self::Starting => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RestartStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Idle => write!(f, "Idle"),
+            Self::Stopping => write!(f, "Stopping"),
+            Self::Starting => write!(f, "Starting"),
+            Self::Done => write!(f, "Done"),
+            Self::Failed(_) => write!(f, "Failed"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct StoppedChain {
     pub manager: Arc<Manager>,
@@ -1066,24 +1637,46 @@ impl StoppedChain {
                 Ok(_) => {
                     // we've started the hashchain
                     // create a `Running` tape and be gone
-                    return Ok(RunningChain::from_manager(
+                    let running_chain = RunningChain::from_manager(
                         self.manager.clone(),
                         self.manager.inner.lock().await,
-                    ));
+                    );
+                    self.manager.event_bus.publish(events::Kind::ChainStarted {
+                        hashboard_idx: self.manager.hashboard_idx,
+                    });
+                    self.manager.hooks.chain_started(self.manager.clone()).await;
+                    return Ok(running_chain);
                 }
                 // start failed
                 Err(e) => {
                     error!("Chain {} start failed: {}", self.manager.hashboard_idx, e);
 
-                    // retry if possible
-                    if tries_left == 0 {
+                    // retry if possible (bitstream doesn't change by retrying)
+                    if matches!(e.kind(), ErrorKind::IncompatibleBitstream(..)) {
+                        return Err((self, e.into()));
+                    } else if tries_left == 0 {
                         error!("No tries left");
                         return Err((self, e.into()));
                     } else {
                         tries_left -= 1;
-                        // TODO: wait with locks unlocked()! Otherwise no-one can halt the miner
-                        // This is not possible with current lock design, but fix this ASAP!
-                        delay_for(ENUM_RETRY_DELAY).await;
+                        // The chain is owned during the wait, so halt has to interrupt it.
+                        // Otherwise the miner cannot be stopped until all retries are done.
+                        if self
+                            .manager
+                            .halt_receiver
+                            .sleep(ENUM_RETRY_DELAY)
+                            .await
+                            .is_err()
+                        {
+                            info!(
+                                "Chain {} start interrupted by halt",
+                                self.manager.hashboard_idx
+                            );
+                            return Err((
+                                self,
+                                ErrorKind::Halt("chain start interrupted".into()).into(),
+                            ));
+                        }
                         info!("Retrying chain {} start...", self.manager.hashboard_idx);
                     }
                 }
@@ -1125,6 +1718,10 @@ impl RunningChain {
 
     pub async fn stop(self) -> StoppedChain {
         self.manager.stop_chain(false).await;
+        self.manager.event_bus.publish(events::Kind::ChainStopped {
+            hashboard_idx: self.manager.hashboard_idx,
+        });
+        self.manager.hooks.chain_stopped(self.manager.clone()).await;
 
         StoppedChain {
             manager: self.manager.clone(),
@@ -1153,25 +1750,70 @@ impl RunningChain {
             .await
     }
 
+    /// Change frequency of the hashchain gradually (see `FrequencyRamp`)
     pub async fn set_frequency(&self, frequency: &FrequencySettings) -> error::Result<()> {
-        let inner = self.manager.inner.lock().await;
-        inner
-            .hash_chain
-            .as_ref()
-            .expect("BUG: hashchain is not running")
-            .set_pll(frequency)
+        // do not hold the lock while ramping the frequency
+        let hash_chain = self
+            .manager
+            .inner
+            .lock()
             .await
+            .hash_chain
+            .clone()
+            .expect("BUG: hashchain is not running");
+        hash_chain
+            .set_pll(frequency, Some(FrequencyRamp::default()))
+            .await?;
+        self.manager
+            .hooks
+            .chain_tuned(
+                self.manager.clone(),
+                hooks::TuningEvent::Frequency(frequency.clone()),
+            )
+            .await;
+        Ok(())
     }
 
     pub async fn set_voltage(&self, voltage: power::Voltage) -> error::Result<()> {
-        let inner = self.manager.inner.lock().await;
-        inner
-            .hash_chain
-            .as_ref()
-            .expect("BUG: hashchain is not running")
-            .voltage_ctrl
-            .set_voltage(voltage)
-            .await
+        {
+            let inner = self.manager.inner.lock().await;
+            inner
+                .hash_chain
+                .as_ref()
+                .expect("BUG: hashchain is not running")
+                .voltage_ctrl
+                .set_voltage(voltage)
+                .await?;
+        }
+        self.manager
+            .hooks
+            .chain_tuned(self.manager.clone(), hooks::TuningEvent::Voltage(voltage))
+            .await;
+        Ok(())
+    }
+
+    /// Change frequency and/or voltage of the hashchain. The voltage is raised before the
+    /// frequency goes up and lowered after the frequency goes down so that the chips don't run
+    /// at high frequency with low voltage.
+    pub async fn tune(&self, request: &TuningRequest) -> error::Result<()> {
+        let frequency_up = match &request.frequency {
+            Some(frequency) => frequency.avg() > self.get_frequency().await.avg(),
+            None => false,
+        };
+        if frequency_up {
+            if let Some(voltage) = request.voltage {
+                self.set_voltage(voltage).await?;
+            }
+        }
+        if let Some(frequency) = &request.frequency {
+            self.set_frequency(frequency).await?;
+        }
+        if !frequency_up {
+            if let Some(voltage) = request.voltage {
+                self.set_voltage(voltage).await?;
+            }
+        }
+        Ok(())
     }
 
     pub async fn reset_counter(&self) {
@@ -1250,11 +1892,11 @@ impl RunningChain {
         const MAX_PREHEAT_DELAY: u64 = 180;
 
         let mut status_receiver = self.manager.status_receiver.clone();
-        // wait for status from monitor
         let started = Instant::now();
         // TODO: wrap `status_receiver` into some kind of API
-        while let Some(status) = status_receiver.next().await {
+        loop {
             // take just non-empty status messages
+            let status = status_receiver.borrow_and_update().clone();
             if let Some(status) = status {
                 if Self::preheat_ok(status) {
                     break;
@@ -1265,6 +1907,10 @@ impl RunningChain {
                 info!("Preheat: waiting too long to heat-up, skipping preheat");
                 return;
             }
+            // wait for status from monitor
+            if status_receiver.changed().await.is_err() {
+                break;
+            }
         }
     }
 }
@@ -1302,12 +1948,35 @@ pub struct Manager {
     plug_pin: PlugPin,
     reset_pin: ResetPin,
     voltage_ctrl_backend: Arc<power::I2cBackend>,
-    midstate_count: MidstateCount,
+    /// Number of midstates used when the hashchain is started (it can be switched at runtime)
+    midstate_count: StdMutex<MidstateCount>,
     /// channel to report to the monitor
     monitor_tx: mpsc::UnboundedSender<monitor::Message>,
     /// TODO: wrap this type in a structure (in Monitor)
     pub status_receiver: watch::Receiver<Option<monitor::Status>>,
     owned_by: StdMutex<Option<&'static str>>,
+    /// Extension hooks notified about hashchain events
+    hooks: Arc<dyn hooks::Hooks>,
+    /// Bus where start and stop of the hashchain is published
+    event_bus: Arc<events::Bus>,
+    /// Miner termination context used to interrupt retry loops
+    halt_receiver: halt::Receiver,
+    /// Handlers of failed hashchain tasks passed to every started hashchain
+    escalation: supervisor::Escalation,
+    /// Health of the hashchain kept across its restarts
+    pub health: Arc<health::Tracker>,
+    /// Hardware self-check updated on every start of the hashchain
+    self_check: Arc<selfcheck::Tracker>,
+    /// Channel of requests to change operating point of the hashchain at runtime
+    tuning_sender: mpsc::UnboundedSender<TuningRequest>,
+    tuning_receiver: Mutex<Option<mpsc::UnboundedReceiver<TuningRequest>>>,
+    /// Channel of requests to restart the hashchain
+    restart_sender: mpsc::UnboundedSender<()>,
+    restart_receiver: Mutex<Option<mpsc::UnboundedReceiver<()>>>,
+    restart_status: StdMutex<RestartStatus>,
+    /// The hashchain has been stopped by scheduled profile and it should be started again when
+    /// the profile changes
+    paused_by_schedule: AtomicBool,
     pub inner: Mutex<ManagerInner>,
     pub chain_config: config::ResolvedChainConfig,
 }
@@ -1360,29 +2029,73 @@ impl Manager {
         inner.start_count += 1;
 
         // make us a hash chain
-        let mut hash_chain = HashChain::new(
+        let mut hash_chain = match HashChain::new(
             self.reset_pin.clone(),
             self.plug_pin.clone(),
             self.voltage_ctrl_backend.clone(),
             self.hashboard_idx,
-            self.midstate_count,
+            self.midstate_count(),
             asic_difficulty,
             self.monitor_tx.clone(),
-        )
-        .expect("BUG: hashchain instantiation failed");
+        ) {
+            Ok(hash_chain) => hash_chain,
+            Err(e) => {
+                // e.g. FPGA bitstream is not compatible with the driver
+                self.self_check.update_chain(self.hashboard_idx, |chain| {
+                    chain.status = selfcheck::ChainStatus::Failed;
+                    chain.error = Some(e.to_string());
+                });
+                self.monitor_tx
+                    .unbounded_send(monitor::Message::Off)
+                    .expect("BUG: send failed");
+                self.health.report_not_well(health::Reason::InitFailed);
+                return Err(e);
+            }
+        };
+        hash_chain.expected_chip_count = self.chain_config.expected_chip_count();
+        hash_chain.work_registry_depth = self.chain_config.work_registry_depth;
+        hash_chain.duplicate_window = self.chain_config.duplicate_window;
+        hash_chain.open_core = self.chain_config.open_core.clone();
+        hash_chain.sensor_emulation = self.chain_config.sensor_emulation.clone();
+        hash_chain.health = self.health.clone();
+        hash_chain.escalation = self.escalation.clone();
+        hash_chain.self_check = self.self_check.clone();
 
         // initialize it
-        let work_registry = match hash_chain
+        let result = hash_chain
             .init(initial_frequency, initial_voltage, accept_less_chips)
-            .await
-        {
+            .await;
+        let pic_version = hash_chain.voltage_ctrl.get_firmware_version().await;
+        self.self_check.update_chain(self.hashboard_idx, |chain| {
+            chain.status = match result {
+                Ok(_) => selfcheck::ChainStatus::Ok,
+                Err(_) => selfcheck::ChainStatus::Failed,
+            };
+            chain.chips = Some(hash_chain.chip_count);
+            chain.pic_version = pic_version;
+            chain.fpga_version = Some(hash_chain.fpga_version.clone());
+            chain.error = result.as_ref().err().map(|e| e.to_string());
+        });
+        let work_registry = match result {
             Err(e) => {
                 // halt is required to stop voltage heart-beat task
-                hash_chain.halt_sender.clone().send_halt().await;
+                hash_chain
+                    .halt_sender
+                    .clone()
+                    .send_halt_with_reason("init failed".into())
+                    .await;
+                // do not leave the hashboard powered if init failed half-way
+                if let Err(shutdown_error) = hash_chain.shutdown().await {
+                    error!(
+                        "Chain {} shutdown failed: {}",
+                        self.hashboard_idx, shutdown_error
+                    );
+                }
                 // deregister us
                 self.monitor_tx
                     .unbounded_send(monitor::Message::Off)
                     .expect("BUG: send failed");
+                self.health.report_not_well(health::Reason::InitFailed);
 
                 return Err(e)?;
             }
@@ -1402,33 +2115,435 @@ impl Manager {
 
         // remember we started
         inner.hash_chain.replace(hash_chain);
+        self.health.report_well();
 
         Ok(())
     }
 
     /// TODO: this function is private and should be called only from `RunningChain`
-    async fn stop_chain(&self, its_ok_if_its_missing: bool) {
+    /// Return value: `true` if the hashchain was running and has been stopped
+    async fn stop_chain(&self, its_ok_if_its_missing: bool) -> bool {
         // lock inner to guarantee atomicity of hashchain stop
         let mut inner = self.inner.lock().await;
 
         // TODO: maybe we should throw an error instead
         let hash_chain = inner.hash_chain.take();
         if hash_chain.is_none() && its_ok_if_its_missing {
-            return;
+            return false;
         }
         let hash_chain = hash_chain.expect("BUG: hashchain is missing");
 
         // stop everything
-        hash_chain.halt_sender.clone().send_halt().await;
+        hash_chain
+            .halt_sender
+            .clone()
+            .send_halt_with_reason("chain stopped".into())
+            .await;
+
+        // and power the hashboard down once there are no tasks using it
+        if let Err(e) = hash_chain.shutdown().await {
+            error!("Chain {} shutdown failed: {}", self.hashboard_idx, e);
+        }
 
         // tell monitor we are done
         self.monitor_tx
             .unbounded_send(monitor::Message::Off)
             .expect("BUG: send failed");
+
+        true
+    }
+
+    /// Task that watches hardware errors of running hashchain and derates its operating point on
+    /// sustained error bursts (see `derate`). Derate steps are forgotten when the hashchain is
+    /// restarted because its operating point is then given by whoever restarted it.
+    async fn derate_task(self: Arc<Self>, derate_config: derate::Config) {
+        let mut controller = derate::Controller::new(derate_config.clone());
+        // operating points the hashchain has been running at before each derate step
+        let mut previous_points: Vec<(FrequencySettings, power::Voltage)> = Vec::new();
+        let mut last_start_id = None;
+        while self
+            .halt_receiver
+            .sleep(derate_config.interval)
+            .await
+            .is_ok()
+        {
+            // skip the check if hashchain is stopped or someone else is handling it
+            let chain = match self.clone().acquire("derate").await {
+                Ok(ChainStatus::Running(chain)) => chain,
+                _ => continue,
+            };
+            if last_start_id.replace(chain.start_id) != Some(chain.start_id) {
+                controller.reset();
+                previous_points.clear();
+            }
+
+            let counter = chain.snapshot_counter().await;
+            let step =
+                match controller.sample(counter.valid, counter.errors, !previous_points.is_empty())
+                {
+                    Some(step) => step,
+                    None => continue,
+                };
+            let frequency = chain.get_frequency().await;
+            let voltage = chain.get_voltage().await;
+            let request = match step {
+                derate::Step::Down => {
+                    let min_frequency = (config::FREQUENCY_MHZ_MIN * 1_000_000.0) as usize;
+                    let max_voltage = derate_config.max_voltage.unwrap_or(0.0);
+                    // per-chip frequencies (e.g. from autotuning) are kept relative to each other
+                    let request = if frequency.max() > min_frequency {
+                        TuningRequest {
+                            frequency: Some(
+                                frequency.lowered_by(derate_config.frequency_step, min_frequency),
+                            ),
+                            voltage: None,
+                        }
+                    } else if voltage.as_volts() < max_voltage {
+                        let raised_voltage =
+                            (voltage.as_volts() + derate_config.voltage_step).min(max_voltage);
+                        match power::Voltage::from_volts(raised_voltage) {
+                            Ok(raised_voltage) => TuningRequest {
+                                frequency: None,
+                                voltage: Some(raised_voltage),
+                            },
+                            Err(e) => {
+                                error!("Chain {} cannot be derated: {}", self.hashboard_idx, e);
+                                continue;
+                            }
+                        }
+                    } else {
+                        warn!(
+                            "Chain {} keeps producing HW errors, but it cannot be derated anymore",
+                            self.hashboard_idx
+                        );
+                        continue;
+                    };
+                    previous_points.push((frequency, voltage));
+                    request
+                }
+                derate::Step::Up => {
+                    let (frequency, voltage) = previous_points
+                        .pop()
+                        .expect("BUG: no derate step to restore");
+                    TuningRequest {
+                        frequency: Some(frequency),
+                        voltage: Some(voltage),
+                    }
+                }
+            };
+
+            if let Err(e) = chain.tune(&request).await {
+                error!("Chain {} derate tuning failed: {}", self.hashboard_idx, e);
+                continue;
+            }
+            let frequency = chain.get_frequency().await.avg() as f64 / 1_000_000.0;
+            let voltage = chain.get_voltage().await.as_volts();
+            let kind = match step {
+                derate::Step::Down => {
+                    let error_rate = controller.error_ratio().unwrap_or_default() * 100.0;
+                    warn!(
+                        "Chain {}: {:.1} % of HW errors, derated to {:.0} MHz and {:.2} V",
+                        self.hashboard_idx, error_rate, frequency, voltage
+                    );
+                    events::Kind::ChainDerated {
+                        hashboard_idx: self.hashboard_idx,
+                        frequency,
+                        voltage,
+                        error_rate,
+                    }
+                }
+                derate::Step::Up => {
+                    info!(
+                        "Chain {}: stable, probed upward to {:.0} MHz and {:.2} V",
+                        self.hashboard_idx, frequency, voltage
+                    );
+                    events::Kind::ChainUprated {
+                        hashboard_idx: self.hashboard_idx,
+                        frequency,
+                        voltage,
+                    }
+                }
+            };
+            self.event_bus.publish(kind);
+        }
+    }
+
+    /// Watch plug pin of the hashboard. Running hashchain is stopped when its hashboard is
+    /// unplugged and it's started again once the hashboard is plugged back.
+    async fn plug_watchdog_task(self: Arc<Self>, mut plug_events: gpio::PinEvents) {
+        // the pin is read after the stream has been created so no change is missed
+        let mut present = self.plug_pin.hashboard_present().unwrap_or(true);
+        let mut stopped_by_unplug = false;
+        while let Some(value) = plug_events.next().await {
+            // filter out bouncing of plug contacts
+            let value = match plug_events.settle(value, PLUG_DEBOUNCE_TIME).await {
+                Some(value) => value,
+                None => break,
+            };
+            if value == present {
+                continue;
+            }
+            present = value;
+            if !present {
+                error!("Chain {}: hashboard has been unplugged", self.hashboard_idx);
+                self.event_bus.publish(events::Kind::ChainBroken {
+                    hashboard_idx: self.hashboard_idx,
+                    reason: "hashboard unplugged".to_string(),
+                });
+                match self.clone().acquire("plug watchdog").await {
+                    Ok(ChainStatus::Running(chain)) => {
+                        chain.stop().await;
+                        stopped_by_unplug = true;
+                    }
+                    Ok(ChainStatus::Stopped(_)) => {}
+                    Err(owner) => warn!(
+                        "Chain {} is owned by {}, unplugged hashboard cannot be stopped",
+                        self.hashboard_idx, owner
+                    ),
+                }
+            } else {
+                info!(
+                    "Chain {}: hashboard has been plugged in",
+                    self.hashboard_idx
+                );
+                if stopped_by_unplug {
+                    stopped_by_unplug = false;
+                    self.request_restart();
+                }
+            }
+        }
+    }
+
+    /// Request change of operating point of the hashchain. The request is applied
+    /// asynchronously when the hashchain is running and nobody else owns it.
+    pub fn request_tuning(&self, request: TuningRequest) {
+        self.tuning_sender
+            .unbounded_send(request)
+            .expect("BUG: tuning channel closed");
+    }
+
+    /// Apply tuning requests to running hashchain. Only the latest request is applied when
+    /// more requests arrive in the meantime.
+    async fn tuning_task(self: Arc<Self>) {
+        let mut tuning_receiver = self
+            .tuning_receiver
+            .lock()
+            .await
+            .take()
+            .expect("BUG: tuning receiver missing");
+        while let Some(mut request) = tuning_receiver.next().await {
+            loop {
+                while let Ok(Some(newer_request)) = tuning_receiver.try_next() {
+                    request = newer_request;
+                }
+                match self.clone().acquire("tuning").await {
+                    Ok(ChainStatus::Running(chain)) => {
+                        if let Err(e) = chain.tune(&request).await {
+                            error!("Chain {} tuning failed: {}", self.hashboard_idx, e);
+                        }
+                        break;
+                    }
+                    Ok(ChainStatus::Stopped(_)) => {
+                        warn!(
+                            "Chain {} is not running, tuning request ignored",
+                            self.hashboard_idx
+                        );
+                        break;
+                    }
+                    Err(owner) => {
+                        debug!(
+                            "Chain {} is owned by {}, postponing tuning",
+                            self.hashboard_idx, owner
+                        );
+                        if self.halt_receiver.sleep(TUNING_RETRY_DELAY).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Request restart of the hashchain. Stopped hashchain is just started. Returns `false`
+    /// when another restart is still in progress.
+    pub fn request_restart(&self) -> bool {
+        {
+            let mut restart_status = self.restart_status.lock().expect("BUG: lock failed");
+            if restart_status.in_progress() {
+                return false;
+            }
+            *restart_status = RestartStatus::Stopping;
+        }
+        self.restart_sender
+            .unbounded_send(())
+            .expect("BUG: restart channel closed");
+        true
+    }
+
+    pub fn restart_status(&self) -> RestartStatus {
+        self.restart_status
+            .lock()
+            .expect("BUG: lock failed")
+            .clone()
+    }
+
+    /// Number of midstates the hashchain is (or will be) started with
+    pub fn midstate_count(&self) -> MidstateCount {
+        *self.midstate_count.lock().expect("BUG: lock failed")
+    }
+
+    fn set_restart_status(&self, status: RestartStatus) {
+        *self.restart_status.lock().expect("BUG: lock failed") = status;
+    }
+
+    /// Restart the hashchain with its current operating point whenever it is requested
+    async fn restart_task(self: Arc<Self>) {
+        let mut restart_receiver = self
+            .restart_receiver
+            .lock()
+            .await
+            .take()
+            .expect("BUG: restart receiver missing");
+        while restart_receiver.next().await.is_some() {
+            let chain = loop {
+                match self.clone().acquire("restart").await {
+                    Ok(chain) => break chain,
+                    Err(owner) => {
+                        debug!(
+                            "Chain {} is owned by {}, postponing restart",
+                            self.hashboard_idx, owner
+                        );
+                        if self.halt_receiver.sleep(TUNING_RETRY_DELAY).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            };
+            let (chain, frequency, voltage, asic_difficulty) = match chain {
+                ChainStatus::Running(chain) => {
+                    info!("Restart: stopping chain {}", self.hashboard_idx);
+                    let frequency = chain.get_frequency().await;
+                    let voltage = chain.get_voltage().await;
+                    let asic_difficulty = chain.asic_difficulty;
+                    (chain.stop().await, frequency, voltage, asic_difficulty)
+                }
+                ChainStatus::Stopped(chain) => (
+                    chain,
+                    self.chain_config.frequency.clone(),
+                    self.chain_config.voltage,
+                    config::DEFAULT_ASIC_DIFFICULTY,
+                ),
+            };
+
+            // explicit restart overrides paused profile
+            self.paused_by_schedule.store(false, Ordering::Relaxed);
+            self.set_restart_status(RestartStatus::Starting);
+            info!("Restart: starting chain {}", self.hashboard_idx);
+            match chain.start(&frequency, voltage, asic_difficulty).await {
+                Ok(_) => self.set_restart_status(RestartStatus::Done),
+                Err((_, e)) => {
+                    error!("Chain {} restart failed: {}", self.hashboard_idx, e);
+                    self.set_restart_status(RestartStatus::Failed(e.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Frequency and voltage of the hashchain in scheduled `profile`. Values missing in the
+    /// profile are taken from the configuration.
+    fn operating_point(
+        &self,
+        profile: Option<&schedule::Profile>,
+    ) -> (FrequencySettings, power::Voltage) {
+        let frequency = match profile.and_then(|profile| profile.frequency) {
+            Some(frequency) => {
+                FrequencySettings::from_frequency((frequency * 1_000_000.0) as usize)
+            }
+            None => self.chain_config.frequency.clone(),
+        };
+        let voltage = match profile.and_then(|profile| Some((profile, profile.voltage?))) {
+            Some((profile, voltage)) => {
+                power::Voltage::from_volts(voltage as f32).unwrap_or_else(|e| {
+                    error!("Profile '{}' voltage invalid: {}", profile, e);
+                    self.chain_config.voltage
+                })
+            }
+            None => self.chain_config.voltage,
+        };
+        (frequency, voltage)
+    }
+
+    /// Apply scheduled `profile` to the hashchain. Paused profile stops running hashchain and
+    /// any other profile starts it again (if it has been paused by schedule) or tunes it.
+    async fn apply_profile(self: Arc<Self>, profile: Option<schedule::Profile>) {
+        let paused = profile
+            .as_ref()
+            .map(|profile| profile.paused)
+            .unwrap_or(false);
+        let (frequency, voltage) = self.operating_point(profile.as_ref());
+        loop {
+            match self.clone().acquire("schedule").await {
+                Ok(ChainStatus::Running(chain)) => {
+                    if paused {
+                        info!("Schedule: pausing chain {}", self.hashboard_idx);
+                        chain.stop().await;
+                        self.paused_by_schedule.store(true, Ordering::Relaxed);
+                    } else {
+                        let request = TuningRequest {
+                            frequency: Some(frequency),
+                            voltage: Some(voltage),
+                        };
+                        if let Err(e) = chain.tune(&request).await {
+                            error!("Chain {} tuning failed: {}", self.hashboard_idx, e);
+                        }
+                    }
+                    break;
+                }
+                Ok(ChainStatus::Stopped(chain)) => {
+                    if !paused && self.paused_by_schedule.swap(false, Ordering::Relaxed) {
+                        info!("Schedule: resuming chain {}", self.hashboard_idx);
+                        if let Err((_, e)) = chain
+                            .start(&frequency, voltage, config::DEFAULT_ASIC_DIFFICULTY)
+                            .await
+                        {
+                            error!("Chain {} start failed: {}", self.hashboard_idx, e);
+                        }
+                    }
+                    break;
+                }
+                Err(owner) => {
+                    debug!(
+                        "Chain {} is owned by {}, postponing profile",
+                        self.hashboard_idx, owner
+                    );
+                    if self.halt_receiver.sleep(TUNING_RETRY_DELAY).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
     }
 
     async fn termination_handler(self: Arc<Self>) {
-        self.stop_chain(true).await;
+        if self.stop_chain(true).await {
+            self.hooks.chain_stopped(self.clone()).await;
+        }
+    }
+
+    /// Collect telemetry of running hashchain, `None` if the hashchain is not running
+    async fn collect_telemetry(&self) -> Option<hooks::ChainTelemetry> {
+        let inner = self.inner.lock().await;
+        match inner.hash_chain.as_ref() {
+            Some(hash_chain) => Some(hooks::ChainTelemetry {
+                hashboard_idx: self.hashboard_idx,
+                chip_count: hash_chain.chip_count,
+                frequency: hash_chain.get_frequency().await,
+                voltage: hash_chain.get_voltage().await,
+                temperature: hash_chain.current_temperature(),
+                counter: hash_chain.snapshot_counter().await,
+            }),
+            None => None,
+        }
     }
 }
 
@@ -1438,6 +2553,10 @@ impl node::WorkSolver for Manager {
         Some(self.hashboard_idx)
     }
 
+    fn get_serial(&self) -> Option<String> {
+        self.chain_config.serial.clone()
+    }
+
     async fn get_nominal_hashrate(&self) -> Option<ii_bitcoin::HashesUnit> {
         let inner = self.inner.lock().await;
         match inner.hash_chain.as_ref() {
@@ -1462,6 +2581,66 @@ impl fmt::Display for Manager {
     }
 }
 
+/// Switch number of midstates (AsicBoost) of all hashchains at runtime. Running hashchains are
+/// stopped, work engines of all clients are regenerated for the new number of midstates and
+/// the hashchains are started again with their current operating point. FPGA and the work
+/// registry are configured for the new number of midstates when the hashchain starts.
+pub async fn switch_midstate_count(
+    managers: &[Arc<Manager>],
+    client_manager: &bosminer::client::Manager,
+    midstate_count: MidstateCount,
+) -> error::Result<()> {
+    // own all hashchains for the whole switch so that no other task (tuning, schedule, ...)
+    // can start them in the meantime
+    let mut chains = Vec::with_capacity(managers.len());
+    for manager in managers {
+        match manager.clone().acquire("asicboost").await {
+            Ok(chain) => chains.push(chain),
+            Err(owner) => Err(ErrorKind::HashChainManager(error::HashChainManager::Busy(
+                manager.hashboard_idx,
+                owner.to_string(),
+            )))?,
+        }
+    }
+
+    // quiesce all running hashchains
+    let mut stopped_chains = Vec::with_capacity(chains.len());
+    let mut restarts = Vec::new();
+    for chain in chains {
+        match chain {
+            ChainStatus::Running(chain) => {
+                info!("AsicBoost: stopping chain {}", chain.manager.hashboard_idx);
+                let frequency = chain.get_frequency().await;
+                let voltage = chain.get_voltage().await;
+                let asic_difficulty = chain.asic_difficulty;
+                restarts.push((chain.stop().await, frequency, voltage, asic_difficulty));
+            }
+            ChainStatus::Stopped(chain) => stopped_chains.push(chain),
+        }
+    }
+
+    info!(
+        "AsicBoost: switching to {} midstate(s)",
+        midstate_count.to_count()
+    );
+    for manager in managers {
+        *manager.midstate_count.lock().expect("BUG: lock failed") = midstate_count;
+    }
+    client_manager
+        .set_midstate_count(midstate_count.to_count())
+        .await;
+
+    // resume mining on previously running hashchains
+    for (chain, frequency, voltage, asic_difficulty) in restarts {
+        let hashboard_idx = chain.manager.hashboard_idx;
+        info!("AsicBoost: starting chain {}", hashboard_idx);
+        if let Err((_, e)) = chain.start(&frequency, voltage, asic_difficulty).await {
+            error!("Chain {} start failed: {}", hashboard_idx, e);
+        }
+    }
+    Ok(())
+}
+
 /// Represents solution from the hardware combined with difficulty
 #[derive(Clone, Debug)]
 pub struct Solution {
@@ -1534,10 +2713,132 @@ impl Backend {
         Ok(detected)
     }
 
-    /// Miner termination handler called when app is shutdown.
-    /// Just propagate the shutdown to all hashchain managers
-    async fn termination_handler(halt_sender: Arc<halt::Sender>) {
-        halt_sender.send_halt().await;
+    /// Record speed of fans into self-check once they had time to spin up
+    async fn fan_check_task(
+        self_check: Arc<selfcheck::Tracker>,
+        status_receiver: watch::Receiver<Option<monitor::Status>>,
+    ) {
+        sleep(selfcheck::FAN_CHECK_DELAY).await;
+        let status = status_receiver.borrow().clone();
+        match status {
+            Some(status) => self_check.record_fans(status.fan_feedback.rpm),
+            None => warn!("Self-check: fans cannot be checked without monitor status"),
+        }
+    }
+
+    /// Start hashchains one after another with `gap` in between, so that inrush currents of
+    /// hashboards being powered on don't add up and trip the PSU
+    async fn chain_start_scheduler(
+        managers: Vec<Arc<Manager>>,
+        gap: Duration,
+        profile: Option<schedule::Profile>,
+        halt_receiver: halt::Receiver,
+    ) {
+        for (i, manager) in managers.into_iter().enumerate() {
+            if i > 0 && halt_receiver.sleep(gap).await.is_err() {
+                info!("Scheduler: miner halted, remaining hashchains are not started");
+                break;
+            }
+            info!("Scheduler: starting hashchain {}", manager.hashboard_idx);
+            let (initial_frequency, initial_voltage) = manager.operating_point(profile.as_ref());
+            tokio::spawn(async move {
+                let result = manager
+                    .acquire("main")
+                    .await
+                    .expect("BUG: failed to acquire hashchain")
+                    .expect_stopped()
+                    .start(
+                        &initial_frequency,
+                        initial_voltage,
+                        config::DEFAULT_ASIC_DIFFICULTY,
+                    )
+                    .await;
+                match result {
+                    Ok(_) => {}
+                    // the miner is being stopped
+                    Err((_, e)) if matches!(e.kind(), ErrorKind::Halt(_)) => {}
+                    // other hashchains can keep mining, the problem is reported by self-check
+                    Err((_, e)) if matches!(e.kind(), ErrorKind::IncompatibleBitstream(..)) => {
+                        error!("Scheduler: hashchain is left stopped: {}", e)
+                    }
+                    Err((_, e)) => panic!("BUG: failed to start hashchain: {}", e),
+                }
+            });
+        }
+    }
+
+    /// Task that applies changes of scheduled profile to all hashchains
+    async fn schedule_task(
+        managers: Vec<Arc<Manager>>,
+        mut profile_receiver: watch::Receiver<Option<schedule::Profile>>,
+    ) {
+        // the initial profile has been applied when the hashchains were started
+        profile_receiver.borrow_and_update();
+        while profile_receiver.changed().await.is_ok() {
+            let profile = profile_receiver.borrow_and_update().clone();
+            info!(
+                "Schedule: applying profile {}",
+                profile
+                    .as_ref()
+                    .map(|profile| format!("'{}'", profile))
+                    .unwrap_or_else(|| "default".to_string())
+            );
+            futures::future::join_all(
+                managers
+                    .iter()
+                    .map(|manager| manager.clone().apply_profile(profile.clone())),
+            )
+            .await;
+        }
+    }
+
+    /// Task restarting hashchains that have been found broken by monitor
+    async fn broken_chain_restart_task(
+        managers: Vec<Arc<Manager>>,
+        mut restart_receiver: mpsc::UnboundedReceiver<usize>,
+    ) {
+        while let Some(hashboard_idx) = restart_receiver.next().await {
+            match managers
+                .iter()
+                .find(|manager| manager.hashboard_idx == hashboard_idx)
+            {
+                Some(manager) => {
+                    if !manager.request_restart() {
+                        info!(
+                            "Monitor: restart of chain {} is already in progress",
+                            hashboard_idx
+                        );
+                    }
+                }
+                None => warn!("Monitor: cannot restart unknown chain {}", hashboard_idx),
+            }
+        }
+    }
+
+    /// Task that periodically collects state of all hashchains and passes it to `hooks`
+    async fn telemetry_task(
+        hooks: Arc<dyn hooks::Hooks>,
+        managers: Vec<Arc<Manager>>,
+        monitor: Arc<monitor::Monitor>,
+        interval: Duration,
+    ) {
+        loop {
+            sleep(interval).await;
+            let mut chains = Vec::with_capacity(managers.len());
+            for manager in managers.iter() {
+                if let Some(chain) = manager.collect_telemetry().await {
+                    chains.push(chain);
+                }
+            }
+            let monitor_status = monitor.status_receiver.borrow().clone();
+            hooks
+                .telemetry(hooks::Telemetry {
+                    timestamp: SystemTime::now(),
+                    monitor_status,
+                    chains,
+                })
+                .await;
+        }
     }
 
     /// Start miner
@@ -1549,6 +2850,7 @@ impl Backend {
         backend_config: config::Backend,
         app_halt_receiver: halt::Receiver,
         app_halt_sender: Arc<halt::Sender>,
+        self_check: Arc<selfcheck::Tracker>,
     ) -> (Vec<Arc<Manager>>, Arc<monitor::Monitor>) {
         // Create hooks
         let hooks = match backend_config.hooks.as_ref() {
@@ -1556,12 +2858,8 @@ impl Backend {
             None => Arc::new(hooks::NoHooks),
         };
 
-        // Create new termination context and link it to the main (app) termination context
-        let (halt_sender, halt_receiver) = halt::make_pair(HALT_TIMEOUT);
-        app_halt_receiver
-            .register_client("miner termination".into())
-            .await
-            .spawn_halt_handler(Self::termination_handler(halt_sender.clone()));
+        // Create new termination context as a child of the main (app) termination context
+        let (halt_sender, halt_receiver) = app_halt_receiver.make_child("miner".into()).await;
         hooks
             .halt_created(
                 halt_sender.clone(),
@@ -1570,6 +2868,16 @@ impl Backend {
             )
             .await;
 
+        let event_bus = backend_config
+            .event_bus
+            .clone()
+            .expect("BUG: missing event bus");
+        let scheduler = backend_config
+            .scheduler
+            .clone()
+            .expect("BUG: missing scheduler");
+        let initial_profile = scheduler.active();
+
         // Start monitor in main (app) termination context
         // Let it shutdown the main context as well
         let monitor_config = backend_config.resolve_monitor_config();
@@ -1578,11 +2886,39 @@ impl Backend {
             monitor_config,
             app_halt_sender.clone(),
             app_halt_receiver.clone(),
+            event_bus.clone(),
         )
         .await;
+        if let Some(history_config) = backend_config.resolve_monitor_history_config() {
+            info!(
+                "Recording monitor status history into '{}'",
+                history_config.path.display()
+            );
+            app_halt_receiver
+                .register_client("monitor history".into())
+                .await
+                .spawn(monitor::history::record_task(
+                    history_config,
+                    monitor.status_receiver.clone(),
+                ));
+        }
         hooks.monitor_started(monitor.clone()).await;
+        app_halt_receiver
+            .register_client("self-check fans".into())
+            .await
+            .spawn(Self::fan_check_task(
+                self_check.clone(),
+                monitor.status_receiver.clone(),
+            ));
 
         let voltage_ctrl_backend = Arc::new(power::I2cBackend::new(0));
+        // failed hashchain tasks are handled by restarting the hashchain via monitor (or by
+        // shutting down the miner)
+        let escalation = supervisor::Escalation {
+            chain_restart: Some(monitor.chain_restart_sender()),
+            miner_shutdown: Some(app_halt_sender.clone()),
+            ..Default::default()
+        };
         let mut managers = Vec::new();
         info!(
             "Initializing miner, enabled_chains={:?}, midstate_count={}",
@@ -1595,12 +2931,22 @@ impl Backend {
             let monitor_tx = monitor.register_hashchain(hashboard_idx).await;
             // make pins
             let chain_config = backend_config.resolve_chain_config(hashboard_idx);
+            if let Some(variant) = chain_config.variant {
+                info!("Hashboard {}: variant {}", hashboard_idx, variant.name());
+            }
+
+            self_check.update_chain(hashboard_idx, |chain| {
+                chain.serial = chain_config.serial.clone();
+                chain.expected_chips = Some(chain_config.expected_chip_count());
+            });
 
             let status_receiver = monitor.status_receiver.clone();
 
             // build hashchain_node for statistics and static parameters
             let manager = work_hub
                 .create_work_solver(|work_generator, solution_sender| {
+                    let (tuning_sender, tuning_receiver) = mpsc::unbounded();
+                    let (restart_sender, restart_receiver) = mpsc::unbounded();
                     Manager {
                         // TODO: create a new substructure of the miner that will hold all gpio and
                         // "physical-insertion" detection data. This structure will be persistent in
@@ -1612,13 +2958,25 @@ impl Backend {
                             .expect("failed to make pin"),
                         voltage_ctrl_backend: voltage_ctrl_backend.clone(),
                         hashboard_idx,
-                        midstate_count: chain_config.midstate_count,
+                        midstate_count: StdMutex::new(chain_config.midstate_count),
                         work_solver_stats: Default::default(),
                         solution_sender,
                         work_generator,
                         monitor_tx,
                         status_receiver,
                         owned_by: StdMutex::new(None),
+                        hooks: hooks.clone(),
+                        event_bus: event_bus.clone(),
+                        halt_receiver: halt_receiver.clone(),
+                        escalation: escalation.clone(),
+                        health: Arc::new(health::Tracker::new()),
+                        self_check: self_check.clone(),
+                        tuning_sender,
+                        tuning_receiver: Mutex::new(Some(tuning_receiver)),
+                        restart_sender,
+                        restart_receiver: Mutex::new(Some(restart_receiver)),
+                        restart_status: StdMutex::new(RestartStatus::Idle),
+                        paused_by_schedule: AtomicBool::new(false),
                         inner: Mutex::new(ManagerInner {
                             hash_chain: None,
                             start_count: 0,
@@ -1631,38 +2989,102 @@ impl Backend {
         }
 
         // start everything
+        let mut scheduled_managers = Vec::new();
         for manager in managers.iter() {
-            let halt_receiver = halt_receiver.clone();
-            let manager = manager.clone();
-
-            let initial_frequency = manager.chain_config.frequency.clone();
-            let initial_voltage = manager.chain_config.voltage;
-            let hooks = hooks.clone();
-
             // Register handler to stop hashchain when miner is stopped
             halt_receiver
-                .register_client("hashchain".into())
+                .register_client(format!("hashchain {}", manager.hashboard_idx))
                 .await
                 .spawn_halt_handler(Manager::termination_handler(manager.clone()));
 
             // Suppress haschain start if chain is either not enabled or haschain hook doesn't
             // want us to start it (default `NoHooks` has all chains enabled).
             if hooks.can_start_chain(manager.clone()).await {
-                tokio::spawn(async move {
-                    manager
-                        .acquire("main")
-                        .await
-                        .expect("BUG: failed to acquire hashchain")
-                        .expect_stopped()
-                        .start(
-                            &initial_frequency,
-                            initial_voltage,
-                            config::DEFAULT_ASIC_DIFFICULTY,
-                        )
-                        .await
-                        .expect("BUG: failed to start hashchain");
+                match initial_profile.as_ref().filter(|profile| profile.paused) {
+                    Some(profile) => {
+                        info!(
+                            "Schedule: chain {} paused by profile '{}'",
+                            manager.hashboard_idx, profile
+                        );
+                        manager.paused_by_schedule.store(true, Ordering::Relaxed);
+                    }
+                    None => {
+                        manager
+                            .monitor_tx
+                            .unbounded_send(monitor::Message::Pending)
+                            .expect("BUG: send failed");
+                        scheduled_managers.push(manager.clone());
+                    }
+                }
+            } else {
+                self_check.update_chain(manager.hashboard_idx, |chain| {
+                    chain.status = selfcheck::ChainStatus::Disabled
                 });
             }
+
+            halt_receiver
+                .register_client(format!("tuning {}", manager.hashboard_idx))
+                .await
+                .spawn(Manager::tuning_task(manager.clone()));
+
+            halt_receiver
+                .register_client(format!("restart {}", manager.hashboard_idx))
+                .await
+                .spawn(Manager::restart_task(manager.clone()));
+
+            match manager.plug_pin.presence_events() {
+                Ok(plug_events) => {
+                    halt_receiver
+                        .register_client(format!("plug watchdog {}", manager.hashboard_idx))
+                        .await
+                        .spawn(Manager::plug_watchdog_task(manager.clone(), plug_events));
+                }
+                Err(e) => warn!(
+                    "Chain {}: hotplug detection is not available: {}",
+                    manager.hashboard_idx, e
+                ),
+            }
+
+            if let Some(derate_config) = manager.chain_config.derate.clone() {
+                halt_receiver
+                    .register_client(format!("derate {}", manager.hashboard_idx))
+                    .await
+                    .spawn(Manager::derate_task(manager.clone(), derate_config));
+            }
+        }
+        halt_receiver
+            .register_client("chain start scheduler".into())
+            .await
+            .spawn(Self::chain_start_scheduler(
+                scheduled_managers,
+                backend_config.resolve_chain_start_gap(),
+                initial_profile,
+                halt_receiver.clone(),
+            ));
+        halt_receiver
+            .register_client("schedule".into())
+            .await
+            .spawn(Self::schedule_task(managers.clone(), scheduler.subscribe()));
+        if let Some(restart_receiver) = monitor.take_restart_receiver().await {
+            halt_receiver
+                .register_client("broken chain restart".into())
+                .await
+                .spawn(Self::broken_chain_restart_task(
+                    managers.clone(),
+                    restart_receiver,
+                ));
+        }
+        // Periodically pass telemetry to hooks that want it
+        if let Some(interval) = hooks.telemetry_interval() {
+            halt_receiver
+                .register_client("telemetry".into())
+                .await
+                .spawn(Self::telemetry_task(
+                    hooks.clone(),
+                    managers.clone(),
+                    monitor.clone(),
+                    interval,
+                ));
         }
         hooks.miner_started().await;
         (managers, monitor)
@@ -1692,28 +3114,106 @@ impl hal::Backend for Backend {
             .take()
             .expect("BUG: missing client manager");
         let group_configs = backend_config.groups.take();
+        let config_path = backend_config.config_path.clone();
         let backend_info = backend_config.info();
 
         let backend = work_hub.to_node().clone();
         let gpio_mgr = gpio::ControlPinManager::new();
+        let identify_led = match led::IdentifyLed::open(&gpio_mgr) {
+            Ok(identify_led) => Some(Arc::new(identify_led)),
+            Err(e) => {
+                warn!("Identification LED is not available: {}", e);
+                None
+            }
+        };
+        let self_check = Arc::new(selfcheck::Tracker::new(Some(
+            config::DEFAULT_SELFCHECK_PATH.to_string(),
+        )));
+        // Remember why the miner terminated last time before the record is replaced
+        let last_shutdown = shutdown::start(config::DEFAULT_SHUTDOWN_STATE_PATH);
+        if let Some(last_shutdown) = last_shutdown.as_ref() {
+            info!(
+                "Last shutdown: {} ({})",
+                last_shutdown.reason, last_shutdown.message
+            );
+        }
         let (app_halt_sender, app_halt_receiver) = halt::make_pair(HALT_TIMEOUT);
+        let hashboards = Self::detect_hashboards(&gpio_mgr).expect("failed detecting hashboards");
+
+        // Front panel has to be started before hash chains so that it sees all chain events
+        let front_panel = Arc::new(front_panel::FrontPanel::new(
+            backend_config.resolve_front_panel_config(),
+            backend_config
+                .event_bus
+                .clone()
+                .expect("BUG: missing event bus"),
+            identify_led.clone(),
+            client_manager.clone(),
+            app_halt_sender.clone(),
+        ));
+        // only enabled hash chains are expected to be mining
+        let enabled_chains = hashboards
+            .iter()
+            .filter(|hashboard_idx| backend_config.resolve_chain_config(**hashboard_idx).enabled)
+            .count();
+        front_panel.start(
+            &gpio_mgr,
+            enabled_chains,
+            backend_config
+                .scheduler
+                .as_ref()
+                .expect("BUG: missing scheduler")
+                .subscribe(),
+        );
+
         let (managers, monitor) = Self::start_miner(
             &gpio_mgr,
-            Self::detect_hashboards(&gpio_mgr).expect("failed detecting hashboards"),
+            hashboards,
             work_hub,
             backend_config,
             app_halt_receiver,
             app_halt_sender.clone(),
+            self_check.clone(),
         )
         .await;
 
-        // On miner exit, halt the whole program
+        // On miner exit, halt the whole program. Exit hooks are run after all hashchains have been
+        // halted so no new work is generated and the solutions found so far can be delivered
+        // to pools before their connections are closed.
+        let exit_client_manager = client_manager.clone();
+        let exit_halt_sender = app_halt_sender.clone();
         app_halt_sender
-            .add_exit_hook(async {
-                println!("Exiting.");
-                std::process::exit(0);
+            .add_exit_hook(async move {
+                let pending_solutions = exit_client_manager
+                    .flush_solutions(SHUTDOWN_FLUSH_TIMEOUT)
+                    .await;
+                if pending_solutions > 0 {
+                    warn!(
+                        "{} solutions have not been acknowledged by pools before exit",
+                        pending_solutions
+                    );
+                }
+                exit_client_manager.stop_clients().await;
+
+                // halt without structured reason is not expected
+                let shutdown_reason = exit_halt_sender
+                    .shutdown_reason()
+                    .unwrap_or(shutdown::Reason::Crash);
+                let reason = exit_halt_sender.status().reason.unwrap_or_default();
+                shutdown::Record::new(shutdown_reason, reason)
+                    .store_or_warn(config::DEFAULT_SHUTDOWN_STATE_PATH);
+                println!("Exiting ({}).", shutdown_reason);
+                std::process::exit(shutdown_reason.exit_code());
             })
             .await;
+        // Restart requested by the frontend (e.g. by share watchdog)
+        let restart_halt_sender = app_halt_sender.clone();
+        let restart_handler: hal::RestartHandler = Box::new(move |reason| {
+            restart_halt_sender
+                .clone()
+                .send_shutdown(shutdown::Reason::Watchdog, reason)
+                .boxed()
+        });
         // Hook `Ctrl-C`, `SIGTERM` and other termination methods
         app_halt_sender.hook_termination_signals();
 
@@ -1727,11 +3227,24 @@ impl hal::Backend for Backend {
             .await?;
         if let Some(hooks) = hooks {
             // Pass the client manager to hook for further processing
-            hooks.clients_loaded(client_manager).await;
+            hooks.clients_loaded(client_manager.clone()).await;
         }
 
         Ok(hal::FrontendConfig {
-            cgminer_custom_commands: cgminer::create_custom_commands(backend, managers, monitor),
+            cgminer_custom_commands: cgminer::create_custom_commands(
+                backend,
+                cgminer::Context {
+                    serial: backend_info.map(|info| info.dev_id).unwrap_or_default(),
+                    managers,
+                    monitor,
+                    identify_led,
+                    last_shutdown,
+                    self_check,
+                    client_manager,
+                    config_path,
+                },
+            ),
+            restart_handler: Some(restart_handler),
         })
     }
 
@@ -23,12 +23,161 @@
 use ii_logging::macros::*;
 
 use bosminer_am1_s9::config;
+use bosminer_am1_s9::diag;
+use bosminer_am1_s9::shutdown;
 
 use bosminer_config::clap;
-use bosminer_config::{ClientDescriptor, ClientUserInfo, GroupConfig, PoolConfig};
+use bosminer_config::{ClientDescriptor, ClientUserInfo, GroupConfig, Layers, PoolConfig};
 
 use ii_async_compat::tokio;
 
+/// Merge configuration file with environment variables and values set from command line
+fn config_layers(
+    matches: &clap::ArgMatches,
+    migrated: config::migration::Migrated,
+) -> Result<Layers, String> {
+    let mut layers = migrated
+        .layers()?
+        .with_env::<config::FormatWrapper<config::Backend>>()?;
+
+    // Set just 1 midstate if user requested disabling asicboost
+    if matches.is_present("disable-asic-boost") {
+        layers.set_override("hash_chain_global.asic_boost", false)?;
+    }
+    for name in &["frequency", "voltage"] {
+        if let Some(value) = matches.value_of(name) {
+            let value = value.parse::<f64>().map_err(|e| {
+                format!(
+                    "cannot use {} '{}' from command line: {}",
+                    name,
+                    value,
+                    e.to_string()
+                )
+            })?;
+            layers.set_override(&format!("hash_chain_global.{}", name), value)?;
+        }
+    }
+    Ok(layers)
+}
+
+/// Validate effective configuration and print all problems found. Returns `true` when there is
+/// no problem.
+fn check_config(matches: &clap::ArgMatches, config_path: &str) -> bool {
+    let config_wrapper = config::FormatWrapper::<config::Backend>::load(config_path)
+        .and_then(|migrated| {
+            config_layers(matches, migrated)
+                .map_err(|msg| config::FormatWrapperError::ParsingError(msg))
+        })
+        .and_then(|layers| config::FormatWrapper::deserialize(layers));
+
+    let mut diagnostics = match config_wrapper {
+        Ok(config_wrapper) => {
+            let mut diagnostics = config_wrapper.check();
+            if !config_wrapper.body.has_pools() && !matches.is_present("pool") {
+                diagnostics.error("group", "no pools specified".to_string());
+            }
+            diagnostics
+        }
+        Err(e) => {
+            let mut diagnostics = config::check::Diagnostics::new();
+            diagnostics.general(e.to_string());
+            diagnostics
+        }
+    };
+
+    if let Some(url) = matches.value_of("pool") {
+        let user_info = matches
+            .value_of("user")
+            .expect("BUG: missing 'user' argument");
+        if let Err(e) = ClientDescriptor::create(url, &ClientUserInfo::parse(user_info), true) {
+            diagnostics.general(format!("cannot set pool from command line: {}", e));
+        }
+    }
+
+    if let Ok(content) = std::fs::read_to_string(config_path) {
+        diagnostics.locate(&content);
+    }
+    if diagnostics.is_empty() {
+        println!("{}: configuration is valid", config_path);
+    }
+    for diagnostic in diagnostics.iter() {
+        println!("{}: {}", config_path, diagnostic);
+    }
+    diagnostics.is_empty()
+}
+
+/// Parse optional numeric argument of sub-command
+fn parse_arg(matches: &clap::ArgMatches, name: &str, default: f64) -> Result<f64, String> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| *value > 0.0)
+            .ok_or_else(|| format!("invalid {} '{}'", name, value)),
+        None => Ok(default),
+    }
+}
+
+/// Handle 'diag' and 'tune' sub-commands which operate hashboards directly without mining.
+/// Reports are printed to stdout and an error is returned when any hashboard fails.
+async fn run_bench(
+    matches: &clap::ArgMatches<'_>,
+    backend_config: config::Backend,
+) -> Result<(), String> {
+    let bench = diag::Bench::new(backend_config).map_err(|e| e.to_string())?;
+    let hashboards = bench.hashboards().map_err(|e| e.to_string())?;
+    if hashboards.is_empty() {
+        return Err("no hashboards found".to_string());
+    }
+
+    if matches.subcommand_matches("diag").is_some() {
+        let mut failed = 0;
+        for hashboard_idx in hashboards {
+            let report = bench.diagnose(hashboard_idx).await;
+            print!("{}", report);
+            if !report.is_ok() {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} hashboard(s) failed diagnostics", failed));
+        }
+    } else if let Some(matches) = matches.subcommand_matches("tune") {
+        let sweep = diag::Sweep {
+            start: parse_arg(matches, "min-frequency", diag::DEFAULT_MIN_FREQUENCY_MHZ)?,
+            end: parse_arg(matches, "max-frequency", diag::DEFAULT_MAX_FREQUENCY_MHZ)?,
+            step: parse_arg(matches, "step", diag::DEFAULT_STEP_MHZ)?,
+            sample_time: std::time::Duration::from_secs_f64(parse_arg(
+                matches,
+                "sample-time",
+                diag::DEFAULT_SAMPLE_TIME.as_secs_f64(),
+            )?),
+        };
+        let mut results = vec![];
+        for hashboard_idx in hashboards {
+            let result = bench.tune(hashboard_idx, &sweep).await;
+            print!("{}", result);
+            results.push(result);
+        }
+        if let Some(path) = matches.value_of("output") {
+            diag::write_profiles(path, &results)?;
+            println!("Profiles written to '{}'", path);
+        }
+        if results.iter().any(|result| result.frequency.is_none()) {
+            return Err("no usable frequency found for some hashboards".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Record the reason of shutdown and exit with corresponding code. The logger is dropped first so
+/// that messages explaining the problem are not lost.
+fn exit<T>(log_guard: T, reason: shutdown::Reason, message: String) -> ! {
+    shutdown::Record::new(reason, message).store_or_warn(config::DEFAULT_SHUTDOWN_STATE_PATH);
+    drop(log_guard);
+    std::process::exit(reason.exit_code());
+}
+
 #[tokio::main]
 async fn main() {
     let app = clap::App::new(bosminer::SIGNATURE)
@@ -80,6 +229,12 @@ async fn main() {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("check-config")
+                .long("check-config")
+                .help("Validate configuration, print all problems found and exit")
+                .required(false),
+        )
         .subcommand(
             clap::SubCommand::with_name("config")
                 .about("Configuration backend API")
@@ -91,6 +246,13 @@ async fn main() {
                         .required(false)
                         .takes_value(false),
                 )
+                .arg(
+                    clap::Arg::with_name("schema")
+                        .long("schema")
+                        .help("Write JSON schema of the configuration to stdout")
+                        .required(false)
+                        .takes_value(false),
+                )
                 .arg(
                     clap::Arg::with_name("data")
                         .long("data")
@@ -105,15 +267,77 @@ async fn main() {
                         .required(false)
                         .takes_value(false),
                 )
+                .arg(
+                    clap::Arg::with_name("migrate")
+                        .long("migrate")
+                        .help("Upgrade configuration file format and write result to stdout")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    clap::Arg::with_name("in-place")
+                        .long("in-place")
+                        .help("Write upgraded configuration back to the configuration file")
+                        .requires("migrate")
+                        .required(false)
+                        .takes_value(false),
+                )
                 .group(
                     clap::ArgGroup::with_name("command")
-                        .args(&["metadata", "data", "save"])
+                        .args(&["metadata", "schema", "data", "save", "migrate"])
                         .required(true),
                 ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("dump-effective-config")
+                .about("Print effective configuration and exit"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("diag")
+                .about("Enumerate chips and probe sensors of all hashboards without mining (bosminer must not be running)"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("tune")
+                .about("Find the highest stable frequency of all hashboards without mining (bosminer must not be running)")
+                .arg(
+                    clap::Arg::with_name("min-frequency")
+                        .long("min-frequency")
+                        .help("Start the sweep at this frequency (in MHz)")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("max-frequency")
+                        .long("max-frequency")
+                        .help("End the sweep at this frequency (in MHz)")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("step")
+                        .long("step")
+                        .help("Frequency step of the sweep (in MHz)")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("sample-time")
+                        .long("sample-time")
+                        .help("Time of hashing at each frequency (in seconds)")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("output")
+                        .long("output")
+                        .help("Write found frequencies as [hash_chain.N] sections to this file")
+                        .required(false)
+                        .takes_value(true),
+                ),
         );
 
     let matches = app.get_matches();
-    let _log_guard =
+    let log_guard =
         ii_logging::setup_for_app(bosminer_am1_s9::config::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE);
 
     let config_path = matches
@@ -125,29 +349,66 @@ async fn main() {
         let config_handler = config::api::Handler::new(config_path);
         if matches.is_present("metadata") {
             config_handler.handle_metadata::<config::Backend>();
+        } else if matches.is_present("schema") {
+            config_handler.handle_schema::<config::Backend>();
         } else if matches.is_present("data") {
             config_handler.handle_data::<config::Backend>();
         } else if matches.is_present("save") {
             config_handler.handle_save::<config::Backend>();
+        } else if matches.is_present("migrate") {
+            config_handler.handle_migrate::<config::Backend>(matches.is_present("in-place"));
+        }
+        return;
+    }
+
+    if matches.is_present("check-config") {
+        if !check_config(&matches, config_path) {
+            std::process::exit(shutdown::Reason::ConfigError.exit_code());
         }
         return;
     }
 
-    let mut backend_config: config::Backend = match config::FormatWrapper::parse(config_path) {
+    let migrated = match config::FormatWrapper::<config::Backend>::load(config_path) {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            error!("Cannot load configuration file \"{}\"", config_path);
+            error!("Reason: {}", e);
+            exit(log_guard, shutdown::Reason::ConfigError, e.to_string());
+        }
+    };
+    if let Some(version) = migrated.original_version.as_ref() {
+        warn!(
+            "Configuration format '{}' has been upgraded, use 'config --migrate --in-place' to save it",
+            version
+        );
+    }
+
+    // Merge configuration layers in order of precedence: file < environment < command line
+    let layers = match config_layers(&matches, migrated) {
+        Ok(layers) => layers,
+        Err(e) => {
+            error!("Cannot load configuration file \"{}\"", config_path);
+            error!("Reason: {}", e);
+            exit(log_guard, shutdown::Reason::ConfigError, e);
+        }
+    };
+
+    let mut config_wrapper = match config::FormatWrapper::from_layers(layers) {
         Err(config::FormatWrapperError::IncompatibleVersion(version, Some(v))) => {
             warn!(
                 "Incompatible format version '{}', but continuing anyway",
                 version
             );
-            v.body
+            v
         }
         Err(e) => {
             error!("Cannot load configuration file \"{}\"", config_path);
             error!("Reason: {}", e);
-            return;
+            exit(log_guard, shutdown::Reason::ConfigError, e.to_string());
         }
-        Ok(v) => v.body,
+        Ok(v) => v,
     };
+    let backend_config = &mut config_wrapper.body;
 
     // Add pools from command line
     if let Some(url) = matches.value_of("pool") {
@@ -159,7 +420,7 @@ async fn main() {
         match ClientDescriptor::create(url, &user_info, true) {
             Err(e) => {
                 error!("Cannot set pool from command line: {}", e.to_string());
-                return;
+                exit(log_guard, shutdown::Reason::ConfigError, e.to_string());
             }
             Ok(_) => {}
         };
@@ -170,6 +431,12 @@ async fn main() {
                 url: url.to_string(),
                 user: user_info.user.to_string(),
                 password: user_info.password.map(|v| v.to_string()),
+                retry_delay: None,
+                retry_delay_max: None,
+                weight: None,
+                extranonce_partition: None,
+                channels: None,
+                endpoints: None,
             }]),
         };
 
@@ -180,6 +447,32 @@ async fn main() {
         backend_config.groups = Some(vec![group_config]);
     }
 
+    // Handle 'dump-effective-config' sub-command which prints configuration with all overrides
+    if matches
+        .subcommand_matches("dump-effective-config")
+        .is_some()
+    {
+        match toml::to_string_pretty(&config_wrapper) {
+            Ok(config) => print!("{}", config),
+            Err(e) => error!("Cannot serialize effective configuration: {}", e),
+        }
+        return;
+    }
+    let mut backend_config = config_wrapper.body;
+    backend_config.config_path = Some(config_path.to_string());
+
+    // Handle 'diag' and 'tune' sub-commands which don't need any pool
+    if matches.subcommand_matches("diag").is_some() || matches.subcommand_matches("tune").is_some()
+    {
+        if let Err(e) = run_bench(&matches, backend_config).await {
+            error!("Hashboard validation failed: {}", e);
+            // shutdown record is not stored because the miner itself hasn't been running
+            drop(log_guard);
+            std::process::exit(shutdown::Reason::HardwareFailure.exit_code());
+        }
+        return;
+    }
+
     // Check if there's enough pools
     if !backend_config.has_pools() {
         error!("No pools specified!");
@@ -190,61 +483,16 @@ async fn main() {
             config_path
         );
         info!("    in [[group.pool]] section");
-        return;
-    }
-
-    // Set just 1 midstate if user requested disabling asicboost
-    if matches.is_present("disable-asic-boost") {
-        backend_config
-            .hash_chain_global
-            .get_or_insert_with(|| Default::default())
-            .asic_boost
-            .replace(false);
-    }
-    if let Some(value) = matches.value_of("frequency") {
-        let frequency = match value.parse::<f64>() {
-            Ok(value) => value,
-            Err(e) => {
-                error!(
-                    "Cannot use frequency '{}' from command line: {}",
-                    value,
-                    e.to_string()
-                );
-                return;
-            }
-        };
-        backend_config
-            .hash_chain_global
-            .get_or_insert_with(|| Default::default())
-            .overridable
-            .get_or_insert_with(|| Default::default())
-            .frequency
-            .replace(frequency);
-    }
-    if let Some(value) = matches.value_of("voltage") {
-        let voltage = match value.parse::<f64>() {
-            Ok(value) => value,
-            Err(e) => {
-                error!(
-                    "Cannot use voltage '{}' from command line: {}",
-                    value,
-                    e.to_string()
-                );
-                return;
-            }
-        };
-        backend_config
-            .hash_chain_global
-            .get_or_insert_with(|| Default::default())
-            .overridable
-            .get_or_insert_with(|| Default::default())
-            .voltage
-            .replace(voltage);
+        exit(
+            log_guard,
+            shutdown::Reason::ConfigError,
+            "no pools specified".to_string(),
+        );
     }
 
     if let Err(e) = backend_config.fill_info::<config::Backend>() {
         error!("Cannot get backend information: {}", e.to_string());
-        return;
+        exit(log_guard, shutdown::Reason::HardwareFailure, e.to_string());
     }
 
     ii_async_compat::setup_panic_handling();
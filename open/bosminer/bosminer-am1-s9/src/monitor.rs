@@ -23,12 +23,21 @@
 //! This module is responsible for collecting temperatures from hashchains and driving
 //! the fans.
 
+pub mod history;
+#[cfg(test)]
+pub mod simulation;
+
 use ii_logging::macros::*;
 
 use crate::fan;
 use crate::halt;
 use crate::sensor::{self, Measurement};
+use crate::shutdown;
+use crate::supervisor;
 
+use bosminer::events;
+
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -38,7 +47,6 @@ use futures::stream::StreamExt;
 use ii_async_compat::futures;
 use ii_async_compat::tokio;
 use tokio::sync::watch;
-use tokio::time::delay_for;
 
 /// If miner start takes longer than this, mark it as `Broken`
 const START_TIMEOUT: Duration = Duration::from_secs(180);
@@ -48,21 +56,45 @@ const START_TIMEOUT: Duration = Duration::from_secs(180);
 const RUN_UPDATE_TIMEOUT: Duration = Duration::from_secs(10);
 /// How often check timeouts and adjust PID
 const TICK_LENGTH: Duration = Duration::from_secs(5);
+/// How many times the failed tick task is spawned again before the miner is shut down
+const MAX_TICK_RESTARTS: usize = 3;
 /// How long does it take until miner warm up? We won't let it tu turn fans off until then...
 const WARM_UP_PERIOD: Duration = Duration::from_secs(90);
+/// How long does it take fans to spin up after they have been started? Missing fans
+/// are tolerated during this period.
+const FAN_SPIN_UP_GRACE_PERIOD: Duration = Duration::from_secs(20);
+/// Fans driven with lower PWM duty cycle (in percent) may be standing still
+const FAN_LOW_SPEED_PWM: usize = 10;
+/// How many consecutive ticks (outside of grace period) must fans be missing before we
+/// consider it a failure
+const FAN_FAILURE_TICKS: usize = 3;
+/// Chip temperatures of a hashboard that differ from median of all its sensors by more than
+/// this are considered outliers (and sensors are flagged as disagreeing)
+const SENSOR_OUTLIER_THRESHOLD: f32 = 10.0;
+/// Hashchain running at least this long after a restart of broken hashchain is considered
+/// recovered (and the number of consecutive restarts is reset)
+const BROKEN_CHAIN_RECOVERY_PERIOD: Duration = Duration::from_secs(30 * 60);
+/// Rate of change of hashchain temperature is computed from measurements in this window
+const TEMP_SLOPE_WINDOW: Duration = Duration::from_secs(60);
+/// Measurements have to span at least this long to compute the rate of change
+const TEMP_SLOPE_MIN_SPAN: Duration = Duration::from_secs(30);
 
 /// A message from hashchain
 ///
 /// Here are some rules that HashChains registered with monitors have to obey:
 ///
-/// - state change must be strictly `[Off -> On -> Running*]*`
+/// - state change must be strictly `[Off -> Pending? -> On -> Running*]*` (`Pending` may be
+///   followed by `Off` when the start is cancelled)
 /// - duration between `On` and first `Running` must be less than START_TIMEOUT
 /// - duration between `Running` measurement and the next one must be less than
 ///   RUN_UPDATE_INTERVAL (ideally set periodic update to half of this interval)
 #[derive(Debug, Clone)]
 pub enum Message {
+    /// Hashchain is waiting for its turn to start
+    Pending,
     On,
-    Running(sensor::Temperature),
+    /// Readings of all hashboard sensors
+    Running(Vec<sensor::Temperature>),
     Off,
 }
 
@@ -98,6 +130,70 @@ impl ChainTemperature {
             }
         }
     }
+
+    /// Aggregate readings of multiple S9 sensors into one chip temperature.
+    /// Readings too far from median are rejected as outliers, the rest is averaged. The
+    /// temperature is unknown when all readings are rejected (e.g. two sensors that disagree).
+    fn from_s9_sensors(temps: &[sensor::Temperature]) -> Self {
+        let chip_temps = s9_chip_temperatures(temps);
+        let median = match median(&chip_temps) {
+            Some(median) => median,
+            None => return Self::Unknown,
+        };
+        let inliers: Vec<f32> = chip_temps
+            .into_iter()
+            .filter(|t| (t - median).abs() <= SENSOR_OUTLIER_THRESHOLD)
+            .collect();
+        if inliers.is_empty() {
+            return Self::Unknown;
+        }
+        Self::Ok(inliers.iter().sum::<f32>() / inliers.len() as f32)
+    }
+}
+
+/// Chip temperatures of all S9 sensors that provided valid (finite) reading
+fn s9_chip_temperatures(temps: &[sensor::Temperature]) -> Vec<f32> {
+    temps
+        .iter()
+        .filter_map(
+            |temp| match ChainTemperature::from_s9_sensor(temp.clone()) {
+                ChainTemperature::Ok(t) if t.is_finite() => Some(t),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Median of `values` or `None` if there are none. Values that cannot be compared (NaN) are
+/// left out.
+fn median(values: &[f32]) -> Option<f32> {
+    let mut values: Vec<f32> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).expect("BUG: NaN value"));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// Return the largest deviation from median of chip temperatures if sensors disagree
+/// by more than `SENSOR_OUTLIER_THRESHOLD`
+fn s9_sensor_disagreement(temps: &[sensor::Temperature]) -> Option<f32> {
+    let chip_temps = s9_chip_temperatures(temps);
+    let median = median(&chip_temps)?;
+    let deviation = chip_temps
+        .iter()
+        .map(|t| (t - median).abs())
+        .fold(0.0, f32::max);
+    if deviation > SENSOR_OUTLIER_THRESHOLD {
+        Some(deviation)
+    } else {
+        None
+    }
 }
 
 /// State of hashchain as seen from Monitor point of view
@@ -105,14 +201,17 @@ impl ChainTemperature {
 /// timeouts use it).
 #[derive(Debug, Clone, PartialEq)]
 enum ChainState {
+    Pending,
     On(Instant),
     Running {
         started: Instant,
         last_heartbeat: Instant,
-        temperature: sensor::Temperature,
+        temperatures: Vec<sensor::Temperature>,
     },
     Off,
     Broken(&'static str),
+    /// Broken hashchain is being restarted (monitor waits for it to start again)
+    Restarting(Instant),
 }
 
 impl ChainState {
@@ -122,28 +221,38 @@ impl ChainState {
     }
 
     /// React on an incoming message by changing modifying state. All messages
-    /// have follow pattern `[Off -> On -> Running*]*`
+    /// have follow pattern `[Off -> Pending? -> On -> Running*]*`
     ///
     /// `now` is timestamp of `message` reception (passed explicitly as argument
     /// to facilitate testing).
     fn transition(&mut self, now: Instant, message: Message) {
         match message {
+            Message::Pending => match *self {
+                ChainState::Off | ChainState::Restarting(_) => *self = ChainState::Pending,
+                _ => self.bad_transition(),
+            },
             Message::On => match *self {
-                ChainState::Off => *self = ChainState::On(now),
+                ChainState::Off | ChainState::Pending | ChainState::Restarting(_) => {
+                    *self = ChainState::On(now)
+                }
                 _ => self.bad_transition(),
             },
-            Message::Running(temperature) => match *self {
+            Message::Running(temperatures) => match *self {
                 ChainState::Running { started, .. } | ChainState::On(started) => {
                     *self = ChainState::Running {
                         started,
                         last_heartbeat: now,
-                        temperature,
+                        temperatures,
                     }
                 }
                 _ => self.bad_transition(),
             },
             Message::Off => match *self {
-                ChainState::On(_) | ChainState::Running { .. } => *self = ChainState::Off,
+                ChainState::Pending | ChainState::On(_) | ChainState::Running { .. } => {
+                    *self = ChainState::Off
+                }
+                // broken hashchain is stopped before it is started again
+                ChainState::Restarting(_) => {}
                 _ => self.bad_transition(),
             },
         }
@@ -164,6 +273,11 @@ impl ChainState {
                     *self = ChainState::Broken("failed to set update in time");
                 }
             }
+            ChainState::Restarting(restarted) => {
+                if now.duration_since(restarted) >= START_TIMEOUT {
+                    *self = ChainState::Broken("took too long to restart");
+                }
+            }
             _ => {}
         }
     }
@@ -173,30 +287,140 @@ impl ChainState {
     /// some numbers a while ago.
     fn get_temperature(&self) -> ChainTemperature {
         match self {
+            ChainState::Pending => ChainTemperature::Unknown,
             ChainState::On(_) => ChainTemperature::Unknown,
             ChainState::Off => ChainTemperature::Unknown,
+            ChainState::Restarting(_) => ChainTemperature::Unknown,
             ChainState::Broken(_) => ChainTemperature::Failed,
-            ChainState::Running { temperature, .. } => {
-                ChainTemperature::from_s9_sensor(temperature.clone())
+            ChainState::Running { temperatures, .. } => {
+                ChainTemperature::from_s9_sensors(temperatures)
             }
         }
     }
 
+    /// Return readings of all hashchain sensors (if there are any)
+    fn get_sensors(&self) -> Vec<sensor::Temperature> {
+        match self {
+            ChainState::Running { temperatures, .. } => temperatures.clone(),
+            _ => vec![],
+        }
+    }
+
+    /// Return how much hashchain sensors disagree (if they disagree over threshold)
+    fn get_sensor_disagreement(&self) -> Option<f32> {
+        match self {
+            ChainState::Running { temperatures, .. } => s9_sensor_disagreement(temperatures),
+            _ => None,
+        }
+    }
+
     /// Is hashchain warming up?
     fn is_warming_up(&self, now: Instant) -> bool {
         match self {
             // chain state stays in "warming up" state until it sends heartbeat
-            ChainState::On(_) => true,
+            ChainState::On(_) | ChainState::Restarting(_) => true,
             ChainState::Running { started, .. } => now.duration_since(*started) <= WARM_UP_PERIOD,
             _ => false,
         }
     }
 }
 
+/// What to do with `Broken` hashchain
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrokenChainPolicy {
+    /// Shutdown the whole miner
+    Shutdown,
+    /// Restart the hashchain at most `max_restarts` times in a row with at least `cooldown`
+    /// between restarts. The miner is shut down when the hashchain is still broken.
+    Restart {
+        max_restarts: usize,
+        cooldown: Duration,
+    },
+}
+
+impl Default for BrokenChainPolicy {
+    fn default() -> Self {
+        Self::Shutdown
+    }
+}
+
+/// Reaction on `Broken` hashchain decided according to `BrokenChainPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BrokenChainAction {
+    Shutdown,
+    Restart,
+    /// Wait for cooldown of the previous restart
+    Wait,
+}
+
+/// Tracks rate of change of hashchain temperature over `TEMP_SLOPE_WINDOW`
+#[derive(Debug, Clone, Default)]
+struct TemperatureSlope {
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl TemperatureSlope {
+    /// Record temperature measured at `now`. Missing measurement interrupts the history.
+    fn add(&mut self, now: Instant, temperature: ChainTemperature) {
+        match temperature {
+            ChainTemperature::Ok(temperature) => {
+                self.samples.push_back((now, temperature));
+                while let Some(&(measured, _)) = self.samples.front() {
+                    if now.duration_since(measured) <= TEMP_SLOPE_WINDOW {
+                        break;
+                    }
+                    self.samples.pop_front();
+                }
+            }
+            _ => self.samples.clear(),
+        }
+    }
+
+    /// Rate of change in degrees Celsius per minute or `None` when the history is too short.
+    /// The rate is the slope of least-squares line fitted to all samples in the window so that
+    /// a single noisy reading does not trigger thermal runaway.
+    fn get(&self) -> Option<f32> {
+        let (first_time, _) = *self.samples.front()?;
+        let (last_time, _) = *self.samples.back()?;
+        if last_time.duration_since(first_time) < TEMP_SLOPE_MIN_SPAN {
+            return None;
+        }
+        let points = || {
+            self.samples.iter().map(move |(time, temperature)| {
+                (
+                    time.duration_since(first_time).as_secs_f64(),
+                    *temperature as f64,
+                )
+            })
+        };
+        let count = self.samples.len() as f64;
+        let (sum_time, sum_temp) = points().fold((0.0, 0.0), |(sum_time, sum_temp), (t, y)| {
+            (sum_time + t, sum_temp + y)
+        });
+        let (mean_time, mean_temp) = (sum_time / count, sum_temp / count);
+        let (covariance, variance) = points().fold((0.0, 0.0), |(covariance, variance), (t, y)| {
+            (
+                covariance + (t - mean_time) * (y - mean_temp),
+                variance + (t - mean_time) * (t - mean_time),
+            )
+        });
+        // variance is not zero because the samples span at least `TEMP_SLOPE_MIN_SPAN`
+        Some((covariance / variance * 60.0) as f32)
+    }
+}
+
 /// Represent hashchains as registered within Monitor
 struct Chain {
     state: ChainState,
     hashboard_idx: usize,
+    /// Sensor failure has already been reported
+    sensor_failed: bool,
+    /// Number of restarts of broken hashchain in a row
+    consecutive_restarts: usize,
+    /// Total number of restarts of broken hashchain
+    restart_count: usize,
+    last_restart: Option<Instant>,
+    temperature_slope: TemperatureSlope,
 }
 
 impl Chain {
@@ -204,8 +428,114 @@ impl Chain {
         Self {
             state: ChainState::Off,
             hashboard_idx,
+            sensor_failed: false,
+            consecutive_restarts: 0,
+            restart_count: 0,
+            last_restart: None,
+            temperature_slope: Default::default(),
+        }
+    }
+
+    /// Hashchain that has been running long enough since its last restart has recovered
+    fn check_recovery(&mut self, now: Instant) {
+        if let ChainState::Running { started, .. } = self.state {
+            if now.duration_since(started) >= BROKEN_CHAIN_RECOVERY_PERIOD {
+                self.consecutive_restarts = 0;
+            }
+        }
+    }
+
+    /// Decide what to do with broken hashchain. When the decision is to restart it, the
+    /// hashchain is switched to `Restarting` state.
+    fn broken_action(&mut self, policy: &BrokenChainPolicy, now: Instant) -> BrokenChainAction {
+        match *policy {
+            BrokenChainPolicy::Shutdown => BrokenChainAction::Shutdown,
+            BrokenChainPolicy::Restart {
+                max_restarts,
+                cooldown,
+            } => {
+                if self.consecutive_restarts >= max_restarts {
+                    return BrokenChainAction::Shutdown;
+                }
+                if let Some(last_restart) = self.last_restart {
+                    if now.duration_since(last_restart) < cooldown {
+                        return BrokenChainAction::Wait;
+                    }
+                }
+                self.consecutive_restarts += 1;
+                self.restart_count += 1;
+                self.last_restart = Some(now);
+                self.state = ChainState::Restarting(now);
+                BrokenChainAction::Restart
+            }
+        }
+    }
+}
+
+/// Hysteresis of the `FAN_DANGER` check
+///
+/// Fans take some time to spin up after they have been started from standstill (or low speed)
+/// and their feedback is unreliable meanwhile. A single reading of "not enough fans" is therefore
+/// no reason to shut down the miner: fans are declared failed only if they are missing for
+/// `failure_ticks` consecutive ticks and `grace_period` has passed since they have been started.
+/// Changes of speed of already spinning fans (e.g. by temperature controller) don't start the
+/// grace period so that they cannot postpone detection of failed fans indefinitely.
+#[derive(Debug, Clone)]
+struct FanGuard {
+    grace_period: Duration,
+    failure_ticks: usize,
+    /// When were the fans last started
+    spin_up_start: Option<Instant>,
+    /// Number of consecutive ticks with fans missing
+    missing_ticks: usize,
+}
+
+impl FanGuard {
+    fn new(grace_period: Duration, failure_ticks: usize) -> Self {
+        Self {
+            grace_period,
+            failure_ticks,
+            spin_up_start: None,
+            missing_ticks: 0,
+        }
+    }
+
+    fn is_spinning(speed: fan::Speed) -> bool {
+        speed.to_pwm() >= FAN_LOW_SPEED_PWM
+    }
+
+    /// Record that fan speed has been changed from `previous` speed to `speed` at `now`
+    fn speed_changed(&mut self, now: Instant, previous: Option<fan::Speed>, speed: fan::Speed) {
+        let was_spinning = previous.map_or(false, Self::is_spinning);
+        if !was_spinning && Self::is_spinning(speed) {
+            self.spin_up_start = Some(now);
         }
     }
+
+    /// Are fans still spinning up after they have been started?
+    fn in_grace_period(&self, now: Instant) -> bool {
+        match self.spin_up_start {
+            Some(changed) => now.duration_since(changed) < self.grace_period,
+            None => false,
+        }
+    }
+
+    /// Account one tick of fan feedback. Returns `true` if fans have been missing long enough
+    /// to be considered failed.
+    fn tick(&mut self, now: Instant, fans_missing: bool) -> bool {
+        if !fans_missing || self.in_grace_period(now) {
+            self.missing_ticks = 0;
+            return false;
+        }
+        self.missing_ticks += 1;
+        self.missing_ticks >= self.failure_ticks
+    }
+}
+
+impl Default for FanGuard {
+    fn default() -> Self {
+        Self::new(FAN_SPIN_UP_GRACE_PERIOD, FAN_FAILURE_TICKS)
+    }
 }
 
 /// What method of controlling fans is configured
@@ -213,6 +543,8 @@ impl Chain {
 pub enum FanControlMode {
     FixedSpeed(fan::Speed),
     TargetTemperature(f32),
+    /// Closed-loop control of fan speed to reach target RPM of each fan
+    TargetRpm(fan::rpm::Targets),
 }
 
 /// Fan configuration
@@ -231,6 +563,33 @@ pub struct TempControlConfig {
     pub hot_temp: f32,
 }
 
+/// What to do when temperature rises too fast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThermalRunawayAction {
+    /// Override fan controller and run fans at full speed. Frequency of hashchains is left
+    /// untouched.
+    FullSpeedFans,
+    /// Shutdown the whole miner
+    Shutdown,
+}
+
+/// Detection of temperature rising too fast (failed fans or cooling) before it reaches
+/// the hot limit
+#[derive(Debug, Clone)]
+pub struct ThermalRunawayConfig {
+    /// Maximal rate of change of hashchain temperature in degrees Celsius per minute
+    pub max_slope: f32,
+    pub action: ThermalRunawayAction,
+}
+
+impl ThermalRunawayConfig {
+    /// Check whether temperature rising by `slope` indicates thermal runaway
+    fn is_exceeded(&self, slope: f32, warming_up: bool) -> bool {
+        // temperature rises quickly while warming up
+        slope > self.max_slope && !warming_up
+    }
+}
+
 /// Overall configuration
 /// "Disabled" is represented as `None`
 #[derive(Debug, Clone)]
@@ -240,6 +599,8 @@ pub struct Config {
     /// If true, then do not let fans bellow predefined limit while miner is warming up.
     /// TODO: this is not particularly nice, it should be done per-chain and run-time.
     pub fans_on_while_warming_up: bool,
+    pub broken_chain_policy: BrokenChainPolicy,
+    pub thermal_runaway: Option<ThermalRunawayConfig>,
 }
 
 #[derive(Debug, Clone)]
@@ -257,6 +618,8 @@ pub enum ControlDecision {
     UsePid { target_temp: f32, input_temp: f32 },
     /// Use fixed speed
     UseFixedSpeed(fan::Speed),
+    /// Pass fan feedback to RPM controller and let it calculate fan speed
+    UseTargetRpm,
     /// Do nothing (only valid when fan control is disabled)
     Nothing,
 }
@@ -281,6 +644,20 @@ impl ControlDecision {
                     reason: "user defined fan speed",
                 };
             }
+            FanControlMode::TargetRpm(_) => {
+                if let ChainTemperature::Ok(input_temp) = temp {
+                    if input_temp >= temp_config.hot_temp {
+                        return ControlDecisionExplained {
+                            decision: Self::UseFixedSpeed(fan::Speed::FULL_SPEED),
+                            reason: "temperature above HOT",
+                        };
+                    }
+                }
+                return ControlDecisionExplained {
+                    decision: Self::UseTargetRpm,
+                    reason: "user defined fan RPM",
+                };
+            }
             FanControlMode::TargetTemperature(target_temp) => match temp {
                 ChainTemperature::Failed | ChainTemperature::Unknown => {
                     panic!("BUG: should've been caught earlier at the top of `decide()` function")
@@ -306,13 +683,19 @@ impl ControlDecision {
 
     /// Decision rules if fan control is enabled and temp control disabled
     fn decide_fan_control_notemp(fan_config: &FanControlConfig) -> ControlDecisionExplained {
-        match fan_config.mode {
+        match &fan_config.mode {
             FanControlMode::FixedSpeed(pwm) => {
                 return ControlDecisionExplained {
-                    decision: Self::UseFixedSpeed(pwm),
+                    decision: Self::UseFixedSpeed(*pwm),
                     reason: "user defined fan speed",
                 };
             }
+            FanControlMode::TargetRpm(_) => {
+                return ControlDecisionExplained {
+                    decision: Self::UseTargetRpm,
+                    reason: "user defined fan RPM",
+                };
+            }
             FanControlMode::TargetTemperature(_) => {
                 // I don't know how to avoid this variant using type system alone
                 // Let's make it non-fatal
@@ -324,10 +707,86 @@ impl ControlDecision {
         }
     }
 
+    /// Fan speed requested by the decision (`None` means that fans are left alone). PID
+    /// controller `pid` or RPM controller `rpm` (with `fan_feedback`) is updated at `now` when
+    /// the decision is to use it.
+    fn fan_speed(
+        &self,
+        config: &Config,
+        pid: &mut fan::pid::TempControl,
+        rpm: &mut fan::rpm::RpmControl,
+        fan_feedback: &fan::Feedback,
+        miner_warming_up: bool,
+        now: Instant,
+    ) -> Option<fan::Speed> {
+        match *self {
+            Self::Shutdown | Self::Nothing => None,
+            Self::UseFixedSpeed(fan_speed) => Some(fan_speed),
+            Self::UseTargetRpm => {
+                if let Some(FanControlMode::TargetRpm(targets)) = config
+                    .fan_config
+                    .as_ref()
+                    .map(|fan_config| &fan_config.mode)
+                {
+                    rpm.set_targets(targets);
+                }
+                let speed = rpm.update_at(fan_feedback, now);
+                info!(
+                    "Monitor: rpm={:?} output={:?} fallback={}",
+                    fan_feedback.rpm,
+                    speed,
+                    rpm.is_fallback()
+                );
+                Some(speed)
+            }
+            Self::UsePid {
+                target_temp,
+                input_temp,
+            } => {
+                if config.fans_on_while_warming_up && miner_warming_up {
+                    pid.set_warm_up_limits();
+                } else {
+                    pid.set_normal_limits();
+                }
+                pid.set_target(target_temp.into());
+                let speed = pid.update_at(input_temp.into(), now);
+                info!(
+                    "Monitor: input={} target={} output={:?}",
+                    input_temp, target_temp, speed
+                );
+                Some(speed)
+            }
+        }
+    }
+
+    /// Override `decision_explained` when temperature of some hashchain rises too fast.
+    /// Shutdown is always kept and fans are left alone when fan control is disabled.
+    fn decide_thermal_runaway(
+        config: &ThermalRunawayConfig,
+        decision_explained: ControlDecisionExplained,
+    ) -> ControlDecisionExplained {
+        match decision_explained.decision {
+            Self::Shutdown => decision_explained,
+            _ if config.action == ThermalRunawayAction::Shutdown => ControlDecisionExplained {
+                decision: Self::Shutdown,
+                reason: "temperature rising too fast",
+            },
+            Self::Nothing => decision_explained,
+            _ => ControlDecisionExplained {
+                decision: Self::UseFixedSpeed(fan::Speed::FULL_SPEED),
+                reason: "temperature rising too fast",
+            },
+        }
+    }
+
     /// Decide what to do depending on temperature/fan feedback.
     /// This function has been factored out of the main control code to facilitate testing.
+    ///
+    /// `fan_guard` is updated with current fan feedback, `now` is timestamp of the decision.
     fn decide(
         config: &Config,
+        fan_guard: &mut FanGuard,
+        now: Instant,
         num_fans_running: usize,
         temp: ChainTemperature,
     ) -> ControlDecisionExplained {
@@ -362,18 +821,16 @@ impl ControlDecision {
             // This section is labeled `FAN_DANGER` in the diagram
             //
             // Check `min_fans` are spinning _unless_ we have been explicitly configured to
-            // turn them off.
-            //
-            // XXX: There's a problem however: if we are configured for stopped fans and then
-            // the configuration changes at runtime to non-stopped fans, the delay of fans
-            // taking some time to spin up will cause this check to fire off!
-            if decision_explained.decision != Self::UseFixedSpeed(fan::Speed::STOPPED) {
-                if num_fans_running < fan_config.min_fans {
-                    return ControlDecisionExplained {
-                        decision: Self::Shutdown,
-                        reason: "not enough fans",
-                    };
-                }
+            // turn them off. Fans that are still spinning up after speed change (or that
+            // went missing just for a moment) are tolerated by `fan_guard`.
+            let fans_missing = decision_explained.decision
+                != Self::UseFixedSpeed(fan::Speed::STOPPED)
+                && num_fans_running < fan_config.min_fans;
+            if fan_guard.tick(now, fans_missing) {
+                return ControlDecisionExplained {
+                    decision: Self::Shutdown,
+                    reason: "not enough fans",
+                };
             }
             decision_explained
         } else {
@@ -429,6 +886,36 @@ impl TemperatureAccumulator {
     }
 }
 
+/// Non-fatal problem detected by `Monitor`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Temperature sensors of a hashboard disagree, `deviation` is the largest difference
+    /// between a sensor and median of all sensors
+    SensorDisagreement {
+        hashboard_idx: usize,
+        deviation: f32,
+    },
+    /// Temperature of a hashboard rises faster than allowed, `slope` is the rate of change
+    /// in degrees Celsius per minute
+    ThermalRunaway { hashboard_idx: usize, slope: f32 },
+}
+
+/// Status of one hashchain as seen by `Monitor`
+#[derive(Debug, Clone)]
+pub struct ChainStatus {
+    pub hashboard_idx: usize,
+    /// Hashchain is waiting for its turn to start
+    pub start_pending: bool,
+    /// Raw readings of all hashboard sensors
+    pub sensors: Vec<sensor::Temperature>,
+    /// Chip temperature aggregated from `sensors`
+    pub temperature: ChainTemperature,
+    /// Number of restarts of the hashchain after it has been broken
+    pub restart_count: usize,
+    /// Rate of change of `temperature` in degrees Celsius per minute
+    pub temperature_slope: Option<f32>,
+}
+
 /// Status of `Monitor` for others to observe
 #[derive(Debug, Clone)]
 pub struct Status {
@@ -437,7 +924,9 @@ pub struct Status {
     pub fan_speed: Option<fan::Speed>,
     pub input_temperature: ChainTemperature,
     pub temperature_accumulator: TemperatureAccumulator,
+    pub chains: Vec<ChainStatus>,
     pub decision_explained: ControlDecisionExplained,
+    pub warnings: Vec<Warning>,
 }
 
 /// Monitor - it holds states of all Chains and everything related to fan control
@@ -447,11 +936,15 @@ pub struct MonitorInner {
     /// temp/fan control configuration
     config: Config,
     /// Fan controller - can set RPM or read feedback
-    fan_control: fan::Control,
+    fan_control: Box<dyn fan::Driver>,
     /// Last fan speed that was set
     current_fan_speed: Option<fan::Speed>,
+    /// Hysteresis for fan failure detection
+    fan_guard: FanGuard,
     /// PID that controls fan with hashchain temperature as input
     pid: fan::pid::TempControl,
+    /// Controller of fan speed with fan RPM as input
+    rpm: fan::rpm::RpmControl,
     /// Flag whether miner is in failure state - temperature critical, hashboards not responding,
     /// fans gone missing...
     failure_state: bool,
@@ -466,6 +959,13 @@ pub struct Monitor {
     /// Context to shutdown when miner enters critical state
     miner_shutdown: Arc<halt::Sender>,
 
+    /// Bus where sensor failures and shutdowns are published
+    event_bus: Arc<events::Bus>,
+
+    /// Requests to restart broken hashchains (by hashboard index)
+    restart_sender: mpsc::UnboundedSender<usize>,
+    restart_receiver: Mutex<Option<mpsc::UnboundedReceiver<usize>>>,
+
     /// Inner context
     inner: Mutex<MonitorInner>,
 }
@@ -475,26 +975,36 @@ impl Monitor {
     ///
     /// * `miner_shutdown` - halt sender to shutdown the whole miner in case of a failure
     /// * `halt_receiver` - termination context in which to start the monitor
+    /// * `event_bus` - bus for publishing of sensor failures and shutdowns
     pub async fn new_and_start(
         config: Config,
         miner_shutdown: Arc<halt::Sender>,
         halt_receiver: halt::Receiver,
+        event_bus: Arc<events::Bus>,
     ) -> Arc<Self> {
         let (status_sender, status_receiver) = watch::channel(None);
+        let (restart_sender, restart_receiver) = mpsc::unbounded();
 
         let inner = MonitorInner {
             chains: Vec::new(),
             config,
-            fan_control: fan::Control::new().expect("failed initializing fan controller"),
+            fan_control: Box::new(
+                fan::Control::new().expect("failed initializing fan controller"),
+            ),
             pid: fan::pid::TempControl::new(),
+            rpm: fan::rpm::RpmControl::new(),
             failure_state: false,
             current_fan_speed: None,
+            fan_guard: FanGuard::default(),
         };
 
         let monitor = Arc::new(Monitor {
             miner_shutdown,
+            event_bus,
             status_sender,
             status_receiver,
+            restart_sender,
+            restart_receiver: Mutex::new(Some(restart_receiver)),
             inner: Mutex::new(inner),
         });
 
@@ -503,10 +1013,27 @@ impl Monitor {
             .await
             .spawn_halt_handler(Self::termination_handler(monitor.clone()));
 
-        halt_receiver
-            .register_client("monitor".into())
-            .await
-            .spawn(Self::tick_task(monitor.clone()));
+        // temperature control must not stop silently, the miner is shut down when it fails
+        let supervisor = supervisor::Supervisor::new(
+            halt_receiver.clone(),
+            None,
+            supervisor::Escalation {
+                chain_restart: None,
+                miner_shutdown: Some(monitor.miner_shutdown.clone()),
+                ..Default::default()
+            },
+        );
+        let tick_monitor = monitor.clone();
+        let tick_halt_receiver = halt_receiver.clone();
+        supervisor
+            .spawn(
+                "monitor".into(),
+                supervisor::Policy::RestartTask {
+                    max_restarts: MAX_TICK_RESTARTS,
+                },
+                move || Self::tick_task(tick_monitor.clone(), tick_halt_receiver.clone()),
+            )
+            .await;
 
         monitor
     }
@@ -524,16 +1051,31 @@ impl Monitor {
     }
 
     /// Shutdown miner
-    async fn shutdown(&self, inner: &mut MonitorInner, reason: String) {
+    async fn shutdown(
+        &self,
+        inner: &mut MonitorInner,
+        shutdown_reason: shutdown::Reason,
+        reason: String,
+    ) {
         error!("Monitor task declared miner shutdown: {}", reason);
+        self.event_bus.publish(events::Kind::ThermalShutdown {
+            reason: reason.clone(),
+        });
         inner.failure_state = true;
-        self.miner_shutdown.clone().send_halt().await;
+        self.miner_shutdown
+            .clone()
+            .send_shutdown(shutdown_reason, format!("monitor: {}", reason))
+            .await;
     }
 
     /// Set fan speed
     fn set_fan_speed(&self, inner: &mut MonitorInner, fan_speed: fan::Speed) {
         info!("Monitor: setting fan to {:?}", fan_speed);
         inner.fan_control.set_speed(fan_speed);
+        if inner.current_fan_speed != Some(fan_speed) {
+            let previous = inner.current_fan_speed;
+            inner.fan_guard.speed_changed(Instant::now(), previous, fan_speed);
+        }
         inner.current_fan_speed = Some(fan_speed);
     }
 
@@ -546,21 +1088,94 @@ impl Monitor {
         let mut inner = self.inner.lock().await;
         let mut temperature_accumulator = TemperatureAccumulator::new();
         let mut miner_warming_up = false;
+        let mut warnings = vec![];
+        let mut chain_statuses = vec![];
+        let broken_chain_policy = inner.config.broken_chain_policy.clone();
+        let thermal_runaway = inner.config.thermal_runaway.clone();
+        let mut thermal_runaway_detected = false;
         for chain in inner.chains.iter() {
             let mut chain = chain.lock().await;
-            chain.state.tick(Instant::now());
-
-            if let ChainState::Broken(reason) = chain.state {
-                // TODO: here comes "Shutdown"
-                let reason = format!("Chain {} is broken: {}", chain.hashboard_idx, reason);
-                // drop `chain` here to drop iterator which holds immutable reference
-                // to `monitor`
-                drop(chain);
-                self.shutdown(&mut inner, reason).await;
-                return;
+            let now = Instant::now();
+            chain.state.tick(now);
+            chain.check_recovery(now);
+
+            if let ChainState::Broken(cause) = chain.state {
+                let reason = format!("Chain {} is broken: {}", chain.hashboard_idx, cause);
+                match chain.broken_action(&broken_chain_policy, now) {
+                    BrokenChainAction::Shutdown => {
+                        // drop `chain` here to drop iterator which holds immutable reference
+                        // to `monitor`
+                        drop(chain);
+                        self.shutdown(&mut inner, shutdown::Reason::HardwareFailure, reason)
+                            .await;
+                        return;
+                    }
+                    BrokenChainAction::Restart => {
+                        warn!(
+                            "Monitor: {}, restarting it (attempt {})",
+                            reason, chain.consecutive_restarts
+                        );
+                        self.event_bus.publish(events::Kind::ChainBroken {
+                            hashboard_idx: chain.hashboard_idx,
+                            reason: cause.to_string(),
+                        });
+                        self.restart_sender
+                            .unbounded_send(chain.hashboard_idx)
+                            .expect("BUG: restart channel closed");
+                    }
+                    BrokenChainAction::Wait => {
+                        debug!("Monitor: {}, waiting for restart cooldown", reason);
+                    }
+                }
             }
             info!("chain {}: {:?}", chain.hashboard_idx, chain.state);
-            temperature_accumulator.add_chain_temp(chain.state.get_temperature());
+            let chain_temperature = match chain.state {
+                // broken hashchain waiting for restart doesn't tell anything about temperature
+                ChainState::Broken(_) => ChainTemperature::Unknown,
+                _ => chain.state.get_temperature(),
+            };
+            let sensor_failed = chain_temperature == ChainTemperature::Failed;
+            if sensor_failed && !chain.sensor_failed {
+                self.event_bus.publish(events::Kind::SensorFailed {
+                    hashboard_idx: chain.hashboard_idx,
+                    reason: "temperature readout failed".into(),
+                });
+            }
+            chain.sensor_failed = sensor_failed;
+            chain.temperature_slope.add(now, chain_temperature);
+            let temperature_slope = chain.temperature_slope.get();
+            if let (Some(slope), Some(thermal_runaway)) = (temperature_slope, &thermal_runaway) {
+                if thermal_runaway.is_exceeded(slope, chain.state.is_warming_up(now)) {
+                    warn!(
+                        "Monitor: chain {} temperature rising too fast ({:.1} C/min)",
+                        chain.hashboard_idx, slope
+                    );
+                    warnings.push(Warning::ThermalRunaway {
+                        hashboard_idx: chain.hashboard_idx,
+                        slope,
+                    });
+                    thermal_runaway_detected = true;
+                }
+            }
+            temperature_accumulator.add_chain_temp(chain_temperature);
+            chain_statuses.push(ChainStatus {
+                hashboard_idx: chain.hashboard_idx,
+                start_pending: chain.state == ChainState::Pending,
+                sensors: chain.state.get_sensors(),
+                temperature: chain_temperature,
+                restart_count: chain.restart_count,
+                temperature_slope,
+            });
+            if let Some(deviation) = chain.state.get_sensor_disagreement() {
+                warn!(
+                    "Monitor: chain {} sensors disagree by {} degrees",
+                    chain.hashboard_idx, deviation
+                );
+                warnings.push(Warning::SensorDisagreement {
+                    hashboard_idx: chain.hashboard_idx,
+                    deviation,
+                });
+            }
             miner_warming_up |= chain.state.is_warming_up(Instant::now());
         }
         let input_temperature = temperature_accumulator.calc_result();
@@ -574,35 +1189,46 @@ impl Monitor {
         );
 
         // all right, temperature has been aggregated, decide what to do
-        let decision_explained =
-            ControlDecision::decide(&inner.config, num_fans_running, input_temperature);
-        info!("Monitor: {:?}", decision_explained);
-        match decision_explained.decision {
-            ControlDecision::Shutdown => {
-                self.shutdown(&mut inner, decision_explained.reason.into())
-                    .await;
+        let decision_explained = {
+            // reborrow guard to be able to borrow its fields separately
+            let inner = &mut *inner;
+            ControlDecision::decide(
+                &inner.config,
+                &mut inner.fan_guard,
+                Instant::now(),
+                num_fans_running,
+                input_temperature,
+            )
+        };
+        let decision_explained = match &thermal_runaway {
+            Some(thermal_runaway) if thermal_runaway_detected => {
+                ControlDecision::decide_thermal_runaway(thermal_runaway, decision_explained)
             }
-            ControlDecision::UseFixedSpeed(fan_speed) => {
+            _ => decision_explained,
+        };
+        info!("Monitor: {:?}", decision_explained);
+        if decision_explained.decision == ControlDecision::Shutdown {
+            self.shutdown(
+                &mut inner,
+                shutdown::Reason::ThermalShutdown,
+                decision_explained.reason.into(),
+            )
+            .await;
+        } else {
+            let fan_speed = {
+                let inner = &mut *inner;
+                decision_explained.decision.fan_speed(
+                    &inner.config,
+                    &mut inner.pid,
+                    &mut inner.rpm,
+                    &fan_feedback,
+                    miner_warming_up,
+                    Instant::now(),
+                )
+            };
+            if let Some(fan_speed) = fan_speed {
                 self.set_fan_speed(&mut inner, fan_speed);
             }
-            ControlDecision::UsePid {
-                target_temp,
-                input_temp,
-            } => {
-                if inner.config.fans_on_while_warming_up && miner_warming_up {
-                    inner.pid.set_warm_up_limits();
-                } else {
-                    inner.pid.set_normal_limits();
-                }
-                inner.pid.set_target(target_temp.into());
-                let speed = inner.pid.update(input_temp.into());
-                info!(
-                    "Monitor: input={} target={} output={:?}",
-                    input_temp, target_temp, speed
-                );
-                self.set_fan_speed(&mut inner, speed);
-            }
-            ControlDecision::Nothing => {}
         }
 
         // Broadcast `Status`
@@ -611,20 +1237,25 @@ impl Monitor {
             fan_speed: inner.current_fan_speed,
             input_temperature,
             temperature_accumulator,
+            chains: chain_statuses,
             decision_explained,
             config: inner.config.clone(),
+            warnings,
         };
         self.status_sender
-            .broadcast(Some(monitor_status))
+            .send(Some(monitor_status))
             .expect("broadcast failed");
     }
 
-    /// Task performing temp control
-    async fn tick_task(self: Arc<Self>) {
+    /// Task performing temp control. Ticking stops as soon as the halt is issued so that it
+    /// cannot interfere with the termination handler.
+    async fn tick_task(self: Arc<Self>, halt_receiver: halt::Receiver) {
         loop {
             self.do_tick().await;
             // TODO: find some of kind "run every x secs" function
-            delay_for(TICK_LENGTH).await;
+            if halt_receiver.sleep(TICK_LENGTH).await.is_err() {
+                break;
+            }
         }
     }
 
@@ -649,6 +1280,17 @@ impl Monitor {
         tx
     }
 
+    /// Sender of requests to restart hashchains (e.g. when their tasks fail)
+    pub fn chain_restart_sender(&self) -> mpsc::UnboundedSender<usize> {
+        self.restart_sender.clone()
+    }
+
+    /// Take receiver of requests to restart broken hashchains. The requests are sent
+    /// when `BrokenChainPolicy::Restart` is configured or by `chain_restart_sender`.
+    pub async fn take_restart_receiver(&self) -> Option<mpsc::UnboundedReceiver<usize>> {
+        self.restart_receiver.lock().await.take()
+    }
+
     pub async fn with_configuration<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&mut Config) -> R,
@@ -708,6 +1350,63 @@ mod test {
         );
     }
 
+    fn remote(t: f32) -> sensor::Temperature {
+        sensor::Temperature {
+            local: sensor::Measurement::Ok(10.0),
+            remote: sensor::Measurement::Ok(t),
+        }
+    }
+
+    /// Test aggregating readings of multiple sensors
+    #[test]
+    fn test_monitor_s9_multiple_sensors() {
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[]),
+            ChainTemperature::Unknown
+        );
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[sensor::INVALID_TEMPERATURE_READING]),
+            ChainTemperature::Unknown
+        );
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[remote(60.0)]),
+            ChainTemperature::Ok(60.0)
+        );
+        // failed sensors are left out
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[
+                sensor::INVALID_TEMPERATURE_READING,
+                remote(60.0),
+                remote(64.0)
+            ]),
+            ChainTemperature::Ok(62.0)
+        );
+        // outlier is rejected
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[remote(60.0), remote(62.0), remote(120.0)]),
+            ChainTemperature::Ok(61.0)
+        );
+        // there is no majority when two sensors disagree
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[remote(60.0), remote(100.0)]),
+            ChainTemperature::Unknown
+        );
+        assert_eq!(
+            ChainTemperature::from_s9_sensors(&[remote(f32::NAN)]),
+            ChainTemperature::Unknown
+        );
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[f32::NAN]), None);
+        assert_eq!(median(&[3.0, f32::NAN, 1.0]), Some(2.0));
+
+        assert_eq!(s9_sensor_disagreement(&[remote(60.0)]), None);
+        assert_eq!(s9_sensor_disagreement(&[remote(60.0), remote(64.0)]), None);
+        assert_eq!(
+            s9_sensor_disagreement(&[remote(60.0), remote(62.0), remote(120.0)]),
+            Some(58.0)
+        );
+    }
+
     fn send(mut state: ChainState, when: Instant, message: Message) -> ChainState {
         state.transition(when, message);
         state
@@ -725,13 +1424,17 @@ mod test {
         let running_state = ChainState::Running {
             started: now,
             last_heartbeat: now,
-            temperature: temp.clone(),
+            temperatures: vec![temp.clone()],
         };
 
         //assert_eq!(send(ChainState::Running(now, temp), later, Message::Off), ChainState::Off);
+        assert_variant!(
+            send(ChainState::Off, later, Message::Pending),
+            ChainState::Pending
+        );
         assert_variant!(send(ChainState::Off, later, Message::On), ChainState::On(_));
         assert_variant!(
-            send(ChainState::Off, later, Message::Running(temp.clone())),
+            send(ChainState::Off, later, Message::Running(vec![temp.clone()])),
             ChainState::Broken(_)
         );
         assert_variant!(
@@ -739,12 +1442,37 @@ mod test {
             ChainState::Broken(_)
         );
 
+        assert_variant!(
+            send(ChainState::Pending, later, Message::Pending),
+            ChainState::Broken(_)
+        );
+        assert_variant!(
+            send(ChainState::Pending, later, Message::On),
+            ChainState::On(_)
+        );
+        assert_variant!(
+            send(
+                ChainState::Pending,
+                later,
+                Message::Running(vec![temp.clone()])
+            ),
+            ChainState::Broken(_)
+        );
+        assert_variant!(
+            send(ChainState::Pending, later, Message::Off),
+            ChainState::Off
+        );
+
         assert_variant!(
             send(ChainState::On(now), later, Message::On),
             ChainState::Broken(_)
         );
         assert_variant!(
-            send(ChainState::On(now), later, Message::Running(temp.clone())),
+            send(
+                ChainState::On(now),
+                later,
+                Message::Running(vec![temp.clone()])
+            ),
             ChainState::Running{ .. }
         );
         assert_variant!(
@@ -760,7 +1488,7 @@ mod test {
             send(
                 running_state.clone(),
                 later,
-                Message::Running(temp.clone())
+                Message::Running(vec![temp.clone()])
             ),
             ChainState::Running { .. }
         );
@@ -783,7 +1511,7 @@ mod test {
         let running_state = ChainState::Running {
             started: now,
             last_heartbeat: now,
-            temperature: temp.clone(),
+            temperatures: vec![temp.clone()],
         };
 
         assert_eq!(ChainState::Off.is_warming_up(now), false);
@@ -812,11 +1540,13 @@ mod test {
         let running_state = ChainState::Running {
             started: now,
             last_heartbeat: now,
-            temperature: temp.clone(),
+            temperatures: vec![temp.clone()],
         };
 
-        // test that chains break when no-one updates them for long (unless they are turned off)
+        // test that chains break when no-one updates them for long (unless they are turned off
+        // or waiting to be started)
         assert_variant!(tick(ChainState::Off, long), ChainState::Off);
+        assert_variant!(tick(ChainState::Pending, long), ChainState::Pending);
         assert_variant!(tick(ChainState::On(now), long), ChainState::Broken(_));
         assert_variant!(tick(running_state.clone(), long), ChainState::Broken(_));
 
@@ -839,6 +1569,78 @@ mod test {
         );
     }
 
+    /// Test restarting of broken chains
+    #[test]
+    fn test_monitor_broken_chain_policy() {
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(60);
+        let policy = BrokenChainPolicy::Restart {
+            max_restarts: 2,
+            cooldown,
+        };
+
+        // shutdown policy never restarts
+        let mut chain = Chain::new(8);
+        chain.state = ChainState::Broken("test");
+        assert_eq!(
+            chain.broken_action(&BrokenChainPolicy::Shutdown, now),
+            BrokenChainAction::Shutdown
+        );
+
+        // the first restart is immediate
+        assert_eq!(
+            chain.broken_action(&policy, now),
+            BrokenChainAction::Restart
+        );
+        assert_eq!(chain.state, ChainState::Restarting(now));
+        assert_eq!(chain.restart_count, 1);
+
+        // chain is stopped and started again by its manager
+        let state = send(chain.state.clone(), now, Message::Off);
+        assert_variant!(state, ChainState::Restarting(_));
+        assert_variant!(send(state, now, Message::On), ChainState::On(_));
+        // restart that takes too long breaks the chain again
+        assert_variant!(
+            tick(ChainState::Restarting(now), now + START_TIMEOUT),
+            ChainState::Broken(_)
+        );
+
+        // the next restart has to wait for cooldown
+        chain.state = ChainState::Broken("test");
+        let later = now + Duration::from_secs(1);
+        assert_eq!(chain.broken_action(&policy, later), BrokenChainAction::Wait);
+        assert_variant!(chain.state, ChainState::Broken(_));
+        let later = now + cooldown;
+        assert_eq!(
+            chain.broken_action(&policy, later),
+            BrokenChainAction::Restart
+        );
+
+        // the limit of restarts is exhausted
+        chain.state = ChainState::Broken("test");
+        let later = later + cooldown;
+        assert_eq!(
+            chain.broken_action(&policy, later),
+            BrokenChainAction::Shutdown
+        );
+        assert_eq!(chain.restart_count, 2);
+
+        // chain running long enough is considered recovered
+        chain.state = ChainState::Running {
+            started: now,
+            last_heartbeat: now,
+            temperatures: vec![],
+        };
+        chain.check_recovery(now + BROKEN_CHAIN_RECOVERY_PERIOD);
+        chain.state = ChainState::Broken("test");
+        let later = later + cooldown;
+        assert_eq!(
+            chain.broken_action(&policy, later),
+            BrokenChainAction::Restart
+        );
+        assert_eq!(chain.restart_count, 3);
+    }
+
     fn test_acc(temp1: ChainTemperature, temp2: ChainTemperature) -> ChainTemperature {
         let mut tacc = TemperatureAccumulator::new();
         tacc.add_chain_temp(temp1);
@@ -891,6 +1693,22 @@ mod test {
         );
     }
 
+    /// Decide with fan hysteresis turned off
+    fn decide_now(
+        config: &Config,
+        num_fans_running: usize,
+        temp: ChainTemperature,
+    ) -> ControlDecisionExplained {
+        let mut fan_guard = FanGuard::new(Duration::from_secs(0), 1);
+        ControlDecision::decide(
+            config,
+            &mut fan_guard,
+            Instant::now(),
+            num_fans_running,
+            temp,
+        )
+    }
+
     /// Test temperature decision tree (non-exhaustive test)
     #[test]
     fn test_decide() {
@@ -909,6 +1727,8 @@ mod test {
         let fans_off = fan::Speed::STOPPED;
         let fans_off_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: Some(FanControlConfig {
                 mode: FanControlMode::FixedSpeed(fans_off),
                 min_fans: 2,
@@ -917,133 +1737,409 @@ mod test {
         };
         let all_off_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: None,
             temp_config: None,
         };
         let fans_on_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: Some(fan_config.clone()),
             temp_config: None,
         };
         let temp_on_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: None,
             temp_config: Some(temp_config.clone()),
         };
         let both_on_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: Some(fan_config.clone()),
             temp_config: Some(temp_config.clone()),
         };
         let both_on_pid_config = Config {
             fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
             fan_config: Some(FanControlConfig {
                 mode: FanControlMode::TargetTemperature(75.0),
                 min_fans: 2,
             }),
             temp_config: Some(temp_config.clone()),
         };
+        let rpm_fan_config = FanControlConfig {
+            mode: FanControlMode::TargetRpm(vec![Some(3000), Some(3000), None, None]),
+            min_fans: 2,
+        };
+        let fans_on_rpm_config = Config {
+            fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
+            fan_config: Some(rpm_fan_config.clone()),
+            temp_config: None,
+        };
+        let both_on_rpm_config = Config {
+            fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
+            fan_config: Some(rpm_fan_config),
+            temp_config: Some(temp_config.clone()),
+        };
 
         assert_variant!(
-            ControlDecision::decide(&all_off_config, 0, dang_temp.clone()).decision,
+            decide_now(&all_off_config, 0, dang_temp.clone()).decision,
             ControlDecision::Nothing
         );
         assert_variant!(
-            ControlDecision::decide(&all_off_config, 0, ChainTemperature::Failed).decision,
+            decide_now(&all_off_config, 0, ChainTemperature::Failed).decision,
             ControlDecision::Nothing
         );
 
         assert_eq!(
-            ControlDecision::decide(&fans_on_config, 2, dang_temp.clone()).decision,
+            decide_now(&fans_on_config, 2, dang_temp.clone()).decision,
             ControlDecision::UseFixedSpeed(fan_speed)
         );
         assert_eq!(
-            ControlDecision::decide(&fans_on_config, 0, dang_temp.clone()).decision,
+            decide_now(&fans_on_config, 0, dang_temp.clone()).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&fans_on_config, 1, dang_temp.clone()).decision,
+            decide_now(&fans_on_config, 1, dang_temp.clone()).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&fans_on_config, 2, ChainTemperature::Failed).decision,
+            decide_now(&fans_on_config, 2, ChainTemperature::Failed).decision,
             ControlDecision::UseFixedSpeed(fan_speed)
         );
 
         // fans set to 0 -> do not check if fans are running
         assert_eq!(
-            ControlDecision::decide(&fans_off_config, 0, dang_temp.clone()).decision,
+            decide_now(&fans_off_config, 0, dang_temp.clone()).decision,
             ControlDecision::UseFixedSpeed(fans_off)
         );
 
         assert_eq!(
-            ControlDecision::decide(&temp_on_config, 0, ChainTemperature::Failed).decision,
+            decide_now(&temp_on_config, 0, ChainTemperature::Failed).decision,
             ControlDecision::Shutdown
         );
         assert_variant!(
-            ControlDecision::decide(&temp_on_config, 0, ChainTemperature::Unknown).decision,
+            decide_now(&temp_on_config, 0, ChainTemperature::Unknown).decision,
             ControlDecision::Nothing
         );
         assert_eq!(
-            ControlDecision::decide(&temp_on_config, 0, dang_temp).decision,
+            decide_now(&temp_on_config, 0, dang_temp).decision,
             ControlDecision::Shutdown
         );
         assert_variant!(
-            ControlDecision::decide(&temp_on_config, 0, hot_temp).decision,
+            decide_now(&temp_on_config, 0, hot_temp).decision,
             ControlDecision::Nothing
         );
 
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 0, low_temp).decision,
+            decide_now(&both_on_config, 0, low_temp).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 2, dang_temp).decision,
+            decide_now(&both_on_config, 2, dang_temp).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 2, ChainTemperature::Failed).decision,
+            decide_now(&both_on_config, 2, ChainTemperature::Failed).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 2, ChainTemperature::Unknown).decision,
+            decide_now(&both_on_config, 2, ChainTemperature::Unknown).decision,
             ControlDecision::UseFixedSpeed(fan::Speed::FULL_SPEED)
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 2, hot_temp).decision,
+            decide_now(&both_on_config, 2, hot_temp).decision,
             ControlDecision::UseFixedSpeed(fan_speed)
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_config, 2, low_temp).decision,
+            decide_now(&both_on_config, 2, low_temp).decision,
             ControlDecision::UseFixedSpeed(fan_speed)
         );
 
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 0, low_temp).decision,
+            decide_now(&both_on_pid_config, 0, low_temp).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 2, dang_temp).decision,
+            decide_now(&both_on_pid_config, 2, dang_temp).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 2, ChainTemperature::Failed).decision,
+            decide_now(&both_on_pid_config, 2, ChainTemperature::Failed).decision,
             ControlDecision::Shutdown
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 2, ChainTemperature::Unknown).decision,
+            decide_now(&both_on_pid_config, 2, ChainTemperature::Unknown).decision,
             ControlDecision::UseFixedSpeed(fan::Speed::FULL_SPEED)
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 2, hot_temp).decision,
+            decide_now(&both_on_pid_config, 2, hot_temp).decision,
             ControlDecision::UseFixedSpeed(fan::Speed::FULL_SPEED)
         );
         assert_eq!(
-            ControlDecision::decide(&both_on_pid_config, 2, low_temp).decision,
+            decide_now(&both_on_pid_config, 2, low_temp).decision,
             ControlDecision::UsePid {
                 target_temp: 75.0,
                 input_temp: 50.0
             }
         );
+
+        assert_eq!(
+            decide_now(&fans_on_rpm_config, 2, ChainTemperature::Unknown).decision,
+            ControlDecision::UseTargetRpm
+        );
+        assert_eq!(
+            decide_now(&fans_on_rpm_config, 1, low_temp).decision,
+            ControlDecision::Shutdown
+        );
+        assert_eq!(
+            decide_now(&both_on_rpm_config, 2, hot_temp).decision,
+            ControlDecision::UseFixedSpeed(fan::Speed::FULL_SPEED)
+        );
+        assert_eq!(
+            decide_now(&both_on_rpm_config, 2, low_temp).decision,
+            ControlDecision::UseTargetRpm
+        );
+    }
+
+    /// Test fan failure hysteresis
+    #[test]
+    fn test_fan_guard() {
+        let now = Instant::now();
+        let tick = Duration::from_secs(5);
+        let mut guard = FanGuard::new(Duration::from_secs(20), 3);
+
+        // fans missing only for a while are fine
+        assert!(!guard.tick(now, true));
+        assert!(!guard.tick(now + tick, true));
+        assert!(!guard.tick(now + tick * 2, false));
+        assert!(!guard.tick(now + tick * 3, true));
+        assert!(!guard.tick(now + tick * 4, true));
+        // sustained failure is not
+        assert!(guard.tick(now + tick * 5, true));
+        assert!(guard.tick(now + tick * 6, true));
+
+        // fans started from standstill get grace period
+        guard.speed_changed(now + tick * 6, Some(fan::Speed::STOPPED), fan::Speed::new(50));
+        assert!(guard.in_grace_period(now + tick * 7));
+        for i in 7..10 {
+            assert!(!guard.tick(now + tick * i, true));
+        }
+        // grace period is over, count from the beginning
+        assert!(!guard.in_grace_period(now + tick * 10));
+        assert!(!guard.tick(now + tick * 10, true));
+        assert!(!guard.tick(now + tick * 11, true));
+        assert!(guard.tick(now + tick * 12, true));
+
+        // speed change of spinning fans doesn't postpone the failure
+        guard.speed_changed(now + tick * 12, Some(fan::Speed::new(50)), fan::Speed::new(60));
+        assert!(!guard.in_grace_period(now + tick * 13));
+        assert!(guard.tick(now + tick * 13, true));
+        // neither does slowing down to low speed
+        guard.speed_changed(now + tick * 13, Some(fan::Speed::new(60)), fan::Speed::new(5));
+        assert!(!guard.in_grace_period(now + tick * 14));
+    }
+
+    #[test]
+    fn test_fan_guard_first_start() {
+        let now = Instant::now();
+        let mut guard = FanGuard::new(Duration::from_secs(20), 1);
+        // the very first speed is a start of fans
+        guard.speed_changed(now, None, fan::Speed::FULL_SPEED);
+        assert!(guard.in_grace_period(now + Duration::from_secs(10)));
+        assert!(!guard.tick(now + Duration::from_secs(10), true));
+        // fans set to low speed aren't expected to be spinning up
+        let mut guard = FanGuard::new(Duration::from_secs(20), 1);
+        guard.speed_changed(now, None, fan::Speed::new(5));
+        assert!(!guard.in_grace_period(now));
+    }
+
+    /// Test that fan check in decision tree obeys hysteresis
+    #[test]
+    fn test_decide_fan_hysteresis() {
+        let low_temp = ChainTemperature::Ok(50.0);
+        let fan_speed = fan::Speed::new(50);
+        let fans_on_config = Config {
+            fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
+            fan_config: Some(FanControlConfig {
+                mode: FanControlMode::FixedSpeed(fan_speed),
+                min_fans: 2,
+            }),
+            temp_config: None,
+        };
+        let both_on_pid_config = Config {
+            fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
+            fan_config: Some(FanControlConfig {
+                mode: FanControlMode::TargetTemperature(75.0),
+                min_fans: 2,
+            }),
+            temp_config: Some(TempControlConfig {
+                dangerous_temp: 100.0,
+                hot_temp: 80.0,
+            }),
+        };
+        let fans_off_config = Config {
+            fans_on_while_warming_up: true,
+            broken_chain_policy: BrokenChainPolicy::Shutdown,
+            thermal_runaway: None,
+            fan_config: Some(FanControlConfig {
+                mode: FanControlMode::FixedSpeed(fan::Speed::STOPPED),
+                min_fans: 2,
+            }),
+            temp_config: None,
+        };
+        let now = Instant::now();
+        let tick = Duration::from_secs(5);
+
+        // fans spinning up after speed change are tolerated
+        let mut guard = FanGuard::new(Duration::from_secs(20), 3);
+        guard.speed_changed(now);
+        for i in 0..4 {
+            assert_eq!(
+                ControlDecision::decide(&fans_on_config, &mut guard, now + tick * i, 0, low_temp)
+                    .decision,
+                ControlDecision::UseFixedSpeed(fan_speed)
+            );
+        }
+        // ... until grace period is over and fans are missing for 3 ticks
+        for i in 4..6 {
+            assert_eq!(
+                ControlDecision::decide(&fans_on_config, &mut guard, now + tick * i, 1, low_temp)
+                    .decision,
+                ControlDecision::UseFixedSpeed(fan_speed)
+            );
+        }
+        assert_eq!(
+            ControlDecision::decide(&fans_on_config, &mut guard, now + tick * 6, 1, low_temp)
+                .decision,
+            ControlDecision::Shutdown
+        );
+
+        // single glitch in fan feedback doesn't shut down the miner
+        let mut guard = FanGuard::new(Duration::from_secs(20), 3);
+        for (i, &num_fans) in [2, 0, 2, 1, 1, 2].iter().enumerate() {
+            assert_eq!(
+                ControlDecision::decide(
+                    &both_on_pid_config,
+                    &mut guard,
+                    now + tick * i as u32,
+                    num_fans,
+                    low_temp
+                )
+                .decision,
+                ControlDecision::UsePid {
+                    target_temp: 75.0,
+                    input_temp: 50.0
+                }
+            );
+        }
+
+        // stopped fans are never counted as missing
+        let mut guard = FanGuard::new(Duration::from_secs(0), 1);
+        assert_eq!(
+            ControlDecision::decide(&fans_off_config, &mut guard, now, 0, low_temp).decision,
+            ControlDecision::UseFixedSpeed(fan::Speed::STOPPED)
+        );
+        assert_eq!(
+            ControlDecision::decide(&fans_on_config, &mut guard, now, 0, low_temp).decision,
+            ControlDecision::Shutdown
+        );
+    }
+
+    /// Test computation of temperature rate of change
+    #[test]
+    fn test_temperature_slope() {
+        let now = Instant::now();
+        let tick = Duration::from_secs(10);
+        let mut slope = TemperatureSlope::default();
+
+        // history is too short
+        slope.add(now, ChainTemperature::Ok(50.0));
+        slope.add(now + tick, ChainTemperature::Ok(51.0));
+        assert_eq!(slope.get(), None);
+
+        // 1 degree per 10 seconds
+        for i in 2..=6 {
+            slope.add(now + tick * i, ChainTemperature::Ok(50.0 + i as f32));
+        }
+        assert!((slope.get().expect("BUG: missing slope") - 6.0).abs() < 1e-3);
+        // old measurements are dropped
+        slope.add(now + tick * 12, ChainTemperature::Ok(56.0));
+        assert_eq!(slope.get(), Some(0.0));
+
+        // single noisy reading is smoothed by the other samples (the first and the last sample
+        // alone would give 10 degrees per minute)
+        let mut slope = TemperatureSlope::default();
+        for i in 0..6 {
+            slope.add(now + tick * i, ChainTemperature::Ok(50.0));
+        }
+        slope.add(now + tick * 6, ChainTemperature::Ok(60.0));
+        let value = slope.get().expect("BUG: missing slope");
+        assert!(value > 6.0 && value < 7.0, "unexpected slope {}", value);
+
+        // missing measurement interrupts history
+        slope.add(now + tick * 13, ChainTemperature::Unknown);
+        slope.add(now + tick * 14, ChainTemperature::Ok(56.0));
+        assert_eq!(slope.get(), None);
+    }
+
+    /// Test decisions when temperature rises too fast
+    #[test]
+    fn test_decide_thermal_runaway() {
+        let mut runaway_config = ThermalRunawayConfig {
+            max_slope: 5.0,
+            action: ThermalRunawayAction::FullSpeedFans,
+        };
+        let explained = |decision| ControlDecisionExplained {
+            decision,
+            reason: "test",
+        };
+        let pid = ControlDecision::UsePid {
+            target_temp: 75.0,
+            input_temp: 50.0,
+        };
+
+        assert_eq!(
+            ControlDecision::decide_thermal_runaway(&runaway_config, explained(pid.clone()))
+                .decision,
+            ControlDecision::UseFixedSpeed(fan::Speed::FULL_SPEED)
+        );
+        // fans are not touched when fan control is disabled
+        assert_eq!(
+            ControlDecision::decide_thermal_runaway(
+                &runaway_config,
+                explained(ControlDecision::Nothing)
+            )
+            .decision,
+            ControlDecision::Nothing
+        );
+
+        runaway_config.action = ThermalRunawayAction::Shutdown;
+        assert_eq!(
+            ControlDecision::decide_thermal_runaway(&runaway_config, explained(pid)).decision,
+            ControlDecision::Shutdown
+        );
+        assert_eq!(
+            ControlDecision::decide_thermal_runaway(
+                &runaway_config,
+                explained(ControlDecision::Nothing)
+            )
+            .decision,
+            ControlDecision::Shutdown
+        );
     }
 }
@@ -0,0 +1,444 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Optional recording of `Monitor` status history into a rotating file on the device. Thermal
+//! incidents can then be analyzed after the fact without any external monitoring.
+
+use super::*;
+
+use ii_async_compat::prelude::*;
+
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Format of the history file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One row per record with lists joined by `;`
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl Format {
+    const CSV_HEADER: &'static str =
+        "time,decision,reason,fan_speed,fan_rpm,input_temp,chain_temps,warnings";
+}
+
+/// History recording configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub path: PathBuf,
+    pub format: Format,
+    /// Minimal time between two records. Changes of warnings or shutdown decision are recorded
+    /// immediately, repeated ones are throttled as regular records.
+    pub interval: Duration,
+    /// Size in bytes after which the file is rotated
+    pub max_size: u64,
+    /// Number of files kept including the current one. Rotated files have suffix `.1`, `.2`, ...
+    /// with the highest number being the oldest one.
+    pub max_files: usize,
+}
+
+/// State of one hashchain in a record
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChainRecord {
+    pub hashboard: usize,
+    pub temperature: Option<f32>,
+    pub temperature_slope: Option<f32>,
+    pub restart_count: usize,
+}
+
+/// One sample of monitor status
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// UTC time in RFC 3339 format
+    pub time: String,
+    pub decision: &'static str,
+    pub reason: &'static str,
+    /// Fan speed in percents
+    pub fan_speed: Option<usize>,
+    pub fan_rpm: Vec<usize>,
+    pub input_temperature: Option<f32>,
+    pub chains: Vec<ChainRecord>,
+    pub warnings: Vec<String>,
+}
+
+fn temperature(temperature: ChainTemperature) -> Option<f32> {
+    match temperature {
+        ChainTemperature::Ok(t) => Some(t),
+        ChainTemperature::Unknown | ChainTemperature::Failed => None,
+    }
+}
+
+fn decision_name(decision: &ControlDecision) -> &'static str {
+    match decision {
+        ControlDecision::Shutdown => "shutdown",
+        ControlDecision::UsePid { .. } => "pid",
+        ControlDecision::UseFixedSpeed(_) => "fixed_speed",
+        ControlDecision::UseTargetRpm => "target_rpm",
+        ControlDecision::Nothing => "nothing",
+    }
+}
+
+fn warning_description(warning: &Warning) -> String {
+    match warning {
+        Warning::SensorDisagreement {
+            hashboard_idx,
+            deviation,
+        } => format!(
+            "chain {} sensors disagree by {:.1} C",
+            hashboard_idx, deviation
+        ),
+        Warning::ThermalRunaway {
+            hashboard_idx,
+            slope,
+        } => format!(
+            "chain {} temperature rising {:.1} C/min",
+            hashboard_idx, slope
+        ),
+    }
+}
+
+/// Quote CSV field when it contains a separator or a quote
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_optional<T: fmt::Display>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+impl Record {
+    pub fn new(time: chrono::DateTime<Utc>, status: &Status) -> Self {
+        Self {
+            time: time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            decision: decision_name(&status.decision_explained.decision),
+            reason: status.decision_explained.reason,
+            fan_speed: status.fan_speed.map(|speed| speed.to_pwm()),
+            fan_rpm: status.fan_feedback.rpm.clone(),
+            input_temperature: temperature(status.input_temperature),
+            chains: status
+                .chains
+                .iter()
+                .map(|chain| ChainRecord {
+                    hashboard: chain.hashboard_idx,
+                    temperature: temperature(chain.temperature),
+                    temperature_slope: chain.temperature_slope,
+                    restart_count: chain.restart_count,
+                })
+                .collect(),
+            warnings: status.warnings.iter().map(warning_description).collect(),
+        }
+    }
+
+    /// Whether the record describes an incident which should not be throttled
+    fn is_notable(&self) -> bool {
+        self.decision == decision_name(&ControlDecision::Shutdown) || !self.warnings.is_empty()
+    }
+
+    /// Whether the record describes the same incident as `other`
+    fn same_incident(&self, other: &Self) -> bool {
+        self.decision == other.decision && self.warnings == other.warnings
+    }
+
+    fn to_csv(&self) -> String {
+        let fan_rpm: Vec<_> = self.fan_rpm.iter().map(|rpm| rpm.to_string()).collect();
+        let chain_temps: Vec<_> = self
+            .chains
+            .iter()
+            .map(|chain| format!("{}:{}", chain.hashboard, csv_optional(chain.temperature)))
+            .collect();
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.time,
+            self.decision,
+            csv_field(self.reason),
+            csv_optional(self.fan_speed),
+            fan_rpm.join(";"),
+            csv_optional(self.input_temperature),
+            chain_temps.join(";"),
+            csv_field(&self.warnings.join(";")),
+        )
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("BUG: cannot serialize monitor record")
+    }
+
+    pub fn to_line(&self, format: Format) -> String {
+        match format {
+            Format::Csv => self.to_csv(),
+            Format::Json => self.to_json(),
+        }
+    }
+}
+
+/// Appends records to the history file and rotates it when it grows over the limit
+pub struct Writer {
+    config: Config,
+    /// Size of the current file or `None` when it hasn't been checked yet
+    size: Option<u64>,
+}
+
+impl Writer {
+    pub fn new(config: Config) -> Self {
+        Self { config, size: None }
+    }
+
+    fn rotated_path(&self, idx: usize) -> PathBuf {
+        let mut path = self.config.path.clone().into_os_string();
+        path.push(format!(".{}", idx));
+        path.into()
+    }
+
+    async fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+        match tokio::fs::rename(from, to).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Shift all rotated files and move the current file to `.1`. The oldest file is overwritten.
+    async fn rotate(&self) -> io::Result<()> {
+        if self.config.max_files <= 1 {
+            return match tokio::fs::remove_file(&self.config.path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        for idx in (1..self.config.max_files - 1).rev() {
+            Self::rename_if_exists(&self.rotated_path(idx), &self.rotated_path(idx + 1)).await?;
+        }
+        Self::rename_if_exists(&self.config.path, &self.rotated_path(1)).await
+    }
+
+    pub async fn write(&mut self, record: &Record) -> io::Result<()> {
+        let size = match self.size {
+            Some(size) => size,
+            None => match tokio::fs::metadata(&self.config.path).await {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            },
+        };
+        let line = format!("{}\n", record.to_line(self.config.format));
+        let mut size = if size > 0 && size + line.len() as u64 > self.config.max_size {
+            self.rotate().await?;
+            0
+        } else {
+            size
+        };
+
+        let mut data = String::new();
+        if size == 0 && self.config.format == Format::Csv {
+            data.push_str(Format::CSV_HEADER);
+            data.push('\n');
+        }
+        data.push_str(&line);
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await?;
+        file.write_all(data.as_bytes()).await?;
+        size += data.len() as u64;
+        self.size = Some(size);
+        Ok(())
+    }
+}
+
+/// Decides which records are written. Regular records are limited to one per interval and new
+/// incidents are let through immediately. Incident which persists is recorded as a regular record
+/// so that it cannot flood the history.
+struct Throttle {
+    interval: Duration,
+    last: Option<(Instant, Record)>,
+}
+
+impl Throttle {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    fn pass(&mut self, now: Instant, record: &Record) -> bool {
+        let pass = match &self.last {
+            None => true,
+            Some((last_time, last_record)) => {
+                now.duration_since(*last_time) >= self.interval
+                    || (record.is_notable() && !record.same_incident(last_record))
+            }
+        };
+        if pass {
+            self.last = Some((now, record.clone()));
+        }
+        pass
+    }
+}
+
+/// Record every change of monitor status into history file. Regular statuses are throttled to
+/// one per `interval`.
+pub async fn record_task(config: Config, mut status_receiver: watch::Receiver<Option<Status>>) {
+    let path = config.path.clone();
+    let mut throttle = Throttle::new(config.interval);
+    let mut writer = Writer::new(config);
+    while status_receiver.changed().await.is_ok() {
+        let status = match status_receiver.borrow_and_update().clone() {
+            Some(status) => status,
+            None => continue,
+        };
+        let record = Record::new(Utc::now(), &status);
+        if !throttle.pass(Instant::now(), &record) {
+            continue;
+        }
+        if let Err(e) = writer.write(&record).await {
+            error!(
+                "Monitor: cannot write status history into '{}': {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(decision: ControlDecision, warnings: Vec<String>) -> Record {
+        Record {
+            time: "2020-01-01T00:00:00Z".to_string(),
+            decision: decision_name(&decision),
+            reason: "temperature, ok",
+            fan_speed: Some(70),
+            fan_rpm: vec![3000, 3100],
+            input_temperature: Some(75.5),
+            chains: vec![
+                ChainRecord {
+                    hashboard: 6,
+                    temperature: Some(75.5),
+                    temperature_slope: None,
+                    restart_count: 0,
+                },
+                ChainRecord {
+                    hashboard: 7,
+                    temperature: None,
+                    temperature_slope: None,
+                    restart_count: 1,
+                },
+            ],
+            warnings,
+        }
+    }
+
+    #[test]
+    fn test_history_record_format() {
+        let pid_record = record(
+            ControlDecision::UsePid {
+                target_temp: 80.0,
+                input_temp: 75.5,
+            },
+            vec![],
+        );
+        assert!(!pid_record.is_notable());
+        assert_eq!(
+            pid_record.to_line(Format::Csv),
+            "2020-01-01T00:00:00Z,pid,\"temperature, ok\",70,3000;3100,75.5,6:75.5;7:,"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&pid_record.to_line(Format::Json)).expect("BUG: invalid JSON");
+        assert_eq!(json["decision"], "pid");
+        assert_eq!(json["chains"][1]["restart_count"], 1);
+        assert!(json["chains"][1]["temperature"].is_null());
+
+        assert!(record(ControlDecision::Shutdown, vec![]).is_notable());
+        assert!(record(ControlDecision::Nothing, vec!["warning".into()]).is_notable());
+    }
+
+    #[test]
+    fn test_history_throttle() {
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut throttle = Throttle::new(interval);
+        let regular = record(ControlDecision::Nothing, vec![]);
+        let warning = record(ControlDecision::Nothing, vec!["warning".into()]);
+        let other_warning = record(ControlDecision::Nothing, vec!["other warning".into()]);
+        let shutdown = record(ControlDecision::Shutdown, vec!["warning".into()]);
+
+        assert!(throttle.pass(start, &regular));
+        assert!(!throttle.pass(start + Duration::from_secs(1), &regular));
+        // new incidents are recorded immediately
+        assert!(throttle.pass(start + Duration::from_secs(2), &warning));
+        assert!(throttle.pass(start + Duration::from_secs(3), &other_warning));
+        assert!(throttle.pass(start + Duration::from_secs(4), &shutdown));
+        // repeated incident is throttled
+        for secs in 5..60 {
+            assert!(!throttle.pass(start + Duration::from_secs(secs), &shutdown));
+        }
+        assert!(throttle.pass(start + Duration::from_secs(4) + interval, &shutdown));
+        // going back to regular state is throttled too
+        assert!(!throttle.pass(start + Duration::from_secs(70), &regular));
+        assert!(throttle.pass(start + Duration::from_secs(4) + 2 * interval, &regular));
+    }
+
+    #[tokio::test]
+    async fn test_history_rotation() {
+        let dir = std::env::temp_dir().join(format!("bosminer-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("monitor.csv");
+        let record = record(ControlDecision::Nothing, vec![]);
+        let line_len = record.to_line(Format::Csv).len() as u64 + 1;
+        let header_len = Format::CSV_HEADER.len() as u64 + 1;
+
+        // every file fits header and two records
+        let mut writer = Writer::new(Config {
+            path: path.clone(),
+            format: Format::Csv,
+            interval: Duration::from_secs(0),
+            max_size: header_len + 2 * line_len,
+            max_files: 3,
+        });
+        for _ in 0..7 {
+            writer.write(&record).await.unwrap();
+        }
+
+        let read_lines = |path: PathBuf| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(read_lines(path.clone()), 2);
+        assert_eq!(read_lines(writer.rotated_path(1)), 3);
+        assert_eq!(read_lines(writer.rotated_path(2)), 3);
+        assert!(!writer.rotated_path(3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Offline simulation of the monitor control loop. Synthetic temperature trajectories and fan
+//! feedback are fed into the same decision process and PID controller as used by `Monitor`.
+//! The resulting timeline of fan speeds and decisions can be rendered which allows tuning and
+//! regression tests of thermal behaviour without hardware.
+
+use super::*;
+
+use std::fmt;
+
+/// Temperature of a hashchain in simulated time. Fan speed currently set by the controller is
+/// passed to allow closed-loop thermal models.
+pub type Trajectory = Box<dyn FnMut(Duration, Option<fan::Speed>) -> ChainTemperature>;
+
+/// Number of running fans in simulated time
+pub type FanFeedback = Box<dyn FnMut(Duration, Option<fan::Speed>) -> usize>;
+
+/// One tick of the simulated control loop
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub time: Duration,
+    pub input_temperature: ChainTemperature,
+    pub num_fans_running: usize,
+    /// Fan speed set after the decision
+    pub fan_speed: Option<fan::Speed>,
+    pub decision_explained: ControlDecisionExplained,
+    pub warnings: Vec<Warning>,
+}
+
+/// Result of the simulation
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub steps: Vec<Step>,
+}
+
+impl Timeline {
+    /// Simulated time when the miner has been shut down
+    pub fn shutdown(&self) -> Option<(Duration, &'static str)> {
+        self.steps
+            .last()
+            .filter(|step| step.decision_explained.decision == ControlDecision::Shutdown)
+            .map(|step| (step.time, step.decision_explained.reason))
+    }
+
+    /// The last step of the simulation
+    pub fn last(&self) -> &Step {
+        self.steps.last().expect("BUG: empty timeline")
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>7} {:>7} {:>5} {:>5}  decision",
+            "time", "temp", "fans", "speed"
+        )?;
+        for step in self.steps.iter() {
+            let temperature = match step.input_temperature {
+                ChainTemperature::Ok(temperature) => format!("{:.1}", temperature),
+                ChainTemperature::Unknown => "?".to_string(),
+                ChainTemperature::Failed => "FAILED".to_string(),
+            };
+            let fan_speed = step
+                .fan_speed
+                .map(|fan_speed| fan_speed.to_pwm().to_string())
+                .unwrap_or_else(|| "-".to_string());
+            writeln!(
+                f,
+                "{:>6}s {:>7} {:>5} {:>5}  {:?} ({})",
+                step.time.as_secs(),
+                temperature,
+                step.num_fans_running,
+                fan_speed,
+                step.decision_explained.decision,
+                step.decision_explained.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Simulation of `Monitor` with hashchains started at the beginning of the simulation
+pub struct Simulation {
+    config: Config,
+    trajectories: Vec<Trajectory>,
+    fan_feedback: FanFeedback,
+}
+
+impl Simulation {
+    pub fn new(config: Config, fan_feedback: FanFeedback) -> Self {
+        Self {
+            config,
+            trajectories: vec![],
+            fan_feedback,
+        }
+    }
+
+    /// Add hashchain with temperature following `trajectory`
+    pub fn with_chain(mut self, trajectory: Trajectory) -> Self {
+        self.trajectories.push(trajectory);
+        self
+    }
+
+    /// Run the control loop for `duration` of simulated time. The simulation ends prematurely
+    /// when the miner is shut down.
+    pub fn run(&mut self, duration: Duration) -> Timeline {
+        let start = Instant::now();
+        let mut pid = fan::pid::TempControl::new();
+        let mut rpm = fan::rpm::RpmControl::new();
+        let mut fan_guard = FanGuard::default();
+        let mut temperature_slopes = vec![TemperatureSlope::default(); self.trajectories.len()];
+        let mut fan_speed = None;
+        let mut steps = vec![];
+
+        let mut time = Duration::from_secs(0);
+        while time <= duration {
+            let now = start + time;
+            let warming_up = time <= WARM_UP_PERIOD;
+
+            let mut temperature_accumulator = TemperatureAccumulator::new();
+            let mut warnings = vec![];
+            for (hashboard_idx, (trajectory, temperature_slope)) in self
+                .trajectories
+                .iter_mut()
+                .zip(temperature_slopes.iter_mut())
+                .enumerate()
+            {
+                let chain_temperature = trajectory(time, fan_speed);
+                temperature_slope.add(now, chain_temperature);
+                if let (Some(slope), Some(thermal_runaway)) =
+                    (temperature_slope.get(), &self.config.thermal_runaway)
+                {
+                    if thermal_runaway.is_exceeded(slope, warming_up) {
+                        warnings.push(Warning::ThermalRunaway {
+                            hashboard_idx,
+                            slope,
+                        });
+                    }
+                }
+                temperature_accumulator.add_chain_temp(chain_temperature);
+            }
+            let input_temperature = temperature_accumulator.calc_result();
+            let num_fans_running = (self.fan_feedback)(time, fan_speed);
+
+            let mut decision_explained = ControlDecision::decide(
+                &self.config,
+                &mut fan_guard,
+                now,
+                num_fans_running,
+                input_temperature,
+            );
+            if let Some(thermal_runaway) = &self.config.thermal_runaway {
+                if !warnings.is_empty() {
+                    decision_explained = ControlDecision::decide_thermal_runaway(
+                        thermal_runaway,
+                        decision_explained,
+                    );
+                }
+            }
+            // RPM of individual fans is not simulated
+            let fan_feedback = fan::Feedback { rpm: vec![] };
+            if let Some(new_fan_speed) = decision_explained.decision.fan_speed(
+                &self.config,
+                &mut pid,
+                &mut rpm,
+                &fan_feedback,
+                warming_up,
+                now,
+            ) {
+                if fan_speed != Some(new_fan_speed) {
+                    fan_guard.speed_changed(now);
+                }
+                fan_speed = Some(new_fan_speed);
+            }
+
+            let shutdown = decision_explained.decision == ControlDecision::Shutdown;
+            steps.push(Step {
+                time,
+                input_temperature,
+                num_fans_running,
+                fan_speed,
+                decision_explained,
+                warnings,
+            });
+            if shutdown {
+                break;
+            }
+            time += TICK_LENGTH;
+        }
+        Timeline { steps }
+    }
+}
+
+/// First order thermal model of a hashchain. Temperature approaches equilibrium given by fan
+/// speed: `max_temp` with stopped fans and `ambient_temp` with fans at full speed.
+pub fn thermal_model(ambient_temp: f32, max_temp: f32, time_constant: Duration) -> Trajectory {
+    let mut temperature = ambient_temp;
+    let mut last_time = Duration::from_secs(0);
+    Box::new(move |time, fan_speed| {
+        let pwm = fan_speed.map_or(0, |fan_speed| fan_speed.to_pwm()) as f32 / 100.0;
+        let equilibrium = max_temp - (max_temp - ambient_temp) * pwm;
+        let dt = (time - last_time).as_secs_f32();
+        temperature += (equilibrium - temperature) * (dt / time_constant.as_secs_f32()).min(1.0);
+        last_time = time;
+        ChainTemperature::Ok(temperature)
+    })
+}
+
+fn temp_config() -> TempControlConfig {
+    TempControlConfig {
+        dangerous_temp: 100.0,
+        hot_temp: 90.0,
+    }
+}
+
+fn config(mode: FanControlMode, thermal_runaway: Option<ThermalRunawayConfig>) -> Config {
+    Config {
+        fan_config: Some(FanControlConfig { mode, min_fans: 1 }),
+        temp_config: Some(temp_config()),
+        fans_on_while_warming_up: true,
+        broken_chain_policy: BrokenChainPolicy::Shutdown,
+        thermal_runaway,
+    }
+}
+
+/// Temperature steady at 60 degrees rising by 10 degrees per minute after 5 minutes
+fn rising_temperature() -> Trajectory {
+    Box::new(|time, _| {
+        let time = time.as_secs_f32();
+        ChainTemperature::Ok(60.0 + (time - 300.0).max(0.0) / 6.0)
+    })
+}
+
+fn all_fans_running() -> FanFeedback {
+    Box::new(|_, _| 2)
+}
+
+/// PID controller keeps closed-loop model at target temperature
+#[test]
+fn test_simulation_pid() {
+    let mut simulation = Simulation::new(
+        config(FanControlMode::TargetTemperature(75.0), None),
+        all_fans_running(),
+    )
+    .with_chain(thermal_model(30.0, 90.0, Duration::from_secs(60)))
+    .with_chain(thermal_model(30.0, 85.0, Duration::from_secs(60)));
+    let timeline = simulation.run(Duration::from_secs(3600));
+    assert_eq!(timeline.shutdown(), None, "\n{}", timeline);
+    match timeline.last().input_temperature {
+        ChainTemperature::Ok(temperature) => assert!(
+            (temperature - 75.0).abs() < 3.0,
+            "temperature {} is far from target",
+            temperature
+        ),
+        temperature => panic!("unexpected temperature {:?}", temperature),
+    }
+    // fans are not let bellow warm up limit until the miner warms up
+    assert!(timeline
+        .steps
+        .iter()
+        .filter(|step| step.time <= WARM_UP_PERIOD)
+        .all(|step| step.fan_speed.map_or(false, |speed| speed.to_pwm() >= 60)));
+}
+
+/// Fans that stop spinning shut down the miner
+#[test]
+fn test_simulation_fan_failure() {
+    let mut simulation = Simulation::new(
+        config(FanControlMode::FixedSpeed(fan::Speed::new(70)), None),
+        Box::new(|time, _| {
+            if time < Duration::from_secs(300) {
+                2
+            } else {
+                0
+            }
+        }),
+    )
+    .with_chain(thermal_model(30.0, 90.0, Duration::from_secs(60)));
+    let timeline = simulation.run(Duration::from_secs(3600));
+
+    assert_eq!(
+        timeline.shutdown(),
+        Some((
+            Duration::from_secs(300) + TICK_LENGTH * (FAN_FAILURE_TICKS as u32 - 1),
+            "not enough fans"
+        ))
+    );
+}
+
+/// Temperature rising too fast shuts down the miner before reaching dangerous temperature
+#[test]
+fn test_simulation_thermal_runaway() {
+    let fixed_speed = FanControlMode::FixedSpeed(fan::Speed::FULL_SPEED);
+
+    // without detection the miner is shut down at dangerous temperature
+    let mut simulation = Simulation::new(config(fixed_speed.clone(), None), all_fans_running())
+        .with_chain(rising_temperature());
+    let timeline = simulation.run(Duration::from_secs(3600));
+    assert_eq!(
+        timeline.shutdown(),
+        Some((Duration::from_secs(540), "temperature above DANGEROUS"))
+    );
+
+    let mut simulation = Simulation::new(
+        config(
+            fixed_speed,
+            Some(ThermalRunawayConfig {
+                max_slope: 5.0,
+                action: ThermalRunawayAction::Shutdown,
+            }),
+        ),
+        all_fans_running(),
+    )
+    .with_chain(rising_temperature());
+    let timeline = simulation.run(Duration::from_secs(3600));
+    let (time, reason) = timeline
+        .shutdown()
+        .unwrap_or_else(|| panic!("miner has not been shut down\n{}", timeline));
+    assert!(time < Duration::from_secs(360));
+    assert_eq!(reason, "temperature rising too fast");
+    match timeline.last().warnings[..] {
+        [Warning::ThermalRunaway {
+            hashboard_idx: 0,
+            slope,
+        }] => assert!(slope > 5.0),
+        ref warnings => panic!("unexpected warnings {:?}", warnings),
+    }
+}
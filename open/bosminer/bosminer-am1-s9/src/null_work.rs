@@ -42,6 +42,7 @@ static NULL_JOB_CLIENT: Lazy<Arc<NullJobClient>> = Lazy::new(|| Arc::new(NullJob
 #[derive(Debug, Copy, Clone)]
 pub struct NullJob {
     hash: ii_bitcoin::DHash,
+    previous_hash: ii_bitcoin::DHash,
     time: u32,
     bits: u32,
     version: u32,
@@ -51,8 +52,10 @@ pub struct NullJob {
 impl NullJob {
     /// XXX: maybe create a structure with named members to pass to this constructor, otherwise it's confusing.
     pub fn new(time: u32, bits: u32, version: u32) -> Self {
+        let hash = ii_bitcoin::DHash::from_slice(&[0xffu8; 32]).unwrap();
         Self {
-            hash: ii_bitcoin::DHash::from_slice(&[0xffu8; 32]).unwrap(),
+            hash,
+            previous_hash: hash,
             time,
             bits,
             version,
@@ -63,6 +66,11 @@ impl NullJob {
     pub fn next(&mut self) {
         self.time += 1;
     }
+
+    /// Replace previous block hash of the job, merkle root is kept
+    pub fn set_previous_hash(&mut self, hash: ii_bitcoin::DHash) {
+        self.previous_hash = hash;
+    }
 }
 
 #[derive(Debug, ClientNode)]
@@ -114,7 +122,7 @@ impl job::Bitcoin for NullJob {
     }
 
     fn previous_hash(&self) -> &ii_bitcoin::DHash {
-        &self.hash
+        &self.previous_hash
     }
 
     fn merkle_root(&self) -> &ii_bitcoin::DHash {
@@ -154,9 +162,14 @@ pub fn prepare(i: u64) -> work::Assignment {
     work::Assignment::new(job, vec![mid], time)
 }
 
-pub fn prepare_opencore(enable_core: bool, midstate_count: usize) -> work::Assignment {
+/// * `seq_num` - position of the work in open-core sequence (used as ntime to make it unique)
+pub fn prepare_opencore(
+    enable_core: bool,
+    midstate_count: usize,
+    seq_num: u32,
+) -> work::Assignment {
     let bits = if enable_core { 0xffff_ffff } else { 0 };
-    let job = Arc::new(NullJob::new(0, bits, 0));
+    let job = Arc::new(NullJob::new(seq_num, bits, 0));
     let time = job.time();
 
     let one_midstate = work::Midstate {
@@ -0,0 +1,165 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Opening of chip cores after hash chain initialization and verification that all cores
+//! actually hash
+//!
+//! Cores are opened by a sequence of special works sent at elevated voltage. Cores that don't
+//! open (or are dead) never return any solution so the verification just waits long enough for
+//! every healthy core to find at least one nonce.
+
+use crate::bm1387;
+use crate::config;
+use crate::power;
+
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+/// Parameters of core opening procedure
+#[derive(Clone)]
+pub struct Config {
+    /// Number of open-core works sent to the hash chain (one per core by default)
+    pub count: usize,
+    /// Voltage used while the open-core works are being processed
+    pub voltage: power::Voltage,
+    /// Delay between consecutive open-core works
+    pub delay: Duration,
+    /// How long the open-core voltage is held after the last open-core work has been sent so
+    /// that chips process all of them before the voltage is lowered
+    pub hold: Duration,
+    /// Time after start when cores that haven't returned any solution are reported, `None`
+    /// disables the verification
+    pub check_time: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            count: bm1387::NUM_CORES_ON_CHIP,
+            voltage: *power::OPEN_CORE_VOLTAGE,
+            delay: Duration::from_secs_f64(config::DEFAULT_OPEN_CORE_DELAY_S),
+            hold: Duration::from_secs_f64(config::DEFAULT_OPEN_CORE_HOLD_S),
+            check_time: Some(Duration::from_secs_f64(config::DEFAULT_CORE_CHECK_TIME_S)),
+        }
+    }
+}
+
+/// Cores of one chip that never returned any solution
+#[derive(Debug, Clone, PartialEq)]
+pub struct SilentCores {
+    pub chip: usize,
+    pub cores: Vec<usize>,
+}
+
+impl SilentCores {
+    /// No core of the chip responds (the whole chip is probably dead)
+    pub fn whole_chip(&self) -> bool {
+        self.cores.len() == bm1387::NUM_CORES_ON_CHIP
+    }
+}
+
+/// Bitmap of cores that returned at least one solution
+#[derive(Debug)]
+pub struct Tracker {
+    responded: StdMutex<Vec<u128>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self {
+            responded: StdMutex::new(vec![]),
+        }
+    }
+
+    /// Forget all responses and track `chip_count` chips
+    pub fn reset(&self, chip_count: usize) {
+        let mut responded = self.responded.lock().expect("BUG: lock poisoned");
+        responded.clear();
+        responded.resize(chip_count, 0);
+    }
+
+    /// Account solution found by core at `addr`
+    pub fn account(&self, addr: bm1387::CoreAddress) {
+        let mut responded = self.responded.lock().expect("BUG: lock poisoned");
+        // nonces from non-existent chips are accounted in hash chain counters
+        if let Some(chip) = responded.get_mut(addr.chip) {
+            *chip |= 1 << addr.core;
+        }
+    }
+
+    /// List cores that haven't returned any solution yet
+    pub fn silent_cores(&self) -> Vec<SilentCores> {
+        self.responded
+            .lock()
+            .expect("BUG: lock poisoned")
+            .iter()
+            .enumerate()
+            .filter_map(|(chip, responded)| {
+                let cores: Vec<_> = (0..bm1387::NUM_CORES_ON_CHIP)
+                    .filter(|core| responded & (1 << core) == 0)
+                    .collect();
+                if cores.is_empty() {
+                    None
+                } else {
+                    Some(SilentCores { chip, cores })
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_silent_cores() {
+        let tracker = Tracker::new();
+        assert!(tracker.silent_cores().is_empty());
+
+        tracker.reset(2);
+        for core in 0..bm1387::NUM_CORES_ON_CHIP {
+            if core != 7 && core != 100 {
+                tracker.account(bm1387::CoreAddress { chip: 1, core });
+            }
+        }
+        // non-existent chip and core address out of core range are ignored
+        tracker.account(bm1387::CoreAddress { chip: 5, core: 1 });
+        tracker.account(bm1387::CoreAddress { chip: 1, core: 120 });
+
+        let silent = tracker.silent_cores();
+        assert_eq!(silent.len(), 2);
+        assert_eq!(silent[0].chip, 0);
+        assert!(silent[0].whole_chip());
+        assert_eq!(
+            silent[1],
+            SilentCores {
+                chip: 1,
+                cores: vec![7, 100]
+            }
+        );
+        assert!(!silent[1].whole_chip());
+
+        tracker.reset(1);
+        assert_eq!(tracker.silent_cores().len(), 1);
+    }
+}
@@ -29,14 +29,14 @@ use std::convert::TryInto;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::async_i2c::AsyncI2cDev;
+use crate::async_i2c::{AsyncI2cDev, AsyncRawBus};
 use crate::error::{self, ErrorKind};
 use crate::halt;
 
 use futures::lock::Mutex;
 use ii_async_compat::futures;
 use ii_async_compat::tokio;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 use once_cell::sync::Lazy;
 
@@ -165,7 +165,7 @@ impl std::cmp::PartialOrd for Voltage {
 /// S9 devices have a single I2C master that manages the voltage controllers on all hashboards.
 /// Therefore, this will be a single communication instance.
 pub struct I2cBackend {
-    inner: AsyncI2cDev,
+    inner: Box<dyn AsyncRawBus>,
 }
 
 impl I2cBackend {
@@ -182,10 +182,15 @@ impl I2cBackend {
     /// Instantiates a new I2C backend
     /// * `i2c_interface_num` - index of the I2C interface in Linux dev filesystem
     pub fn new(i2c_interface_num: usize) -> Self {
-        Self {
-            inner: AsyncI2cDev::open(format!("/dev/i2c-{}", i2c_interface_num))
+        Self::from_bus(Box::new(
+            AsyncI2cDev::open(format!("/dev/i2c-{}", i2c_interface_num))
                 .expect("I2C instantiation failed"),
-        }
+        ))
+    }
+
+    /// Instantiates a backend on top of arbitrary raw I2C bus (used mainly for testing)
+    pub(crate) fn from_bus(bus: Box<dyn AsyncRawBus>) -> Self {
+        Self { inner: bus }
     }
 
     /// Attempt to write a byte to power controller on I2C.
@@ -208,7 +213,7 @@ impl I2cBackend {
                 "I2C transaction on hashboard {} failed, retrying...",
                 hashboard_idx
             );
-            delay_for(Self::I2C_RETRY_DELAY).await;
+            sleep(Self::I2C_RETRY_DELAY).await;
         }
     }
 
@@ -423,6 +428,8 @@ pub struct Control {
     /// Information from PIC flash
     badcore_flash: Mutex<Option<FlashBadcore>>,
     freq_flash: Mutex<Option<FlashFreq>>,
+    /// Version of firmware running in the voltage controller
+    firmware_version: Mutex<Option<u8>>,
 }
 
 impl Control {
@@ -453,7 +460,7 @@ impl Control {
         let backend = self.backend.lock().await;
         backend.write(command, data).await?;
         // wait for delay while holding lock
-        delay_for(delay).await;
+        sleep(delay).await;
         Ok(())
     }
 
@@ -602,6 +609,16 @@ impl Control {
         self.write(ENABLE_VOLTAGE, &[false as u8]).await
     }
 
+    /// Turn off the voltage and forget the current voltage setting. The controller has to be
+    /// initialized again before it can be used.
+    pub async fn shutdown(&self) -> error::Result<()> {
+        let mut current_voltage = self.current_voltage.lock().await;
+        info!("Disabling voltage");
+        self.disable_voltage().await?;
+        current_voltage.take();
+        Ok(())
+    }
+
     pub async fn get_current_voltage(&self) -> Option<Voltage> {
         *self.current_voltage.lock().await
     }
@@ -625,6 +642,12 @@ impl Control {
         Ok(self.read(GET_VOLTAGE, 1).await?[0])
     }
 
+    /// Read back voltage the controller has been set to. Note that the controller doesn't measure
+    /// its output, so it's only the setpoint it has accepted.
+    pub async fn read_voltage(&self) -> error::Result<Voltage> {
+        Voltage::from_pic_value(self.get_voltage().await?)
+    }
+
     pub async fn send_heart_beat(&self) -> error::Result<()> {
         self.write(SEND_HEART_BEAT, &[]).await
     }
@@ -669,9 +692,15 @@ impl Control {
             current_voltage: Mutex::new(None),
             badcore_flash: Mutex::new(None),
             freq_flash: Mutex::new(None),
+            firmware_version: Mutex::new(None),
         }
     }
 
+    /// Version of firmware that has been read during initialization
+    pub async fn get_firmware_version(&self) -> Option<u8> {
+        *self.firmware_version.lock().await
+    }
+
     async fn reset_and_start_app(&self) -> error::Result<u8> {
         self.reset().await?;
         // Dump PIC flash. This can be done only before jumping to app.
@@ -683,7 +712,9 @@ impl Control {
             FlashFreq::parse(self.read_flash(FlashFreq::START, FlashFreq::LEN).await?);
 
         self.jump_from_loader_to_app().await?;
-        Ok(self.get_version().await?)
+        let version = self.get_version().await?;
+        self.firmware_version.lock().await.replace(version);
+        Ok(version)
     }
 
     /// Initialize voltage controller
@@ -720,6 +751,9 @@ impl Control {
     ///
     /// The reason is to notify the voltage controller that we are alive so that it wouldn't
     /// cut-off power supply to the hashing chips on the board.
+    ///
+    /// Note: voltage is not disabled when the heartbeat task is halted, the owner of this
+    /// controller is responsible for calling `shutdown` (see `HashChain::shutdown`).
     async fn start_heart_beat_task(self: Arc<Self>, halt_receiver: halt::Receiver) {
         // Start heartbeat thread in termination context
         let voltage_ctrl = self.clone();
@@ -732,28 +766,16 @@ impl Control {
                         .send_heart_beat()
                         .await
                         .expect("send_heart_beat failed");
-                    delay_for(VOLTAGE_CTRL_HEART_BEAT_PERIOD).await;
+                    sleep(VOLTAGE_CTRL_HEART_BEAT_PERIOD).await;
                 }
             });
-
-        // Make a termination handler that disables voltage when stopped
-        let voltage_ctrl = self.clone();
-        halt_receiver
-            .register_client("power heartbeat termination".into())
-            .await
-            .spawn_halt_handler(async move {
-                info!("Disabling voltage");
-                voltage_ctrl
-                    .disable_voltage()
-                    .await
-                    .expect("failed disabling voltage");
-            });
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::i2c::test_utils::FakePicBus;
 
     #[test]
     fn test_pic_address_words() {
@@ -800,4 +822,56 @@ mod test {
         // pic=-1
         assert!(Voltage::from_volts(9.443653453490631).is_err());
     }
+
+    /// Test that initialization enables voltage and `shutdown` disables it again
+    #[tokio::test]
+    async fn test_voltage_ctrl_init_shutdown() {
+        let bus = FakePicBus::new(
+            I2cBackend::get_i2c_address(1),
+            EXPECTED_VOLTAGE_CTRL_VERSION,
+        );
+        let backend = Arc::new(I2cBackend::from_bus(Box::new(bus.clone())));
+        let voltage_ctrl = Arc::new(Control::new(backend, 1));
+        let (halt_sender, halt_receiver) = halt::make_pair(Duration::from_secs(1));
+
+        voltage_ctrl
+            .clone()
+            .init(halt_receiver)
+            .await
+            .expect("voltage controller init failed");
+        assert!(bus.voltage_enabled());
+        assert!(bus.heart_beats() > 0);
+        assert_eq!(bus.voltage(), OPEN_CORE_VOLTAGE.as_pic_value());
+        assert!(voltage_ctrl.get_current_voltage().await.is_some());
+
+        // halting heartbeat alone must not touch the voltage
+        halt_sender.send_halt().await;
+        assert!(bus.voltage_enabled());
+
+        voltage_ctrl.shutdown().await.expect("shutdown failed");
+        assert!(!bus.voltage_enabled());
+        assert!(voltage_ctrl.get_current_voltage().await.is_none());
+    }
+
+    /// Test readback of voltage setpoint
+    #[tokio::test]
+    async fn test_voltage_ctrl_readback() {
+        let bus = FakePicBus::new(
+            I2cBackend::get_i2c_address(1),
+            EXPECTED_VOLTAGE_CTRL_VERSION,
+        );
+        let backend = Arc::new(I2cBackend::from_bus(Box::new(bus.clone())));
+        let voltage_ctrl = Control::new(backend, 1);
+
+        let voltage = Voltage::from_volts(8.9).unwrap();
+        voltage_ctrl
+            .set_voltage(voltage)
+            .await
+            .expect("set voltage failed");
+        let readback = voltage_ctrl
+            .read_voltage()
+            .await
+            .expect("voltage readback failed");
+        assert_eq!(readback.as_pic_value(), voltage.as_pic_value());
+    }
 }
@@ -22,10 +22,8 @@
 
 //! PIC firmware loader
 
-use crate::error::{self, ErrorKind};
+use crate::error::{self, ErrorKind, ResultExt};
 use crate::power::{PicAddress, PicWords};
-use failure::ResultExt;
-
 use std::convert::AsRef;
 use std::fs::File;
 use std::path::Path;
@@ -24,6 +24,9 @@ use crate::Solution;
 
 use bosminer::work;
 use std::iter::Iterator;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex as StdMutex, MutexGuard};
 
 /// Mining registry item contains work and solutions
 #[derive(Clone)]
@@ -34,9 +37,16 @@ pub struct WorkRegistryItem {
     solutions: std::vec::Vec<Solution>,
     /// Flag that work is only for initialization of the mining chips and any results coming from it should be ignored
     pub initial_work: bool,
+    /// Maximal number of most recent solutions kept for duplicate detection
+    duplicate_window: usize,
 }
 
 impl WorkRegistryItem {
+    #[inline]
+    pub fn work(&self) -> &work::Assignment {
+        &self.work
+    }
+
     /// Associates a specified solution with mining work, accounts for duplicates and nonce
     /// mismatches
     /// * `solution` - solution to be inserted
@@ -58,6 +68,10 @@ impl WorkRegistryItem {
             // hardware error detected == meets the target), it can be appended to the solution list
             // for this work item
             // TODO: call the evaluator for the solution
+            if self.solutions.len() >= self.duplicate_window {
+                // forget the oldest solution so that the window doesn't grow indefinitely
+                self.solutions.remove(0);
+            }
             self.solutions.push(new_solution.clone());
         } else {
             // now we now it's a duplicate, but we return it anyway
@@ -82,74 +96,315 @@ pub struct InsertSolutionStatus {
     pub unique_solution: Option<work::Solution>,
 }
 
+/// Statistics about solutions that couldn't be paired with any active work
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Solutions that arrived for work that has already been retired
+    pub stale_solutions: usize,
+    /// Solutions that arrived for `work_id` that has never been used (or has been cleared)
+    pub unknown_solutions: usize,
+    /// The longest observed delay of a stale solution expressed in number of work items stored
+    /// to the registry after its work has been retired (stale window length)
+    pub max_stale_age: usize,
+    /// Work items retired early because they became stale (e.g. after new block)
+    pub purged_works: usize,
+}
+
+/// Locked registry slot with active work. The slot stays locked (only this one, other slots
+/// are still accessible) until the guard is dropped so it must not be held across `await`.
+pub struct ItemGuard<'a>(MutexGuard<'a, Slot>);
+
+impl<'a> ItemGuard<'a> {
+    fn new(guard: MutexGuard<'a, Slot>) -> Option<Self> {
+        if guard.is_active() {
+            Some(Self(guard))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Deref for ItemGuard<'a> {
+    type Target = WorkRegistryItem;
+
+    fn deref(&self) -> &Self::Target {
+        match &*self.0 {
+            Slot::Active(item) => item,
+            _ => unreachable!("BUG: guarded slot is not active"),
+        }
+    }
+}
+
+impl<'a> DerefMut for ItemGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut *self.0 {
+            Slot::Active(item) => item,
+            _ => unreachable!("BUG: guarded slot is not active"),
+        }
+    }
+}
+
+/// Locked registry slot with active work which derefs directly to the work so that it can be
+/// serialized without cloning
+pub struct WorkGuard<'a>(ItemGuard<'a>);
+
+impl<'a> Deref for WorkGuard<'a> {
+    type Target = work::Assignment;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.work
+    }
+}
+
+/// Outcome of looking up work for a solution reported by hardware
+pub enum Lookup<'a> {
+    /// Work is still active and can be paired with the solution
+    Active(ItemGuard<'a>),
+    /// Work has been retired, `age` is the number of work items stored since its retirement
+    Retired { age: usize },
+    /// There's no record of work under this `work_id`
+    Unknown,
+}
+
+/// State of a single registry slot
+enum Slot {
+    Empty,
+    Active(WorkRegistryItem),
+    /// Work has been retired when `retired_at` work items have been stored to the registry
+    Retired {
+        retired_at: u64,
+    },
+}
+
+impl Slot {
+    fn is_active(&self) -> bool {
+        match self {
+            Self::Active(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Lock-free accumulator of `Stats`
+#[derive(Default)]
+struct AtomicStats {
+    stale_solutions: AtomicUsize,
+    unknown_solutions: AtomicUsize,
+    max_stale_age: AtomicUsize,
+    purged_works: AtomicUsize,
+}
+
+impl AtomicStats {
+    fn snapshot(&self) -> Stats {
+        Stats {
+            stale_solutions: self.stale_solutions.load(Ordering::Relaxed),
+            unknown_solutions: self.unknown_solutions.load(Ordering::Relaxed),
+            max_stale_age: self.max_stale_age.load(Ordering::Relaxed),
+            purged_works: self.purged_works.load(Ordering::Relaxed),
+        }
+    }
+}
+
 /// Simple work registry with `work_id` allocator
 ///
 /// Registry is responsible for associating `work` with `work_id` and managing
 /// this relation for the lifetime of the work.
 /// The `work_id` is allocated in circular fashion from the range `[0, registry_size - 1]`.
-/// The lifetime of work is set to `registry_size / 2` - after this much new work
-/// has been inserted after some particular work, the work is retired.
+/// The lifetime of work is set to `depth` (`registry_size / 2` by default) - after this much new
+/// work has been inserted after some particular work, the work is retired.
 ///
 /// The idea behind this registry is that we manage `registry_size` of slots and
 /// we assign work to them (under `work_id` we generate for each inserted work), but
-/// we always keep at least `registry_size / 2` slots free, so that we can detect
-/// stale work.
+/// we always keep `registry_size - depth` slots with retired work, so that we can detect
+/// (and account) stale solutions.
+///
+/// The registry is shared between work TX and solution RX tasks without any global lock. Slots
+/// are pre-allocated and each one of them is locked separately and the `work_id` allocator and
+/// statistics are atomic. Work submission touches only the slot of new work and the slot being
+/// retired while solution processing touches only the slot of the solution's work so the tasks
+/// don't contend unless a solution arrives for work which is just being retired.
 pub struct WorkRegistry {
     /// Number of elements in registry. Determines `work_id` range
     registry_size: usize,
-    /// Next id that is to be assigned to work, this increases modulo `registry_size`
-    next_work_id: usize,
+    /// Number of most recent work items that are kept active
+    depth: usize,
+    /// Number of most recent solutions of each work item kept for duplicate detection
+    duplicate_window: usize,
+    /// Total number of work items stored to the registry. The next `work_id` is derived from it
+    /// modulo `registry_size`
+    stored_count: AtomicU64,
     /// Current pending work list Each work item has a list of associated work solutions
-    pending_work_list: std::vec::Vec<Option<WorkRegistryItem>>,
+    pending_work_list: std::vec::Vec<StdMutex<Slot>>,
+    stats: AtomicStats,
 }
 
 impl WorkRegistry {
-    /// Create new registry with `registry_size` slots
+    /// Create new registry with `registry_size` slots where half of them is kept active
     pub fn new(registry_size: usize) -> Self {
+        Self::with_depth(registry_size, Self::default_depth(registry_size))
+    }
+
+    /// Create new registry with `registry_size` slots where the `depth` most recent work items
+    /// are kept active
+    pub fn with_depth(registry_size: usize, depth: usize) -> Self {
+        assert!(
+            depth > 0 && depth < registry_size,
+            "BUG: registry depth {} out of range for registry of size {}",
+            depth,
+            registry_size
+        );
         Self {
             registry_size,
-            next_work_id: 0,
-            pending_work_list: vec![None; registry_size],
+            depth,
+            duplicate_window: Self::DEFAULT_DUPLICATE_WINDOW,
+            stored_count: AtomicU64::new(0),
+            pending_work_list: (0..registry_size)
+                .map(|_| StdMutex::new(Slot::Empty))
+                .collect(),
+            stats: Default::default(),
         }
     }
 
-    /// Allocate next `work_id`. IDs are assigned in circular fashion.
-    /// This function is internal to the registry
-    fn alloc_next_work_id(&mut self) -> usize {
-        let work_id = self.next_work_id;
+    /// Solutions are kept for duplicate detection for the whole lifetime of work by default
+    pub const DEFAULT_DUPLICATE_WINDOW: usize = usize::MAX;
+
+    /// Set number of most recent solutions of each work item kept for duplicate detection. The
+    /// smaller the window, the less memory and time is spent on each solution but duplicates
+    /// of older solutions are not detected.
+    pub fn set_duplicate_window(&mut self, duplicate_window: usize) {
+        assert!(
+            duplicate_window > 0,
+            "BUG: duplicate window has to be greater than zero"
+        );
+        self.duplicate_window = duplicate_window;
+    }
 
-        // advance next work_id and wrap it manually
-        self.next_work_id = (work_id + 1) % self.registry_size;
+    pub fn duplicate_window(&self) -> usize {
+        self.duplicate_window
+    }
 
-        // return next work id
-        work_id
+    /// Default number of active work items for registry of `registry_size` slots
+    #[inline]
+    pub fn default_depth(registry_size: usize) -> usize {
+        registry_size / 2
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    #[inline]
+    fn lock_slot(&self, work_id: usize) -> MutexGuard<Slot> {
+        assert!(work_id < self.registry_size);
+        self.pending_work_list[work_id]
+            .lock()
+            .expect("BUG: registry slot lock poisoned")
+    }
+
+    /// Allocate next `work_id`. IDs are assigned in circular fashion.
+    /// This function is internal to the registry
+    /// Returns: new `work_id` and number of stored work items including the new one
+    fn alloc_next_work_id(&self) -> (usize, u64) {
+        let index = self.stored_count.fetch_add(1, Ordering::Relaxed);
+        ((index % self.registry_size as u64) as usize, index + 1)
     }
 
     /// Store new work to work registry and generate `work_id` for it
     /// As a side effect, retire stale work.
     /// Returns: new `work_id`
-    pub fn store_work(&mut self, work: work::Assignment, initial_work: bool) -> usize {
-        let work_id = self.alloc_next_work_id();
+    pub fn store_work(&self, work: work::Assignment, initial_work: bool) -> usize {
+        let (work_id, stored_count) = self.alloc_next_work_id();
 
-        // retire stale work
-        let retire_id = (work_id + self.registry_size / 2) % self.registry_size;
-        self.pending_work_list[retire_id] = None;
+        // retire work that has been stored `depth` items ago
+        let retire_id = (work_id + self.registry_size - self.depth) % self.registry_size;
+        {
+            let mut slot = self.lock_slot(retire_id);
+            if slot.is_active() {
+                *slot = Slot::Retired {
+                    retired_at: stored_count,
+                };
+            }
+        }
 
         // put new work into registry
-        self.pending_work_list[work_id] = Some(WorkRegistryItem {
+        *self.lock_slot(work_id) = Slot::Active(WorkRegistryItem {
             work,
             solutions: std::vec::Vec::new(),
             initial_work,
+            duplicate_window: self.duplicate_window,
         });
 
         // return assigned work id
         work_id
     }
 
+    /// Retire active work items matching `is_stale` before their time so that solutions for
+    /// them are dropped as stale. Work is searched from the most recent one and the search ends
+    /// at the first slot which isn't active (older work has already been retired). Initial work
+    /// is never purged.
+    /// Returns: number of purged work items
+    pub fn purge<F>(&self, is_stale: F) -> usize
+    where
+        F: Fn(&work::Assignment) -> bool,
+    {
+        let stored_count = self.stored_count.load(Ordering::Relaxed);
+        let first = stored_count.saturating_sub(self.depth as u64);
+        let mut purged = 0;
+        for index in (first..stored_count).rev() {
+            let mut slot = self.lock_slot((index % self.registry_size as u64) as usize);
+            let stale = match &*slot {
+                Slot::Active(item) => !item.initial_work && is_stale(&item.work),
+                _ => break,
+            };
+            if stale {
+                *slot = Slot::Retired {
+                    retired_at: stored_count,
+                };
+                purged += 1;
+            }
+        }
+        self.stats.purged_works.fetch_add(purged, Ordering::Relaxed);
+        purged
+    }
+
+    /// Get work stored under `work_id` so it can be serialized directly from the registry
+    /// without cloning it
+    pub fn get_work(&self, work_id: usize) -> Option<WorkGuard> {
+        self.find_work(work_id).map(WorkGuard)
+    }
+
     /// Look-up work id
-    pub fn find_work(&mut self, work_id: usize) -> &mut Option<WorkRegistryItem> {
-        assert!(work_id < self.registry_size);
-        &mut self.pending_work_list[work_id]
+    pub fn find_work(&self, work_id: usize) -> Option<ItemGuard> {
+        ItemGuard::new(self.lock_slot(work_id))
+    }
+
+    /// Look-up work for a solution with `work_id` and account solutions that cannot be paired
+    /// with active work
+    pub fn lookup_solution_work(&self, work_id: usize) -> Lookup {
+        let slot = self.lock_slot(work_id);
+        if slot.is_active() {
+            return Lookup::Active(ItemGuard(slot));
+        }
+        match &*slot {
+            Slot::Active(_) => unreachable!(),
+            Slot::Retired { retired_at } => {
+                let age = self
+                    .stored_count
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(*retired_at) as usize;
+                self.stats.stale_solutions.fetch_add(1, Ordering::Relaxed);
+                self.stats.max_stale_age.fetch_max(age, Ordering::Relaxed);
+                Lookup::Retired { age }
+            }
+            Slot::Empty => {
+                self.stats.unknown_solutions.fetch_add(1, Ordering::Relaxed);
+                Lookup::Unknown
+            }
+        }
     }
 }
 
@@ -161,7 +416,7 @@ mod test {
     /// Test that it's possible to store work
     #[test]
     fn test_store_work() {
-        let mut registry = WorkRegistry::new(4);
+        let registry = WorkRegistry::new(4);
         let work1 = null_work::prepare(0);
         let work2 = null_work::prepare(1);
 
@@ -177,7 +432,7 @@ mod test {
     fn test_store_work_retiring() {
         const REGISTRY_SIZE: usize = 8;
         const NUM_WORK_ITEMS: usize = REGISTRY_SIZE * 2 + REGISTRY_SIZE / 2 + 1;
-        let mut registry = WorkRegistry::new(REGISTRY_SIZE);
+        let registry = WorkRegistry::new(REGISTRY_SIZE);
 
         // we store more than REGISTRY_SIZE items so it has to roll over
         for i in 0..NUM_WORK_ITEMS {
@@ -189,7 +444,7 @@ mod test {
         let num_used_slots: usize = registry
             .pending_work_list
             .iter()
-            .map(|x| x.is_some() as usize)
+            .map(|x| x.lock().unwrap().is_active() as usize)
             .sum();
         assert_eq!(num_used_slots, REGISTRY_SIZE / 2);
 
@@ -203,7 +458,7 @@ mod test {
     #[test]
     fn test_work_id_wrap_around() {
         const REGISTRY_SIZE: usize = 4;
-        let mut registry = WorkRegistry::new(REGISTRY_SIZE);
+        let registry = WorkRegistry::new(REGISTRY_SIZE);
         let work = null_work::prepare(0);
         assert_eq!(registry.store_work(work.clone(), false), 0);
         assert_eq!(registry.store_work(work.clone(), false), 1);
@@ -215,7 +470,7 @@ mod test {
     /// Test that `initial_work` flag propagates to `WorkRegistryItem`
     #[test]
     fn test_initial_work() {
-        let mut registry = WorkRegistry::new(4);
+        let registry = WorkRegistry::new(4);
         let work1 = null_work::prepare(0);
         let work2 = null_work::prepare(0);
 
@@ -238,4 +493,146 @@ mod test {
             false
         );
     }
+
+    /// Test that custom depth determines number of active work items
+    #[test]
+    fn test_store_work_depth() {
+        const REGISTRY_SIZE: usize = 8;
+        const DEPTH: usize = 3;
+        let registry = WorkRegistry::with_depth(REGISTRY_SIZE, DEPTH);
+
+        for i in 0..REGISTRY_SIZE * 2 + 1 {
+            let work = null_work::prepare(i as u64);
+            assert_eq!(registry.store_work(work, false), i % REGISTRY_SIZE);
+        }
+        let num_used_slots: usize = registry
+            .pending_work_list
+            .iter()
+            .map(|x| x.lock().unwrap().is_active() as usize)
+            .sum();
+        assert_eq!(num_used_slots, DEPTH);
+        // the last stored work id is 0, so 1..=5 (rest of the window) has to be retired
+        assert!(registry.find_work(0).is_some());
+        assert!(registry.find_work(7).is_some());
+        assert!(registry.find_work(6).is_some());
+        assert!(registry.find_work(5).is_none());
+    }
+
+    /// Test that solutions for retired and unused work are accounted
+    #[test]
+    fn test_stale_solutions() {
+        let registry = WorkRegistry::new(8);
+        for i in 0..6 {
+            registry.store_work(null_work::prepare(i), false);
+        }
+        // work 0 and 1 got retired when work 4 and 5 were stored
+        assert!(match registry.lookup_solution_work(0) {
+            Lookup::Retired { age: 1 } => true,
+            _ => false,
+        });
+        assert!(match registry.lookup_solution_work(1) {
+            Lookup::Retired { age: 0 } => true,
+            _ => false,
+        });
+        assert!(match registry.lookup_solution_work(5) {
+            Lookup::Active(_) => true,
+            _ => false,
+        });
+        assert!(match registry.lookup_solution_work(7) {
+            Lookup::Unknown => true,
+            _ => false,
+        });
+        assert_eq!(
+            registry.stats(),
+            Stats {
+                stale_solutions: 2,
+                unknown_solutions: 1,
+                max_stale_age: 1,
+                purged_works: 0,
+            }
+        );
+    }
+
+    /// Test that stale work is retired early and initial work is kept
+    #[test]
+    fn test_purge() {
+        let registry = WorkRegistry::new(16);
+        registry.store_work(null_work::prepare_opencore(true, 1, 0), true);
+        for i in 1..8 {
+            registry.store_work(null_work::prepare_opencore(true, 1, i), false);
+        }
+        assert_eq!(registry.purge(|work| work.ntime < 5), 4);
+        assert!(registry.find_work(0).is_some());
+        for work_id in 1..5 {
+            assert!(match registry.lookup_solution_work(work_id) {
+                Lookup::Retired { age: 0 } => true,
+                _ => false,
+            });
+        }
+        for work_id in 5..8 {
+            assert!(registry.find_work(work_id).is_some());
+        }
+        // already purged work stops the search
+        registry.store_work(null_work::prepare_opencore(true, 1, 8), false);
+        assert_eq!(registry.purge(|_| true), 4);
+        assert!(registry.find_work(0).is_some());
+        assert_eq!(registry.stats().purged_works, 8);
+    }
+
+    /// Test that solutions can be looked up while work is being stored from another thread
+    #[test]
+    fn test_concurrent_store_and_lookup() {
+        const REGISTRY_SIZE: usize = 64;
+        const NUM_WORK_ITEMS: usize = REGISTRY_SIZE * 16;
+        let registry = std::sync::Arc::new(WorkRegistry::new(REGISTRY_SIZE));
+
+        let tx_registry = registry.clone();
+        let tx = std::thread::spawn(move || {
+            for i in 0..NUM_WORK_ITEMS {
+                let work_id = tx_registry.store_work(null_work::prepare(i as u64), false);
+                assert!(tx_registry.get_work(work_id).is_some());
+            }
+        });
+        let mut active = 0;
+        for i in 0..NUM_WORK_ITEMS {
+            if let Lookup::Active(_) = registry.lookup_solution_work(i % REGISTRY_SIZE) {
+                active += 1;
+            }
+        }
+        tx.join().expect("BUG: work TX thread failed");
+
+        let stats = registry.stats();
+        assert_eq!(
+            active + stats.stale_solutions + stats.unknown_solutions,
+            NUM_WORK_ITEMS
+        );
+        assert!(stats.max_stale_age < REGISTRY_SIZE);
+    }
+
+    fn prepare_solution(nonce: u32) -> Solution {
+        let hw_solution = crate::io::Solution {
+            nonce,
+            midstate_idx: 0,
+            solution_idx: 0,
+            hardware_id: 0,
+        };
+        Solution::from_hw_solution(&hw_solution, Default::default())
+    }
+
+    /// Test that duplicates are detected only within the duplicate window
+    #[test]
+    fn test_duplicate_window() {
+        let mut registry = WorkRegistry::new(4);
+        registry.set_duplicate_window(2);
+        let work_id = registry.store_work(null_work::prepare(0), false);
+        let mut work_item = registry.find_work(work_id).expect("work not found");
+
+        assert!(!work_item.insert_solution(prepare_solution(1)).duplicate);
+        assert!(!work_item.insert_solution(prepare_solution(2)).duplicate);
+        assert!(work_item.insert_solution(prepare_solution(1)).duplicate);
+        // the first solution falls out of the window
+        assert!(!work_item.insert_solution(prepare_solution(3)).duplicate);
+        assert!(!work_item.insert_solution(prepare_solution(1)).duplicate);
+        assert!(work_item.insert_solution(prepare_solution(3)).duplicate);
+    }
 }
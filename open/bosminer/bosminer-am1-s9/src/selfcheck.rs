@@ -0,0 +1,277 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Hardware self-check report assembled while the miner starts. It records what has been found
+//! on every hashboard (chips, sensors, firmware versions) and whether the fans spin, so that
+//! e.g. a hashboard which is not hashing can be diagnosed remotely. The report is stored as JSON
+//! and it is exposed via `selfcheck` CGMiner API command.
+
+use ii_logging::macros::*;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fans are checked after this time from the start of the miner so that they can spin up
+pub const FAN_CHECK_DELAY: Duration = Duration::from_secs(30);
+
+/// Outcome of the start of hashchain
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainStatus {
+    /// The hashchain is not started by the miner
+    Disabled,
+    /// The hashchain hasn't been started yet
+    Pending,
+    Ok,
+    Failed,
+}
+
+impl fmt::Display for ChainStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "Disabled"),
+            Self::Pending => write!(f, "Pending"),
+            Self::Ok => write!(f, "OK"),
+            Self::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// Self-check of one hashboard
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub hashboard_idx: usize,
+    /// Serial number of the hashboard when it is known
+    pub serial: Option<String>,
+    pub status: ChainStatus,
+    /// Number of chips found by the last enumeration
+    pub chips: Option<usize>,
+    pub expected_chips: Option<usize>,
+    /// Number of temperature sensors found
+    pub sensors: Option<usize>,
+    /// Chips that haven't responded to register readback during initialization
+    #[serde(default)]
+    pub unresponsive_chips: Vec<usize>,
+    /// Version of voltage controller firmware
+    pub pic_version: Option<u8>,
+    /// Version of FPGA bitstream
+    pub fpga_version: Option<String>,
+    /// Error of the last failed start
+    pub error: Option<String>,
+}
+
+impl Chain {
+    pub fn new(hashboard_idx: usize) -> Self {
+        Self {
+            hashboard_idx,
+            serial: None,
+            status: ChainStatus::Pending,
+            chips: None,
+            expected_chips: None,
+            sensors: None,
+            unresponsive_chips: vec![],
+            pic_version: None,
+            fpga_version: None,
+            error: None,
+        }
+    }
+
+    /// Problems found on the hashboard in human readable form
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.status == ChainStatus::Failed {
+            problems.push(format!(
+                "start failed: {}",
+                self.error.as_deref().unwrap_or("unknown error")
+            ));
+        }
+        if let (Some(chips), Some(expected_chips)) = (self.chips, self.expected_chips) {
+            if chips < expected_chips {
+                problems.push(format!("{}/{} chips found", chips, expected_chips));
+            }
+        }
+        if self.sensors == Some(0) {
+            problems.push("no temperature sensor found".to_string());
+        }
+        if !self.unresponsive_chips.is_empty() {
+            problems.push(format!("chips {:?} don't respond", self.unresponsive_chips));
+        }
+        problems
+    }
+}
+
+/// Self-check of the whole miner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Unix time in seconds of the last update
+    pub timestamp: u64,
+    /// All detected hashboards ordered by their index
+    pub chains: Vec<Chain>,
+    /// Speed of fans in RPM, `None` until fans have been checked
+    pub fan_rpm: Option<Vec<usize>>,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            timestamp: 0,
+            chains: vec![],
+            fan_rpm: None,
+        }
+    }
+
+    /// Number of fans that spin
+    pub fn running_fans(&self) -> usize {
+        self.fan_rpm
+            .iter()
+            .flatten()
+            .filter(|rpm| **rpm > 0)
+            .count()
+    }
+
+    pub fn store(&self, path: &str) -> io::Result<()> {
+        fs::write(path, format!("{}\n", serde_json::to_string(self)?))
+    }
+}
+
+/// Collects results of individual checks into the report and stores it after every update
+pub struct Tracker {
+    report: StdMutex<Report>,
+    /// Nothing is stored when the path is not set
+    path: Option<String>,
+}
+
+impl Tracker {
+    pub fn new(path: Option<String>) -> Self {
+        Self {
+            report: StdMutex::new(Report::new()),
+            path,
+        }
+    }
+
+    /// Snapshot of the current report
+    pub fn report(&self) -> Report {
+        self.report
+            .lock()
+            .expect("BUG: lock self-check report")
+            .clone()
+    }
+
+    fn update<F: FnOnce(&mut Report)>(&self, f: F) {
+        let report = {
+            let mut report = self.report.lock().expect("BUG: lock self-check report");
+            f(&mut report);
+            report.timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            report.clone()
+        };
+        if let Some(path) = self.path.as_ref() {
+            if let Err(e) = report.store(path) {
+                warn!("Cannot write self-check report '{}': {}", path, e);
+            }
+        }
+    }
+
+    /// Update self-check of hashboard with `hashboard_idx` (it's added when it's not present)
+    pub fn update_chain<F: FnOnce(&mut Chain)>(&self, hashboard_idx: usize, f: F) {
+        self.update(|report| {
+            let position = match report
+                .chains
+                .iter()
+                .position(|chain| chain.hashboard_idx >= hashboard_idx)
+            {
+                Some(position) if report.chains[position].hashboard_idx == hashboard_idx => {
+                    position
+                }
+                Some(position) => {
+                    report.chains.insert(position, Chain::new(hashboard_idx));
+                    position
+                }
+                None => {
+                    report.chains.push(Chain::new(hashboard_idx));
+                    report.chains.len() - 1
+                }
+            };
+            f(&mut report.chains[position]);
+        });
+    }
+
+    pub fn record_fans(&self, fan_rpm: Vec<usize>) {
+        if !fan_rpm.iter().any(|rpm| *rpm > 0) {
+            warn!("Self-check: no fan is running");
+        }
+        self.update(|report| report.fan_rpm = Some(fan_rpm));
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_update_chain() {
+        let tracker = Tracker::default();
+        tracker.update_chain(3, |chain| chain.status = ChainStatus::Disabled);
+        tracker.update_chain(1, |chain| {
+            chain.chips = Some(60);
+            chain.expected_chips = Some(63);
+        });
+        tracker.update_chain(3, |chain| chain.sensors = Some(0));
+        tracker.update_chain(1, |chain| chain.unresponsive_chips = vec![7]);
+        tracker.record_fans(vec![0, 4200]);
+
+        let report = tracker.report();
+        assert_eq!(
+            report
+                .chains
+                .iter()
+                .map(|chain| chain.hashboard_idx)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(report.chains[0].status, ChainStatus::Pending);
+        assert_eq!(report.chains[1].status, ChainStatus::Disabled);
+        assert_eq!(
+            report.chains[0].problems(),
+            vec!["60/63 chips found", "chips [7] don't respond"]
+        );
+        assert_eq!(
+            report.chains[1].problems(),
+            vec!["no temperature sensor found"]
+        );
+        assert_eq!(report.running_fans(), 1);
+        assert_ne!(report.timestamp, 0);
+    }
+}
@@ -36,6 +36,7 @@
 //! * Maybe provide a generic temperature readout structure that has just the `local` and `remote`
 //!   portions (and make a conversion function when needed).
 
+pub mod emulated;
 mod tmp42x;
 mod tmp451;
 
@@ -92,6 +93,23 @@ pub struct Temperature {
     pub remote: Measurement,
 }
 
+impl Temperature {
+    /// Pick one reading to represent a hashboard with multiple sensors: the first one
+    /// with valid remote temperature, then the first one with valid local temperature.
+    pub fn primary(temps: &[Temperature]) -> Temperature {
+        temps
+            .iter()
+            .find(|temp| matches!(temp.remote, Measurement::Ok(_)))
+            .or_else(|| {
+                temps
+                    .iter()
+                    .find(|temp| matches!(temp.local, Measurement::Ok(_)))
+            })
+            .cloned()
+            .unwrap_or(INVALID_TEMPERATURE_READING)
+    }
+}
+
 lazy_static! {
     /// List of all known I2C address where sensors are present
     static ref SENSOR_I2C_ADDRESS: [i2c::Address; 3] = [
@@ -185,6 +203,27 @@ mod test {
         result.is_some()
     }
 
+    #[test]
+    fn test_primary_temperature() {
+        let local_only = Temperature {
+            local: Measurement::Ok(40.0),
+            remote: Measurement::OpenCircuit,
+        };
+        let both = Temperature {
+            local: Measurement::Ok(45.0),
+            remote: Measurement::Ok(60.0),
+        };
+        assert_eq!(Temperature::primary(&[]), INVALID_TEMPERATURE_READING);
+        assert_eq!(
+            Temperature::primary(&[INVALID_TEMPERATURE_READING, local_only.clone()]),
+            local_only
+        );
+        assert_eq!(
+            Temperature::primary(&[local_only.clone(), both.clone()]),
+            both
+        );
+    }
+
     #[tokio::test]
     async fn inner_test_probe_i2c_sensors() {
         assert_eq!(test_probe_address(0x98, 0x55, 0x13).await, true);
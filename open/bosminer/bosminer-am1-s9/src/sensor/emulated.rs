@@ -0,0 +1,185 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Emulated sensor reporting temperature that follows configured trajectory. It's used on
+//! development boards without sensor chips and in tests of temperature control. Hashchain with
+//! a real sensor refuses the emulation so that the temperature control cannot be fooled.
+
+use crate::error;
+use crate::sensor::{self, Measurement, Temperature};
+
+use async_trait::async_trait;
+use std::boxed::Box;
+use std::time::{Duration, Instant};
+
+/// Local temperature of the sensor is lower than temperature of chips (remote temperature)
+const LOCAL_TEMP_OFFSET: f32 = 10.0;
+
+/// Maximal time of a trajectory point (one year)
+const TIME_S_MAX: f64 = 365.0 * 24.0 * 3600.0;
+
+/// Piecewise linear temperature over time since initialization of the sensor. Temperature
+/// before the first point and after the last point is constant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trajectory {
+    /// Time since initialization and temperature in degrees Celsius ordered by time
+    points: Vec<(Duration, f32)>,
+}
+
+impl Trajectory {
+    pub fn constant(temperature: f32) -> Self {
+        Self {
+            points: vec![(Duration::from_secs(0), temperature)],
+        }
+    }
+
+    /// Parse trajectory from points in format `SECONDS:CELSIUS` delimited by commas, e.g.
+    /// `0:40,300:75,600:110` heats up from 40 °C to 75 °C during the first five minutes and then
+    /// continues to 110 °C
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut points: Vec<(Duration, f32)> = vec![];
+        for point in spec.split(',').map(str::trim) {
+            let mut parts = point.splitn(2, ':');
+            let (time, temperature) = match (parts.next(), parts.next()) {
+                (Some(time), Some(temperature)) => (time.trim(), temperature.trim()),
+                _ => {
+                    return Err(format!(
+                        "point '{}' is not in format SECONDS:CELSIUS",
+                        point
+                    ))
+                }
+            };
+            let time = time
+                .parse::<f64>()
+                .ok()
+                .filter(|time| (0.0..=TIME_S_MAX).contains(time))
+                .ok_or_else(|| format!("invalid time '{}'", time))?;
+            let temperature = temperature
+                .parse::<f32>()
+                .ok()
+                .filter(|temperature| temperature.is_finite())
+                .ok_or_else(|| format!("invalid temperature '{}'", temperature))?;
+            let time = Duration::from_secs_f64(time);
+            if let Some((last_time, _)) = points.last() {
+                if time <= *last_time {
+                    return Err(format!("time of point '{}' is not increasing", point));
+                }
+            }
+            points.push((time, temperature));
+        }
+        Ok(Self { points })
+    }
+
+    /// Temperature at `elapsed` time since initialization
+    pub fn temperature(&self, elapsed: Duration) -> f32 {
+        let next = self
+            .points
+            .iter()
+            .position(|(time, _)| *time > elapsed)
+            .unwrap_or(self.points.len());
+        match (
+            next.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(next).copied(),
+        ) {
+            (Some((time0, temp0)), Some((time1, temp1))) => {
+                let ratio = (elapsed - time0).as_secs_f32() / (time1 - time0).as_secs_f32();
+                temp0 + (temp1 - temp0) * ratio
+            }
+            (Some((_, temperature)), None) | (None, Some((_, temperature))) => temperature,
+            (None, None) => unreachable!("BUG: empty trajectory"),
+        }
+    }
+}
+
+pub struct Emulated {
+    trajectory: Trajectory,
+    /// Time of initialization, the trajectory starts at this time
+    start: Instant,
+}
+
+impl Emulated {
+    pub fn new(trajectory: Trajectory) -> Box<dyn sensor::Sensor> {
+        Box::new(Self {
+            trajectory,
+            start: Instant::now(),
+        }) as Box<dyn sensor::Sensor>
+    }
+}
+
+#[async_trait]
+impl sensor::Sensor for Emulated {
+    async fn init(&mut self) -> error::Result<()> {
+        self.start = Instant::now();
+        Ok(())
+    }
+
+    async fn read_temperature(&mut self) -> error::Result<Temperature> {
+        let remote = self.trajectory.temperature(self.start.elapsed());
+        Ok(Temperature {
+            local: Measurement::Ok(remote - LOCAL_TEMP_OFFSET),
+            remote: Measurement::Ok(remote),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ii_async_compat::tokio;
+
+    #[test]
+    fn test_trajectory() {
+        let trajectory = Trajectory::parse("10:40, 70:100,100:70").expect("parse failed");
+        let temperature = |secs| trajectory.temperature(Duration::from_secs(secs));
+        assert_eq!(temperature(0), 40.0);
+        assert_eq!(temperature(10), 40.0);
+        assert_eq!(temperature(40), 70.0);
+        assert_eq!(temperature(70), 100.0);
+        assert_eq!(temperature(85), 85.0);
+        assert_eq!(temperature(1000), 70.0);
+
+        assert_eq!(
+            Trajectory::parse("0:55").unwrap(),
+            Trajectory::constant(55.0)
+        );
+        assert!(Trajectory::parse("").is_err());
+        assert!(Trajectory::parse("10:40,5:50").is_err());
+        assert!(Trajectory::parse("-1:40").is_err());
+        assert!(Trajectory::parse("1e30:40").is_err());
+        assert!(Trajectory::parse("inf:40").is_err());
+        assert!(Trajectory::parse("NaN:40").is_err());
+        assert!(Trajectory::parse("0:hot").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_emulated_sensor() {
+        let mut sensor = Emulated::new(Trajectory::constant(75.0));
+        sensor.init().await.expect("init failed");
+        assert_eq!(
+            sensor.read_temperature().await.expect("read failed"),
+            Temperature {
+                local: Measurement::Ok(65.0),
+                remote: Measurement::Ok(75.0),
+            }
+        );
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Structured exit codes of the miner and persistence of the reason of its last shutdown.
+//! The record is replaced with `Reason::Crash` when the miner starts, so when the miner
+//! terminates without having a chance to record anything (e.g. after a panic), the crash can be
+//! told apart from protective shutdowns after restart.
+
+use ii_logging::macros::*;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Message of the record stored for the currently running miner
+const RUNNING_MESSAGE: &str = "miner terminated unexpectedly";
+
+/// Reason why the miner has been shut down
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// Regular shutdown requested by user (e.g. with `SIGTERM`)
+    UserRequest,
+    /// Unexpected termination of the miner
+    Crash,
+    /// Configuration cannot be loaded or it is not valid
+    ConfigError,
+    /// Hashboards cannot be operated (e.g. a hashchain is broken)
+    HardwareFailure,
+    /// Protective shutdown due to overheating or failure of fans
+    ThermalShutdown,
+    /// Restart requested by a watchdog (e.g. no shares have been accepted for a long time)
+    Watchdog,
+}
+
+impl Reason {
+    /// Exit code of the process
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::UserRequest => 0,
+            Self::Crash => 1,
+            Self::ConfigError => 2,
+            Self::HardwareFailure => 3,
+            Self::ThermalShutdown => 4,
+            Self::Watchdog => 5,
+        }
+    }
+
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UserRequest => "user_request",
+            Self::Crash => "crash",
+            Self::ConfigError => "config_error",
+            Self::HardwareFailure => "hardware_failure",
+            Self::ThermalShutdown => "thermal_shutdown",
+            Self::Watchdog => "watchdog",
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UserRequest => write!(f, "user request"),
+            Self::Crash => write!(f, "crash"),
+            Self::ConfigError => write!(f, "configuration error"),
+            Self::HardwareFailure => write!(f, "hardware failure"),
+            Self::ThermalShutdown => write!(f, "thermal shutdown"),
+            Self::Watchdog => write!(f, "watchdog"),
+        }
+    }
+}
+
+/// Persistent record of the miner shutdown
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub reason: Reason,
+    pub message: String,
+    /// Unix time in seconds
+    pub timestamp: u64,
+}
+
+impl Record {
+    pub fn new(reason: Reason, message: String) -> Self {
+        Self {
+            reason,
+            message,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Load record from `path`. Nothing is returned when no record has been stored yet.
+    pub fn load(path: &str) -> io::Result<Option<Self>> {
+        let record = match fs::read_to_string(path) {
+            Ok(record) => record,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(serde_json::from_str(&record)?))
+    }
+
+    pub fn store(&self, path: &str) -> io::Result<()> {
+        fs::write(path, format!("{}\n", serde_json::to_string(self)?))
+    }
+
+    /// Store the record and report failure in the log only because there is nothing else to do
+    /// when the miner is about to exit
+    pub fn store_or_warn(&self, path: &str) {
+        if let Err(e) = self.store(path) {
+            warn!("Cannot write shutdown state '{}': {}", path, e);
+        }
+    }
+}
+
+/// Load record of the previous shutdown and mark the miner as running. The mark is interpreted
+/// as a crash when the miner does not record any other reason before it terminates.
+pub fn start(path: &str) -> Option<Record> {
+    let last_record = Record::load(path).unwrap_or_else(|e| {
+        warn!("Cannot read shutdown state '{}': {}", path, e);
+        None
+    });
+    Record::new(Reason::Crash, RUNNING_MESSAGE.to_string()).store_or_warn(path);
+    last_record
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let reasons = [
+            Reason::UserRequest,
+            Reason::Crash,
+            Reason::ConfigError,
+            Reason::HardwareFailure,
+            Reason::ThermalShutdown,
+            Reason::Watchdog,
+        ];
+        assert_eq!(Reason::UserRequest.exit_code(), 0);
+        for (i, reason) in reasons.iter().enumerate() {
+            // codes are used in the state file so they have to match the serialized form
+            assert_eq!(
+                serde_json::to_string(reason).unwrap(),
+                format!("\"{}\"", reason.code())
+            );
+            for other in reasons[i + 1..].iter() {
+                assert_ne!(reason.exit_code(), other.exit_code());
+            }
+        }
+    }
+
+    #[test]
+    fn test_start_marks_crash() {
+        let path = std::env::temp_dir().join(format!("bosminer-shutdown-{}", std::process::id()));
+        let path = path.to_str().expect("BUG: invalid temporary path");
+        let _ = fs::remove_file(path);
+
+        // the first start has no record
+        assert_eq!(start(path), None);
+
+        // the miner terminated without recording any reason
+        let last_record = start(path).expect("missing record");
+        assert_eq!(last_record.reason, Reason::Crash);
+
+        // protective shutdown recorded by the miner is reported after restart
+        let record = Record::new(Reason::ThermalShutdown, "too hot".to_string());
+        record.store(path).expect("store failed");
+        assert_eq!(start(path), Some(record));
+        assert_eq!(
+            Record::load(path).unwrap().map(|record| record.reason),
+            Some(Reason::Crash)
+        );
+
+        fs::remove_file(path).expect("remove failed");
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Supervision of long running tasks. A task that terminates (or panics) while its termination
+//! context hasn't been halted is handled according to its `Policy` instead of leaving the
+//! hashchain silently idle.
+
+use ii_logging::macros::*;
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use crate::halt;
+use crate::shutdown;
+
+use futures::channel::mpsc;
+use ii_async_compat::futures;
+use ii_async_compat::prelude::*;
+
+/// Delay before the task is spawned again
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+
+/// Maximal number of restarts of one hashchain requested by supervisors within
+/// `CHAIN_RESTART_WINDOW`. The failure is escalated when the budget is exhausted.
+const MAX_CHAIN_RESTARTS: usize = 3;
+const CHAIN_RESTART_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// What to do when a supervised task terminates unexpectedly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Spawn the task again at most `max_restarts` times, then restart the hashchain
+    RestartTask { max_restarts: usize },
+    /// Restart the hashchain the task belongs to (used for tasks owning resources that cannot
+    /// be recreated, e.g. FIFOs). The failure is escalated when the hashchain has been restarted
+    /// too many times recently.
+    RestartChain,
+    /// Shut down the whole miner
+    Escalate,
+}
+
+/// Limits restarts of hashchains so that a task failing deterministically (e.g. panicking on
+/// the same data) does not restart its hashchain forever
+#[derive(Debug)]
+pub struct RestartBudget {
+    max_restarts: usize,
+    window: Duration,
+    /// Times of recent restarts of each hashchain
+    restarts: StdMutex<HashMap<usize, VecDeque<Instant>>>,
+}
+
+impl RestartBudget {
+    pub fn new(max_restarts: usize, window: Duration) -> Self {
+        Self {
+            max_restarts,
+            window,
+            restarts: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Account restart of hashchain `hashboard_idx` at `now`. Returns `false` when the
+    /// hashchain has been restarted `max_restarts` times within the window.
+    pub fn try_restart(&self, hashboard_idx: usize, now: Instant) -> bool {
+        let mut restarts = self.restarts.lock().expect("BUG: lock restart budget");
+        let restarts = restarts.entry(hashboard_idx).or_default();
+        while let Some(&restarted) = restarts.front() {
+            if now.saturating_duration_since(restarted) < self.window {
+                break;
+            }
+            restarts.pop_front();
+        }
+        if restarts.len() >= self.max_restarts {
+            return false;
+        }
+        restarts.push_back(now);
+        true
+    }
+}
+
+impl Default for RestartBudget {
+    fn default() -> Self {
+        Self::new(MAX_CHAIN_RESTARTS, CHAIN_RESTART_WINDOW)
+    }
+}
+
+/// Handlers of failures that cannot be resolved by restarting the task itself
+#[derive(Clone, Default)]
+pub struct Escalation {
+    /// Channel accepting indexes of hashchains that should be restarted
+    pub chain_restart: Option<mpsc::UnboundedSender<usize>>,
+    /// Budget of hashchain restarts shared by all supervisors
+    pub chain_restart_budget: Arc<RestartBudget>,
+    /// Halt sender used to shut down the whole miner
+    pub miner_shutdown: Option<Arc<halt::Sender>>,
+}
+
+/// Spawns tasks in termination context and watches their termination
+#[derive(Clone)]
+pub struct Supervisor {
+    halt_receiver: halt::Receiver,
+    /// Hashchain the supervised tasks belong to
+    hashboard_idx: Option<usize>,
+    escalation: Escalation,
+}
+
+impl Supervisor {
+    pub fn new(
+        halt_receiver: halt::Receiver,
+        hashboard_idx: Option<usize>,
+        escalation: Escalation,
+    ) -> Self {
+        Self {
+            halt_receiver,
+            hashboard_idx,
+            escalation,
+        }
+    }
+
+    /// Spawn task created by `make_task` in the termination context of the supervisor. The task
+    /// is dropped when the context is halted, any other termination is handled with `policy`.
+    pub async fn spawn<F, T>(&self, name: String, policy: Policy, mut make_task: F)
+    where
+        F: FnMut() -> T + Send + 'static,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        self.halt_receiver
+            .register_client(name.clone())
+            .await
+            .spawn(async move {
+                let mut restarts = 0;
+                loop {
+                    let reason = match ii_async_compat::catch_panic(make_task()).await {
+                        Ok(()) => "task exited".to_string(),
+                        Err(payload) => format!("task panicked: {}", panic_message(&*payload)),
+                    };
+                    // the task has terminated because it's being halted
+                    if supervisor.halt_receiver.is_halted() {
+                        return;
+                    }
+                    error!("Supervisor: '{}' terminated unexpectedly: {}", name, reason);
+
+                    match policy {
+                        Policy::RestartTask { max_restarts } if restarts < max_restarts => {
+                            restarts += 1;
+                            warn!(
+                                "Supervisor: restarting '{}' (attempt {}/{})",
+                                name, restarts, max_restarts
+                            );
+                            if supervisor.halt_receiver.sleep(RESTART_DELAY).await.is_err() {
+                                return;
+                            }
+                        }
+                        Policy::RestartTask { .. } | Policy::RestartChain => {
+                            supervisor.restart_chain(&name, reason).await;
+                            return;
+                        }
+                        Policy::Escalate => {
+                            supervisor.escalate(&name, reason).await;
+                            return;
+                        }
+                    }
+                }
+            });
+    }
+
+    async fn restart_chain(&self, name: &str, reason: String) {
+        if let (Some(hashboard_idx), Some(chain_restart)) =
+            (self.hashboard_idx, self.escalation.chain_restart.as_ref())
+        {
+            if !self
+                .escalation
+                .chain_restart_budget
+                .try_restart(hashboard_idx, Instant::now())
+            {
+                error!(
+                    "Supervisor: chain {} has been restarted too many times due to '{}'",
+                    hashboard_idx, name
+                );
+                self.escalate(name, reason).await;
+                return;
+            }
+            warn!(
+                "Supervisor: restarting chain {} due to '{}'",
+                hashboard_idx, name
+            );
+            if chain_restart.unbounded_send(hashboard_idx).is_ok() {
+                return;
+            }
+        }
+        // there's no one to restart the hashchain
+        self.escalate(name, reason).await;
+    }
+
+    async fn escalate(&self, name: &str, reason: String) {
+        match self.escalation.miner_shutdown.as_ref() {
+            Some(miner_shutdown) => {
+                miner_shutdown
+                    .clone()
+                    .send_shutdown(
+                        shutdown::Reason::HardwareFailure,
+                        format!("supervisor: '{}' {}", name, reason),
+                    )
+                    .await
+            }
+            None => error!("Supervisor: failure of '{}' cannot be escalated", name),
+        }
+    }
+}
+
+/// Extract message from panic payload
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_restart_policy() {
+        let (halt_sender, halt_receiver) = halt::make_pair(Duration::from_secs(1));
+        let (chain_restart_sender, mut chain_restart_receiver) = mpsc::unbounded();
+        let supervisor = Supervisor::new(
+            halt_receiver,
+            Some(3),
+            Escalation {
+                chain_restart: Some(chain_restart_sender),
+                chain_restart_budget: Arc::new(RestartBudget::new(1, Duration::from_secs(3600))),
+                miner_shutdown: None,
+            },
+        );
+
+        // failing task is restarted and then the whole hashchain is restarted
+        let runs = Arc::new(AtomicUsize::new(0));
+        let task_runs = runs.clone();
+        supervisor
+            .spawn(
+                "failing".into(),
+                Policy::RestartTask { max_restarts: 2 },
+                move || {
+                    task_runs.fetch_add(1, Ordering::Relaxed);
+                    async {
+                        if true {
+                            panic!("task failure");
+                        }
+                    }
+                },
+            )
+            .await;
+        let hashboard_idx = chain_restart_receiver
+            .next()
+            .timeout(Duration::from_secs(5))
+            .await
+            .expect("chain restart not requested");
+        assert_eq!(hashboard_idx, Some(3));
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+
+        // the restart budget of the hashchain is exhausted so the failure is escalated instead
+        supervisor
+            .spawn("failing again".into(), Policy::RestartChain, || async {
+                if true {
+                    panic!("task failure");
+                }
+            })
+            .await;
+        assert!(chain_restart_receiver
+            .next()
+            .timeout(Duration::from_millis(100))
+            .await
+            .is_err());
+
+        // task terminated due to halt is not handled
+        let halt_receiver = supervisor.halt_receiver.clone();
+        supervisor
+            .spawn("halted".into(), Policy::RestartChain, move || {
+                let halt_receiver = halt_receiver.clone();
+                async move {
+                    let _ = halt_receiver.sleep(Duration::from_secs(100)).await;
+                }
+            })
+            .await;
+        halt_sender.send_halt().await;
+        drop(supervisor);
+        assert_eq!(chain_restart_receiver.next().await, None);
+    }
+
+    #[test]
+    fn test_restart_budget() {
+        let budget = RestartBudget::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(budget.try_restart(0, now));
+        assert!(budget.try_restart(0, now + Duration::from_secs(10)));
+        assert!(!budget.try_restart(0, now + Duration::from_secs(20)));
+        // budget is kept for each hashchain
+        assert!(budget.try_restart(1, now + Duration::from_secs(20)));
+        // old restarts are forgotten
+        assert!(budget.try_restart(0, now + Duration::from_secs(60)));
+        assert!(!budget.try_restart(0, now + Duration::from_secs(65)));
+    }
+}
@@ -107,3 +107,87 @@ fn test_work_time_computation() {
         36296
     );
 }
+
+#[test]
+fn test_frequency_step_towards() {
+    let current = FrequencySettings {
+        chip: vec![600_000_000, 650_000_000, 500_000_000],
+    };
+    let mut target = FrequencySettings::from_frequency(550_000_000);
+    target.set_chip_count(3);
+
+    let step = current.step_towards(&target, 40_000_000);
+    assert_eq!(step.chip, vec![560_000_000, 610_000_000, 540_000_000]);
+    assert!(!step.reached(&target));
+
+    let step = step.step_towards(&target, 40_000_000);
+    assert_eq!(step.chip, vec![550_000_000, 570_000_000, 550_000_000]);
+
+    let step = step.step_towards(&target, 40_000_000);
+    assert!(step.reached(&target));
+}
+
+#[test]
+fn test_frequency_lowered_by() {
+    let current = FrequencySettings {
+        chip: vec![600_000_000, 650_000_000, 500_000_000],
+    };
+    let lowered = current.lowered_by(25_000_000, 480_000_000);
+    assert_eq!(lowered.chip, vec![575_000_000, 625_000_000, 480_000_000]);
+    let lowered = lowered.lowered_by(200_000_000, 480_000_000);
+    assert_eq!(lowered.chip, vec![480_000_000; 3]);
+}
+
+/// Run chip enumeration on emulated chain
+async fn enumerate_mock_chain(chain: &io::mock::Chain) -> error::Result<usize> {
+    let (command_io, _, _) = chain.split(0, MidstateCount::new(1));
+    HashChain::enumerate_chain(&command::Context::new(command_io)).await
+}
+
+#[tokio::test]
+async fn test_enumerate_chain() {
+    let chain = io::mock::Chain::new(MAX_CHIPS_ON_CHAIN - 1);
+    assert_eq!(
+        enumerate_mock_chain(&chain)
+            .await
+            .expect("enumeration failed"),
+        MAX_CHIPS_ON_CHAIN - 1
+    );
+    for i in 0..MAX_CHIPS_ON_CHAIN - 1 {
+        assert_eq!(chain.chip_address(i), Some((i * 4) as u8));
+    }
+
+    // chip that doesn't respond is left out
+    let chain = io::mock::Chain::new(10);
+    chain.set_responding(3, false);
+    assert_eq!(
+        enumerate_mock_chain(&chain)
+            .await
+            .expect("enumeration failed"),
+        9
+    );
+    assert_eq!(chain.chip_address(3), None);
+    assert_eq!(chain.chip_address(4), Some(3 * 4));
+}
+
+#[tokio::test]
+async fn test_enumerate_chain_failure() {
+    // no chips
+    assert!(enumerate_mock_chain(&io::mock::Chain::new(0))
+        .await
+        .is_err());
+    // too many chips
+    assert!(
+        enumerate_mock_chain(&io::mock::Chain::new(MAX_CHIPS_ON_CHAIN))
+            .await
+            .is_err()
+    );
+    // unexpected chip revision
+    let chain = io::mock::Chain::new(10);
+    chain.set_chip_rev(5, 0x1385);
+    assert!(enumerate_mock_chain(&chain).await.is_err());
+    // command FIFO stalled
+    let chain = io::mock::Chain::new(10);
+    chain.set_stalled(true);
+    assert!(enumerate_mock_chain(&chain).await.is_err());
+}
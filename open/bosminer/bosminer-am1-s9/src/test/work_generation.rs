@@ -24,7 +24,7 @@ use ii_logging::macros::*;
 
 use super::*;
 use crate::bm1387::MidstateCount;
-use crate::fan;
+use crate::fan::{self, Driver as _};
 use crate::{FrequencySettings, HashChain, Solution};
 
 use bosminer::work;
@@ -37,7 +37,7 @@ use futures::channel::mpsc;
 use futures::stream::StreamExt;
 
 use ii_async_compat::{tokio, FutureExt};
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 const ASIC_DIFFICULTY: usize = 1;
 
@@ -79,7 +79,7 @@ async fn sender_task(
     mut work_receiver: mpsc::UnboundedReceiver<work::Assignment>,
 ) {
     let mut tx_io = hash_chain.take_work_tx_io().await;
-    let mut work_registry = registry::WorkRegistry::new(tx_io.work_id_count());
+    let work_registry = registry::WorkRegistry::new(tx_io.work_id_count());
 
     loop {
         tx_io.wait_for_room().await.expect("wait for tx room");
@@ -90,6 +90,18 @@ async fn sender_task(
     }
 }
 
+/// CPU time (user and system) consumed by this process in clock ticks
+fn process_cpu_ticks() -> u64 {
+    let stat = std::fs::read_to_string("/proc/self/stat").expect("failed reading process stat");
+    // skip PID and command name which may contain spaces, `utime` and `stime` are then the 12th
+    // and 13th field
+    let fields: Vec<_> = stat[stat.rfind(')').expect("BUG: invalid process stat") + 1..]
+        .split_whitespace()
+        .collect();
+    let parse = |field: &str| field.parse::<u64>().expect("BUG: invalid process stat");
+    parse(fields[11]) + parse(fields[12])
+}
+
 async fn send_and_receive_test_workloads<'a>(
     work_sender: &'a mpsc::UnboundedSender<work::Assignment>,
     solution_receiver: &'a mut mpsc::UnboundedReceiver<Solution>,
@@ -109,7 +121,7 @@ async fn send_and_receive_test_workloads<'a>(
         // TODO: come up with a formula instead of fixed time interval
         // wait = work_time * number_of_chips + time_to_send_out_a_jov
 
-        delay_for(Duration::from_millis(100)).await;
+        sleep(Duration::from_millis(100)).await;
     }
     let mut returned_solution_count = 0;
     while let Ok(res) = solution_receiver
@@ -213,3 +225,54 @@ async fn test_work_generation() {
     // stop everything
     hash_chain.halt_sender.clone().send_halt().await;
 }
+
+/// Benchmark of CPU time spent on submission of work one by one and in batches
+#[tokio::test]
+async fn bench_work_tx_batch() {
+    const WORK_COUNT: usize = 5000;
+
+    let (monitor_sender, _monitor_receiver) = mpsc::unbounded();
+    let hash_chain = Arc::new(start_hchain(monitor_sender).await);
+    let mut tx_io = hash_chain.take_work_tx_io().await;
+    let work_registry = registry::WorkRegistry::new(tx_io.work_id_count());
+    let work = prepare_test_work(1);
+
+    let start = process_cpu_ticks();
+    for _ in 0..WORK_COUNT {
+        tx_io.wait_for_room().await.expect("wait for tx room");
+        let work_id = work_registry.store_work(work.clone(), false);
+        tx_io.send_work(&work, work_id).expect("send work");
+    }
+    let single_ticks = process_cpu_ticks() - start;
+
+    let batch_size = tx_io.batch_size();
+    let mut work_ids = Vec::with_capacity(batch_size);
+    let start = process_cpu_ticks();
+    for _ in 0..WORK_COUNT / batch_size {
+        tx_io.wait_for_room().await.expect("wait for tx room");
+        for _ in 0..batch_size {
+            work_ids.push(work_registry.store_work(work.clone(), false));
+        }
+        tx_io.send_work_batch(work_ids.drain(..).map(|work_id| {
+            (
+                work_registry.get_work(work_id).expect("work not stored"),
+                work_id,
+            )
+        }));
+    }
+    let batch_ticks = process_cpu_ticks() - start;
+
+    info!(
+        "Sending {} works took {} CPU ticks one by one and {} CPU ticks in batches of {}",
+        WORK_COUNT, single_ticks, batch_ticks, batch_size
+    );
+    assert!(
+        batch_ticks <= single_ticks,
+        "batched submission is not cheaper ({} > {} CPU ticks)",
+        batch_ticks,
+        single_ticks
+    );
+
+    // stop everything
+    hash_chain.halt_sender.clone().send_halt().await;
+}
@@ -21,7 +21,7 @@
 // contact us at opensource@braiins.com.
 
 use ii_async_compat::tokio;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 use bosminer_am1_s9::gpio;
 use bosminer_am1_s9::power;
@@ -45,7 +45,7 @@ async fn test_voltage_ctrl_on_1_hashboard(
 
     // perform reset of the hashboard
     reset_pin.enter_reset().unwrap();
-    delay_for(Duration::from_secs(1)).await;
+    sleep(Duration::from_secs(1)).await;
     reset_pin.exit_reset().unwrap();
 
     let backend = Arc::new(power::I2cBackend::new(0));
@@ -0,0 +1,52 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Description of Antminer hardware models. Backends implement `Model` for each supported model
+//! and the shared infrastructure is then parametrized by it.
+
+use crate::gpio;
+
+pub trait Model: Send + Sync + 'static {
+    /// Name of the hardware model (reported as hardware revision)
+    const NAME: &'static str;
+
+    /// Range of hash chain indices (connectors on the control board)
+    const HASH_CHAIN_INDEX_MIN: usize;
+    const HASH_CHAIN_INDEX_MAX: usize;
+
+    /// Number of chips expected on fully working hash chain
+    const CHIPS_ON_CHAIN: usize;
+
+    /// Number of fan connectors on the control board
+    const FAN_COUNT: usize;
+
+    /// GPIO number of a control board output pin
+    fn pin_out(name: gpio::PinOutName) -> u64;
+
+    /// GPIO number of a control board input pin
+    fn pin_in(name: gpio::PinInName) -> u64;
+
+    /// Check whether hash chain `idx` exists on this model
+    fn is_hash_chain_index(idx: usize) -> bool {
+        idx >= Self::HASH_CHAIN_INDEX_MIN && idx <= Self::HASH_CHAIN_INDEX_MAX
+    }
+}
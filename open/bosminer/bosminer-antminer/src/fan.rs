@@ -0,0 +1,104 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Fan speed representation and control algorithms shared by Antminer backends. Access to the
+//! actual fan controller is provided by the backend through `Driver` trait.
+
+pub mod pid;
+pub mod rpm;
+
+/// Structure representing PWM of fan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed(usize);
+
+impl Speed {
+    pub const FULL_SPEED: Self = Self(100);
+    pub const STOPPED: Self = Self(0);
+
+    pub fn new(speed: usize) -> Self {
+        assert!(speed <= 100);
+
+        Speed(speed)
+    }
+
+    pub fn to_pwm(&self) -> usize {
+        self.0
+    }
+}
+
+/// Speed of fans read from feedback pins
+#[derive(Debug, Clone)]
+pub struct Feedback {
+    pub rpm: Vec<usize>,
+}
+
+impl Feedback {
+    pub fn num_fans_running(&self) -> usize {
+        self.rpm.iter().filter(|rpm| **rpm > 0).count()
+    }
+}
+
+/// Fan controller of specific control board
+pub trait Driver: Send + Sync {
+    /// Read feedback of all fans
+    fn read_feedback(&self) -> Feedback;
+
+    /// Set PWM for fans in percent (0 means fans stopped, 100 means fans on full)
+    fn set_speed(&self, speed: Speed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fan_speed() {
+        assert_eq!(Speed::STOPPED.0, 0);
+        assert_eq!(Speed::FULL_SPEED.0, 100);
+        assert_eq!(Speed::new(70).0, 70);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fan_speed_fail() {
+        Speed::new(101);
+    }
+
+    #[test]
+    fn test_feedback_fan_count() {
+        assert_eq!(
+            Feedback {
+                rpm: vec![50, 0, 11, 0, 0]
+            }
+            .num_fans_running(),
+            2
+        );
+        assert_eq!(
+            Feedback {
+                rpm: vec![0, 0, 0, 0, 0]
+            }
+            .num_fans_running(),
+            0
+        );
+        assert_eq!(Feedback { rpm: Vec::new() }.num_fans_running(), 0);
+    }
+}
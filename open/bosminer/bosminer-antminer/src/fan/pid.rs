@@ -22,7 +22,7 @@
 
 //! Implementation of fan control using PID
 
-mod offset_pid;
+pub(super) mod offset_pid;
 
 use super::Speed;
 use offset_pid::OffsetPIDController;
@@ -63,10 +63,18 @@ impl TempControl {
     }
 
     pub fn update(&mut self, temperature: f64) -> Speed {
-        let pwm = self
-            .pid
-            .update(temperature, self.last_update.elapsed().as_secs_f64());
-        self.last_update = Instant::now();
+        self.update_at(temperature, Instant::now())
+    }
+
+    /// Update controller with `temperature` measured at `now` (allows to run the controller
+    /// in simulated time)
+    pub fn update_at(&mut self, temperature: f64, now: Instant) -> Speed {
+        let pwm = self.pid.update(
+            temperature,
+            now.saturating_duration_since(self.last_update)
+                .as_secs_f64(),
+        );
+        self.last_update = now;
         Speed::new(pwm as usize)
     }
 }
@@ -0,0 +1,297 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Closed-loop control of fan speed with RPM feedback as input
+//!
+//! Some fans do not respond well to the whole PWM range (they stop under some duty cycle or their
+//! speed is far from linear) but report their RPM reliably. The controller adjusts common PWM
+//! of all fans until the slowest controlled fan reaches its target RPM.
+//!
+//! Fans which have never reported any RPM are considered not connected (the control board has
+//! more fan connectors than there are fans in the miner) so they are not controlled. Missing fans
+//! are detected by the monitor according to the minimal number of running fans.
+
+use super::pid::offset_pid::OffsetPIDController;
+use super::{Feedback, Speed};
+
+use ii_logging::macros::*;
+
+use pid_control::Controller;
+use std::time::Instant;
+
+/// Fan running under this percentage of its target RPM while being driven is considered stalled
+const STALL_THRESHOLD: f64 = 10.0;
+/// Number of consecutive updates with a stalled fan after which all fans are run at full speed
+const STALL_UPDATES: usize = 3;
+/// Margin added to PWM at which a fan stalled to prevent it from stalling again
+const STALL_PWM_MARGIN: f64 = 10.0;
+/// Lowest PWM that the controller is allowed to set
+const MIN_PWM: f64 = 1.0;
+
+/// Target RPM for each fan (indexed by fan position), `None` means that the fan is not
+/// controlled (e.g. not connected)
+pub type Targets = Vec<Option<usize>>;
+
+pub struct RpmControl {
+    pid: OffsetPIDController,
+    targets: Targets,
+    /// Number of consecutive updates in which each fan has been stalled
+    stall_updates: Vec<usize>,
+    /// Fans which have reported their RPM at least once
+    present: Vec<bool>,
+    /// Fans are run at full speed because some fan stalled
+    fallback: bool,
+    /// Fan speed applied when the first fan started stalling
+    stall_speed: Option<Speed>,
+    /// Lower PWM limit raised after each stall
+    min_pwm: f64,
+    last_speed: Speed,
+    last_update: Instant,
+}
+
+impl RpmControl {
+    pub fn new() -> Self {
+        // The controller input is relative speed of the slowest fan in percent of its target RPM
+        let mut pid = OffsetPIDController::new(0.5, 0.1, 0.0, 60.0);
+        pid.set_target(100.0);
+        pid.set_limits(MIN_PWM, 100.0);
+
+        Self {
+            pid,
+            targets: vec![],
+            stall_updates: vec![],
+            present: vec![],
+            fallback: false,
+            stall_speed: None,
+            min_pwm: MIN_PWM,
+            last_speed: Speed::FULL_SPEED,
+            last_update: Instant::now(),
+        }
+    }
+
+    pub fn set_targets(&mut self, targets: &Targets) {
+        if self.targets != *targets {
+            self.targets = targets.clone();
+            self.stall_updates = vec![0; targets.len()];
+            self.present.resize(targets.len(), false);
+        }
+    }
+
+    /// Fans are run at full speed because some of them stalled
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Indices of fans that are currently considered stalled
+    pub fn stalled_fans(&self) -> Vec<usize> {
+        self.stall_updates
+            .iter()
+            .enumerate()
+            .filter(|(_, updates)| **updates >= STALL_UPDATES)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    pub fn update(&mut self, feedback: &Feedback) -> Speed {
+        self.update_at(feedback, Instant::now())
+    }
+
+    /// Update controller with fan `feedback` measured at `now` (allows to run the controller
+    /// in simulated time)
+    pub fn update_at(&mut self, feedback: &Feedback, now: Instant) -> Speed {
+        let delta_t = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        self.last_update = now;
+
+        // Relative speed of the slowest controlled fan
+        let mut slowest: Option<f64> = None;
+        for (idx, target) in self.targets.iter().enumerate() {
+            let target = match target {
+                Some(target) if *target > 0 => *target,
+                _ => continue,
+            };
+            let rpm = feedback.rpm.get(idx).copied().unwrap_or(0);
+            if rpm > 0 {
+                self.present[idx] = true;
+            } else if !self.present[idx] {
+                // the fan is not connected
+                continue;
+            }
+            let relative_speed = rpm as f64 * 100.0 / target as f64;
+            if relative_speed < STALL_THRESHOLD && self.last_speed != Speed::STOPPED {
+                self.stall_updates[idx] += 1;
+            } else {
+                self.stall_updates[idx] = 0;
+            }
+            slowest = Some(slowest.map_or(relative_speed, |slowest| slowest.min(relative_speed)));
+        }
+
+        if self.stall_updates.iter().all(|updates| *updates == 0) {
+            self.stall_speed = None;
+        } else if self.stall_speed.is_none() {
+            self.stall_speed = Some(self.last_speed);
+        }
+
+        let stalled_fans = self.stalled_fans();
+        let speed = if !stalled_fans.is_empty() {
+            if !self.fallback {
+                warn!(
+                    "Fan control: fans {:?} stalled, running all fans at full speed",
+                    stalled_fans
+                );
+                // Do not go that low again after the fans recover (stall at full speed is
+                // a failure of the fan itself)
+                if let Some(stall_speed) =
+                    self.stall_speed.filter(|speed| *speed != Speed::FULL_SPEED)
+                {
+                    self.min_pwm = (stall_speed.to_pwm() as f64 + STALL_PWM_MARGIN)
+                        .min(100.0)
+                        .max(self.min_pwm);
+                    self.pid.set_limits(self.min_pwm, 100.0);
+                }
+                self.fallback = true;
+            }
+            self.pid.reset();
+            Speed::FULL_SPEED
+        } else {
+            if self.fallback {
+                info!(
+                    "Fan control: fans recovered, resuming RPM control with minimal PWM {}",
+                    self.min_pwm
+                );
+                self.fallback = false;
+            }
+            match slowest {
+                // There is nothing to control so keep fans on the safe side
+                None => Speed::FULL_SPEED,
+                Some(relative_speed) => {
+                    let pwm = self.pid.update(relative_speed, delta_t);
+                    Speed::new(pwm.round().max(0.0).min(100.0) as usize)
+                }
+            }
+        };
+        self.last_speed = speed;
+        speed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn feedback(rpm: &[usize]) -> Feedback {
+        Feedback { rpm: rpm.to_vec() }
+    }
+
+    /// Verify that PWM goes up when fans are too slow and down when they are too fast
+    #[test]
+    fn test_rpm_control_direction() {
+        let start = Instant::now();
+        let mut control = RpmControl::new();
+        control.set_targets(&vec![Some(3000), Some(3000), None, None]);
+
+        let slow = control.update_at(&feedback(&[1500, 1500, 0, 0]), start);
+        let mut fast_control = RpmControl::new();
+        fast_control.set_targets(&vec![Some(3000), Some(3000), None, None]);
+        let fast = fast_control.update_at(&feedback(&[4500, 4500, 0, 0]), start);
+        assert!(slow.to_pwm() > fast.to_pwm());
+        assert!(!control.is_fallback());
+
+        // the slowest fan is decisive
+        let mut mixed_control = RpmControl::new();
+        mixed_control.set_targets(&vec![Some(3000), Some(3000), None, None]);
+        let mixed = mixed_control.update_at(&feedback(&[1500, 4500, 0, 0]), start);
+        assert_eq!(mixed, slow);
+
+        // uncontrolled fans are ignored (even when they stopped)
+        let speed = control.update_at(
+            &feedback(&[3000, 3000, 0, 0]),
+            start + Duration::from_secs(5),
+        );
+        assert!(speed.to_pwm() < 100);
+    }
+
+    /// Verify that stalled fan switches all fans to full speed until it recovers
+    #[test]
+    fn test_rpm_control_stall() {
+        let start = Instant::now();
+        let mut control = RpmControl::new();
+        control.set_targets(&vec![Some(3000), Some(3000)]);
+
+        let mut now = start;
+        let mut tick = |control: &mut RpmControl, rpm: &[usize]| {
+            now += Duration::from_secs(5);
+            control.update_at(&feedback(rpm), now)
+        };
+
+        let speed = tick(&mut control, &[3000, 3000]);
+        assert!(speed.to_pwm() < 100);
+        for _ in 1..STALL_UPDATES {
+            tick(&mut control, &[3000, 0]);
+            assert!(!control.is_fallback());
+        }
+        assert_eq!(tick(&mut control, &[3000, 0]), Speed::FULL_SPEED);
+        assert!(control.is_fallback());
+        assert_eq!(control.stalled_fans(), vec![1]);
+        assert_eq!(tick(&mut control, &[5000, 0]), Speed::FULL_SPEED);
+
+        // the fan recovered and the controller must not go under the PWM at which it stalled
+        let speed = tick(&mut control, &[5000, 5000]);
+        assert!(!control.is_fallback());
+        assert!(control.stalled_fans().is_empty());
+        assert!(speed.to_pwm() as f64 >= control.min_pwm);
+    }
+
+    /// Verify that fans which have never been running are not controlled
+    #[test]
+    fn test_rpm_control_missing_fans() {
+        let start = Instant::now();
+        let mut control = RpmControl::new();
+        control.set_targets(&vec![Some(3000); 4]);
+
+        for i in 1..=2 * STALL_UPDATES {
+            let speed = control.update_at(
+                &feedback(&[3000, 3000, 0, 0]),
+                start + Duration::from_secs(5 * i as u64),
+            );
+            assert!(speed.to_pwm() < 100);
+        }
+        assert!(!control.is_fallback());
+        assert!(control.stalled_fans().is_empty());
+
+        // nothing is known about the fans at the beginning
+        let mut control = RpmControl::new();
+        control.set_targets(&vec![Some(3000); 4]);
+        assert_eq!(control.update(&feedback(&[0, 0, 0, 0])), Speed::FULL_SPEED);
+        assert!(!control.is_fallback());
+    }
+
+    /// Verify that fans are run at full speed when there is no controlled fan
+    #[test]
+    fn test_rpm_control_no_targets() {
+        let mut control = RpmControl::new();
+        control.set_targets(&vec![None, Some(0)]);
+        assert_eq!(control.update(&feedback(&[3000, 3000])), Speed::FULL_SPEED);
+    }
+}
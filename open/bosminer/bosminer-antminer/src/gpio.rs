@@ -0,0 +1,234 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use ii_logging::macros::*;
+
+use crate::board;
+
+use embedded_hal;
+use sysfs_gpio;
+
+use futures::channel::mpsc;
+use futures::stream::{Stream, StreamExt};
+use ii_async_compat::{futures, tokio};
+use tokio::task;
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Timeout of waiting for pin interrupt after which the event thread checks whether anyone is
+/// still interested in the events
+const EVENT_POLL_TIMEOUT_MS: isize = 1000;
+
+/// Helper struct for altering output pins which implements OutputPin trait
+#[derive(Clone)]
+pub struct PinOut(sysfs_gpio::Pin);
+
+impl embedded_hal::digital::v2::OutputPin for PinOut {
+    type Error = sysfs_gpio::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_value(0)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_value(1)
+    }
+}
+
+/// Helper struct for reading input pins which implements InputPin trait
+#[derive(Clone)]
+pub struct PinIn(sysfs_gpio::Pin);
+
+impl PinIn {
+    /// Start listening for interrupts on `edge` of the input signal. The returned stream yields
+    /// value of the pin after each edge. Interrupts are waited for in a separate blocking thread
+    /// which is stopped shortly after the stream is dropped.
+    ///
+    /// Although this function is not async, it has to be called from within Tokio context.
+    /// NOTE: current value of the pin has to be read separately (e.g. after creating the stream
+    /// so that no change is missed).
+    pub fn events(&self, edge: Edge) -> Result<PinEvents, sysfs_gpio::Error> {
+        self.0.set_edge(edge.into())?;
+        let mut poller = self.0.get_poller()?;
+        let pin_num = self.0.get_pin_num();
+        let (sender, receiver) = mpsc::unbounded();
+
+        task::spawn_blocking(move || loop {
+            match poller.poll(EVENT_POLL_TIMEOUT_MS) {
+                Ok(Some(value)) => {
+                    if sender.unbounded_send(value > 0).is_err() {
+                        break;
+                    }
+                }
+                // timeout
+                Ok(None) => {
+                    if sender.is_closed() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Waiting for event on GPIO pin {} failed: {}", pin_num, e);
+                    break;
+                }
+            }
+        });
+        Ok(PinEvents(receiver))
+    }
+}
+
+impl embedded_hal::digital::v2::InputPin for PinIn {
+    type Error = sysfs_gpio::Error;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.0.get_value().map(|value| value > 0)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.0.get_value().map(|value| value == 0)
+    }
+}
+
+/// Edge of input signal which triggers pin event
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl From<Edge> for sysfs_gpio::Edge {
+    fn from(edge: Edge) -> Self {
+        match edge {
+            Edge::Rising => sysfs_gpio::Edge::RisingEdge,
+            Edge::Falling => sysfs_gpio::Edge::FallingEdge,
+            Edge::Both => sysfs_gpio::Edge::BothEdges,
+        }
+    }
+}
+
+/// Stream of input pin values triggered by pin interrupts (see `PinIn::events`)
+pub struct PinEvents(mpsc::UnboundedReceiver<bool>);
+
+impl PinEvents {
+    /// Debounce the pin: wait until no further event arrives for `hold_time` and return the
+    /// value the pin has settled on. `value` is the last known value of the pin. `None` is
+    /// returned when the stream of events terminates.
+    pub async fn settle(&mut self, mut value: bool, hold_time: Duration) -> Option<bool> {
+        loop {
+            match tokio::time::timeout(hold_time, self.next()).await {
+                Ok(Some(new_value)) => value = new_value,
+                Ok(None) => return None,
+                Err(_) => return Some(value),
+            }
+        }
+    }
+}
+
+impl Stream for PinEvents {
+    type Item = bool;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// All known output pin types on Antminer control board
+#[derive(Debug)]
+pub enum PinOutName {
+    LEDFrontRed,
+    LEDFrontGreen,
+    Buzzer,
+    Rst(usize),
+}
+
+/// All known input pin types on Antminer control board
+#[derive(Debug, Copy, Clone)]
+pub enum PinInName {
+    ResetButton,
+    IPSelect,
+    Plug(usize),
+}
+
+/// Provides functionality for configuring specific control pins of board `M`
+/// The pins can be accessed by name (see PinOutName and PinInName)
+pub struct ControlPinManager<M> {
+    _marker: PhantomData<M>,
+}
+
+impl<M: board::Model> ControlPinManager<M> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a specified output pin and initializes it (export in sysfs)
+    pub fn get_pin_out(&self, pin_name: PinOutName) -> Result<PinOut, sysfs_gpio::Error> {
+        let pin = sysfs_gpio::Pin::new(M::pin_out(pin_name));
+        pin.export()?;
+        pin.set_direction(sysfs_gpio::Direction::Out)?;
+        Ok(PinOut(pin))
+    }
+
+    /// Returns a specified input pin and initializes it (export in sysfs)
+    pub fn get_pin_in(&self, pin_name: PinInName) -> Result<PinIn, sysfs_gpio::Error> {
+        let pin = sysfs_gpio::Pin::new(M::pin_in(pin_name));
+        pin.export()?;
+        pin.set_direction(sysfs_gpio::Direction::In)?;
+        Ok(PinIn(pin))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pin_events_settle() {
+        const HOLD_TIME: Duration = Duration::from_millis(50);
+
+        let (sender, receiver) = mpsc::unbounded();
+        let mut events = PinEvents(receiver);
+
+        // bouncing contacts settle on the last value
+        for &value in [false, true, false, true].iter() {
+            sender
+                .unbounded_send(value)
+                .expect("BUG: cannot send event");
+        }
+        assert_eq!(events.settle(true, HOLD_TIME).await, Some(true));
+
+        // a single glitch is reverted before the hold time elapses
+        sender
+            .unbounded_send(false)
+            .expect("BUG: cannot send event");
+        sender.unbounded_send(true).expect("BUG: cannot send event");
+        let value = events.next().await.expect("BUG: missing event");
+        assert_eq!(events.settle(value, HOLD_TIME).await, Some(true));
+
+        drop(sender);
+        assert_eq!(events.settle(true, HOLD_TIME).await, None);
+    }
+}
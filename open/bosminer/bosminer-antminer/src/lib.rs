@@ -0,0 +1,33 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Infrastructure shared by backends for Antminer control boards: GPIO pins and fan control.
+//! Specifics of individual hardware models are described by `board::Model` trait so each backend
+//! only provides its own implementation of the trait.
+//!
+//! PIC voltage control and the monitor still live in the S9 backend because they are built on
+//! its I2C bus access, halt handling and configuration. They are to be moved here together with
+//! abstractions of those once a second backend needs them.
+
+pub mod board;
+pub mod fan;
+pub mod gpio;
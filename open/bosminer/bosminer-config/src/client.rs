@@ -28,11 +28,12 @@ use url::Url;
 
 use std::convert::TryFrom;
 use std::fmt;
+use std::time::Duration;
 
 use failure::ResultExt;
 
 pub const URL_JAVA_SCRIPT_REGEX: &'static str =
-    "(?:drain|(?:stratum2?\\+tcp(?:\\+insecure)?)):\\/\\/[\\w\\.-]+(?::\\d+)?(?:\\/[\\dA-HJ-NP-Za-km-z]+)?";
+    "(?:drain|bitcoind\\+http|(?:stratum2?\\+tcp(?:\\+insecure)?)):\\/\\/[\\w\\.-]+(?::\\d+)?(?:\\/[\\dA-Za-z]+)?";
 
 #[derive(Clone, Debug)]
 pub enum Protocol {
@@ -40,6 +41,8 @@ pub enum Protocol {
     StratumV1,
     StratumV2(v2::noise::auth::EncodedEd25519PublicKey),
     StratumV2Insecure,
+    /// Solo mining against bitcoind with coinbase paying to the script (scriptPubKey)
+    Bitcoind(Vec<u8>),
 }
 
 impl Protocol {
@@ -47,11 +50,13 @@ impl Protocol {
     pub const SCHEME_STRATUM_V1: &'static str = "stratum+tcp";
     pub const SCHEME_STRATUM_V2: &'static str = "stratum2+tcp";
     pub const SCHEME_STRATUM_V2_INSECURE: &'static str = "stratum2+tcp+insecure";
+    pub const SCHEME_BITCOIND: &'static str = "bitcoind+http";
 
     pub const DEFAULT_PORT_DRAIN: u16 = 0;
     pub const DEFAULT_PORT_STRATUM_V1: u16 = 3333;
     pub const DEFAULT_PORT_STRATUM_V2: u16 = 3336;
     pub const DEFAULT_PORT_STRATUM_V2_INSECURE: u16 = 3336;
+    pub const DEFAULT_PORT_BITCOIND: u16 = 8332;
 
     pub fn default_port(&self) -> u16 {
         match self {
@@ -59,6 +64,7 @@ impl Protocol {
             Self::StratumV1 => Self::DEFAULT_PORT_STRATUM_V1,
             Self::StratumV2(_) => Self::DEFAULT_PORT_STRATUM_V2,
             Self::StratumV2Insecure => Self::DEFAULT_PORT_STRATUM_V2_INSECURE,
+            Self::Bitcoind(_) => Self::DEFAULT_PORT_BITCOIND,
         }
     }
 
@@ -89,6 +95,18 @@ impl Protocol {
                 Self::StratumV2(upstream_authority_public_key)
             }
             Self::SCHEME_STRATUM_V2_INSECURE => Self::StratumV2Insecure,
+            Self::SCHEME_BITCOIND => {
+                let payout_script = match path.get(1..).filter(|s| !s.is_empty()) {
+                    Some(s) => hex::decode(s).map_err(|_| {
+                        error::ErrorKind::Client(format!("invalid payout script: {}", s))
+                    })?,
+                    None => Err(error::ErrorKind::Client(format!(
+                        "missing payout script for {} connection",
+                        scheme
+                    )))?,
+                };
+                Self::Bitcoind(payout_script)
+            }
             _ => Err(error::ErrorKind::Client(format!(
                 "unknown protocol '{}'",
                 scheme
@@ -102,6 +120,7 @@ impl Protocol {
             Self::StratumV1 => Self::SCHEME_STRATUM_V1,
             Self::StratumV2(_) => Self::SCHEME_STRATUM_V2,
             Self::StratumV2Insecure => Self::SCHEME_STRATUM_V2_INSECURE,
+            Self::Bitcoind(_) => Self::SCHEME_BITCOIND,
         }
     }
 }
@@ -115,6 +134,13 @@ impl fmt::Display for Protocol {
                 write!(f, "Stratum V2 (authority key: {})", public_key)
             }
             Protocol::StratumV2Insecure => write!(f, "Stratum V2 Insecure"),
+            Protocol::Bitcoind(payout_script) => {
+                write!(
+                    f,
+                    "Bitcoind (payout script: {})",
+                    hex::encode(payout_script)
+                )
+            }
         }
     }
 }
@@ -143,6 +169,170 @@ impl<'a> UserInfo<'a> {
     }
 }
 
+/// Schedule of delays between repeated attempts to connect to a pool. The delay starts at
+/// `initial_delay` and doubles with every failed attempt until it reaches `max_delay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetrySchedule {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetrySchedule {
+    pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+    /// Configured delays are clamped to this value
+    pub const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> error::Result<Self> {
+        if initial_delay == Duration::from_secs(0) {
+            Err(error::ErrorKind::Client(
+                "retry delay has to be greater than zero".to_string(),
+            ))?;
+        }
+        if max_delay < initial_delay {
+            Err(error::ErrorKind::Client(format!(
+                "maximal retry delay {:?} is less than initial delay {:?}",
+                max_delay, initial_delay
+            )))?;
+        }
+        Ok(Self {
+            initial_delay,
+            max_delay,
+        })
+    }
+
+    /// Build schedule from optional delays in seconds as used in configuration files. Delays
+    /// longer than `MAX_DELAY` are clamped.
+    pub fn from_secs(initial_delay: Option<f64>, max_delay: Option<f64>) -> error::Result<Self> {
+        let to_duration = |value: Option<f64>, default: Duration| match value {
+            Some(secs) if secs.is_finite() && secs >= 0.0 => Ok(Duration::from_secs_f64(
+                secs.min(Self::MAX_DELAY.as_secs_f64()),
+            )),
+            Some(secs) => Err(error::ErrorKind::Client(format!(
+                "retry delay '{}' is not valid",
+                secs
+            ))),
+            None => Ok(default),
+        };
+        let initial_delay = to_duration(initial_delay, Self::DEFAULT_INITIAL_DELAY)?;
+        // Make sure that the default cap doesn't break a long initial delay
+        let max_delay = to_duration(max_delay, Self::DEFAULT_MAX_DELAY.max(initial_delay))?;
+        Self::new(initial_delay, max_delay)
+    }
+}
+
+impl Default for RetrySchedule {
+    fn default() -> Self {
+        Self {
+            initial_delay: Self::DEFAULT_INITIAL_DELAY,
+            max_delay: Self::DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+/// Partition of coinbase extranonce space shared by multiple miners (instances) which mine on
+/// the same pool account or with the same coinbase (e.g. behind a local proxy with one
+/// subscription). Every instance has to be configured with a unique `index` so that they don't
+/// search the same space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtranoncePartition {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl ExtranoncePartition {
+    pub fn new(index: u32, count: u32) -> error::Result<Self> {
+        if index >= count {
+            Err(error::ErrorKind::Client(format!(
+                "extranonce partition index {} is out of range of {} partitions",
+                index, count
+            )))?;
+        }
+        Ok(Self { index, count })
+    }
+
+    /// Parse partition in format `INDEX/COUNT` as used in configuration files
+    pub fn parse(value: &str) -> error::Result<Self> {
+        let invalid = || {
+            error::ErrorKind::Client(format!(
+                "extranonce partition '{}' is not in format 'INDEX/COUNT'",
+                value
+            ))
+        };
+        let mut parts = value.splitn(2, '/');
+        let mut next_number = || {
+            parts
+                .next()
+                .and_then(|part| part.trim().parse::<u32>().ok())
+                .ok_or_else(invalid)
+        };
+        let index = next_number()?;
+        let count = next_number()?;
+        Self::new(index, count)
+    }
+
+    /// Extranonce with sequence number `seq` from this partition
+    #[inline]
+    pub fn extranonce(&self, seq: u64) -> u64 {
+        seq * self.count as u64 + self.index as u64
+    }
+}
+
+impl Default for ExtranoncePartition {
+    /// The whole extranonce space
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl fmt::Display for ExtranoncePartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Alternative endpoint of a pool which is published under multiple hosts (e.g. regional
+/// servers of the same pool)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub host: String,
+    /// Port of the pool URL is used when it is missing
+    pub port: Option<u16>,
+}
+
+impl Endpoint {
+    /// Parse endpoint in format `HOST[:PORT]` as used in configuration files
+    pub fn parse(value: &str) -> error::Result<Self> {
+        let invalid = || {
+            error::ErrorKind::Client(format!(
+                "pool endpoint '{}' is not in format 'HOST[:PORT]'",
+                value
+            ))
+        };
+        // Reuse URL parser to handle IPv6 addresses and validation of host names
+        let url =
+            Url::parse(format!("endpoint://{}", value.trim()).as_str()).map_err(|_| invalid())?;
+        if url.path() != "" || url.username() != "" || url.query().is_some() {
+            Err(invalid())?;
+        }
+        let host = url.host().ok_or_else(invalid)?.to_string();
+
+        Ok(Self {
+            host,
+            port: url.port(),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
 /// Contains basic information about client used for obtaining jobs for solving.
 #[derive(Clone, Debug)]
 pub struct Descriptor {
@@ -154,9 +344,24 @@ pub struct Descriptor {
     pub port: Option<u16>,
     // Currently used only for `#xnsub`: `stratum+tcp://equihash.eu.nicehash.com:3357#xnsub`
     pub fragment: Option<String>,
+    /// Delays between reconnection attempts
+    pub retry_schedule: RetrySchedule,
+    /// Relative weight of the client within its group
+    pub weight: f64,
+    /// Part of extranonce space reserved for this miner
+    pub extranonce_partition: ExtranoncePartition,
+    /// Number of mining channels opened on a single connection (supported only by protocols
+    /// with channels)
+    pub channel_count: usize,
+    /// Alternative endpoints of the pool besides `host` (supported only by stratum protocols)
+    pub endpoints: Vec<Endpoint>,
 }
 
 impl Descriptor {
+    pub const DEFAULT_WEIGHT: f64 = 1.0;
+    pub const DEFAULT_CHANNEL_COUNT: usize = 1;
+    pub const MAX_CHANNEL_COUNT: usize = 16;
+
     pub fn port(&self) -> u16 {
         match self.port {
             Some(value) => value,
@@ -164,6 +369,20 @@ impl Descriptor {
         }
     }
 
+    /// Addresses (`host:port`) of all endpoints of the pool starting with the one from URL
+    pub fn endpoint_addresses(&self) -> Vec<String> {
+        let default_port = self.port();
+        std::iter::once(format!("{}:{}", self.host, default_port))
+            .chain(self.endpoints.iter().map(|endpoint| {
+                format!(
+                    "{}:{}",
+                    endpoint.host,
+                    endpoint.port.unwrap_or(default_port)
+                )
+            }))
+            .collect()
+    }
+
     pub fn get_url(&self, protocol: bool, port: bool, user: bool) -> String {
         let mut result = if protocol {
             self.protocol.scheme().to_string() + "://"
@@ -209,6 +428,11 @@ impl Descriptor {
             host,
             port,
             fragment,
+            retry_schedule: Default::default(),
+            weight: Self::DEFAULT_WEIGHT,
+            extranonce_partition: Default::default(),
+            channel_count: Self::DEFAULT_CHANNEL_COUNT,
+            endpoints: vec![],
         })
     }
 }
@@ -20,9 +20,12 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use crate::schema;
+
+use bosminer_macros::Schema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub enum LoadBalanceStrategy {
     #[serde(rename = "quota")]
@@ -49,8 +52,22 @@ impl LoadBalanceStrategy {
     }
 }
 
+/// Policy for selecting the pool that supplies jobs among all pools in a group
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq)]
+pub enum PoolSelection {
+    /// The first working pool is used, the others serve as a backup
+    #[serde(rename = "primary_with_backup")]
+    PrimaryWithBackup,
+    /// Work is split evenly between all working pools
+    #[serde(rename = "round_robin")]
+    RoundRobin,
+    /// Work is split between all working pools in proportion to their weights
+    #[serde(rename = "weighted")]
+    Weighted,
+}
+
 /// Contains basic information about group
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Descriptor {
     pub name: String,
@@ -59,6 +76,8 @@ pub struct Descriptor {
     #[serde(flatten)]
     #[serde(skip_serializing_if = "Option::is_none")]
     strategy: Option<LoadBalanceStrategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_selection: Option<PoolSelection>,
 }
 
 impl Descriptor {
@@ -74,6 +93,7 @@ impl Descriptor {
             name,
             private,
             strategy: strategy.into(),
+            pool_selection: None,
         }
     }
 
@@ -103,6 +123,7 @@ impl Default for Descriptor {
             name: Self::DEFAULT_NAME.to_string(),
             private: false,
             strategy: None,
+            pool_selection: None,
         }
     }
 }
@@ -0,0 +1,318 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Layered configuration merged from several sources in a defined order of precedence:
+//! configuration file < environment variables < explicit overrides (command line)
+
+use crate::schema::{self, Schema};
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+/// Prefix of environment variables overriding values from configuration file
+pub const ENV_PREFIX: &'static str = "BOSMINER";
+
+/// Separator of nested keys in environment variable names. E.g. the variable
+/// `BOSMINER_HASH_CHAIN_GLOBAL__FREQUENCY` overrides `frequency` in `[hash_chain_global]`.
+pub const ENV_SEPARATOR: &'static str = "__";
+
+/// Environment source which converts values to the type of the field they override
+///
+/// The generic `config::Environment` passes all values as strings which cannot be deserialized
+/// into numbers when the target structure contains flattened fields. The type of each field is
+/// looked up in JSON schema of the target structure so that string fields are never converted.
+#[derive(Clone, Debug)]
+struct Environment {
+    inner: config::Environment,
+    schema: schema::Value,
+}
+
+impl Environment {
+    fn new(prefix: &str, schema: schema::Value) -> Self {
+        Self {
+            inner: config::Environment::with_prefix(prefix)
+                .separator(ENV_SEPARATOR)
+                .ignore_empty(true),
+            schema,
+        }
+    }
+}
+
+/// JSON schema type of a value at `key` path (e.g. `hash_chain.6.voltage`)
+fn field_type<'a>(schema: &'a schema::Value, key: &str) -> Option<&'a str> {
+    let mut schema = schema;
+    for name in key.split('.') {
+        schema = match (
+            schema
+                .get("properties")
+                .and_then(|properties| properties.get(name)),
+            schema.get("additionalProperties"),
+            schema.get("items"),
+        ) {
+            (Some(property), _, _) => property,
+            (None, Some(value), _) if value.is_object() => value,
+            (None, _, Some(item)) if name.parse::<usize>().is_ok() => item,
+            _ => return None,
+        };
+    }
+    schema.get("type")?.as_str()
+}
+
+/// Convert string to the type of configuration value described by `field_type`. Values which
+/// cannot be converted are kept as strings and reported when the configuration is deserialized.
+fn typed_value(origin: Option<&String>, field_type: Option<&str>, value: String) -> config::Value {
+    match field_type {
+        Some("boolean") => {
+            if let Ok(typed) = value.parse::<bool>() {
+                return config::Value::new(origin, typed);
+            }
+        }
+        Some("integer") => {
+            if let Ok(typed) = value.parse::<i64>() {
+                return config::Value::new(origin, typed);
+            }
+        }
+        Some("number") => {
+            if let Ok(typed) = value.parse::<f64>() {
+                return config::Value::new(origin, typed);
+            }
+        }
+        _ => {}
+    }
+    config::Value::new(origin, value)
+}
+
+impl config::Source for Environment {
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, config::Value>, config::ConfigError> {
+        let origin = "the environment".to_string();
+        Ok(self
+            .inner
+            .collect()?
+            .into_iter()
+            .map(|(key, value)| {
+                let value = value
+                    .into_str()
+                    .expect("BUG: environment value is not a string");
+                let field_type = field_type(&self.schema, &key);
+                (key, typed_value(Some(&origin), field_type, value))
+            })
+            .collect())
+    }
+}
+
+/// Configuration merged from individual layers. Every following layer takes precedence over the
+/// previous ones.
+#[derive(Debug)]
+pub struct Layers {
+    settings: config::Config,
+}
+
+impl Layers {
+    /// Start with configuration file located at `config_path`
+    pub fn new(config_path: &str) -> Result<Self, String> {
+        let mut settings = config::Config::default();
+        settings
+            .merge(config::File::with_name(config_path))
+            .map_err(|e| format!("{}", e))?;
+        Ok(Self { settings })
+    }
+
+    /// Start with configuration in TOML format
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let mut settings = config::Config::default();
+        settings
+            .merge(config::File::from_str(content, config::FileFormat::Toml))
+            .map_err(|e| format!("{}", e))?;
+        Ok(Self { settings })
+    }
+
+    /// Override values with environment variables prefixed with `ENV_PREFIX`. The values are
+    /// converted to types of fields of configuration structure `T`.
+    pub fn with_env<T: Schema>(self) -> Result<Self, String> {
+        self.merge_env(Environment::new(ENV_PREFIX, T::schema()))
+    }
+
+    fn merge_env(mut self, environment: Environment) -> Result<Self, String> {
+        self.settings
+            .merge(environment)
+            .map_err(|e| format!("cannot apply environment variables: {}", e))?;
+        Ok(self)
+    }
+
+    /// Override a value with the highest precedence (typically from command line). The `key` is
+    /// a path expression such as `hash_chain_global.frequency`.
+    pub fn set_override<T>(&mut self, key: &str, value: T) -> Result<(), String>
+    where
+        T: Into<config::Value>,
+    {
+        self.settings
+            .set(key, value)
+            .map_err(|e| format!("cannot override '{}': {}", key, e))?;
+        Ok(())
+    }
+
+    /// Check if the value is present in any layer
+    pub fn contains(&self, key: &str) -> bool {
+        self.settings.get::<config::Value>(key).is_ok()
+    }
+
+    /// Deserialize the effective configuration
+    pub fn try_into<'a, T>(self) -> Result<T, String>
+    where
+        T: Deserialize<'a>,
+    {
+        self.settings.try_into::<T>().map_err(|e| format!("{}", e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PoolConfig;
+
+    use bosminer_macros::Schema;
+    use serde::Serialize;
+
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Schema)]
+    struct TestChain {
+        frequency: Option<f64>,
+        chip_count: Option<usize>,
+        enabled: Option<bool>,
+    }
+
+    /// Flattened structure like the configuration body in `FormatWrapper`
+    #[derive(Serialize, Deserialize, Schema)]
+    struct TestBody {
+        hash_chain: Option<BTreeMap<String, TestChain>>,
+        pool: Option<PoolConfig>,
+    }
+
+    #[derive(Serialize, Deserialize, Schema)]
+    struct TestConfig {
+        #[serde(flatten)]
+        body: TestBody,
+    }
+
+    #[test]
+    fn test_field_type() {
+        let schema = TestConfig::schema();
+        assert_eq!(
+            field_type(&schema, "hash_chain.6.frequency"),
+            Some("number")
+        );
+        assert_eq!(
+            field_type(&schema, "hash_chain.6.chip_count"),
+            Some("integer")
+        );
+        assert_eq!(field_type(&schema, "hash_chain.6.enabled"), Some("boolean"));
+        assert_eq!(field_type(&schema, "pool.user"), Some("string"));
+        assert_eq!(field_type(&schema, "pool.unknown"), None);
+        assert_eq!(field_type(&schema, "unknown.user"), None);
+    }
+
+    #[test]
+    fn test_typed_value() {
+        let typed = |field_type, value: &str| typed_value(None, field_type, value.to_string());
+
+        assert_eq!(typed(Some("boolean"), "true").into_bool().unwrap(), true);
+        assert_eq!(typed(Some("integer"), "007").into_int().unwrap(), 7);
+        assert_eq!(typed(Some("number"), "1e5").into_float().unwrap(), 100000.0);
+        assert_eq!(typed(Some("number"), "8").into_float().unwrap(), 8.0);
+        // string fields and unknown fields are never converted
+        for value in &["007", "1e5", "true", "8.8"] {
+            assert_eq!(typed(Some("string"), value).into_str().unwrap(), *value);
+            assert_eq!(typed(None, value).into_str().unwrap(), *value);
+        }
+        // invalid values are left to deserialization which reports them
+        assert_eq!(typed(Some("integer"), "many").into_str().unwrap(), "many");
+    }
+
+    #[test]
+    fn test_env_override() {
+        // the prefix is unique to this test so it doesn't interfere with any other one
+        const PREFIX: &str = "BOSMINER_TEST_ENV_OVERRIDE";
+        let vars = [
+            ("HASH_CHAIN__6__FREQUENCY", "700"),
+            ("HASH_CHAIN__6__CHIP_COUNT", "063"),
+            ("HASH_CHAIN__6__ENABLED", "false"),
+            ("POOL__URL", "stratum+tcp://1e5:3333"),
+            ("POOL__USER", "007"),
+            ("POOL__PASSWORD", "1e5"),
+        ];
+        for (name, value) in vars.iter() {
+            std::env::set_var(format!("{}_{}", PREFIX, name), value);
+        }
+
+        let config: TestConfig = Layers::from_toml(
+            "[hash_chain.6]\nfrequency = 650.0\n\n[pool]\nurl = 'stratum+tcp://pool'\nuser = 'x'\n",
+        )
+        .expect("BUG: cannot parse configuration")
+        .merge_env(Environment::new(PREFIX, TestConfig::schema()))
+        .expect("BUG: cannot apply environment")
+        .try_into()
+        .expect("BUG: cannot deserialize configuration");
+
+        for (name, _) in vars.iter() {
+            std::env::remove_var(format!("{}_{}", PREFIX, name));
+        }
+
+        let chain = &config.body.hash_chain.as_ref().expect("BUG: missing chain")["6"];
+        assert_eq!(chain.frequency, Some(700.0));
+        assert_eq!(chain.chip_count, Some(63));
+        assert_eq!(chain.enabled, Some(false));
+        let pool = config.body.pool.as_ref().expect("BUG: missing pool");
+        assert_eq!(pool.url, "stratum+tcp://1e5:3333");
+        assert_eq!(pool.user, "007");
+        assert_eq!(pool.password.as_deref(), Some("1e5"));
+    }
+
+    #[test]
+    fn test_override_precedence() {
+        let mut settings = config::Config::default();
+        settings
+            .merge(config::File::from_str(
+                "[hash_chain_global]\nfrequency = 650.0\nvoltage = 8.8\n",
+                config::FileFormat::Toml,
+            ))
+            .expect("BUG: cannot parse configuration");
+        let mut layers = Layers { settings };
+        layers
+            .set_override("hash_chain_global.frequency", 700.0)
+            .expect("BUG: cannot override frequency");
+
+        assert!(layers.contains("hash_chain_global.voltage"));
+        assert!(!layers.contains("group"));
+
+        let settings: HashMap<String, HashMap<String, f64>> = layers
+            .try_into()
+            .expect("BUG: cannot deserialize configuration");
+        assert_eq!(settings["hash_chain_global"]["frequency"], 700.0);
+        assert_eq!(settings["hash_chain_global"]["voltage"], 8.8);
+    }
+}
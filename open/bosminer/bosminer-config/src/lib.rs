@@ -23,23 +23,35 @@
 mod client;
 mod error;
 mod group;
+mod layers;
+pub mod schema;
 
 // Reexport inner structures
 pub use client::Descriptor as ClientDescriptor;
+pub use client::Endpoint as ClientEndpoint;
+pub use client::ExtranoncePartition as ClientExtranoncePartition;
 pub use client::Protocol as ClientProtocol;
+pub use client::RetrySchedule as ClientRetrySchedule;
 pub use client::UserInfo as ClientUserInfo;
 pub use client::URL_JAVA_SCRIPT_REGEX as CLIENT_URL_JAVA_SCRIPT_REGEX;
 
 pub use group::Descriptor as GroupDescriptor;
 pub use group::LoadBalanceStrategy;
+pub use group::PoolSelection;
+
+pub use layers::Layers;
+pub use layers::{ENV_PREFIX, ENV_SEPARATOR};
 
 // reexport common crates
 pub use clap;
 pub use config;
 
+use bosminer_macros::Schema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use std::time;
+
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct PoolConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -48,11 +60,334 @@ pub struct PoolConfig {
     pub user: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
+    /// Initial delay in seconds between reconnection attempts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = ClientRetrySchedule::DEFAULT_INITIAL_DELAY.as_secs_f64(), minimum = 0)]
+    pub retry_delay: Option<f64>,
+    /// Maximal delay in seconds between reconnection attempts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = ClientRetrySchedule::DEFAULT_MAX_DELAY.as_secs_f64(), minimum = 0)]
+    pub retry_delay_max: Option<f64>,
+    /// Relative weight of the pool used by weighted pool selection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = ClientDescriptor::DEFAULT_WEIGHT, minimum = 0)]
+    pub weight: Option<f64>,
+    /// Part of extranonce space used by this miner in format `INDEX/COUNT` when multiple miners
+    /// share the same pool subscription or coinbase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extranonce_partition: Option<String>,
+    /// Number of standard channels opened on a single Stratum V2 connection (e.g. one per
+    /// hashboard). Each hashboard mines in one channel, hashboards are assigned to the channels
+    /// in order they have been registered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = ClientDescriptor::DEFAULT_CHANNEL_COUNT,
+        minimum = 1,
+        maximum = ClientDescriptor::MAX_CHANNEL_COUNT
+    )]
+    pub channels: Option<usize>,
+    /// Alternative endpoints of the same pool in format `HOST[:PORT]`. The client connects to
+    /// the healthy endpoint with the lowest latency.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoints: Option<Vec<String>>,
+}
+
+impl PoolConfig {
+    pub fn retry_schedule(&self) -> error::Result<ClientRetrySchedule> {
+        ClientRetrySchedule::from_secs(self.retry_delay, self.retry_delay_max)
+    }
+
+    pub fn weight(&self) -> error::Result<f64> {
+        let weight = self.weight.unwrap_or(ClientDescriptor::DEFAULT_WEIGHT);
+        if !(weight >= 0.0 && weight.is_finite()) {
+            Err(error::ErrorKind::Client(format!(
+                "pool weight '{}' is not valid",
+                weight
+            )))?;
+        }
+        Ok(weight)
+    }
+
+    pub fn extranonce_partition(&self) -> error::Result<ClientExtranoncePartition> {
+        match &self.extranonce_partition {
+            Some(value) => ClientExtranoncePartition::parse(value),
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub fn channel_count(&self) -> error::Result<usize> {
+        let channel_count = self
+            .channels
+            .unwrap_or(ClientDescriptor::DEFAULT_CHANNEL_COUNT);
+        if channel_count == 0 || channel_count > ClientDescriptor::MAX_CHANNEL_COUNT {
+            Err(error::ErrorKind::Client(format!(
+                "channel count {} is out of range 1..={}",
+                channel_count,
+                ClientDescriptor::MAX_CHANNEL_COUNT
+            )))?;
+        }
+        Ok(channel_count)
+    }
+
+    pub fn endpoints(&self) -> error::Result<Vec<ClientEndpoint>> {
+        self.endpoints
+            .iter()
+            .flatten()
+            .map(|value| ClientEndpoint::parse(value))
+            .collect()
+    }
+}
+
+/// Handling of solutions meeting the network target (found blocks)
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BlockFoundConfig {
+    /// File where every found block is appended as one JSON line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_path: Option<String>,
+    /// Shell command executed for every found block. Details of the block are passed in
+    /// `BOSMINER_BLOCK_*` environment variables so the command can e.g. call a webhook.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+}
+
+/// Small share of generated work directed to a designated pool account (e.g. development fee)
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FeeConfig {
+    pub url: String,
+    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Percentage of generated work submitted to the fee pool
+    #[schema(minimum = 0, maximum = FeeConfig::MAX_PERCENT)]
+    pub percent: f64,
+}
+
+impl FeeConfig {
+    pub const MAX_PERCENT: f64 = 10.0;
+
+    /// Fixed share ratio of the group with the fee pool
+    pub fn share_ratio(&self) -> error::Result<f64> {
+        if !(self.percent > 0.0 && self.percent <= Self::MAX_PERCENT) {
+            Err(error::ErrorKind::Client(format!(
+                "fee percentage '{}' is out of range (0, {}]",
+                self.percent,
+                Self::MAX_PERCENT
+            )))?;
+        }
+        Ok(self.percent / 100.0)
+    }
+
+    /// Configuration of the fee pool client
+    pub fn pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            enabled: Some(true),
+            url: self.url.clone(),
+            user: self.user.clone(),
+            password: self.password.clone(),
+            retry_delay: None,
+            retry_delay_max: None,
+            weight: None,
+            extranonce_partition: None,
+            channels: None,
+            endpoints: None,
+        }
+    }
+}
+
+/// Alerting on pools whose accepted hashrate is lower than the hashrate expected from nominal
+/// hashrate of the miner
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PoolHealthConfig {
+    /// Percentage of expected hashrate under which the pool is considered unhealthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = 0, maximum = 100)]
+    pub threshold: Option<f64>,
+    /// Time in seconds the pool has to stay under the threshold to raise an alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = 0)]
+    pub period: Option<f64>,
+    /// Shell command executed when an alert is raised or cleared. Details are passed in
+    /// `BOSMINER_POOL_HEALTH_*` environment variables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+}
+
+/// Handling of miner lifecycle and fault events
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EventsConfig {
+    /// Shell command executed for every event. Details of the event are passed in
+    /// `BOSMINER_EVENT_*` environment variables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+    /// Execute the command only for fault events (sensor failures, thermal shutdown, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub faults_only: Option<bool>,
+}
+
+/// Miner status reported to Stratum V2 pools through the telemetry protocol extension
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Submit telemetry to pools which support it (disabled by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = TelemetryConfig::DEFAULT_ENABLED)]
+    pub enabled: Option<bool>,
+    /// Interval in seconds between two miner status snapshots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = TelemetryConfig::DEFAULT_INTERVAL,
+        minimum = TelemetryConfig::MIN_INTERVAL,
+        maximum = TelemetryConfig::MAX_INTERVAL
+    )]
+    pub interval: Option<f64>,
+}
+
+impl TelemetryConfig {
+    pub const DEFAULT_ENABLED: bool = false;
+    pub const DEFAULT_INTERVAL: f64 = 60.0;
+    pub const MIN_INTERVAL: f64 = 1.0;
+    pub const MAX_INTERVAL: f64 = 86400.0;
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(Self::DEFAULT_ENABLED)
+    }
+
+    pub fn interval(&self) -> error::Result<time::Duration> {
+        let interval = self.interval.unwrap_or(Self::DEFAULT_INTERVAL);
+        if !(Self::MIN_INTERVAL..=Self::MAX_INTERVAL).contains(&interval) {
+            Err(error::ErrorKind::General(format!(
+                "telemetry interval '{}' is not valid (range is {}-{} s)",
+                interval,
+                Self::MIN_INTERVAL,
+                Self::MAX_INTERVAL
+            )))?;
+        }
+        Ok(time::Duration::from_secs_f64(interval))
+    }
+}
+
+/// Action taken by the share watchdog
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq)]
+pub enum WatchdogAction {
+    /// Reconnect all enabled pools
+    #[serde(rename = "restart_pools")]
+    RestartPools,
+    /// Terminate the miner so that it is started again by the service manager
+    #[serde(rename = "restart_miner")]
+    RestartMiner,
+}
+
+/// Watchdog which fires when no share has been accepted by any pool for a long time while the
+/// miner is hashing (e.g. when a stratum session is wedged without being disconnected)
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ShareWatchdogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(default = ShareWatchdogConfig::DEFAULT_ENABLED)]
+    pub enabled: Option<bool>,
+    /// Time in seconds without any accepted share after which the watchdog fires
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(
+        default = ShareWatchdogConfig::DEFAULT_TIMEOUT,
+        minimum = ShareWatchdogConfig::MIN_TIMEOUT,
+        maximum = ShareWatchdogConfig::MAX_TIMEOUT
+    )]
+    pub timeout: Option<f64>,
+    /// Action taken when the watchdog fires (`restart_pools` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<WatchdogAction>,
+}
+
+impl ShareWatchdogConfig {
+    pub const DEFAULT_ENABLED: bool = false;
+    pub const DEFAULT_TIMEOUT: f64 = 1800.0;
+    pub const MIN_TIMEOUT: f64 = 60.0;
+    pub const MAX_TIMEOUT: f64 = 86400.0;
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(Self::DEFAULT_ENABLED)
+    }
+
+    pub fn timeout(&self) -> error::Result<time::Duration> {
+        let timeout = self.timeout.unwrap_or(Self::DEFAULT_TIMEOUT);
+        if !(Self::MIN_TIMEOUT..=Self::MAX_TIMEOUT).contains(&timeout) {
+            Err(error::ErrorKind::General(format!(
+                "share watchdog timeout '{}' is not valid (range is {}-{} s)",
+                timeout,
+                Self::MIN_TIMEOUT,
+                Self::MAX_TIMEOUT
+            )))?;
+        }
+        Ok(time::Duration::from_secs_f64(timeout))
+    }
+
+    pub fn action(&self) -> WatchdogAction {
+        self.action.unwrap_or(WatchdogAction::RestartPools)
+    }
+}
+
+/// Options for troubleshooting of the miner which are not intended for regular operation
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DebugConfig {
+    /// Directory where frames received from Stratum V2 pools are recorded (one file per
+    /// connection) so that they can be replayed in tests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stratum_record_dir: Option<String>,
+}
+
+/// Time-of-day and day-of-week mining profiles
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Offset of local time from UTC in minutes used for evaluation of profile windows (-720 to
+    /// 840). The offset is fixed so it has to be changed manually when daylight saving time
+    /// starts or ends.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<i32>,
+    /// File where manual override of the active profile is persisted across restarts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_path: Option<String>,
+    /// Profiles in order of precedence (the first one matching current time is active)
+    #[serde(rename = "profile")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<Vec<ProfileConfig>>,
+}
+
+/// Mining profile applied in a time window
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    /// Days of week (`mon`, `tue`, ... `sun` or full names) when the window starts, every day by
+    /// default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<String>>,
+    /// Start of the window in local time (`HH:MM`), midnight by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    /// End of the window in local time (`HH:MM`), midnight by default. The window continues on
+    /// the next day when it ends before it starts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    /// Frequency of hash chains in MHz
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    /// Voltage of hash chains in volts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+    /// Stop mining for the whole window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
 }
 
 // NOTE: `#[serde(deny_unknown_fields)]` cannot be used due to flatten descriptor but the error is
 // caught in the `GroupDescriptor`
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Schema, Clone, Debug)]
 pub struct GroupConfig {
     #[serde(flatten)]
     pub descriptor: GroupDescriptor,
@@ -66,11 +401,5 @@ pub fn parse<'a, T>(config_path: &str) -> Result<T, String>
 where
     T: Deserialize<'a>,
 {
-    let mut settings = config::Config::default();
-    settings
-        .merge(config::File::with_name(config_path))
-        .map_err(|e| format!("{}", e))?;
-
-    // Parse it into structure
-    settings.try_into::<T>().map_err(|e| format!("{}", e))
+    Layers::new(config_path)?.try_into()
 }
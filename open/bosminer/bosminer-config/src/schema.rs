@@ -0,0 +1,227 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Machine-readable description of the configuration in the form of JSON schema. The schema is
+//! generated from the configuration structures with `#[derive(Schema)]` from `bosminer-macros`
+//! so user interfaces can validate and render the configuration without keeping a separate copy
+//! of types, ranges and defaults.
+
+pub use serde_json::{json, Map, Value};
+
+use std::collections::BTreeMap;
+
+/// Version of JSON schema specification used for the exported schema
+pub const DRAFT: &str = "http://json-schema.org/draft-07/schema#";
+
+/// Type which is able to describe its serialized form
+pub trait Schema {
+    /// JSON schema of the serialized type. The result is always a JSON object.
+    fn schema() -> Value;
+}
+
+/// Merge properties of `schema` describing a flattened structure to `properties` and `required`
+/// list of the parent structure. Properties of the flattened structure are not required when the
+/// whole structure is `optional`. Alternatives of externally tagged enums are merged as optional
+/// properties because only one of them can be present.
+pub fn flatten(
+    schema: Value,
+    properties: &mut Map<String, Value>,
+    required: &mut Vec<Value>,
+    optional: bool,
+) {
+    let mut schema = match schema {
+        Value::Object(schema) => schema,
+        _ => panic!("BUG: schema is not an object"),
+    };
+    if let Some(Value::Array(alternatives)) = schema.remove("oneOf") {
+        for alternative in alternatives {
+            flatten(alternative, properties, required, true);
+        }
+        return;
+    }
+    if let Some(Value::Object(flattened)) = schema.remove("properties") {
+        properties.extend(flattened);
+    }
+    if !optional {
+        if let Some(Value::Array(flattened)) = schema.remove("required") {
+            required.extend(flattened);
+        }
+    }
+}
+
+impl Schema for bool {
+    fn schema() -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+macro_rules! impl_schema {
+    ($($type:ty),* => $schema:tt) => {
+        $(
+            impl Schema for $type {
+                fn schema() -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+impl_schema!(u8, u16, u32, u64, usize => { "type": "integer", "minimum": 0 });
+impl_schema!(i8, i16, i32, i64, isize => { "type": "integer" });
+impl_schema!(f32, f64 => { "type": "number" });
+impl_schema!(String => { "type": "string" });
+
+/// Optional values are simply omitted so the schema is the same as for the inner type
+impl<T: Schema> Schema for Option<T> {
+    fn schema() -> Value {
+        T::schema()
+    }
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema() -> Value {
+        json!({
+            "type": "array",
+            "items": T::schema(),
+        })
+    }
+}
+
+impl<T: Schema> Schema for BTreeMap<String, T> {
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "additionalProperties": T::schema(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::schema::{self, json, Schema};
+    use crate::{GroupConfig, LoadBalanceStrategy, PoolSelection};
+
+    use bosminer_macros::Schema;
+    use serde::{Deserialize, Serialize};
+
+    /// Testing inner structure
+    #[derive(Serialize, Deserialize, Schema)]
+    #[serde(deny_unknown_fields)]
+    struct Inner {
+        #[schema(minimum = 1, maximum = 4)]
+        count: usize,
+    }
+
+    #[derive(Serialize, Deserialize, Schema)]
+    #[serde(rename_all = "snake_case")]
+    enum Mode {
+        Auto,
+        #[serde(rename = "off")]
+        Disabled,
+        ManualOverride,
+    }
+
+    #[derive(Serialize, Deserialize, Schema)]
+    struct Outer {
+        /// Some name
+        name: String,
+        #[serde(rename = "value")]
+        #[schema(default = 1.5)]
+        values: Option<Vec<f64>>,
+        mode: Option<Mode>,
+        #[serde(default)]
+        enabled: bool,
+        #[serde(skip)]
+        private: bool,
+        #[serde(flatten)]
+        inner: Option<Inner>,
+    }
+
+    #[test]
+    fn test_struct_schema() {
+        assert_eq!(
+            Outer::schema(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "Some name",
+                    },
+                    "value": {
+                        "type": "array",
+                        "items": { "type": "number" },
+                        "default": 1.5,
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["auto", "off", "manual_override"],
+                    },
+                    "enabled": { "type": "boolean" },
+                    "count": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": 4,
+                    },
+                },
+                "required": ["name"],
+                "additionalProperties": true,
+            })
+        );
+        assert_eq!(Inner::schema()["additionalProperties"], json!(false));
+        assert_eq!(
+            Inner::schema()["description"],
+            json!("Testing inner structure")
+        );
+        assert_eq!(Inner::schema()["required"], json!(["count"]));
+    }
+
+    #[test]
+    fn test_group_schema() {
+        let schema = GroupConfig::schema();
+        let properties = &schema["properties"];
+
+        assert_eq!(properties["name"]["type"], json!("string"));
+        assert_eq!(properties["quota"]["type"], json!("integer"));
+        assert_eq!(properties["fixed_share_ratio"]["type"], json!("number"));
+        assert_eq!(
+            properties["pool"]["items"]["required"],
+            json!(["url", "user"])
+        );
+        assert_eq!(schema["required"], json!(["name"]));
+
+        assert_eq!(
+            PoolSelection::schema()["enum"],
+            schema::Value::Array(
+                ["primary_with_backup", "round_robin", "weighted"]
+                    .iter()
+                    .map(|name| json!(name))
+                    .collect()
+            )
+        );
+        assert_eq!(
+            LoadBalanceStrategy::schema()["oneOf"][0]["required"],
+            json!(["quota"])
+        );
+    }
+}
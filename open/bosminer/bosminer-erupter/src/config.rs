@@ -20,6 +20,8 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use crate::icarus;
+
 use bosminer::client;
 use bosminer::hal;
 
@@ -39,30 +41,123 @@ pub const DEFAULT_HASHRATE_INTERVAL: Duration = Duration::from_secs(60);
 /// Maximum time it takes to compute one job under normal circumstances
 pub const JOB_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Maximal timeout of waiting for nonces of one work which can be configured
+pub const MAX_NONCE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Select Icarus device model by its `name` and optionally override its default hash time
+/// (`hash_time_ns` in nanoseconds) e.g. when the device runs on non-default frequency
+pub fn parse_model(name: &str, hash_time_ns: Option<&str>) -> Result<icarus::Model, String> {
+    let mut model = icarus::Model::find(name)
+        .ok_or_else(|| {
+            let names: Vec<_> = icarus::MODELS.iter().map(|model| model.name).collect();
+            format!(
+                "unknown device model '{}' (supported: {})",
+                name,
+                names.join(", ")
+            )
+        })?
+        .clone();
+    if let Some(hash_time_ns) = hash_time_ns {
+        let hash_time_ns = hash_time_ns
+            .parse::<f64>()
+            .ok()
+            .filter(|hash_time_ns| *hash_time_ns > 0.0 && hash_time_ns.is_finite())
+            .ok_or_else(|| format!("hash time '{}' is not valid", hash_time_ns))?;
+        model.hash_time = hash_time_ns / 1_000_000_000.0;
+    }
+    Ok(model)
+}
+
+/// Parse positive finite number from command line argument
+fn parse_positive(value: &str, name: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| *value > 0.0 && value.is_finite())
+        .ok_or_else(|| format!("{} '{}' is not valid", name, value))
+}
+
+/// Apply per-device settings to the device `model`
+pub fn parse_device(
+    mut model: icarus::Model,
+    frequency: Option<&str>,
+    hashrate: Option<&str>,
+    nonce_timeout_ms: Option<&str>,
+) -> Result<Device, String> {
+    let frequency = match frequency {
+        Some(frequency) => Some(model.set_frequency(parse_positive(frequency, "frequency")?)?),
+        None => None,
+    };
+    // measured hashrate is more precise than hash time derived from frequency
+    if let Some(hashrate) = hashrate {
+        model.hash_time = 1.0 / (parse_positive(hashrate, "hashrate")? * 1_000_000_000.0);
+    }
+    let nonce_timeout = match nonce_timeout_ms {
+        Some(nonce_timeout_ms) => {
+            let nonce_timeout = parse_positive(nonce_timeout_ms, "nonce timeout")? / 1000.0;
+            // check the range before the conversion which panics on overflow
+            if nonce_timeout > MAX_NONCE_TIMEOUT.as_secs_f64() {
+                return Err(format!(
+                    "nonce timeout '{}' is out of range (max {} ms)",
+                    nonce_timeout_ms,
+                    MAX_NONCE_TIMEOUT.as_millis()
+                ));
+            }
+            Some(Duration::from_secs_f64(nonce_timeout))
+        }
+        None => None,
+    };
+    Ok(Device {
+        model,
+        frequency,
+        nonce_timeout,
+    })
+}
+
+/// Icarus device with settings overriding defaults of its model
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Device {
+    /// Model of the device with hash time adjusted to configured frequency or hashrate
+    pub model: icarus::Model,
+    /// Chip frequency in MHz set during initialization
+    pub frequency: Option<f64>,
+    /// Timeout of waiting for nonces of one work, derived from the hash time by default
+    pub nonce_timeout: Option<Duration>,
+}
+
 #[derive(Debug, Default)]
 pub struct Backend {
     client_manager: Option<client::Manager>,
     client_descriptor: Option<ClientDescriptor>,
+    /// Icarus device to look for
+    pub device: Device,
 }
 
 impl Backend {
-    pub fn new(client_descriptor: ClientDescriptor) -> Self {
+    pub fn new(client_descriptor: ClientDescriptor, device: Device) -> Self {
         Self {
             client_manager: None,
             client_descriptor: Some(client_descriptor),
+            device,
         }
     }
 
     pub async fn init_client(self) {
         if let Some(client_descriptor) = self.client_descriptor {
-            let group = self
-                .client_manager
-                .expect("BUG: missing client manager")
-                .create_or_get_default_group()
-                .await;
+            let client_manager = self.client_manager.expect("BUG: missing client manager");
+            let group = client_manager.create_or_get_default_group().await;
 
             group
-                .push_client(client::Handle::new(client_descriptor, None, None))
+                .push_client(client::Handle::new(
+                    client_descriptor,
+                    client::Settings {
+                        backend_info: None,
+                        version_mask: client_manager.version_mask(),
+                        min_midstate_count: client_manager.min_midstate_count(),
+                        extensions: None,
+                        stratum_record_dir: None,
+                    },
+                ))
                 .await;
         }
     }
@@ -78,3 +173,20 @@ impl hal::BackendConfig for Backend {
         self.client_manager.replace(client_manager);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nonce_timeout() {
+        let model = icarus::Model::find("antminer-u1").unwrap().clone();
+        let device =
+            parse_device(model.clone(), None, None, Some("1500")).expect("BUG: invalid device");
+        assert_eq!(device.nonce_timeout, Some(Duration::from_millis(1500)));
+
+        assert!(parse_device(model.clone(), None, None, Some("1e300")).is_err());
+        assert!(parse_device(model.clone(), None, None, Some("inf")).is_err());
+        assert!(parse_device(model, None, None, Some("-1")).is_err());
+    }
+}
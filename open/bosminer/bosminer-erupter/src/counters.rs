@@ -0,0 +1,100 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Solution counters of Block Erupter device
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Device {
+    /// Solutions meeting the ASIC target
+    pub valid: usize,
+    /// Solutions not meeting the ASIC target (hardware errors)
+    pub errors: usize,
+    /// Duplicate solutions (also included in `errors`)
+    pub duplicates: usize,
+    /// Work sent to the device
+    pub works: usize,
+    pub started: Instant,
+    pub stopped: Option<Instant>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Self {
+            valid: 0,
+            errors: 0,
+            duplicates: 0,
+            works: 0,
+            started: Instant::now(),
+            stopped: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.valid = 0;
+        self.errors = 0;
+        self.duplicates = 0;
+        self.works = 0;
+        self.started = Instant::now();
+    }
+
+    /// Create a snapshot of the current state of counters.
+    /// This will set stopped time to current timestamp so that the hashrate will not decay
+    /// from this moment on.
+    pub fn snapshot(&self) -> Self {
+        let mut snapshot = self.clone();
+        snapshot.stopped = Some(Instant::now());
+        snapshot
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.stopped
+            .unwrap_or_else(|| Instant::now())
+            .duration_since(self.started)
+    }
+
+    pub fn add_valid(&mut self) {
+        self.valid += 1;
+    }
+
+    pub fn add_error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Account solution which has already been received for the same work. Duplicates are
+    /// accounted as errors as well so they still degrade the error rate of the device.
+    pub fn add_duplicate(&mut self) {
+        self.add_error();
+        self.duplicates += 1;
+    }
+
+    pub fn add_work(&mut self) {
+        self.works += 1;
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Self::new()
+    }
+}
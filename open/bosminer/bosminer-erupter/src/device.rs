@@ -20,24 +20,29 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-//! Provides Block Erupter USB driver witch translates work generated by `work::Generator` into
-//! a form that is recognized by the hashing chip
+//! Provides USB driver of Icarus devices (e.g. Block Erupter) witch translates work generated by
+//! `work::Generator` into a form that is recognized by the hashing chip
 
-use crate::error::{self, ErrorKind};
+use ii_logging::macros::*;
+
+use crate::config;
+use crate::counters;
+use crate::error::{self, ErrorKind, ResultExt};
 use crate::icarus;
 use crate::Solution;
 
 use bosminer::work;
 
-use failure::{Fail, ResultExt};
-
-use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::mem::size_of;
+use std::sync::Arc;
 use std::time::{self, Duration};
 
-use futures::executor::block_on;
-use ii_async_compat::futures;
+use futures::lock::Mutex;
+use ii_async_compat::prelude::*;
+use lazy_static::lazy_static;
+use tokio::task;
 
 const CP210X_TYPE_OUT: u8 = 0x41;
 const CP210X_REQUEST_IFC_ENABLE: u8 = 0x00;
@@ -46,10 +51,6 @@ const CP210X_REQUEST_BAUD: u8 = 0x1e;
 
 const CP210X_VALUE_UART_ENABLE: u16 = 0x0001;
 const CP210X_VALUE_DATA: u16 = 0x0303;
-const CP210X_DATA_BAUD: u32 = 115200;
-
-const ID_VENDOR: u16 = 0x10c4;
-const ID_PRODUCT: u16 = 0xea60;
 
 const DEVICE_IFACE: u8 = 0;
 const DEVICE_CONFIGURATION: u8 = 1;
@@ -63,29 +64,75 @@ const WAIT_TIMEOUT: Duration = Duration::from_millis(WAIT_TIMEOUT_MS);
 
 /// How many ms below the expected completion time to abort work
 /// extra in case the last read is delayed
-const READ_REDUCE_MS: f64 = WAIT_TIMEOUT_MS as f64 * 1.5;
+const READ_REDUCE: Duration = Duration::from_millis(WAIT_TIMEOUT_MS * 3 / 2);
+
+/// Waiting for nonce is split into reads limited by this timeout so that the blocking thread is
+/// released shortly after the solver is dropped
+const READ_SLICE: Duration = Duration::from_millis(500);
 
-/// Timeout in ms for reading nonce from USB -> UART bridge read
-/// initialization has some latency which is reduced from full nonce time
-const MAX_READ_TIME: Duration =
-    Duration::from_millis((icarus::FULL_NONCE_TIME_MS - READ_REDUCE_MS) as u64);
+/// Shortest read of nonce, remaining time of the work shorter than this is not worth waiting
+const MIN_READ_TIME: Duration = Duration::from_millis(1);
+
+/// Extra time given to blocking USB transfer over its own timeout before the device is
+/// considered hung
+const DEVICE_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// USB context lives for the whole program so that the device handles can be moved into
+    /// blocking tasks
+    static ref USB_CONTEXT: Result<libusb::Context, libusb::Error> = libusb::Context::new();
+}
+
+/// Get USB context shared by all devices
+pub fn usb_context() -> error::Result<&'static libusb::Context> {
+    USB_CONTEXT
+        .as_ref()
+        .map_err(|e| error::Error::with_source(ErrorKind::Usb("cannot create USB context"), *e))
+}
 
 pub struct BlockErupter<'a> {
     context: &'a libusb::Context,
     device: libusb::DeviceHandle<'a>,
+    config: config::Device,
 }
 
 impl<'a> BlockErupter<'a> {
-    pub fn new(context: &'a libusb::Context, device: libusb::DeviceHandle<'a>) -> Self {
-        Self { context, device }
+    pub fn new(
+        context: &'a libusb::Context,
+        device: libusb::DeviceHandle<'a>,
+        config: config::Device,
+    ) -> Self {
+        Self {
+            context,
+            device,
+            config,
+        }
     }
 
-    /// Try to find Block Erupter connected to USB
-    /// Only first device is returned when multiple Block Erupters are connected.
-    pub fn find(context: &'a libusb::Context) -> Option<Self> {
+    /// Try to find device of configured model connected to USB
+    /// Only first device is returned when multiple devices are connected.
+    pub fn find(context: &'a libusb::Context, config: config::Device) -> Option<Self> {
         context
-            .open_device_with_vid_pid(ID_VENDOR, ID_PRODUCT)
-            .map(|device| Self::new(context, device))
+            .open_device_with_vid_pid(config.model.vendor_id, config.model.product_id)
+            .map(|device| Self::new(context, device, config))
+    }
+
+    #[inline]
+    pub fn model(&self) -> &icarus::Model {
+        &self.config.model
+    }
+
+    /// Timeout for reading nonce from USB -> UART bridge read
+    /// initialization has some latency which is reduced from full nonce time
+    /// (the timeout can be also configured explicitly)
+    pub fn max_read_time(&self) -> Duration {
+        self.config.nonce_timeout.unwrap_or_else(|| {
+            self.config
+                .model
+                .work_time()
+                .checked_sub(READ_REDUCE)
+                .unwrap_or(WAIT_TIMEOUT)
+        })
     }
 
     /// Initialize Block Erupter device to accept work to solution
@@ -142,11 +189,49 @@ impl<'a> BlockErupter<'a> {
                 CP210X_REQUEST_BAUD,
                 0,
                 0,
-                &CP210X_DATA_BAUD.to_le_bytes(),
+                &self.config.model.baud_rate.to_le_bytes(),
                 WAIT_TIMEOUT,
             )
             .with_context(|_| ErrorKind::Usb("cannot set baud rate"))?;
 
+        if let Some(frequency) = self.config.frequency {
+            self.set_frequency(frequency)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set chip PLL to `frequency` in MHz (supported only by models with adjustable clock)
+    pub fn set_frequency(&self, frequency: f64) -> error::Result<()> {
+        self.device
+            .write_bulk(
+                WRITE_ADDR,
+                &icarus::set_frequency_command(frequency),
+                WAIT_TIMEOUT,
+            )
+            .with_context(|_| ErrorKind::Usb("cannot set frequency"))?;
+        // the new frequency is applied by reading the PLL register
+        self.device
+            .write_bulk(WRITE_ADDR, &icarus::read_pll_command(), WAIT_TIMEOUT)
+            .with_context(|_| ErrorKind::Usb("cannot read PLL register"))?;
+        // discard the content of the register
+        let mut register = [0u8; icarus::COMMAND_SIZE];
+        match self
+            .device
+            .read_bulk(READ_ADDR, &mut register, WAIT_TIMEOUT)
+        {
+            Ok(_) | Err(libusb::Error::Timeout) => {}
+            Err(e) => {
+                return Err(error::Error::with_source(
+                    ErrorKind::Usb("cannot read PLL register"),
+                    e,
+                ))
+            }
+        }
+        info!(
+            "{}: frequency set to {} MHz",
+            self.config.model.description, frequency
+        );
         Ok(())
     }
 
@@ -164,7 +249,7 @@ impl<'a> BlockErupter<'a> {
     /// The work have to be previously send using `send_work` method.
     /// More solution may exist so this method must be called multiple times to get all of them.
     /// When all search space is exhausted then the chip stops finding new nonce. The maximal time
-    /// of searching is constant for this chip and after this time no new solution is found.
+    /// of searching is constant for the device model and after this time no new solution is found.
     /// The `None` is returned then timeout occurs and any nonce is found.
     /// It is possible that during sending new work the nonce for old one can be found and returned
     /// from this method!
@@ -180,73 +265,126 @@ impl<'a> BlockErupter<'a> {
                     .expect("slice with incorrect length"))
             }
             Err(libusb::Error::Timeout) => Ok(None),
-            Err(e) => Err(e.context(ErrorKind::Usb("cannot read nonce")).into()),
+            Err(e) => Err(error::Error::with_source(
+                ErrorKind::Usb("cannot read nonce"),
+                e,
+            )),
         }
     }
+}
 
-    /// Converts Block Erupter device into iterator which solving generated work
-    pub fn into_solver(self, work_generator: work::Generator) -> BlockErupterSolver<'a> {
-        BlockErupterSolver::new(self, work_generator)
+impl BlockErupter<'static> {
+    /// Find the first device of configured model connected to USB and initialize it
+    pub async fn open(config: config::Device) -> error::Result<Self> {
+        task::spawn_blocking(move || {
+            let mut device = Self::find(usb_context()?, config)
+                .ok_or_else(|| ErrorKind::Usb("cannot find device"))?;
+            device.init()?;
+            Ok(device)
+        })
+        .await
+        .context(ErrorKind::Usb("device initialization has failed"))?
+    }
+
+    /// Converts Block Erupter device into solver of generated work
+    pub fn into_solver(
+        self,
+        work_generator: work::Generator,
+        counter: Arc<Mutex<counters::Device>>,
+    ) -> Solver {
+        Solver::new(self, work_generator, counter)
     }
 }
 
-/// Wrap the Block Erupter device and work generator to implement iterable object which solves
-/// incoming work and tries to find solution which is returned as an unique mining work solution
-pub struct BlockErupterSolver<'a> {
-    device: BlockErupter<'a>,
-    work_generator: work::Generator,
-    work_start: time::Instant,
-    curr_work: Option<work::Assignment>,
-    next_solution: Option<work::Solution>,
+/// Work which is currently solved by the device
+struct CurrentWork {
+    work: work::Assignment,
+    start: time::Instant,
     solution_idx: usize,
-    stop_reason: RefCell<error::Result<()>>,
+    /// Nonces already found for this work to detect duplicate solutions
+    nonces: Vec<u32>,
 }
 
-impl<'a> BlockErupterSolver<'a> {
-    fn new(device: BlockErupter<'a>, work_generator: work::Generator) -> Self {
+/// Solves incoming work with the Icarus device and returns found solutions as unique
+/// mining work solutions. USB transfers are blocking so each of them runs in the blocking thread
+/// pool but it is limited by a short timeout. Dropping the solver (e.g. when the miner halts)
+/// therefore never leaves a thread blocked on the device for a long time.
+pub struct Solver {
+    device: Arc<BlockErupter<'static>>,
+    work_generator: work::Generator,
+    curr_work: Option<CurrentWork>,
+    prev_work: Option<(work::Assignment, usize)>,
+    pending_solutions: VecDeque<work::Solution>,
+    counter: Arc<Mutex<counters::Device>>,
+}
+
+impl Solver {
+    fn new(
+        device: BlockErupter<'static>,
+        work_generator: work::Generator,
+        counter: Arc<Mutex<counters::Device>>,
+    ) -> Self {
         Self {
-            device,
+            device: Arc::new(device),
             work_generator,
-            work_start: time::Instant::now(),
             curr_work: None,
-            next_solution: None,
-            solution_idx: 0,
-            stop_reason: RefCell::new(Ok(())),
+            prev_work: None,
+            pending_solutions: VecDeque::new(),
+            counter,
         }
     }
 
-    /// Consume the iterator and return the reason of stream termination
-    pub fn get_stop_reason(self) -> error::Result<()> {
-        // the object is consumed so replacing with `Ok` is fine
-        self.stop_reason.replace(Ok(()))
+    /// Run blocking USB transfer `f` which is limited by `timeout`. The device is considered hung
+    /// when the transfer doesn't return in time.
+    async fn blocking<F, T>(&self, timeout: Duration, f: F) -> error::Result<T>
+    where
+        F: FnOnce(&BlockErupter<'static>) -> error::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let device = self.device.clone();
+        task::spawn_blocking(move || f(&device))
+            .timeout(timeout + DEVICE_TIMEOUT_MARGIN)
+            .await
+            .map_err(|_| ErrorKind::Usb("device is not responding"))?
+            .context(ErrorKind::Usb("USB transfer has failed"))?
     }
 
-    fn send_work(&mut self, work: &work::Assignment) {
+    /// Take new work from work generator and send it to the device. Returns `false` when there
+    /// is no more work.
+    async fn next_work(&mut self) -> error::Result<bool> {
+        self.prev_work = self
+            .curr_work
+            .take()
+            .map(|curr_work| (curr_work.work, curr_work.solution_idx));
+        let work = match self.work_generator.generate().await {
+            // end of stream
+            None => return Ok(false),
+            Some(work) => work,
+        };
+
         let work_payload = icarus::WorkPayload::new(
             &work.midstates[0].state,
             work.merkle_root_tail(),
             work.ntime,
             work.bits(),
         );
-        self.work_start = time::Instant::now();
-        self.device.send_work(work_payload).unwrap_or_else(|e| {
-            *self.stop_reason.get_mut() = Err(e);
+        self.blocking(WAIT_TIMEOUT, move |device| device.send_work(work_payload))
+            .await?;
+        self.counter.lock().await.add_work();
+
+        self.curr_work = Some(CurrentWork {
+            work,
+            start: time::Instant::now(),
+            solution_idx: 0,
+            nonces: Vec::new(),
         });
+        Ok(true)
     }
 
-    fn wait_for_nonce(&self) -> Option<(u32, time::Instant)> {
-        let duration = time::Instant::now().duration_since(self.work_start);
-        let timeout_rem = MAX_READ_TIME.checked_sub(duration).unwrap_or(WAIT_TIMEOUT);
-
-        self.device
-            .wait_for_nonce(timeout_rem)
-            .unwrap_or_else(|e| {
-                // return `None` to indicate that nonce wasn't found and store error to the object
-                // the stop reason can be later obtained with `get_stop_reason`
-                *self.stop_reason.borrow_mut() = Err(e);
-                None
-            })
-            .map(|nonce| (nonce, time::Instant::now()))
+    /// Wait for nonce of current work at most for `timeout`
+    async fn wait_for_nonce(&self, timeout: Duration) -> error::Result<Option<u32>> {
+        self.blocking(timeout, move |device| device.wait_for_nonce(timeout))
+            .await
     }
 
     fn create_unique_solution(
@@ -257,74 +395,86 @@ impl<'a> BlockErupterSolver<'a> {
     ) -> work::Solution {
         work::Solution::new(work, Solution::new(nonce, solution_idx), Some(timestamp))
     }
-}
 
-impl<'a> Iterator for BlockErupterSolver<'a> {
-    type Item = work::Solution;
+    /// Convert `nonce` found by the device into solutions waiting for being returned
+    async fn process_nonce(&mut self, nonce: u32, timestamp: time::Instant) {
+        let curr_work = self
+            .curr_work
+            .as_mut()
+            .expect("BUG: nonce without current work");
+        if curr_work.nonces.contains(&nonce) {
+            self.counter.lock().await.add_duplicate();
+            return;
+        }
+        curr_work.nonces.push(nonce);
+
+        let mut solutions = Vec::with_capacity(2);
+        // when solution has been found very quickly then it is possible that the nonce
+        // corresponds to previous work, the work validation determines if the nonce is
+        // solution for old work or new one
+        if let Some((prev_work, prev_solution_idx)) = self.prev_work.take() {
+            solutions.push(Self::create_unique_solution(
+                prev_work,
+                nonce,
+                timestamp,
+                prev_solution_idx,
+            ));
+        }
+        solutions.push(Self::create_unique_solution(
+            curr_work.work.clone(),
+            nonce,
+            timestamp,
+            curr_work.solution_idx,
+        ));
+        // increment counter for next solution id
+        curr_work.solution_idx = curr_work
+            .solution_idx
+            .checked_add(1)
+            .expect("too many solutions");
+
+        if solutions
+            .iter()
+            .any(|solution| solution.hash().meets(solution.backend_target()))
+        {
+            self.counter.lock().await.add_valid();
+        } else {
+            info!(
+                "{}: solution not hitting ASIC target",
+                self.device.model().description
+            );
+            self.counter.lock().await.add_error();
+        }
+        self.pending_solutions.extend(solutions);
+    }
 
-    /// Waits for new work and send it to the Block Erupter device
+    /// Waits for new work and send it to the Icarus device.
     /// When the solution is found then the result is returned as an unique mining work solution.
-    /// When an error occurs then `None` is returned and the failure reason can be obtained with
-    /// `get_stop_reason` method which consumes the iterator.
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(solution) = self.next_solution.take() {
-            // return solution for new work
-            // this solves the issue when the solution is found for old work during sending new one
-            // the work validation determines if the nonce is solution for old work or new one
-            return Some(solution);
-        }
-        let mut prev_work = None;
-        while self.stop_reason.borrow().is_ok() {
-            if let Some(work) = &self.curr_work {
-                // waiting for solution for maximal remaining time
-                if let Some((nonce, timestamp)) = self.wait_for_nonce() {
-                    // found solution!
-                    let solution = Self::create_unique_solution(
-                        work.clone(),
-                        nonce,
-                        timestamp,
-                        self.solution_idx,
-                    );
-                    // increment counter for next solution id
-                    self.solution_idx = self
-                        .solution_idx
-                        .checked_add(1)
-                        .expect("too many solutions");
-                    return Some(match prev_work.take() {
-                        None => solution,
-                        // when solution has been found very quickly then it is possible that the
-                        // nonce corresponds to previous work
-                        Some((prev_work, prev_solution_idx)) => {
-                            self.next_solution = Some(solution);
-                            Self::create_unique_solution(
-                                prev_work,
-                                nonce,
-                                timestamp,
-                                prev_solution_idx,
-                            )
-                        }
-                    });
+    /// `None` is returned when the stream from work generator is closed.
+    pub async fn next(&mut self) -> error::Result<Option<work::Solution>> {
+        loop {
+            if let Some(solution) = self.pending_solutions.pop_front() {
+                return Ok(Some(solution));
+            }
+            // remaining time for searching the nonce space of current work
+            let max_read_time = self.device.max_read_time();
+            let timeout_rem = self.curr_work.as_ref().and_then(|curr_work| {
+                max_read_time.checked_sub(time::Instant::now().duration_since(curr_work.start))
+            });
+            match timeout_rem {
+                // zero timeout would wait for the nonce indefinitely
+                Some(timeout_rem) if timeout_rem >= MIN_READ_TIME => {
+                    if let Some(nonce) = self.wait_for_nonce(timeout_rem.min(READ_SLICE)).await? {
+                        self.process_nonce(nonce, time::Instant::now()).await;
+                    }
                 }
-                if self.stop_reason.borrow().is_err() {
-                    // some error occurs during waiting for solution
-                    break;
+                // the whole search space has been exhausted (or there is no work yet)
+                _ => {
+                    if !self.next_work().await? {
+                        return Ok(None);
+                    }
                 }
             }
-
-            prev_work = self.curr_work.take().map(|work| (work, self.solution_idx));
-            match block_on(self.work_generator.generate()) {
-                // end of stream
-                None => break,
-                // send new work and wait for result in the next iteration when no error occurs
-                Some(work) => {
-                    self.send_work(&work);
-                    self.curr_work = Some(work);
-                    self.solution_idx = 0;
-                }
-            };
         }
-        // some error occurs or stream from work generator is closed
-        None
     }
 }
 
@@ -337,12 +487,8 @@ pub mod test {
     use std::ops::{Deref, DerefMut};
     use std::sync;
 
-    use lazy_static::lazy_static;
-
     lazy_static! {
         pub static ref USB_CONTEXT_MUTEX: sync::Mutex<()> = sync::Mutex::new(());
-        pub static ref USB_CONTEXT: libusb::Context =
-            libusb::Context::new().expect("cannot create new USB context");
     }
 
     struct BlockErupterGuard<'a> {
@@ -384,7 +530,8 @@ pub mod test {
         // lock USB context for mutual exclusion
         let mut context_guard = Some(USB_CONTEXT_MUTEX.lock().expect("cannot lock USB context"));
 
-        let mut device = BlockErupter::find(&*USB_CONTEXT).unwrap_or_else(|| {
+        let usb_context = usb_context().expect("cannot create new USB context");
+        let mut device = BlockErupter::find(usb_context, Default::default()).unwrap_or_else(|| {
             // unlock the guard before panicking the thread!
             context_guard.take();
             panic!("cannot find Block Erupter device")
@@ -422,7 +569,7 @@ pub mod test {
                 .expect("cannot send work to Block Erupter");
 
             // wait for solution
-            let timeout = MAX_READ_TIME;
+            let timeout = device.max_read_time();
             let mut timeout_rem = timeout;
             let mut nonce_found = false;
 
@@ -450,20 +597,21 @@ pub mod test {
         }
     }
 
-    #[test]
-    fn test_block_erupter_solver() {
+    #[tokio::test]
+    async fn test_block_erupter_solver() {
         let work_solver = test_utils::create_test_work_solver();
         let work_generator = test_utils::create_test_work_generator(work_solver.clone());
         let (device, _device_guard) = get_block_erupter().into_device();
 
         // convert Block Erupter device to work solver
         // the work is generated from test work generator
-        let mut solver = device.into_solver(work_generator);
+        let counter = Arc::new(Mutex::new(counters::Device::new()));
+        let mut solver = device.into_solver(work_generator, counter.clone());
 
         let mut blocks_iter = test_utils::TEST_BLOCKS.iter();
         let mut block = blocks_iter.next().expect("there is no test block");
 
-        for solution in &mut solver {
+        while let Some(solution) = solver.next().await.expect("solver failed") {
             if &block.hash == solution.hash() {
                 // when solution has been found for current block then
                 // move to the next one and wait for its solution
@@ -474,7 +622,7 @@ pub mod test {
                 };
             }
         }
-        solver.get_stop_reason().expect("solver failed");
+        assert!(counter.lock().await.valid > 0);
         assert!(
             blocks_iter.next().is_none(),
             "Block Erupter solver does not solve all test blocks"
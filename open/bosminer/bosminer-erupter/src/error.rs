@@ -22,66 +22,92 @@
 
 //! The Block erupter errors
 
-use failure::{Backtrace, Context, Fail};
-use std::fmt::{self, Debug, Display};
+use thiserror::Error;
 
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+
+/// Underlying error that caused `Error`
+pub type Source = Box<dyn StdError + Send + Sync + 'static>;
+
+#[derive(Debug)]
 pub struct Error {
-    inner: Context<ErrorKind>,
+    kind: ErrorKind,
+    source: Option<Source>,
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum ErrorKind {
     /// Lib USB error.
-    #[fail(display = "USB: {}", _0)]
+    #[error("USB: {0}")]
     Usb(&'static str),
     /// Error related to time measurement.
-    #[fail(display = "Timer: {}", _0)]
+    #[error("Timer: {0}")]
     Timer(&'static str),
 }
 
-/// Implement Fail trait instead of use Derive to get more control over custom type.
-/// The main advantage is customization of Context type which allows conversion of
-/// any error types to this custom error with general error kind by calling context
-/// method on any result type.
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
+impl Error {
+    /// Create error of `kind` caused by `source`
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: Into<Source>,
+    {
+        Self {
+            kind,
+            source: Some(source.into()),
+        }
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+    pub fn kind(&self) -> ErrorKind {
+        self.kind.clone()
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
+        Display::fmt(&self.kind, f)
     }
 }
 
-impl Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Debug::fmt(&self.inner, f)
-    }
-}
-
-impl Error {
-    pub fn kind(&self) -> ErrorKind {
-        self.inner.get_context().clone()
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn StdError + 'static))
     }
 }
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
-        Self {
-            inner: Context::new(kind),
-        }
+        Self { kind, source: None }
     }
 }
 
-impl From<Context<ErrorKind>> for Error {
-    fn from(inner: Context<ErrorKind>) -> Self {
-        Self { inner }
+/// Attach `ErrorKind` context to an error while keeping the original error as a source
+pub trait ResultExt<T, E> {
+    fn context(self, kind: ErrorKind) -> Result<T>;
+
+    fn with_context<F>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> ErrorKind;
+}
+
+impl<T, E> ResultExt<T, E> for std::result::Result<T, E>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn context(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|e| Error::with_source(kind, e))
+    }
+
+    fn with_context<F>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> ErrorKind,
+    {
+        self.map_err(|e| {
+            let kind = f(&e);
+            Error::with_source(kind, e)
+        })
     }
 }
 
@@ -97,5 +123,11 @@ impl From<ErrorKind> for bosminer::error::Error {
     }
 }
 
+impl From<ErrorKind> for bosminer::error::ErrorKind {
+    fn from(kind: ErrorKind) -> Self {
+        bosminer::error::ErrorKind::Backend(kind.to_string())
+    }
+}
+
 /// A specialized `Result` type bound to [`Error`].
 pub type Result<T> = std::result::Result<T, Error>;
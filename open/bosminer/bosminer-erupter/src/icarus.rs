@@ -20,12 +20,13 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-//! Provides Icarus hashing chip driver
+//! Provides Icarus hashing chip driver and parameters of USB miners speaking Icarus protocol
 
 use packed_struct::prelude::*;
 use packed_struct_codegen::PackedStruct;
 
 use std::mem::size_of;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
 
@@ -34,11 +35,213 @@ lazy_static! {
     pub static ref ASIC_TARGET: ii_bitcoin::Target = Default::default();
 }
 
-// time for computation one double hash and target comparison in seconds
-pub const HASH_TIME_S: f64 = 0.0000000029761;
+/// Number of nonces in the whole search space of one work
+pub const FULL_NONCE_RANGE: u64 = 0x1_0000_0000;
+
+/// USB vendor ID of Silicon Labs CP210x USB to UART bridge
+pub const CP210X_VENDOR_ID: u16 = 0x10c4;
+/// USB product ID of Silicon Labs CP210x USB to UART bridge
+pub const CP210X_PRODUCT_ID: u16 = 0xea60;
+
+/// Name of the model used when none is configured
+pub const DEFAULT_MODEL: &str = "block-erupter";
+
+/// Reference clock of the chip PLL in MHz
+pub const PLL_REFERENCE_FREQUENCY: f64 = 25.0;
+
+/// Adjustable clock of the chip (frequencies in MHz)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clock {
+    /// Frequency the hash time of the model corresponds to
+    pub default: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Identification and hashing parameters of a USB miner speaking Icarus protocol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    /// Name used for selecting the model in configuration
+    pub name: &'static str,
+    /// Human readable name of the device
+    pub description: &'static str,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Baud rate of the USB to UART bridge
+    pub baud_rate: u32,
+    /// Time for computation of one double hash and target comparison in seconds
+    pub hash_time: f64,
+    /// Number of nonces the device searches for one work before it stops
+    pub nonce_range: u64,
+    /// Clock of devices which allow setting the chip frequency
+    pub clock: Option<Clock>,
+    /// Chip frequency in MHz the hash time corresponds to, `None` for the default frequency
+    pub frequency: Option<f64>,
+}
+
+/// Table of supported devices. Devices with the same USB IDs cannot be told apart so the model
+/// has to be selected in configuration. Hash time corresponds to the default frequency of the
+/// device.
+pub const MODELS: &[Model] = &[
+    Model {
+        name: DEFAULT_MODEL,
+        description: "ASICMiner Block Erupter",
+        vendor_id: CP210X_VENDOR_ID,
+        product_id: CP210X_PRODUCT_ID,
+        baud_rate: 115200,
+        hash_time: 0.0000000029761,
+        nonce_range: FULL_NONCE_RANGE,
+        clock: None,
+        frequency: None,
+    },
+    Model {
+        name: "antminer-u1",
+        description: "Bitmain Antminer U1",
+        vendor_id: CP210X_VENDOR_ID,
+        product_id: CP210X_PRODUCT_ID,
+        baud_rate: 115200,
+        hash_time: 0.000000000625,
+        nonce_range: FULL_NONCE_RANGE,
+        clock: Some(Clock {
+            default: 200.0,
+            min: 125.0,
+            max: 500.0,
+        }),
+        frequency: None,
+    },
+    Model {
+        name: "antminer-u2",
+        description: "Bitmain Antminer U2",
+        vendor_id: CP210X_VENDOR_ID,
+        product_id: CP210X_PRODUCT_ID,
+        baud_rate: 115200,
+        hash_time: 0.0000000005,
+        nonce_range: FULL_NONCE_RANGE,
+        clock: Some(Clock {
+            default: 250.0,
+            min: 125.0,
+            max: 500.0,
+        }),
+        frequency: None,
+    },
+    Model {
+        name: "compac",
+        description: "GekkoScience Compac",
+        vendor_id: CP210X_VENDOR_ID,
+        product_id: CP210X_PRODUCT_ID,
+        baud_rate: 115200,
+        hash_time: 0.000000000125,
+        nonce_range: FULL_NONCE_RANGE,
+        clock: None,
+        frequency: None,
+    },
+];
+
+impl Model {
+    /// Find model by its `name`
+    pub fn find(name: &str) -> Option<&'static Self> {
+        MODELS.iter().find(|model| model.name == name)
+    }
+
+    /// Time needed for iteration of the whole search space of one work
+    pub fn work_time(&self) -> Duration {
+        Duration::from_secs_f64(self.hash_time * self.nonce_range as f64)
+    }
+
+    /// Expected hashrate of the device
+    pub fn nominal_hashrate(&self) -> ii_bitcoin::HashesUnit {
+        ii_bitcoin::HashesUnit::KiloHashes((1.0 / self.hash_time) / 1000.0)
+    }
+
+    /// Adjust hash time to chip `frequency` in MHz. Returns the frequency which can be really
+    /// set by the PLL.
+    pub fn set_frequency(&mut self, frequency: f64) -> Result<f64, String> {
+        let clock = self
+            .clock
+            .ok_or_else(|| format!("frequency of '{}' cannot be set", self.name))?;
+        if !(frequency >= clock.min && frequency <= clock.max) {
+            return Err(format!(
+                "frequency {} MHz is out of range {}..={} MHz",
+                frequency, clock.min, clock.max
+            ));
+        }
+        let (_, frequency) = pll_register(frequency);
+        // derive hash time from its value at the default frequency so that repeated calls do not
+        // compound
+        let base_hash_time =
+            self.hash_time * self.frequency.unwrap_or(clock.default) / clock.default;
+        self.hash_time = base_hash_time * clock.default / frequency;
+        self.frequency = Some(frequency);
+        Ok(frequency)
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::find(DEFAULT_MODEL)
+            .expect("BUG: missing default model")
+            .clone()
+    }
+}
+
+/// Find PLL register value with output frequency closest to `frequency` in MHz. Returns the
+/// register value together with its output frequency.
+pub fn pll_register(frequency: f64) -> (u16, f64) {
+    let mut best = (0, 0.0);
+    let mut best_diff = f64::INFINITY;
+    for od in 0..4u16 {
+        let no = (1 << od) as f64;
+        for n in 0..16u16 {
+            let nr = (n + 1) as f64;
+            for m in 0..64u16 {
+                let nf = (m + 1) as f64;
+                let output = PLL_REFERENCE_FREQUENCY * nf / (nr * no);
+                let diff = (output - frequency).abs();
+                if diff >= best_diff {
+                    continue;
+                }
+                // band select of VCO
+                let bs = (500.0..=1000.0).contains(&(output * no)) as u16;
+                best = ((bs << 14) | (m << 7) | (n << 2) | od, output);
+                best_diff = diff;
+                if diff == 0.0 {
+                    return best;
+                }
+            }
+        }
+    }
+    best
+}
+
+/// CRC5 of the first `bits` of `data` used by chip commands
+fn crc5(data: &[u8], bits: usize) -> u8 {
+    let mut crc = 0x1f;
+    for i in 0..bits {
+        let bit = (data[i / 8] >> (7 - i % 8)) & 1;
+        let feedback = ((crc >> 4) & 1) ^ bit;
+        crc = ((crc << 1) & 0x1f) ^ (feedback * 0x05);
+    }
+    crc
+}
+
+/// Size of chip command
+pub const COMMAND_SIZE: usize = 4;
+
+/// Command setting PLL of the chip to `frequency` in MHz
+pub fn set_frequency_command(frequency: f64) -> [u8; COMMAND_SIZE] {
+    let (register, _) = pll_register(frequency);
+    let mut command = [0x82, (register >> 8) as u8, register as u8, 0];
+    command[3] = crc5(&command, 27);
+    command
+}
+
+/// Command reading the PLL register which applies the new frequency
+pub fn read_pll_command() -> [u8; COMMAND_SIZE] {
+    let mut command = [0x84, 0x00, 0x04, 0];
+    command[3] = crc5(&command, 27);
+    command
+}
 
-/// Time needed for iteration of the whole search space in milliseconds
-pub const FULL_NONCE_TIME_MS: f64 = (HASH_TIME_S * (0xffffffffu64 + 1u64) as f64) * 1000f64;
 /// Size of work structure required by the chip
 pub const WORK_PAYLOAD_SIZE: usize = 64;
 
@@ -94,6 +297,40 @@ pub mod test {
     use bosminer::job::Bitcoin;
     use bosminer::test_utils;
 
+    #[test]
+    fn test_models() {
+        let model = Model::default();
+        assert_eq!(model.name, DEFAULT_MODEL);
+        assert_eq!(model.work_time().as_millis(), 12782);
+        assert_eq!(
+            Model::find("antminer-u1").unwrap().work_time().as_millis(),
+            2684
+        );
+        assert!(Model::find("unknown").is_none());
+
+        // model names are used in configuration so they have to be unique
+        for (i, model) in MODELS.iter().enumerate() {
+            assert!(MODELS[i + 1..].iter().all(|other| other.name != model.name));
+        }
+    }
+
+    #[test]
+    fn test_frequency() {
+        assert_eq!(pll_register(200.0), (0x0380, 200.0));
+        assert_eq!(pll_register(212.5), (0x0804, 212.5));
+
+        let mut model = Model::find("antminer-u1").unwrap().clone();
+        assert_eq!(model.set_frequency(250.0), Ok(250.0));
+        assert_eq!(model.work_time().as_millis(), 2147);
+        // setting the same frequency again doesn't change hash time
+        assert_eq!(model.set_frequency(250.0), Ok(250.0));
+        assert_eq!(model.work_time().as_millis(), 2147);
+        assert_eq!(model.set_frequency(200.0), Ok(200.0));
+        assert_eq!(model.work_time().as_millis(), 2684);
+        assert!(model.set_frequency(1000.0).is_err());
+        assert!(Model::default().set_frequency(200.0).is_err());
+    }
+
     #[test]
     fn test_work_payload() {
         for block in test_utils::TEST_BLOCKS.iter() {
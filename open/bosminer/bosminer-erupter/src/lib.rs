@@ -23,28 +23,28 @@
 use ii_logging::macros::*;
 
 pub mod config;
+pub mod counters;
 pub mod device;
 pub mod error;
 pub mod icarus;
 
 use bosminer::async_trait;
-use bosminer::error::backend::ResultExt;
 use bosminer::hal;
 use bosminer::node;
 use bosminer::stats;
 use bosminer::work;
 use bosminer_macros::WorkSolverNode;
 
-use error::ErrorKind;
-
-use ii_async_compat::tokio;
-use tokio::task;
+use futures::lock::Mutex;
+use ii_async_compat::prelude::*;
+use ii_async_compat::select;
+use tokio::signal::unix::{signal, SignalKind};
 
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
 
-/// Represents raw solution from the Block Erupter
+/// Represents raw solution from the Icarus device
 #[derive(Debug)]
 pub struct Solution {
     /// Actual nonce
@@ -89,29 +89,37 @@ impl hal::BackendSolution for Solution {
 pub struct Backend {
     #[member_work_solver_stats]
     work_solver_stats: stats::BasicWorkSolver,
-    work_generator: Mutex<Option<work::Generator>>,
+    work_generator: StdMutex<Option<work::Generator>>,
     solution_sender: work::SolutionSender,
+    /// Icarus device with its settings
+    device: config::Device,
+    /// Solution counters of the device
+    pub counter: Arc<Mutex<counters::Device>>,
 }
 
 impl Backend {
-    pub fn new(work_generator: work::Generator, solution_sender: work::SolutionSender) -> Self {
+    pub fn new(
+        work_generator: work::Generator,
+        solution_sender: work::SolutionSender,
+        device: config::Device,
+    ) -> Self {
         Self {
             work_solver_stats: Default::default(),
-            work_generator: Mutex::new(Some(work_generator)),
+            work_generator: StdMutex::new(Some(work_generator)),
             solution_sender,
+            device,
+            counter: Arc::new(Mutex::new(counters::Device::new())),
         }
     }
 
-    fn run(&self) -> bosminer::error::Result<()> {
-        info!("Block Erupter: finding device in USB...");
-        let usb_context =
-            libusb::Context::new().context(ErrorKind::Usb("cannot create USB context"))?;
-        let mut device = device::BlockErupter::find(&usb_context)
-            .ok_or_else(|| ErrorKind::Usb("cannot find Block Erupter device"))?;
+    pub async fn snapshot_counter(&self) -> counters::Device {
+        self.counter.lock().await.snapshot()
+    }
 
-        info!("Block Erupter: initialization...");
-        device.init()?;
-        info!("Block Erupter: initialized and ready to solve the work!");
+    async fn run(&self) -> bosminer::error::Result<()> {
+        info!("{}: finding device in USB and initializing it...", self);
+        let device = device::BlockErupter::open(self.device.clone()).await?;
+        info!("{}: initialized and ready to solve the work!", self);
 
         let mut solver = device.into_solver(
             self.work_generator
@@ -119,41 +127,65 @@ impl Backend {
                 .expect("cannot lock work generator")
                 .take()
                 .expect("missing work generator"),
+            self.counter.clone(),
         );
 
         // iterate until there exists any work or the error occurs
-        for solution in &mut solver {
+        while let Some(solution) = solver.next().await? {
             self.solution_sender.send(solution);
         }
-
-        // check solver for errors
-        solver.get_stop_reason()?;
         Ok(())
     }
 
+    /// Wait for `SIGINT`, `SIGHUP` or `SIGTERM`
+    async fn wait_for_termination_signal() {
+        let mut interrupt = signal(SignalKind::interrupt()).expect("BUG: failed hooking signal");
+        let mut hangup = signal(SignalKind::hangup()).expect("BUG: failed hooking signal");
+        let mut terminate = signal(SignalKind::terminate()).expect("BUG: failed hooking signal");
+        select! {
+            _ = interrupt.recv().fuse() => {}
+            _ = hangup.recv().fuse() => {}
+            _ = terminate.recv().fuse() => {}
+        }
+    }
+
     fn enable(self: Arc<Self>) {
-        // Spawn the future in a separate blocking pool (for blocking operations)
-        // so that this doesn't block the regular threadpool.
-        task::spawn_blocking(move || {
-            if let Err(e) = self.run() {
-                error!("{}", e);
+        let (halt_trigger, halt_tripwire) = Tripwire::new();
+        let solver_handle = tokio::spawn(async move {
+            select! {
+                result = self.run().fuse() => {
+                    if let Err(e) = result {
+                        error!("{}", e);
+                    }
+                }
+                _ = halt_tripwire.fuse() => {
+                    info!("{}: solver halted", self);
+                }
             }
         });
+
+        // Halt the solver on termination signals so that the program doesn't exit in the middle
+        // of USB transfer
+        tokio::spawn(async move {
+            Self::wait_for_termination_signal().await;
+            drop(halt_trigger);
+            let _ = solver_handle.await;
+            println!("Exiting.");
+            std::process::exit(0);
+        });
     }
 }
 
 #[async_trait]
 impl node::WorkSolver for Backend {
     async fn get_nominal_hashrate(&self) -> Option<ii_bitcoin::HashesUnit> {
-        Some(ii_bitcoin::HashesUnit::KiloHashes(
-            (1.0 / icarus::HASH_TIME_S) / 1000.0,
-        ))
+        Some(self.device.model.nominal_hashrate())
     }
 }
 
 impl fmt::Display for Backend {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Block Erupter")
+        write!(f, "{}", self.device.model.description)
     }
 }
 
@@ -165,9 +197,10 @@ impl hal::Backend for Backend {
     const DEFAULT_HASHRATE_INTERVAL: Duration = config::DEFAULT_HASHRATE_INTERVAL;
     const JOB_TIMEOUT: Duration = config::JOB_TIMEOUT;
 
-    fn create(_backend_config: &mut config::Backend) -> hal::WorkNode<Self> {
-        node::WorkSolverType::WorkSolver(Box::new(|work_generator, solution_sender| {
-            Self::new(work_generator, solution_sender)
+    fn create(backend_config: &mut config::Backend) -> hal::WorkNode<Self> {
+        let device = backend_config.device.clone();
+        node::WorkSolverType::WorkSolver(Box::new(move |work_generator, solution_sender| {
+            Self::new(work_generator, solution_sender, device)
         }))
     }
 
@@ -190,6 +223,7 @@ impl hal::Backend for Backend {
 
         Ok(hal::FrontendConfig {
             cgminer_custom_commands: None,
+            restart_handler: None,
         })
     }
 }
@@ -22,7 +22,7 @@
 
 use ii_logging::macros::*;
 
-use bosminer_erupter::config;
+use bosminer_erupter::{config, icarus};
 
 use bosminer_config::clap;
 use bosminer_config::{ClientDescriptor, ClientUserInfo};
@@ -50,6 +50,59 @@ async fn main() {
                 .help("Specify user and worker name")
                 .required(true)
                 .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("model")
+                .short("m")
+                .long("model")
+                .value_name("MODEL")
+                .help("Model of the Icarus device")
+                .possible_values(
+                    &icarus::MODELS
+                        .iter()
+                        .map(|model| model.name)
+                        .collect::<Vec<_>>(),
+                )
+                .default_value(icarus::DEFAULT_MODEL)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hash-time")
+                .long("hash-time")
+                .value_name("NANOSECONDS")
+                .help("Override default hash time of the device model")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("frequency")
+                .long("frequency")
+                .value_name("MHZ")
+                .help("Set chip frequency (only models with adjustable clock)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hashrate")
+                .long("hashrate")
+                .value_name("GHS")
+                .help("Hashrate of the device used for deriving the work timeout")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("nonce-timeout")
+                .long("nonce-timeout")
+                .value_name("MILLISECONDS")
+                .help("Override timeout of waiting for nonces of one work")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("check-config")
+                .long("check-config")
+                .help("Validate configuration, print all problems found and exit")
+                .required(false),
         );
 
     let matches = app.get_matches();
@@ -64,14 +117,55 @@ async fn main() {
         .expect("BUG: missing 'user' attribute");
     let user_info = ClientUserInfo::parse(user_info);
 
-    let backend_config =
-        config::Backend::new(match ClientDescriptor::create(url, &user_info, true) {
+    let client_descriptor = ClientDescriptor::create(url, &user_info, true);
+    let device = config::parse_model(
+        matches
+            .value_of("model")
+            .expect("BUG: missing 'model' attribute"),
+        matches.value_of("hash-time"),
+    )
+    .and_then(|model| {
+        config::parse_device(
+            model,
+            matches.value_of("frequency"),
+            matches.value_of("hashrate"),
+            matches.value_of("nonce-timeout"),
+        )
+    });
+    if matches.is_present("check-config") {
+        let mut valid = true;
+        if let Err(e) = &client_descriptor {
+            println!("cannot set pool from command line: {}", e);
+            valid = false;
+        }
+        if let Err(e) = &device {
+            println!("cannot set device from command line: {}", e);
+            valid = false;
+        }
+        if !valid {
+            std::process::exit(1);
+        }
+        println!("configuration is valid");
+        return;
+    }
+
+    let device = match device {
+        Err(e) => {
+            error!("Cannot set device from command line: {}", e);
+            return;
+        }
+        Ok(v) => v,
+    };
+    let backend_config = config::Backend::new(
+        match client_descriptor {
             Err(e) => {
                 error!("Cannot set pool from command line: {}", e.to_string());
                 return;
             }
             Ok(v) => v,
-        });
+        },
+        device,
+    );
 
     ii_async_compat::setup_panic_handling();
     bosminer::main::<bosminer_erupter::Backend>(backend_config, bosminer::SIGNATURE.to_string())
@@ -24,7 +24,7 @@ use ii_async_compat::tokio;
 
 #[test]
 fn block_mining() {
-    #[tokio::main(threaded_scheduler)]
+    #[tokio::main(flavor = "multi_thread")]
     async fn inner() {
         bosminer::test_utils::block_mining::run::<bosminer_erupter::Backend>(Default::default())
             .await;
@@ -24,6 +24,8 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
 use syn::DeriveInput;
 
 /// Generates implementation of `node::Info` and `node::Stats` traits for a type marked by this
@@ -215,6 +217,9 @@ fn impl_derive_mining_stats(ast: &DeriveInput, derive_name: &str) -> proc_macro2
         member_accepted,
         member_rejected,
         member_stale,
+        member_attribution,
+        member_reject_reasons,
+        member_latency,
         member_valid_network_diff,
         member_valid_job_diff,
         member_valid_backend_diff,
@@ -243,6 +248,9 @@ fn impl_derive_client_stats(
     let accepted = find_member(&fields, "member_accepted");
     let rejected = find_member(&fields, "member_rejected");
     let stale = find_member(&fields, "member_stale");
+    let attribution = find_member(&fields, "member_attribution");
+    let reject_reasons = find_member(&fields, "member_reject_reasons");
+    let latency = find_member(&fields, "member_latency");
 
     stream.extend(quote! {
         impl#generics stats::Client for #name#generics {
@@ -275,6 +283,21 @@ fn impl_derive_client_stats(
             fn stale(&self) -> &stats::Meter {
                 &self.#stale
             }
+
+            #[inline]
+            fn attribution(&self) -> &stats::Attribution {
+                &self.#attribution
+            }
+
+            #[inline]
+            fn reject_reasons(&self) -> &stats::RejectReasons {
+                &self.#reject_reasons
+            }
+
+            #[inline]
+            fn latency(&self) -> &stats::Latency {
+                &self.#latency
+            }
         }
     });
     stream
@@ -330,6 +353,338 @@ fn impl_derive_work_solver_stats(
     stream
 }
 
+/// Generates implementation of `schema::Schema` trait describing serialized form of a type marked
+/// by this derive. Serde attributes `rename`, `rename_all`, `skip`, `flatten`, `default` and
+/// `deny_unknown_fields` are respected and doc comments are used as descriptions. Additional
+/// keywords can be specified with `#[schema(keyword = expression, ...)]` where the expression is
+/// converted with `schema::json!` (e.g. `#[schema(default = DEFAULT_FAN_SPEED, minimum = 0)]`).
+#[proc_macro_derive(Schema, attributes(schema))]
+pub fn derive_schema(input: TokenStream) -> TokenStream {
+    let ast: DeriveInput = syn::parse(input).unwrap();
+    impl_derive_schema(&ast).into()
+}
+
+/// One `keyword = expression` pair of `#[schema(...)]` attribute
+struct SchemaKeyword {
+    name: syn::Ident,
+    value: proc_macro2::TokenStream,
+}
+
+impl Parse for SchemaKeyword {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<syn::Token![=]>()?;
+        // take all tokens up to the next delimiter (nested delimiters are enclosed in groups)
+        let mut value = proc_macro2::TokenStream::new();
+        while !input.is_empty() && !input.peek(syn::Token![,]) {
+            value.extend(std::iter::once(input.parse::<proc_macro2::TokenTree>()?));
+        }
+        Ok(Self { name, value })
+    }
+}
+
+/// Serde attributes relevant for the schema
+#[derive(Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+    flatten: bool,
+    default: bool,
+    deny_unknown_fields: bool,
+}
+
+impl SerdeAttributes {
+    fn from_attrs(attrs: &[syn::Attribute]) -> Self {
+        let mut result = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("serde")) {
+            let nested = match attr.parse_meta() {
+                Ok(syn::Meta::List(list)) => list.nested,
+                _ => panic!("unsupported serde attribute"),
+            };
+            for meta in nested {
+                match meta {
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                        if path.is_ident("skip") {
+                            result.skip = true;
+                        } else if path.is_ident("flatten") {
+                            result.flatten = true;
+                        } else if path.is_ident("default") {
+                            result.default = true;
+                        } else if path.is_ident("deny_unknown_fields") {
+                            result.deny_unknown_fields = true;
+                        }
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(name_value)) => {
+                        let value = match name_value.lit {
+                            syn::Lit::Str(value) => value.value(),
+                            _ => continue,
+                        };
+                        if name_value.path.is_ident("rename") {
+                            result.rename = Some(value);
+                        } else if name_value.path.is_ident("rename_all") {
+                            result.rename_all = Some(value);
+                        } else if name_value.path.is_ident("default") {
+                            result.default = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        result
+    }
+
+    /// Serialized name of field or variant `ident` of a container with `rename_all` rule
+    fn name(&self, ident: &syn::Ident, rename_all: Option<&String>) -> String {
+        if let Some(rename) = self.rename.as_ref() {
+            return rename.clone();
+        }
+        let name = ident.to_string();
+        match rename_all.map(String::as_str) {
+            None | Some("PascalCase") => name,
+            Some("lowercase") => name.to_lowercase(),
+            Some("UPPERCASE") => name.to_uppercase(),
+            Some("snake_case") => to_snake_case(&name),
+            Some("kebab-case") => to_snake_case(&name).replace('_', "-"),
+            Some(rule) => panic!("unsupported serde rule `rename_all = \"{}\"`", rule),
+        }
+    }
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Description collected from doc comments
+fn get_description(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                lit: syn::Lit::Str(line),
+                ..
+            })) => Some(line.value().trim().to_string()),
+            _ => None,
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+/// Statements inserting description and keywords from `#[schema(...)]` attributes to `schema`
+fn annotate_schema(
+    schema: &proc_macro2::TokenStream,
+    attrs: &[syn::Attribute],
+) -> proc_macro2::TokenStream {
+    let mut stream = proc_macro2::TokenStream::new();
+    if let Some(description) = get_description(attrs) {
+        stream.extend(quote! {
+            #schema.insert("description".to_string(), schema::json!(#description));
+        });
+    }
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("schema")) {
+        let keywords = attr
+            .parse_args_with(Punctuated::<SchemaKeyword, syn::Token![,]>::parse_terminated)
+            .expect("invalid schema attribute");
+        for keyword in keywords {
+            let name = keyword.name.to_string();
+            let value = keyword.value;
+            stream.extend(quote! {
+                #schema.insert(#name.to_string(), schema::json!(#value));
+            });
+        }
+    }
+    stream
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == "Option")
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn impl_derive_schema(ast: &DeriveInput) -> proc_macro2::TokenStream {
+    let name = &ast.ident;
+    let container = SerdeAttributes::from_attrs(&ast.attrs);
+
+    let mut generics = ast.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(schema::Schema));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &ast.data {
+        syn::Data::Struct(data) => impl_struct_schema(&container, &data.fields),
+        syn::Data::Enum(data) => impl_enum_schema(&container, data),
+        _ => panic!("#[derive(Schema)] can only be used with structs and enums"),
+    };
+    let object = quote!(object);
+    let annotations = annotate_schema(&object, &ast.attrs);
+
+    quote! {
+        impl#impl_generics schema::Schema for #name#ty_generics #where_clause {
+            fn schema() -> schema::Value {
+                let mut object = schema::Map::new();
+                #body
+                #annotations
+                schema::Value::Object(object)
+            }
+        }
+    }
+}
+
+fn impl_struct_schema(
+    container: &SerdeAttributes,
+    fields: &syn::Fields,
+) -> proc_macro2::TokenStream {
+    let fields = match fields {
+        syn::Fields::Named(fields) => &fields.named,
+        _ => panic!("#[derive(Schema)] can only be used with braced structs"),
+    };
+
+    let mut stream = quote! {
+        #[allow(unused_mut)]
+        let mut properties = schema::Map::new();
+        #[allow(unused_mut)]
+        let mut required: ::std::vec::Vec<schema::Value> = ::std::vec::Vec::new();
+    };
+    for field in fields {
+        let attributes = SerdeAttributes::from_attrs(&field.attrs);
+        if attributes.skip {
+            continue;
+        }
+        let ty = &field.ty;
+        let optional = is_option(ty) || attributes.default || container.default;
+        if attributes.flatten {
+            stream.extend(quote! {
+                schema::flatten(
+                    <#ty as schema::Schema>::schema(),
+                    &mut properties,
+                    &mut required,
+                    #optional,
+                );
+            });
+            continue;
+        }
+        let field_name = attributes.name(
+            field.ident.as_ref().expect("missing field name"),
+            container.rename_all.as_ref(),
+        );
+        let field_schema = quote!(field_schema);
+        let annotations = annotate_schema(&field_schema, &field.attrs);
+        stream.extend(quote! {
+            {
+                let mut field_schema = match <#ty as schema::Schema>::schema() {
+                    schema::Value::Object(field_schema) => field_schema,
+                    _ => panic!("BUG: schema is not an object"),
+                };
+                #annotations
+                properties.insert(#field_name.to_string(), schema::Value::Object(field_schema));
+            }
+        });
+        if !optional {
+            stream.extend(quote! {
+                required.push(schema::json!(#field_name));
+            });
+        }
+    }
+    let additional_properties = !container.deny_unknown_fields;
+    stream.extend(quote! {
+        object.insert("type".to_string(), schema::json!("object"));
+        object.insert("properties".to_string(), schema::Value::Object(properties));
+        if !required.is_empty() {
+            object.insert("required".to_string(), schema::Value::Array(required));
+        }
+        object.insert(
+            "additionalProperties".to_string(),
+            schema::json!(#additional_properties),
+        );
+    });
+    stream
+}
+
+fn impl_enum_schema(container: &SerdeAttributes, data: &syn::DataEnum) -> proc_macro2::TokenStream {
+    let unit_only = data.variants.iter().all(|variant| match variant.fields {
+        syn::Fields::Unit => true,
+        _ => false,
+    });
+
+    let mut names = vec![];
+    let mut alternatives = vec![];
+    for variant in &data.variants {
+        let attributes = SerdeAttributes::from_attrs(&variant.attrs);
+        if attributes.skip {
+            continue;
+        }
+        let variant_name = attributes.name(&variant.ident, container.rename_all.as_ref());
+        let alternative = quote!(alternative);
+        let annotations = annotate_schema(&alternative, &variant.attrs);
+        let alternative_body = match &variant.fields {
+            syn::Fields::Unit => quote! {
+                alternative.insert("enum".to_string(), schema::json!([#variant_name]));
+            },
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                quote! {
+                    alternative.insert("type".to_string(), schema::json!("object"));
+                    alternative.insert(
+                        "properties".to_string(),
+                        schema::json!({ #variant_name: <#ty as schema::Schema>::schema() }),
+                    );
+                    alternative.insert("required".to_string(), schema::json!([#variant_name]));
+                    alternative.insert("additionalProperties".to_string(), schema::json!(false));
+                }
+            }
+            _ => panic!("#[derive(Schema)] supports only unit and newtype enum variants"),
+        };
+        names.push(variant_name);
+        alternatives.push(quote! {
+            {
+                let mut alternative = schema::Map::new();
+                #alternative_body
+                #annotations
+                schema::Value::Object(alternative)
+            }
+        });
+    }
+
+    if unit_only {
+        quote! {
+            object.insert("type".to_string(), schema::json!("string"));
+            object.insert("enum".to_string(), schema::json!([#(#names),*]));
+        }
+    } else {
+        quote! {
+            object.insert(
+                "oneOf".to_string(),
+                schema::Value::Array(vec![#(#alternatives),*]),
+            );
+        }
+    }
+}
+
 fn get_fields<'a>(ast: &'a DeriveInput, derive_name: &str) -> &'a syn::Fields {
     match ast.data {
         syn::Data::Struct(ref data) => &data.fields,
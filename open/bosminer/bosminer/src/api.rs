@@ -25,9 +25,25 @@ mod cgminer;
 use crate::hal;
 use crate::hub;
 
+use ii_cgminer_api::support;
+
 use std::sync::Arc;
 
-pub async fn run(core: Arc<hub::Core>, config: hal::FrontendConfig, signature: String) {
+pub async fn run(
+    core: Arc<hub::Core>,
+    config: hal::FrontendConfig,
+    cgminer_compatibility: support::Compatibility,
+    websocket_origins: Vec<String>,
+    signature: String,
+) {
     let addr = "0.0.0.0:4028".parse().unwrap();
-    cgminer::run(core, addr, config.cgminer_custom_commands, signature).await;
+    cgminer::run(
+        core,
+        addr,
+        config.cgminer_custom_commands,
+        cgminer_compatibility,
+        websocket_origins,
+        signature,
+    )
+    .await;
 }
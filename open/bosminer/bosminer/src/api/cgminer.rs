@@ -26,15 +26,31 @@
 use crate::client;
 use crate::error;
 use crate::hub;
+use crate::job;
 use crate::node::{self, Stats as _, WorkSolver, WorkSolverStats as _};
+use crate::pool_health;
+use crate::schedule;
 use crate::stats::{self, UnixTime as _};
 use crate::sync;
 use crate::version;
 
-use ii_cgminer_api::support::ValueExt as _;
-use ii_cgminer_api::{command, json, response};
+use ii_cgminer_api::support::{self, ValueExt as _};
+use ii_cgminer_api::command::{
+    EVENTS, FEE, LOGS, LOG_FILTER, NODES, PAUSE, PIPELINE, PROFILE, REJECTIONS, RESUME,
+    SET_GROUPS, SHARES, TASKS,
+};
+use ii_cgminer_api::parameter::Parameters;
+use ii_cgminer_api::{command, commands, json, response};
 
-use bosminer_config::{ClientDescriptor, ClientUserInfo};
+#[cfg(feature = "websocket")]
+use ii_async_compat::task;
+#[cfg(feature = "websocket")]
+use ii_logging::macros::*;
+
+use bosminer_config::{ClientDescriptor, ClientUserInfo, GroupConfig};
+
+use futures::future::{BoxFuture, FutureExt};
+use ii_async_compat::futures;
 
 use std::future::Future;
 use std::net::SocketAddr;
@@ -50,6 +66,10 @@ use stats::TIME_MEAN_INTERVAL_5S as INTERVAL_5S;
 /// Default interval used for computation of default rolling average.
 const DEFAULT_LOG_INTERVAL: u32 = 5;
 
+/// Port of WebSocket bridge to the API commands
+#[cfg(feature = "websocket")]
+const WEBSOCKET_PORT: u16 = 4029;
+
 struct Handler {
     core: Arc<hub::Core>,
 }
@@ -73,7 +93,33 @@ impl Handler {
         list
     }
 
-    async fn get_pool_status(idx: usize, client: Arc<client::Handle>) -> response::Pool {
+    /// Pool status of the client and whether it is connected
+    fn get_client_status(client: &client::Handle) -> (response::PoolStatus, bool) {
+        let (status, stratum_active) = match client.status() {
+            sync::Status::Running => (response::PoolStatus::Alive, true),
+            sync::Status::Created
+            | sync::Status::Starting
+            | sync::Status::Stopping
+            | sync::Status::Restarting
+            | sync::Status::Stopped => (response::PoolStatus::Alive, false),
+            sync::Status::Failing
+            | sync::Status::Declining
+            | sync::Status::Retrying
+            | sync::Status::Recovering
+            | sync::Status::Failed => (response::PoolStatus::Dead, false),
+        };
+        if !client.is_enabled() {
+            (response::PoolStatus::Disabled, stratum_active)
+        } else {
+            (status, stratum_active)
+        }
+    }
+
+    async fn get_pool_status(
+        idx: usize,
+        client: Arc<client::Handle>,
+        health: pool_health::Score,
+    ) -> response::Pool {
         let client_descriptor = client.descriptor().await;
         let last_job = client.get_last_job().await;
 
@@ -86,7 +132,12 @@ impl Handler {
         let stale = client_stats.stale().take_snapshot().await;
         let last_share = client_stats.last_share().take_snapshot().await;
         let valid_backend_diff = client_stats.valid_backend_diff().take_snapshot().await;
+        let valid_job_diff = client_stats.valid_job_diff().take_snapshot().await;
         let best_share = client_stats.best_share().take_snapshot();
+        let latency = client_stats.latency().take_snapshot();
+
+        let now = time::Instant::now();
+        let elapsed = now.duration_since(*client_stats.start_time());
 
         let last_share_time = last_share
             .as_ref()
@@ -114,22 +165,12 @@ impl Handler {
             .unwrap_or(0.0);
         let current_block_version = last_job.map(|job| job.version()).unwrap_or_default();
 
-        let (mut status, stratum_active) = match client.status() {
-            sync::Status::Running => (response::PoolStatus::Alive, true),
-            sync::Status::Created
-            | sync::Status::Starting
-            | sync::Status::Stopping
-            | sync::Status::Restarting
-            | sync::Status::Stopped => (response::PoolStatus::Alive, false),
-            sync::Status::Failing
-            | sync::Status::Declining
-            | sync::Status::Retrying
-            | sync::Status::Recovering
-            | sync::Status::Failed => (response::PoolStatus::Dead, false),
+        let (status, stratum_active) = Self::get_client_status(&client);
+        let latency_ms = |f: fn(&stats::LatencySnapshot) -> time::Duration| {
+            latency
+                .as_ref()
+                .map_or(0.0, |latency| f(latency).as_secs_f64() * 1e3)
         };
-        if !client.is_enabled() {
-            status = response::PoolStatus::Disabled;
-        }
 
         response::Pool {
             idx: idx as i32,
@@ -178,12 +219,29 @@ impl Handler {
             current_block_version,
             // TODO: get actual value from client
             asic_boost: true,
+            work_utility: accepted.shares.to_sharerate(elapsed) * 60.0,
+            share_difficulty_5m: valid_job_diff.to_difficulty(*INTERVAL_5M, now),
+            share_difficulty_15m: valid_job_diff.to_difficulty(*INTERVAL_15M, now),
+            share_difficulty_24h: valid_job_diff.to_difficulty(*INTERVAL_24H, now),
+            earned_work_5m: accepted.to_difficulty(*INTERVAL_5M, now),
+            earned_work_15m: accepted.to_difficulty(*INTERVAL_15M, now),
+            earned_work_24h: accepted.to_difficulty(*INTERVAL_24H, now),
+            health_score: health.percent.unwrap_or_default(),
+            health_alert: health.alert.into(),
+            latency_last: latency_ms(|latency| latency.last),
+            latency_min: latency_ms(|latency| latency.min),
+            latency_mean: latency_ms(|latency| latency.mean),
+            latency_median: latency_ms(|latency| latency.median),
+            latency_p95: latency_ms(|latency| latency.p95),
+            latency_max: latency_ms(|latency| latency.max),
         }
     }
 
     async fn collect_pool_statuses(&self) -> Vec<response::Pool> {
+        let pool_health = self.core.pool_health.clone();
         self.collect_data(self.get_clients(), 0, |idx, client| {
-            async move { Self::get_pool_status(idx, client).await }
+            let health = pool_health.score(&client);
+            async move { Self::get_pool_status(idx, client, health).await }
         })
         .await
     }
@@ -198,7 +256,10 @@ impl Handler {
         let error_backend_diff = mining_stats.error_backend_diff().take_snapshot().await;
 
         let now = time::Instant::now();
-        let elapsed = now.duration_since(*mining_stats.start_time());
+        // `Elapsed` refers to the request time which is shared by all responses in legacy mode
+        let elapsed = support::TimestampSplit::current()
+            .instant
+            .saturating_duration_since(*mining_stats.start_time());
 
         let last_work_time =
             last_work_time.map_or(0, |time| time.get_unix_time().unwrap_or_default());
@@ -325,7 +386,7 @@ impl Handler {
 
     async fn get_asc_stats(
         idx: usize,
-        _work_solver: Arc<dyn node::WorkSolver>,
+        work_solver: Arc<dyn node::WorkSolver>,
     ) -> response::AscStats {
         response::AscStats {
             header: response::StatsHeader {
@@ -337,6 +398,7 @@ impl Handler {
                 max: 0.0,
                 min: 0.0,
             },
+            serial: work_solver.get_serial(),
         }
     }
 
@@ -358,6 +420,18 @@ impl Handler {
         clients
     }
 
+    /// Returns the last job of the first running client which is the one currently being mined
+    async fn get_active_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
+        for client in self.get_clients().await {
+            if client.status() == sync::Status::Running {
+                if let Some(job) = client.get_last_job().await {
+                    return Some(job);
+                }
+            }
+        }
+        None
+    }
+
     async fn get_client(
         &self,
         idx: i32,
@@ -431,7 +505,10 @@ impl command::Handler for Handler {
         let best_share = mining_stats.best_share().take_snapshot();
 
         let now = time::Instant::now();
-        let elapsed = now.duration_since(*mining_stats.start_time());
+        // `Elapsed` refers to the request time which is shared by all responses in legacy mode
+        let elapsed = support::TimestampSplit::current()
+            .instant
+            .saturating_duration_since(*mining_stats.start_time());
 
         let last_work_time =
             last_work_time.map_or(0, |time| time.get_unix_time().unwrap_or_default());
@@ -541,6 +618,12 @@ impl command::Handler for Handler {
             pool_rejected_ratio: pools_rejected_ratio,
             pool_stale_ratio: pools_stale_ratio,
             last_getwork: last_work_time,
+            total_found_blocks: self.core.found_blocks.total(),
+            last_found_block: self
+                .core
+                .found_blocks
+                .last()
+                .map_or(0, |block| block.unix_time() as response::Time),
         })
     }
 
@@ -616,17 +699,13 @@ impl command::Handler for Handler {
             .get_client_descriptor(parameter)
             .map_err(|_| response::ErrorCode::InvalidAddPoolDetails(parameter.to_string()))?;
 
-        let group = self
-            .core
-            .get_client_manager()
-            .create_or_get_default_group()
-            .await;
+        let client_manager = self.core.get_client_manager();
+        let group = client_manager.create_or_get_default_group().await;
         let client = group
-            .push_client(client::Handle::new(
-                client_descriptor.clone(),
-                self.core.backend_info.clone(),
-                None,
-            ))
+            .push_client(
+                client_manager
+                    .create_client(client_descriptor.clone(), self.core.backend_info.clone()),
+            )
             .await;
         let clients = group.get_clients().await;
 
@@ -724,15 +803,27 @@ impl command::Handler for Handler {
     }
 
     async fn handle_coin(&self) -> command::Result<response::Coin> {
+        let active_job = self.get_active_job().await;
+
+        let current_block_time = active_job
+            .as_ref()
+            .map(|job| job.time() as f64)
+            .unwrap_or(0.0);
+        let current_block_hash = active_job
+            .as_ref()
+            .map(|job| job.previous_hash().to_string())
+            .unwrap_or_default();
+        let network_difficulty = active_job
+            .and_then(|job| ii_bitcoin::Target::from_compact(job.bits()).ok())
+            .map(|target| target.get_difficulty() as f64)
+            .unwrap_or(0.0);
+
         Ok(response::Coin {
             hash_method: "sha256".to_string(),
-            // TODO: get actual value from clients
-            current_block_time: 0.0,
-            // TODO: get actual value from clients
-            current_block_hash: "".to_string(),
+            current_block_time,
+            current_block_hash,
             lp: true,
-            // TODO: get actual value from clients
-            network_difficulty: 0.0,
+            network_difficulty,
         })
     }
 
@@ -778,21 +869,553 @@ impl command::Handler for Handler {
     }
 }
 
+/// Handler of extended commands implemented by the core
+struct ExtHandler {
+    core: Arc<hub::Core>,
+}
+
+impl ExtHandler {
+    pub fn new(core: Arc<hub::Core>) -> Self {
+        Self { core }
+    }
+
+    async fn handle_events(&self) -> command::Result<response::ext::Events> {
+        // the most recent event goes first
+        let list = self
+            .core
+            .events
+            .recent()
+            .into_iter()
+            .rev()
+            .enumerate()
+            .map(|(idx, event)| response::ext::Event {
+                idx: idx as i32,
+                when: event.unix_time() as response::Time,
+                code: event.kind.code().to_string(),
+                fault: event.kind.is_fault().into(),
+                msg: event.kind.to_string(),
+            })
+            .collect();
+
+        Ok(response::ext::Events { list })
+    }
+
+    /// List scheduled profiles. With parameter the active profile is overridden with the named
+    /// profile or the automatic selection is restored with `auto`.
+    async fn handle_profile(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::Profiles> {
+        let scheduler = &self.core.scheduler;
+        if let Some(name) = Parameters::new(parameter).get_opt::<String>(0, "profile")? {
+            let name = if name == schedule::AUTO_MODE {
+                None
+            } else {
+                Some(name.as_str())
+            };
+            scheduler.set_override(name).map_err(|_| {
+                response::ErrorCode::InvalidParameter(
+                    "profile".to_string(),
+                    name.unwrap_or_default().to_string(),
+                )
+            })?;
+        }
+
+        let active = scheduler.active();
+        let overridden = scheduler.mode() != schedule::Mode::Auto;
+        let list = scheduler
+            .profiles()
+            .iter()
+            .enumerate()
+            .map(|(idx, profile)| {
+                let active = active.as_ref() == Some(profile);
+                response::ext::Profile {
+                    idx: idx as i32,
+                    name: profile.name.clone(),
+                    days: profile.days(),
+                    start: profile.start(),
+                    end: profile.end(),
+                    frequency: profile.frequency,
+                    voltage: profile.voltage,
+                    paused: profile.paused.into(),
+                    active: active.into(),
+                    override_: (active && overridden).into(),
+                }
+            })
+            .collect();
+
+        Ok(response::ext::Profiles { list })
+    }
+
+    async fn handle_pause(&self) -> command::Result<response::ext::Pause> {
+        Ok(response::ext::Pause {
+            changed: self.core.pause().await,
+        })
+    }
+
+    async fn handle_resume(&self) -> command::Result<response::ext::Resume> {
+        Ok(response::ext::Resume {
+            changed: self.core.resume().await,
+        })
+    }
+
+    async fn handle_tasks(&self) -> command::Result<response::ext::Tasks> {
+        let list = ii_async_compat::task::registry()
+            .snapshot()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, task)| response::ext::Task {
+                idx: idx as i32,
+                id: task.id,
+                name: task.name,
+                alive: task.alive.into(),
+                polls: task.polls,
+                elapsed: task.age.as_secs(),
+                idle: task.idle.as_secs_f64(),
+            })
+            .collect();
+
+        Ok(response::ext::Tasks { list })
+    }
+
+    async fn handle_pipeline(&self) -> command::Result<response::ext::Pipeline> {
+        let snapshot = self.core.pipeline.snapshot();
+        Ok(response::ext::Pipeline {
+            info: response::ext::PipelineInfo {
+                generators: snapshot.generators as u32,
+                generator_wait: snapshot.generator_wait.as_secs_f64(),
+                generator_waits: snapshot.generator_waits,
+                tx_fifo_full: snapshot.tx_fifo_full,
+                rx_fifo_overflow: snapshot.rx_fifo_overflow,
+                pending_solutions: snapshot.pending_solutions as u32,
+                max_pending_solutions: snapshot.max_pending_solutions as u32,
+                solution_channel_saturated: snapshot.solution_channel_saturated,
+                prefetch_depth: snapshot.prefetch_depth as u32,
+                prefetch_target_depth: snapshot.prefetch_target_depth as u32,
+                prefetch_stale: snapshot.prefetch_stale,
+                prefetch_underruns: snapshot.prefetch_underruns,
+            },
+        })
+    }
+
+    /// Cross-tab of shares accepted and rejected by each pool split by hash chains which have
+    /// found them
+    async fn handle_shares(&self) -> command::Result<response::ext::Shares> {
+        let work_solvers: Vec<_> = self
+            .core
+            .get_work_solvers()
+            .await
+            .into_iter()
+            .map(|work_solver| work_solver.get_unique_ptr())
+            .collect();
+
+        let mut clients = vec![];
+        for group in self.core.get_client_manager().get_groups().await {
+            clients.extend(group.get_clients().await.into_iter());
+        }
+
+        let now = time::Instant::now();
+        let mut list = vec![];
+        for (pool_idx, client) in clients.into_iter().enumerate() {
+            let url = client.descriptor().await.get_url(true, true, false);
+            for (solver, shares) in client.stats().attribution().solvers().await {
+                let solver_ptr = solver.clone().get_unique_ptr();
+                let asc_idx = match work_solvers
+                    .iter()
+                    .position(|work_solver| Arc::ptr_eq(work_solver, &solver_ptr))
+                {
+                    Some(asc_idx) => asc_idx,
+                    // the work solver is no longer registered
+                    None => continue,
+                };
+                let accepted = shares.accepted.take_snapshot().await;
+                let rejected = shares.rejected.take_snapshot().await;
+                list.push(response::ext::ShareAttribution {
+                    idx: list.len() as i32,
+                    asc: asc_idx as i32,
+                    name: solver.to_string(),
+                    pool: pool_idx as i32,
+                    url: url.clone(),
+                    accepted: accepted.solutions,
+                    rejected: rejected.solutions,
+                    difficulty_accepted: accepted.shares.as_f64(),
+                    difficulty_rejected: rejected.shares.as_f64(),
+                    mhs_5m: accepted.to_mega_hashes(*INTERVAL_5M, now).into_f64(),
+                    mhs_15m: accepted.to_mega_hashes(*INTERVAL_15M, now).into_f64(),
+                });
+            }
+        }
+
+        Ok(response::ext::Shares { list })
+    }
+
+    /// Shares rejected by each pool split by reason of rejection. Stale shares and shares of
+    /// unknown jobs usually indicate network latency, low difficulty shares indicate problems
+    /// with hardware tuning.
+    async fn handle_rejections(&self) -> command::Result<response::ext::Rejections> {
+        let mut list = vec![];
+        for group in self.core.get_client_manager().get_groups().await {
+            for client in group.get_clients().await {
+                let client_stats = client.stats();
+                let reasons = client_stats.reject_reasons();
+                let count = |reason| *reasons.get(reason).take_snapshot();
+                list.push(response::ext::PoolRejections {
+                    idx: list.len() as i32,
+                    url: client.descriptor().await.get_url(true, true, false),
+                    rejected: client_stats.rejected().take_snapshot().await.solutions,
+                    stale: count(stats::RejectReason::Stale),
+                    low_difficulty: count(stats::RejectReason::LowDifficulty),
+                    duplicate: count(stats::RejectReason::Duplicate),
+                    job_not_found: count(stats::RejectReason::JobNotFound),
+                    other: count(stats::RejectReason::Other),
+                });
+            }
+        }
+
+        Ok(response::ext::Rejections { list })
+    }
+
+    /// Statistics of `node` and recursively of all its child nodes. Nodes are numbered in
+    /// pre-order starting with `next_idx`.
+    fn get_node_tree<'a>(
+        &'a self,
+        node: Arc<dyn node::WorkSolver>,
+        next_idx: &'a mut i32,
+    ) -> BoxFuture<'a, response::ext::Node> {
+        async move {
+            let idx = *next_idx;
+            *next_idx += 1;
+
+            let mining_stats = node.mining_stats();
+            let valid_backend_diff = mining_stats.valid_backend_diff().take_snapshot().await;
+            let error_backend_diff = mining_stats.error_backend_diff().take_snapshot().await;
+            let best_share = mining_stats.best_share().take_snapshot();
+            let nominal_mhs = node
+                .get_nominal_hashrate()
+                .await
+                .map_or(0.0, |hashrate| hashrate.into_mega_hashes().into_f64());
+            let work_hub = self.core.is_work_hub(&node).await;
+
+            let now = time::Instant::now();
+            let mhs = |interval| valid_backend_diff.to_mega_hashes(interval, now).into_f64();
+            let mut tree = response::ext::Node {
+                idx,
+                id: node.get_id().map(|id| id as i32),
+                name: node.to_string(),
+                work_hub: work_hub.into(),
+                nominal_mhs,
+                mhs_5s: mhs(*INTERVAL_5S),
+                mhs_1m: mhs(*INTERVAL_1M),
+                mhs_5m: mhs(*INTERVAL_5M),
+                mhs_15m: mhs(*INTERVAL_15M),
+                mhs_24h: mhs(*INTERVAL_24H),
+                hardware_errors: error_backend_diff.solutions,
+                best_share: best_share.map(|inner| *inner).unwrap_or_default() as u64,
+                children: vec![],
+            };
+            for child in self.core.get_children(&node).await {
+                tree.children.push(self.get_node_tree(child, next_idx).await);
+            }
+            tree
+        }
+        .boxed()
+    }
+
+    /// Work solver hierarchy of the backend (e.g. backend, hash chain managers, hash chains) with
+    /// statistics of each node so that the device topology does not have to be hard-coded
+    async fn handle_nodes(&self) -> command::Result<response::ext::Nodes> {
+        let mut list = vec![];
+        if let Some(root_hub) = self.core.get_root_hub().await {
+            let mut next_idx = 0;
+            list.push(self.get_node_tree(root_hub, &mut next_idx).await);
+        }
+        Ok(response::ext::Nodes { list })
+    }
+
+    /// Atomically replace all pool groups. The parameter is a JSON array (or a string with it) of
+    /// groups in the same format as the `group` section of the configuration file. The change is
+    /// not persisted.
+    async fn handle_set_groups(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::SetGroups> {
+        let invalid_parameter =
+            |e: String| response::ErrorCode::InvalidParameter("groups".to_string(), e);
+        let group_configs: Vec<GroupConfig> = match parameter {
+            Some(json::Value::String(value)) => json::from_str(value),
+            Some(value) => json::from_value(value.clone()),
+            None => Err(response::ErrorCode::MissingParameter("groups".to_string()))?,
+        }
+        .map_err(|e| invalid_parameter(e.to_string()))?;
+
+        let reconfiguration = self
+            .core
+            .get_client_manager()
+            .reconfigure(group_configs, self.core.backend_info.as_ref(), true)
+            .await
+            .map_err(|e| invalid_parameter(e.to_string()))?;
+
+        Ok(response::ext::SetGroups {
+            groups: reconfiguration.groups,
+            added: reconfiguration.added_clients,
+            kept: reconfiguration.kept_clients,
+            removed: reconfiguration.removed_clients,
+        })
+    }
+
+    async fn handle_fee(&self) -> command::Result<response::ext::Fee> {
+        let mut list = vec![];
+        if let Some(fee_status) = self.core.get_client_manager().get_fee_status().await {
+            for client in fee_status.group.get_clients().await {
+                let client_descriptor = client.descriptor().await;
+                let client_stats = client.stats();
+                let generated_work = client_stats.generated_work().take_snapshot();
+                let accepted = client_stats.accepted().take_snapshot().await;
+                let rejected = client_stats.rejected().take_snapshot().await;
+                let (status, _) = Handler::get_client_status(&client);
+
+                list.push(response::ext::FeePool {
+                    idx: list.len() as i32,
+                    url: client_descriptor.get_url(true, true, false),
+                    user: client_descriptor.user.clone(),
+                    status,
+                    percent: fee_status.share_ratio * 100.0,
+                    generated_percent: fee_status.generated_share_ratio * 100.0,
+                    works: *generated_work,
+                    accepted: accepted.solutions,
+                    rejected: rejected.solutions,
+                    difficulty_accepted: accepted.shares.as_f64(),
+                    difficulty_rejected: rejected.shares.as_f64(),
+                });
+            }
+        }
+
+        Ok(response::ext::Fee { list })
+    }
+
+    /// Recent log records captured in memory, optionally only the last `count` of them
+    async fn handle_logs(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::Logs> {
+        let count = Parameters::new(parameter).get_opt::<usize>(0, "count")?;
+        let list = ii_logging::RING_BUFFER
+            .recent(count)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, record)| response::ext::LogRecord {
+                idx: idx as i32,
+                when: record.time.get_unix_time().unwrap_or_default(),
+                level: record.level.as_str().to_string(),
+                module: record.module,
+                msg: record.message,
+            })
+            .collect();
+        Ok(response::ext::Logs { list })
+    }
+
+    /// Get or set per-module levels of log records captured in memory
+    async fn handle_log_filter(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<response::ext::LogFilter> {
+        // the filter itself is delimited by commas so CGMiner compatible string is taken whole
+        let filter = match parameter {
+            Some(json::Value::String(value)) if !value.is_empty() => Some(value.clone()),
+            _ => Parameters::new(parameter).get_opt::<String>(0, "filter")?,
+        };
+        if let Some(filter) = filter {
+            let filter = ii_logging::ring::Filter::parse(&filter).map_err(|_| {
+                response::ErrorCode::InvalidParameter("filter".to_string(), filter.clone())
+            })?;
+            ii_logging::RING_BUFFER.set_filter(filter);
+        }
+
+        Ok(response::ext::LogFilter {
+            filter: ii_logging::RING_BUFFER.filter().to_string(),
+            capacity: ii_logging::RING_BUFFER.capacity() as u32,
+        })
+    }
+}
+
+#[cfg(feature = "websocket")]
+async fn run_websocket(
+    command_receiver: Arc<command::Receiver>,
+    listen_addr: SocketAddr,
+    allowed_origins: Vec<String>,
+) {
+    info!("Starting WebSocket API on {}", listen_addr);
+    if let Err(e) =
+        ii_cgminer_api::websocket::run(command_receiver, listen_addr, allowed_origins).await
+    {
+        error!("WebSocket API: cannot listen on {} ({})", listen_addr, e);
+    }
+}
+
+fn create_ext_commands(
+    core: Arc<hub::Core>,
+    custom_commands: Option<command::Map>,
+) -> command::Map {
+    let handler = Arc::new(ExtHandler::new(core));
+
+    let mut commands = commands![
+        (EVENTS: ParameterLess -> handler.handle_events),
+        (TASKS: ParameterLess -> handler.handle_tasks),
+        (PIPELINE: ParameterLess -> handler.handle_pipeline),
+        (SHARES: ParameterLess -> handler.handle_shares),
+        (REJECTIONS: ParameterLess -> handler.handle_rejections),
+        (NODES: ParameterLess -> handler.handle_nodes),
+        (SET_GROUPS: Parameter(None) -> handler.handle_set_groups; "groups"),
+        (FEE: ParameterLess -> handler.handle_fee),
+        (LOGS: Parameter(None) -> handler.handle_logs; "count"),
+        (LOG_FILTER: Parameter(None) -> handler.handle_log_filter; "filter"),
+        (PROFILE: Parameter(None) -> handler.handle_profile; "profile"),
+        (PAUSE: ParameterLess -> handler.handle_pause),
+        (RESUME: ParameterLess -> handler.handle_resume)
+    ];
+    // backend specific commands take precedence
+    if let Some(custom_commands) = custom_commands {
+        commands.extend(custom_commands.into_iter());
+    }
+    commands
+}
+
 pub async fn run(
     core: Arc<hub::Core>,
     listen_addr: SocketAddr,
     custom_commands: Option<command::Map>,
+    compatibility: support::Compatibility,
+    websocket_origins: Vec<String>,
     signature: String,
 ) {
+    let custom_commands = create_ext_commands(core.clone(), custom_commands);
     let handler = Handler::new(core);
     let command_receiver = command::Receiver::new(
         handler,
         signature,
         version::STRING.to_string(),
         custom_commands,
+    )
+    .with_compatibility(compatibility);
+    let command_receiver = Arc::new(command_receiver);
+
+    #[cfg(feature = "websocket")]
+    task::spawn_named(
+        "websocket api",
+        run_websocket(
+            command_receiver.clone(),
+            SocketAddr::new(listen_addr.ip(), WEBSOCKET_PORT),
+            websocket_origins,
+        ),
     );
+    #[cfg(not(feature = "websocket"))]
+    let _ = websocket_origins;
 
     ii_cgminer_api::run(command_receiver, listen_addr)
         .await
         .unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend;
+    use crate::test_utils;
+    use crate::work;
+
+    use futures::channel::mpsc;
+    use ii_async_compat::tokio;
+
+    fn build_core(backend_registry: &Arc<backend::Registry>) -> Arc<hub::Core> {
+        Arc::new(hub::Core::new(
+            1,
+            1,
+            ii_bitcoin::BIP320_VERSION_MASK,
+            0,
+            work::midstate::default(),
+            Arc::new(work::policy::PrimaryWithBackup),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            backend_registry,
+            None,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_nodes() {
+        let backend_registry = Arc::new(backend::Registry::new());
+        let core = build_core(&backend_registry);
+
+        // backend -> (manager -> (chain, chain), solver)
+        let (solution_sender, _solution_receiver) = mpsc::unbounded();
+        let work_solver_builder = work::SolverBuilder::new(
+            core.frontend.clone(),
+            backend_registry.clone(),
+            test_utils::create_test_work_receiver(),
+            solution_sender,
+            core.pipeline.clone(),
+        );
+        let backend = work_solver_builder
+            .create_work_hub(test_utils::TestWorkSolver::new)
+            .await;
+        let manager = backend
+            .create_work_hub(test_utils::TestWorkSolver::new)
+            .await;
+        for _ in 0..2 {
+            manager
+                .create_work_solver(|_, _| test_utils::TestWorkSolver::new())
+                .await;
+        }
+        let solver = backend
+            .create_work_solver(|_, _| test_utils::TestWorkSolver::new())
+            .await;
+
+        // direct children are listed in order of their registration
+        let root_hub = core.get_root_hub().await.expect("BUG: missing root hub");
+        let manager_node = manager.to_node().clone() as Arc<dyn node::WorkSolver>;
+        let children = backend_registry.get_children(&root_hub).await;
+        assert_eq!(children.len(), 2);
+        assert!(Arc::ptr_eq(&children[0], &manager_node));
+        assert!(Arc::ptr_eq(
+            &children[1],
+            &(solver.clone() as Arc<dyn node::WorkSolver>)
+        ));
+        assert_eq!(backend_registry.get_children(&manager_node).await.len(), 2);
+        assert!(backend_registry.get_children(&children[1]).await.is_empty());
+
+        let nodes = ExtHandler::new(core)
+            .handle_nodes()
+            .await
+            .expect("BUG: nodes command failed");
+        assert_eq!(nodes.list.len(), 1);
+        let tree = &nodes.list[0];
+        assert_eq!(tree.count(), 5);
+
+        let tree = json::to_value(tree).expect("BUG: cannot serialize node tree");
+        let summary = |node: &json::Value| {
+            (
+                node["NODE"].as_i64().expect("BUG: missing node index"),
+                node["Work Hub"]
+                    .as_str()
+                    .expect("BUG: missing work hub flag")
+                    == "Y",
+                node["Children"]
+                    .as_array()
+                    .expect("BUG: missing children")
+                    .len(),
+            )
+        };
+        // nodes are numbered in pre-order
+        assert_eq!(summary(&tree), (0, true, 2));
+        assert_eq!(summary(&tree["Children"][0]), (1, true, 2));
+        assert_eq!(summary(&tree["Children"][0]["Children"][0]), (2, false, 0));
+        assert_eq!(summary(&tree["Children"][0]["Children"][1]), (3, false, 0));
+        assert_eq!(summary(&tree["Children"][1]), (4, false, 0));
+        assert_eq!(tree["Name"], "Test work solver");
+    }
+}
@@ -76,6 +76,8 @@ pub struct Registry {
     work_hubs: Mutex<Vec<Arc<dyn node::WorkSolver>>>,
     /// List of work solvers which do real work and usually represents physical HW
     work_solvers: Mutex<Vec<Arc<dyn node::WorkSolver>>>,
+    /// Pairs of parent work hub and its child node describing the backend hierarchy
+    branches: Mutex<Vec<(Arc<dyn node::WorkSolver>, Arc<dyn node::WorkSolver>)>>,
 }
 
 impl Registry {
@@ -84,6 +86,7 @@ impl Registry {
             root_hub: Mutex::new(None),
             work_hubs: Mutex::new(vec![]),
             work_solvers: Mutex::new(vec![]),
+            branches: Mutex::new(vec![]),
         }
     }
 
@@ -131,6 +134,28 @@ impl Registry {
     pub async fn lock_work_solvers<'a>(&'a self) -> MutexGuard<'a, Vec<Arc<dyn node::WorkSolver>>> {
         self.work_solvers.lock().await
     }
+
+    /// Direct child nodes of `work_hub` in order of their registration
+    pub async fn get_children(
+        &self,
+        work_hub: &Arc<dyn node::WorkSolver>,
+    ) -> Vec<Arc<dyn node::WorkSolver>> {
+        self.branches
+            .lock()
+            .await
+            .iter()
+            .filter(|(parent, _)| Arc::ptr_eq(parent, work_hub))
+            .map(|(_, child)| child.clone())
+            .collect()
+    }
+
+    pub async fn is_work_hub(&self, node: &Arc<dyn node::WorkSolver>) -> bool {
+        self.work_hubs
+            .lock()
+            .await
+            .iter()
+            .any(|work_hub| Arc::ptr_eq(work_hub, node))
+    }
 }
 
 #[async_trait]
@@ -149,4 +174,16 @@ impl HierarchyBuilder for Registry {
         // and add its actual type (work hub/solver)
         self.add_node(node).await;
     }
+
+    async fn branch(
+        &self,
+        parent_work_hub: Arc<dyn node::WorkSolver>,
+        node: WorkSolverType<Arc<dyn node::WorkSolver>>,
+    ) {
+        self.branches
+            .lock()
+            .await
+            .push((parent_work_hub, node.as_ref().clone()));
+        self.add_node(node).await;
+    }
 }
@@ -0,0 +1,236 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Detection of solutions which meet the network target (found blocks), their persistent log and
+//! notification of the operator
+
+use ii_logging::macros::*;
+
+use crate::hook;
+use crate::work;
+
+use bosminer_config::BlockFoundConfig;
+
+use ii_bitcoin::MeetsTarget;
+
+use ii_async_compat::prelude::*;
+use serde_json::json;
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+/// Description of a found block
+#[derive(Debug, Clone)]
+pub struct FoundBlock {
+    pub time: time::SystemTime,
+    pub hash: ii_bitcoin::DHash,
+    pub header: ii_bitcoin::BlockHeader,
+    /// Client which supplied the job of the block
+    pub pool: String,
+}
+
+impl FoundBlock {
+    fn from_solution(solution: &work::Solution) -> Self {
+        Self {
+            time: time::SystemTime::now(),
+            hash: *solution.hash(),
+            header: solution.get_block_header(),
+            pool: solution
+                .origin()
+                .upgrade()
+                .map(|client| client.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn unix_time(&self) -> u64 {
+        hook::unix_time(self.time)
+    }
+
+    fn header_hex(&self) -> String {
+        hex::encode(&self.header.into_bytes()[..])
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "time": self.unix_time(),
+            "hash": self.hash.to_string(),
+            "header": self.header_hex(),
+            "pool": self.pool,
+        })
+    }
+}
+
+/// Keeps track of all found blocks
+#[derive(Debug)]
+pub struct Log {
+    config: BlockFoundConfig,
+    /// Number of found blocks including the ones persisted by previous runs
+    total: AtomicU32,
+    last: StdMutex<Option<FoundBlock>>,
+}
+
+impl Log {
+    pub fn new(config: BlockFoundConfig) -> Self {
+        let total = match &config.log_path {
+            Some(path) => Self::count_persisted(path).unwrap_or_else(|e| {
+                warn!("Cannot read log of found blocks '{}': {}", path, e);
+                0
+            }),
+            None => 0,
+        };
+        Self {
+            config,
+            total: AtomicU32::new(total),
+            last: StdMutex::new(None),
+        }
+    }
+
+    fn count_persisted(path: &str) -> io::Result<u32> {
+        match fs::File::open(path) {
+            Ok(file) => {
+                let mut count = 0;
+                for line in io::BufReader::new(file).lines() {
+                    if !line?.trim().is_empty() {
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[inline]
+    pub fn is_block(solution: &work::Solution) -> bool {
+        solution.hash().meets(&solution.network_target())
+    }
+
+    /// Total number of found blocks including the persisted ones
+    pub fn total(&self) -> u32 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn last(&self) -> Option<FoundBlock> {
+        self.last.lock().expect("BUG: lock last block").clone()
+    }
+
+    /// Describe the solution when it is a valid block
+    pub fn detect(solution: &work::Solution) -> Option<FoundBlock> {
+        if Self::is_block(solution) {
+            Some(FoundBlock::from_solution(solution))
+        } else {
+            None
+        }
+    }
+
+    /// Record found block. Writing into the persistent log and running the hook is done in
+    /// a separate task so that submission of the block is never delayed by slow storage.
+    pub fn record(&self, block: FoundBlock) {
+        warn!(
+            "Block found! hash={} pool={} header={}",
+            block.hash,
+            block.pool,
+            block.header_hex()
+        );
+
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.last
+            .lock()
+            .expect("BUG: lock last block")
+            .replace(block.clone());
+
+        let log_path = self.config.log_path.clone();
+        let command = self.config.exec.clone();
+        if log_path.is_none() && command.is_none() {
+            return;
+        }
+        tokio::spawn(async move {
+            if let Some(path) = log_path {
+                if let Err(e) = Self::append(&path, &block).await {
+                    error!("Cannot write found block into '{}': {}", path, e);
+                }
+            }
+            if let Some(command) = command {
+                hook::exec(
+                    "found block",
+                    &command,
+                    vec![
+                        ("BOSMINER_BLOCK_TIME", block.unix_time().to_string()),
+                        ("BOSMINER_BLOCK_HASH", block.hash.to_string()),
+                        ("BOSMINER_BLOCK_HEADER", block.header_hex()),
+                        ("BOSMINER_BLOCK_POOL", block.pool.clone()),
+                    ],
+                )
+                .await;
+            }
+        });
+    }
+
+    async fn append(path: &str, block: &FoundBlock) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(format!("{}\n", block.to_json()).as_bytes())
+            .await?;
+        file.sync_all().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_count_persisted() {
+        let path = std::env::temp_dir().join(format!("bosminer-blocks-{}", std::process::id()));
+        let path = path.to_str().expect("BUG: temporary path");
+
+        assert_eq!(Log::count_persisted(path).unwrap(), 0);
+        fs::write(path, "{\"hash\":\"00\"}\n\n{\"hash\":\"01\"}\n").unwrap();
+        assert_eq!(Log::count_persisted(path).unwrap(), 2);
+        assert_eq!(
+            Log::new(BlockFoundConfig {
+                log_path: Some(path.to_string()),
+                exec: None,
+            })
+            .total(),
+            2
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_is_block() {
+        for block in test_utils::TEST_BLOCKS.iter() {
+            // test blocks are real blocks meeting their network target
+            assert!(Log::is_block(&block.into()));
+        }
+    }
+}
@@ -26,10 +26,14 @@
 mod scheduler;
 
 // Sub-modules with client implementation
+pub mod bitcoind;
 pub mod drain;
+pub mod endpoint;
 pub mod stratum_v2;
 pub mod stratum_v2_channels;
 
+use ii_logging::macros::*;
+
 use crate::error;
 use crate::hal;
 use crate::job;
@@ -42,17 +46,35 @@ use crate::work;
 pub use scheduler::JobExecutor;
 
 use bosminer_config::{
-    ClientDescriptor, ClientProtocol, ClientUserInfo, GroupConfig, GroupDescriptor,
-    LoadBalanceStrategy,
+    ClientDescriptor, ClientProtocol, ClientUserInfo, FeeConfig, GroupConfig, GroupDescriptor,
+    LoadBalanceStrategy, PoolConfig,
 };
 
 use futures::channel::mpsc;
 use futures::lock::Mutex;
-use ii_async_compat::futures;
+use ii_async_compat::{futures, task, tokio};
 
+use std::path::PathBuf;
 use std::slice;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time;
+
+/// Parameters of a new client which are not part of its descriptor
+pub struct Settings {
+    /// Information about the backend passed to the upstream
+    pub backend_info: Option<hal::BackendInfo>,
+    /// Block version bits the client has to negotiate with its upstream
+    pub version_mask: u32,
+    /// The client cannot mine when its upstream doesn't allow rolling enough version bits for
+    /// this number of midstates
+    pub min_midstate_count: usize,
+    /// Multiplexer of protocol extensions so that stratum V2 client can communicate with external
+    /// clients that implement some protocol extension
+    pub extensions: Option<Arc<stratum_v2::extension::Multiplexer>>,
+    /// Directory where stratum V2 client records received frames
+    pub stratum_record_dir: Option<PathBuf>,
+}
 
 #[derive(Debug)]
 pub struct Handle {
@@ -65,16 +87,14 @@ pub struct Handle {
 }
 
 impl Handle {
-    /// `channel` - endpoints for 2 channels so that stratum V2 client can communicate with an
-    /// external client that implements some protocol extension
-    pub fn new(
-        descriptor: ClientDescriptor,
-        backend_info: Option<hal::BackendInfo>,
-        channel: Option<(
-            stratum_v2::ExtensionChannelToStratumReceiver,
-            stratum_v2::ExtensionChannelFromStratumSender,
-        )>,
-    ) -> Self {
+    pub fn new(descriptor: ClientDescriptor, settings: Settings) -> Self {
+        let Settings {
+            backend_info,
+            version_mask,
+            min_midstate_count,
+            extensions,
+            stratum_record_dir,
+        } = settings;
         let (solution_sender, solution_receiver) = mpsc::unbounded();
         // Initially register new client without ability to send work
         let engine_sender = Arc::new(work::EngineSender::new(None));
@@ -83,15 +103,15 @@ impl Handle {
         let node: Arc<dyn node::Client> = match &descriptor.protocol {
             ClientProtocol::Drain => {
                 assert!(
-                    channel.is_none(),
-                    "BUG: protocol 'Drain' does not support channel"
+                    extensions.is_none(),
+                    "BUG: protocol 'Drain' does not support extensions"
                 );
                 Arc::new(drain::Client::new(descriptor.get_full_url(), job_solver))
             }
             ClientProtocol::StratumV1 => {
                 assert!(
-                    channel.is_none(),
-                    "BUG: protocol 'Stratum V1' does not support channel"
+                    extensions.is_none(),
+                    "BUG: protocol 'Stratum V1' does not support extensions"
                 );
                 Arc::new(stratum_v2_channels::StratumClient::new(
                     stratum_v2_channels::ConnectionDetails::from_descriptor(&descriptor),
@@ -101,15 +121,31 @@ impl Handle {
             ClientProtocol::StratumV2(_) => Arc::new(stratum_v2::StratumClient::new(
                 stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
                 backend_info,
+                version_mask,
+                min_midstate_count,
                 job_solver,
-                channel,
+                extensions,
+                stratum_record_dir,
             )),
             ClientProtocol::StratumV2Insecure => Arc::new(stratum_v2::StratumClient::new(
                 stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
                 backend_info,
+                version_mask,
+                min_midstate_count,
                 job_solver,
-                channel,
+                extensions,
+                stratum_record_dir,
             )),
+            ClientProtocol::Bitcoind(_) => {
+                assert!(
+                    extensions.is_none(),
+                    "BUG: protocol 'Bitcoind' does not support extensions"
+                );
+                Arc::new(bitcoind::Client::new(
+                    bitcoind::ConnectionDetails::from_descriptor(&descriptor),
+                    job_solver,
+                ))
+            }
         };
 
         Self {
@@ -241,6 +277,17 @@ impl Handle {
         Ok(())
     }
 
+    /// Number of solutions the client hasn't delivered to the remote server yet
+    #[inline]
+    pub fn pending_solutions(&self) -> usize {
+        self.node.pending_solutions()
+    }
+
+    #[inline]
+    pub(crate) fn endpoints(&self) -> Option<Arc<endpoint::Selector>> {
+        self.node.endpoints()
+    }
+
     #[inline]
     pub(crate) fn stats(&self) -> &dyn stats::Client {
         self.node.client_stats()
@@ -250,6 +297,15 @@ impl Handle {
     pub(crate) async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
         self.node.get_last_job().await
     }
+
+    /// Replace the current work engine with a new one generated from the last job so that
+    /// changed work parameters take effect immediately. Clients mining more jobs in parallel
+    /// continue with the last one until they receive new jobs.
+    async fn regenerate_engine(&self) {
+        if let Some(job) = self.get_last_job().await {
+            self.engine_sender.broadcast_job(job);
+        }
+    }
 }
 
 impl Drop for Handle {
@@ -272,21 +328,38 @@ pub struct Group {
     pub descriptor: GroupDescriptor,
     scheduler_client_handles: Mutex<Vec<scheduler::ClientHandle>>,
     event_sender: event::Sender,
-    /// All clients in the group must support the same amount of midstates
-    midstate_count: usize,
+    /// All clients in the group must support the same amount of midstates. It is shared with the
+    /// client manager which is able to change it at runtime.
+    midstate_count: Arc<AtomicUsize>,
+    /// Minimal number of midstates the backend is able to solve
+    min_midstate_count: usize,
+    /// Number of seconds the backend rolls ntime of each work
+    ntime_roll: u32,
+    /// Implementation of midstate computation used by work engines of the clients
+    midstate_compute: work::midstate::DynCompute,
+    /// Decides which client supplies jobs
+    selection_policy: work::policy::DynSelectionPolicy,
 }
 
 impl Group {
     fn new(
         descriptor: GroupDescriptor,
         event_sender: event::Sender,
-        midstate_count: usize,
+        midstate_count: Arc<AtomicUsize>,
+        min_midstate_count: usize,
+        ntime_roll: u32,
+        midstate_compute: work::midstate::DynCompute,
+        selection_policy: work::policy::DynSelectionPolicy,
     ) -> Self {
         Self {
             descriptor,
             scheduler_client_handles: Mutex::new(vec![]),
             event_sender,
             midstate_count,
+            min_midstate_count,
+            ntime_roll,
+            midstate_compute,
+            selection_policy,
         }
     }
 
@@ -311,15 +384,45 @@ impl Group {
     }
 
     pub async fn push_client(&self, client_handle: Handle) -> Arc<Handle> {
-        let midstate_count = self.midstate_count;
+        let midstate_count = self.midstate_count.clone();
+        let min_midstate_count = self.min_midstate_count;
+        let ntime_roll = self.ntime_roll;
+        let midstate_compute = self.midstate_compute.clone();
         let _ = client_handle.replace_engine_generator(Box::new(move |job| {
-            Arc::new(work::engine::VersionRolling::new(job, midstate_count))
+            let midstate_count = midstate_count.load(Ordering::Relaxed);
+            // work which would be rejected by the backend is not generated at all
+            let supported_midstate_count = work::engine::VersionRolling::supported_midstate_count(
+                job.as_ref(),
+                midstate_count,
+            );
+            let required_midstate_count = min_midstate_count.min(midstate_count);
+            if supported_midstate_count < required_midstate_count {
+                warn!(
+                    "Job version mask {:#010x} allows only {} midstate(s), backend requires {}",
+                    job.version_mask(),
+                    supported_midstate_count,
+                    required_midstate_count
+                );
+                return Arc::new(work::engine::ExhaustedWork);
+            }
+            let engine = if midstate_count == 1 {
+                // AsicBoost is disabled so the block version is not rolled at all
+                work::engine::VersionRolling::with_fixed_version(job, midstate_compute.clone())
+            } else {
+                work::engine::VersionRolling::with_midstate_compute(
+                    job,
+                    midstate_count,
+                    midstate_compute.clone(),
+                )
+            };
+            Arc::new(engine.with_ntime_roll(ntime_roll))
         }));
         let _ = client_handle.try_disable();
         client_handle.set_event_sender(self.event_sender.clone());
 
+        let weight = client_handle.descriptor().await.weight;
         let client_handle = Arc::new(client_handle);
-        let scheduler_client_handle = scheduler::ClientHandle::new(client_handle.clone());
+        let scheduler_client_handle = scheduler::ClientHandle::new(client_handle.clone(), weight);
         self.scheduler_client_handles
             .lock()
             .await
@@ -341,6 +444,28 @@ impl Group {
         client_handle
     }
 
+    /// Move a client from previous configuration to this group and apply its new `descriptor`
+    /// without interrupting its connection
+    async fn push_existing_client(&self, client_handle: Arc<Handle>, descriptor: ClientDescriptor) {
+        let enabled = descriptor.enabled;
+        let weight = descriptor.weight;
+        client_handle.change_descriptor(descriptor).await;
+        client_handle.set_event_sender(self.event_sender.clone());
+
+        let scheduler_client_handle = scheduler::ClientHandle::new(client_handle.clone(), weight);
+        self.scheduler_client_handles
+            .lock()
+            .await
+            .push(scheduler_client_handle);
+        self.event_sender.notify();
+
+        if enabled {
+            let _ = client_handle.try_enable();
+        } else {
+            let _ = client_handle.try_disable();
+        }
+    }
+
     pub async fn remove_client_at(&self, index: usize) -> Result<Arc<Handle>, error::Client> {
         let mut scheduler_client_handles = self.scheduler_client_handles.lock().await;
         if index >= scheduler_client_handles.len() {
@@ -411,6 +536,8 @@ impl Group {
 /// Keeps track of all active clients
 pub struct GroupRegistry {
     list: Vec<scheduler::GroupHandle>,
+    /// Clients removed by reconfiguration which still deliver solutions of already generated work
+    draining: Vec<Arc<Handle>>,
     event_monitor: event::Monitor,
     total_quota: usize,
     fixed_share_ratio_count: usize,
@@ -421,6 +548,7 @@ impl GroupRegistry {
     pub fn new(event_monitor: event::Monitor) -> Self {
         Self {
             list: vec![],
+            draining: vec![],
             event_monitor,
             total_quota: 0,
             fixed_share_ratio_count: 0,
@@ -455,9 +583,28 @@ impl GroupRegistry {
     pub fn create_group(
         &mut self,
         descriptor: GroupDescriptor,
-        midstate_count: usize,
+        midstate_count: Arc<AtomicUsize>,
+        min_midstate_count: usize,
+        ntime_roll: u32,
+        midstate_compute: work::midstate::DynCompute,
+        selection_policy: work::policy::DynSelectionPolicy,
     ) -> Result<Arc<Group>, error::Client> {
-        match descriptor.strategy() {
+        let group_handle = Arc::new(Group::new(
+            descriptor,
+            self.event_monitor.publish(),
+            midstate_count,
+            min_midstate_count,
+            ntime_roll,
+            midstate_compute,
+            selection_policy,
+        ));
+        self.add_group(group_handle.clone())?;
+        Ok(group_handle)
+    }
+
+    /// Register already existing group and recalculate quotas of all groups
+    fn add_group(&mut self, group_handle: Arc<Group>) -> Result<(), error::Client> {
+        match group_handle.descriptor.strategy() {
             LoadBalanceStrategy::Quota(quota) => {
                 self.total_quota += quota;
             }
@@ -472,16 +619,11 @@ impl GroupRegistry {
             }
         }
 
-        let group_handle = Arc::new(Group::new(
-            descriptor,
-            self.event_monitor.publish(),
-            midstate_count,
-        ));
-        let scheduler_group_handle = scheduler::GroupHandle::new(group_handle.clone());
+        let scheduler_group_handle = scheduler::GroupHandle::new(group_handle);
         self.list.push(scheduler_group_handle);
         self.recalculate_quotas(true);
 
-        Ok(group_handle)
+        Ok(())
     }
 
     pub fn get_groups(&self) -> Vec<Arc<Group>> {
@@ -498,6 +640,32 @@ impl GroupRegistry {
             .map(|scheduler_group_handle| scheduler_group_handle.group_handle.clone())
     }
 
+    /// Find private group with given `name` together with its allocated share ratio and ratio
+    /// of work the group has really generated since the last recalculation of quotas
+    fn get_private_group_status(&self, name: &str) -> Option<(Arc<Group>, f64, f64)> {
+        let total_generated_work: u64 = self
+            .iter()
+            .map(|scheduler_group_handle| scheduler_group_handle.generated_work())
+            .sum();
+        self.iter()
+            .find(|scheduler_group_handle| {
+                scheduler_group_handle.is_private()
+                    && scheduler_group_handle.group_handle.descriptor.name == name
+            })
+            .map(|scheduler_group_handle| {
+                let generated_share_ratio = if total_generated_work > 0 {
+                    scheduler_group_handle.generated_work() as f64 / total_generated_work as f64
+                } else {
+                    0.0
+                };
+                (
+                    scheduler_group_handle.group_handle.clone(),
+                    scheduler_group_handle.share_ratio,
+                    generated_share_ratio,
+                )
+            })
+    }
+
     /// Find client which given solution is associated with
     async fn find_client(&self, solution: &work::Solution) -> Option<Arc<Handle>> {
         for scheduler_group_handle in &self.list {
@@ -510,7 +678,10 @@ impl GroupRegistry {
                 None => {}
             }
         }
-        None
+        self.draining
+            .iter()
+            .find(|client_handle| client_handle.matching_solution(solution))
+            .cloned()
     }
 
     fn recalculate_quotas(&mut self, reset_generated_work: bool) {
@@ -543,23 +714,194 @@ impl GroupRegistry {
     }
 }
 
+/// Summary of replacement of the whole client group configuration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconfiguration {
+    /// Number of groups in the new configuration
+    pub groups: usize,
+    /// Clients created for pools which haven't been configured before
+    pub added_clients: usize,
+    /// Clients kept from previous configuration (possibly with changed settings)
+    pub kept_clients: usize,
+    /// Clients removed from configuration which are draining their pending solutions
+    pub removed_clients: usize,
+}
+
+/// Current state of the group directing a share of generated work to the fee pool
+#[derive(Debug, Clone)]
+pub struct FeeStatus {
+    pub group: Arc<Group>,
+    /// Share ratio allocated to the fee group
+    pub share_ratio: f64,
+    /// Ratio of work really generated for the fee pool since the last recalculation of quotas
+    pub generated_share_ratio: f64,
+}
+
 #[derive(Debug, Clone)]
+/// Parameters of work generation and clients shared by all groups of the client manager
+pub struct ManagerSettings {
+    /// Initial number of midstates in work generated for backends
+    pub midstate_count: usize,
+    /// Minimal number of midstates the backend is able to solve
+    pub min_midstate_count: usize,
+    /// Block version bits the backend needs to roll
+    pub version_mask: u32,
+    /// Number of seconds the backend is able to roll ntime of each work
+    pub ntime_roll: u32,
+    /// Implementation of midstate computation used by work engines
+    pub midstate_compute: work::midstate::DynCompute,
+    /// Policy used by groups without explicitly configured pool selection
+    pub selection_policy: work::policy::DynSelectionPolicy,
+    /// Publisher of telemetry submitted by Stratum V2 clients
+    pub telemetry: Option<Arc<stratum_v2::telemetry::Publisher>>,
+    /// Directory where Stratum V2 clients record received frames (for debugging only)
+    pub stratum_record_dir: Option<PathBuf>,
+}
+
 pub struct Manager {
     group_registry: Arc<Mutex<GroupRegistry>>,
     event_monitor: event::Monitor,
-    midstate_count: usize,
+    /// Number of midstates in work generated for backends shared with all groups
+    midstate_count: Arc<AtomicUsize>,
+    /// Minimal number of midstates the backend is able to solve
+    min_midstate_count: usize,
+    /// Block version bits the backend needs to roll
+    version_mask: u32,
+    /// Number of seconds the backend is able to roll ntime of each work
+    ntime_roll: u32,
+    /// Implementation of midstate computation used by work engines
+    midstate_compute: work::midstate::DynCompute,
+    /// Policy used by groups without explicitly configured pool selection
+    selection_policy: work::policy::DynSelectionPolicy,
+    /// Publisher of telemetry submitted by Stratum V2 clients
+    telemetry: Option<Arc<stratum_v2::telemetry::Publisher>>,
+    /// Directory where Stratum V2 clients record received frames (for debugging only)
+    stratum_record_dir: Option<PathBuf>,
 }
 
 impl Manager {
-    pub fn new(midstate_count: usize) -> Self {
+    /// How often pending solutions are checked while flushing them
+    const FLUSH_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+    /// Minimal time a removed client keeps running to receive solutions of work which has been
+    /// already sent to backends
+    const DRAIN_GRACE_PERIOD: time::Duration = time::Duration::from_secs(5);
+    /// Maximal time a removed client waits for acknowledgement of its pending solutions
+    const DRAIN_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+    /// Name of the private group with the fee pool
+    pub const FEE_GROUP_NAME: &'static str = "Fee";
+
+    pub fn new(settings: ManagerSettings) -> Self {
+        let ManagerSettings {
+            midstate_count,
+            min_midstate_count,
+            version_mask,
+            ntime_roll,
+            midstate_compute,
+            selection_policy,
+            telemetry,
+            stratum_record_dir,
+        } = settings;
         let event_monitor = event::Monitor::new();
         Self {
             group_registry: Arc::new(Mutex::new(GroupRegistry::new(event_monitor.clone()))),
             event_monitor,
-            midstate_count,
+            midstate_count: Arc::new(AtomicUsize::new(midstate_count)),
+            min_midstate_count,
+            version_mask,
+            ntime_roll,
+            midstate_compute,
+            selection_policy,
+            telemetry,
+            stratum_record_dir,
         }
     }
 
+    fn resolve_selection_policy(
+        &self,
+        descriptor: &GroupDescriptor,
+    ) -> work::policy::DynSelectionPolicy {
+        descriptor
+            .pool_selection
+            .map(work::policy::from_config)
+            .unwrap_or_else(|| self.selection_policy.clone())
+    }
+
+    /// Number of midstates in work generated for backends
+    #[inline]
+    pub fn midstate_count(&self) -> usize {
+        self.midstate_count.load(Ordering::Relaxed)
+    }
+
+    /// Change number of midstates in work generated for backends. Work engines of all clients
+    /// (including the ones in private groups) are regenerated so the backend has to be ready to
+    /// accept work with the previous number of midstates which has been already generated.
+    pub async fn set_midstate_count(&self, midstate_count: usize) {
+        self.midstate_count.store(midstate_count, Ordering::Relaxed);
+        let groups: Vec<_> = self
+            .group_registry
+            .lock()
+            .await
+            .iter()
+            .map(|scheduler_group_handle| scheduler_group_handle.group_handle.clone())
+            .collect();
+        for group in groups {
+            for client in group.get_clients().await {
+                client.regenerate_engine().await;
+            }
+        }
+    }
+
+    /// Version mask that has to be negotiated by all clients with their pools
+    #[inline]
+    pub fn version_mask(&self) -> u32 {
+        self.version_mask
+    }
+
+    /// Minimal number of midstates in work the backend is able to solve
+    #[inline]
+    pub fn min_midstate_count(&self) -> usize {
+        self.min_midstate_count
+    }
+
+    /// Create a new client for `descriptor`. Stratum V2 clients get the telemetry extension when
+    /// telemetry is enabled.
+    pub fn create_client(
+        &self,
+        descriptor: ClientDescriptor,
+        backend_info: Option<hal::BackendInfo>,
+    ) -> Handle {
+        let telemetry = match &descriptor.protocol {
+            ClientProtocol::StratumV2(_) | ClientProtocol::StratumV2Insecure => {
+                self.telemetry.clone()
+            }
+            _ => None,
+        };
+        let extensions = telemetry
+            .as_ref()
+            .map(|_| Arc::new(stratum_v2::extension::Multiplexer::new()));
+        let dev_id = backend_info
+            .as_ref()
+            .map(|info| info.dev_id.clone())
+            .unwrap_or_default();
+        let url = descriptor.get_full_url();
+        let handle = Handle::new(
+            descriptor,
+            Settings {
+                backend_info,
+                version_mask: self.version_mask,
+                min_midstate_count: self.min_midstate_count,
+                extensions: extensions.clone(),
+                stratum_record_dir: self.stratum_record_dir.clone(),
+            },
+        );
+        if let (Some(telemetry), Some(extensions)) = (telemetry, extensions) {
+            if let Err(e) = telemetry.attach(dev_id, &extensions, handle.node.clone()) {
+                error!("Cannot attach telemetry to '{}': {}", url, e);
+            }
+        }
+        handle
+    }
+
     pub async fn load_config<T>(
         &self,
         group_configs: T,
@@ -574,16 +916,9 @@ impl Manager {
                 let group = self.create_group(group_config.descriptor).await?;
                 if let Some(pool_configs) = group_config.pools {
                     for pool_config in pool_configs {
-                        let descriptor = ClientDescriptor::create(
-                            pool_config.url.as_str(),
-                            &ClientUserInfo::new(
-                                pool_config.user.as_str(),
-                                pool_config.password.as_deref(),
-                            ),
-                            pool_config.enabled.unwrap_or(default_pool_enabled),
-                        )
-                        .map_err(|e| e.to_string())?;
-                        let client_handle = Handle::new(descriptor, backend_info.cloned(), None);
+                        let descriptor =
+                            Self::create_client_descriptor(&pool_config, default_pool_enabled)?;
+                        let client_handle = self.create_client(descriptor, backend_info.cloned());
                         group.push_client(client_handle).await;
                     }
                 }
@@ -592,6 +927,175 @@ impl Manager {
         Ok(())
     }
 
+    fn create_client_descriptor(
+        pool_config: &PoolConfig,
+        default_pool_enabled: bool,
+    ) -> error::Result<ClientDescriptor> {
+        let mut descriptor = ClientDescriptor::create(
+            pool_config.url.as_str(),
+            &ClientUserInfo::new(pool_config.user.as_str(), pool_config.password.as_deref()),
+            pool_config.enabled.unwrap_or(default_pool_enabled),
+        )
+        .map_err(|e| e.to_string())?;
+        descriptor.retry_schedule = pool_config.retry_schedule().map_err(|e| e.to_string())?;
+        descriptor.weight = pool_config.weight().map_err(|e| e.to_string())?;
+        descriptor.extranonce_partition = pool_config
+            .extranonce_partition()
+            .map_err(|e| e.to_string())?;
+        descriptor.channel_count = pool_config.channel_count().map_err(|e| e.to_string())?;
+        descriptor.endpoints = pool_config.endpoints().map_err(|e| e.to_string())?;
+        Ok(descriptor)
+    }
+
+    /// Atomically replace all (non-private) client groups with `group_configs`. The whole
+    /// configuration is validated before any change so an invalid configuration leaves current
+    /// groups untouched. Clients of pools with the same URL and user are kept connected so the
+    /// work generation continues without any gap. Removed clients are taken out of scheduling
+    /// immediately but they keep running until their pending solutions are delivered.
+    pub async fn reconfigure(
+        &self,
+        group_configs: Vec<GroupConfig>,
+        backend_info: Option<&hal::BackendInfo>,
+        default_pool_enabled: bool,
+    ) -> error::Result<Reconfiguration> {
+        let mut client_descriptors = vec![];
+        for group_config in &group_configs {
+            client_descriptors.push(
+                group_config
+                    .pools
+                    .iter()
+                    .flatten()
+                    .map(|pool_config| {
+                        Self::create_client_descriptor(pool_config, default_pool_enabled)
+                    })
+                    .collect::<error::Result<Vec<_>>>()?,
+            );
+        }
+
+        let mut group_registry = self.group_registry.lock().await;
+
+        // Create all groups first because their load balance strategies may not be compatible
+        let mut next_group_registry = GroupRegistry::new(self.event_monitor.clone());
+        let mut groups = vec![];
+        for group_config in group_configs {
+            let selection_policy = self.resolve_selection_policy(&group_config.descriptor);
+            groups.push(next_group_registry.create_group(
+                group_config.descriptor,
+                self.midstate_count.clone(),
+                self.min_midstate_count,
+                self.ntime_roll,
+                self.midstate_compute.clone(),
+                selection_policy,
+            )?);
+        }
+        // Private groups are not part of the configuration
+        for scheduler_group_handle in group_registry.iter() {
+            if scheduler_group_handle.is_private() {
+                next_group_registry.add_group(scheduler_group_handle.group_handle.clone())?;
+            }
+        }
+
+        let mut previous_clients = vec![];
+        for group in group_registry.get_groups() {
+            for client_handle in group.get_clients().await {
+                let url = client_handle.descriptor().await.get_full_url();
+                previous_clients.push((url, client_handle));
+            }
+        }
+
+        let mut reconfiguration = Reconfiguration {
+            groups: groups.len(),
+            ..Default::default()
+        };
+        for (group, client_descriptors) in groups.iter().zip(client_descriptors) {
+            for descriptor in client_descriptors {
+                let url = descriptor.get_full_url();
+                match previous_clients
+                    .iter()
+                    .position(|(previous_url, _)| *previous_url == url)
+                {
+                    Some(idx) => {
+                        let (_, client_handle) = previous_clients.remove(idx);
+                        group.push_existing_client(client_handle, descriptor).await;
+                        reconfiguration.kept_clients += 1;
+                    }
+                    None => {
+                        let client_handle = self.create_client(descriptor, backend_info.cloned());
+                        group.push_client(client_handle).await;
+                        reconfiguration.added_clients += 1;
+                    }
+                }
+            }
+        }
+
+        next_group_registry.draining = std::mem::take(&mut group_registry.draining);
+        for (_, client_handle) in previous_clients {
+            // Remove event sender not to notify about removed client status changes
+            client_handle.take_event_sender();
+            next_group_registry.draining.push(client_handle.clone());
+            task::spawn_named(
+                "client drain",
+                Self::drain_client(self.group_registry.clone(), client_handle),
+            );
+            reconfiguration.removed_clients += 1;
+        }
+        *group_registry = next_group_registry;
+
+        Ok(reconfiguration)
+    }
+
+    /// Keep removed client running until its pending solutions are delivered and then stop it
+    async fn drain_client(group_registry: Arc<Mutex<GroupRegistry>>, client_handle: Arc<Handle>) {
+        tokio::time::sleep(Self::DRAIN_GRACE_PERIOD).await;
+        let deadline = time::Instant::now() + Self::DRAIN_TIMEOUT;
+        while client_handle.is_running()
+            && client_handle.pending_solutions() > 0
+            && time::Instant::now() < deadline
+        {
+            tokio::time::sleep(Self::FLUSH_POLL_INTERVAL).await;
+        }
+        let _ = client_handle.try_disable();
+        group_registry
+            .lock()
+            .await
+            .draining
+            .retain(|draining_client| draining_client != &client_handle);
+    }
+
+    /// Create private group which directs fixed share of generated work to the fee pool. It has to
+    /// be created after regular groups because the first group cannot have fixed share ratio.
+    /// The group is not reported among regular groups and it survives reconfiguration.
+    pub async fn create_fee_group(
+        &self,
+        fee_config: &FeeConfig,
+        backend_info: Option<&hal::BackendInfo>,
+    ) -> error::Result<Arc<Group>> {
+        let share_ratio = fee_config.share_ratio().map_err(|e| e.to_string())?;
+        let descriptor = Self::create_client_descriptor(&fee_config.pool_config(), true)?;
+        let group = self
+            .create_group(GroupDescriptor::new(
+                Self::FEE_GROUP_NAME.to_string(),
+                true,
+                LoadBalanceStrategy::FixedShareRatio(share_ratio),
+            ))
+            .await?;
+        let client_handle = self.create_client(descriptor, backend_info.cloned());
+        group.push_client(client_handle).await;
+        Ok(group)
+    }
+
+    pub async fn get_fee_status(&self) -> Option<FeeStatus> {
+        self.group_registry
+            .lock()
+            .await
+            .get_private_group_status(Self::FEE_GROUP_NAME)
+            .map(|(group, share_ratio, generated_share_ratio)| FeeStatus {
+                group,
+                share_ratio,
+                generated_share_ratio,
+            })
+    }
+
     #[inline]
     pub fn subscribe_to_clients_status_changes(&self) -> event::Receiver {
         self.event_monitor.subscribe()
@@ -602,10 +1106,15 @@ impl Manager {
         &self,
         descriptor: GroupDescriptor,
     ) -> Result<Arc<Group>, error::Client> {
-        self.group_registry
-            .lock()
-            .await
-            .create_group(descriptor, self.midstate_count)
+        let selection_policy = self.resolve_selection_policy(&descriptor);
+        self.group_registry.lock().await.create_group(
+            descriptor,
+            self.midstate_count.clone(),
+            self.min_midstate_count,
+            self.ntime_roll,
+            self.midstate_compute.clone(),
+            selection_policy,
+        )
     }
 
     pub async fn create_or_get_default_group(&self) -> Arc<Group> {
@@ -613,7 +1122,14 @@ impl Manager {
         match group_registry.get_group(GroupDescriptor::DEFAULT_INDEX) {
             Some(group) => group,
             None => group_registry
-                .create_group(Default::default(), self.midstate_count)
+                .create_group(
+                    Default::default(),
+                    self.midstate_count.clone(),
+                    self.min_midstate_count,
+                    self.ntime_roll,
+                    self.midstate_compute.clone(),
+                    self.selection_policy.clone(),
+                )
                 .expect("BUG: cannot create default group"),
         }
     }
@@ -630,4 +1146,219 @@ impl Manager {
     pub async fn get_groups(&self) -> Vec<Arc<Group>> {
         self.group_registry.lock().await.get_groups()
     }
+
+    /// Number of solutions waiting for submission or acknowledgement in all running clients.
+    /// Solutions of clients which are not running cannot be delivered so they are not counted.
+    pub async fn pending_solutions(&self) -> usize {
+        let mut pending_solutions = 0;
+        for group in self.get_groups().await {
+            for client in group.get_clients().await {
+                if client.is_running() {
+                    pending_solutions += client.pending_solutions();
+                }
+            }
+        }
+        pending_solutions
+    }
+
+    /// Wait until all running clients deliver their pending solutions or until `timeout`
+    /// elapses. Work generation should be stopped beforehand otherwise the flush may never
+    /// finish. Returns number of solutions which are still pending.
+    pub async fn flush_solutions(&self, timeout: time::Duration) -> usize {
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            let pending_solutions = self.pending_solutions().await;
+            if pending_solutions == 0 || time::Instant::now() >= deadline {
+                return pending_solutions;
+            }
+            tokio::time::sleep(Self::FLUSH_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Reconnect all enabled clients
+    pub async fn restart_clients(&self) {
+        for group in self.get_groups().await {
+            for client in group.get_clients().await {
+                if client.is_enabled() {
+                    let _ = client.try_restart(true);
+                }
+            }
+        }
+    }
+
+    /// Disable all clients and close their connections
+    pub async fn stop_clients(&self) {
+        for group in self.get_groups().await {
+            for client in group.get_clients().await {
+                let _ = client.try_disable();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bosminer_config::PoolSelection;
+
+    use serde_json::json;
+
+    fn group_configs(value: serde_json::Value) -> Vec<GroupConfig> {
+        serde_json::from_value(value).expect("BUG: invalid group configuration")
+    }
+
+    fn test_settings() -> ManagerSettings {
+        ManagerSettings {
+            midstate_count: 1,
+            min_midstate_count: 1,
+            version_mask: 0,
+            ntime_roll: 0,
+            midstate_compute: work::midstate::default(),
+            selection_policy: work::policy::from_config(PoolSelection::PrimaryWithBackup),
+            telemetry: None,
+            stratum_record_dir: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure() {
+        let manager = Manager::new(test_settings());
+
+        let reconfiguration = manager
+            .reconfigure(
+                group_configs(json!([{
+                    "name": "Default",
+                    "quota": 1,
+                    "pool": [
+                        { "url": "drain://a", "user": "user", "enabled": false },
+                        { "url": "drain://b", "user": "user", "enabled": false },
+                    ],
+                }])),
+                None,
+                true,
+            )
+            .await
+            .expect("BUG: cannot reconfigure groups");
+        assert_eq!(
+            reconfiguration,
+            Reconfiguration {
+                groups: 1,
+                added_clients: 2,
+                ..Default::default()
+            }
+        );
+        let kept_client = manager.get_groups().await[0].get_clients().await[1].clone();
+
+        // pool `a` is removed, `b` is moved to a new group and `c` is added
+        let reconfiguration = manager
+            .reconfigure(
+                group_configs(json!([
+                    {
+                        "name": "Main",
+                        "quota": 2,
+                        "pool": [
+                            { "url": "drain://b", "user": "user", "enabled": false, "weight": 2.0 },
+                        ],
+                    },
+                    {
+                        "name": "Backup",
+                        "quota": 1,
+                        "pool": [{ "url": "drain://c", "user": "user", "enabled": false }],
+                    },
+                ])),
+                None,
+                true,
+            )
+            .await
+            .expect("BUG: cannot reconfigure groups");
+        assert_eq!(
+            reconfiguration,
+            Reconfiguration {
+                groups: 2,
+                added_clients: 1,
+                kept_clients: 1,
+                removed_clients: 1,
+            }
+        );
+        let groups = manager.get_groups().await;
+        assert_eq!(groups[0].descriptor.name, "Main");
+        assert!(Arc::ptr_eq(&groups[0].get_clients().await[0], &kept_client));
+        assert_eq!(kept_client.descriptor().await.weight, 2.0);
+        assert_eq!(groups[1].get_clients().await.len(), 1);
+        // removed client still receives solutions until it is drained
+        assert_eq!(manager.group_registry.lock().await.draining.len(), 1);
+
+        // invalid configuration is rejected as a whole
+        assert!(manager
+            .reconfigure(
+                group_configs(json!([{ "name": "Fixed", "fixed_share_ratio": 0.5 }])),
+                None,
+                true,
+            )
+            .await
+            .is_err());
+        assert_eq!(manager.get_groups().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fee_group() {
+        let manager = Manager::new(test_settings());
+        let fee_config: FeeConfig = serde_json::from_value(json!({
+            "url": "drain://fee",
+            "user": "fee",
+            "percent": 2.0,
+        }))
+        .expect("BUG: invalid fee configuration");
+
+        // fee cannot be the only source of work
+        assert!(manager.create_fee_group(&fee_config, None).await.is_err());
+        assert!(manager.get_fee_status().await.is_none());
+
+        manager
+            .load_config(
+                group_configs(json!([{
+                    "name": "Default",
+                    "pool": [{ "url": "drain://a", "user": "user", "enabled": false }],
+                }])),
+                None,
+                true,
+            )
+            .await
+            .expect("BUG: cannot load groups");
+        manager
+            .create_fee_group(&fee_config, None)
+            .await
+            .expect("BUG: cannot create fee group");
+        assert_eq!(manager.get_groups().await.len(), 1);
+
+        let fee_status = manager
+            .get_fee_status()
+            .await
+            .expect("BUG: missing fee group");
+        assert!((fee_status.share_ratio - 0.02).abs() < f64::EPSILON);
+        assert_eq!(fee_status.generated_share_ratio, 0.0);
+
+        // fee group is kept when regular groups are replaced
+        manager
+            .reconfigure(
+                group_configs(json!([{
+                    "name": "Main",
+                    "pool": [{ "url": "drain://b", "user": "user", "enabled": false }],
+                }])),
+                None,
+                true,
+            )
+            .await
+            .expect("BUG: cannot reconfigure groups");
+        let fee_status = manager
+            .get_fee_status()
+            .await
+            .expect("BUG: missing fee group");
+        assert_eq!(fee_status.group.get_clients().await.len(), 1);
+
+        let mut invalid_fee_config = fee_config.clone();
+        invalid_fee_config.percent = FeeConfig::MAX_PERCENT + 1.0;
+        assert!(invalid_fee_config.share_ratio().is_err());
+    }
 }
@@ -0,0 +1,566 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Solo mining client which gets block templates from bitcoind (`getblocktemplate`) and submits
+//! found blocks back to it (`submitblock`)
+
+mod template;
+
+use ii_logging::macros::*;
+
+use crate::error;
+use crate::job;
+use crate::node;
+use crate::stats;
+use crate::sync;
+use crate::work;
+
+use bosminer_config::{ClientDescriptor, ClientExtranoncePartition, ClientRetrySchedule};
+use bosminer_macros::ClientNode;
+
+use ii_bitcoin::FromHex;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use ii_async_compat::prelude::*;
+use ii_async_compat::select;
+use tokio::time::sleep;
+
+use serde_json::json;
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionDetails {
+    pub user: String,
+    pub password: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// Script (scriptPubKey) the block reward is paid to
+    pub payout_script: Vec<u8>,
+    pub retry_schedule: ClientRetrySchedule,
+    /// Part of coinbase extranonce space used by this miner so that multiple miners paying to
+    /// the same script don't build identical coinbases
+    pub extranonce_partition: ClientExtranoncePartition,
+}
+
+impl ConnectionDetails {
+    const RPC_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+    pub fn from_descriptor(descriptor: &ClientDescriptor) -> Self {
+        let payout_script = match &descriptor.protocol {
+            bosminer_config::ClientProtocol::Bitcoind(payout_script) => payout_script.clone(),
+            _ => panic!("BUG: client supports only bitcoind protocol!"),
+        };
+        Self {
+            user: descriptor.user.clone(),
+            password: descriptor.password.clone(),
+            host: descriptor.host.clone(),
+            port: descriptor.port(),
+            payout_script,
+            retry_schedule: descriptor.retry_schedule,
+            extranonce_partition: descriptor.extranonce_partition,
+        }
+    }
+
+    fn get_host_and_port(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Send one HTTP JSON-RPC request and return raw HTTP response
+    async fn send_request(&self, body: String) -> error::Result<Vec<u8>> {
+        let credentials = format!(
+            "{}:{}",
+            self.user,
+            self.password.as_ref().map(String::as_str).unwrap_or("")
+        );
+        let request = format!(
+            "POST / HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Authorization: Basic {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            self.get_host_and_port(),
+            base64::encode(&credentials),
+            body.len(),
+            body
+        );
+
+        let mut stream = ii_wire::Address(self.host.clone(), self.port)
+            .connect()
+            .await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok(response)
+    }
+
+    /// Call bitcoind RPC `method` and return its result
+    async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> error::Result<serde_json::Value> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "bosminer",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let response = self
+            .send_request(body)
+            .timeout(Self::RPC_TIMEOUT)
+            .await
+            .map_err(|_| error::Error::from(format!("Bitcoind: '{}' timeout", method)))??;
+
+        let separator = b"\r\n\r\n";
+        let header_end = response
+            .windows(separator.len())
+            .position(|window| window == separator)
+            .ok_or("Bitcoind: malformed HTTP response")?;
+        let (header, body) = response.split_at(header_end + separator.len());
+        let status_line = String::from_utf8_lossy(header);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        if status_line.contains(" 401 ") || status_line.contains(" 403 ") {
+            Err(format!(
+                "Bitcoind: authorization failed for user '{}'",
+                self.user
+            ))?;
+        }
+
+        // Bitcoind responds with error status but valid JSON when the call itself fails
+        let mut response: serde_json::Value = serde_json::from_slice(body).map_err(|e| {
+            error::Error::from(format!(
+                "Bitcoind: invalid response ({}): {}",
+                status_line, e
+            ))
+        })?;
+        match response.get("error") {
+            None | Some(serde_json::Value::Null) => {}
+            Some(rpc_error) => Err(format!("Bitcoind: '{}' failed: {}", method, rpc_error))?,
+        }
+        Ok(response["result"].take())
+    }
+}
+
+/// Block template extended with parts constructed by the client
+#[derive(Debug)]
+struct BlockTemplate {
+    version: u32,
+    prev_hash: ii_bitcoin::DHash,
+    time: u32,
+    bits: u32,
+    target: ii_bitcoin::Target,
+    transactions: Vec<template::Transaction>,
+    merkle_branch: Vec<ii_bitcoin::DHash>,
+    coinbase: template::Coinbase,
+    /// Solutions of the template are worthless when the network moves to a new block
+    valid: AtomicBool,
+}
+
+impl BlockTemplate {
+    fn parse(result: &serde_json::Value, payout_script: &[u8]) -> error::Result<Self> {
+        let field = |name: &str| {
+            result
+                .get(name)
+                .ok_or_else(|| format!("Bitcoind: missing '{}' in block template", name))
+        };
+        let field_u64 = |name: &str| {
+            field(name)?
+                .as_u64()
+                .ok_or_else(|| format!("Bitcoind: invalid '{}' in block template", name))
+        };
+        let field_str = |name: &str| {
+            field(name)?
+                .as_str()
+                .ok_or_else(|| format!("Bitcoind: invalid '{}' in block template", name))
+        };
+        let invalid = |name: &str| format!("Bitcoind: invalid '{}' in block template", name);
+
+        let bits = u32::from_str_radix(field_str("bits")?, 16).map_err(|_| invalid("bits"))?;
+        let target = ii_bitcoin::Target::from_compact(bits).map_err(|_| invalid("bits"))?;
+
+        let transactions = field("transactions")?
+            .as_array()
+            .ok_or_else(|| invalid("transactions"))?
+            .iter()
+            .map(|transaction| {
+                let data = transaction["data"]
+                    .as_str()
+                    .and_then(|data| hex::decode(data).ok());
+                let txid = transaction["txid"]
+                    .as_str()
+                    .and_then(|txid| ii_bitcoin::DHash::from_hex(txid).ok());
+                match (data, txid) {
+                    (Some(data), Some(txid)) => Ok(template::Transaction { data, txid }),
+                    _ => Err(invalid("transactions")),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let txids: Vec<_> = transactions
+            .iter()
+            .map(|transaction| transaction.txid)
+            .collect();
+
+        let witness_commitment = match result.get("default_witness_commitment") {
+            Some(commitment) => Some(
+                commitment
+                    .as_str()
+                    .and_then(|commitment| hex::decode(commitment).ok())
+                    .ok_or_else(|| invalid("default_witness_commitment"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            version: field_u64("version")? as u32,
+            prev_hash: ii_bitcoin::DHash::from_hex(field_str("previousblockhash")?)
+                .map_err(|_| invalid("previousblockhash"))?,
+            time: field_u64("curtime")? as u32,
+            bits,
+            target,
+            merkle_branch: template::merkle_branch(&txids),
+            transactions,
+            coinbase: template::Coinbase {
+                height: field_u64("height")?,
+                value: field_u64("coinbasevalue")?,
+                payout_script: payout_script.to_vec(),
+                witness_commitment,
+            },
+            valid: AtomicBool::new(true),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Job {
+    client: Weak<Client>,
+    template: Arc<BlockTemplate>,
+    /// Unique value in coinbase script making the merkle root of each job different
+    extranonce: u64,
+    merkle_root: ii_bitcoin::DHash,
+}
+
+impl Job {
+    fn new(client: &Arc<Client>, template: Arc<BlockTemplate>, extranonce: u64) -> Self {
+        let merkle_root =
+            template::merkle_root(template.coinbase.txid(extranonce), &template.merkle_branch);
+        Self {
+            client: Arc::downgrade(client),
+            template,
+            extranonce,
+            merkle_root,
+        }
+    }
+
+    /// Serialize the whole block with the solved header
+    fn serialize_block(&self, header: &ii_bitcoin::BlockHeader) -> Vec<u8> {
+        template::serialize_block(
+            header,
+            &self.template.coinbase.serialize(self.extranonce, true),
+            &self.template.transactions,
+        )
+    }
+}
+
+impl job::Bitcoin for Job {
+    fn origin(&self) -> Weak<dyn node::Client> {
+        self.client.clone()
+    }
+
+    fn version(&self) -> u32 {
+        self.template.version
+    }
+
+    fn version_mask(&self) -> u32 {
+        ii_bitcoin::BIP320_VERSION_MASK
+    }
+
+    fn previous_hash(&self) -> &ii_bitcoin::DHash {
+        &self.template.prev_hash
+    }
+
+    fn merkle_root(&self) -> &ii_bitcoin::DHash {
+        &self.merkle_root
+    }
+
+    fn time(&self) -> u32 {
+        self.template.time
+    }
+
+    fn bits(&self) -> u32 {
+        self.template.bits
+    }
+
+    fn target(&self) -> ii_bitcoin::Target {
+        // Only solutions meeting network target are worth submitting
+        self.template.target
+    }
+
+    fn is_valid(&self) -> bool {
+        self.template.valid.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, ClientNode)]
+pub struct Client {
+    connection_details: ConnectionDetails,
+    #[member_status]
+    status: sync::StatusMonitor,
+    #[member_client_stats]
+    stats: stats::BasicClient,
+    stop_sender: mpsc::Sender<()>,
+    stop_receiver: Mutex<mpsc::Receiver<()>>,
+    last_job: Mutex<Option<Arc<Job>>>,
+    /// Sequence number of coinbase extranonce (unique for every job)
+    extranonce_seq: AtomicU64,
+    job_sender: Mutex<job::Sender>,
+    solution_receiver: Mutex<job::SolutionReceiver>,
+}
+
+impl Client {
+    /// How often bitcoind is asked for a new block template
+    const POLL_INTERVAL: time::Duration = time::Duration::from_secs(5);
+    /// Maximal age of a job with the same previous block hash (to include new transactions)
+    const NEW_JOB_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+    pub fn new(connection_details: ConnectionDetails, solver: job::Solver) -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel(1);
+        Self {
+            connection_details,
+            status: Default::default(),
+            stats: Default::default(),
+            stop_sender,
+            stop_receiver: Mutex::new(stop_receiver),
+            last_job: Mutex::new(None),
+            extranonce_seq: AtomicU64::new(0),
+            job_sender: Mutex::new(solver.job_sender),
+            solution_receiver: Mutex::new(solver.solution_receiver),
+        }
+    }
+
+    async fn update_last_job(&self, job: Arc<Job>) {
+        self.last_job.lock().await.replace(job);
+    }
+
+    async fn last_job(&self) -> Option<Arc<Job>> {
+        self.last_job.lock().await.as_ref().map(|job| job.clone())
+    }
+
+    async fn get_block_template(&self) -> error::Result<BlockTemplate> {
+        let result = self
+            .connection_details
+            .call("getblocktemplate", json!([{ "rules": ["segwit"] }]))
+            .await?;
+        BlockTemplate::parse(&result, &self.connection_details.payout_script)
+    }
+
+    async fn send_job(self: &Arc<Self>, template: Arc<BlockTemplate>) {
+        let extranonce = self
+            .connection_details
+            .extranonce_partition
+            .extranonce(self.extranonce_seq.fetch_add(1, Ordering::Relaxed));
+        let job = Arc::new(Job::new(self, template, extranonce));
+
+        self.update_last_job(job.clone()).await;
+        self.job_sender.lock().await.send(job);
+    }
+
+    /// Get new block template and replace current job when the network moved to a new block or
+    /// the current job is too old
+    async fn update_job_and_wait(
+        self: Arc<Self>,
+        last_update: &mut Option<time::Instant>,
+    ) -> error::Result<()> {
+        let template = self.get_block_template().await?;
+        let last_job = self.last_job().await;
+        let new_block = last_job
+            .as_ref()
+            .map(|job| job.template.prev_hash != template.prev_hash)
+            .unwrap_or(true);
+        let expired = last_update
+            .map(|instant| instant.elapsed() >= Self::NEW_JOB_INTERVAL)
+            .unwrap_or(true);
+
+        if new_block || expired {
+            if new_block {
+                info!(
+                    "Bitcoind: new block template at height {} on top of {}",
+                    template.coinbase.height, template.prev_hash
+                );
+                if let Some(job) = last_job {
+                    job.template.valid.store(false, Ordering::Relaxed);
+                }
+            }
+            self.send_job(Arc::new(template)).await;
+            last_update.replace(time::Instant::now());
+        }
+
+        sleep(Self::POLL_INTERVAL).await;
+        Ok(())
+    }
+
+    async fn submit_solution(&self, solution: work::Solution) -> error::Result<()> {
+        let job: &Job = solution.job();
+        let block = job.serialize_block(&solution.get_block_header());
+        info!(
+            "Bitcoind: submitting block {} at height {}",
+            solution.hash(),
+            job.template.coinbase.height
+        );
+
+        let result = self
+            .connection_details
+            .call("submitblock", json!([hex::encode(block)]))
+            .await?;
+        let now = std::time::Instant::now();
+        // Null result means that the block has been accepted, otherwise it contains a reason
+        if result.is_null() {
+            info!("Bitcoind: block {} accepted", solution.hash());
+            stats::account_accepted_solution(&self.stats, &solution, now).await;
+        } else {
+            warn!("Bitcoind: block {} rejected: {}", solution.hash(), result);
+            let reason = stats::RejectReason::classify(result.as_str().unwrap_or_default());
+            stats::account_rejected_solution(&self.stats, &solution, now, reason).await;
+        }
+        Ok(())
+    }
+
+    async fn main_loop(self: Arc<Self>, template: BlockTemplate) -> error::Result<()> {
+        let mut solution_receiver = self.solution_receiver.lock().await;
+
+        self.send_job(Arc::new(template)).await;
+        let mut last_update = Some(time::Instant::now());
+
+        while !self.status.is_shutting_down() {
+            select! {
+                result = self.clone().update_job_and_wait(&mut last_update).fuse() => result?,
+                solution = solution_receiver.receive().fuse() => {
+                    match solution {
+                        Some(solution) => self.submit_solution(solution).await?,
+                        None => {
+                            // TODO: initiate Destroying and remove error
+                            Err("Standard application shutdown")?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn wait_for_retry(&self) {
+        let retries = self.status.retries();
+        if retries > 0 {
+            let delay =
+                sync::backoff::Backoff::new(self.connection_details.retry_schedule).delay(retries);
+            info!(
+                "Bitcoind: retrying connection to {} (attempt #{}) in {:.1}s",
+                self.connection_details.get_host_and_port(),
+                retries,
+                delay.as_secs_f64()
+            );
+            sleep(delay).await;
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        self.wait_for_retry().await;
+        // The first block template also verifies the connection and credentials
+        match self.get_block_template().await {
+            Ok(template) => {
+                if self.status.initiate_running() {
+                    if let Err(e) = self.clone().main_loop(template).await {
+                        warn!("Bitcoind: {}", e);
+                        self.status.initiate_failing();
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Bitcoind: {}", e);
+                self.status.initiate_failing();
+            }
+        }
+    }
+
+    async fn main_task(self: Arc<Self>) {
+        loop {
+            let mut stop_receiver = self.stop_receiver.lock().await;
+            select! {
+                _ = self.clone().run().fuse() => {}
+                _ = stop_receiver.next() => {}
+            }
+
+            // Invalidate current job to stop working on it
+            self.job_sender.lock().await.invalidate();
+
+            if self.status.can_stop() {
+                // NOTE: it is not safe to add here any code!
+                break;
+            }
+            // Restarting
+        }
+    }
+}
+
+#[async_trait]
+impl node::Client for Client {
+    fn start(self: Arc<Self>) {
+        tokio::spawn(self.clone().main_task());
+    }
+
+    fn stop(&self) {
+        if let Err(e) = self.stop_sender.clone().try_send(()) {
+            assert!(
+                e.is_full(),
+                "BUG: Unexpected error in stop sender: {}",
+                e.to_string()
+            );
+        }
+    }
+
+    async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
+        self.last_job()
+            .await
+            .map(|job| job as Arc<dyn job::Bitcoin>)
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://{}",
+            bosminer_config::ClientProtocol::SCHEME_BITCOIND,
+            self.connection_details.get_host_and_port()
+        )
+    }
+}
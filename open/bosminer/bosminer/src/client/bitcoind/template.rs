@@ -0,0 +1,291 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Construction of block parts which are not provided by bitcoind block template: coinbase
+//! transaction, merkle branch of the coinbase and the final block serialization
+
+use ii_bitcoin::HashTrait;
+
+/// Text embedded into coinbase script
+const COINBASE_TAG: &[u8] = b"/bosminer/";
+
+/// Transaction taken over from block template
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// Serialized transaction (including witness data)
+    pub data: Vec<u8>,
+    /// Transaction ID used for merkle root computation
+    pub txid: ii_bitcoin::DHash,
+}
+
+/// Append integer encoded as Bitcoin `CompactSize`
+pub fn write_var_int(buf: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => buf.push(value as u8),
+        0xfd..=0xffff => {
+            buf.push(0xfd);
+            buf.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            buf.push(0xfe);
+            buf.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            buf.push(0xff);
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Append data push operation into script
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    assert!(
+        data.len() < 0x4c,
+        "BUG: unsupported length of script data push"
+    );
+    script.push(data.len() as u8);
+    script.extend_from_slice(data);
+}
+
+/// Append block height in the form required by BIP34 (same as `CScript() << height`)
+fn push_height(script: &mut Vec<u8>, height: u64) {
+    match height {
+        // OP_0
+        0 => script.push(0x00),
+        // OP_1 .. OP_16
+        1..=16 => script.push(0x50 + height as u8),
+        _ => {
+            // Minimal little endian script number with additional byte when the sign bit is set
+            let mut number: Vec<u8> = height
+                .to_le_bytes()
+                .iter()
+                .cloned()
+                .rev()
+                .skip_while(|byte| *byte == 0)
+                .collect();
+            number.reverse();
+            if number.last().map(|byte| byte & 0x80 != 0).unwrap_or(false) {
+                number.push(0x00);
+            }
+            push_data(script, &number);
+        }
+    }
+}
+
+/// Generation transaction paying the block reward to the miner
+#[derive(Debug, Clone)]
+pub struct Coinbase {
+    pub height: u64,
+    /// Block reward including transaction fees
+    pub value: u64,
+    pub payout_script: Vec<u8>,
+    /// Script of the output committing to witness data (BIP141)
+    pub witness_commitment: Option<Vec<u8>>,
+}
+
+impl Coinbase {
+    /// Build coinbase with `extranonce` in its script. Witness serialization is used for the block
+    /// and legacy serialization is used for the transaction ID.
+    pub fn serialize(&self, extranonce: u64, with_witness: bool) -> Vec<u8> {
+        let with_witness = with_witness && self.witness_commitment.is_some();
+        let mut tx = Vec::new();
+
+        // Version
+        tx.extend_from_slice(&1u32.to_le_bytes());
+        if with_witness {
+            // Marker and flag
+            tx.extend_from_slice(&[0x00, 0x01]);
+        }
+
+        // Single input spending null outpoint
+        write_var_int(&mut tx, 1);
+        tx.extend_from_slice(&[0u8; 32]);
+        tx.extend_from_slice(&u32::max_value().to_le_bytes());
+        let mut script = Vec::new();
+        push_height(&mut script, self.height);
+        push_data(&mut script, &extranonce.to_le_bytes());
+        push_data(&mut script, COINBASE_TAG);
+        write_var_int(&mut tx, script.len() as u64);
+        tx.extend_from_slice(&script);
+        tx.extend_from_slice(&u32::max_value().to_le_bytes());
+
+        // Outputs
+        write_var_int(
+            &mut tx,
+            1 + self.witness_commitment.as_ref().map_or(0, |_| 1),
+        );
+        tx.extend_from_slice(&self.value.to_le_bytes());
+        write_var_int(&mut tx, self.payout_script.len() as u64);
+        tx.extend_from_slice(&self.payout_script);
+        if let Some(witness_commitment) = &self.witness_commitment {
+            tx.extend_from_slice(&0u64.to_le_bytes());
+            write_var_int(&mut tx, witness_commitment.len() as u64);
+            tx.extend_from_slice(witness_commitment);
+        }
+
+        if with_witness {
+            // Witness reserved value: one item of 32 zero bytes
+            write_var_int(&mut tx, 1);
+            write_var_int(&mut tx, 32);
+            tx.extend_from_slice(&[0u8; 32]);
+        }
+
+        // Lock time
+        tx.extend_from_slice(&0u32.to_le_bytes());
+        tx
+    }
+
+    pub fn txid(&self, extranonce: u64) -> ii_bitcoin::DHash {
+        ii_bitcoin::DHash::hash(&self.serialize(extranonce, false))
+    }
+}
+
+fn hash_pair(left: &ii_bitcoin::DHash, right: &ii_bitcoin::DHash) -> ii_bitcoin::DHash {
+    let mut data = [0u8; 2 * ii_bitcoin::SHA256_DIGEST_SIZE];
+    data[..ii_bitcoin::SHA256_DIGEST_SIZE].copy_from_slice(&left.into_inner());
+    data[ii_bitcoin::SHA256_DIGEST_SIZE..].copy_from_slice(&right.into_inner());
+    ii_bitcoin::DHash::hash(&data)
+}
+
+/// Build merkle branch of the first (coinbase) transaction from IDs of all the other
+/// transactions in the block. The branch doesn't depend on the coinbase so it can be reused for
+/// every extranonce.
+pub fn merkle_branch(txids: &[ii_bitcoin::DHash]) -> Vec<ii_bitcoin::DHash> {
+    let mut branch = Vec::new();
+    // Nodes of the current tree level without the leftmost one which depends on the coinbase
+    let mut level = txids.to_vec();
+    while let Some((sibling, rest)) = level.split_first() {
+        branch.push(*sibling);
+        level = rest
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+    branch
+}
+
+/// Compute merkle root from coinbase transaction ID and its merkle branch
+pub fn merkle_root(
+    coinbase_txid: ii_bitcoin::DHash,
+    branch: &[ii_bitcoin::DHash],
+) -> ii_bitcoin::DHash {
+    branch
+        .iter()
+        .fold(coinbase_txid, |node, sibling| hash_pair(&node, sibling))
+}
+
+/// Serialize whole block ready for submission
+pub fn serialize_block(
+    header: &ii_bitcoin::BlockHeader,
+    coinbase: &[u8],
+    transactions: &[Transaction],
+) -> Vec<u8> {
+    let mut block = header.into_bytes().to_vec();
+    write_var_int(&mut block, 1 + transactions.len() as u64);
+    block.extend_from_slice(coinbase);
+    for transaction in transactions {
+        block.extend_from_slice(&transaction.data);
+    }
+    block
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn var_int(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_var_int(&mut buf, value);
+        buf
+    }
+
+    fn height(value: u64) -> Vec<u8> {
+        let mut script = Vec::new();
+        push_height(&mut script, value);
+        script
+    }
+
+    /// Compute merkle root the straightforward way from all transaction IDs
+    fn full_merkle_root(txids: &[ii_bitcoin::DHash]) -> ii_bitcoin::DHash {
+        let mut level = txids.to_vec();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn test_var_int() {
+        assert_eq!(var_int(0), vec![0x00]);
+        assert_eq!(var_int(0xfc), vec![0xfc]);
+        assert_eq!(var_int(0xfd), vec![0xfd, 0xfd, 0x00]);
+        assert_eq!(var_int(0x1_0000), vec![0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            var_int(0x1_0000_0000),
+            vec![0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_bip34_height() {
+        assert_eq!(height(0), vec![0x00]);
+        assert_eq!(height(1), vec![0x51]);
+        assert_eq!(height(16), vec![0x60]);
+        assert_eq!(height(17), vec![0x01, 0x11]);
+        assert_eq!(height(128), vec![0x02, 0x80, 0x00]);
+        assert_eq!(height(500_000), vec![0x03, 0x20, 0xa1, 0x07]);
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        let txids: Vec<_> = (0u8..12).map(|i| ii_bitcoin::DHash::hash(&[i])).collect();
+        for count in 1..=txids.len() {
+            let txids = &txids[..count];
+            let branch = merkle_branch(&txids[1..]);
+            assert_eq!(merkle_root(txids[0], &branch), full_merkle_root(txids));
+        }
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let mut coinbase = Coinbase {
+            height: 100,
+            value: 50_0000_0000,
+            payout_script: vec![0x51],
+            witness_commitment: None,
+        };
+        let legacy = coinbase.serialize(1, false);
+        // Witness serialization is used only when witness commitment is present
+        assert_eq!(coinbase.serialize(1, true), legacy);
+        assert_ne!(coinbase.txid(1), coinbase.txid(2));
+
+        coinbase.witness_commitment = Some(vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed]);
+        let legacy = coinbase.serialize(1, false);
+        let witness = coinbase.serialize(1, true);
+        // Marker, flag and witness reserved value
+        assert_eq!(witness.len(), legacy.len() + 2 + 1 + 1 + 32);
+        assert_eq!(&witness[4..6], &[0x00, 0x01]);
+    }
+}
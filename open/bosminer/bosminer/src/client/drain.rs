@@ -37,7 +37,7 @@ use futures::channel::mpsc;
 use futures::lock::Mutex;
 use ii_async_compat::prelude::*;
 use ii_async_compat::select;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -142,11 +142,14 @@ impl Difficulty {
             };
             let old_index = index;
 
-            index = self
-                .index
-                .compare_and_swap(index, new_index, Ordering::Relaxed);
-            if index == old_index {
-                break;
+            match self.index.compare_exchange(
+                old_index,
+                new_index,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => index = current,
             }
         }
     }
@@ -262,7 +265,7 @@ impl Client {
         self.update_last_job(job.clone()).await;
         self.job_sender.lock().await.send(job);
 
-        delay_for(Self::NEW_JOB_INTERVAL).await;
+        sleep(Self::NEW_JOB_INTERVAL).await;
     }
 
     async fn account_solution(&self, solution: work::Solution) {
@@ -0,0 +1,277 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Selection of an endpoint of a pool published under multiple hosts. Health of each endpoint
+//! is kept across reconnections and all endpoints are probed before every connection attempt
+//! so that the client connects to the healthy endpoint with the lowest latency. Switching of
+//! endpoints is transparent for the rest of the miner as the client (and its statistics) stays
+//! the same.
+//!
+//! Latency of the endpoint used by each running client is also measured periodically by timing
+//! of TCP connection setup and the samples are kept in client statistics.
+
+use ii_logging::macros::*;
+
+use crate::hub;
+
+use ii_async_compat::{futures, tokio};
+use tokio::time::sleep;
+
+use bosminer_config::ClientDescriptor;
+
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+use std::time;
+
+/// How often the latency of selected endpoints is measured
+const LATENCY_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Health {
+    /// Time needed to open TCP connection during the last successful attempt
+    latency: Option<time::Duration>,
+    /// Number of consecutive failed connection attempts
+    failures: u32,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Addresses of endpoints (`host:port`) with their health
+    endpoints: Vec<(String, Health)>,
+    /// Endpoint returned by the last selection
+    selected: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Selector {
+    inner: StdMutex<Inner>,
+}
+
+impl Selector {
+    /// Maximal time for opening TCP connection to each endpoint during probing
+    const PROBE_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+    pub fn new(addresses: Vec<String>) -> Self {
+        assert!(!addresses.is_empty(), "BUG: missing pool endpoint");
+        Self {
+            inner: StdMutex::new(Inner {
+                endpoints: addresses
+                    .into_iter()
+                    .map(|address| (address, Default::default()))
+                    .collect(),
+                selected: None,
+            }),
+        }
+    }
+
+    pub fn from_descriptor(descriptor: &ClientDescriptor) -> Self {
+        Self::new(descriptor.endpoint_addresses())
+    }
+
+    fn lock_inner(&self) -> StdMutexGuard<Inner> {
+        self.inner.lock().expect("BUG: cannot lock endpoints")
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        self.lock_inner()
+            .endpoints
+            .iter()
+            .map(|(address, _)| address.clone())
+            .collect()
+    }
+
+    fn update_health<F>(&self, address: &str, f: F)
+    where
+        F: FnOnce(&mut Health),
+    {
+        if let Some((_, health)) = self
+            .lock_inner()
+            .endpoints
+            .iter_mut()
+            .find(|(endpoint_address, _)| endpoint_address == address)
+        {
+            f(health);
+        }
+    }
+
+    /// Account successful connection to endpoint `address` which took `latency`
+    pub fn account_success(&self, address: &str, latency: time::Duration) {
+        self.update_health(address, |health| {
+            health.latency = Some(latency);
+            health.failures = 0;
+        });
+    }
+
+    /// Account failed connection attempt to endpoint `address`
+    pub fn account_failure(&self, address: &str) {
+        self.update_health(address, |health| health.failures += 1);
+    }
+
+    /// Endpoint with the fewest consecutive failures and the lowest latency. Endpoints with
+    /// unknown latency come after the measured ones and ties keep the configured order.
+    pub fn best(&self) -> String {
+        self.lock_inner()
+            .endpoints
+            .iter()
+            .min_by_key(|(_, health)| (health.failures, health.latency.is_none(), health.latency))
+            .map(|(address, _)| address.clone())
+            .expect("BUG: missing pool endpoint")
+    }
+
+    /// Endpoint returned by the last selection
+    pub fn selected(&self) -> Option<String> {
+        self.lock_inner().selected.clone()
+    }
+
+    /// Time needed to open TCP connection to endpoint `address`
+    async fn probe(address: &str) -> io::Result<time::Duration> {
+        let address = ii_wire::Address::from_str(address)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let start = time::Instant::now();
+        address.connect_timeout(Self::PROBE_TIMEOUT).await?;
+        Ok(start.elapsed())
+    }
+
+    /// Probe all endpoints (when there are more of them) and select the best one for the next
+    /// connection attempt
+    pub async fn select(&self) -> String {
+        let addresses = self.addresses();
+        if addresses.len() > 1 {
+            let results =
+                futures::future::join_all(addresses.iter().map(|address| Self::probe(address)))
+                    .await;
+            for (address, result) in addresses.iter().zip(results) {
+                match result {
+                    Ok(latency) => {
+                        trace!(
+                            "Stratum: endpoint {} latency {:.1} ms",
+                            address,
+                            latency.as_secs_f64() * 1e3
+                        );
+                        self.account_success(address, latency);
+                    }
+                    Err(e) => {
+                        debug!("Stratum: endpoint {} is not reachable: {}", address, e);
+                        self.account_failure(address);
+                    }
+                }
+            }
+        }
+
+        let best = self.best();
+        let mut inner = self.lock_inner();
+        if inner
+            .selected
+            .as_ref()
+            .map_or(false, |selected| *selected != best)
+        {
+            info!("Stratum: switching to endpoint {}", best);
+        }
+        inner.selected = Some(best.clone());
+        best
+    }
+
+    /// Measure latency of the selected endpoint. Return `None` when no endpoint has been selected
+    /// yet.
+    pub async fn measure_latency(&self) -> Option<io::Result<time::Duration>> {
+        let address = self.selected()?;
+        let result = Self::probe(&address).await;
+        if let Ok(latency) = &result {
+            self.account_success(&address, *latency);
+        }
+        Some(result)
+    }
+}
+
+/// Periodically measure latency of endpoints used by all running clients
+pub async fn latency_task(core: Arc<hub::Core>) {
+    loop {
+        sleep(LATENCY_INTERVAL).await;
+        for group in core.get_client_manager().get_groups().await {
+            for client in group.get_clients().await {
+                if !client.is_running() {
+                    continue;
+                }
+                let endpoints = match client.endpoints() {
+                    Some(endpoints) => endpoints,
+                    None => continue,
+                };
+                match endpoints.measure_latency().await {
+                    Some(Ok(latency)) => client.stats().latency().record(latency),
+                    Some(Err(e)) => debug!(
+                        "Stratum: cannot measure latency of endpoint {}: {}",
+                        endpoints.selected().unwrap_or_default(),
+                        e
+                    ),
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn selector() -> Selector {
+        Selector::new(vec![
+            "eu.pool:3333".to_string(),
+            "us.pool:3333".to_string(),
+            "asia.pool:3333".to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_best_by_latency() {
+        let selector = selector();
+        // Configured order is kept until latencies are known
+        assert_eq!(selector.best(), "eu.pool:3333");
+
+        selector.account_success("eu.pool:3333", time::Duration::from_millis(120));
+        selector.account_success("us.pool:3333", time::Duration::from_millis(30));
+        assert_eq!(selector.best(), "us.pool:3333");
+        // Endpoint with unknown latency is not preferred to slower measured ones
+        selector.account_success("us.pool:3333", time::Duration::from_millis(200));
+        assert_eq!(selector.best(), "eu.pool:3333");
+    }
+
+    #[test]
+    fn test_failing_endpoint() {
+        let selector = selector();
+        selector.account_success("eu.pool:3333", time::Duration::from_millis(10));
+        selector.account_success("us.pool:3333", time::Duration::from_millis(50));
+        selector.account_failure("eu.pool:3333");
+        assert_eq!(selector.best(), "us.pool:3333");
+
+        // All endpoints fail, the one with fewest failures is used
+        selector.account_failure("us.pool:3333");
+        selector.account_failure("us.pool:3333");
+        selector.account_failure("asia.pool:3333");
+        assert_eq!(selector.best(), "eu.pool:3333");
+
+        // Recovered endpoint is healthy again
+        selector.account_success("us.pool:3333", time::Duration::from_millis(50));
+        assert_eq!(selector.best(), "us.pool:3333");
+    }
+}
@@ -31,6 +31,16 @@ use ii_async_compat::{futures, FutureExt};
 use std::sync::Arc;
 use std::time;
 
+/// Half-life of work accounted to a client by the selection policy. Work generated long ago is
+/// forgotten so that a client which has been down for a while doesn't take all the work until
+/// it catches up with the others.
+const RECENT_WORK_HALF_LIFE: time::Duration = time::Duration::from_secs(60);
+
+/// Amount of `recent_work` which is left after `elapsed` time
+fn decay_recent_work(recent_work: f64, elapsed: time::Duration) -> f64 {
+    recent_work * 0.5f64.powf(elapsed.as_secs_f64() / RECENT_WORK_HALF_LIFE.as_secs_f64())
+}
+
 /// This struct cannot be shared and it is possible to use mutable references. However, the
 /// client handle is shared object with interior mutability scheduler::ClientHandle. It solves
 /// many synchronization problems.
@@ -38,16 +48,35 @@ use std::time;
 pub struct ClientHandle {
     pub client_handle: Arc<client::Handle>,
     last_generated_work: u64,
+    /// Work generated from the client recently (exponentially decayed with
+    /// `RECENT_WORK_HALF_LIFE`)
+    recent_work: f64,
+    /// Time of the last update of `recent_work`
+    last_update: time::Instant,
+    /// Relative weight of the client used by the selection policy
+    weight: f64,
 }
 
 impl ClientHandle {
-    pub fn new(client_handle: Arc<client::Handle>) -> Self {
+    pub fn new(client_handle: Arc<client::Handle>, weight: f64) -> Self {
         Self {
             last_generated_work: Self::get_generated_work(&client_handle),
+            recent_work: 0.0,
+            last_update: time::Instant::now(),
+            weight,
             client_handle,
         }
     }
 
+    #[inline]
+    fn candidate(&self) -> work::policy::Candidate {
+        work::policy::Candidate {
+            running: self.is_running(),
+            recent_work: self.recent_work,
+            weight: self.weight,
+        }
+    }
+
     #[inline]
     fn is_running(&self) -> bool {
         self.client_handle.is_running()
@@ -91,6 +120,13 @@ impl ClientHandle {
 
         let delta = next_generated_work - self.last_generated_work;
         self.last_generated_work = next_generated_work;
+
+        let now = time::Instant::now();
+        self.recent_work = decay_recent_work(
+            self.recent_work,
+            now.saturating_duration_since(self.last_update),
+        ) + delta as f64;
+        self.last_update = now;
         delta
     }
 }
@@ -150,23 +186,42 @@ impl GroupHandle {
         self.active_client = None;
         for scheduler_client_handle in scheduler_client_handles.iter_mut() {
             generated_work_delta += scheduler_client_handle.get_delta_and_update_generated_work();
-            match self.active_client {
-                None => {
-                    if scheduler_client_handle.is_running() {
-                        self.active_client = Some(scheduler_client_handle.client_handle.clone());
-                    } else {
-                        let _ = scheduler_client_handle.try_start();
-                    }
+        }
+
+        let selection_policy = &self.group_handle.selection_policy;
+        let candidates: Vec<_> = scheduler_client_handles
+            .iter()
+            .map(|scheduler_client_handle| scheduler_client_handle.candidate())
+            .collect();
+        let selected_idx = selection_policy.select(&candidates);
+
+        for (idx, scheduler_client_handle) in scheduler_client_handles.iter().enumerate() {
+            match selected_idx {
+                Some(selected_idx) if idx == selected_idx => {
+                    self.active_client = Some(scheduler_client_handle.client_handle.clone());
                 }
-                Some(_) => {
+                // Clients behind the selected one are not needed unless the policy wants them
+                // to be ready for switching
+                Some(selected_idx) if idx > selected_idx && !selection_policy.keep_connected() => {
                     let _ = scheduler_client_handle.try_delayed_stop();
                 }
+                _ => {
+                    if !scheduler_client_handle.is_running() {
+                        let _ = scheduler_client_handle.try_start();
+                    }
+                }
             }
         }
 
         self.generated_work += generated_work_delta;
     }
 
+    /// Work generated by all clients in the group since the last reset
+    #[inline]
+    pub fn generated_work(&self) -> u64 {
+        self.generated_work
+    }
+
     #[inline]
     pub fn reset_generated_work(&mut self) {
         self.generated_work = 0;
@@ -224,6 +279,9 @@ impl PartialEq<Arc<client::Handle>> for ActiveClient {
 struct JobDispatcher {
     active_client: ActiveClient,
     group_registry: Arc<Mutex<client::GroupRegistry>>,
+    /// When mining is paused, the channel to backends is parked in this idle engine sender which
+    /// broadcasts only exhausted work. Clients keep running and receiving jobs in the meantime.
+    parked: Option<Arc<work::EngineSender>>,
 }
 
 impl JobDispatcher {
@@ -234,6 +292,35 @@ impl JobDispatcher {
         Self {
             active_client: ActiveClient::None(Arc::new(engine_sender)),
             group_registry,
+            parked: None,
+        }
+    }
+
+    #[inline]
+    fn is_paused(&self) -> bool {
+        self.parked.is_some()
+    }
+
+    /// Stop passing jobs to backends. Returns `false` when mining has been already paused.
+    fn pause(&mut self) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        let parked = Arc::new(work::EngineSender::new(None));
+        parked.swap_sender(self.active_client.get_engine_sender());
+        self.parked = Some(parked);
+        true
+    }
+
+    /// Pass the most recent job of active client to backends again. Returns `false` when mining
+    /// has not been paused.
+    fn resume(&mut self) -> bool {
+        match self.parked.take() {
+            Some(parked) => {
+                self.active_client.get_engine_sender().swap_sender(&parked);
+                true
+            }
+            None => false,
         }
     }
 
@@ -363,6 +450,20 @@ impl JobExecutor {
         client.map(|client| client.solution_sender.clone())
     }
 
+    /// Pause mining without disconnecting clients. Backends get only exhausted work so they
+    /// stay idle until `resume` is called.
+    pub async fn pause(&self) -> bool {
+        self.lock_dispatcher().await.pause()
+    }
+
+    pub async fn resume(&self) -> bool {
+        self.lock_dispatcher().await.resume()
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        self.lock_dispatcher().await.is_paused()
+    }
+
     pub async fn run(self: Arc<Self>) {
         let mut event_receiver = self
             .event_monitor
@@ -396,3 +497,54 @@ impl JobExecutor {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::sync::event;
+    use crate::test_utils;
+
+    #[test]
+    fn test_decay_recent_work() {
+        assert_eq!(
+            decay_recent_work(100.0, time::Duration::from_secs(0)),
+            100.0
+        );
+        assert_eq!(decay_recent_work(100.0, RECENT_WORK_HALF_LIFE), 50.0);
+        assert_eq!(decay_recent_work(100.0, 2 * RECENT_WORK_HALF_LIFE), 25.0);
+        // work generated long ago is forgotten
+        assert!(decay_recent_work(1e12, 60 * RECENT_WORK_HALF_LIFE) < 1.0);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (engine_sender, engine_receiver) = work::engine_channel(work::IgnoreEvents);
+        let mut dispatcher = JobDispatcher::new(
+            engine_sender,
+            Arc::new(Mutex::new(
+                client::GroupRegistry::new(event::Monitor::new()),
+            )),
+        );
+        dispatcher
+            .active_client
+            .get_engine_sender()
+            .broadcast_engine(Arc::new(test_utils::TestWorkEngine::new()));
+        assert!(engine_receiver.has_work());
+
+        assert!(dispatcher.pause());
+        assert!(!dispatcher.pause());
+        assert!(!engine_receiver.has_work());
+
+        // job received while mining is paused is not passed to backends
+        dispatcher
+            .active_client
+            .get_engine_sender()
+            .broadcast_engine(Arc::new(test_utils::TestWorkEngine::new()));
+        assert!(!engine_receiver.has_work());
+
+        assert!(dispatcher.resume());
+        assert!(!dispatcher.resume());
+        assert!(engine_receiver.has_work());
+    }
+}
@@ -21,11 +21,15 @@
 // contact us at opensource@braiins.com.
 
 // Sub-modules with client implementation
+pub mod extension;
+pub mod record;
+pub mod submit;
 pub mod telemetry;
 
 use ii_logging::macros::*;
 
-use crate::error;
+use crate::client::endpoint;
+use crate::error::{self, ResultExt};
 use crate::hal;
 use crate::job;
 use crate::node;
@@ -33,11 +37,9 @@ use crate::stats;
 use crate::sync;
 use crate::work;
 
-use failure::ResultExt;
-
 use ii_bitcoin::HashTrait;
 
-use bosminer_config::{ClientDescriptor, ClientProtocol};
+use bosminer_config::{ClientDescriptor, ClientProtocol, ClientRetrySchedule};
 use bosminer_macros::ClientNode;
 
 use async_trait::async_trait;
@@ -46,9 +48,11 @@ use futures::lock::Mutex;
 use ii_async_compat::prelude::*;
 use ii_async_compat::select;
 
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Mutex as StdMutex;
 use std::sync::{Arc, Weak};
 use std::time;
@@ -57,7 +61,8 @@ use ii_stratum::v2::messages::{
     NewMiningJob, OpenStandardMiningChannel, OpenStandardMiningChannelError,
     OpenStandardMiningChannelSuccess, SetNewPrevHash, SetTarget, SetupConnection,
     SetupConnectionError, SetupConnectionSuccess, SubmitSharesError, SubmitSharesStandard,
-    SubmitSharesSuccess,
+    SubmitSharesSuccess, SETUP_CONNECTION_REQUIRES_VERSION_ROLLING,
+    SETUP_CONNECTION_SUCCESS_REQUIRES_FIXED_VERSION,
 };
 use ii_stratum::v2::types::*;
 use ii_stratum::v2::{
@@ -68,9 +73,6 @@ use ii_stratum::v2::{build_message_from_frame, extensions, Handler};
 
 use std::collections::HashMap;
 
-// TODO: move it to the stratum crate
-const VERSION_MASK: u32 = 0x1fffe000;
-
 #[derive(Debug, Clone)]
 pub struct ConnectionDetails {
     /// TODO temporary field that denotes the protocol, it will be replaced by a `Connector`
@@ -79,6 +81,11 @@ pub struct ConnectionDetails {
     pub user: String,
     pub host: String,
     pub port: u16,
+    pub retry_schedule: ClientRetrySchedule,
+    /// Number of standard channels opened on the connection
+    pub channel_count: usize,
+    /// All endpoints of the pool with their health
+    pub endpoints: Arc<endpoint::Selector>,
 }
 
 impl ConnectionDetails {
@@ -88,6 +95,9 @@ impl ConnectionDetails {
             user: descriptor.user.clone(),
             host: descriptor.host.clone(),
             port: descriptor.port(),
+            retry_schedule: descriptor.retry_schedule,
+            channel_count: descriptor.channel_count,
+            endpoints: Arc::new(endpoint::Selector::from_descriptor(descriptor)),
         }
     }
 
@@ -107,6 +117,8 @@ pub struct StratumJob {
     time: u32,
     bits: u32,
     target: ii_bitcoin::Target,
+    /// Version bits that the upstream allowed to be rolled
+    version_mask: u32,
 }
 
 impl StratumJob {
@@ -128,6 +140,7 @@ impl StratumJob {
             time: prevhash_msg.min_ntime,
             bits: prevhash_msg.nbits,
             target,
+            version_mask: client.granted_version_mask(),
         }
     }
 }
@@ -142,7 +155,7 @@ impl job::Bitcoin for StratumJob {
     }
 
     fn version_mask(&self) -> u32 {
-        VERSION_MASK
+        self.version_mask
     }
 
     fn previous_hash(&self) -> &ii_bitcoin::DHash {
@@ -174,125 +187,207 @@ impl job::Bitcoin for StratumJob {
     }
 }
 
-/// Queue that contains pairs of solution and its assigned sequence number. It is our responsibility
-/// to keep the sequence number monotonic so that we as a stratum V2 client can easily process bulk
-/// acknowledgements. The sequence number type has been selected as u32 to match
-/// up with the protocol.
-type SolutionQueue = Mutex<VecDeque<(work::Solution, u32)>>;
-
-/// Helper task for `StratumClient` that implements Stratum V2 visitor which processes incoming
-/// messages from remote server.
-struct StratumEventHandler {
-    client: Arc<StratumClient>,
+/// Mining state of one standard channel opened on the connection
+struct Channel {
     all_jobs: HashMap<u32, NewMiningJob>,
     current_prevhash_msg: Option<SetNewPrevHash>,
+    /// Job referenced by the current prevhash which hasn't been received yet
+    missing_job_id: Option<u32>,
     /// Mining target for the next job that is to be solved
     current_target: ii_bitcoin::Target,
+    /// Job that is currently being solved in this channel
+    current_job: Option<Arc<StratumJob>>,
 }
 
-impl StratumEventHandler {
-    pub fn new(client: Arc<StratumClient>, current_target: ii_bitcoin::Target) -> Self {
+impl Channel {
+    fn new(current_target: ii_bitcoin::Target) -> Self {
         Self {
-            client,
             all_jobs: Default::default(),
             current_prevhash_msg: None,
+            missing_job_id: None,
             current_target,
+            current_job: None,
+        }
+    }
+}
+
+/// Helper task for `StratumClient` that implements Stratum V2 visitor which processes incoming
+/// messages from remote server.
+struct StratumEventHandler {
+    client: Arc<StratumClient>,
+    /// All opened channels indexed by channel ID
+    channels: BTreeMap<u32, Channel>,
+}
+
+impl StratumEventHandler {
+    /// `channels` - pairs of channel ID and initial target of all opened channels
+    pub fn new(client: Arc<StratumClient>, channels: Vec<(u32, ii_bitcoin::Target)>) -> Self {
+        Self {
+            client,
+            channels: channels
+                .into_iter()
+                .map(|(channel_id, target)| (channel_id, Channel::new(target)))
+                .collect(),
+        }
+    }
+
+    fn get_channel(&mut self, channel_id: u32) -> Option<&mut Channel> {
+        let channel = self.channels.get_mut(&channel_id);
+        if channel.is_none() {
+            warn!(
+                "Stratum: ignoring message for unknown channel {}",
+                channel_id
+            );
         }
+        channel
     }
 
     /// Convert new mining job message into StratumJob and send it down the line for solving.
+    /// Jobs of all channels are solved in parallel.
     ///
     /// * `job_msg` - job message used as a base for the StratumJob
     async fn update_job(&mut self, job_msg: &NewMiningJob) {
+        let client = self.client.clone();
+        let channel = match self.get_channel(job_msg.channel_id) {
+            Some(channel) => channel,
+            None => return,
+        };
         let job = Arc::new(StratumJob::new(
-            self.client.clone(),
+            client,
             job_msg,
-            self.current_prevhash_msg
+            channel
+                .current_prevhash_msg
                 .as_ref()
                 .expect("TODO: no prevhash"),
-            self.current_target,
+            channel.current_target,
         ));
+        channel.current_job.replace(job.clone());
         self.client.update_last_job(job.clone()).await;
-        self.client.job_sender.lock().await.send(job);
+
+        if self.channels.len() == 1 {
+            self.client.job_sender.lock().await.send(job);
+            return;
+        }
+        // Jobs of other channels which haven't received the new prevhash yet would produce only
+        // stale shares
+        for channel in self.channels.values_mut() {
+            if let Some(current_job) = &channel.current_job {
+                if current_job.prev_hash != job.prev_hash {
+                    channel.current_job = None;
+                }
+            }
+        }
+        let jobs = self
+            .channels
+            .values()
+            .filter_map(|channel| channel.current_job.clone())
+            .map(|job| job as Arc<dyn job::Bitcoin>)
+            .collect();
+        self.client.job_sender.lock().await.send_all(jobs);
+    }
+
+    /// Stop solving the current job of `channel_id` when it has been invalidated and there is
+    /// no replacement for it. Jobs of other channels are still solved.
+    async fn invalidate_job(&mut self, channel_id: u32) {
+        if let Some(channel) = self.channels.get_mut(&channel_id) {
+            channel.current_job = None;
+        }
+        let jobs: Vec<_> = self
+            .channels
+            .values()
+            .filter_map(|channel| channel.current_job.clone())
+            .map(|job| job as Arc<dyn job::Bitcoin>)
+            .collect();
+        let job_sender = self.client.job_sender.lock().await;
+        if jobs.is_empty() {
+            job_sender.invalidate();
+        } else {
+            job_sender.send_all(jobs);
+        }
     }
 
-    fn update_target(&mut self, value: Uint256Bytes) {
+    fn update_target(&mut self, channel_id: u32, value: Uint256Bytes) {
         let new_target: ii_bitcoin::Target = value.into();
-        info!(
-            "Stratum: changing target to {} diff={}",
-            new_target,
-            new_target.get_difficulty()
-        );
-        self.current_target = new_target;
+        if let Some(channel) = self.get_channel(channel_id) {
+            info!(
+                "Stratum: changing target of channel {} to {} diff={}",
+                channel_id,
+                new_target,
+                new_target.get_difficulty()
+            );
+            channel.current_target = new_target;
+        }
     }
 
     async fn process_accepted_shares(&self, success_msg: &SubmitSharesSuccess) {
         let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+        let shares = self
+            .client
+            .submit_queue
+            .acknowledge(success_msg.channel_id, success_msg.last_seq_num);
+        let found = shares.last().map(|share| share.seq_num) == Some(success_msg.last_seq_num);
+        for share in shares {
             info!(
                 "Stratum: accepted solution #{} with nonce={:08x}",
-                seq_num,
-                solution.nonce()
+                share.seq_num,
+                share.solution.nonce()
+            );
+            stats::account_accepted_solution(&self.client.client_stats, &share.solution, now).await;
+        }
+        if !found {
+            warn!(
+                "Stratum: last accepted solution #{} hasn't been found!",
+                success_msg.last_seq_num
             );
-            self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
-            if success_msg.last_seq_num == seq_num {
-                // all accepted solutions have been found
-                return;
-            }
         }
-        warn!(
-            "Stratum: last accepted solution #{} hasn't been found!",
-            success_msg.last_seq_num
-        );
     }
 
     async fn process_rejected_shares(&self, error_msg: &SubmitSharesError) {
         let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
-            if error_msg.seq_num == seq_num {
-                info!(
-                    "Stratum: rejected solution #{} with nonce={:08x}!",
-                    seq_num,
-                    solution.nonce()
-                );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
-                // the rejected solution has been found
-                return;
-            } else {
-                // TODO: this is currently not according to stratum V2 specification
-                // preceding solutions are treated as accepted
+        let (accepted_shares, rejected_share) = self
+            .client
+            .submit_queue
+            .reject(error_msg.channel_id, error_msg.seq_num);
+        for share in accepted_shares {
+            // TODO: this is currently not according to stratum V2 specification
+            // preceding solutions are treated as accepted
+            info!(
+                "Stratum: accepted solution #{} with nonce={}",
+                share.seq_num,
+                share.solution.nonce()
+            );
+            stats::account_accepted_solution(&self.client.client_stats, &share.solution, now).await;
+            warn!(
+                "Stratum: the solution #{} precedes rejected solution #{}!",
+                share.seq_num, error_msg.seq_num
+            );
+            warn!(
+                "Stratum: the solution #{} is treated as an accepted one",
+                share.seq_num
+            );
+        }
+        match rejected_share {
+            Some(share) => {
+                let code = error_msg.code.to_string();
                 info!(
-                    "Stratum: accepted solution #{} with nonce={}",
-                    seq_num,
-                    solution.nonce()
-                );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
-                warn!(
-                    "Stratum: the solution #{} precedes rejected solution #{}!",
-                    seq_num, error_msg.seq_num
-                );
-                warn!(
-                    "Stratum: the solution #{} is treated as an accepted one",
-                    seq_num
+                    "Stratum: rejected solution #{} with nonce={:08x} ({})!",
+                    share.seq_num,
+                    share.solution.nonce(),
+                    code
                 );
+                stats::account_rejected_solution(
+                    &self.client.client_stats,
+                    &share.solution,
+                    now,
+                    stats::RejectReason::classify(&code),
+                )
+                .await;
             }
+            None => warn!(
+                "Stratum: rejected solution #{} hasn't been found!",
+                error_msg.seq_num
+            ),
         }
-        warn!(
-            "Stratum: rejected solution #{} hasn't been found!",
-            error_msg.seq_num
-        );
     }
 }
 
@@ -306,10 +401,26 @@ impl Handler for StratumEventHandler {
     //      - replace it
     //      - start mining the job it references (by job id)
     //      - flush all other jobs
+    //  - when prevhash message references a job that hasn't been received (misbehaving pool)
+    //      - stop mining the current job of the channel as it would produce only stale shares
+    //      - start mining the referenced job as soon as it comes (even with future_job flag)
 
     async fn visit_new_mining_job(&mut self, _header: &Header, job_msg: &NewMiningJob) {
+        let channel = match self.get_channel(job_msg.channel_id) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let mut job_msg = job_msg.clone();
+        if channel.missing_job_id == Some(job_msg.job_id) {
+            info!(
+                "Stratum: received missing job {} referenced by prevhash of channel {}",
+                job_msg.job_id, job_msg.channel_id
+            );
+            channel.missing_job_id = None;
+            job_msg.future_job = false;
+        }
         // all jobs since last `prevmsg` have to be stored in job table
-        self.all_jobs.insert(job_msg.job_id, job_msg.clone());
+        channel.all_jobs.insert(job_msg.job_id, job_msg.clone());
         // TODO: close connection when maximal capacity of `all_jobs` has been reached
 
         // When not marked as future job, we can start mining on it right away
@@ -319,26 +430,44 @@ impl Handler for StratumEventHandler {
         //  send the new prevhash ahead of this job. This scenario is still yet to be investigated
         //  as it should prevented typically on the V2->V1->upstream translation proxies. These
         //  proxies should guarantee that no such case like a job without a prevhash would exist.
-        if !job_msg.future_job && self.current_prevhash_msg.is_some() {
-            self.update_job(job_msg).await;
+        if !job_msg.future_job && channel.current_prevhash_msg.is_some() {
+            self.update_job(&job_msg).await;
         }
     }
 
     async fn visit_set_new_prev_hash(&mut self, _header: &Header, prevhash_msg: &SetNewPrevHash) {
-        self.current_prevhash_msg.replace(prevhash_msg.clone());
+        let channel = match self.get_channel(prevhash_msg.channel_id) {
+            Some(channel) => channel,
+            None => return,
+        };
+        channel.current_prevhash_msg.replace(prevhash_msg.clone());
 
         // find the future job with ID referenced in prevhash_msg
-        let (_, mut future_job_msg) = self
-            .all_jobs
-            .remove_entry(&prevhash_msg.job_id)
-            .expect("TODO: requested job ID not found");
-
+        let future_job_msg = channel.all_jobs.remove(&prevhash_msg.job_id);
         // remove all other jobs (they are now invalid)
-        self.all_jobs.retain(|_, _| true);
+        channel.all_jobs.clear();
+
+        let mut future_job_msg = match future_job_msg {
+            Some(future_job_msg) => {
+                channel.missing_job_id = None;
+                future_job_msg
+            }
+            None => {
+                warn!(
+                    "Stratum: prevhash of channel {} references unknown job {}, waiting for it",
+                    prevhash_msg.channel_id, prevhash_msg.job_id
+                );
+                channel.missing_job_id = Some(prevhash_msg.job_id);
+                self.client.client_stats.invalid_jobs.inc();
+                self.invalidate_job(prevhash_msg.channel_id).await;
+                return;
+            }
+        };
         // turn the job into an immediate job
         future_job_msg.future_job = false;
         // reinsert the job
-        self.all_jobs
+        channel
+            .all_jobs
             .insert(future_job_msg.job_id, future_job_msg.clone());
 
         // and start immediately solving it
@@ -346,7 +475,7 @@ impl Handler for StratumEventHandler {
     }
 
     async fn visit_set_target(&mut self, _header: &Header, target_msg: &SetTarget) {
-        self.update_target(target_msg.max_target);
+        self.update_target(target_msg.channel_id, target_msg.max_target);
     }
 
     async fn visit_submit_shares_success(
@@ -400,10 +529,11 @@ impl<T> FrameStream for T where
 {
 }
 
+/// Sends shares from the submission queue to the server. It runs independently of the reception
+/// of solutions so that short network stalls don't block the solution path.
 struct StratumSolutionHandler<S> {
     client: Arc<StratumClient>,
     connection_tx: Arc<Mutex<S>>,
-    seq_num: u32,
 }
 
 impl<S, E> StratumSolutionHandler<S>
@@ -419,30 +549,20 @@ where
         Self {
             client,
             connection_tx,
-            seq_num: 0,
         }
     }
 
-    async fn process_solution(&mut self, solution: work::Solution) -> error::Result<()> {
-        let job: &StratumJob = solution.job();
-
-        let seq_num = self.seq_num;
-        self.seq_num = self.seq_num.wrapping_add(1);
+    async fn submit_share(&self, share: submit::Share) -> error::Result<()> {
+        let job: &StratumJob = share.solution.job();
 
         let share_msg = SubmitSharesStandard {
-            channel_id: job.channel_id,
-            seq_num,
+            channel_id: share.channel_id,
+            seq_num: share.seq_num,
             job_id: job.id,
-            nonce: solution.nonce(),
-            ntime: solution.time(),
-            version: solution.version(),
+            nonce: share.solution.nonce(),
+            ntime: share.solution.time(),
+            version: share.solution.version(),
         };
-        // store solution with sequence number for future server acknowledge
-        self.client
-            .solutions
-            .lock()
-            .await
-            .push_back((solution, seq_num));
         // send solutions back to the stratum server
         StratumClient::send_msg(&self.connection_tx, share_msg)
             .await
@@ -450,11 +570,23 @@ where
         // the response is handled in a separate task
         Ok(())
     }
+
+    /// Submit shares as long as the connection works. Shares which have been sent stay in the
+    /// submission queue until they are acknowledged so nothing is lost when it fails.
+    async fn run(self) -> error::Result<()> {
+        loop {
+            let share = self.client.submit_queue.next().await;
+            self.submit_share(share).await?;
+        }
+    }
 }
 
 struct StratumConnectionHandler {
     client: Arc<StratumClient>,
-    init_target: ii_bitcoin::Target,
+    /// Request ID of the channel which is being opened
+    req_id: u32,
+    /// Pairs of channel ID and initial target of all opened channels
+    channels: Vec<(u32, ii_bitcoin::Target)>,
     status: Option<error::Result<()>>,
 }
 
@@ -462,7 +594,8 @@ impl StratumConnectionHandler {
     pub fn new(client: Arc<StratumClient>) -> Self {
         Self {
             client,
-            init_target: Default::default(),
+            req_id: 0,
+            channels: vec![],
             status: None,
         }
     }
@@ -477,11 +610,17 @@ impl StratumConnectionHandler {
         S: FrameSink,
     {
         let connection_details = self.client.connection_details();
+        // Ask for version rolling only when the backend actually needs it
+        let flags = if self.client.version_mask != 0 {
+            SETUP_CONNECTION_REQUIRES_VERSION_ROLLING
+        } else {
+            0
+        };
         let setup_msg = SetupConnection {
             protocol: 0,
             max_version: 2,
             min_version: 2,
-            flags: 0,
+            flags,
             endpoint_host: Str0_255::from_string(connection_details.host.clone()),
             endpoint_port: connection_details.port,
             device: self.client.backend_info.clone().unwrap_or_default().into(),
@@ -504,6 +643,7 @@ impl StratumConnectionHandler {
 
     async fn open_channel<R, S>(
         &mut self,
+        req_id: u32,
         connection_rx: &mut R,
         connection_tx: Arc<Mutex<S>>,
     ) -> error::Result<()>
@@ -511,8 +651,9 @@ impl StratumConnectionHandler {
         R: FrameStream,
         S: FrameSink,
     {
+        self.req_id = req_id;
         let channel_msg = OpenStandardMiningChannel {
-            req_id: 10, // TODO? come up with request ID sequencing
+            req_id,
             user: self
                 .client
                 .connection_details()
@@ -543,10 +684,21 @@ impl StratumConnectionHandler {
 
     async fn connect(&self) -> error::Result<v2::Framed> {
         let connection_details = self.client.connection_details();
-        let addr = ii_wire::Address::from_str(connection_details.get_host_and_port().as_str())?;
+        let endpoint = connection_details.endpoints.select().await;
+        let addr = ii_wire::Address::from_str(endpoint.as_str())?;
         let mut client = ii_wire::Client::new(addr);
+        // Every resolved address of the host gets its own time slot
+        client.set_attempt_timeout(StratumClient::CONNECTION_TIMEOUT);
         // Attempt only once to connect (as the stratum client is being managed externally)
-        let connection = client.next().await?;
+        let start = time::Instant::now();
+        let connection = client.next().await;
+        match &connection {
+            Ok(_) => connection_details
+                .endpoints
+                .account_success(&endpoint, start.elapsed()),
+            Err(_) => connection_details.endpoints.account_failure(&endpoint),
+        }
+        let connection = connection?;
 
         // TODO this will be replaced by a 'connector' that will be set when building stratum
         // client instance
@@ -556,7 +708,11 @@ impl StratumConnectionHandler {
                 let noise_initiator =
                     v2::noise::Initiator::new(upstream_authority_public_key.into_inner());
                 // Successful noise initiator handshake results in a stream/sink for V2 frames
-                noise_initiator.connect(connection).await?
+                noise_initiator
+                    .connect(connection)
+                    .timeout(StratumClient::CONNECTION_TIMEOUT)
+                    .await
+                    .map_err(|_| error::Error::from("Noise handshake timeout"))??
             }
             // V2 insecure connector
             ClientProtocol::StratumV2Insecure => {
@@ -569,12 +725,13 @@ impl StratumConnectionHandler {
         Ok(client_framed_stream)
     }
 
-    /// Starts mining session and provides the initial target negotiated by the upstream endpoint
+    /// Starts mining session and provides channel IDs with initial targets negotiated by the
+    /// upstream endpoint for all opened channels
     async fn init_mining_session<R, S>(
         mut self,
         connection_rx: &mut R,
         connection_tx: Arc<Mutex<S>>,
-    ) -> error::Result<ii_bitcoin::Target>
+    ) -> error::Result<Vec<(u32, ii_bitcoin::Target)>>
     where
         R: FrameStream,
         S: FrameSink,
@@ -582,11 +739,14 @@ impl StratumConnectionHandler {
         self.setup_mining_connection(connection_rx, connection_tx.clone())
             .await
             .context("Cannot setup stratum mining connection")?;
-        self.open_channel(connection_rx, connection_tx)
-            .await
-            .context("Cannot open stratum channel")?;
+        let channel_count = self.client.connection_details().channel_count;
+        for req_id in 0..channel_count as u32 {
+            self.open_channel(req_id, connection_rx, connection_tx.clone())
+                .await
+                .context("Cannot open stratum channel")?;
+        }
 
-        Ok(self.init_target)
+        Ok(self.channels)
     }
 }
 
@@ -595,9 +755,18 @@ impl Handler for StratumConnectionHandler {
     async fn visit_setup_connection_success(
         &mut self,
         _header: &Header,
-        _success_msg: &SetupConnectionSuccess,
+        success_msg: &SetupConnectionSuccess,
     ) {
-        self.status = Ok(()).into();
+        // Standard channels have no means to negotiate the mask bit by bit so the upstream
+        // either allows the whole BIP320 range or it requires the version to be fixed
+        let granted_version_mask =
+            if success_msg.flags & SETUP_CONNECTION_SUCCESS_REQUIRES_FIXED_VERSION != 0 {
+                0
+            } else {
+                ii_stratum::BIP320_N_VERSION_MASK
+            };
+        self.client.set_granted_version_mask(granted_version_mask);
+        self.status = self.client.check_version_mask().into();
     }
 
     async fn visit_setup_connection_error(
@@ -614,7 +783,17 @@ impl Handler for StratumConnectionHandler {
         _header: &Header,
         success_msg: &OpenStandardMiningChannelSuccess,
     ) {
-        self.init_target = success_msg.target.into();
+        if success_msg.req_id != self.req_id {
+            self.status = Err(format!(
+                "Open channel response with unexpected request ID {} (expected {})",
+                success_msg.req_id, self.req_id
+            )
+            .into())
+            .into();
+            return;
+        }
+        self.channels
+            .push((success_msg.channel_id, success_msg.target.into()));
         self.status = Ok(()).into();
     }
 
@@ -628,31 +807,16 @@ impl Handler for StratumConnectionHandler {
     }
 }
 
-/// Messages to control the extension channel
-#[derive(Debug)]
-pub enum ExtensionChannelMsg {
-    /// Starts the extension channel
-    Start,
-    /// Stops the extension channel
-    Stop,
-    /// Frame being forwarded into the extension channel
-    Frame(<Framing as ii_wire::Framing>::Tx),
-}
-
-/// Receiver for Stratum <-- Remote direction (stratum client end)
-pub type ExtensionChannelToStratumReceiver = mpsc::Receiver<<Framing as ii_wire::Framing>::Rx>;
-/// Remote sender for Stratum <-- Remote direction (remote end)
-pub type ExtensionChannelToStratumSender = mpsc::Sender<<Framing as ii_wire::Framing>::Tx>;
-
-/// Receiver for Stratum --> Remote direction (remote end)
-pub type ExtensionChannelFromStratumReceiver = mpsc::Receiver<ExtensionChannelMsg>;
-/// Sender for Stratum --> Remote direction (stratum client end)
-pub type ExtensionChannelFromStratumSender = mpsc::Sender<ExtensionChannelMsg>;
-
 #[derive(Debug, ClientNode)]
 pub struct StratumClient {
     connection_details: Arc<StdMutex<ConnectionDetails>>,
     backend_info: Option<hal::BackendInfo>,
+    /// Version bits the backend needs to roll to generate its work
+    version_mask: u32,
+    /// Minimal number of midstates in work the backend is able to solve
+    min_midstate_count: usize,
+    /// Version bits the upstream allowed to roll during connection setup
+    granted_version_mask: AtomicU32,
     #[member_status]
     status: sync::StatusMonitor,
     #[member_client_stats]
@@ -662,96 +826,75 @@ pub struct StratumClient {
     // Last job has to be weak reference to prevent circular reference (the `StratumJob` keeps
     // reference to `StratumClient`)
     last_job: Mutex<Option<Arc<StratumJob>>>,
-    solutions: SolutionQueue,
+    /// Shares waiting for submission or for acknowledgement from the server
+    submit_queue: submit::Queue,
     job_sender: Mutex<job::Sender>,
     solution_receiver: Mutex<job::SolutionReceiver>,
-    /// Frames received from this channel will be forwarded to the network connection
-    extension_channel_receiver: Mutex<ExtensionChannelToStratumReceiver>,
-    /// Frames intended for the specified extension will be forwarded into this channel (wrapped
-    /// into ExtensionChannelMsg
-    extension_channel_sender: Mutex<ExtensionChannelFromStratumSender>,
+    /// Protocol extensions sharing the connection with the mining protocol
+    extensions: Arc<extension::Multiplexer>,
+    /// Directory where frames received on each connection are recorded
+    record_dir: Option<PathBuf>,
 }
 
 impl StratumClient {
     const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    /// Upper bound for the whole connection including all resolved addresses and the handshake
+    const CONNECT_TOTAL_TIMEOUT: time::Duration = time::Duration::from_secs(20);
     const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(150);
     const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
-
-    /// Start a task that plays a dummy role for both communication channels that the stratum
-    /// client uses to talk to stratum extension.
-    fn start_dummy_extension_task(
-        connection_details: ConnectionDetails,
-    ) -> (
-        ExtensionChannelToStratumReceiver,
-        ExtensionChannelFromStratumSender,
-    ) {
-        // Dummy channel for Stratum client --> extension communication
-        let (sender_from_client, mut receiver_from_client) = mpsc::channel(1);
-        // Dummy channel for Stratum client <-- extension communication
-        let (sender_to_client, receiver_to_client) = mpsc::channel(1);
-
-        tokio::spawn(async move {
-            info!(
-                "Stratum extension: starting dummy task[{:?}]... ",
-                connection_details
-            );
-            // Make sure the sender is moved inside the dummy task to prevent it from being
-            // dropped. Otherwise the receiver_to_client would immediately indicate end of stream
-            let _sender_to_client = sender_to_client;
-            //
-            while let Some(message) = receiver_from_client.next().await {
-                info!(
-                    "Stratum extension: dummy task[{:?}] received: {:?},",
-                    connection_details, message
-                );
-            }
-            info!(
-                "Stratum extension: dummy task[{:?}] terminated",
-                connection_details
-            );
-        });
-        (receiver_to_client, sender_from_client)
-    }
-
+    /// Maximal number of submitted shares which haven't been acknowledged by the server yet
+    const SUBMIT_WINDOW: usize = 64;
+    /// Maximal number of solutions waiting for submission when the submission window is full
+    const SUBMIT_QUEUE_CAPACITY: usize = 1024;
+
+    /// `extensions` - multiplexer with registered protocol extensions, frames of any extension
+    /// are dropped when it is missing
+    /// `record_dir` - directory where received frames are recorded for debugging (see `record`)
     pub fn new(
         connection_details: ConnectionDetails,
         backend_info: Option<hal::BackendInfo>,
+        version_mask: u32,
+        min_midstate_count: usize,
         solver: job::Solver,
-        channel: Option<(
-            ExtensionChannelToStratumReceiver,
-            ExtensionChannelFromStratumSender,
-        )>,
+        extensions: Option<Arc<extension::Multiplexer>>,
+        record_dir: Option<PathBuf>,
     ) -> Self {
         let (stop_sender, stop_receiver) = mpsc::channel(1);
 
-        // Extract the both channel endpoints that connect the client with the stratum extension
-        // or populate it with dummy endpoints. That way we can handle the endpoints uniformly
-        // regardless whether they are configured or not (see `main_loop()`)
-        // that would handle all events regards
-        let (extension_channel_receiver, extension_channel_sender) = channel.unwrap_or_else(|| {
-            info!(
-                "V2: starting dummy task for client: {:?}",
-                connection_details
-            );
-            Self::start_dummy_extension_task(connection_details.clone())
-        });
-
         Self {
             connection_details: Arc::new(StdMutex::new(connection_details)),
             backend_info,
+            version_mask,
+            min_midstate_count,
+            granted_version_mask: AtomicU32::new(0),
             status: Default::default(),
             client_stats: Default::default(),
             stop_sender: stop_sender,
             stop_receiver: Mutex::new(stop_receiver),
             last_job: Mutex::new(None),
-            solutions: Mutex::new(VecDeque::new()),
+            submit_queue: submit::Queue::new(Self::SUBMIT_WINDOW, Self::SUBMIT_QUEUE_CAPACITY),
             job_sender: Mutex::new(solver.job_sender),
             solution_receiver: Mutex::new(solver.solution_receiver),
-            extension_channel_receiver: Mutex::new(extension_channel_receiver),
-            extension_channel_sender: Mutex::new(extension_channel_sender),
+            extensions: extensions.unwrap_or_default(),
+            record_dir,
         }
     }
 
+    /// Start recording of frames received on a new connection to `host_and_port` when it is
+    /// enabled
+    fn create_recorder(&self, host_and_port: &str) -> Option<record::Recorder> {
+        let record_dir = self.record_dir.as_ref()?;
+        record::Recorder::create(record_dir, host_and_port)
+            .map_err(|e| {
+                error!(
+                    "Stratum: cannot record frames to '{}': {}",
+                    record_dir.display(),
+                    e
+                )
+            })
+            .ok()
+    }
+
     fn connection_details(&self) -> ConnectionDetails {
         self.connection_details
             .lock()
@@ -759,10 +902,80 @@ impl StratumClient {
             .clone()
     }
 
+    #[inline]
+    fn granted_version_mask(&self) -> u32 {
+        self.granted_version_mask.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn set_granted_version_mask(&self, mask: u32) {
+        self.granted_version_mask.store(mask, Ordering::Relaxed);
+    }
+
+    /// Verify the version mask granted by the upstream. Work for jobs which don't allow rolling
+    /// all version bits the backend needs is generated with fewer midstates, but it is an error
+    /// when the backend cannot solve such work at all (no work would be generated for the pool).
+    fn check_version_mask(&self) -> error::Result<()> {
+        let granted_version_mask = self.granted_version_mask();
+        if self.version_mask & !granted_version_mask == 0 {
+            return Ok(());
+        }
+        let midstate_count =
+            1usize << (granted_version_mask & ii_bitcoin::BIP320_VERSION_MASK).count_ones();
+        if midstate_count < self.min_midstate_count {
+            Err(format!(
+                "No work can be generated: version mask {:#010x} granted by upstream allows only \
+                 {} midstate(s), backend requires {}",
+                granted_version_mask, midstate_count, self.min_midstate_count
+            ))?;
+        }
+        warn!(
+            "Stratum: insufficient version mask {:#010x} granted by upstream (required {:#010x}), \
+             work is generated with at most {} midstate(s)",
+            granted_version_mask, self.version_mask, midstate_count
+        );
+        Ok(())
+    }
+
     async fn update_last_job(&self, job: Arc<StratumJob>) {
         self.last_job.lock().await.replace(job);
     }
 
+    /// Queue `solution` for submission in the channel of its job. The oldest pending solution
+    /// is accounted as stale when the queue is full.
+    async fn queue_solution(&self, solution: work::Solution) {
+        let job: &StratumJob = solution.job();
+        if let Some(dropped) = self.submit_queue.push(job.channel_id, solution) {
+            warn!(
+                "Stratum: submission queue is full, solution with nonce={:08x} discarded as stale",
+                dropped.nonce()
+            );
+            self.client_stats
+                .stale
+                .account_solution(&dropped.job_target(), time::Instant::now())
+                .await;
+        }
+    }
+
+    /// Drop all shares of the previous session from the submission queue and account them as
+    /// stale. They cannot be submitted in a new session because its channels and jobs differ.
+    async fn reset_submit_queue(&self) {
+        let now = time::Instant::now();
+        let discarded = self.submit_queue.reset();
+        if !discarded.is_empty() {
+            info!(
+                "Stratum: {} unacknowledged solutions discarded as stale",
+                discarded.len()
+            );
+        }
+        for solution in discarded {
+            self.client_stats
+                .stale
+                .account_solution(&solution.job_target(), now)
+                .await;
+        }
+    }
+
     /// Send a message down a specified Tx Sink
     /// TODO: temporarily, this became an associated method so that we don't have to generalize
     ///  with type parameters the full StratumClient struct. Once this is done, we will use the
@@ -807,19 +1020,7 @@ impl StratumClient {
                     "Received protocol extension frame: {:x?} passing down",
                     frame
                 );
-                // Intentionally capture a potential error as an issue with extension channel
-                // must not cause the client to fail completely
-                if let Err(e) = self
-                    .extension_channel_sender
-                    .lock()
-                    .await
-                    .try_send(ExtensionChannelMsg::Frame(frame))
-                {
-                    info!(
-                        "Cannot pass extension frame, extension channel not available: {:?}",
-                        e
-                    );
-                }
+                self.extensions.dispatch(frame);
             }
         }
         Ok(())
@@ -836,41 +1037,32 @@ impl StratumClient {
         S: FrameSink,
     {
         let mut solution_receiver = self.solution_receiver.lock().await;
-        let mut extension_channel_rx = self.extension_channel_receiver.lock().await;
-        let mut solution_handler = StratumSolutionHandler::new(self.clone(), connection_tx.clone());
+        let solution_handler = StratumSolutionHandler::new(self.clone(), connection_tx.clone())
+            .run()
+            .fuse();
+        futures::pin_mut!(solution_handler);
 
-        // Notify the extension user that we are ready to start forwarding its protocol, use a
-        // separate block, so that the lock is dropped immediately after the start notification
-        // is sent
-        {
-            self.extension_channel_sender
-                .lock()
-                .await
-                .try_send(ExtensionChannelMsg::Start)
-                .map_err(|e| {
-                    info!("Stratum extension channel start error: {:?}", e);
-                })
-                .expect("BUG: stratum extension channel not available for start");
-        }
+        // Notify the extensions that we are ready to start forwarding their protocols
+        self.extensions.start();
         while !self.status.is_shutting_down() {
             select! {
-                frame = connection_rx.next().timeout(Self::EVENT_TIMEOUT).fuse() => {
+                frame = connection_rx.next().fuse() => {
                     match frame {
-                        Ok(Some(frame)) => self.handle_frame(frame?, &mut event_handler).await?,
-                        Ok(None) | Err(_) => {
+                        Some(frame) => self.handle_frame(frame?, &mut event_handler).await?,
+                        None => {
                             Err("The remote stratum server was disconnected prematurely")?;
                         }
                     }
                 }
                 // Forward extension protocol frames onto the network
-                frame = extension_channel_rx.next().fuse() => {
-                    connection_tx.lock().await
-                        .send(frame.expect("BUG: extension channel must not shutdown!"))
-                        .await?;
+                frame = self.extensions.next_frame().fuse() => {
+                    connection_tx.lock().await.send(frame).await?;
                 }
+                // Submission of shares fails only when the connection is broken
+                result = &mut solution_handler => result?,
                 solution = solution_receiver.receive().fuse() => {
                     match solution {
-                        Some(solution) => solution_handler.process_solution(solution).await?,
+                        Some(solution) => self.queue_solution(solution).await,
                         None => {
                             // TODO: initiate Destroying and remove error
                             Err("Standard application shutdown")?;
@@ -886,12 +1078,12 @@ impl StratumClient {
         self: Arc<Self>,
         connection_rx: R,
         connection_tx: Arc<Mutex<S>>,
-        init_target: ii_bitcoin::Target,
+        init_channels: Vec<(u32, ii_bitcoin::Target)>,
     ) where
         R: FrameStream,
         S: FrameSink,
     {
-        let event_handler = StratumEventHandler::new(self.clone(), init_target);
+        let event_handler = StratumEventHandler::new(self.clone(), init_channels);
         // TODO consider changing main_loop to accept Arc<Self> and build the solution_handler
         //  along with solution handler communication channels inside of the main_loop.
         let client = self.clone();
@@ -903,20 +1095,48 @@ impl StratumClient {
         }
     }
 
+    /// Wait before the next connection attempt when the previous ones failed so that a dead
+    /// pool doesn't make the client spin
+    async fn wait_for_retry(&self, connection_details: &ConnectionDetails) {
+        let retries = self.status.retries();
+        if retries > 0 {
+            let delay =
+                sync::backoff::Backoff::new(connection_details.retry_schedule).delay(retries);
+            info!(
+                "Stratum: retrying connection to {} (attempt #{}) in {:.1}s",
+                connection_details.get_host_and_port(),
+                retries,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
     async fn run(self: Arc<Self>) {
         let connection_handler = StratumConnectionHandler::new(self.clone());
         let connection_details = connection_handler.client.connection_details();
         let host_and_port = connection_details.get_host_and_port();
         let user = connection_details.user.clone();
 
+        self.wait_for_retry(&connection_details).await;
+
         match connection_handler
             .connect()
-            .timeout(Self::CONNECTION_TIMEOUT)
+            .timeout(Self::CONNECT_TOTAL_TIMEOUT)
             .await
             .map_err(|_| error::ErrorKind::General("Connection timeout".to_string()).into())
         {
             Ok(Ok(framed_connection)) => {
-                let (framed_sink, mut framed_stream) = framed_connection.split();
+                let (framed_sink, framed_stream) = framed_connection.split();
+                // Stratum V2 has no ping message so a pool which hasn't sent anything for too
+                // long is considered dead (e.g. silently dropped by NAT)
+                let framed_stream = ii_wire::IdleTimeout::new(framed_stream, Self::EVENT_TIMEOUT);
+                let mut recorder = self.create_recorder(&host_and_port);
+                let mut framed_stream =
+                    framed_stream.map(move |frame| match (recorder.as_mut(), frame) {
+                        (Some(recorder), Ok(frame)) => recorder.record(frame),
+                        (_, frame) => frame,
+                    });
                 let framed_sink = Arc::new(Mutex::new(framed_sink));
                 match connection_handler
                     .init_mining_session(&mut framed_stream, framed_sink.clone())
@@ -925,10 +1145,10 @@ impl StratumClient {
                     .map_err(|_| {
                         error::ErrorKind::General("Init mining session timeout".to_string()).into()
                     }) {
-                    Ok(Ok(init_target)) => {
+                    Ok(Ok(init_channels)) => {
                         if self.status.initiate_running() {
                             self.clone()
-                                .run_job_solver(framed_stream, framed_sink, init_target)
+                                .run_job_solver(framed_stream, framed_sink, init_channels)
                                 .await;
                         }
                     }
@@ -957,6 +1177,7 @@ impl StratumClient {
         // TODO: Count as a discarded solution?
         // Flush all obsolete solutions from previous run
         self.solution_receiver.lock().await.flush();
+        self.reset_submit_queue().await;
 
         loop {
             let mut stop_receiver = self.stop_receiver.lock().await;
@@ -965,29 +1186,15 @@ impl StratumClient {
                 _ = stop_receiver.next() => {}
             }
 
-            // Notify the other end that uses the extension channel that it should restart its
-            // operation
-            // TODO Note that this error is triggered also when there is not extension channel.
-            //  It needs to be reworked once we eliminate the need for a dummy extension channel
-            //  pair
-
-            if let Err(e) = self
-                .extension_channel_sender
-                .lock()
-                .await
-                .try_send(ExtensionChannelMsg::Stop)
-            {
-                info!(
-                    "Cannot send stop notification into the extension channel: {:?}",
-                    e
-                );
-            }
+            // Notify the extensions that they should restart their operation
+            self.extensions.stop();
             // Invalidate current job to stop working on it
             self.job_sender.lock().await.invalidate();
             // Flush all unprocessed solutions to empty buffer
             // TODO: Count as a discarded solution?
             self.solution_receiver.lock().await.flush();
-            self.solutions.lock().await.clear();
+            // Shares of the closed session cannot be submitted anymore
+            self.reset_submit_queue().await;
 
             if self.status.can_stop() {
                 // NOTE: it is not safe to add here any code!
@@ -1031,6 +1238,14 @@ impl node::Client for StratumClient {
             .expect("BUG: cannot lock connection details") =
             ConnectionDetails::from_descriptor(descriptor);
     }
+
+    fn pending_solutions(&self) -> usize {
+        self.submit_queue.pending_count() + self.submit_queue.in_flight_count()
+    }
+
+    fn endpoints(&self) -> Option<Arc<endpoint::Selector>> {
+        Some(self.connection_details().endpoints)
+    }
 }
 
 impl fmt::Display for StratumClient {
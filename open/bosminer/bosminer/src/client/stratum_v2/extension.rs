@@ -0,0 +1,308 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Multiplexer of Stratum V2 protocol extensions. Every extension registers itself for its
+//! `extension_type` and gets an `Endpoint` for receiving control messages and frames of the
+//! extension and for submitting frames upstream. The multiplexer lives as long as the Stratum
+//! client so the endpoints stay valid across reconnects, extensions are only notified by
+//! `Message::Start` and `Message::Stop` whenever a connection is established or lost. Endpoints
+//! don't keep the multiplexer alive so an extension learns that its client has been dropped when
+//! its receiver is closed.
+
+use crate::error;
+
+use futures::channel::mpsc;
+use ii_async_compat::prelude::*;
+use ii_logging::macros::*;
+use ii_stratum::v2::framing::{ExtType, Frame, Framing};
+use tokio::sync::Notify;
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+
+/// Messages to control the extension
+#[derive(Debug)]
+pub enum Message {
+    /// Connection to the server has been established and the extension may start its protocol
+    Start,
+    /// Connection has been lost and the extension has to restart its protocol on next `Start`
+    Stop,
+    /// Frame of the extension received from the server
+    Frame(Frame),
+}
+
+/// Sender of extension frames to the server
+#[derive(Debug, Clone)]
+pub struct Sender {
+    extension_type: ExtType,
+    multiplexer: Weak<Multiplexer>,
+}
+
+impl Sender {
+    /// Serialize `message` and queue it for sending. Frames submitted while there is no
+    /// connection are sent after the next connection has been established.
+    pub fn send<M>(&self, message: M) -> error::Result<()>
+    where
+        M: TryInto<<Framing as ii_wire::Framing>::Tx, Error = <Framing as ii_wire::Framing>::Error>,
+    {
+        self.send_frame(message.try_into()?)
+    }
+
+    /// Queue already serialized `frame` for sending
+    pub fn send_frame(&self, frame: Frame) -> error::Result<()> {
+        if frame.header.extension_type != self.extension_type {
+            Err(error::ErrorKind::Stratum(format!(
+                "frame of extension {:#06x} cannot be sent by extension {:#06x}",
+                frame.header.extension_type, self.extension_type
+            )))?;
+        }
+        self.multiplexer
+            .upgrade()
+            .ok_or_else(|| error::ErrorKind::Stratum("stratum client has been dropped".into()))?
+            .submit(frame);
+        Ok(())
+    }
+}
+
+/// Communication endpoints of one registered extension
+#[derive(Debug)]
+pub struct Endpoint {
+    pub receiver: mpsc::Receiver<Message>,
+    pub sender: Sender,
+}
+
+#[derive(Debug)]
+pub struct Multiplexer {
+    /// Registered extensions indexed by their extension type
+    handlers: StdMutex<HashMap<ExtType, mpsc::Sender<Message>>>,
+    /// Frames from extensions waiting for being sent to the server
+    outgoing: StdMutex<VecDeque<Frame>>,
+    /// Wakes up the Stratum client when there is a new outgoing frame
+    outgoing_notify: Notify,
+}
+
+impl Multiplexer {
+    /// Capacity of a channel with messages for one extension
+    const HANDLER_CAPACITY: usize = 16;
+    /// Maximal number of outgoing frames kept while there is no connection. The oldest frames
+    /// are dropped first.
+    const OUTGOING_CAPACITY: usize = 64;
+
+    pub fn new() -> Self {
+        Self {
+            handlers: StdMutex::new(HashMap::new()),
+            outgoing: StdMutex::new(VecDeque::new()),
+            outgoing_notify: Notify::new(),
+        }
+    }
+
+    /// Register handler for `extension_type`. Only one handler can be registered for each
+    /// extension.
+    pub fn register(self: &Arc<Self>, extension_type: ExtType) -> error::Result<Endpoint> {
+        let mut handlers = self
+            .handlers
+            .lock()
+            .expect("BUG: cannot lock extension handlers");
+        if handlers.contains_key(&extension_type) {
+            Err(error::ErrorKind::Stratum(format!(
+                "extension {:#06x} is already registered",
+                extension_type
+            )))?;
+        }
+        let (sender, receiver) = mpsc::channel(Self::HANDLER_CAPACITY);
+        handlers.insert(extension_type, sender);
+
+        Ok(Endpoint {
+            receiver,
+            sender: Sender {
+                extension_type,
+                multiplexer: Arc::downgrade(self),
+            },
+        })
+    }
+
+    /// Broadcast `message` to all registered extensions
+    fn broadcast(&self, message: impl Fn() -> Message) {
+        let mut handlers = self
+            .handlers
+            .lock()
+            .expect("BUG: cannot lock extension handlers");
+        for (extension_type, handler) in handlers.iter_mut() {
+            let message = message();
+            if let Err(e) = handler.try_send(message) {
+                info!(
+                    "Stratum extension {:#06x}: cannot pass control message ({})",
+                    extension_type, e
+                );
+            }
+        }
+    }
+
+    /// Notify all extensions about newly established connection
+    pub(super) fn start(&self) {
+        self.broadcast(|| Message::Start);
+    }
+
+    /// Notify all extensions about lost connection
+    pub(super) fn stop(&self) {
+        self.broadcast(|| Message::Stop);
+    }
+
+    /// Pass `frame` received from the server to the extension it belongs to
+    pub(super) fn dispatch(&self, frame: Frame) {
+        let extension_type = frame.header.extension_type;
+        let mut handlers = self
+            .handlers
+            .lock()
+            .expect("BUG: cannot lock extension handlers");
+        match handlers.get_mut(&extension_type) {
+            // Intentionally capture a potential error as an issue with extension must not
+            // cause the client to fail completely
+            Some(handler) => {
+                if let Err(e) = handler.try_send(Message::Frame(frame)) {
+                    info!(
+                        "Stratum extension {:#06x}: cannot pass frame ({})",
+                        extension_type, e
+                    );
+                }
+            }
+            None => info!(
+                "Stratum extension {:#06x}: not registered, dropping frame {:x?}",
+                extension_type, frame
+            ),
+        }
+    }
+
+    fn submit(&self, frame: Frame) {
+        {
+            let mut outgoing = self
+                .outgoing
+                .lock()
+                .expect("BUG: cannot lock outgoing frames");
+            if outgoing.len() >= Self::OUTGOING_CAPACITY {
+                let dropped = outgoing.pop_front().expect("BUG: empty outgoing frames");
+                warn!(
+                    "Stratum extension {:#06x}: too many outgoing frames, dropping the oldest one",
+                    dropped.header.extension_type
+                );
+            }
+            outgoing.push_back(frame);
+        }
+        self.outgoing_notify.notify_one();
+    }
+
+    /// Wait for the next frame which should be sent to the server
+    pub(super) async fn next_frame(&self) -> Frame {
+        loop {
+            if let Some(frame) = self
+                .outgoing
+                .lock()
+                .expect("BUG: cannot lock outgoing frames")
+                .pop_front()
+            {
+                return frame;
+            }
+            self.outgoing_notify.notified().await;
+        }
+    }
+}
+
+impl Default for Multiplexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ii_async_compat::{bytes, tokio};
+    use ii_stratum::v2::extensions;
+
+    fn frame(extension_type: ExtType, msg_type: u8) -> Frame {
+        Frame::from_serialized_payload(false, extension_type, msg_type, bytes::BytesMut::new())
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let multiplexer = Arc::new(Multiplexer::new());
+        let mut endpoint = multiplexer
+            .register(extensions::TELEMETRY)
+            .expect("BUG: cannot register extension");
+        assert!(multiplexer.register(extensions::TELEMETRY).is_err());
+
+        multiplexer.start();
+        // Frames of unknown extensions are dropped
+        multiplexer.dispatch(frame(0x8000, 1));
+        multiplexer.dispatch(frame(extensions::TELEMETRY, 2));
+        multiplexer.stop();
+
+        match endpoint.receiver.next().await {
+            Some(Message::Start) => {}
+            message => panic!("unexpected message {:?}", message),
+        }
+        match endpoint.receiver.next().await {
+            Some(Message::Frame(frame)) => assert_eq!(frame.header.msg_type, 2),
+            message => panic!("unexpected message {:?}", message),
+        }
+        match endpoint.receiver.next().await {
+            Some(Message::Stop) => {}
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_buffer() {
+        let multiplexer = Arc::new(Multiplexer::new());
+        let endpoint = multiplexer
+            .register(extensions::TELEMETRY)
+            .expect("BUG: cannot register extension");
+
+        // Extension cannot send frames of other extensions
+        assert!(endpoint
+            .sender
+            .send_frame(frame(extensions::BASE, 0))
+            .is_err());
+
+        // Frames are kept until they are taken by the client and the oldest ones are dropped
+        let count = Multiplexer::OUTGOING_CAPACITY + 2;
+        for msg_type in 0..count {
+            endpoint
+                .sender
+                .send_frame(frame(extensions::TELEMETRY, msg_type as u8))
+                .expect("BUG: cannot send frame");
+        }
+        for msg_type in 2..count {
+            assert_eq!(
+                multiplexer.next_frame().await.header.msg_type,
+                msg_type as u8
+            );
+        }
+        assert!(multiplexer
+            .next_frame()
+            .timeout(std::time::Duration::from_millis(10))
+            .await
+            .is_err());
+    }
+}
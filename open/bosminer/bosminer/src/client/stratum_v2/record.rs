@@ -0,0 +1,371 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Recording of frames received from Stratum V2 servers for troubleshooting of protocol
+//! handling. Frames are stored in the (unencrypted) wire format one after another so that a
+//! record can be read back with the standard codec and replayed against `StratumEventHandler`.
+
+use ii_logging::macros::*;
+
+use crate::error;
+
+use bytes::BytesMut;
+use ii_async_compat::{bytes, tokio_util};
+use ii_stratum::v2;
+use tokio_util::codec::{Decoder, Encoder};
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time;
+
+/// Writes frames received on one connection into a record file
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    /// The file is closed after first failure so that recording doesn't affect the connection
+    file: Option<io::BufWriter<fs::File>>,
+    codec: v2::Codec,
+}
+
+impl Recorder {
+    /// Extension of record files
+    pub const EXTENSION: &'static str = "v2rec";
+
+    /// Create a new record file in `dir` for connection to `host_and_port`. The file name
+    /// contains the time of connection to distinguish records of reconnections.
+    pub fn create<P: AsRef<Path>>(dir: P, host_and_port: &str) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name: String = host_and_port
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = dir.join(format!("{}-{}.{}", name, timestamp, Self::EXTENSION));
+        let file = fs::File::create(&path)?;
+        info!("Stratum: recording received frames to '{}'", path.display());
+
+        Ok(Self {
+            path,
+            file: Some(io::BufWriter::new(file)),
+            codec: v2::Codec::default(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Append `frame` to the record. The frame has to be taken apart to access its payload so
+    /// an equivalent frame is returned for further processing.
+    pub fn record(&mut self, frame: v2::Frame) -> ii_stratum::error::Result<v2::Frame> {
+        let (header, payload) = frame.split();
+        let payload = payload.into_bytes_mut()?;
+        let build_frame = |payload| {
+            v2::Frame::from_serialized_payload(
+                header.is_channel_message,
+                header.extension_type,
+                header.msg_type,
+                payload,
+            )
+        };
+
+        if let Some(file) = self.file.as_mut() {
+            let mut buf = BytesMut::new();
+            self.codec.encode(build_frame(payload.clone()), &mut buf)?;
+            if let Err(e) = file.write_all(&buf).and_then(|_| file.flush()) {
+                error!(
+                    "Stratum: stopping recording to '{}': {}",
+                    self.path.display(),
+                    e
+                );
+                self.file = None;
+            }
+        }
+        Ok(build_frame(payload))
+    }
+}
+
+/// Read all frames stored in record file `path`
+pub fn read_frames<P: AsRef<Path>>(path: P) -> error::Result<Vec<v2::Frame>> {
+    let mut buf = BytesMut::from(&fs::read(path)?[..]);
+    let mut codec = v2::Codec::default();
+    let mut frames = Vec::new();
+    while let Some(frame) = codec.decode(&mut buf)? {
+        frames.push(frame);
+    }
+    if !buf.is_empty() {
+        Err(format!("truncated record ({} trailing bytes)", buf.len()))?;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use super::*;
+
+    use ii_async_compat::tokio;
+    use ii_stratum::test_utils::v2::*;
+
+    use std::convert::TryInto;
+
+    /// Outcome of replayed frames
+    struct Replay {
+        /// Event handler in the state after processing of all frames
+        event_handler: StratumEventHandler,
+        /// All jobs sent for solving in the same order
+        jobs: Vec<StratumJob>,
+    }
+
+    /// Replay `frames` against the event handler of a client with mock job sender
+    async fn replay(frames: Vec<v2::Frame>, channels: Vec<(u32, ii_bitcoin::Target)>) -> Replay {
+        let jobs = Arc::new(StdMutex::new(Vec::new()));
+        let engine_sender = Arc::new(work::EngineSender::new(None));
+        let sent_jobs = jobs.clone();
+        engine_sender.replace_engine_generator(Box::new(move |job| {
+            sent_jobs.lock().expect("BUG: cannot lock jobs").push(job);
+            Arc::new(work::engine::ExhaustedWork)
+        }));
+        let (_, solution_receiver) = mpsc::unbounded();
+
+        let client = Arc::new(StratumClient::new(
+            ConnectionDetails {
+                protocol: ClientProtocol::StratumV2Insecure,
+                user: "test".to_string(),
+                host: "localhost".to_string(),
+                port: 3336,
+                retry_schedule: Default::default(),
+                channel_count: channels.len(),
+                endpoints: Arc::new(endpoint::Selector::new(vec!["localhost:3336".to_string()])),
+            },
+            None,
+            0,
+            1,
+            job::Solver::new(engine_sender, solution_receiver),
+            None,
+            None,
+        ));
+        let mut event_handler = StratumEventHandler::new(client.clone(), channels);
+        for frame in frames {
+            client
+                .handle_frame(frame, &mut event_handler)
+                .await
+                .expect("BUG: cannot replay frame");
+        }
+
+        let jobs = jobs
+            .lock()
+            .expect("BUG: cannot lock jobs")
+            .iter()
+            .map(|job| {
+                job.downcast_ref::<StratumJob>()
+                    .expect("BUG: unexpected job type")
+                    .clone()
+            })
+            .collect();
+        Replay {
+            event_handler,
+            jobs,
+        }
+    }
+
+    fn frame<T>(message: T) -> v2::Frame
+    where
+        T: TryInto<v2::Frame>,
+        T::Error: std::fmt::Debug,
+    {
+        message.try_into().expect("BUG: cannot build frame")
+    }
+
+    fn new_job(job_id: u32, future_job: bool) -> v2::Frame {
+        frame(NewMiningJob {
+            job_id,
+            future_job,
+            ..build_new_mining_job()
+        })
+    }
+
+    fn channels() -> Vec<(u32, ii_bitcoin::Target)> {
+        vec![(0, Default::default())]
+    }
+
+    /// Record frames into a temporary file and read them back
+    fn record_roundtrip(name: &str, frames: Vec<v2::Frame>) -> Vec<v2::Frame> {
+        let dir =
+            std::env::temp_dir().join(format!("bosminer-record-{}-{}", name, std::process::id()));
+        let mut recorder = Recorder::create(&dir, "localhost:3336").expect("BUG: cannot create");
+        for frame in frames {
+            recorder.record(frame).expect("BUG: cannot record frame");
+        }
+        let path = recorder.path().to_path_buf();
+        drop(recorder);
+
+        let frames = read_frames(&path).expect("BUG: cannot read record");
+        fs::remove_dir_all(&dir).expect("BUG: cannot remove record");
+        frames
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let frames = vec![
+            new_job(0, true),
+            frame(build_set_new_prev_hash()),
+            new_job(1, false),
+        ];
+        let recorded = record_roundtrip("roundtrip", frames);
+        assert_eq!(recorded.len(), 3);
+        // Recorded frames match frames built directly from serialized messages
+        let expected: Vec<_> = vec![
+            new_job(0, true),
+            frame(build_set_new_prev_hash()),
+            new_job(1, false),
+        ]
+        .into_iter()
+        .map(|frame| {
+            let (header, payload) = frame.split();
+            v2::Frame::from_serialized_payload(
+                header.is_channel_message,
+                header.extension_type,
+                header.msg_type,
+                payload.into_bytes_mut().unwrap(),
+            )
+        })
+        .collect();
+        assert_eq!(recorded, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replay_future_job() {
+        let frames = record_roundtrip(
+            "future-job",
+            vec![
+                new_job(0, true),
+                frame(build_set_new_prev_hash()),
+                new_job(1, false),
+            ],
+        );
+        let jobs = replay(frames, channels()).await.jobs;
+
+        // The future job is solved only after it is activated by the prevhash
+        let job_ids: Vec<_> = jobs.iter().map(|job| job.id).collect();
+        assert_eq!(job_ids, vec![0, 1]);
+        assert!(jobs.iter().all(|job| job.prev_hash == jobs[0].prev_hash));
+    }
+
+    #[tokio::test]
+    async fn test_replay_job_without_prevhash() {
+        // Jobs arriving ahead of any prevhash cannot be solved
+        let jobs = replay(vec![new_job(0, false)], channels()).await.jobs;
+        assert!(jobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay_set_target() {
+        let target: ii_bitcoin::Target = build_open_channel_success().target.into();
+        let frames = vec![
+            new_job(0, true),
+            frame(build_set_new_prev_hash()),
+            frame(SetTarget {
+                channel_id: 0,
+                max_target: target.into(),
+            }),
+            new_job(1, false),
+        ];
+        let jobs = replay(frames, channels()).await.jobs;
+
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].target, ii_bitcoin::Target::default());
+        assert_eq!(jobs[1].target, target);
+    }
+
+    #[tokio::test]
+    async fn test_replay_unknown_channel() {
+        let frames = vec![
+            new_job(0, true),
+            frame(SetNewPrevHash {
+                channel_id: 1,
+                ..build_set_new_prev_hash()
+            }),
+        ];
+        let jobs = replay(frames, channels()).await.jobs;
+        assert!(jobs.is_empty());
+    }
+
+    fn set_new_prev_hash(job_id: u32, prev_hash: u8) -> v2::Frame {
+        frame(SetNewPrevHash {
+            job_id,
+            prev_hash: Uint256Bytes([prev_hash; 32]),
+            ..build_set_new_prev_hash()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_replay_unknown_prevhash_job() {
+        let frames = vec![
+            new_job(0, true),
+            set_new_prev_hash(0, 1),
+            // Misbehaving pool references a job which hasn't been sent
+            set_new_prev_hash(1, 2),
+        ];
+        let replay = replay(frames, channels()).await;
+        assert_eq!(replay.jobs.len(), 1);
+
+        // The job built on top of the previous prevhash is not solved anymore
+        let channel = &replay.event_handler.channels[&0];
+        assert!(channel.current_job.is_none());
+        assert_eq!(channel.missing_job_id, Some(1));
+        assert!(channel.all_jobs.is_empty());
+        let client_stats = &replay.event_handler.client.client_stats;
+        assert_eq!(*client_stats.invalid_jobs.take_snapshot(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_late_prevhash_job() {
+        let frames = vec![
+            new_job(0, true),
+            set_new_prev_hash(0, 1),
+            set_new_prev_hash(1, 2),
+            // The referenced job is solved as soon as it comes even when marked as future job
+            new_job(1, true),
+            new_job(2, true),
+        ];
+        let replay = replay(frames, channels()).await;
+
+        let job_ids: Vec<_> = replay.jobs.iter().map(|job| job.id).collect();
+        assert_eq!(job_ids, vec![0, 1]);
+        assert_ne!(replay.jobs[0].prev_hash, replay.jobs[1].prev_hash);
+        let channel = &replay.event_handler.channels[&0];
+        assert_eq!(channel.missing_job_id, None);
+        assert_eq!(channel.all_jobs.len(), 2);
+    }
+}
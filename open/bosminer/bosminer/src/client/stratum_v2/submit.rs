@@ -0,0 +1,349 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Queue of shares submitted by the Stratum V2 client. Solutions are queued without waiting for
+//! the connection and they are sent by a separate task as long as the number of shares which
+//! haven't been acknowledged by the server yet fits into the submission window. Shares are kept
+//! in the queue until they are acknowledged. Jobs and channels don't survive the connection so
+//! all queued shares are dropped as stale when the connection is lost.
+
+use crate::work;
+
+use ii_async_compat::prelude::*;
+use tokio::sync::Notify;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex as StdMutex, MutexGuard as StdMutexGuard};
+
+/// Solution submitted in a channel with its assigned sequence number
+#[derive(Debug, Clone)]
+pub struct Share {
+    pub channel_id: u32,
+    pub seq_num: u32,
+    pub solution: work::Solution,
+}
+
+#[derive(Debug)]
+struct QueueInner {
+    /// Solutions waiting for submission with ID of the channel they belong to
+    pending: VecDeque<(u32, work::Solution)>,
+    /// Submitted shares waiting for acknowledgement from the server (for each channel)
+    in_flight: HashMap<u32, VecDeque<Share>>,
+    /// Total number of shares in `in_flight`
+    in_flight_count: usize,
+    /// Sequence number of the next share for each channel. It is our responsibility to keep the
+    /// sequence number monotonic within the channel so that we as a stratum V2 client can easily
+    /// process bulk acknowledgements.
+    next_seq_nums: HashMap<u32, u32>,
+}
+
+impl QueueInner {
+    /// Remove shares preceding share with `seq_num` (inclusive) in the channel `channel_id`
+    fn take_in_flight(&mut self, channel_id: u32, seq_num: u32) -> Vec<Share> {
+        let mut shares = vec![];
+        if let Some(in_flight) = self.in_flight.get_mut(&channel_id) {
+            while let Some(share) = in_flight.pop_front() {
+                let last = share.seq_num == seq_num;
+                shares.push(share);
+                if last {
+                    break;
+                }
+            }
+        }
+        self.in_flight_count -= shares.len();
+        shares
+    }
+}
+
+#[derive(Debug)]
+pub struct Queue {
+    inner: StdMutex<QueueInner>,
+    /// Maximal number of submitted shares which haven't been acknowledged yet
+    window: usize,
+    /// Maximal number of solutions waiting for submission
+    capacity: usize,
+    /// Wakes up the submitting task when there is a new share that can be sent
+    notify: Notify,
+}
+
+impl Queue {
+    pub fn new(window: usize, capacity: usize) -> Self {
+        assert!(window > 0, "BUG: empty submission window");
+        assert!(capacity > 0, "BUG: empty submission queue");
+        Self {
+            inner: StdMutex::new(QueueInner {
+                pending: VecDeque::new(),
+                in_flight: HashMap::new(),
+                in_flight_count: 0,
+                next_seq_nums: HashMap::new(),
+            }),
+            window,
+            capacity,
+            notify: Notify::new(),
+        }
+    }
+
+    fn lock_inner(&self) -> StdMutexGuard<QueueInner> {
+        self.inner
+            .lock()
+            .expect("BUG: cannot lock submission queue")
+    }
+
+    /// Number of solutions waiting for submission
+    pub fn pending_count(&self) -> usize {
+        self.lock_inner().pending.len()
+    }
+
+    /// Number of submitted shares waiting for acknowledgement
+    pub fn in_flight_count(&self) -> usize {
+        self.lock_inner().in_flight_count
+    }
+
+    /// Queue `solution` for submission in the channel `channel_id`. When the queue is full, the
+    /// oldest pending solution is dropped and returned.
+    pub fn push(&self, channel_id: u32, solution: work::Solution) -> Option<work::Solution> {
+        let mut inner = self.lock_inner();
+        let dropped = if inner.pending.len() >= self.capacity {
+            inner.pending.pop_front().map(|(_, solution)| solution)
+        } else {
+            None
+        };
+        inner.pending.push_back((channel_id, solution));
+        drop(inner);
+
+        self.notify.notify_one();
+        dropped
+    }
+
+    /// Assign sequence number to the oldest pending solution and mark it as submitted. Nothing is
+    /// returned when the submission window is full.
+    pub fn try_next(&self) -> Option<Share> {
+        let mut inner = self.lock_inner();
+        if inner.in_flight_count >= self.window {
+            return None;
+        }
+        let (channel_id, solution) = inner.pending.pop_front()?;
+        let next_seq_num = inner.next_seq_nums.entry(channel_id).or_insert(0);
+        let seq_num = *next_seq_num;
+        *next_seq_num = seq_num.wrapping_add(1);
+
+        let share = Share {
+            channel_id,
+            seq_num,
+            solution,
+        };
+        inner
+            .in_flight
+            .entry(channel_id)
+            .or_default()
+            .push_back(share.clone());
+        inner.in_flight_count += 1;
+        Some(share)
+    }
+
+    /// Wait for the next share which can be sent to the server
+    pub async fn next(&self) -> Share {
+        loop {
+            if let Some(share) = self.try_next() {
+                return share;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Process bulk acknowledgement of all shares up to `last_seq_num` (inclusive) in the channel
+    /// `channel_id` and return them
+    pub fn acknowledge(&self, channel_id: u32, last_seq_num: u32) -> Vec<Share> {
+        let shares = self.lock_inner().take_in_flight(channel_id, last_seq_num);
+        self.notify.notify_one();
+        shares
+    }
+
+    /// Process rejection of share `seq_num` in the channel `channel_id`. Returns the shares
+    /// preceding the rejected one and the rejected share (if it has been found).
+    pub fn reject(&self, channel_id: u32, seq_num: u32) -> (Vec<Share>, Option<Share>) {
+        let mut shares = self.lock_inner().take_in_flight(channel_id, seq_num);
+        self.notify.notify_one();
+        let rejected = match shares.last() {
+            Some(share) if share.seq_num == seq_num => shares.pop(),
+            _ => None,
+        };
+        (shares, rejected)
+    }
+
+    /// Prepare the queue for a new connection. Channels of the previous connection are closed so
+    /// neither submitted shares which haven't been acknowledged nor pending solutions can be
+    /// submitted anymore. They are removed from the queue and returned (in order they have been
+    /// found). Sequence numbers are restarted.
+    pub fn reset(&self) -> Vec<work::Solution> {
+        let mut inner = self.lock_inner();
+        let mut solutions: Vec<_> = inner
+            .in_flight
+            .drain()
+            .flat_map(|(_, shares)| shares)
+            .map(|share| share.solution)
+            .collect();
+        solutions.sort_by_key(|solution| solution.timestamp());
+        solutions.extend(inner.pending.drain(..).map(|(_, solution)| solution));
+        inner.in_flight_count = 0;
+        inner.next_seq_nums.clear();
+        solutions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+
+    use ii_async_compat::tokio;
+
+    fn solution(i: usize) -> work::Solution {
+        (&test_utils::TEST_BLOCKS[i]).into()
+    }
+
+    fn seq_nums(shares: &[Share]) -> Vec<u32> {
+        shares.iter().map(|share| share.seq_num).collect()
+    }
+
+    #[test]
+    fn test_window() {
+        let queue = Queue::new(2, 8);
+        assert!(queue.try_next().is_none());
+
+        for i in 0..3 {
+            queue.push(1, solution(i));
+        }
+        assert_eq!(queue.try_next().map(|share| share.seq_num), Some(0));
+        assert_eq!(queue.try_next().map(|share| share.seq_num), Some(1));
+        // the window is full
+        assert!(queue.try_next().is_none());
+        assert_eq!(queue.pending_count(), 1);
+        assert_eq!(queue.in_flight_count(), 2);
+
+        assert_eq!(seq_nums(&queue.acknowledge(1, 0)), vec![0]);
+        assert_eq!(queue.try_next().map(|share| share.seq_num), Some(2));
+        assert_eq!(queue.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_channels() {
+        let queue = Queue::new(8, 8);
+        queue.push(1, solution(0));
+        queue.push(2, solution(1));
+        queue.push(1, solution(2));
+
+        // sequence numbers are assigned per channel
+        let shares: Vec<_> = (0..3).filter_map(|_| queue.try_next()).collect();
+        let ids: Vec<_> = shares
+            .iter()
+            .map(|share| (share.channel_id, share.seq_num))
+            .collect();
+        assert_eq!(ids, vec![(1, 0), (2, 0), (1, 1)]);
+
+        // acknowledgement affects only its own channel
+        assert_eq!(seq_nums(&queue.acknowledge(1, 1)), vec![0, 1]);
+        assert_eq!(queue.in_flight_count(), 1);
+        assert!(queue.acknowledge(3, 0).is_empty());
+        assert_eq!(seq_nums(&queue.acknowledge(2, 0)), vec![0]);
+        assert_eq!(queue.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn test_reject() {
+        let queue = Queue::new(8, 8);
+        for i in 0..3 {
+            queue.push(1, solution(i));
+            queue.try_next().expect("missing share");
+        }
+
+        let (accepted, rejected) = queue.reject(1, 1);
+        assert_eq!(seq_nums(&accepted), vec![0]);
+        assert_eq!(rejected.map(|share| share.seq_num), Some(1));
+
+        // unknown share
+        let (accepted, rejected) = queue.reject(1, 5);
+        assert_eq!(seq_nums(&accepted), vec![2]);
+        assert!(rejected.is_none());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let queue = Queue::new(8, 2);
+        assert!(queue.push(1, solution(0)).is_none());
+        assert!(queue.push(1, solution(1)).is_none());
+
+        // the oldest pending solution is dropped
+        let dropped = queue
+            .push(1, solution(2))
+            .expect("missing dropped solution");
+        assert_eq!(dropped.nonce(), solution(0).nonce());
+        assert_eq!(queue.pending_count(), 2);
+
+        // submitted shares don't occupy the queue
+        queue.try_next().expect("missing share");
+        assert!(queue.push(1, solution(3)).is_none());
+    }
+
+    #[test]
+    fn test_reset() {
+        let queue = Queue::new(2, 8);
+        for i in 0..3 {
+            queue.push(i as u32, solution(i));
+        }
+        queue.try_next().expect("missing share");
+        queue.try_next().expect("missing share");
+
+        // both submitted and pending solutions are dropped
+        let dropped: Vec<_> = queue
+            .reset()
+            .iter()
+            .map(|solution| solution.nonce())
+            .collect();
+        let expected: Vec<_> = (0..3).map(|i| solution(i).nonce()).collect();
+        assert_eq!(dropped, expected);
+        assert_eq!(queue.in_flight_count(), 0);
+        assert_eq!(queue.pending_count(), 0);
+
+        // sequence numbers start from 0 again
+        queue.push(1, solution(0));
+        let share = queue.try_next().expect("missing share");
+        assert_eq!((share.channel_id, share.seq_num), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_next() {
+        let queue = Queue::new(1, 8);
+        queue.push(1, solution(0));
+        queue.push(1, solution(1));
+        assert_eq!(queue.next().await.seq_num, 0);
+
+        // the window is full until the share is acknowledged
+        assert!(queue
+            .next()
+            .timeout(std::time::Duration::from_millis(10))
+            .await
+            .is_err());
+        queue.acknowledge(1, 0);
+        assert_eq!(queue.next().await.seq_num, 1);
+    }
+}
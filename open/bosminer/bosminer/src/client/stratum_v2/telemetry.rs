@@ -20,24 +20,209 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use crate::error::{self, ResultExt};
+//! Telemetry protocol extension of Stratum V2. The `Publisher` periodically takes a snapshot of
+//! the miner status and passes it to telemetry clients of all Stratum V2 connections. Each pool
+//! receives share statistics of its own connection only. Each `Client` opens a telemetry channel
+//! whenever its connection is established and submits the snapshots through it.
+
+use crate::error;
+use crate::hub;
+use crate::node::{self, Stats as _, WorkSolver as _};
+use crate::stats;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
 use futures::channel::mpsc;
 
 use ii_async_compat::prelude::*;
-use ii_async_compat::{bytes, select};
+use ii_async_compat::{bytes, select, task, tokio};
 use ii_logging::macros::*;
 use ii_stratum::v2::{self, extensions, framing, telemetry::messages::*, types::*};
+use serde_json::json;
+
+use super::extension;
 
-use super::ExtensionChannelMsg;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
 
 /// Make channel ID type more visible in the code
 type ChannelId = u32;
 
-/// TODO consider transforming the code below into a state pattern instead of multiplexing the
-/// states all the time
+/// Status of the miner submitted as telemetry data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub time: time::SystemTime,
+    /// Time since the start of the miner
+    pub uptime: time::Duration,
+    /// Hashrate of all work solvers in GH/s averaged over 1 minute, 5 minutes and 15 minutes
+    pub hashrate: [f64; 3],
+    /// Sum of nominal hashrates of all work solvers in GH/s
+    pub nominal_hashrate: f64,
+    /// Shares accepted by the pool receiving the snapshot
+    pub accepted: u64,
+    /// Shares rejected by the pool receiving the snapshot
+    pub rejected: u64,
+    pub hardware_errors: u64,
+    pub work_solvers: usize,
+}
+
+impl Snapshot {
+    /// Collects status of the whole miner. Share statistics are filled in for each pool by
+    /// `for_client`.
+    pub async fn collect(core: &hub::Core) -> Self {
+        let now = time::Instant::now();
+        let mining_stats = core.frontend.mining_stats();
+        let valid_backend_diff = mining_stats.valid_backend_diff().take_snapshot().await;
+        let hashrate = |interval| {
+            valid_backend_diff
+                .to_kilo_hashes(interval, now)
+                .into_hashes()
+                .into_f64()
+                / 1e9
+        };
+
+        let work_solvers = core.get_work_solvers().await;
+        let mut nominal_hashrate = 0.0;
+        for work_solver in work_solvers.iter() {
+            if let Some(hashrate) = work_solver.get_nominal_hashrate().await {
+                nominal_hashrate += hashrate.into_hashes().into_f64() / 1e9;
+            }
+        }
+
+        Self {
+            time: time::SystemTime::now(),
+            uptime: now.duration_since(*mining_stats.start_time()),
+            hashrate: [
+                hashrate(*stats::TIME_MEAN_INTERVAL_1M),
+                hashrate(*stats::TIME_MEAN_INTERVAL_5M),
+                hashrate(*stats::TIME_MEAN_INTERVAL_15M),
+            ],
+            nominal_hashrate,
+            accepted: 0,
+            rejected: 0,
+            hardware_errors: mining_stats
+                .error_backend_diff()
+                .take_snapshot()
+                .await
+                .solutions,
+            work_solvers: work_solvers.len(),
+        }
+    }
+
+    /// Snapshot with share statistics of `client`
+    pub async fn for_client(&self, client: &dyn node::Client) -> Self {
+        let client_stats = client.client_stats();
+        Self {
+            accepted: client_stats.accepted().take_snapshot().await.solutions,
+            rejected: client_stats.rejected().take_snapshot().await.solutions,
+            ..self.clone()
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "time": self
+                .time
+                .duration_since(time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            "uptime": self.uptime.as_secs(),
+            "hashrate_1m": self.hashrate[0],
+            "hashrate_5m": self.hashrate[1],
+            "hashrate_15m": self.hashrate[2],
+            "nominal_hashrate": self.nominal_hashrate,
+            "accepted": self.accepted,
+            "rejected": self.rejected,
+            "hardware_errors": self.hardware_errors,
+            "work_solvers": self.work_solvers,
+        })
+    }
+
+    /// Serialized snapshot used as a payload of telemetry submission
+    pub fn to_payload(&self) -> BytesMut {
+        BytesMut::from(self.to_json().to_string().as_bytes())
+    }
+}
+
+/// Telemetry client of one Stratum V2 connection
+#[derive(Debug, Clone)]
+struct Subscriber {
+    /// Stratum V2 client whose share statistics are submitted
+    client: Arc<dyn node::Client>,
+    sender: mpsc::UnboundedSender<BytesMut>,
+}
+
+/// Distributes periodic miner status snapshots to telemetry clients of all Stratum V2
+/// connections
+#[derive(Debug)]
+pub struct Publisher {
+    interval: time::Duration,
+    /// Telemetry clients that are still alive
+    subscribers: StdMutex<Vec<Subscriber>>,
+}
+
+impl Publisher {
+    pub fn new(interval: time::Duration) -> Self {
+        Self {
+            interval,
+            subscribers: StdMutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers a telemetry client that identifies itself as `dev_id` in protocol `extensions`
+    /// of Stratum V2 `client`. The telemetry client terminates together with the Stratum V2
+    /// client which owns the multiplexer.
+    pub fn attach(
+        &self,
+        dev_id: String,
+        extensions: &Arc<extension::Multiplexer>,
+        client: Arc<dyn node::Client>,
+    ) -> error::Result<()> {
+        let telemetry_client = Client::new(dev_id, extensions)?;
+        self.subscribers
+            .lock()
+            .expect("BUG: cannot lock telemetry clients")
+            .push(Subscriber {
+                client,
+                sender: telemetry_client.get_unbounded_sender(),
+            });
+        task::spawn_named("telemetry client", async move {
+            if let Err(e) = telemetry_client.run().await {
+                error!("Telemetry: client terminated: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Returns telemetry clients that are still alive and forgets the terminated ones
+    fn subscribers(&self) -> Vec<Subscriber> {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("BUG: cannot lock telemetry clients");
+        subscribers.retain(|subscriber| !subscriber.sender.is_closed());
+        subscribers.clone()
+    }
+
+    pub async fn run(self: Arc<Self>, core: Arc<hub::Core>) {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            let snapshot = Snapshot::collect(&core).await;
+            for subscriber in self.subscribers() {
+                let snapshot = snapshot.for_client(subscriber.client.as_ref()).await;
+                trace!(
+                    "Telemetry: publishing {:?} to {}",
+                    snapshot,
+                    subscriber.client
+                );
+                // Terminated client is forgotten during the next round
+                let _ = subscriber.sender.unbounded_send(snapshot.to_payload());
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 enum State {
     /// Telemetry Channel not open yet
@@ -58,10 +243,9 @@ pub struct Client {
     state: State,
 
     /// Receive control commands and telemetry extension messages
-    /// TODO: consider replacing this channel with a standard API -> start/stop/handle_frame()
-    stratum_receiver: super::ExtensionChannelFromStratumReceiver,
-    /// Channel to send telemetry extension messages
-    stratum_sender: super::ExtensionChannelToStratumSender,
+    stratum_receiver: mpsc::Receiver<extension::Message>,
+    /// Send telemetry extension messages
+    stratum_sender: extension::Sender,
 
     /// Raw telemetry data being received from all components that were given the
     /// `telem_data_sender` endpoint
@@ -69,6 +253,9 @@ pub struct Client {
     /// Sender endpoint that this client provides to any component that is interested in
     /// submitting the telemetry data
     telem_data_sender: mpsc::UnboundedSender<BytesMut>,
+    /// Telemetry data received while the channel is not operational. It is submitted as soon as
+    /// the channel (re)opens.
+    pending_data: VecDeque<BytesMut>,
 
     /// Current request ID/sequence ID.
     curr_request_id: u32,
@@ -80,53 +267,52 @@ pub struct Client {
 }
 
 impl Client {
-    const CHANNEL_CAPACITY: usize = 16;
-
-    /// Creates a new client and provides the communication endpoints for it
-    pub fn new(
-        dev_id: String,
-    ) -> (
-        Self,
-        super::ExtensionChannelToStratumReceiver,
-        super::ExtensionChannelFromStratumSender,
-    ) {
-        // Prepare the communication channels between stratum client and the telemetry extension
-        let (from_stratum_sender, from_stratum_receiver) = mpsc::channel(Self::CHANNEL_CAPACITY);
-        let (to_stratum_sender, to_stratum_receiver) = mpsc::channel(Self::CHANNEL_CAPACITY);
-
+    /// Maximal number of telemetry submissions kept while the channel is not operational. The
+    /// oldest data are dropped first.
+    const PENDING_DATA_CAPACITY: usize = 16;
+
+    /// Creates a new client registered in stratum `extensions`
+    pub fn new(dev_id: String, extensions: &Arc<extension::Multiplexer>) -> error::Result<Self> {
+        let dev_id = Str0_255::try_from(dev_id.as_str()).map_err(|_| {
+            error::ErrorKind::Stratum(format!("Telemetry: invalid device ID '{}'", dev_id))
+        })?;
+        let endpoint = extensions.register(extensions::TELEMETRY)?;
         let (telem_data_sender, telem_data_receiver) = mpsc::unbounded();
 
-        let client = Self {
+        Ok(Self {
             state: State::Init,
-            stratum_receiver: from_stratum_receiver,
-            stratum_sender: to_stratum_sender,
+            stratum_receiver: endpoint.receiver,
+            stratum_sender: endpoint.sender,
             telem_data_sender,
             telem_data_receiver,
+            pending_data: VecDeque::new(),
             curr_request_id: 0,
             curr_data_sequence_id: 0,
-            dev_id: dev_id.try_into().expect("TODO: dev ID cannot be converted"),
-        };
-
-        (client, to_stratum_receiver, from_stratum_sender)
+            dev_id,
+        })
     }
 
+    /// Runs until the Stratum V2 client is dropped. Protocol errors are only logged.
     pub async fn run(mut self) -> error::Result<()> {
         loop {
             select! {
                 message = self.stratum_receiver.next().fuse() => {
                     match message {
                         Some(message) => {
-                            self.handle_message(message).await?
-                        }
-                        None => {
-                            Err("The remote endpoint stopped")?;
+                            if let Err(e) = self.handle_message(message).await {
+                                self.log_error(format!("{}", e).as_str());
+                            }
                         }
+                        // The Stratum V2 client together with its extensions has been dropped
+                        None => return Ok(()),
                     }
                 }
                 // Wrap telemetry data and send it upstream
                 data = self.telem_data_receiver.next().fuse() => {
                     let data = data.ok_or("End of telemetry stream")?;
-                    self.send_telemetry(data).await?;
+                    if let Err(e) = self.send_telemetry(data).await {
+                        self.log_error(format!("Cannot send telemetry: {}", e).as_str());
+                    }
                 }
             }
         }
@@ -136,29 +322,26 @@ impl Client {
         self.telem_data_sender.clone()
     }
 
-    ///
-    async fn handle_message(&mut self, message: ExtensionChannelMsg) -> error::Result<()> {
+    async fn handle_message(&mut self, message: extension::Message) -> error::Result<()> {
         match message {
-            ExtensionChannelMsg::Start => self.start_channel().await,
-            // TODO currently there is no channel close protocol. This may need to be improved
-            ExtensionChannelMsg::Stop => {
+            extension::Message::Start => self.start_channel().await,
+            // There is no channel close protocol, the server forgets the channel together with
+            // the connection. Telemetry data are kept until the channel is opened again.
+            extension::Message::Stop => {
                 self.state = State::Init;
                 Ok(())
             }
-            ExtensionChannelMsg::Frame(frame) => self.handle_frame(frame).await,
+            extension::Message::Frame(frame) => self.handle_frame(frame).await,
         }
     }
 
-    /// Submits data when in operational state, ignores the data in any other state.
-    /// TODO: consider queueing the data to some level
+    /// Submits data when in operational state, queues the data in any other state
     async fn send_telemetry(&mut self, data: BytesMut) -> error::Result<()> {
         match self.state {
             State::Operational(channel_id) => {
                 let msg = SubmitTelemetryData {
                     channel_id,
                     seq_num: self.next_data_sequence_id(),
-                    // TODO investigate why stratum TryFrom<Bytes0_64k> returns () as error variant
-                    //  see
                     telemetry_payload: data[..]
                         .try_into()
                         .map_err(|e| format!("Invalid telemetry data to serialize {:?}", e))?,
@@ -166,15 +349,27 @@ impl Client {
                 self.send_msg(msg).await
             }
             _ => {
-                // Telemetry cannot be sent in any other state. We will ignore the data for the time
-                // being. However, we will not communicate the error as we don't want to break
-                // the possibly ongoing handshake stage
-                self.log_error("Cannot send telemetry, ignoring the data");
+                // Telemetry cannot be sent in any other state. We will keep the data until the
+                // channel is operational. However, we will not communicate the error as we don't
+                // want to break the possibly ongoing handshake stage
+                if self.pending_data.len() >= Self::PENDING_DATA_CAPACITY {
+                    self.log_error("Cannot send telemetry, dropping the oldest data");
+                    self.pending_data.pop_front();
+                }
+                self.pending_data.push_back(data);
                 Ok(())
             }
         }
     }
 
+    /// Submits all data received before the channel became operational
+    async fn send_pending_telemetry(&mut self) -> error::Result<()> {
+        while let Some(data) = self.pending_data.pop_front() {
+            self.send_telemetry(data).await?;
+        }
+        Ok(())
+    }
+
     async fn handle_frame(&mut self, frame: framing::Frame) -> error::Result<()> {
         assert_eq!(
             frame.header.extension_type,
@@ -188,22 +383,17 @@ impl Client {
     }
 
     async fn start_channel(&mut self) -> error::Result<()> {
-        match self.state {
-            State::Init => {
-                self.state = State::Handshake;
-                let msg = OpenTelemetryChannel {
-                    req_id: self.next_request_id(),
-                    dev_id: self.dev_id.clone(),
-                };
-                self.log_info(format!("starting client, message: {:?}", msg).as_str());
-                self.send_msg(msg).await
-            }
-            _ => {
-                let err_msg = "Cannot start telemetry client";
-                self.log_error(err_msg);
-                Err(error::ErrorKind::Stratum(err_msg.to_string()).into())
-            }
+        if !matches!(self.state, State::Init) {
+            // The previous connection has been lost without notifying us so start over
+            self.log_info("restarting channel");
         }
+        self.state = State::Handshake;
+        let msg = OpenTelemetryChannel {
+            req_id: self.next_request_id(),
+            dev_id: self.dev_id.clone(),
+        };
+        self.log_info(format!("starting client, message: {:?}", msg).as_str());
+        self.send_msg(msg).await
     }
 
     async fn send_msg<M>(&mut self, message: M) -> error::Result<()>
@@ -213,12 +403,7 @@ impl Client {
             Error = <framing::Framing as ii_wire::Framing>::Error,
         >,
     {
-        let frame = message.try_into()?;
-
-        self.stratum_sender
-            .try_send(frame)
-            .context("submit message")
-            .map_err(Into::into)
+        self.stratum_sender.send(message)
     }
 
     /// Helper that logs about an error appending the current telemetry state
@@ -284,6 +469,9 @@ impl v2::Handler for Client {
                     self.state = State::Operational(payload.channel_id);
                     self.log_info("channel operational");
                     self.next_request_id();
+                    if let Err(e) = self.send_pending_telemetry().await {
+                        self.log_error(format!("Cannot send pending telemetry: {}", e).as_str());
+                    }
                 } else {
                     self.log_error_request_id("OpenTelemetryChannelSuccess", payload.req_id);
                     self.state = State::Init;
@@ -374,3 +562,118 @@ impl v2::Handler for Client {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    /// Run telemetry client until all pending messages are processed
+    async fn process(client: &mut Client) {
+        while let Ok(Some(message)) = client
+            .stratum_receiver
+            .next()
+            .timeout(time::Duration::from_millis(10))
+            .await
+        {
+            client
+                .handle_message(message)
+                .await
+                .expect("BUG: telemetry message failed");
+        }
+    }
+
+    async fn next_msg_type(extensions: &extension::Multiplexer) -> Option<u8> {
+        extensions
+            .next_frame()
+            .timeout(time::Duration::from_millis(10))
+            .await
+            .ok()
+            .map(|frame| frame.header.msg_type)
+    }
+
+    #[tokio::test]
+    async fn test_pending_data() {
+        let extensions = Arc::new(extension::Multiplexer::new());
+        let mut client =
+            Client::new("test-device".to_string(), &extensions).expect("BUG: cannot create client");
+
+        // Data are kept until the channel is operational
+        for _ in 0..Client::PENDING_DATA_CAPACITY + 1 {
+            client
+                .send_telemetry(BytesMut::from(&b"data"[..]))
+                .await
+                .expect("BUG: cannot send telemetry");
+        }
+        assert_eq!(client.pending_data.len(), Client::PENDING_DATA_CAPACITY);
+        assert_eq!(next_msg_type(&extensions).await, None);
+
+        extensions.start();
+        process(&mut client).await;
+        assert_eq!(
+            next_msg_type(&extensions).await,
+            Some(MessageType::OpenTelemetryChannel as u8)
+        );
+
+        let success: framing::Frame = OpenTelemetryChannelSuccess {
+            req_id: client.curr_request_id,
+            channel_id: 7,
+        }
+        .try_into()
+        .expect("BUG: cannot serialize message");
+        extensions.dispatch(success);
+        process(&mut client).await;
+        assert!(client.pending_data.is_empty());
+        for _ in 0..Client::PENDING_DATA_CAPACITY {
+            assert_eq!(
+                next_msg_type(&extensions).await,
+                Some(MessageType::SubmitTelemetryData as u8)
+            );
+        }
+
+        // Lost connection reopens the channel with the next start
+        extensions.stop();
+        process(&mut client).await;
+        client
+            .send_telemetry(BytesMut::from(&b"data"[..]))
+            .await
+            .expect("BUG: cannot send telemetry");
+        assert_eq!(client.pending_data.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_extensions() {
+        let extensions = Arc::new(extension::Multiplexer::new());
+        let client =
+            Client::new("test-device".to_string(), &extensions).expect("BUG: cannot create client");
+        drop(extensions);
+        client
+            .run()
+            .timeout(time::Duration::from_secs(1))
+            .await
+            .expect("BUG: client has not terminated")
+            .expect("BUG: client failed");
+    }
+
+    #[test]
+    fn test_snapshot_payload() {
+        let snapshot = Snapshot {
+            time: time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000),
+            uptime: time::Duration::from_secs(3600),
+            hashrate: [13.5, 13.0, 12.5],
+            nominal_hashrate: 14.0,
+            accepted: 10,
+            rejected: 1,
+            hardware_errors: 2,
+            work_solvers: 3,
+        };
+        let payload: serde_json::Value =
+            serde_json::from_slice(&snapshot.to_payload()[..]).expect("BUG: invalid payload");
+        assert_eq!(payload["time"], 1_600_000_000);
+        assert_eq!(payload["uptime"], 3600);
+        assert_eq!(payload["hashrate_5m"], 13.0);
+        assert_eq!(payload["rejected"], 1);
+        assert_eq!(payload["work_solvers"], 3);
+    }
+}
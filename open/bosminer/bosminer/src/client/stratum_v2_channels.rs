@@ -24,18 +24,19 @@
 
 use ii_logging::macros::*;
 
-use crate::error;
+use crate::client::endpoint;
+use crate::error::{self, ResultExt};
 use crate::job;
 use crate::node;
 use crate::stats;
 use crate::sync;
 use crate::work;
 
-use failure::ResultExt;
-
 use ii_bitcoin::HashTrait;
 
-use bosminer_config::{ClientDescriptor, ClientProtocol};
+use bosminer_config::{
+    ClientDescriptor, ClientExtranoncePartition, ClientProtocol, ClientRetrySchedule,
+};
 use bosminer_macros::ClientNode;
 
 use async_trait::async_trait;
@@ -46,7 +47,7 @@ use ii_async_compat::select;
 
 use std::collections::VecDeque;
 use std::fmt;
-use std::net::ToSocketAddrs;
+use std::str::FromStr;
 use std::sync::{Arc, Weak};
 use std::time;
 
@@ -66,8 +67,9 @@ use ii_wire::Connection;
 
 use std::collections::HashMap;
 
-// TODO: move it to the stratum crate
-const VERSION_MASK: u32 = 0x1fffe000;
+/// The V2->V1 translation negotiates the whole BIP320 range with the upstream (via
+/// `mining.configure`) and fails the connection when the pool doesn't grant it
+const VERSION_MASK: u32 = ii_stratum::BIP320_N_VERSION_MASK;
 
 #[derive(Debug)]
 pub struct ConnectionDetails {
@@ -75,6 +77,10 @@ pub struct ConnectionDetails {
     pub host: String,
     pub port: u16,
     pub fragment: Option<String>,
+    pub retry_schedule: ClientRetrySchedule,
+    pub extranonce_partition: ClientExtranoncePartition,
+    /// All endpoints of the pool with their health
+    pub endpoints: Arc<endpoint::Selector>,
 }
 
 impl ConnectionDetails {
@@ -84,6 +90,9 @@ impl ConnectionDetails {
             host: descriptor.host.clone(),
             port: descriptor.port(),
             fragment: descriptor.fragment.clone(),
+            retry_schedule: descriptor.retry_schedule,
+            extranonce_partition: descriptor.extranonce_partition,
+            endpoints: Arc::new(endpoint::Selector::from_descriptor(descriptor)),
         }
     }
 
@@ -239,11 +248,7 @@ impl StratumEventHandler {
                 seq_num,
                 solution.nonce()
             );
-            self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
+            stats::account_accepted_solution(&self.client.client_stats, &solution, now).await;
             if success_msg.last_seq_num == seq_num {
                 // all accepted solutions have been found
                 return;
@@ -259,16 +264,20 @@ impl StratumEventHandler {
         let now = std::time::Instant::now();
         while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
             if error_msg.seq_num == seq_num {
+                let code = error_msg.code.to_string();
                 info!(
-                    "Stratum: rejected solution #{} with nonce={:08x}!",
+                    "Stratum: rejected solution #{} with nonce={:08x} ({})!",
                     seq_num,
-                    solution.nonce()
+                    solution.nonce(),
+                    code
                 );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                stats::account_rejected_solution(
+                    &self.client.client_stats,
+                    &solution,
+                    now,
+                    stats::RejectReason::classify(&code),
+                )
+                .await;
                 // the rejected solution has been found
                 return;
             } else {
@@ -279,11 +288,7 @@ impl StratumEventHandler {
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                stats::account_accepted_solution(&self.client.client_stats, &solution, now).await;
                 warn!(
                     "Stratum: the solution #{} precedes rejected solution #{}!",
                     seq_num, error_msg.seq_num
@@ -407,7 +412,7 @@ where
     S: FrameSink,
     //    S: Sink<<Framing as ii_wire::Framing>::Tx, Error = E> + std::marker::Unpin + std::fmt::Debug
     //    + 'static,
-    //    E: failure::Fail + std::marker::Unpin + 'static
+    //    E: std::error::Error + std::marker::Unpin + 'static
 {
     fn new(client: Arc<StratumClient>, connection_tx: S) -> Self {
         Self {
@@ -550,22 +555,26 @@ impl StratumConnectionHandler {
     }
 
     async fn connect(self) -> error::Result<v1::Framed> {
-        let socket_addr = self
-            .client
-            .connection_details
-            .get_host_and_port()
-            .to_socket_addrs()
-            .context("Invalid server address")?
-            // TODO: this is not correct as it always only attempts to ever connect to the first
-            //  IP address from the resolved set
-            .next()
-            .ok_or("Cannot resolve any IP address")?;
-
-        let connection = Connection::<v1::Framing>::connect(&socket_addr)
-            .await
+        let endpoints = &self.client.connection_details.endpoints;
+        let endpoint = endpoints.select().await;
+        let addr =
+            ii_wire::Address::from_str(endpoint.as_str()).context("Invalid server address")?;
+        // The host is resolved again on every reconnection and all its addresses are tried
+        let start = time::Instant::now();
+        let stream = addr
+            .connect_timeout(StratumClient::CONNECTION_TIMEOUT)
+            .await;
+        match &stream {
+            Ok(_) => endpoints.account_success(&endpoint, start.elapsed()),
+            Err(_) => endpoints.account_failure(&endpoint),
+        }
+        let stream = stream
+            .map_err(error::Error::from)
             .context("Cannot connect to stratum server")?;
+        // The connection is still usable without keepalive
+        let _ = ii_wire::TcpKeepalive::default().apply(&stream);
 
-        Ok(connection.into_inner())
+        Ok(Connection::<v1::Framing>::new(stream).into_inner())
     }
 
     /// Starts mining session and provides the initial target negotiated by the upstream endpoint
@@ -646,6 +655,8 @@ pub struct StratumClient {
 
 impl StratumClient {
     const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    /// Upper bound for the whole connection including all resolved addresses
+    const CONNECT_TOTAL_TIMEOUT: time::Duration = time::Duration::from_secs(20);
     const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(60);
     const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
 
@@ -746,16 +757,35 @@ impl StratumClient {
         }
     }
 
+    /// Wait before the next connection attempt when the previous ones failed so that a dead
+    /// pool doesn't make the client spin
+    async fn wait_for_retry(&self) {
+        let retries = self.status.retries();
+        if retries > 0 {
+            let delay =
+                sync::backoff::Backoff::new(self.connection_details.retry_schedule).delay(retries);
+            info!(
+                "Stratum: retrying connection to {} (attempt #{}) in {:.1}s",
+                self.connection_details.get_host_and_port(),
+                retries,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
     async fn run(self: Arc<Self>) {
+        self.wait_for_retry().await;
         match StratumConnectionHandler::new(self.clone())
             .connect()
-            .timeout(Self::CONNECTION_TIMEOUT)
+            .timeout(Self::CONNECT_TOTAL_TIMEOUT)
             .await
         {
             Ok(Ok(v1_framed_connection)) => {
                 if self.status.initiate_running() {
                     let options = V2ToV1TranslationOptions {
                         try_enable_xnsub: self.connection_details.try_enable_xnsub(),
+                        extra_nonce2: self.connection_details.extranonce_partition.index,
                     };
                     let (translation_handler, v2_translation_rx, v2_translation_tx) =
                         TranslationHandler::new(v1_framed_connection, options);
@@ -818,6 +848,21 @@ struct TranslationHandler {
 
 impl TranslationHandler {
     const MAX_TRANSLATION_CHANNEL_SIZE: usize = 10;
+    /// Idle time after which the upstream is pinged, it has to be well below `EVENT_TIMEOUT`
+    const PING_INTERVAL: time::Duration = time::Duration::from_secs(20);
+    const PING_TIMEOUT: time::Duration = time::Duration::from_secs(20);
+    /// The translation allocates request IDs from zero so the ping uses the top of the range
+    const PING_ID: u32 = u32::MAX;
+
+    /// V1 has no standard ping request. Any response (including the 'unknown method' error)
+    /// proves that the pool is alive and it is consumed here without reaching the translation.
+    fn ping_frame() -> v1::Frame {
+        let ping = format!(
+            r#"{{"id":{},"method":"mining.ping","params":[]}}"#,
+            Self::PING_ID
+        );
+        v1::Frame::from_serialized_payload(ping.as_bytes().into())
+    }
 
     /// Builds the new translation handler and provides Tx/Rx communication ends
     fn new(
@@ -855,14 +900,18 @@ impl TranslationHandler {
     async fn run(mut self) -> error::Result<()> {
         //while !self.status.is_shutting_down() {
         info!("Starting V2->V1 translation handler");
+        let mut heartbeat = ii_wire::Heartbeat::new(Self::PING_INTERVAL, Self::PING_TIMEOUT);
         loop {
             select! {
                 // Receive V1 frame and translate it to V2 message
                 v1_frame = self.v1_conn.next().timeout(StratumClient::EVENT_TIMEOUT).fuse() => {
                     match v1_frame {
                         Ok(Some(v1_frame)) => {
+                            heartbeat.received();
                             let v1_msg = v1::build_message_from_frame(v1_frame?)?;
-                            v1_msg.accept(&mut self.translation).await;
+                            if v1_msg.header != Some(Self::PING_ID) {
+                                v1_msg.accept(&mut self.translation).await;
+                            }
                         }
                         Ok(None) | Err(_) => {
                             Err("Upstream V1 stratum connection dropped terminating translation")?;
@@ -901,6 +950,15 @@ impl TranslationHandler {
                         }
                     }
                 },
+                // Ping the upstream when the connection is idle
+                ping = heartbeat.tick().fuse() => {
+                    ping.context("Upstream V1 stratum connection doesn't respond")?;
+                    self.v1_conn
+                        .send(Self::ping_frame())
+                        .timeout(StratumClient::EVENT_TIMEOUT)
+                        .await
+                        .map_err(|_| "V1 send timeout")??;
+                },
             }
         }
     }
@@ -929,6 +987,10 @@ impl node::Client for StratumClient {
             .as_ref()
             .and_then(|job| job.upgrade().map(|job| job as Arc<dyn job::Bitcoin>))
     }
+
+    fn endpoints(&self) -> Option<Arc<endpoint::Selector>> {
+        Some(self.connection_details.endpoints.clone())
+    }
 }
 
 impl fmt::Display for StratumClient {
@@ -25,11 +25,14 @@
 
 use crate::api;
 use crate::backend;
+use crate::client;
 use crate::hal::{self, BackendConfig as _};
 use crate::hub;
+use crate::pipeline;
 use crate::stats;
 
-use ii_async_compat::tokio;
+use ii_async_compat::task;
+use ii_logging::macros::*;
 
 use std::sync::Arc;
 
@@ -37,27 +40,75 @@ pub async fn main<T: hal::Backend>(backend_config: T::Config, signature: String)
     let backend_registry = Arc::new(backend::Registry::new());
     // Get frontend specific settings from backend config
     let backend_info = backend_config.info();
+    let cgminer_compatibility = backend_config.cgminer_compatibility();
+    let websocket_origins = backend_config.websocket_origins();
+    let fee = backend_config.fee();
 
     // Initialize hub core which manages all resources
     let core = Arc::new(hub::Core::new(
         backend_config.midstate_count(),
+        backend_config.min_midstate_count(),
+        backend_config.version_mask(),
+        backend_config.ntime_roll(),
+        backend_config.midstate_compute(),
+        backend_config.selection_policy(),
+        backend_config.block_found(),
+        backend_config.pool_health(),
+        backend_config.share_watchdog(),
+        backend_config.events(),
+        backend_config.schedule(),
+        backend_config.telemetry(),
+        backend_config.debug(),
         &backend_registry,
         backend_info.clone(),
     ));
 
     // Create and initialize the backend
-    let frontend_config = core
+    let mut frontend_config = core
         .build_backend::<T>(backend_config)
         .await
         .expect("Backend initialization failed");
 
-    tokio::spawn(core.clone().run());
+    // Fee group is created after the backend has loaded regular groups because it cannot be the
+    // first one
+    if let Some(fee) = fee {
+        if let Err(e) = core
+            .get_client_manager()
+            .create_fee_group(&fee, backend_info.as_ref())
+            .await
+        {
+            error!("Cannot create fee group: {}", e);
+        }
+    }
+
+    task::spawn_named("core", core.clone().run());
+    task::spawn_named("pool health", core.pool_health.clone().run(core.clone()));
+    task::spawn_named("pool latency", client::endpoint::latency_task(core.clone()));
+    task::spawn_named(
+        "share watchdog",
+        core.share_watchdog
+            .clone()
+            .run(core.clone(), frontend_config.restart_handler.take()),
+    );
+    task::spawn_named("pipeline monitor", pipeline::monitor_task(core.clone()));
+    task::spawn_named("events", core.events.clone().run(core.clone()));
+    task::spawn_named("scheduler", core.scheduler.clone().run());
+    if let Some(telemetry) = core.telemetry.clone() {
+        task::spawn_named("telemetry", telemetry.run(core.clone()));
+    }
     // start statistics processing
-    tokio::spawn(stats::mining_task(
-        core.frontend.clone(),
-        T::DEFAULT_HASHRATE_INTERVAL,
-    ));
+    task::spawn_named(
+        "mining stats",
+        stats::mining_task(core.frontend.clone(), T::DEFAULT_HASHRATE_INTERVAL),
+    );
 
     // the bosminer is controlled with API which also controls when the miner will end
-    api::run(core, frontend_config, signature).await;
+    api::run(
+        core,
+        frontend_config,
+        cgminer_compatibility,
+        websocket_origins,
+        signature,
+    )
+    .await;
 }
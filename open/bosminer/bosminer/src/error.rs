@@ -28,75 +28,92 @@ pub use client::ErrorKind as Client;
 
 use ii_async_compat::prelude::*;
 
-use failure::{Backtrace, Context, Fail};
-use std::fmt::{self, Debug, Display};
+use thiserror::Error;
 
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
 use std::io;
 
+/// Underlying error that caused `Error`
+pub type Source = Box<dyn StdError + Send + Sync + 'static>;
+
+/// Error is a pair of `ErrorKind` that gives the context and an optional underlying error
+/// (available via `std::error::Error::source`) so that the whole chain is preserved
+#[derive(Debug)]
 pub struct Error {
-    inner: Context<ErrorKind>,
+    kind: ErrorKind,
+    source: Option<Source>,
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum ErrorKind {
     /// Standard input/output error
-    #[fail(display = "IO error: {}", _0)]
+    #[error("IO error: {0}")]
     Io(String),
 
     /// General error used for more specific input/output error
-    #[fail(display = "General error: {}", _0)]
+    #[error("General error: {0}")]
     General(String),
 
     /// Error generated by backend for selected target
-    #[fail(display = "Backend error: {}", _0)]
+    #[error("Backend error: {0}")]
     Backend(String),
 
     /// Error generated by backend for selected target
-    #[fail(display = "Stratum error: {}", _0)]
+    #[error("Stratum error: {0}")]
     Stratum(String),
 
     /// Error related to clients
-    #[fail(display = "Client error: {}", _0)]
+    #[error("Client error: {0}")]
     Client(Client),
 }
 
-/// Implement Fail trait instead of use Derive to get more control over custom type.
-/// The main advantage is customization of Context type which allows conversion of
-/// any error types to this custom error with general error kind by calling context
-/// method on any result type.
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
+impl Error {
+    /// Create error of `kind` caused by `source`
+    pub fn with_source<E>(kind: ErrorKind, source: E) -> Self
+    where
+        E: Into<Source>,
+    {
+        Self {
+            kind,
+            source: Some(source.into()),
+        }
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+    pub fn kind(&self) -> ErrorKind {
+        self.kind.clone()
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
+        Display::fmt(&self.kind, f)
     }
 }
 
-impl Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Debug::fmt(&self.inner, f)
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn StdError + 'static))
     }
 }
 
-impl Error {
-    pub fn kind(&self) -> ErrorKind {
-        self.inner.get_context().clone()
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, source: None }
     }
 }
 
-impl From<ErrorKind> for Error {
-    fn from(kind: ErrorKind) -> Self {
-        Self {
-            inner: Context::new(kind),
-        }
+impl From<&str> for ErrorKind {
+    fn from(msg: &str) -> Self {
+        ErrorKind::General(msg.to_string())
+    }
+}
+
+impl From<String> for ErrorKind {
+    fn from(msg: String) -> Self {
+        ErrorKind::General(msg)
     }
 }
 
@@ -108,7 +125,8 @@ impl From<ii_stratum::error::Error> for Error {
 
 impl From<ii_wire::AddressParseError> for Error {
     fn from(address_error: ii_wire::AddressParseError) -> Self {
-        ErrorKind::General(address_error.to_string()).into()
+        let msg = address_error.to_string();
+        Self::with_source(ErrorKind::General(msg), address_error)
     }
 }
 
@@ -124,12 +142,6 @@ impl From<Client> for Error {
     }
 }
 
-impl From<Context<ErrorKind>> for Error {
-    fn from(inner: Context<ErrorKind>) -> Self {
-        Self { inner }
-    }
-}
-
 impl From<&str> for Error {
     fn from(msg: &str) -> Self {
         ErrorKind::General(msg.to_string()).into()
@@ -145,97 +157,90 @@ impl From<String> for Error {
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         let msg = e.to_string();
-        Self {
-            inner: e.context(ErrorKind::Io(msg)),
-        }
+        Self::with_source(ErrorKind::Io(msg), e)
     }
 }
 
 impl From<futures::channel::mpsc::SendError> for Error {
     fn from(e: futures::channel::mpsc::SendError) -> Self {
         let msg = e.to_string();
-        Self {
-            inner: e.context(ErrorKind::Io(msg)),
-        }
+        Self::with_source(ErrorKind::Io(msg), e)
     }
 }
 
-impl From<Context<&str>> for Error {
-    fn from(context: Context<&str>) -> Self {
-        Self {
-            inner: context.map(|info| ErrorKind::General(info.to_string())),
-        }
-    }
-}
+/// Attach context (anything convertible to `ErrorKind`) to an error while keeping the original
+/// error as a source
+pub trait ResultExt<T, E> {
+    fn context<K>(self, kind: K) -> Result<T>
+    where
+        K: Into<ErrorKind>;
 
-impl From<Context<String>> for Error {
-    fn from(context: Context<String>) -> Self {
-        Self {
-            inner: context.map(|info| ErrorKind::General(info)),
-        }
-    }
+    fn with_context<F, K>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>;
 }
 
-pub trait ResultExt<T, E> {
-    fn context<D>(self, context: D) -> std::result::Result<T, Context<ErrorKind>>
+impl<T, E> ResultExt<T, E> for std::result::Result<T, E>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn context<K>(self, kind: K) -> Result<T>
     where
-        D: Display + Send + Sync + 'static;
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|e| Error::with_source(kind.into(), e))
+    }
 
-    fn with_context<F, D>(self, f: F) -> std::result::Result<T, Context<ErrorKind>>
+    fn with_context<F, K>(self, f: F) -> Result<T>
     where
-        F: FnOnce(&E) -> D,
-        D: Display + Send + Sync + 'static;
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|e| {
+            let kind = f(&e).into();
+            Error::with_source(kind, e)
+        })
+    }
 }
 
 pub mod backend {
     pub use super::ResultExt;
     use super::{Error, ErrorKind};
 
-    use failure::{Context, Fail};
-    use std::fmt::Display;
+    use std::error::Error as StdError;
 
-    pub fn from_error<T: Fail>(error: T) -> Error {
+    pub fn from_error<T>(error: T) -> Error
+    where
+        T: StdError + Send + Sync + 'static,
+    {
         let msg = error.to_string();
-        Error {
-            inner: error.context(ErrorKind::Backend(msg)),
-        }
+        Error::with_source(ErrorKind::Backend(msg), error)
     }
 
     pub fn from_error_kind<T: ToString>(kind: T) -> Error {
-        Error {
-            inner: Context::new(ErrorKind::Backend(kind.to_string())),
-        }
-    }
-
-    impl<T, E> ResultExt<T, E> for Result<T, E>
-    where
-        E: Fail,
-    {
-        fn context<D>(self, context: D) -> Result<T, Context<ErrorKind>>
-        where
-            D: Display + Send + Sync + 'static,
-        {
-            self.map_err(|failure| {
-                failure
-                    .context(context)
-                    .map(|info| ErrorKind::Backend(info.to_string()))
-            })
-        }
-
-        fn with_context<F, D>(self, f: F) -> Result<T, Context<ErrorKind>>
-        where
-            F: FnOnce(&E) -> D,
-            D: Display + Send + Sync + 'static,
-        {
-            self.map_err(|failure| {
-                let context = f(&failure);
-                failure
-                    .context(context)
-                    .map(|info| ErrorKind::Backend(info.to_string()))
-            })
-        }
+        ErrorKind::Backend(kind.to_string()).into()
     }
 }
 
 /// A specialized `Result` type bound to [`Error`].
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_context_keeps_source() {
+        let result: std::result::Result<(), io::Error> =
+            Err(io::Error::new(io::ErrorKind::Other, "broken pipe"));
+        let error = result.context("cannot send").unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::General("cannot send".to_string()));
+        assert_eq!(error.to_string(), "General error: cannot send");
+        assert_eq!(
+            error.source().expect("missing source").to_string(),
+            "broken pipe"
+        );
+    }
+}
@@ -0,0 +1,396 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Internal bus for miner lifecycle and fault events. Subsystems publish structured events which
+//! are logged, kept for the API and optionally passed to an external hook.
+
+use ii_logging::macros::*;
+
+use crate::hook;
+use crate::hub;
+
+use bosminer_config::EventsConfig;
+
+use ii_async_compat::tokio;
+use tokio::sync::broadcast;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// Number of the most recent events kept for the API
+pub const RECENT_EVENTS: usize = 100;
+
+/// Number of events buffered for each subscriber before it starts lagging
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    ChainStarted {
+        hashboard_idx: usize,
+    },
+    ChainStopped {
+        hashboard_idx: usize,
+    },
+    SensorFailed {
+        hashboard_idx: usize,
+        reason: String,
+    },
+    /// Broken chain is being restarted
+    ChainBroken {
+        hashboard_idx: usize,
+        reason: String,
+    },
+    PoolConnected {
+        url: String,
+    },
+    PoolDisconnected {
+        url: String,
+    },
+    ThermalShutdown {
+        reason: String,
+    },
+    /// Operating point of hash chain has been lowered due to hardware errors (frequency in MHz,
+    /// voltage in volts and ratio of hardware errors in percent)
+    ChainDerated {
+        hashboard_idx: usize,
+        frequency: f64,
+        voltage: f32,
+        error_rate: f64,
+    },
+    /// Derated hash chain has been stable and it has been probed upward
+    ChainUprated {
+        hashboard_idx: usize,
+        frequency: f64,
+        voltage: f32,
+    },
+    ProfileChanged {
+        profile: Option<String>,
+    },
+    MiningPaused,
+    MiningResumed,
+    /// Button on the front panel of the control board has been pressed
+    ButtonPressed {
+        button: String,
+    },
+    /// No share has been accepted by any pool while the miner is hashing (time in seconds)
+    NoAcceptedShares {
+        idle_time: u64,
+        action: String,
+    },
+}
+
+impl Kind {
+    /// Short machine readable identifier of the event kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ChainStarted { .. } => "chain_started",
+            Self::ChainStopped { .. } => "chain_stopped",
+            Self::SensorFailed { .. } => "sensor_failed",
+            Self::ChainBroken { .. } => "chain_broken",
+            Self::PoolConnected { .. } => "pool_connected",
+            Self::PoolDisconnected { .. } => "pool_disconnected",
+            Self::ThermalShutdown { .. } => "thermal_shutdown",
+            Self::ChainDerated { .. } => "chain_derated",
+            Self::ChainUprated { .. } => "chain_uprated",
+            Self::ProfileChanged { .. } => "profile_changed",
+            Self::MiningPaused => "mining_paused",
+            Self::MiningResumed => "mining_resumed",
+            Self::ButtonPressed { .. } => "button_pressed",
+            Self::NoAcceptedShares { .. } => "no_accepted_shares",
+        }
+    }
+
+    /// Fault events require attention of the operator
+    pub fn is_fault(&self) -> bool {
+        match self {
+            Self::SensorFailed { .. }
+            | Self::ChainBroken { .. }
+            | Self::ThermalShutdown { .. }
+            | Self::ChainDerated { .. }
+            | Self::NoAcceptedShares { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChainStarted { hashboard_idx } => write!(f, "Chain {} started", hashboard_idx),
+            Self::ChainStopped { hashboard_idx } => write!(f, "Chain {} stopped", hashboard_idx),
+            Self::SensorFailed {
+                hashboard_idx,
+                reason,
+            } => write!(f, "Chain {} sensor failed: {}", hashboard_idx, reason),
+            Self::ChainBroken {
+                hashboard_idx,
+                reason,
+            } => write!(f, "Chain {} broken: {}", hashboard_idx, reason),
+            Self::PoolConnected { url } => write!(f, "Pool '{}' connected", url),
+            Self::PoolDisconnected { url } => write!(f, "Pool '{}' disconnected", url),
+            Self::ThermalShutdown { reason } => write!(f, "Thermal shutdown: {}", reason),
+            Self::ChainDerated {
+                hashboard_idx,
+                frequency,
+                voltage,
+                error_rate,
+            } => write!(
+                f,
+                "Chain {} derated to {:.0} MHz and {:.2} V after {:.1} % of HW errors",
+                hashboard_idx, frequency, voltage, error_rate
+            ),
+            Self::ChainUprated {
+                hashboard_idx,
+                frequency,
+                voltage,
+            } => write!(
+                f,
+                "Chain {} probed upward to {:.0} MHz and {:.2} V",
+                hashboard_idx, frequency, voltage
+            ),
+            Self::ProfileChanged {
+                profile: Some(profile),
+            } => write!(f, "Profile '{}' activated", profile),
+            Self::ProfileChanged { profile: None } => write!(f, "Default profile activated"),
+            Self::MiningPaused => write!(f, "Mining paused"),
+            Self::MiningResumed => write!(f, "Mining resumed"),
+            Self::ButtonPressed { button } => write!(f, "Button '{}' pressed", button),
+            Self::NoAcceptedShares { idle_time, action } => write!(
+                f,
+                "No share accepted for {} s while hashing, {}",
+                idle_time, action
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub time: time::SystemTime,
+    pub kind: Kind,
+}
+
+impl Event {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            time: time::SystemTime::now(),
+            kind,
+        }
+    }
+
+    pub fn unix_time(&self) -> u64 {
+        hook::unix_time(self.time)
+    }
+}
+
+#[derive(Debug)]
+pub struct Bus {
+    sender: broadcast::Sender<Event>,
+    recent: StdMutex<VecDeque<Event>>,
+    exec: Option<String>,
+    faults_only: bool,
+}
+
+impl Bus {
+    pub fn new(config: EventsConfig) -> Self {
+        // Throwaway the receiver as subscribers are created with the `subscribe()` method
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            recent: StdMutex::new(VecDeque::with_capacity(RECENT_EVENTS)),
+            exec: config.exec,
+            faults_only: config.faults_only.unwrap_or(false),
+        }
+    }
+
+    /// Publish event to all subscribers. Publishing never blocks and it is not an error when
+    /// nobody listens.
+    pub fn publish(&self, kind: Kind) {
+        let event = Event::new(kind);
+        {
+            let mut recent = self.recent.lock().expect("BUG: lock recent events");
+            if recent.len() == RECENT_EVENTS {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        let _ = self.sender.send(event);
+    }
+
+    #[inline]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// The most recent events starting with the oldest one
+    pub fn recent(&self) -> Vec<Event> {
+        self.recent
+            .lock()
+            .expect("BUG: lock recent events")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn log(event: &Event) {
+        if event.kind.is_fault() {
+            error!("Event: {}", event.kind);
+        } else {
+            info!("Event: {}", event.kind);
+        }
+    }
+
+    fn exec(command: String, event: Event) {
+        let fault = if event.kind.is_fault() { "1" } else { "0" };
+        hook::spawn(
+            "events",
+            command,
+            vec![
+                ("BOSMINER_EVENT_TIME", event.unix_time().to_string()),
+                ("BOSMINER_EVENT_CODE", event.kind.code().to_string()),
+                ("BOSMINER_EVENT_FAULT", fault.to_string()),
+                ("BOSMINER_EVENT_MESSAGE", event.kind.to_string()),
+            ],
+        );
+    }
+
+    /// Subscriber which logs all events and passes them to the configured hook
+    async fn dispatch_task(self: Arc<Self>, mut receiver: broadcast::Receiver<Event>) {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    Self::log(&event);
+                    if let Some(command) = &self.exec {
+                        if !self.faults_only || event.kind.is_fault() {
+                            Self::exec(command.clone(), event);
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Event subscriber missed {} event(s)", count)
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Translate change of pool connection state into event. Newly added pools which are not
+    /// connected yet are not reported.
+    fn pool_transition(previous: Option<bool>, running: bool) -> Option<bool> {
+        match (previous, running) {
+            (Some(false), true) | (None, true) => Some(true),
+            (Some(true), false) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Publish events about pools connecting and disconnecting
+    async fn pool_task(self: Arc<Self>, core: Arc<hub::Core>) {
+        let client_manager = core.get_client_manager();
+        let mut status_receiver = client_manager.subscribe_to_clients_status_changes();
+        // Connection state of pools indexed by address of client handle
+        let mut connected = HashMap::new();
+        loop {
+            let mut keys = Vec::new();
+            for group in client_manager.get_groups().await {
+                for client in group.get_clients().await {
+                    let key = Arc::as_ptr(&client) as usize;
+                    let running = client.is_running();
+                    keys.push(key);
+                    if let Some(running) =
+                        Self::pool_transition(connected.insert(key, running), running)
+                    {
+                        let url = client.descriptor().await.get_url(true, true, true);
+                        self.publish(if running {
+                            Kind::PoolConnected { url }
+                        } else {
+                            Kind::PoolDisconnected { url }
+                        });
+                    }
+                }
+            }
+            // Forget removed clients
+            connected.retain(|key, _| keys.contains(key));
+
+            if status_receiver.wait_for_event().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>, core: Arc<hub::Core>) {
+        tokio::spawn(self.clone().dispatch_task(self.subscribe()));
+        self.pool_task(core).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recent_events() {
+        let bus = Bus::new(Default::default());
+        let mut receiver = bus.subscribe();
+
+        for hashboard_idx in 0..RECENT_EVENTS + 2 {
+            bus.publish(Kind::ChainStarted { hashboard_idx });
+        }
+        let recent = bus.recent();
+        assert_eq!(recent.len(), RECENT_EVENTS);
+        // the oldest events are dropped
+        assert_eq!(recent[0].kind, Kind::ChainStarted { hashboard_idx: 2 });
+
+        // subscriber sees only the last events which fit into the channel
+        match receiver.try_recv() {
+            Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(
+            receiver.try_recv().expect("BUG: missing event").kind,
+            Kind::ChainStarted {
+                hashboard_idx: RECENT_EVENTS + 2 - CHANNEL_CAPACITY
+            }
+        );
+    }
+
+    #[test]
+    fn test_pool_transition() {
+        assert_eq!(Bus::pool_transition(None, false), None);
+        assert_eq!(Bus::pool_transition(None, true), Some(true));
+        assert_eq!(Bus::pool_transition(Some(false), true), Some(true));
+        assert_eq!(Bus::pool_transition(Some(true), true), None);
+        assert_eq!(Bus::pool_transition(Some(true), false), Some(false));
+        assert_eq!(Bus::pool_transition(Some(false), false), None);
+    }
+
+    #[test]
+    fn test_fault_events() {
+        assert!(Kind::ThermalShutdown {
+            reason: "temperature above DANGEROUS".into()
+        }
+        .is_fault());
+        assert!(!Kind::PoolConnected { url: "".into() }.is_fault());
+    }
+}
@@ -22,12 +22,17 @@
 
 use crate::client;
 use crate::error;
+use crate::events;
 use crate::node;
+use crate::schedule;
 use crate::work;
 
-use ii_cgminer_api::command;
+use ii_cgminer_api::{command, support};
 use ii_stratum::v2::types::DeviceInfo;
 
+use futures::future::BoxFuture;
+use ii_async_compat::futures;
+
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -46,6 +51,11 @@ pub trait BackendSolution: Debug + Send + Sync {
     /// Backend target used for finding this nonce
     /// This information is used mainly for detecting HW errors
     fn target(&self) -> &ii_bitcoin::Target;
+    /// Number of seconds the backend rolled ntime of the work to find this nonce (it never
+    /// exceeds `work::Assignment::ntime_roll`)
+    fn ntime_offset(&self) -> u32 {
+        0
+    }
 }
 
 /// Enum returned from `Backend::create` is intended for choosing type of backend root node (work
@@ -104,16 +114,90 @@ impl From<BackendInfo> for DeviceInfo {
 pub trait BackendConfig: Debug + Send + Sync {
     /// Number of midstates that backend is able to solve at once
     fn midstate_count(&self) -> usize;
+    /// Minimal number of midstates in work the backend is able to solve. Jobs which don't allow
+    /// rolling enough version bits for `midstate_count` get work with fewer midstates, but jobs
+    /// which cannot provide this number of midstates are not mined by the backend at all. It is
+    /// capped at the current `midstate_count`.
+    fn min_midstate_count(&self) -> usize {
+        1
+    }
+    /// Block version bits the backend needs to roll (AsicBoost). The work engine rolls the
+    /// whole BIP320 range regardless of midstate count so it is required by default.
+    fn version_mask(&self) -> u32 {
+        ii_bitcoin::BIP320_VERSION_MASK
+    }
+    /// Maximal number of seconds the backend is able to roll ntime of one work in hardware
+    fn ntime_roll(&self) -> u32 {
+        0
+    }
+    /// Implementation of midstate computation used by work engines
+    fn midstate_compute(&self) -> work::midstate::DynCompute {
+        work::midstate::default()
+    }
+    /// Policy for selecting a client within a group that doesn't specify its own
+    fn selection_policy(&self) -> work::policy::DynSelectionPolicy {
+        Arc::new(work::policy::PrimaryWithBackup)
+    }
+    /// Handling of found blocks
+    fn block_found(&self) -> bosminer_config::BlockFoundConfig {
+        Default::default()
+    }
+    /// Alerting on pools with low accepted hashrate
+    fn pool_health(&self) -> bosminer_config::PoolHealthConfig {
+        Default::default()
+    }
+    /// Handling of miner lifecycle and fault events
+    fn events(&self) -> bosminer_config::EventsConfig {
+        Default::default()
+    }
+    /// Share of generated work directed to a designated fee pool
+    fn fee(&self) -> Option<bosminer_config::FeeConfig> {
+        None
+    }
+    /// Time-of-day and day-of-week mining profiles
+    fn schedule(&self) -> bosminer_config::ScheduleConfig {
+        Default::default()
+    }
+    /// Miner status submitted to Stratum V2 pools
+    fn telemetry(&self) -> bosminer_config::TelemetryConfig {
+        Default::default()
+    }
+    /// Restarting of the miner when no shares are accepted
+    fn share_watchdog(&self) -> bosminer_config::ShareWatchdogConfig {
+        Default::default()
+    }
+    /// Options for troubleshooting of the miner
+    fn debug(&self) -> bosminer_config::DebugConfig {
+        Default::default()
+    }
+    /// How strictly the CGMiner API follows the original CGMiner
+    fn cgminer_compatibility(&self) -> support::Compatibility {
+        Default::default()
+    }
+    /// Origins of web pages allowed to use the WebSocket API besides pages served by the miner
+    fn websocket_origins(&self) -> Vec<String> {
+        vec![]
+    }
     /// Pass client manager to backend to get access to its functionality
     fn set_client_manager(&mut self, _client_manager: client::Manager) {}
+    /// Pass event bus to backend so it can publish its lifecycle and fault events
+    fn set_event_bus(&mut self, _event_bus: Arc<events::Bus>) {}
+    /// Pass scheduler to backend so it can apply the active mining profile
+    fn set_scheduler(&mut self, _scheduler: Arc<schedule::Scheduler>) {}
     /// Optional information about backend
     fn info(&self) -> Option<BackendInfo> {
         None
     }
 }
 
+/// Graceful termination of the whole miner with the passed reason. The miner is expected to be
+/// started again by the service manager.
+pub type RestartHandler = Box<dyn Fn(String) -> BoxFuture<'static, ()> + Send + Sync>;
+
 pub struct FrontendConfig {
     pub cgminer_custom_commands: Option<command::Map>,
+    /// Backend specific restart of the miner, `None` when the backend does not support it
+    pub restart_handler: Option<RestartHandler>,
 }
 
 /// Minimal interface for running compatible backend with BOSminer crate
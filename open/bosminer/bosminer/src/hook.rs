@@ -0,0 +1,77 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Execution of external commands (hooks) configured by the operator. Details of the notification
+//! are passed to the command in environment variables.
+
+use ii_logging::macros::*;
+
+use ii_async_compat::tokio;
+
+use std::time;
+
+/// Convert time into seconds since UNIX epoch as it is passed to hooks
+pub fn unix_time(time: time::SystemTime) -> u64 {
+    time.duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Run `command` by shell with additional environment variables `envs` and wait for it. The `name`
+/// describes the hook in log messages.
+pub async fn exec(name: &str, command: &str, envs: Vec<(&'static str, String)>) {
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(envs)
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Hook '{}' of {} failed: {}", command, name, status),
+        Err(e) => error!("Cannot execute hook '{}' of {}: {}", command, name, e),
+    }
+}
+
+/// Run the hook in background so that the caller is never delayed by it
+pub fn spawn(name: &'static str, command: String, envs: Vec<(&'static str, String)>) {
+    tokio::spawn(async move { exec(name, &command, envs).await });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unix_time() {
+        assert_eq!(unix_time(time::UNIX_EPOCH), 0);
+        assert_eq!(
+            unix_time(time::UNIX_EPOCH + time::Duration::from_millis(1_600_000_000_999)),
+            1_600_000_000
+        );
+        // time before the epoch cannot be represented
+        assert_eq!(
+            unix_time(time::UNIX_EPOCH - time::Duration::from_secs(1)),
+            0
+        );
+    }
+}
@@ -26,10 +26,16 @@
 use ii_logging::macros::*;
 
 use crate::backend;
+use crate::blocks;
 use crate::client;
 use crate::error;
+use crate::events;
 use crate::hal::{self, BackendConfig};
 use crate::node;
+use crate::pipeline;
+use crate::pool_health;
+use crate::schedule;
+use crate::share_watchdog;
 use crate::work;
 
 use futures::channel::mpsc;
@@ -37,7 +43,9 @@ use futures::lock::Mutex;
 use futures::stream::StreamExt;
 use ii_async_compat::{futures, tokio};
 
+use std::path::PathBuf;
 use std::sync::{Arc, Weak};
+use std::time;
 
 /// Handle external events. Currently it is used only wor handling exhausted work from work engine.
 /// It usually signals some serious problem in backend.
@@ -53,22 +61,30 @@ impl work::ExhaustedHandler for EventHandler {
 /// Responsible for delivering work solution to the client from which the work has been generated
 struct SolutionRouter {
     job_executor: Arc<client::JobExecutor>,
+    found_blocks: Arc<blocks::Log>,
+    pipeline_stats: Arc<pipeline::Stats>,
     solution_receiver: mpsc::UnboundedReceiver<work::Solution>,
 }
 
 impl SolutionRouter {
     fn new(
         job_executor: Arc<client::JobExecutor>,
+        found_blocks: Arc<blocks::Log>,
+        pipeline_stats: Arc<pipeline::Stats>,
         solution_receiver: mpsc::UnboundedReceiver<work::Solution>,
     ) -> Self {
         Self {
             job_executor,
+            found_blocks,
+            pipeline_stats,
             solution_receiver,
         }
     }
 
     async fn run(mut self) {
         while let Some(solution) = self.solution_receiver.next().await {
+            self.pipeline_stats.solution_received();
+            let block = blocks::Log::detect(&solution);
             // NOTE: all solutions targeting to removed clients are discarded
             if let Some(solution_sender) = self.job_executor.get_solution_sender(&solution).await {
                 solution_sender
@@ -77,6 +93,10 @@ impl SolutionRouter {
             } else {
                 warn!("Hub: solution has been discarded because client does not exist anymore");
             }
+            // Every block is recorded even when its client is not able to submit it
+            if let Some(block) = block {
+                self.found_blocks.record(block);
+            }
         }
     }
 }
@@ -86,6 +106,20 @@ pub struct Core {
     // NOTE: Weak reference must be released first!
     backend_registry: Weak<backend::Registry>,
     pub frontend: Arc<crate::Frontend>,
+    /// Solutions meeting the network target
+    pub found_blocks: Arc<blocks::Log>,
+    /// Scoring of pools by their accepted hashrate
+    pub pool_health: Arc<pool_health::Monitor>,
+    /// Restarting of the miner when no shares are accepted
+    pub share_watchdog: Arc<share_watchdog::Watchdog>,
+    /// Bus for miner lifecycle and fault events
+    pub events: Arc<events::Bus>,
+    /// Selection of scheduled mining profile
+    pub scheduler: Arc<schedule::Scheduler>,
+    /// Instrumentation of the work pipeline between clients and backends
+    pub pipeline: Arc<pipeline::Stats>,
+    /// Publisher of miner status to Stratum V2 pools, `None` when telemetry is disabled
+    pub telemetry: Option<Arc<client::stratum_v2::telemetry::Publisher>>,
     job_executor: Arc<client::JobExecutor>,
     engine_receiver: work::EngineReceiver,
     solution_sender: mpsc::UnboundedSender<work::Solution>,
@@ -98,15 +132,53 @@ pub struct Core {
 impl Core {
     pub fn new(
         midstate_count: usize,
+        min_midstate_count: usize,
+        version_mask: u32,
+        ntime_roll: u32,
+        midstate_compute: work::midstate::DynCompute,
+        selection_policy: work::policy::DynSelectionPolicy,
+        block_found: bosminer_config::BlockFoundConfig,
+        pool_health: bosminer_config::PoolHealthConfig,
+        share_watchdog: bosminer_config::ShareWatchdogConfig,
+        events: bosminer_config::EventsConfig,
+        schedule: bosminer_config::ScheduleConfig,
+        telemetry: bosminer_config::TelemetryConfig,
+        debug: bosminer_config::DebugConfig,
         backend_registry: &Arc<backend::Registry>,
         backend_info: Option<hal::BackendInfo>,
     ) -> Self {
         let frontend = Arc::new(crate::Frontend::new());
+        let found_blocks = Arc::new(blocks::Log::new(block_found));
+        let pool_health = Arc::new(pool_health::Monitor::new(pool_health));
+        let share_watchdog = Arc::new(share_watchdog::Watchdog::new(share_watchdog));
+        let events = Arc::new(events::Bus::new(events));
+        let scheduler = Arc::new(schedule::Scheduler::new(schedule, events.clone()));
+        let pipeline = Arc::new(pipeline::Stats::new());
+        let telemetry = if telemetry.enabled() {
+            let interval = telemetry.interval().unwrap_or_else(|e| {
+                error!("Telemetry: {}, using default interval", e);
+                time::Duration::from_secs_f64(bosminer_config::TelemetryConfig::DEFAULT_INTERVAL)
+            });
+            Some(Arc::new(client::stratum_v2::telemetry::Publisher::new(
+                interval,
+            )))
+        } else {
+            None
+        };
 
         let (engine_sender, engine_receiver) = work::engine_channel(EventHandler);
         let (solution_sender, solution_receiver) = mpsc::unbounded();
 
-        let client_manager = client::Manager::new(midstate_count);
+        let client_manager = client::Manager::new(client::ManagerSettings {
+            midstate_count,
+            min_midstate_count,
+            version_mask,
+            ntime_roll,
+            midstate_compute,
+            selection_policy,
+            telemetry: telemetry.clone(),
+            stratum_record_dir: debug.stratum_record_dir.map(PathBuf::from),
+        });
         let job_executor = Arc::new(client::JobExecutor::new(
             frontend.clone(),
             engine_sender,
@@ -117,10 +189,22 @@ impl Core {
             backend_info,
             backend_registry: Arc::downgrade(backend_registry),
             frontend,
+            found_blocks: found_blocks.clone(),
+            pool_health,
+            share_watchdog,
+            events,
+            scheduler,
+            pipeline: pipeline.clone(),
+            telemetry,
             job_executor: job_executor.clone(),
             engine_receiver,
             solution_sender,
-            solution_router: Mutex::new(Some(SolutionRouter::new(job_executor, solution_receiver))),
+            solution_router: Mutex::new(Some(SolutionRouter::new(
+                job_executor,
+                found_blocks,
+                pipeline,
+                solution_receiver,
+            ))),
             client_manager,
         }
     }
@@ -139,9 +223,12 @@ impl Core {
                 .expect("BUG: missing backend registry"),
             self.engine_receiver.clone(),
             self.solution_sender.clone(),
+            self.pipeline.clone(),
         );
 
         backend_config.set_client_manager(self.get_client_manager().clone());
+        backend_config.set_event_bus(self.events.clone());
+        backend_config.set_scheduler(self.scheduler.clone());
         // call backend create to determine the preferred hierarchy
         match T::create(&mut backend_config) {
             // the generic tree hierarchy where the backend consists of multiple devices
@@ -197,10 +284,54 @@ impl Core {
         }
     }
 
+    /// Direct child nodes of `work_hub` in the backend hierarchy
+    pub async fn get_children(
+        &self,
+        work_hub: &Arc<dyn node::WorkSolver>,
+    ) -> Vec<Arc<dyn node::WorkSolver>> {
+        match self.backend_registry.upgrade() {
+            Some(backend_registry) => backend_registry.get_children(work_hub).await,
+            None => vec![],
+        }
+    }
+
+    pub async fn is_work_hub(&self, node: &Arc<dyn node::WorkSolver>) -> bool {
+        match self.backend_registry.upgrade() {
+            Some(backend_registry) => backend_registry.is_work_hub(node).await,
+            None => false,
+        }
+    }
+
     pub fn get_client_manager(&self) -> &client::Manager {
         &self.client_manager
     }
 
+    /// Stop work generation while keeping connections to pools alive. Backends are left without
+    /// work so their hash chains idle (but stay initialized) until mining is resumed. Returns
+    /// `false` when mining has been already paused.
+    pub async fn pause(&self) -> bool {
+        let paused = self.job_executor.pause().await;
+        if paused {
+            self.events.publish(events::Kind::MiningPaused);
+        }
+        paused
+    }
+
+    /// Resume work generation with the most recent job. Returns `false` when mining has not
+    /// been paused.
+    pub async fn resume(&self) -> bool {
+        let resumed = self.job_executor.resume().await;
+        if resumed {
+            self.events.publish(events::Kind::MiningResumed);
+        }
+        resumed
+    }
+
+    #[inline]
+    pub async fn is_paused(&self) -> bool {
+        self.job_executor.is_paused().await
+    }
+
     pub async fn run(self: Arc<Self>) {
         let solution_router = self
             .solution_router
@@ -239,6 +370,7 @@ pub mod test {
                 Arc::new(backend::Registry::new()),
                 engine_receiver,
                 solution_sender,
+                Arc::new(pipeline::Stats::new()),
             ),
         )
     }
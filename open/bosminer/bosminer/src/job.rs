@@ -150,6 +150,32 @@ impl Sender {
         }
     }
 
+    /// Send multiple jobs of one client which are mined in parallel (e.g. jobs of multiple
+    /// mining channels). Jobs with invalid attributes are skipped.
+    pub fn send_all(&self, jobs: Vec<Arc<dyn job::Bitcoin>>) {
+        let mut valid_jobs = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            let origin = job.origin().upgrade();
+            if !Self::job_sanity_check(&job, &origin) {
+                origin.map(|origin| origin.client_stats().invalid_jobs().inc());
+                continue;
+            }
+            match origin {
+                Some(origin) => origin.client_stats().valid_jobs().inc(),
+                // Origin has been removed and no one will receive any solution
+                None => continue,
+            }
+            valid_jobs.push(job);
+        }
+
+        if valid_jobs.is_empty() {
+            info!("--- discarding jobs ---");
+        } else {
+            info!("--- broadcasting {} new jobs ---", valid_jobs.len());
+            self.engine_sender.broadcast_jobs(valid_jobs);
+        }
+    }
+
     #[inline]
     pub fn invalidate(&self) {
         self.engine_sender.invalidate();
@@ -190,6 +216,15 @@ impl SolutionReceiver {
         while let Some(solution) = self.solution_channel.next().await {
             let path = solution.path();
             let time = solution.timestamp();
+            if !solution.has_valid_ntime() {
+                warn!(
+                    "Solution with ntime rolled by {} seconds out of allowed range",
+                    solution.ntime_offset()
+                );
+                stats::account_error_backend_diff(&path, &solution.backend_target(), time).await;
+                // skip submitting the solution as this is a backend error
+                continue;
+            }
             let hash = solution.hash();
             let job_target = solution.job_target();
 
@@ -26,14 +26,21 @@
 
 mod api;
 pub mod backend;
+pub mod blocks;
 pub mod client;
 pub mod config;
 pub mod entry;
 pub mod error;
+pub mod events;
 pub mod hal;
+pub mod hook;
 pub mod hub;
 pub mod job;
 pub mod node;
+pub mod pipeline;
+pub mod pool_health;
+pub mod schedule;
+pub mod share_watchdog;
 pub mod stats;
 pub mod sync;
 pub mod version;
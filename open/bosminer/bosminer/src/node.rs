@@ -20,6 +20,7 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use crate::client;
 use crate::job;
 use crate::stats;
 use crate::sync;
@@ -60,6 +61,14 @@ pub trait Client: Info + ClientStats {
     async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>>;
     /// FIXME: Do not allow dynamic descriptor changes
     fn change_connection_details(&self, _descriptor: &bosminer_config::ClientDescriptor) {}
+    /// Number of solutions waiting for submission or for acknowledgement from the remote server
+    fn pending_solutions(&self) -> usize {
+        0
+    }
+    /// Endpoints of the remote server when the client selects between them
+    fn endpoints(&self) -> Option<Arc<client::endpoint::Selector>> {
+        None
+    }
 }
 
 pub trait ClientStats: Stats {
@@ -99,6 +108,10 @@ pub trait WorkSolver: Info + WorkSolverStats {
     fn get_id(&self) -> Option<usize> {
         None
     }
+    /// Optionally return serial number of the hardware (e.g. hashboard serial number)
+    fn get_serial(&self) -> Option<String> {
+        None
+    }
     /// Return nominal/expected hashrate in hashes per second
     async fn get_nominal_hashrate(&self) -> Option<ii_bitcoin::HashesUnit>;
 }
@@ -0,0 +1,341 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Instrumentation of the work pipeline between clients (frontend) and backends. Counters are
+//! kept for each stage where the pipeline can stall: work generators waiting for new work,
+//! backend waiting for room in its work FIFO, backend losing solutions due to full solution
+//! FIFO and solutions piling up before they are taken by clients. Sustained backpressure is
+//! reported in the log so throughput problems can be localized between frontend and backend.
+
+use ii_logging::macros::*;
+
+use crate::hub;
+
+use ii_async_compat::tokio;
+use tokio::time::sleep;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time;
+
+/// How often the backpressure is evaluated
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+/// Number of solutions waiting for clients above which the solution channel is considered to be
+/// saturated
+pub const SOLUTION_CHANNEL_THRESHOLD: usize = 256;
+
+/// Percentage of time work generators may wait for new work before it is reported
+pub const GENERATOR_WAIT_THRESHOLD: f64 = 50.0;
+
+/// Snapshot of pipeline counters
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    /// Number of work generators (one for each work solver)
+    pub generators: usize,
+    /// Total time work generators waited for new work
+    pub generator_wait: time::Duration,
+    /// Number of times work generators had to wait for new work
+    pub generator_waits: u64,
+    /// Number of times backends had to wait for room in work TX FIFO
+    pub tx_fifo_full: u64,
+    /// Number of times solution RX FIFO has been found full (solutions may have been lost)
+    pub rx_fifo_overflow: u64,
+    /// Solutions found by backends which haven't been taken by clients yet
+    pub pending_solutions: usize,
+    /// The highest number of pending solutions
+    pub max_pending_solutions: usize,
+    /// Number of times pending solutions exceeded `SOLUTION_CHANNEL_THRESHOLD`
+    pub solution_channel_saturated: u64,
+    /// Works generated ahead of time which haven't been taken by backends yet
+    pub prefetch_depth: usize,
+    /// Sum of adaptive depths of all prefetch buffers
+    pub prefetch_target_depth: usize,
+    /// Number of buffered works discarded after job change
+    pub prefetch_stale: u64,
+    /// Number of times backends found prefetch buffer empty
+    pub prefetch_underruns: u64,
+}
+
+impl Snapshot {
+    /// Percentage of time work generators waited for new work since the `previous` snapshot
+    /// taken `interval` ago
+    pub fn generator_wait_percent(&self, previous: &Self, interval: time::Duration) -> f64 {
+        if self.generators == 0 || interval.as_secs_f64() <= 0.0 {
+            return 0.0;
+        }
+        let wait = self.generator_wait.as_secs_f64() - previous.generator_wait.as_secs_f64();
+        wait / (interval.as_secs_f64() * self.generators as f64) * 100.0
+    }
+}
+
+/// Lock-free pipeline counters shared by all work generators and solution senders
+#[derive(Debug, Default)]
+pub struct Stats {
+    generators: AtomicUsize,
+    generator_wait_us: AtomicU64,
+    generator_waits: AtomicU64,
+    tx_fifo_full: AtomicU64,
+    rx_fifo_overflow: AtomicU64,
+    pending_solutions: AtomicUsize,
+    max_pending_solutions: AtomicUsize,
+    solution_channel_saturated: AtomicU64,
+    prefetch_depth: AtomicUsize,
+    prefetch_target_depth: AtomicUsize,
+    prefetch_stale: AtomicU64,
+    prefetch_underruns: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register a new work generator and return its index
+    pub(crate) fn add_generator(&self) -> usize {
+        self.generators.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Account time a work generator waited for new work
+    pub fn account_generator_wait(&self, wait: time::Duration) {
+        self.generator_wait_us
+            .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+        self.generator_waits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account backend waiting for room in its work TX FIFO
+    pub fn account_tx_fifo_full(&self) {
+        self.tx_fifo_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account full solution RX FIFO in backend
+    pub fn account_rx_fifo_overflow(&self) {
+        self.rx_fifo_overflow.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account solution sent by backend to the solution channel
+    pub(crate) fn solution_sent(&self) {
+        let pending = self.pending_solutions.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_pending_solutions
+            .fetch_max(pending, Ordering::Relaxed);
+        if pending == SOLUTION_CHANNEL_THRESHOLD + 1 {
+            self.solution_channel_saturated
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Account solution taken from the solution channel
+    pub(crate) fn solution_received(&self) {
+        self.pending_solutions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Account works pushed to a prefetch buffer
+    pub(crate) fn account_prefetch_pushed(&self, count: usize) {
+        self.prefetch_depth.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Account works taken from a prefetch buffer
+    pub(crate) fn account_prefetch_taken(&self, count: usize) {
+        self.prefetch_depth.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /// Account stale works discarded from a prefetch buffer
+    pub(crate) fn account_prefetch_stale(&self, count: usize) {
+        self.account_prefetch_taken(count);
+        self.prefetch_stale
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Account change of adaptive depth of a prefetch buffer
+    pub(crate) fn account_prefetch_target(&self, previous: usize, current: usize) {
+        self.prefetch_target_depth
+            .fetch_add(current, Ordering::Relaxed);
+        self.prefetch_target_depth
+            .fetch_sub(previous, Ordering::Relaxed);
+    }
+
+    /// Account backend finding prefetch buffer empty
+    pub(crate) fn account_prefetch_underrun(&self) {
+        self.prefetch_underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            generators: self.generators.load(Ordering::Relaxed),
+            generator_wait: time::Duration::from_micros(
+                self.generator_wait_us.load(Ordering::Relaxed),
+            ),
+            generator_waits: self.generator_waits.load(Ordering::Relaxed),
+            tx_fifo_full: self.tx_fifo_full.load(Ordering::Relaxed),
+            rx_fifo_overflow: self.rx_fifo_overflow.load(Ordering::Relaxed),
+            pending_solutions: self.pending_solutions.load(Ordering::Relaxed),
+            max_pending_solutions: self.max_pending_solutions.load(Ordering::Relaxed),
+            solution_channel_saturated: self.solution_channel_saturated.load(Ordering::Relaxed),
+            prefetch_depth: self.prefetch_depth.load(Ordering::Relaxed),
+            prefetch_target_depth: self.prefetch_target_depth.load(Ordering::Relaxed),
+            prefetch_stale: self.prefetch_stale.load(Ordering::Relaxed),
+            prefetch_underruns: self.prefetch_underruns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Backpressure detected in one check interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backpressure {
+    /// Work generators waited for new work for the given percentage of time
+    GeneratorStarved(f64),
+    /// Backends lost solutions due to full RX FIFO
+    RxFifoOverflow(u64),
+    /// Clients don't take solutions fast enough
+    SolutionChannelSaturated(usize),
+}
+
+fn evaluate(
+    previous: &Snapshot,
+    current: &Snapshot,
+    interval: time::Duration,
+) -> Vec<Backpressure> {
+    let mut result = vec![];
+    let wait_percent = current.generator_wait_percent(previous, interval);
+    if wait_percent > GENERATOR_WAIT_THRESHOLD {
+        result.push(Backpressure::GeneratorStarved(wait_percent));
+    }
+    if current.rx_fifo_overflow > previous.rx_fifo_overflow {
+        result.push(Backpressure::RxFifoOverflow(
+            current.rx_fifo_overflow - previous.rx_fifo_overflow,
+        ));
+    }
+    if current.solution_channel_saturated > previous.solution_channel_saturated
+        || current.pending_solutions > SOLUTION_CHANNEL_THRESHOLD
+    {
+        result.push(Backpressure::SolutionChannelSaturated(
+            current.pending_solutions,
+        ));
+    }
+    result
+}
+
+/// Periodically check pipeline counters and warn about sustained backpressure
+pub async fn monitor_task(core: std::sync::Arc<hub::Core>) {
+    let mut previous = core.pipeline.snapshot();
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        let current = core.pipeline.snapshot();
+        // Generators are expected to starve while mining is paused
+        if !core.is_paused().await {
+            for backpressure in evaluate(&previous, &current, CHECK_INTERVAL) {
+                match backpressure {
+                    Backpressure::GeneratorStarved(percent) => warn!(
+                        "Work pipeline: generators waited {:.0}% of time for new work \
+                         (clients don't supply jobs fast enough)",
+                        percent
+                    ),
+                    Backpressure::RxFifoOverflow(count) => warn!(
+                        "Work pipeline: solution FIFO in backend has been full {} times \
+                         (solutions may have been lost)",
+                        count
+                    ),
+                    Backpressure::SolutionChannelSaturated(pending) => warn!(
+                        "Work pipeline: {} solutions waiting for clients \
+                         (clients don't take solutions fast enough)",
+                        pending
+                    ),
+                }
+            }
+        }
+        previous = current;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_solution_channel() {
+        let stats = Stats::new();
+        for _ in 0..SOLUTION_CHANNEL_THRESHOLD + 2 {
+            stats.solution_sent();
+        }
+        for _ in 0..10 {
+            stats.solution_received();
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.pending_solutions, SOLUTION_CHANNEL_THRESHOLD - 8);
+        assert_eq!(
+            snapshot.max_pending_solutions,
+            SOLUTION_CHANNEL_THRESHOLD + 2
+        );
+        assert_eq!(snapshot.solution_channel_saturated, 1);
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let stats = Stats::new();
+        stats.account_prefetch_target(0, 4);
+        stats.account_prefetch_target(0, 2);
+        stats.account_prefetch_target(4, 8);
+        stats.account_prefetch_pushed(10);
+        stats.account_prefetch_taken(3);
+        stats.account_prefetch_stale(2);
+        stats.account_prefetch_underrun();
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.prefetch_depth, 5);
+        assert_eq!(snapshot.prefetch_target_depth, 10);
+        assert_eq!(snapshot.prefetch_stale, 2);
+        assert_eq!(snapshot.prefetch_underruns, 1);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let interval = time::Duration::from_secs(10);
+        let stats = Stats::new();
+        stats.add_generator();
+        stats.add_generator();
+        let previous = stats.snapshot();
+        assert!(evaluate(&previous, &stats.snapshot(), interval).is_empty());
+
+        // 12 s of 20 s of both generators
+        stats.account_generator_wait(time::Duration::from_secs(8));
+        stats.account_generator_wait(time::Duration::from_secs(4));
+        stats.account_tx_fifo_full();
+        let current = stats.snapshot();
+        assert_eq!(current.generator_wait_percent(&previous, interval), 60.0);
+        assert_eq!(
+            evaluate(&previous, &current, interval),
+            vec![Backpressure::GeneratorStarved(60.0)]
+        );
+
+        let previous = current;
+        stats.account_rx_fifo_overflow();
+        stats.account_rx_fifo_overflow();
+        for _ in 0..SOLUTION_CHANNEL_THRESHOLD + 1 {
+            stats.solution_sent();
+        }
+        assert_eq!(
+            evaluate(&previous, &stats.snapshot(), interval),
+            vec![
+                Backpressure::RxFifoOverflow(2),
+                Backpressure::SolutionChannelSaturated(SOLUTION_CHANNEL_THRESHOLD + 1)
+            ]
+        );
+    }
+}
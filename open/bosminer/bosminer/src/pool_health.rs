@@ -0,0 +1,280 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Pool health scoring which compares hashrate accepted by each pool with the hashrate expected
+//! from the nominal hashrate of all work solvers. An alert is raised when the pool stays under
+//! the threshold for a sustained period which helps to detect silent degradation of hash chains
+//! as well as problems on the pool side.
+
+use ii_logging::macros::*;
+
+use crate::client;
+use crate::hook;
+use crate::hub;
+use crate::node::{Stats as _, WorkSolver as _};
+use crate::stats;
+
+use bosminer_config::PoolHealthConfig;
+
+use ii_async_compat::tokio;
+use tokio::time::sleep;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// Default percentage of expected hashrate under which the pool is considered unhealthy
+pub const DEFAULT_THRESHOLD: f64 = 80.0;
+
+/// Default time in seconds the pool has to stay under the threshold to raise an alert
+pub const DEFAULT_PERIOD: f64 = 900.0;
+
+/// How often the pools are evaluated
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
+/// Health of a pool as reported to the API
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Score {
+    /// Hashrate accepted by the pool relative to the expected hashrate in percent
+    pub percent: Option<f64>,
+    /// The pool has been under the threshold for the whole alert period
+    pub alert: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transition {
+    Raised,
+    Cleared,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    score: Score,
+    below_since: Option<time::Instant>,
+}
+
+#[derive(Debug)]
+pub struct Monitor {
+    threshold: f64,
+    period: time::Duration,
+    exec: Option<String>,
+    /// Pool states indexed by address of client handle
+    states: StdMutex<HashMap<usize, State>>,
+}
+
+impl Monitor {
+    pub fn new(config: PoolHealthConfig) -> Self {
+        Self {
+            threshold: config.threshold.unwrap_or(DEFAULT_THRESHOLD),
+            period: time::Duration::from_secs_f64(config.period.unwrap_or(DEFAULT_PERIOD)),
+            exec: config.exec,
+            states: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    #[inline]
+    fn key(client: &Arc<client::Handle>) -> usize {
+        Arc::as_ptr(client) as usize
+    }
+
+    pub fn score(&self, client: &Arc<client::Handle>) -> Score {
+        self.states
+            .lock()
+            .expect("BUG: lock pool health")
+            .get(&Self::key(client))
+            .map(|state| state.score)
+            .unwrap_or_default()
+    }
+
+    /// Pool is expected to get the part of nominal hashrate which corresponds to its share of all
+    /// work solved by the backend
+    fn compute_score(accepted: f64, backend: f64, total_backend: f64, nominal: f64) -> Option<f64> {
+        if backend <= 0.0 || total_backend <= 0.0 || nominal <= 0.0 {
+            return None;
+        }
+        let expected = nominal * backend / total_backend;
+        Some(accepted / expected * 100.0)
+    }
+
+    fn update(&self, key: usize, percent: Option<f64>, now: time::Instant) -> Option<Transition> {
+        let mut states = self.states.lock().expect("BUG: lock pool health");
+        let state = states.entry(key).or_default();
+        state.score.percent = percent;
+
+        match percent {
+            Some(percent) if percent < self.threshold => {
+                let below_since = *state.below_since.get_or_insert(now);
+                if !state.score.alert && now.duration_since(below_since) >= self.period {
+                    state.score.alert = true;
+                    return Some(Transition::Raised);
+                }
+            }
+            _ => {
+                state.below_since = None;
+                if state.score.alert {
+                    state.score.alert = false;
+                    return Some(Transition::Cleared);
+                }
+            }
+        }
+        None
+    }
+
+    async fn notify(&self, client: &client::Handle, transition: Transition, percent: Option<f64>) {
+        let url = client.descriptor().await.get_url(true, true, true);
+        let percent = percent.unwrap_or_default();
+        match transition {
+            Transition::Raised => warn!(
+                "Pool '{}' accepts only {:.1}% of expected hashrate for {} s",
+                url,
+                percent,
+                self.period.as_secs()
+            ),
+            Transition::Cleared => info!(
+                "Pool '{}' recovered and accepts {:.1}% of expected hashrate",
+                url, percent
+            ),
+        }
+        if let Some(command) = &self.exec {
+            let state = match transition {
+                Transition::Raised => "alert",
+                Transition::Cleared => "ok",
+            };
+            hook::spawn(
+                "pool health",
+                command.clone(),
+                vec![
+                    ("BOSMINER_POOL_HEALTH_STATE", state.to_string()),
+                    ("BOSMINER_POOL_HEALTH_POOL", url),
+                    ("BOSMINER_POOL_HEALTH_SCORE", format!("{:.1}", percent)),
+                ],
+            );
+        }
+    }
+
+    async fn check(&self, core: &hub::Core) {
+        let interval = *stats::TIME_MEAN_INTERVAL_5M;
+        let now = time::Instant::now();
+
+        let mut nominal = 0.0;
+        for work_solver in core.get_work_solvers().await {
+            if let Some(hashrate) = work_solver.get_nominal_hashrate().await {
+                nominal += hashrate.into_hashes().into_f64();
+            }
+        }
+        let total_backend = core
+            .frontend
+            .mining_stats()
+            .valid_backend_diff()
+            .take_snapshot()
+            .await
+            .to_kilo_hashes(interval, now)
+            .into_hashes()
+            .into_f64();
+
+        let mut keys = Vec::new();
+        for group in core.get_client_manager().get_groups().await {
+            for client in group.get_clients().await {
+                let client_stats = client.stats();
+                // The hashrate is not representative until the whole interval is measured
+                let percent = if now.duration_since(*client_stats.start_time()) < interval {
+                    None
+                } else {
+                    let accepted = client_stats.accepted().take_snapshot().await;
+                    let backend = client_stats.valid_backend_diff().take_snapshot().await;
+                    Self::compute_score(
+                        accepted
+                            .to_kilo_hashes(interval, now)
+                            .into_hashes()
+                            .into_f64(),
+                        backend
+                            .to_kilo_hashes(interval, now)
+                            .into_hashes()
+                            .into_f64(),
+                        total_backend,
+                        nominal,
+                    )
+                };
+
+                let key = Self::key(&client);
+                keys.push(key);
+                if let Some(transition) = self.update(key, percent, now) {
+                    self.notify(&client, transition, percent).await;
+                }
+            }
+        }
+
+        // Forget removed clients
+        self.states
+            .lock()
+            .expect("BUG: lock pool health")
+            .retain(|key, _| keys.contains(key));
+    }
+
+    pub async fn run(self: Arc<Self>, core: Arc<hub::Core>) {
+        loop {
+            sleep(CHECK_INTERVAL).await;
+            self.check(&core).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compute_score() {
+        // the pool solves half of all work so it should get half of the nominal hashrate
+        assert_eq!(Monitor::compute_score(50.0, 1.0, 2.0, 100.0), Some(100.0));
+        assert_eq!(Monitor::compute_score(25.0, 1.0, 2.0, 100.0), Some(50.0));
+        assert_eq!(Monitor::compute_score(25.0, 0.0, 2.0, 100.0), None);
+        assert_eq!(Monitor::compute_score(25.0, 1.0, 2.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_sustained_alert() {
+        let monitor = Monitor::new(PoolHealthConfig {
+            threshold: Some(80.0),
+            period: Some(60.0),
+            exec: None,
+        });
+        let start = time::Instant::now();
+        let after = |secs| start + time::Duration::from_secs(secs);
+
+        assert_eq!(monitor.update(0, Some(50.0), start), None);
+        assert_eq!(monitor.update(0, Some(50.0), after(30)), None);
+        // short recovery restarts the period
+        assert_eq!(monitor.update(0, Some(90.0), after(40)), None);
+        assert_eq!(monitor.update(0, Some(50.0), after(50)), None);
+        assert_eq!(monitor.update(0, Some(50.0), after(100)), None);
+        assert_eq!(
+            monitor.update(0, Some(50.0), after(110)),
+            Some(Transition::Raised)
+        );
+        assert_eq!(monitor.update(0, Some(40.0), after(120)), None);
+        assert_eq!(
+            monitor.update(0, Some(85.0), after(130)),
+            Some(Transition::Cleared)
+        );
+    }
+}
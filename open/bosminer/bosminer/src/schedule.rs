@@ -0,0 +1,531 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Scheduled mining profiles. Each profile applies in a time window given by days of week and
+//! time of day (e.g. lower frequency during peak tariff or no mining during demand-response
+//! events). The scheduler only decides which profile is active and the backend subscribes to the
+//! changes and applies them to its hardware. The active profile can be overridden manually and
+//! the override is persisted so that it survives restarts.
+//!
+//! Local time is derived from UTC by a fixed offset. Daylight saving time is not applied so the
+//! windows shift by an hour against the wall clock when DST starts or ends unless the offset is
+//! reconfigured.
+
+use ii_logging::macros::*;
+
+use crate::events;
+
+use bosminer_config::{ProfileConfig, ScheduleConfig};
+
+use ii_async_compat::tokio;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+use serde_json::json;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// How often the windows of profiles are evaluated
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const HOURS_PER_DAY: u32 = 24;
+const MINUTES_PER_HOUR: u32 = 60;
+const DAYS_PER_WEEK: u32 = 7;
+/// Names of days of week starting with Monday
+const DAY_NAMES: [&str; DAYS_PER_WEEK as usize] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+/// Day of week of the Unix epoch (Thursday)
+const EPOCH_WEEKDAY: i64 = 3;
+
+/// Range of offsets of local time from UTC in minutes (UTC-12:00 to UTC+14:00)
+pub const UTC_OFFSET_MIN: i32 = -12 * 60;
+pub const UTC_OFFSET_MAX: i32 = 14 * 60;
+
+/// Name which restores automatic selection of the profile when passed to the API
+pub const AUTO_MODE: &str = "auto";
+
+/// Key of the override in the persisted state
+const STATE_OVERRIDE: &str = "override";
+
+/// Local day of week (0 is Monday) and minute of the day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub weekday: u32,
+    pub minute: u32,
+}
+
+impl LocalTime {
+    pub fn new(time: time::SystemTime, utc_offset: i32) -> Self {
+        let secs = match time.duration_since(time::UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        } + utc_offset as i64 * 60;
+        let days = secs.div_euclid(24 * 60 * 60);
+        Self {
+            weekday: (days + EPOCH_WEEKDAY).rem_euclid(DAYS_PER_WEEK as i64) as u32,
+            minute: (secs.rem_euclid(24 * 60 * 60) / 60) as u32,
+        }
+    }
+
+    fn previous_weekday(&self) -> u32 {
+        (self.weekday + DAYS_PER_WEEK - 1) % DAYS_PER_WEEK
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// Bit mask of days of week when the window starts (bit 0 is Monday)
+    days: u8,
+    /// Start of the window in minutes since midnight
+    start: u32,
+    /// End of the window in minutes since midnight
+    end: u32,
+    /// Frequency of hash chains in MHz
+    pub frequency: Option<f64>,
+    /// Voltage of hash chains in volts
+    pub voltage: Option<f64>,
+    /// Mining is stopped for the whole window
+    pub paused: bool,
+}
+
+impl Profile {
+    /// Parse full or abbreviated (the first three letters) name of day of week
+    fn parse_day(day: &str) -> Result<u8, String> {
+        let day = day.trim().to_lowercase();
+        DAY_NAMES
+            .iter()
+            .position(|name| day == *name || day == name[..3])
+            .map(|idx| 1 << idx)
+            .ok_or_else(|| format!("invalid day of week '{}'", day))
+    }
+
+    /// Parse time of day in format `HH:MM` into minutes since midnight. `24:00` is accepted as
+    /// the end of the day.
+    fn parse_time(time: &str) -> Result<u32, String> {
+        let error = || format!("invalid time of day '{}' (expected 'HH:MM')", time);
+        let mut parts = time.splitn(2, ':');
+        let hours = parts
+            .next()
+            .and_then(|hours| hours.trim().parse::<u32>().ok())
+            .ok_or_else(error)?;
+        let minutes = parts
+            .next()
+            .and_then(|minutes| minutes.trim().parse::<u32>().ok())
+            .ok_or_else(error)?;
+        // check the ranges before the conversion to minutes so that it cannot overflow
+        if hours > HOURS_PER_DAY || minutes >= MINUTES_PER_HOUR {
+            return Err(error());
+        }
+        let time = hours * MINUTES_PER_HOUR + minutes;
+        if time > MINUTES_PER_DAY {
+            return Err(error());
+        }
+        Ok(time)
+    }
+
+    pub fn from_config(config: &ProfileConfig) -> Result<Self, String> {
+        if config.name.is_empty() || config.name == AUTO_MODE {
+            return Err(format!("invalid profile name '{}'", config.name));
+        }
+        let days = match &config.days {
+            Some(days) => {
+                let mut mask = 0;
+                for day in days {
+                    mask |= Self::parse_day(day)?;
+                }
+                mask
+            }
+            None => (1 << DAYS_PER_WEEK) - 1,
+        };
+        let start = match &config.start {
+            Some(start) => Self::parse_time(start)? % MINUTES_PER_DAY,
+            None => 0,
+        };
+        let end = match &config.end {
+            Some(end) => Self::parse_time(end)? % MINUTES_PER_DAY,
+            None => 0,
+        };
+        Ok(Self {
+            name: config.name.clone(),
+            days,
+            start,
+            end,
+            frequency: config.frequency,
+            voltage: config.voltage,
+            paused: config.paused.unwrap_or(false),
+        })
+    }
+
+    #[inline]
+    fn starts_on(&self, weekday: u32) -> bool {
+        self.days & (1 << weekday) != 0
+    }
+
+    /// Check if the window of the profile covers `time`. The window which ends before (or when)
+    /// it starts continues on the next day.
+    pub fn is_active(&self, time: LocalTime) -> bool {
+        if self.start < self.end {
+            self.starts_on(time.weekday) && time.minute >= self.start && time.minute < self.end
+        } else {
+            (self.starts_on(time.weekday) && time.minute >= self.start)
+                || (self.starts_on(time.previous_weekday()) && time.minute < self.end)
+        }
+    }
+
+    /// Days of week when the window starts as a comma separated list
+    pub fn days(&self) -> String {
+        DAY_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.starts_on(*idx as u32))
+            .map(|(_, name)| &name[..3])
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn format_time(minutes: u32) -> String {
+        format!("{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+
+    /// Start of the window in format `HH:MM`
+    pub fn start(&self) -> String {
+        Self::format_time(self.start)
+    }
+
+    /// End of the window in format `HH:MM`
+    pub fn end(&self) -> String {
+        Self::format_time(self.end)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// How the active profile is selected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    /// Profile is selected by its time window
+    Auto,
+    /// Profile is selected manually regardless of time
+    Override(String),
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    profiles: Vec<Profile>,
+    utc_offset: i32,
+    state_path: Option<String>,
+    mode: StdMutex<Mode>,
+    active_sender: watch::Sender<Option<Profile>>,
+    active_receiver: watch::Receiver<Option<Profile>>,
+    event_bus: Arc<events::Bus>,
+}
+
+impl Scheduler {
+    pub fn new(config: ScheduleConfig, event_bus: Arc<events::Bus>) -> Self {
+        let mut profiles: Vec<Profile> = Vec::new();
+        for profile in config.profiles.unwrap_or_default().iter() {
+            match Profile::from_config(profile) {
+                Ok(profile) if profiles.iter().any(|other| other.name == profile.name) => {
+                    error!("Schedule: duplicate profile '{}' ignored", profile.name)
+                }
+                Ok(profile) => profiles.push(profile),
+                Err(e) => error!("Schedule: profile '{}' ignored: {}", profile.name, e),
+            }
+        }
+        let utc_offset = match config.utc_offset {
+            Some(offset) if !(UTC_OFFSET_MIN..=UTC_OFFSET_MAX).contains(&offset) => {
+                error!("Schedule: UTC offset '{}' ignored", offset);
+                0
+            }
+            offset => offset.unwrap_or(0),
+        };
+        let mode = match &config.state_path {
+            Some(path) => Self::load_mode(path).unwrap_or_else(|e| {
+                warn!("Cannot read schedule state '{}': {}", path, e);
+                Mode::Auto
+            }),
+            None => Mode::Auto,
+        };
+        let mode = match mode {
+            Mode::Override(name) if !profiles.iter().any(|profile| profile.name == name) => {
+                warn!("Schedule: persisted override of unknown profile '{}'", name);
+                Mode::Auto
+            }
+            mode => mode,
+        };
+
+        let (active_sender, active_receiver) = watch::channel(None);
+        let scheduler = Self {
+            profiles,
+            utc_offset,
+            state_path: config.state_path,
+            mode: StdMutex::new(mode),
+            active_sender,
+            active_receiver,
+            event_bus,
+        };
+        // select the initial profile so that the backend can start with it
+        scheduler
+            .active_sender
+            .send_replace(scheduler.select(time::SystemTime::now()));
+        scheduler
+    }
+
+    fn load_mode(path: &str) -> io::Result<Mode> {
+        let state = match fs::read_to_string(path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Mode::Auto),
+            Err(e) => return Err(e),
+        };
+        let state: serde_json::Value = serde_json::from_str(&state)?;
+        Ok(
+            match state.get(STATE_OVERRIDE).and_then(|name| name.as_str()) {
+                Some(name) => Mode::Override(name.to_string()),
+                None => Mode::Auto,
+            },
+        )
+    }
+
+    fn store_mode(path: &str, mode: &Mode) -> io::Result<()> {
+        let state = match mode {
+            Mode::Auto => json!({}),
+            Mode::Override(name) => json!({ STATE_OVERRIDE: name }),
+        };
+        fs::write(path, format!("{}\n", state))
+    }
+
+    #[inline]
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode.lock().expect("BUG: lock schedule mode").clone()
+    }
+
+    /// Currently active profile. `None` means that no profile is active and the backend runs
+    /// with its own configuration.
+    pub fn active(&self) -> Option<Profile> {
+        self.active_receiver.borrow().clone()
+    }
+
+    /// Subscribe to changes of the active profile
+    #[inline]
+    pub fn subscribe(&self) -> watch::Receiver<Option<Profile>> {
+        self.active_receiver.clone()
+    }
+
+    /// Profile which should be active at `now`
+    fn select(&self, now: time::SystemTime) -> Option<Profile> {
+        match self.mode() {
+            Mode::Override(name) => self
+                .profiles
+                .iter()
+                .find(|profile| profile.name == name)
+                .cloned(),
+            Mode::Auto => {
+                let time = LocalTime::new(now, self.utc_offset);
+                self.profiles
+                    .iter()
+                    .find(|profile| profile.is_active(time))
+                    .cloned()
+            }
+        }
+    }
+
+    /// Re-evaluate the active profile and notify subscribers when it changes
+    fn update(&self) {
+        let profile = self.select(time::SystemTime::now());
+        if *self.active_receiver.borrow() == profile {
+            return;
+        }
+        self.event_bus.publish(events::Kind::ProfileChanged {
+            profile: profile.as_ref().map(|profile| profile.name.clone()),
+        });
+        self.active_sender.send_replace(profile);
+    }
+
+    /// Override the active profile with profile `name` or return to automatic selection when
+    /// `name` is `None`. The override is persisted when state path is configured.
+    pub fn set_override(&self, name: Option<&str>) -> Result<(), String> {
+        let mode = match name {
+            Some(name) => {
+                if !self.profiles.iter().any(|profile| profile.name == name) {
+                    return Err(format!("unknown profile '{}'", name));
+                }
+                Mode::Override(name.to_string())
+            }
+            None => Mode::Auto,
+        };
+        if let Some(path) = &self.state_path {
+            if let Err(e) = Self::store_mode(path, &mode) {
+                error!("Cannot write schedule state '{}': {}", path, e);
+            }
+        }
+        info!("Schedule: mode set to {:?}", mode);
+        *self.mode.lock().expect("BUG: lock schedule mode") = mode;
+        self.update();
+        Ok(())
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        if self.profiles.is_empty() {
+            return;
+        }
+        loop {
+            self.update();
+            sleep(CHECK_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(days: Option<&[&str]>, start: &str, end: &str) -> Profile {
+        Profile::from_config(&ProfileConfig {
+            name: "test".to_string(),
+            days: days.map(|days| days.iter().map(|day| day.to_string()).collect()),
+            start: Some(start.to_string()),
+            end: Some(end.to_string()),
+            ..Default::default()
+        })
+        .expect("BUG: invalid profile")
+    }
+
+    fn at(weekday: u32, hours: u32, minutes: u32) -> LocalTime {
+        LocalTime {
+            weekday,
+            minute: hours * 60 + minutes,
+        }
+    }
+
+    #[test]
+    fn test_local_time() {
+        // 1970-01-01 00:00 UTC was Thursday
+        assert_eq!(LocalTime::new(time::UNIX_EPOCH, 0), at(3, 0, 0));
+        // 2020-03-02 12:30 UTC was Monday
+        let time = time::UNIX_EPOCH + time::Duration::from_secs(1_583_152_200);
+        assert_eq!(LocalTime::new(time, 0), at(0, 12, 30));
+        assert_eq!(LocalTime::new(time, 60), at(0, 13, 30));
+        assert_eq!(LocalTime::new(time, -13 * 60), at(6, 23, 30));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Profile::parse_time("07:30"), Ok(450));
+        assert_eq!(Profile::parse_time("24:00"), Ok(MINUTES_PER_DAY));
+        assert!(Profile::parse_time("24:01").is_err());
+        assert!(Profile::parse_time("7").is_err());
+        assert!(Profile::parse_time("07:60").is_err());
+        assert!(Profile::parse_time("25:00").is_err());
+        assert!(Profile::parse_time("-1:00").is_err());
+        // would overflow when converted to minutes without the range check
+        assert!(Profile::parse_time("4294967295:00").is_err());
+        assert!(Profile::parse_time("71582789:00").is_err());
+        assert!(Profile::parse_time("00:4294967295").is_err());
+        assert_eq!(Profile::parse_day("Mon"), Ok(1));
+        assert_eq!(Profile::parse_day("sunday"), Ok(1 << 6));
+        assert!(Profile::parse_day("mo").is_err());
+        assert!(Profile::parse_day("monkey").is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let profile = profile(Some(&["mon", "tue"]), "08:00", "20:00");
+        assert!(!profile.is_active(at(0, 7, 59)));
+        assert!(profile.is_active(at(0, 8, 0)));
+        assert!(profile.is_active(at(1, 19, 59)));
+        assert!(!profile.is_active(at(1, 20, 0)));
+        assert!(!profile.is_active(at(2, 12, 0)));
+        assert_eq!(profile.days(), "mon,tue");
+        assert_eq!(profile.start(), "08:00");
+        assert_eq!(profile.end(), "20:00");
+    }
+
+    #[test]
+    fn test_window_over_midnight() {
+        let profile = profile(Some(&["fri"]), "22:00", "06:00");
+        assert!(!profile.is_active(at(4, 21, 59)));
+        assert!(profile.is_active(at(4, 22, 0)));
+        assert!(profile.is_active(at(5, 5, 59)));
+        assert!(!profile.is_active(at(5, 6, 0)));
+        assert!(!profile.is_active(at(5, 22, 0)));
+
+        // the whole day
+        let profile = profile(None, "00:00", "24:00");
+        assert!(profile.is_active(at(6, 0, 0)));
+        assert!(profile.is_active(at(2, 23, 59)));
+    }
+
+    #[test]
+    fn test_override() {
+        let config = ScheduleConfig {
+            utc_offset: None,
+            state_path: None,
+            profiles: Some(vec![ProfileConfig {
+                name: "eco".to_string(),
+                frequency: Some(500.0),
+                // window never matches
+                days: Some(vec![]),
+                ..Default::default()
+            }]),
+        };
+        let scheduler = Scheduler::new(config, Arc::new(events::Bus::new(Default::default())));
+        let receiver = scheduler.subscribe();
+        assert_eq!(scheduler.active(), None);
+
+        assert!(scheduler.set_override(Some("turbo")).is_err());
+        scheduler
+            .set_override(Some("eco"))
+            .expect("BUG: override failed");
+        assert_eq!(scheduler.mode(), Mode::Override("eco".to_string()));
+        assert_eq!(
+            receiver
+                .borrow()
+                .as_ref()
+                .map(|profile| profile.name.as_str()),
+            Some("eco")
+        );
+
+        scheduler.set_override(None).expect("BUG: override failed");
+        assert_eq!(scheduler.active(), None);
+    }
+}
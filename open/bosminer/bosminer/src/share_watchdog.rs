@@ -0,0 +1,224 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Watchdog of accepted shares. When no share has been accepted by any pool for a long time
+//! while work solvers keep finding valid solutions, the stratum session is most likely wedged
+//! without the client noticing it. The watchdog then records an incident and restarts all clients
+//! or the whole miner.
+
+use ii_logging::macros::*;
+
+use crate::events;
+use crate::hal;
+use crate::hub;
+use crate::node::Stats as _;
+use crate::stats;
+
+use bosminer_config::{ShareWatchdogConfig, WatchdogAction};
+
+use ii_async_compat::tokio;
+use tokio::time::sleep;
+
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// How often the accepted shares are checked
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+#[derive(Debug)]
+struct State {
+    /// Sum of shares accepted by all pools
+    accepted: u64,
+    /// Start of the period without any accepted share
+    idle_since: time::Instant,
+}
+
+#[derive(Debug)]
+pub struct Watchdog {
+    enabled: bool,
+    timeout: time::Duration,
+    action: WatchdogAction,
+    state: StdMutex<State>,
+}
+
+impl Watchdog {
+    pub fn new(config: ShareWatchdogConfig) -> Self {
+        let timeout = config.timeout().unwrap_or_else(|e| {
+            error!("Share watchdog: {}, using default timeout", e);
+            time::Duration::from_secs_f64(ShareWatchdogConfig::DEFAULT_TIMEOUT)
+        });
+        Self {
+            enabled: config.enabled(),
+            timeout,
+            action: config.action(),
+            state: StdMutex::new(State {
+                accepted: 0,
+                idle_since: time::Instant::now(),
+            }),
+        }
+    }
+
+    /// Account the current sum of `accepted` shares. The idle period is restarted whenever a new
+    /// share is accepted or the miner is not hashing. Returns the length of the idle period when
+    /// the watchdog fires and starts a new period so that the action has time to take effect.
+    fn update(&self, accepted: u64, hashing: bool, now: time::Instant) -> Option<time::Duration> {
+        let mut state = self.state.lock().expect("BUG: lock share watchdog");
+        // The sum may also decrease when a pool is removed
+        if accepted != state.accepted || !hashing {
+            state.idle_since = now;
+        }
+        state.accepted = accepted;
+
+        let idle_time = now.duration_since(state.idle_since);
+        if idle_time >= self.timeout {
+            state.idle_since = now;
+            Some(idle_time)
+        } else {
+            None
+        }
+    }
+
+    async fn check(&self, core: &hub::Core, restart_handler: Option<&hal::RestartHandler>) {
+        let now = time::Instant::now();
+        let mut accepted = 0;
+        for group in core.get_client_manager().get_groups().await {
+            for client in group.get_clients().await {
+                accepted += client.stats().accepted().take_snapshot().await.solutions;
+            }
+        }
+        let hashrate = core
+            .frontend
+            .mining_stats()
+            .valid_backend_diff()
+            .take_snapshot()
+            .await
+            .to_kilo_hashes(*stats::TIME_MEAN_INTERVAL_5M, now);
+        // Work solvers are expected to stop hashing while mining is paused
+        let hashing = hashrate.into_hashes().into_f64() > 0.0 && !core.is_paused().await;
+
+        let idle_time = match self.update(accepted, hashing, now) {
+            Some(idle_time) => idle_time,
+            None => return,
+        };
+        let action = match (self.action, restart_handler) {
+            (WatchdogAction::RestartMiner, Some(_)) => "restarting miner",
+            (WatchdogAction::RestartMiner, None) => {
+                warn!("Share watchdog: backend does not support restart, restarting pools");
+                "restarting pools"
+            }
+            (WatchdogAction::RestartPools, _) => "restarting pools",
+        };
+        error!(
+            "Share watchdog: no share accepted for {} s while hashing at {}/s ({} shares accepted \
+             in total), {}",
+            idle_time.as_secs(),
+            hashrate.into_pretty_hashes(),
+            accepted,
+            action
+        );
+        core.events.publish(events::Kind::NoAcceptedShares {
+            idle_time: idle_time.as_secs(),
+            action: action.to_string(),
+        });
+
+        match (self.action, restart_handler) {
+            (WatchdogAction::RestartMiner, Some(restart_handler)) => {
+                restart_handler(format!(
+                    "share watchdog: no share accepted for {} s",
+                    idle_time.as_secs()
+                ))
+                .await
+            }
+            _ => core.get_client_manager().restart_clients().await,
+        }
+    }
+
+    pub async fn run(
+        self: Arc<Self>,
+        core: Arc<hub::Core>,
+        restart_handler: Option<hal::RestartHandler>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        info!(
+            "Share watchdog: enabled with timeout {} s",
+            self.timeout.as_secs()
+        );
+        loop {
+            sleep(CHECK_INTERVAL).await;
+            self.check(&core, restart_handler.as_ref()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_idle_period() {
+        let watchdog = Watchdog::new(ShareWatchdogConfig {
+            enabled: Some(true),
+            timeout: Some(600.0),
+            action: None,
+        });
+        let start = time::Instant::now();
+        let after = |secs| start + time::Duration::from_secs(secs);
+
+        assert_eq!(watchdog.update(10, true, start), None);
+        assert_eq!(watchdog.update(10, true, after(300)), None);
+        // accepted share restarts the period
+        assert_eq!(watchdog.update(11, true, after(400)), None);
+        assert_eq!(watchdog.update(11, true, after(900)), None);
+        assert_eq!(
+            watchdog.update(11, true, after(1000)),
+            Some(time::Duration::from_secs(600))
+        );
+        // the watchdog does not fire again until another timeout elapses
+        assert_eq!(watchdog.update(11, true, after(1100)), None);
+        assert_eq!(
+            watchdog.update(11, true, after(1600)),
+            Some(time::Duration::from_secs(600))
+        );
+    }
+
+    #[test]
+    fn test_not_hashing() {
+        let watchdog = Watchdog::new(ShareWatchdogConfig {
+            enabled: Some(true),
+            timeout: Some(600.0),
+            action: Some(WatchdogAction::RestartMiner),
+        });
+        let start = time::Instant::now();
+        let after = |secs| start + time::Duration::from_secs(secs);
+
+        assert_eq!(watchdog.update(0, true, start), None);
+        // no shares are expected without hashing
+        assert_eq!(watchdog.update(0, false, after(500)), None);
+        assert_eq!(watchdog.update(0, true, after(1000)), None);
+        assert_eq!(
+            watchdog.update(0, true, after(1100)),
+            Some(time::Duration::from_secs(600))
+        );
+    }
+}
@@ -32,10 +32,12 @@ use ii_stats::WindowedTimeMean;
 
 use futures::lock::Mutex;
 use ii_async_compat::{futures, tokio};
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
-use std::fmt::Debug;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time;
 
 use once_cell::sync::Lazy;
@@ -60,6 +62,9 @@ static DEFAULT_TIME_MEAN_INTERVALS: Lazy<Vec<time::Duration>> = Lazy::new(|| {
     ]
 });
 
+/// Number of hashes represented by a share with difficulty 1
+const DIFFICULTY_1_HASHES: f64 = 4_294_967_296.0;
+
 /// Auxiliary structure for adding time to snapshots
 pub struct Snapshot<T> {
     pub snapshot_time: time::Instant,
@@ -146,6 +151,12 @@ impl MeterSnapshot {
     ) -> ii_bitcoin::HashesUnit {
         self.to_kilo_hashes(interval, now).into_pretty_hashes()
     }
+
+    /// Approximate sum of difficulties of all solutions accounted within given time interval
+    pub fn to_difficulty(&self, interval: time::Duration, now: time::Instant) -> f64 {
+        let hashes = self.to_kilo_hashes(interval, now).into_hashes().into_f64();
+        hashes * interval.as_secs_f64() / DIFFICULTY_1_HASHES
+    }
 }
 
 #[derive(Debug)]
@@ -252,18 +263,18 @@ impl BestShare {
 
     pub(crate) fn account_solution(&self, target: &ii_bitcoin::Target) {
         let new_diff = target.get_difficulty();
-        let mut old_diff = self.inner.load(Ordering::Relaxed);
-
-        while old_diff < new_diff {
-            let prev_diff = self
-                .inner
-                .compare_and_swap(old_diff, new_diff, Ordering::Relaxed);
-            if old_diff == prev_diff {
-                break;
-            } else {
-                old_diff = prev_diff;
-            }
+        self.inner.fetch_max(new_diff, Ordering::Relaxed);
+    }
+
+    /// Account actual difficulty of the solution hash which is usually higher than difficulty of
+    /// the target the solution has met
+    pub(crate) fn account_hash(&self, hash: &ii_bitcoin::DHash) {
+        let target = ii_bitcoin::Target::from(*hash);
+        // Zero hash would cause division by zero and it is not found in practice anyway
+        if target.into_inner().is_zero() {
+            return;
         }
+        self.account_solution(&target);
     }
 }
 
@@ -421,6 +432,191 @@ pub trait Mining: Send + Sync {
     fn error_backend_diff(&self) -> &Meter;
 }
 
+/// Shares submitted by a client which have been found by one work solver
+#[derive(Debug, Default)]
+pub struct SolverShares {
+    /// Shares accepted by remote server
+    pub accepted: Meter,
+    /// Shares rejected by remote server
+    pub rejected: Meter,
+}
+
+/// Attribution of shares submitted by a client to work solvers (e.g. hash chains) which have
+/// found them
+#[derive(Debug, Default)]
+pub struct Attribution {
+    solvers: Mutex<Vec<(node::DynInfo, Arc<SolverShares>)>>,
+}
+
+impl Attribution {
+    async fn get(&self, solver: &node::DynInfo) -> Arc<SolverShares> {
+        let mut solvers = self.solvers.lock().await;
+        let solver_ptr = solver.clone().get_unique_ptr();
+        match solvers
+            .iter()
+            .find(|(node, _)| Arc::ptr_eq(&node.clone().get_unique_ptr(), &solver_ptr))
+        {
+            Some((_, shares)) => shares.clone(),
+            None => {
+                let shares = Arc::new(SolverShares::default());
+                solvers.push((solver.clone(), shares.clone()));
+                shares
+            }
+        }
+    }
+
+    /// Return shares of all work solvers which have found at least one submitted share
+    pub async fn solvers(&self) -> Vec<(node::DynInfo, Arc<SolverShares>)> {
+        self.solvers.lock().await.clone()
+    }
+}
+
+/// Reason of share rejection classified from the message sent by remote server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Share of a job which has been already replaced by a new block
+    Stale,
+    /// Share which doesn't meet the target of the job
+    LowDifficulty,
+    /// Share which has been already submitted
+    Duplicate,
+    /// Share of a job unknown to the remote server
+    JobNotFound,
+    Other,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 5] = [
+        Self::Stale,
+        Self::LowDifficulty,
+        Self::Duplicate,
+        Self::JobNotFound,
+        Self::Other,
+    ];
+
+    /// Classify rejection `message` of Stratum V2 (`stale-share`, `difficulty-too-low`, ...),
+    /// Stratum V1 translated by the proxy (`ShareRjct:StratumError(21, "Job not found", ...)`)
+    /// or `submitblock` of bitcoind (`duplicate`, `high-hash`, ...)
+    pub fn classify(message: &str) -> Self {
+        let message = message.to_lowercase();
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+
+        if contains_any(&["duplicate", "(22,"]) {
+            Self::Duplicate
+        } else if contains_any(&["stale"]) {
+            Self::Stale
+        } else if contains_any(&["job not found", "invalid-job-id", "unknown job", "(21,"]) {
+            Self::JobNotFound
+        } else if contains_any(&["low diff", "difficulty-too-low", "high-hash", "(23,"]) {
+            Self::LowDifficulty
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Stale => "Stale",
+            Self::LowDifficulty => "Low Difficulty",
+            Self::Duplicate => "Duplicate",
+            Self::JobNotFound => "Job Not Found",
+            Self::Other => "Other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Number of shares rejected by remote server for each reason
+#[derive(Debug, Default)]
+pub struct RejectReasons {
+    counters: [CounterU64; RejectReason::ALL.len()],
+}
+
+impl RejectReasons {
+    #[inline]
+    pub fn get(&self, reason: RejectReason) -> &CounterU64 {
+        &self.counters[reason as usize]
+    }
+
+    pub fn take_snapshot(&self) -> Vec<(RejectReason, u64)> {
+        RejectReason::ALL
+            .iter()
+            .map(|reason| (*reason, *self.get(*reason).take_snapshot()))
+            .collect()
+    }
+}
+
+/// Distribution of latency samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySnapshot {
+    pub last: time::Duration,
+    pub min: time::Duration,
+    pub mean: time::Duration,
+    pub median: time::Duration,
+    pub p95: time::Duration,
+    pub max: time::Duration,
+    /// Number of samples in the window
+    pub samples: usize,
+}
+
+/// Rolling window of latency samples measured to remote server
+#[derive(Debug)]
+pub struct Latency {
+    samples: StdMutex<VecDeque<time::Duration>>,
+    capacity: usize,
+}
+
+impl Latency {
+    /// Default number of samples kept in the window
+    pub const DEFAULT_CAPACITY: usize = 60;
+
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "BUG: empty latency window");
+        Self {
+            samples: StdMutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, latency: time::Duration) {
+        let mut samples = self.samples.lock().expect("BUG: cannot lock latency");
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    /// Return `None` when no sample has been measured yet
+    pub fn take_snapshot(&self) -> Option<LatencySnapshot> {
+        let samples = self.samples.lock().expect("BUG: cannot lock latency");
+        let last = *samples.back()?;
+        let mut sorted: Vec<_> = samples.iter().cloned().collect();
+        drop(samples);
+        sorted.sort();
+
+        let count = sorted.len();
+        // Nearest-rank percentile
+        let percentile = |p: usize| sorted[(count * p + 99) / 100 - 1];
+        Some(LatencySnapshot {
+            last,
+            min: sorted[0],
+            mean: sorted.iter().sum::<time::Duration>() / count as u32,
+            median: percentile(50),
+            p95: percentile(95),
+            max: sorted[count - 1],
+            samples: count,
+        })
+    }
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
 pub trait Client: Mining {
     /// Number of valid jobs received from remote server
     fn valid_jobs(&self) -> &CounterUsize;
@@ -434,6 +630,12 @@ pub trait Client: Mining {
     fn rejected(&self) -> &Meter;
     /// Valid shares rejected by remote server or discarded due to some error
     fn stale(&self) -> &Meter;
+    /// Accepted and rejected shares split by work solvers which have found them
+    fn attribution(&self) -> &Attribution;
+    /// Rejected shares split by reason of rejection
+    fn reject_reasons(&self) -> &RejectReasons;
+    /// Latency of remote server measured periodically
+    fn latency(&self) -> &Latency;
 }
 
 pub trait WorkSolver: Mining {
@@ -501,6 +703,12 @@ pub struct BasicClient {
     pub rejected: stats::Meter,
     #[member_stale]
     pub stale: stats::Meter,
+    #[member_attribution]
+    pub attribution: Attribution,
+    #[member_reject_reasons]
+    pub reject_reasons: RejectReasons,
+    #[member_latency]
+    pub latency: Latency,
     #[member_valid_network_diff]
     pub valid_network_diff: Meter,
     #[member_valid_job_diff]
@@ -523,6 +731,9 @@ impl BasicClient {
             accepted: Meter::new(&intervals),
             rejected: Meter::new(&intervals),
             stale: Default::default(),
+            attribution: Default::default(),
+            reject_reasons: Default::default(),
+            latency: Default::default(),
             valid_network_diff: Meter::new(&intervals),
             valid_job_diff: Meter::new(&intervals),
             valid_backend_diff: Meter::new(&intervals),
@@ -606,6 +817,38 @@ account_impl!(account_valid_job_diff, valid_job_diff);
 account_impl!(account_valid_backend_diff, valid_backend_diff);
 account_impl!(account_error_backend_diff, error_backend_diff);
 
+/// Account a `solution` accepted by remote server to the `client` and to the work solver which
+/// has found it
+pub async fn account_accepted_solution(
+    client: &dyn Client,
+    solution: &work::Solution,
+    time: time::Instant,
+) {
+    let target = solution.job_target();
+    client.accepted().account_solution(target, time).await;
+    if let Some(solver) = solution.solver() {
+        let shares = client.attribution().get(&solver).await;
+        shares.accepted.account_solution(target, time).await;
+    }
+}
+
+/// Account a `solution` rejected by remote server for `reason` to the `client` and to the work
+/// solver which has found it
+pub async fn account_rejected_solution(
+    client: &dyn Client,
+    solution: &work::Solution,
+    time: time::Instant,
+    reason: RejectReason,
+) {
+    let target = solution.job_target();
+    client.rejected().account_solution(target, time).await;
+    client.reject_reasons().get(reason).inc();
+    if let Some(solver) = solution.solver() {
+        let shares = client.attribution().get(&solver).await;
+        shares.rejected.account_solution(target, time).await;
+    }
+}
+
 /// Describes which difficulty target a particular solution has met.
 /// It also determines in which statistics a particular solution should be accounted.
 #[derive(Debug, PartialEq)]
@@ -645,14 +888,15 @@ pub async fn account_valid_solution(
                 .last_share()
                 .account_solution(target, time::SystemTime::now())
                 .await;
-            mining_stats.best_share().account_solution(target);
+            // the best share is tracked by real difficulty of the solution like in cgminer
+            mining_stats.best_share().account_hash(solution.hash());
         }
     }
 }
 
 pub async fn mining_task(node: node::DynInfo, interval: time::Duration) {
     loop {
-        delay_for(time::Duration::from_secs(1)).await;
+        sleep(time::Duration::from_secs(1)).await;
         let valid_job_diff = node.mining_stats().valid_job_diff().take_snapshot().await;
         let valid_backend_diff = node
             .mining_stats()
@@ -669,3 +913,141 @@ pub async fn mining_task(node: node::DynInfo, interval: time::Duration) {
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+
+    #[tokio::test]
+    async fn test_meter_difficulty() {
+        let meter = Meter::default();
+        let target = ii_bitcoin::Target::from_pool_difficulty(4);
+        let now = time::Instant::now();
+        for _ in 0..3 {
+            meter.account_solution(&target, now).await;
+        }
+
+        let snapshot = meter.take_snapshot().await;
+        assert_eq!(snapshot.solutions, 3);
+        let difficulty = snapshot.to_difficulty(*TIME_MEAN_INTERVAL_5M, now);
+        assert!(
+            (difficulty - 12.0).abs() < 1e-6,
+            "difficulty {}",
+            difficulty
+        );
+    }
+
+    #[tokio::test]
+    async fn test_attribution() {
+        let client = BasicClient::default();
+        let solvers: Vec<node::DynInfo> = (0..2)
+            .map(|_| test_utils::create_test_work_solver() as node::DynInfo)
+            .collect();
+        let test_block = &test_utils::TEST_BLOCKS[0];
+        let solution = |solver: &node::DynInfo| {
+            let mut work: work::Assignment = test_block.into();
+            work.path.push(solver.clone());
+            work::Solution::new(work, test_utils::TestSolution::new(test_block), None)
+        };
+
+        let now = time::Instant::now();
+        account_accepted_solution(&client, &solution(&solvers[0]), now).await;
+        account_accepted_solution(&client, &solution(&solvers[0]), now).await;
+        account_rejected_solution(
+            &client,
+            &solution(&solvers[1]),
+            now,
+            RejectReason::Duplicate,
+        )
+        .await;
+        assert_eq!(client.accepted.take_snapshot().await.solutions, 2);
+        assert_eq!(client.rejected.take_snapshot().await.solutions, 1);
+        assert_eq!(
+            *client
+                .reject_reasons
+                .get(RejectReason::Duplicate)
+                .take_snapshot(),
+            1
+        );
+
+        let attributed = client.attribution.solvers().await;
+        assert_eq!(attributed.len(), 2);
+        // solvers are listed in order of their first share
+        let expected = solvers.iter().zip(&[(2, 0), (0, 1)]);
+        for ((solver, shares), (expected_solver, (accepted, rejected))) in
+            attributed.iter().zip(expected)
+        {
+            assert!(Arc::ptr_eq(
+                &solver.clone().get_unique_ptr(),
+                &expected_solver.clone().get_unique_ptr()
+            ));
+            assert_eq!(shares.accepted.take_snapshot().await.solutions, *accepted);
+            assert_eq!(shares.rejected.take_snapshot().await.solutions, *rejected);
+        }
+    }
+
+    #[test]
+    fn test_reject_reason_classify() {
+        assert_eq!(RejectReason::classify("stale-share"), RejectReason::Stale);
+        assert_eq!(
+            RejectReason::classify("difficulty-too-low"),
+            RejectReason::LowDifficulty
+        );
+        assert_eq!(
+            RejectReason::classify("invalid-job-id"),
+            RejectReason::JobNotFound
+        );
+        assert_eq!(
+            RejectReason::classify("ShareRjct:StratumError(21, \"Job n"),
+            RejectReason::JobNotFound
+        );
+        assert_eq!(
+            RejectReason::classify("ShareRjct:StratumError(22, \"Dupl"),
+            RejectReason::Duplicate
+        );
+        assert_eq!(
+            RejectReason::classify("high-hash"),
+            RejectReason::LowDifficulty
+        );
+        assert_eq!(RejectReason::classify("bad-txns"), RejectReason::Other);
+    }
+
+    #[test]
+    fn test_best_share_hash() {
+        let best_share = BestShare::default();
+        assert!(best_share.take_snapshot().is_none());
+
+        for block in ii_bitcoin::TEST_BLOCKS.iter() {
+            best_share.account_hash(&block.hash);
+            // hash of a block always meets its network target
+            assert!(*best_share.take_snapshot().unwrap() >= block.target.get_difficulty());
+        }
+    }
+
+    #[test]
+    fn test_latency_window() {
+        let ms = time::Duration::from_millis;
+        let latency = Latency::new(20);
+        assert!(latency.take_snapshot().is_none());
+
+        for i in (1..=20).rev() {
+            latency.record(ms(i * 10));
+        }
+        let snapshot = latency.take_snapshot().unwrap();
+        assert_eq!(snapshot.last, ms(10));
+        assert_eq!(snapshot.min, ms(10));
+        assert_eq!(snapshot.max, ms(200));
+        assert_eq!(snapshot.mean, ms(105));
+        assert_eq!(snapshot.median, ms(100));
+        assert_eq!(snapshot.p95, ms(190));
+        assert_eq!(snapshot.samples, 20);
+
+        // the oldest samples drop out of the window
+        latency.record(ms(5));
+        let snapshot = latency.take_snapshot().unwrap();
+        assert_eq!(snapshot.max, ms(190));
+        assert_eq!(snapshot.min, ms(5));
+        assert_eq!(snapshot.samples, 20);
+    }
+}
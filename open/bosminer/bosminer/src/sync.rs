@@ -20,10 +20,11 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+pub mod backoff;
 pub mod event;
 
 use std::fmt;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Mutex;
 
 use atomic_enum::atomic_enum;
@@ -56,6 +57,8 @@ impl fmt::Display for Status {
 #[derive(Debug)]
 pub struct StatusMonitor {
     status: AtomicStatus,
+    /// Number of consecutive attempts to start the node after a failure
+    retries: AtomicU32,
     event_sender: Mutex<Option<event::Sender>>,
 }
 
@@ -65,6 +68,21 @@ impl StatusMonitor {
         self.status.load(Ordering::Relaxed)
     }
 
+    /// Number of the current retry attempt or zero when the node hasn't failed since it has
+    /// been running for the last time
+    #[inline]
+    pub fn retries(&self) -> u32 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Store `new` status when the current one is still `current` and return the previous status
+    #[inline]
+    fn compare_and_swap(&self, current: Status, new: Status) -> Status {
+        self.status
+            .compare_exchange(current, new, Ordering::Relaxed, Ordering::Relaxed)
+            .unwrap_or_else(|previous| previous)
+    }
+
     pub fn initiate_starting(&self) -> bool {
         let mut status = self.status();
 
@@ -72,37 +90,30 @@ impl StatusMonitor {
             let previous = status;
             match status {
                 Status::Created | Status::Stopped => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Starting, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Starting);
                     if status == previous {
                         // Starting has been initiated successfully
                         return true;
                     }
                 }
                 Status::Failed => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Retrying, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Retrying);
                     if status == previous {
                         // Retrying has been initiated successfully
+                        self.retries.fetch_add(1, Ordering::Relaxed);
                         return true;
                     }
                 }
                 Status::Stopping => {
                     // Try to change state to `Restarting`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Restarting, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Restarting);
                     if status == previous {
                         break;
                     }
                 }
                 Status::Failing | Status::Declining => {
                     // Try to change state to `Recovering`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Recovering, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Recovering);
                     if status == previous {
                         break;
                     }
@@ -131,11 +142,10 @@ impl StatusMonitor {
                     panic!("BUG: 'report_fail': unexpected state '{:?}'", status)
                 }
                 Status::Starting | Status::Retrying => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Running, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Running);
                     if status == previous {
                         // Running has been set successfully
+                        self.retries.store(0, Ordering::Relaxed);
                         self.notify();
                         break;
                     }
@@ -166,18 +176,14 @@ impl StatusMonitor {
                 | Status::Failed => break,
                 // Client is currently started
                 Status::Starting | Status::Running | Status::Restarting => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Stopping, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Stopping);
                     if status == previous {
                         // Stopping has been initiated successfully
                         return true;
                     }
                 }
                 Status::Retrying | Status::Recovering => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Declining, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Declining);
                     if status == previous {
                         // Stopping has been initiated successfully
                         return true;
@@ -201,18 +207,14 @@ impl StatusMonitor {
                     panic!("BUG: 'report_fail': unexpected state '{:?}'", status)
                 }
                 Status::Running | Status::Stopping => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Failing, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Failing);
                     if status == previous {
                         // Failing has been set successfully
                         break;
                     }
                 }
                 Status::Starting | Status::Retrying => {
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Declining, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Declining);
                     if status == previous {
                         // Failing has been set successfully
                         break;
@@ -254,9 +256,7 @@ impl StatusMonitor {
                 | Status::Failed => panic!("BUG: 'can_stop': unexpected state '{:?}'", status),
                 Status::Stopping => {
                     // Try to change state to `Stopped`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Stopped, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Stopped);
                     if status == previous {
                         self.notify();
                         return true;
@@ -264,9 +264,7 @@ impl StatusMonitor {
                 }
                 Status::Failing => {
                     // Try to change state to `Failed`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Failed, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Failed);
                     if status == previous {
                         self.notify();
                         return true;
@@ -274,9 +272,7 @@ impl StatusMonitor {
                 }
                 Status::Declining => {
                     // Try to change state to `Failed`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Failed, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Failed);
                     if status == previous {
                         // Do not notify about repeated failures when status wasn't in running
                         // state before
@@ -286,9 +282,7 @@ impl StatusMonitor {
                 // Restarting has been initiated
                 Status::Restarting => {
                     // Try to change state to `Starting`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Starting, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Starting);
                     if status == previous {
                         break;
                     }
@@ -296,10 +290,9 @@ impl StatusMonitor {
                 // Recovering after previous fail has been initiated
                 Status::Recovering => {
                     // Try to change state to `Retrying`
-                    status =
-                        self.status
-                            .compare_and_swap(status, Status::Retrying, Ordering::Relaxed);
+                    status = self.compare_and_swap(status, Status::Retrying);
                     if status == previous {
+                        self.retries.fetch_add(1, Ordering::Relaxed);
                         break;
                     }
                 }
@@ -338,6 +331,7 @@ impl Default for StatusMonitor {
     fn default() -> Self {
         Self {
             status: AtomicStatus::new(Status::Created),
+            retries: AtomicU32::new(0),
             event_sender: Mutex::new(None),
         }
     }
@@ -0,0 +1,109 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Exponential backoff with jitter used for delaying repeated attempts to start a node
+
+use bosminer_config::ClientRetrySchedule;
+
+use std::time::Duration;
+
+/// Delay of the n-th retry is chosen randomly from the upper half of the nominal delay so that
+/// the clients failing at the same time don't hammer the upstream in lockstep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    schedule: ClientRetrySchedule,
+}
+
+impl Backoff {
+    pub fn new(schedule: ClientRetrySchedule) -> Self {
+        Self { schedule }
+    }
+
+    /// Delay without jitter before `attempt` (counted from 1). The delay saturates at the
+    /// maximal delay of the schedule instead of overflowing.
+    pub fn nominal_delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.schedule
+            .initial_delay
+            .checked_mul(factor)
+            .map_or(self.schedule.max_delay, |delay| {
+                delay.min(self.schedule.max_delay)
+            })
+    }
+
+    /// Randomized delay before `attempt` (counted from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let half = self.nominal_delay(attempt) / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nominal_delay() {
+        let backoff = Backoff::new(
+            ClientRetrySchedule::new(Duration::from_secs(1), Duration::from_secs(10))
+                .expect("BUG: invalid retry schedule"),
+        );
+        assert_eq!(backoff.nominal_delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.nominal_delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.nominal_delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.nominal_delay(4), Duration::from_secs(8));
+        // The delay is capped
+        assert_eq!(backoff.nominal_delay(5), Duration::from_secs(10));
+        assert_eq!(
+            backoff.nominal_delay(std::u32::MAX),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_nominal_delay_overflow() {
+        let max_delay = Duration::from_secs(std::u64::MAX);
+        let backoff = Backoff::new(
+            ClientRetrySchedule::new(Duration::from_secs(std::u64::MAX / 4), max_delay)
+                .expect("BUG: invalid retry schedule"),
+        );
+        assert_eq!(
+            backoff.nominal_delay(2),
+            Duration::from_secs(std::u64::MAX / 4 * 2)
+        );
+        assert_eq!(backoff.nominal_delay(4), max_delay);
+        assert_eq!(backoff.nominal_delay(std::u32::MAX), max_delay);
+    }
+
+    #[test]
+    fn test_delay_jitter() {
+        let backoff = Backoff::new(Default::default());
+        for attempt in 1..10 {
+            let nominal_delay = backoff.nominal_delay(attempt);
+            for _ in 0..100 {
+                let delay = backoff.delay(attempt);
+                assert!(delay >= nominal_delay / 2);
+                assert!(delay <= nominal_delay);
+            }
+        }
+    }
+}
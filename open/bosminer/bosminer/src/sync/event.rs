@@ -78,10 +78,10 @@ impl Receiver {
         loop {
             match self.broadcast_receiver.recv().await {
                 Ok(_) => return Ok(()),
-                Err(broadcast::RecvError::Lagged(_)) => {
+                Err(broadcast::error::RecvError::Lagged(_)) => {
                     // Receiver handle falls behind - try it again
                 }
-                Err(broadcast::RecvError::Closed) => return Err(()),
+                Err(broadcast::error::RecvError::Closed) => return Err(()),
             }
         }
     }
@@ -25,6 +25,7 @@ pub mod block_mining;
 use crate::hal;
 use crate::job::{self, Bitcoin as _};
 use crate::node;
+use crate::pipeline;
 use crate::stats;
 use crate::sync;
 use crate::work;
@@ -136,7 +137,7 @@ impl job::Bitcoin for TestBlock {
     }
 
     fn version_mask(&self) -> u32 {
-        0
+        ii_bitcoin::BIP320_VERSION_MASK
     }
 
     fn previous_hash(&self) -> &ii_bitcoin::DHash {
@@ -179,7 +180,7 @@ impl TestBlockBuilder for TestBlock {
 }
 
 #[derive(Debug)]
-struct TestSolution {
+pub struct TestSolution {
     test_block: TestBlock,
     target: ii_bitcoin::Target,
 }
@@ -389,9 +390,11 @@ pub fn create_test_work_receiver() -> work::EngineReceiver {
 
 pub fn create_test_work_generator(work_solver: Arc<dyn node::WorkSolver>) -> work::Generator {
     work::Generator::new(
+        0,
         create_test_work_receiver(),
         vec![],
         Arc::new(Mutex::new(Some(Arc::downgrade(&work_solver)))),
+        Arc::new(pipeline::Stats::new()),
     )
 }
 
@@ -33,13 +33,14 @@ use crate::backend;
 use crate::hal::{self, BackendConfig as _};
 use crate::job::Bitcoin;
 use crate::node;
+use crate::pipeline;
 use crate::test_utils;
 use crate::work;
 
 use std::time::{Duration, Instant};
 
 use ii_async_compat::tokio;
-use tokio::time::delay_for;
+use tokio::time::sleep;
 
 use futures::channel::mpsc;
 use futures::lock::Mutex;
@@ -281,6 +282,7 @@ fn build_solvers() -> (
             Arc::new(backend::IgnoreHierarchy),
             engine_receiver,
             solution_queue_tx,
+            Arc::new(pipeline::Stats::new()),
         ),
     )
 }
@@ -361,7 +363,7 @@ pub async fn run<T: hal::Backend>(mut backend_config: T::Config) {
     // wait for hw to finish computation
     let timeout_started = Instant::now();
     while timeout_started.elapsed() < T::JOB_TIMEOUT {
-        delay_for(Duration::from_secs(1)).await;
+        sleep(Duration::from_secs(1)).await;
 
         if registry.lock().await.check_everything_solved(false) {
             break;
@@ -24,6 +24,9 @@
 //! to the actual work solving (mining) backends
 
 pub mod engine;
+pub mod midstate;
+pub mod policy;
+pub mod prefetch;
 mod solver;
 
 use crate::hal;
@@ -95,6 +98,8 @@ pub struct Assignment {
     pub midstates: Vec<Midstate>,
     /// nTime value for current work
     pub ntime: u32,
+    /// Number of seconds the backend is allowed to roll `ntime` of this work (0 means no rolling)
+    pub ntime_roll: u32,
 }
 
 impl Assignment {
@@ -104,9 +109,16 @@ impl Assignment {
             job,
             midstates,
             ntime,
+            ntime_roll: 0,
         }
     }
 
+    /// Allow backend to roll `ntime` of this work up to `ntime + ntime_roll`
+    pub fn with_ntime_roll(mut self, ntime_roll: u32) -> Self {
+        self.ntime_roll = ntime_roll;
+        self
+    }
+
     /// Return origin from which the work has been generated
     #[inline]
     pub fn origin(&self) -> Weak<dyn node::Client> {
@@ -125,10 +137,22 @@ impl Assignment {
         self.job.bits()
     }
 
-    /// Return number of generated work associated within this work assignment
+    /// Return number of generated work associated within this work assignment (each rolled
+    /// ntime value multiplies the work)
     #[inline]
     pub fn generated_work_amount(&self) -> usize {
-        self.midstates.len()
+        self.midstates.len() * (self.ntime_roll as usize + 1)
+    }
+
+    #[inline]
+    pub fn has_valid_job(&self) -> bool {
+        self.job.is_valid()
+    }
+
+    /// Return double SHA256 hash of the previous block header
+    #[inline]
+    pub fn previous_hash(&self) -> &ii_bitcoin::DHash {
+        self.job.previous_hash()
     }
 }
 
@@ -189,9 +213,23 @@ impl Solution {
         self.solution.nonce()
     }
 
+    /// Return ntime of the solution including ntime rolled by the backend
     #[inline]
     pub fn time(&self) -> u32 {
-        self.work.ntime
+        self.work.ntime.wrapping_add(self.ntime_offset())
+    }
+
+    /// Number of seconds the backend rolled ntime of the original work. The value is reported
+    /// by the backend so it has to be checked with `has_valid_ntime` before use.
+    #[inline]
+    pub fn ntime_offset(&self) -> u32 {
+        self.solution.ntime_offset()
+    }
+
+    /// Check that the backend has not rolled ntime out of the range allowed for the work
+    #[inline]
+    pub fn has_valid_ntime(&self) -> bool {
+        self.ntime_offset() <= self.work.ntime_roll
     }
 
     #[inline]
@@ -247,6 +285,12 @@ impl Solution {
         self.work.job.is_valid()
     }
 
+    /// Return work solver (backend node) which has found this solution
+    #[inline]
+    pub fn solver(&self) -> Option<node::DynInfo> {
+        self.work.path.last().cloned()
+    }
+
     /// Return the whole unique path starting from job origin and ending in backend.
     pub fn path(&self) -> node::Path {
         // Arc does not support dynamic casting to trait bounds so there must be used another Arc
@@ -284,6 +328,12 @@ pub trait Engine: Debug + Send + Sync {
     fn is_exhausted(&self) -> bool;
 
     fn next_work(&self) -> LoopState<Assignment>;
+
+    /// Generate work for the work generator with index `generator_idx`. Composite engines can use
+    /// it to assign each generator (e.g. a hashboard) its own source of work.
+    fn next_work_for(&self, _generator_idx: usize) -> LoopState<Assignment> {
+        self.next_work()
+    }
 }
 
 /// Shared work engine type
@@ -330,7 +380,7 @@ impl EngineSenderInner {
     fn re_broadcast(&mut self) {
         if let Some(sender) = &self.sender {
             sender
-                .broadcast(self.current_engine.clone())
+                .send(self.current_engine.clone())
                 .expect("cannot broadcast work engine");
         }
     }
@@ -349,6 +399,22 @@ impl EngineSenderInner {
         self.broadcast_engine(engine);
     }
 
+    /// Generates a work engine for each of the `jobs` and broadcasts them at once so that all
+    /// jobs are mined in parallel
+    fn broadcast_jobs(&mut self, jobs: Vec<Arc<dyn job::Bitcoin>>) {
+        let engine_generator = self
+            .engine_generator
+            .as_ref()
+            .expect("BUG: missing engine generator");
+        let mut engines: Vec<_> = jobs.into_iter().map(engine_generator).collect();
+        let engine: DynEngine = if engines.len() == 1 {
+            engines.pop().expect("BUG: missing work engine")
+        } else {
+            Arc::new(engine::Partitioned::new(engines))
+        };
+        self.broadcast_engine(engine);
+    }
+
     fn invalidate(&mut self) {
         self.current_engine = Arc::new(engine::ExhaustedWork);
         self.re_broadcast();
@@ -417,6 +483,11 @@ impl EngineSender {
         self.lock_inner().broadcast_job(job)
     }
 
+    #[inline]
+    pub fn broadcast_jobs(&self, jobs: Vec<Arc<dyn job::Bitcoin>>) {
+        self.lock_inner().broadcast_jobs(jobs)
+    }
+
     #[inline]
     pub fn invalidate(&self) {
         self.lock_inner().invalidate();
@@ -454,21 +525,27 @@ impl EngineReceiver {
     /// Provides the most recent WorkEngine as long as the engine is able to provide any work.
     /// Otherwise, it sleeps and waits for a new
     pub async fn get_engine(&mut self) -> Option<DynEngine> {
-        let mut engine = self.watch_receiver.borrow().clone();
+        let mut engine = self.watch_receiver.borrow_and_update().clone();
         loop {
             if !engine.is_exhausted() {
                 // return only work engine which can generate some work
                 return Some(engine);
             }
-            match self.watch_receiver.next().await {
+            if self.watch_receiver.changed().await.is_err() {
                 // end of stream
-                None => return None,
-                // new work engine received
-                Some(value) => engine = value,
+                return None;
             }
+            // new work engine received
+            engine = self.watch_receiver.borrow_and_update().clone();
         }
     }
 
+    /// Check whether the most recent WorkEngine can provide some work without waiting
+    #[inline]
+    pub fn has_work(&self) -> bool {
+        !self.watch_receiver.borrow().is_exhausted()
+    }
+
     /// This function should be called just when last entry has been taken out of engine
     #[inline]
     pub fn handle_exhausted(&self, engine: DynEngine) {
@@ -479,6 +556,8 @@ impl EngineReceiver {
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::hal::BackendSolution as _;
+    use crate::job::Bitcoin as _;
 
     #[test]
     fn test_block_double_hash() {
@@ -494,4 +573,57 @@ pub mod test {
             assert_eq!(&block.hash, hash);
         }
     }
+
+    /// Solution with ntime rolled by the backend
+    #[derive(Debug)]
+    struct RolledSolution {
+        inner: crate::test_utils::TestSolution,
+        ntime_offset: u32,
+    }
+
+    impl hal::BackendSolution for RolledSolution {
+        fn nonce(&self) -> u32 {
+            self.inner.nonce()
+        }
+
+        fn midstate_idx(&self) -> usize {
+            self.inner.midstate_idx()
+        }
+
+        fn solution_idx(&self) -> usize {
+            self.inner.solution_idx()
+        }
+
+        fn target(&self) -> &ii_bitcoin::Target {
+            self.inner.target()
+        }
+
+        fn ntime_offset(&self) -> u32 {
+            self.ntime_offset
+        }
+    }
+
+    #[test]
+    fn test_solution_ntime_offset() {
+        let block = &crate::test_utils::TEST_BLOCKS[0];
+        let solution = |ntime_roll, ntime_offset| {
+            let work = Assignment::from(block).with_ntime_roll(ntime_roll);
+            let solution = RolledSolution {
+                inner: crate::test_utils::TestSolution::new(block),
+                ntime_offset,
+            };
+            Solution::new(work, solution, None)
+        };
+
+        let valid = solution(5, 5);
+        assert!(valid.has_valid_ntime());
+        assert_eq!(valid.time(), block.time() + 5);
+
+        // misbehaving backend must not panic the miner
+        let invalid = solution(4, 5);
+        assert!(!invalid.has_valid_ntime());
+        let invalid = solution(0, u32::MAX);
+        assert!(!invalid.has_valid_ntime());
+        let _ = invalid.time();
+    }
 }
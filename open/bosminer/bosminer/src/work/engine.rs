@@ -25,7 +25,7 @@
 use super::*;
 use crate::job;
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -43,9 +43,6 @@ impl Engine for ExhaustedWork {
     }
 }
 
-/// BIP320 specifies sixteen bits in block header nVersion field
-/// The maximal index represent the range which is excluded so it must be incremented by 1.
-const BIP320_UPPER_BOUND_EXCLUSIVE_INDEX: u32 = ii_bitcoin::BIP320_VERSION_MAX + 1;
 /// Once we exhaust the version we roll, we have to roll ntime.
 /// The current limit gives us support for miners with speed up to 2.4 PH/s
 /// hash_space * roll_ntime_seconds / new_stratum_job_every_sec = 2**(32 + 16) * 256 / 30 = 2.4e15
@@ -109,8 +106,8 @@ impl AtomicRange {
                 Some(next) => {
                     if self
                         .curr_index
-                        .compare_and_swap(current, next, Ordering::Relaxed)
-                        != current
+                        .compare_exchange(current, next, Ordering::Relaxed, Ordering::Relaxed)
+                        .is_err()
                     {
                         // try it again when concurrent task has been faster
                         continue;
@@ -133,6 +130,9 @@ impl AtomicRange {
 /// range is full exhausted. After version has been rolled over, ntime is incremented and version
 /// resetted to 0. The limit of `ntime` range is determined by `ROLL_NTIME_SECONDS`.
 ///
+/// Only BIP320 bits allowed by the job version mask are rolled. When the job doesn't allow enough
+/// bits for the requested number of midstates, each work has fewer midstates.
+///
 /// TODO: Rolling ntime together with version IS A HACK. This needs to be fixed properly by raising
 /// `ntime` in sync with real-time clock.
 #[derive(Debug, Clone)]
@@ -141,47 +141,121 @@ pub struct VersionRolling {
     /// Number of midstates that each generated work covers
     midstate_count: usize,
     /// Current range of the rolled part of the version (before BIP320 shift)
-    /// We keep current version in lower 16 bits and `ntime_offset`
-    /// in upper 8 bits. When version overflows, the ntime_offset gets
+    /// We keep current version in lower bits (`version_space`) and `ntime_offset`
+    /// in upper bits. When version overflows, the ntime_offset gets
     /// automatically incremented.
     curr_range: AtomicRange,
-    /// Base Bitcoin block header version with BIP320 bits cleared
+    /// BIP320 bits of the version which are rolled
+    version_mask: u32,
+    /// Number of versions available for each ntime step
+    version_space: u32,
+    /// Base Bitcoin block header version with rolled bits cleared
     base_version: u32,
+    /// Implementation of midstate computation
+    midstate_compute: midstate::DynCompute,
+    /// Number of seconds the backend rolls ntime of each work in hardware. The engine then
+    /// steps ntime by `ntime_roll + 1` seconds after the version space has been exhausted.
+    ntime_roll: u32,
 }
 
 impl VersionRolling {
     pub fn new(job: Arc<dyn job::Bitcoin>, midstate_count: usize) -> Self {
-        let base_version = job.version() & !ii_bitcoin::BIP320_VERSION_MASK;
+        Self::with_midstate_compute(job, midstate_count, midstate::default())
+    }
+
+    pub fn with_midstate_compute(
+        job: Arc<dyn job::Bitcoin>,
+        midstate_count: usize,
+        midstate_compute: midstate::DynCompute,
+    ) -> Self {
+        let version_mask = job.version_mask() & ii_bitcoin::BIP320_VERSION_MASK;
+        Self::with_version_mask(job, version_mask, midstate_count, midstate_compute)
+    }
+
+    /// Engine which keeps block version of the job intact and rolls only ntime. Each work has
+    /// exactly one midstate so it is used when AsicBoost is disabled.
+    pub fn with_fixed_version(
+        job: Arc<dyn job::Bitcoin>,
+        midstate_compute: midstate::DynCompute,
+    ) -> Self {
+        Self::with_version_mask(job, 0, 1, midstate_compute)
+    }
+
+    fn with_version_mask(
+        job: Arc<dyn job::Bitcoin>,
+        version_mask: u32,
+        midstate_count: usize,
+        midstate_compute: midstate::DynCompute,
+    ) -> Self {
+        let version_space = Self::get_version_space(version_mask);
+        let midstate_count = midstate_count.min(version_space as usize);
+        let base_version = job.version() & !version_mask;
         // we have to be sure we have no "leftover" midstates when we roll
-        assert_eq!(
-            BIP320_UPPER_BOUND_EXCLUSIVE_INDEX % (midstate_count as u32),
-            0
-        );
+        assert_eq!(version_space % (midstate_count as u32), 0);
         Self {
             job,
             midstate_count,
-            curr_range: AtomicRange::new(
-                0,
-                BIP320_UPPER_BOUND_EXCLUSIVE_INDEX * ROLL_NTIME_SECONDS,
-                midstate_count as u32,
-            ),
+            curr_range: Self::new_range(version_space, midstate_count, 0),
+            version_mask,
+            version_space,
             base_version,
+            midstate_compute,
+            ntime_roll: 0,
         }
     }
 
-    /// Convert the allocated index to a block version as per BIP320
+    /// Number of midstates (up to `midstate_count`) in work generated for the `job`. It is lower
+    /// when the job version mask doesn't allow rolling enough bits.
+    pub fn supported_midstate_count(job: &dyn job::Bitcoin, midstate_count: usize) -> usize {
+        let version_space =
+            Self::get_version_space(job.version_mask() & ii_bitcoin::BIP320_VERSION_MASK);
+        midstate_count.min(version_space as usize)
+    }
+
+    /// Number of versions which can be created by rolling bits in `version_mask`
+    #[inline]
+    fn get_version_space(version_mask: u32) -> u32 {
+        1 << version_mask.count_ones()
+    }
+
+    /// Let the backend roll ntime of each work by up to `ntime_roll` seconds (limited by
+    /// `ROLL_NTIME_SECONDS`). It has to be called before any work is generated.
+    pub fn with_ntime_roll(mut self, ntime_roll: u32) -> Self {
+        let ntime_roll = ntime_roll.min(ROLL_NTIME_SECONDS - 1);
+        self.curr_range = Self::new_range(self.version_space, self.midstate_count, ntime_roll);
+        self.ntime_roll = ntime_roll;
+        self
+    }
+
+    /// Range of indexes covering all versions for each ntime step
+    fn new_range(version_space: u32, midstate_count: usize, ntime_roll: u32) -> AtomicRange {
+        let ntime_steps = ROLL_NTIME_SECONDS / (ntime_roll + 1);
+        AtomicRange::new(0, version_space * ntime_steps, midstate_count as u32)
+    }
+
+    /// Convert the allocated index to a block version as per BIP320. Bits of the index are
+    /// scattered to the rolled bits of the version (from the least significant one).
     #[inline]
     fn get_block_version(&self, index: u32) -> u32 {
-        let version = index % BIP320_UPPER_BOUND_EXCLUSIVE_INDEX;
-        assert!(version <= ii_bitcoin::BIP320_VERSION_MAX);
-        self.base_version | (version << ii_bitcoin::BIP320_VERSION_SHIFT)
+        let mut index = index % self.version_space;
+        let mut mask = self.version_mask;
+        let mut version = self.base_version;
+        while mask != 0 {
+            let bit = mask & mask.wrapping_neg();
+            if index & 1 != 0 {
+                version |= bit;
+            }
+            index >>= 1;
+            mask &= mask - 1;
+        }
+        version
     }
 
     /// Convert the allocated index to a ntime offset
     #[inline]
     fn get_ntime_offset(&self, index: u32) -> u32 {
-        let ntime_offset = index / BIP320_UPPER_BOUND_EXCLUSIVE_INDEX;
-        assert!(ntime_offset < ROLL_NTIME_SECONDS);
+        let ntime_offset = index / self.version_space * (self.ntime_roll + 1);
+        assert!(ntime_offset + self.ntime_roll < ROLL_NTIME_SECONDS);
         ntime_offset
     }
 }
@@ -209,22 +283,19 @@ impl Engine for VersionRolling {
         let mut midstates = Vec::with_capacity(self.midstate_count);
 
         // prepare block chunk1 with all invariants
-        let mut block_chunk1 = ii_bitcoin::BlockHeader {
+        let block_chunk1 = ii_bitcoin::BlockHeader {
             previous_hash: self.job.previous_hash().into_inner(),
             merkle_root: self.job.merkle_root().into_inner(),
             ..Default::default()
         };
 
         // generate all midstates from given range of indexes
-        for index in current..next {
-            // use index for generation compatible header version
-            let version = self.get_block_version(index);
-            block_chunk1.version = version;
-            midstates.push(Midstate {
-                version,
-                state: block_chunk1.midstate(),
-            })
-        }
+        // use index for generation compatible header version
+        let versions: Vec<u32> = (current..next)
+            .map(|index| self.get_block_version(index))
+            .collect();
+        self.midstate_compute
+            .compute(&block_chunk1, &versions, &mut midstates);
 
         // Once we exhaust version-rolling-space, we start rolling ntime.
         // We can be sure ntime offset is common for all blocks, because `midstate_count`
@@ -233,7 +304,8 @@ impl Engine for VersionRolling {
         let ntime_offset = self.get_ntime_offset(current);
         assert_eq!(ntime_offset, self.get_ntime_offset(next - 1));
 
-        let work = Assignment::new(self.job.clone(), midstates, self.job.time() + ntime_offset);
+        let work = Assignment::new(self.job.clone(), midstates, self.job.time() + ntime_offset)
+            .with_ntime_roll(self.ntime_roll);
         if self.curr_range.is_exhausted(next) {
             // when the whole version space has been exhausted then mark the generated work as
             // a last one (the next call of this method will return 'Exhausted')
@@ -244,12 +316,74 @@ impl Engine for VersionRolling {
     }
 }
 
+/// Composite engine used when a client mines several jobs at once (e.g. one per mining channel).
+/// Each work generator (e.g. a hashboard) is assigned to one of the engines by its index so that
+/// all solutions of the generator are submitted in the same channel. A generator whose engine is
+/// exhausted gets work from the following ones. Work requested without generator index is
+/// distributed in round-robin fashion.
+#[derive(Debug)]
+pub struct Partitioned {
+    engines: Vec<DynEngine>,
+    /// Index of engine which will be asked for the next work without generator index
+    next_idx: AtomicUsize,
+}
+
+impl Partitioned {
+    pub fn new(engines: Vec<DynEngine>) -> Self {
+        assert!(!engines.is_empty(), "BUG: no engines to partition");
+        Self {
+            engines,
+            next_idx: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Engine for Partitioned {
+    fn terminate(&self) {
+        for engine in &self.engines {
+            engine.terminate();
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.engines.iter().all(|engine| engine.is_exhausted())
+    }
+
+    fn next_work(&self) -> LoopState<Assignment> {
+        self.next_work_for(self.next_idx.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn next_work_for(&self, generator_idx: usize) -> LoopState<Assignment> {
+        let engine_count = self.engines.len();
+        let start_idx = generator_idx % engine_count;
+        for i in 0..engine_count {
+            let engine = &self.engines[(start_idx + i) % engine_count];
+            match engine.next_work() {
+                // try the following engine
+                LoopState::Exhausted => continue,
+                LoopState::Break(work) | LoopState::Continue(work) => {
+                    return if self.is_exhausted() {
+                        LoopState::Break(work)
+                    } else {
+                        LoopState::Continue(work)
+                    };
+                }
+            }
+        }
+        LoopState::Exhausted
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
     use crate::job::Bitcoin;
     use crate::test_utils;
 
+    /// BIP320 specifies sixteen bits in block header nVersion field
+    /// The maximal index represent the range which is excluded so it must be incremented by 1.
+    const BIP320_UPPER_BOUND_EXCLUSIVE_INDEX: u32 = ii_bitcoin::BIP320_VERSION_MAX + 1;
+
     fn compare_range(start: u32, stop: u32, step: u32) {
         let range = AtomicRange::new(start, stop, step);
         for i in (start..stop - (step - 1)).step_by(step as usize) {
@@ -357,6 +491,64 @@ pub mod test {
         assert!(!engine.is_exhausted());
     }
 
+    #[test]
+    fn test_ntime_roll() {
+        const NTIME_ROLL: u32 = 3;
+        const NTIME_STEPS: u32 = ROLL_NTIME_SECONDS / (NTIME_ROLL + 1);
+        let job = Arc::new(test_utils::TEST_BLOCKS[0]);
+        let engine = VersionRolling::new(job.clone(), 1).with_ntime_roll(NTIME_ROLL);
+
+        let work = engine.next_work().unwrap();
+        assert_eq!(work.ntime_roll, NTIME_ROLL);
+        assert_eq!(work.generated_work_amount(), NTIME_ROLL as usize + 1);
+
+        // ntime is stepped over the range rolled by backend
+        engine.curr_range.curr_index.store(
+            make_compound_index(0, ii_bitcoin::BIP320_VERSION_MAX),
+            Ordering::Relaxed,
+        );
+        engine.next_work();
+        match engine.next_work() {
+            LoopState::Continue(work) => {
+                assert_eq!(get_block_version(&job, 0), work.midstates[0].version);
+                assert_eq!(get_ntime(&job, NTIME_ROLL + 1), work.ntime);
+            }
+            _ => panic!("expected 'LoopState::Continue'"),
+        }
+
+        // the last work may be rolled up to the limit
+        engine.curr_range.curr_index.store(
+            make_compound_index(NTIME_STEPS - 1, ii_bitcoin::BIP320_VERSION_MAX),
+            Ordering::Relaxed,
+        );
+        match engine.next_work() {
+            LoopState::Break(work) => {
+                assert_eq!(
+                    get_ntime(&job, ROLL_NTIME_SECONDS - 1),
+                    work.ntime + work.ntime_roll
+                );
+            }
+            _ => panic!("expected 'LoopState::Break'"),
+        }
+        assert!(engine.is_exhausted());
+    }
+
+    #[test]
+    fn test_fixed_version() {
+        let job = Arc::new(test_utils::TEST_BLOCKS[0]);
+        let engine = VersionRolling::with_fixed_version(job.clone(), midstate::default());
+
+        // only ntime is rolled
+        for ntime_index in 0..ROLL_NTIME_SECONDS {
+            assert!(!engine.is_exhausted());
+            let work = engine.next_work().unwrap();
+            assert_eq!(work.midstates.len(), 1);
+            assert_eq!(work.midstates[0].version, job.version());
+            assert_eq!(get_ntime(&job, ntime_index), work.ntime);
+        }
+        assert!(engine.is_exhausted());
+    }
+
     #[test]
     fn test_exhausted_work() {
         // use first test block for job
@@ -403,4 +595,161 @@ pub mod test {
         }
         assert!(engine.is_exhausted());
     }
+
+    /// Test job which allows rolling only some of the version bits
+    #[derive(Debug)]
+    struct MaskedJob {
+        block: test_utils::TestBlock,
+        version_mask: u32,
+    }
+
+    impl job::Bitcoin for MaskedJob {
+        fn origin(&self) -> std::sync::Weak<dyn crate::node::Client> {
+            self.block.origin()
+        }
+
+        fn version(&self) -> u32 {
+            self.block.version()
+        }
+
+        fn version_mask(&self) -> u32 {
+            self.version_mask
+        }
+
+        fn previous_hash(&self) -> &ii_bitcoin::DHash {
+            self.block.previous_hash()
+        }
+
+        fn merkle_root(&self) -> &ii_bitcoin::DHash {
+            self.block.merkle_root()
+        }
+
+        fn time(&self) -> u32 {
+            self.block.time()
+        }
+
+        fn bits(&self) -> u32 {
+            self.block.bits()
+        }
+
+        fn target(&self) -> ii_bitcoin::Target {
+            self.block.target()
+        }
+
+        fn is_valid(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_restricted_version_mask() {
+        let block = test_utils::TEST_BLOCKS[0];
+        // only two BIP320 bits can be rolled, the bit outside of BIP320 is ignored
+        let version_mask = 0x0000_2000 | 0x1000_0000 | 0x0000_0001;
+        let job = Arc::new(MaskedJob {
+            block,
+            version_mask,
+        });
+        assert_eq!(VersionRolling::supported_midstate_count(job.as_ref(), 4), 4);
+        let engine = VersionRolling::new(job.clone(), 4);
+
+        let work = engine.next_work().unwrap();
+        let versions: Vec<_> = work
+            .midstates
+            .iter()
+            .map(|midstate| midstate.version)
+            .collect();
+        assert_eq!(
+            versions,
+            vec![
+                block.version(),
+                block.version() | 0x0000_2000,
+                block.version() | 0x1000_0000,
+                block.version() | 0x1000_2000,
+            ]
+        );
+        // the version space is exhausted so ntime is rolled
+        let work = engine.next_work().unwrap();
+        assert_eq!(work.midstates[0].version, block.version());
+        assert_eq!(work.ntime, block.time() + 1);
+
+        // midstates are reduced when the job doesn't allow rolling enough bits
+        let job = Arc::new(MaskedJob {
+            block,
+            version_mask: 0x0000_2000,
+        });
+        assert_eq!(VersionRolling::supported_midstate_count(job.as_ref(), 4), 2);
+        let work = VersionRolling::new(job, 4).next_work().unwrap();
+        assert_eq!(work.midstates.len(), 2);
+
+        // fixed version
+        let job = Arc::new(MaskedJob {
+            block,
+            version_mask: 0,
+        });
+        assert_eq!(VersionRolling::supported_midstate_count(job.as_ref(), 4), 1);
+        let engine = VersionRolling::new(job, 4);
+        for i in 0..2 {
+            let work = engine.next_work().unwrap();
+            assert_eq!(work.midstates.len(), 1);
+            assert_eq!(work.midstates[0].version, block.version());
+            assert_eq!(work.ntime, block.time() + i);
+        }
+    }
+
+    #[test]
+    fn test_partitioned() {
+        let jobs: Vec<_> = test_utils::TEST_BLOCKS
+            .iter()
+            .take(2)
+            .map(|block| Arc::new(*block))
+            .collect();
+        let engines: Vec<_> = jobs
+            .iter()
+            .map(|job| Arc::new(VersionRolling::new(job.clone(), 1)))
+            .collect();
+        let engine = Partitioned::new(
+            engines
+                .iter()
+                .map(|engine| engine.clone() as DynEngine)
+                .collect(),
+        );
+
+        // without generator index work is alternately generated from both jobs
+        for i in 0..4 {
+            let work = engine.next_work().unwrap();
+            assert_eq!(jobs[i % 2].merkle_root_tail(), work.merkle_root_tail());
+            assert!(!engine.is_exhausted());
+        }
+
+        // each generator gets work from its own job
+        for generator_idx in 0..4 {
+            for _ in 0..2 {
+                let work = engine.next_work_for(generator_idx).unwrap();
+                assert_eq!(
+                    jobs[generator_idx % 2].merkle_root_tail(),
+                    work.merkle_root_tail()
+                );
+            }
+        }
+
+        // exhausted engine is skipped
+        engines[0].terminate();
+        assert!(!engine.is_exhausted());
+        for generator_idx in 0..2 {
+            match engine.next_work_for(generator_idx) {
+                LoopState::Continue(work) => {
+                    assert_eq!(jobs[1].merkle_root_tail(), work.merkle_root_tail())
+                }
+                _ => panic!("expected 'LoopState::Continue'"),
+            }
+        }
+
+        engine.terminate();
+        assert!(engine.is_exhausted());
+        match engine.next_work() {
+            LoopState::Exhausted => {}
+            _ => panic!("expected 'LoopState::Exhausted'"),
+        }
+    }
 }
@@ -0,0 +1,287 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Computation of SHA256 midstates for the work engine. Midstates of all versions rolled in one
+//! work are computed at once so that an implementation can process several versions together.
+
+use super::Midstate;
+
+use ii_bitcoin::BLOCK_HEADER_CHUNK1_SIZE;
+
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub trait Compute: Debug + Send + Sync {
+    /// Name of the implementation used for logging and selection in configuration
+    fn name(&self) -> &'static str;
+
+    /// Compute midstates of `header` with each version from `versions` and append them to
+    /// `midstates` in the same order
+    fn compute(
+        &self,
+        header: &ii_bitcoin::BlockHeader,
+        versions: &[u32],
+        midstates: &mut Vec<Midstate>,
+    );
+}
+
+/// Shared midstate computation type
+pub type DynCompute = Arc<dyn Compute>;
+
+/// Implementation used when the backend doesn't select any other
+pub fn default() -> DynCompute {
+    Arc::new(Generic)
+}
+
+/// Find implementation by its name
+pub fn from_name(name: &str) -> Option<DynCompute> {
+    match name {
+        Generic::NAME => Some(Arc::new(Generic)),
+        Interleaved::NAME => Some(Arc::new(Interleaved)),
+        _ => None,
+    }
+}
+
+/// Straightforward computation of one midstate after another with SHA256 engine
+#[derive(Debug, Default)]
+pub struct Generic;
+
+impl Generic {
+    pub const NAME: &'static str = "generic";
+}
+
+impl Compute for Generic {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn compute(
+        &self,
+        header: &ii_bitcoin::BlockHeader,
+        versions: &[u32],
+        midstates: &mut Vec<Midstate>,
+    ) {
+        let mut header = *header;
+        for &version in versions {
+            header.version = version;
+            midstates.push(Midstate {
+                version,
+                state: header.midstate(),
+            });
+        }
+    }
+}
+
+/// Number of midstates computed together
+const LANES: usize = 4;
+
+/// One SHA256 word for each computed midstate
+type Lanes = [u32; LANES];
+
+/// SHA256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA256 initial hash value
+const H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Apply `f` to each lane of its arguments
+#[inline(always)]
+fn lanes<F: Fn(usize) -> u32>(f: F) -> Lanes {
+    let mut result = [0; LANES];
+    for (lane, word) in result.iter_mut().enumerate() {
+        *word = f(lane);
+    }
+    result
+}
+
+/// Midstates of versions differing only in the first word of the chunk are computed together
+/// with SHA256 rounds interleaved over lanes of plain arrays. The message schedule shared by all
+/// versions is prepared only once per work. See `benches/midstate.rs` of the S9 backend for
+/// comparison with `Generic`.
+#[derive(Debug, Default)]
+pub struct Interleaved;
+
+impl Interleaved {
+    pub const NAME: &'static str = "interleaved";
+
+    /// SHA256 compression of the first chunk for all lanes
+    fn compress(schedule: &mut [Lanes; 64]) -> [Lanes; 8] {
+        for t in 16..64 {
+            schedule[t] = lanes(|l| {
+                let w2 = schedule[t - 2][l];
+                let w15 = schedule[t - 15][l];
+                let sigma1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+                let sigma0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+                sigma1
+                    .wrapping_add(schedule[t - 7][l])
+                    .wrapping_add(sigma0)
+                    .wrapping_add(schedule[t - 16][l])
+            });
+        }
+
+        let mut state: [Lanes; 8] = [[0; LANES]; 8];
+        for (word, h) in state.iter_mut().zip(H.iter()) {
+            *word = [*h; LANES];
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for t in 0..64 {
+            let t1 = lanes(|l| {
+                let sigma1 = e[l].rotate_right(6) ^ e[l].rotate_right(11) ^ e[l].rotate_right(25);
+                let ch = (e[l] & f[l]) ^ (!e[l] & g[l]);
+                h[l].wrapping_add(sigma1)
+                    .wrapping_add(ch)
+                    .wrapping_add(K[t])
+                    .wrapping_add(schedule[t][l])
+            });
+            let t2 = lanes(|l| {
+                let sigma0 = a[l].rotate_right(2) ^ a[l].rotate_right(13) ^ a[l].rotate_right(22);
+                let maj = (a[l] & b[l]) ^ (a[l] & c[l]) ^ (b[l] & c[l]);
+                sigma0.wrapping_add(maj)
+            });
+            h = g;
+            g = f;
+            f = e;
+            e = lanes(|l| d[l].wrapping_add(t1[l]));
+            d = c;
+            c = b;
+            b = a;
+            a = lanes(|l| t1[l].wrapping_add(t2[l]));
+        }
+
+        let result = [a, b, c, d, e, f, g, h];
+        for (word, value) in state.iter_mut().zip(result.iter()) {
+            *word = lanes(|l| word[l].wrapping_add(value[l]));
+        }
+        state
+    }
+}
+
+impl Compute for Interleaved {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn compute(
+        &self,
+        header: &ii_bitcoin::BlockHeader,
+        versions: &[u32],
+        midstates: &mut Vec<Midstate>,
+    ) {
+        // words of the first chunk are the same for all versions except the first one
+        let chunk1 = header.into_bytes();
+        let mut template = [[0; LANES]; 64];
+        for (word, bytes) in template
+            .iter_mut()
+            .zip(chunk1[..BLOCK_HEADER_CHUNK1_SIZE].chunks_exact(4))
+        {
+            *word = [u32::from_be_bytes(bytes.try_into().expect("BUG: invalid chunk")); LANES];
+        }
+
+        for group in versions.chunks(LANES) {
+            // the last incomplete group is padded with its first version
+            let mut schedule = template;
+            schedule[0] = lanes(|l| group.get(l).unwrap_or(&group[0]).swap_bytes());
+            let state = Self::compress(&mut schedule);
+
+            for (l, &version) in group.iter().enumerate() {
+                let mut bytes = [0u8; 32];
+                for (chunk, word) in bytes.chunks_exact_mut(4).zip(state.iter()) {
+                    chunk.copy_from_slice(&word[l].to_be_bytes());
+                }
+                midstates.push(Midstate {
+                    version,
+                    state: bytes.into(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils;
+
+    use ii_bitcoin::HashTrait as _;
+
+    fn compute_states(compute: &dyn Compute, versions: &[u32]) -> Vec<ii_bitcoin::Midstate> {
+        let block = &test_utils::TEST_BLOCKS[0];
+        let header = ii_bitcoin::BlockHeader {
+            previous_hash: block.previous_hash.into_inner(),
+            merkle_root: block.merkle_root.into_inner(),
+            ..Default::default()
+        };
+        let mut midstates = Vec::new();
+        compute.compute(&header, versions, &mut midstates);
+        assert_eq!(
+            midstates.iter().map(|m| m.version).collect::<Vec<_>>(),
+            versions
+        );
+        midstates.into_iter().map(|m| m.state).collect()
+    }
+
+    #[test]
+    fn test_interleaved_matches_generic() {
+        for count in 1..=9 {
+            let versions: Vec<u32> = (0..count).map(|i| 0x2000_0000 | (i << 13)).collect();
+            assert_eq!(
+                compute_states(&Interleaved, &versions),
+                compute_states(&Generic, &versions)
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_midstates() {
+        for block in test_utils::TEST_BLOCKS.iter() {
+            let header = ii_bitcoin::BlockHeader {
+                version: block.version,
+                previous_hash: block.previous_hash.into_inner(),
+                merkle_root: block.merkle_root.into_inner(),
+                ..Default::default()
+            };
+            let mut midstates = Vec::new();
+            Interleaved.compute(&header, &[block.version], &mut midstates);
+            assert_eq!(midstates[0].state, block.midstate);
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(from_name("generic").unwrap().name(), Generic::NAME);
+        assert_eq!(from_name("interleaved").unwrap().name(), Interleaved::NAME);
+        assert!(from_name("neon").is_none());
+        assert!(from_name("gpu").is_none());
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Policies deciding which client (pool) of a group supplies jobs for the work engine
+
+use bosminer_config::PoolSelection;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// State of a client as seen by `SelectionPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    /// Client is connected and provides jobs
+    pub running: bool,
+    /// Amount of work recently generated from the client jobs. Older work is decayed so that
+    /// the split of work evens out within a bounded time window.
+    pub recent_work: f64,
+    /// Relative weight of the client within its group
+    pub weight: f64,
+}
+
+pub trait SelectionPolicy: Debug + Send + Sync {
+    /// Inactive clients are kept connected so that the policy can switch to them immediately
+    fn keep_connected(&self) -> bool;
+
+    /// Return index of the candidate that should supply jobs or `None` when no candidate is
+    /// usable
+    fn select(&self, candidates: &[Candidate]) -> Option<usize>;
+}
+
+/// Shared selection policy type
+pub type DynSelectionPolicy = Arc<dyn SelectionPolicy>;
+
+/// Build policy object for the pool selection specified in configuration
+pub fn from_config(pool_selection: PoolSelection) -> DynSelectionPolicy {
+    match pool_selection {
+        PoolSelection::PrimaryWithBackup => Arc::new(PrimaryWithBackup),
+        PoolSelection::RoundRobin => Arc::new(RoundRobin),
+        PoolSelection::Weighted => Arc::new(Weighted),
+    }
+}
+
+/// The first running client supplies all the work, the following ones serve as a backup and
+/// they are connected only when all the preceding clients fail
+#[derive(Debug, Default)]
+pub struct PrimaryWithBackup;
+
+impl SelectionPolicy for PrimaryWithBackup {
+    fn keep_connected(&self) -> bool {
+        false
+    }
+
+    fn select(&self, candidates: &[Candidate]) -> Option<usize> {
+        candidates.iter().position(|candidate| candidate.running)
+    }
+}
+
+/// Index of the running candidate with the lowest `load` which is ignored when `None`
+fn least_loaded<F>(candidates: &[Candidate], load: F) -> Option<usize>
+where
+    F: Fn(&Candidate) -> Option<f64>,
+{
+    candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.running)
+        .filter_map(|(idx, candidate)| load(candidate).map(|load| (idx, load)))
+        .fold(
+            None,
+            |selected: Option<(usize, f64)>, (idx, load)| match selected {
+                Some((_, min_load)) if min_load <= load => selected,
+                _ => Some((idx, load)),
+            },
+        )
+        .map(|(idx, _)| idx)
+}
+
+/// Work is split evenly between all running clients
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl SelectionPolicy for RoundRobin {
+    fn keep_connected(&self) -> bool {
+        true
+    }
+
+    fn select(&self, candidates: &[Candidate]) -> Option<usize> {
+        least_loaded(candidates, |candidate| Some(candidate.recent_work))
+    }
+}
+
+/// Work is split between all running clients in proportion to their weights
+#[derive(Debug, Default)]
+pub struct Weighted;
+
+impl SelectionPolicy for Weighted {
+    fn keep_connected(&self) -> bool {
+        true
+    }
+
+    fn select(&self, candidates: &[Candidate]) -> Option<usize> {
+        // Select the client which is furthest behind its share of the work
+        least_loaded(candidates, |candidate| {
+            if candidate.weight > 0.0 {
+                Some(candidate.recent_work / candidate.weight)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(running: bool, recent_work: f64, weight: f64) -> Candidate {
+        Candidate {
+            running,
+            recent_work,
+            weight,
+        }
+    }
+
+    #[test]
+    fn test_primary_with_backup() {
+        let policy = PrimaryWithBackup;
+        assert_eq!(policy.select(&[]), None);
+        assert_eq!(
+            policy.select(&[candidate(false, 0.0, 1.0), candidate(false, 0.0, 1.0)]),
+            None
+        );
+        assert_eq!(
+            policy.select(&[candidate(true, 100.0, 1.0), candidate(true, 0.0, 1.0)]),
+            Some(0)
+        );
+        assert_eq!(
+            policy.select(&[candidate(false, 100.0, 1.0), candidate(true, 200.0, 1.0)]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let policy = RoundRobin;
+        assert_eq!(policy.select(&[candidate(false, 0.0, 1.0)]), None);
+        assert_eq!(
+            policy.select(&[
+                candidate(true, 100.0, 1.0),
+                candidate(false, 0.0, 1.0),
+                candidate(true, 50.0, 1.0)
+            ]),
+            Some(2)
+        );
+        // Weights are ignored
+        assert_eq!(
+            policy.select(&[candidate(true, 100.0, 10.0), candidate(true, 50.0, 1.0)]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_weighted() {
+        let policy = Weighted;
+        assert_eq!(policy.select(&[candidate(true, 0.0, 0.0)]), None);
+        assert_eq!(
+            policy.select(&[candidate(true, 100.0, 3.0), candidate(true, 50.0, 1.0)]),
+            Some(0)
+        );
+        assert_eq!(
+            policy.select(&[candidate(true, 300.0, 3.0), candidate(true, 50.0, 1.0)]),
+            Some(1)
+        );
+        assert_eq!(
+            policy.select(&[candidate(true, 300.0, 3.0), candidate(false, 0.0, 1.0)]),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Ahead-of-time buffering of generated work. Backend takes works from a buffer which is refilled
+//! by a separate task so that the work is ready as soon as there is room in the hardware work
+//! FIFO. Depth of the buffer adapts to the rate in which backend consumes the work and to the
+//! latency of refilling: it has to cover consumption until the refilling task catches up but
+//! every buffered work becomes stale when the job changes.
+
+use super::*;
+use crate::pipeline;
+
+use ii_async_compat::{task, tokio};
+use tokio::sync::Notify;
+
+use std::collections::VecDeque;
+
+/// Weight of a new sample in moving averages of consumption rate and refill latency
+const SMOOTHING: f64 = 0.1;
+/// Buffered works should cover this multiple of works consumed during refill latency
+const SAFETY_FACTOR: f64 = 2.0;
+
+/// Default minimal number of buffered works
+pub const DEFAULT_MIN_DEPTH: usize = 1;
+/// Default maximal number of buffered works
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+fn smooth(average: Option<f64>, sample: f64) -> f64 {
+    match average {
+        Some(average) => average + SMOOTHING * (sample - average),
+        None => sample,
+    }
+}
+
+/// Estimation of required buffer depth from measured consumption rate and refill latency
+#[derive(Debug, Clone)]
+pub struct Depth {
+    min: usize,
+    max: usize,
+    /// Works consumed per second
+    rate: Option<f64>,
+    /// Seconds from taking works out of the buffer to pushing the first refilled work
+    latency: Option<f64>,
+    last_consumed: Option<time::Instant>,
+}
+
+impl Depth {
+    pub fn new(min: usize, max: usize) -> Self {
+        assert!(min <= max, "BUG: minimal depth is greater than maximal");
+        Self {
+            min,
+            max,
+            rate: None,
+            latency: None,
+            last_consumed: None,
+        }
+    }
+
+    /// Account `count` works taken out of the buffer at `now`
+    pub fn account_consumed(&mut self, count: usize, now: time::Instant) {
+        if let Some(last_consumed) = self.last_consumed {
+            let interval = now.duration_since(last_consumed).as_secs_f64();
+            if interval > 0.0 {
+                self.rate = Some(smooth(self.rate, count as f64 / interval));
+            }
+        }
+        self.last_consumed = Some(now);
+    }
+
+    /// Account time needed for refilling the buffer
+    pub fn account_latency(&mut self, latency: time::Duration) {
+        self.latency = Some(smooth(self.latency, latency.as_secs_f64()));
+    }
+
+    /// Number of works which should be kept in the buffer
+    pub fn target(&self) -> usize {
+        let expected = match (self.rate, self.latency) {
+            (Some(rate), Some(latency)) => (rate * latency * SAFETY_FACTOR).ceil() as usize,
+            _ => self.min,
+        };
+        expected.max(self.min).min(self.max)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    works: VecDeque<Assignment>,
+    depth: Depth,
+    /// The last target depth reported to pipeline counters
+    target: usize,
+    /// Time when works have been taken and the buffer started to be refilled
+    refill_start: Option<time::Instant>,
+    /// Consumer has been dropped
+    closed: bool,
+    /// Generator has been shut down
+    finished: bool,
+}
+
+impl State {
+    fn update_target(&mut self, pipeline_stats: &pipeline::Stats) {
+        let target = self.depth.target();
+        if target != self.target {
+            pipeline_stats.account_prefetch_target(self.target, target);
+            self.target = target;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: StdMutex<State>,
+    pipeline_stats: Arc<pipeline::Stats>,
+    /// Signals the refilling task that works have been taken
+    room: Notify,
+    /// Signals the consumer that new works are available
+    ready: Notify,
+    /// Signals the refilling task that the consumer has been dropped
+    close: Notify,
+}
+
+impl Shared {
+    #[inline]
+    fn lock(&self) -> StdMutexGuard<State> {
+        self.state.lock().expect("BUG: cannot lock prefetch buffer")
+    }
+}
+
+/// Task generating works into the buffer until the consumer is dropped or the generator is shut
+/// down
+async fn refill_task(shared: Arc<Shared>, mut generator: Generator) {
+    loop {
+        loop {
+            let room = shared.room.notified();
+            {
+                let state = shared.lock();
+                if state.closed {
+                    return;
+                }
+                if state.works.len() < state.target {
+                    break;
+                }
+            }
+            room.await;
+        }
+        // waiting for new job from clients is not part of the refill latency
+        if !generator.has_work() {
+            shared.lock().refill_start = None;
+        }
+        let work = tokio::select! {
+            work = generator.generate() => work,
+            _ = shared.close.notified() => return,
+        };
+        let mut state = shared.lock();
+        match work {
+            Some(work) => {
+                if let Some(refill_start) = state.refill_start.take() {
+                    state.depth.account_latency(refill_start.elapsed());
+                    state.update_target(&shared.pipeline_stats);
+                }
+                state.works.push_back(work);
+                shared.pipeline_stats.account_prefetch_pushed(1);
+            }
+            None => state.finished = true,
+        }
+        let finished = state.finished;
+        drop(state);
+        shared.ready.notify_one();
+        if finished {
+            return;
+        }
+    }
+}
+
+/// Adaptive buffer of works generated ahead of time by the wrapped `Generator`
+#[derive(Debug)]
+pub struct Prefetch {
+    shared: Arc<Shared>,
+}
+
+impl Prefetch {
+    /// Start refilling the buffer from `generator`. Depth of the buffer is kept in range
+    /// `min_depth..=max_depth`.
+    pub fn new(generator: Generator, min_depth: usize, max_depth: usize) -> Self {
+        let pipeline_stats = generator.pipeline_stats().clone();
+        let depth = Depth::new(min_depth, max_depth);
+        let target = depth.target();
+        pipeline_stats.account_prefetch_target(0, target);
+        let shared = Arc::new(Shared {
+            state: StdMutex::new(State {
+                works: VecDeque::with_capacity(max_depth),
+                depth,
+                target,
+                refill_start: None,
+                closed: false,
+                finished: false,
+            }),
+            pipeline_stats,
+            room: Notify::new(),
+            ready: Notify::new(),
+            close: Notify::new(),
+        });
+        task::spawn_named("work prefetch", refill_task(shared.clone(), generator));
+        Self { shared }
+    }
+
+    /// Number of currently buffered works
+    pub fn depth(&self) -> usize {
+        self.shared.lock().works.len()
+    }
+
+    /// Number of works the buffer is currently refilled to
+    pub fn target_depth(&self) -> usize {
+        self.shared.lock().target
+    }
+
+    /// Work pipeline counters which should be updated by backend (e.g. full work FIFO)
+    #[inline]
+    pub fn pipeline_stats(&self) -> &Arc<pipeline::Stats> {
+        &self.shared.pipeline_stats
+    }
+
+    /// Take up to `count` works from the buffer and append them to `works` so the caller can reuse
+    /// its allocation. It waits only when the buffer is empty. Works of invalidated jobs are
+    /// discarded. No new work signals Generator shutdown.
+    pub async fn next_batch(&mut self, count: usize, works: &mut Vec<Assignment>) {
+        let mut underrun = false;
+        loop {
+            let ready = self.shared.ready.notified();
+            {
+                let mut state = self.shared.lock();
+                let pipeline_stats = &self.shared.pipeline_stats;
+
+                // purge stale works after job change
+                let buffered = state.works.len();
+                state.works.retain(|work| work.has_valid_job());
+                let stale = buffered - state.works.len();
+                if stale > 0 {
+                    pipeline_stats.account_prefetch_stale(stale);
+                }
+
+                let taken = count.min(state.works.len());
+                if taken > 0 {
+                    works.extend(state.works.drain(..taken));
+                    pipeline_stats.account_prefetch_taken(taken);
+
+                    let now = time::Instant::now();
+                    state.depth.account_consumed(taken, now);
+                    state.update_target(pipeline_stats);
+                    state.refill_start.get_or_insert(now);
+                    drop(state);
+                    self.shared.room.notify_one();
+                    return;
+                }
+                if state.finished {
+                    return;
+                }
+                if !underrun {
+                    underrun = true;
+                    pipeline_stats.account_prefetch_underrun();
+                }
+            }
+            // wake up the refilling task in case the stale works have been purged
+            self.shared.room.notify_one();
+            ready.await;
+        }
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        self.shared
+            .pipeline_stats
+            .account_prefetch_taken(state.works.len());
+        self.shared
+            .pipeline_stats
+            .account_prefetch_target(state.target, 0);
+        state.works.clear();
+        drop(state);
+        self.shared.close.notify_one();
+        self.shared.room.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::job::Bitcoin as _;
+    use crate::test_utils;
+
+    #[test]
+    fn test_depth() {
+        let mut depth = Depth::new(2, 10);
+        assert_eq!(depth.target(), 2);
+
+        let now = time::Instant::now();
+        depth.account_consumed(4, now);
+        // rate is not known until the second consumption
+        depth.account_latency(time::Duration::from_millis(100));
+        assert_eq!(depth.target(), 2);
+
+        // 20 works per second consumed during 100 ms latency with safety factor
+        depth.account_consumed(4, now + time::Duration::from_millis(200));
+        assert_eq!(depth.target(), 4);
+
+        // limited by maximal depth
+        depth.account_latency(time::Duration::from_secs(10));
+        assert_eq!(depth.target(), 10);
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let mut depth = Depth::new(3, 3);
+        let now = time::Instant::now();
+        depth.account_consumed(1, now);
+        depth.account_consumed(1, now + time::Duration::from_secs(1));
+        depth.account_latency(time::Duration::from_millis(1));
+        assert_eq!(depth.target(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let work_solver = test_utils::create_test_work_solver();
+        let generator = test_utils::create_test_work_generator(work_solver);
+        let pipeline_stats = generator.pipeline_stats().clone();
+        let mut prefetch = Prefetch::new(generator, 2, 4);
+
+        let mut works = vec![];
+        loop {
+            let len = works.len();
+            prefetch.next_batch(3, &mut works).await;
+            if works.len() == len {
+                break;
+            }
+            assert!(works.len() - len <= 3);
+        }
+
+        // all works are delivered in the order they have been generated
+        assert_eq!(works.len(), test_utils::TEST_BLOCKS.len());
+        for (block, work) in test_utils::TEST_BLOCKS.iter().zip(works.iter()) {
+            assert_eq!(block.merkle_root_tail(), work.merkle_root_tail());
+        }
+        assert_eq!(prefetch.depth(), 0);
+        assert_eq!(pipeline_stats.snapshot().prefetch_depth, 0);
+
+        drop(prefetch);
+        assert_eq!(pipeline_stats.snapshot().prefetch_target_depth, 0);
+    }
+}
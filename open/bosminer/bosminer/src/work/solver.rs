@@ -23,6 +23,7 @@
 use super::*;
 use crate::backend;
 use crate::node;
+use crate::pipeline;
 
 use futures::channel::mpsc;
 use futures::lock::Mutex;
@@ -64,12 +65,16 @@ where
         hierarchy_builder: Arc<dyn backend::HierarchyBuilder>,
         engine_receiver: EngineReceiver,
         solution_sender: mpsc::UnboundedSender<Solution>,
+        pipeline_stats: Arc<pipeline::Stats>,
     ) -> Self {
         Self {
             node: NodeType::Base(base_work_solver),
             path: vec![],
             engine_receiver,
-            solution_sender: SolutionSender(solution_sender),
+            solution_sender: SolutionSender {
+                sender: solution_sender,
+                pipeline_stats,
+            },
             hierarchy_builder,
         }
     }
@@ -140,10 +145,14 @@ where
         let inner_work_solver = Arc::new(Mutex::new(None));
 
         let path = self.get_path();
+        let pipeline_stats = self.solution_sender.pipeline_stats().clone();
+        let generator_idx = pipeline_stats.add_generator();
         let work_generator = Generator::new(
+            generator_idx,
             self.engine_receiver.clone(),
             path,
             inner_work_solver.clone(),
+            pipeline_stats,
         );
         let solution_sender = self.solution_sender.clone();
 
@@ -164,6 +173,8 @@ where
 /// `MiningWork` as possible from it.
 #[derive(Debug, Clone)]
 pub struct Generator {
+    /// Index of the generator unique within the mining backend
+    idx: usize,
     /// Unique path describing internal hierarchy of backend solvers
     path: WorkSolverPath,
     /// Work solver node associated with this generator
@@ -172,21 +183,39 @@ pub struct Generator {
     work_solver: Arc<Mutex<Option<Weak<dyn node::WorkSolver>>>>,
     /// Source of trait objects that implement `WorkEngine` interface
     engine_receiver: EngineReceiver,
+    /// Work pipeline counters shared with other generators
+    pipeline_stats: Arc<pipeline::Stats>,
 }
 
 impl Generator {
     pub fn new(
+        idx: usize,
         engine_receiver: EngineReceiver,
         path: WorkSolverPath,
         work_solver: Arc<Mutex<Option<Weak<dyn node::WorkSolver>>>>,
+        pipeline_stats: Arc<pipeline::Stats>,
     ) -> Self {
         Self {
+            idx,
             path,
             work_solver,
             engine_receiver,
+            pipeline_stats,
         }
     }
 
+    /// Work pipeline counters which should be updated by backend (e.g. full work FIFO)
+    #[inline]
+    pub fn pipeline_stats(&self) -> &Arc<pipeline::Stats> {
+        &self.pipeline_stats
+    }
+
+    /// Check whether new work can be generated without waiting
+    #[inline]
+    pub fn has_work(&self) -> bool {
+        self.engine_receiver.has_work()
+    }
+
     /// Loops until new work is available or no more `WorkEngines` are supplied (signals
     /// Generator shutdown)
     pub async fn generate(&mut self) -> Option<Assignment> {
@@ -200,13 +229,23 @@ impl Generator {
             .expect("BUG: calling work generator after node destruction");
 
         loop {
+            // measure time spent by waiting for new work from clients
+            let wait_start = if self.engine_receiver.has_work() {
+                None
+            } else {
+                Some(time::Instant::now())
+            };
             let engine = match self.engine_receiver.get_engine().await {
                 // end of stream
                 None => return None,
                 Some(value) => value,
             };
+            if let Some(wait_start) = wait_start {
+                self.pipeline_stats
+                    .account_generator_wait(wait_start.elapsed());
+            }
             // try to generate new work from engine
-            let mut work = match engine.next_work() {
+            let mut work = match engine.next_work_for(self.idx) {
                 // one or more competing work engines are exhausted
                 // try to gen new work engine
                 // NOTE: this can happen simultaneously for multiple parallel generators because
@@ -247,17 +286,43 @@ impl Generator {
             return Some(work);
         }
     }
+
+    /// Generate up to `count` works at once and append them to `works` so the caller can reuse
+    /// its allocation. It waits only for the first work, further works are generated only while
+    /// the current work engine can provide them without waiting. No new work signals Generator
+    /// shutdown.
+    pub async fn generate_batch(&mut self, count: usize, works: &mut Vec<Assignment>) {
+        for i in 0..count {
+            if i > 0 && !self.engine_receiver.has_work() {
+                break;
+            }
+            match self.generate().await {
+                Some(work) => works.push(work),
+                None => break,
+            }
+        }
+    }
 }
 
 /// This struct is to be passed to the underlying mining backend. It allows submission of
 /// `work::Solution`
 #[derive(Debug, Clone)]
-pub struct SolutionSender(mpsc::UnboundedSender<Solution>);
+pub struct SolutionSender {
+    sender: mpsc::UnboundedSender<Solution>,
+    pipeline_stats: Arc<pipeline::Stats>,
+}
 
 impl SolutionSender {
     pub fn send(&self, solution: Solution) {
-        self.0
+        self.pipeline_stats.solution_sent();
+        self.sender
             .unbounded_send(solution)
             .expect("solution queue send failed");
     }
+
+    /// Work pipeline counters which should be updated by backend (e.g. full solution FIFO)
+    #[inline]
+    pub fn pipeline_stats(&self) -> &Arc<pipeline::Stats> {
+        &self.pipeline_stats
+    }
 }
@@ -214,6 +214,31 @@ impl<'a, T: FromMidstateWord<T>> DoubleEndedIterator for MidstateWords<'a, T> {
     }
 }
 
+/// Convert 256bit number to floating point number (precision is limited to 64 most significant
+/// bits which is still more than `f64` mantissa can hold)
+fn u256_to_f64(value: uint::U256) -> f64 {
+    let shift = value.bits().saturating_sub(64);
+    (value >> shift).low_u64() as f64 * 2f64.powi(shift as i32)
+}
+
+/// Convert non-negative floating point number to 256bit number. The fractional part is truncated
+/// and values which do not fit are saturated.
+fn f64_to_u256(value: f64) -> uint::U256 {
+    if value < 1.0 {
+        return uint::U256::zero();
+    }
+    let exponent = value.log2().floor() as i32;
+    if exponent >= 256 {
+        uint::U256::max_value()
+    } else if exponent < 64 {
+        (value as u64).into()
+    } else {
+        // keep 64 most significant bits and shift them back to their position
+        let shift = exponent - 63;
+        uint::U256::from((value / 2f64.powi(shift)) as u64) << shift as usize
+    }
+}
+
 /// Bitcoin target represents the network/pool difficulty as a 256bit number
 /// The structure provides various conversion functions and formatters for uniform display of the
 /// target as a hexadecimal string similar to Bitcoin double hash which is SHA256 double hash
@@ -243,6 +268,17 @@ impl Target {
         Self(Self::difficulty_1_target() / difficulty)
     }
 
+    /// Create target from fractional difficulty (pools and stratum V2 channels can set
+    /// difficulty lower than 1)
+    pub fn from_difficulty_f64(difficulty: f64) -> Result<Self, &'static str> {
+        if !(difficulty > 0.0 && difficulty.is_finite()) {
+            return Err("difficulty has to be a positive number");
+        }
+        Ok(Self(f64_to_u256(
+            u256_to_f64(Self::difficulty_1_target()) / difficulty,
+        )))
+    }
+
     /// Create target from its compact representation used by Bitcoin protocol
     pub fn from_compact(bits: u32) -> Result<Self, &'static str> {
         // this code is inspired by `rust-bitcoin` crate implementation
@@ -260,6 +296,14 @@ impl Target {
         if mantissa > 0x7fffff {
             return Err("largest legal value for mantissa has been exceeded");
         }
+        // the same check as in Bitcoin Core: the value must fit into 256 bits
+        if mantissa != 0
+            && (exponent > 34
+                || (mantissa > 0xff && exponent > 33)
+                || (mantissa > 0xffff && exponent > 32))
+        {
+            return Err("target doesn't fit into 256 bits");
+        }
 
         Ok(if exponent <= 3 {
             Into::<uint::U256>::into(mantissa >> (8 * (3 - exponent)))
@@ -274,6 +318,15 @@ impl Target {
         (Self::difficulty_1_target() / self.0).low_u64() as usize
     }
 
+    /// Convert target to difficulty including its fractional part. This is the same value as the
+    /// network difficulty reported by Bitcoin Core for target in compact representation.
+    pub fn get_difficulty_f64(&self) -> f64 {
+        if self.0.is_zero() {
+            return std::f64::INFINITY;
+        }
+        u256_to_f64(Self::difficulty_1_target()) / u256_to_f64(self.0)
+    }
+
     /// Convert target to its compact representation used by Bitcoin protocol
     pub fn into_compact(self) -> u32 {
         // this code is inspired by `rust-bitcoin` crate implementation
@@ -390,6 +443,78 @@ impl MeetsTarget for DHash {
     }
 }
 
+/// Auxiliary trait for computing difficulty of a hash which is the highest difficulty of a target
+/// the hash still meets
+pub trait HashDifficulty {
+    fn difficulty(&self) -> f64;
+}
+
+impl HashDifficulty for DHash {
+    fn difficulty(&self) -> f64 {
+        Target::from(self.into_inner()).get_difficulty_f64()
+    }
+}
+
+/// Histogram of share difficulties with buckets for powers of two. Bucket `i` counts shares
+/// with difficulty in range `[2^i, 2^(i + 1))`, shares with difficulty lower than 1 are counted
+/// in the first bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DifficultyBuckets {
+    counts: Vec<u64>,
+}
+
+impl DifficultyBuckets {
+    /// Index of bucket for share with given difficulty
+    pub fn bucket_idx(difficulty: f64) -> usize {
+        if difficulty < 2.0 {
+            0
+        } else {
+            difficulty.log2().floor() as usize
+        }
+    }
+
+    /// The lowest difficulty of share counted in bucket with index `bucket_idx`
+    pub fn bucket_difficulty(bucket_idx: usize) -> f64 {
+        2f64.powi(bucket_idx as i32)
+    }
+
+    /// Account share with given difficulty. Non-finite difficulty (e.g. of a zero hash) has no
+    /// bucket and it is rejected.
+    pub fn account(&mut self, difficulty: f64) -> Result<(), &'static str> {
+        if !(difficulty >= 0.0 && difficulty.is_finite()) {
+            return Err("difficulty has to be a non-negative finite number");
+        }
+        let bucket_idx = Self::bucket_idx(difficulty);
+        if bucket_idx >= self.counts.len() {
+            self.counts.resize(bucket_idx + 1, 0);
+        }
+        self.counts[bucket_idx] += 1;
+        Ok(())
+    }
+
+    /// Account share represented by its hash
+    #[inline]
+    pub fn account_hash(&mut self, hash: &DHash) -> Result<(), &'static str> {
+        self.account(hash.difficulty())
+    }
+
+    /// Number of shares in each bucket
+    #[inline]
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Number of shares with difficulty at least `2^bucket_idx`
+    pub fn count_from(&self, bucket_idx: usize) -> u64 {
+        self.counts.iter().skip(bucket_idx).sum()
+    }
+
+    /// Index of the highest non-empty bucket
+    pub fn best_bucket_idx(&self) -> Option<usize> {
+        self.counts.iter().rposition(|&count| count > 0)
+    }
+}
+
 /// Structure used for storing all shares determined from solution target difficulty
 /// Share=1 represents a space of 2^32 calculated hashes for Bitcoin mainnet; exactly
 /// 2^256 / (0xffff << 208), where 0xffff << 208 is defined as target difficulty 1 for Bitcoin
@@ -768,7 +893,135 @@ pub mod test {
     /// Check detection of invalid representation of target in compact format
     #[test]
     fn test_corrupted_compact() {
-        assert!(Target::from_compact(0xfffffff).is_err())
+        assert!(Target::from_compact(0xfffffff).is_err());
+        // overflow of 256 bits
+        assert!(Target::from_compact(0x2300_0001).is_err());
+        assert!(Target::from_compact(0x2200_0100).is_err());
+        assert!(Target::from_compact(0x2101_0000).is_err());
+        // the highest representable values
+        assert!(Target::from_compact(0x2200_00ff).is_ok());
+        assert!(Target::from_compact(0x2100_ffff).is_ok());
+        assert!(Target::from_compact(0x207f_ffff).is_ok());
+        // zero is valid with any exponent
+        assert!(Target::from_compact(0xff00_0000).is_ok());
+    }
+
+    #[test]
+    fn test_difficulty_f64() {
+        // (compact target, network difficulty)
+        for &(bits, difficulty) in [
+            (0x1d00ffff, 1.0),
+            (0x1b0404cb, 16307.420938523983),
+            (0x1715a35c, 13008091666971.898),
+        ]
+        .iter()
+        {
+            let target = Target::from_compact(bits).unwrap();
+            let relative_error = (target.get_difficulty_f64() - difficulty).abs() / difficulty;
+            assert!(relative_error < 1e-12, "{:#x}", bits);
+
+            // conversion from difficulty gives the same target in compact representation
+            assert_eq!(
+                Target::from_difficulty_f64(difficulty)
+                    .unwrap()
+                    .into_compact(),
+                bits
+            );
+        }
+
+        // integer difficulty gives the same target as pool difficulty (exactly only for powers
+        // of two because of limited precision of floating point division)
+        for &difficulty in [1, 2, 64, 1000, 8192, 65536].iter() {
+            let target = Target::from_difficulty_f64(difficulty as f64).unwrap();
+            let pool_target = Target::from_pool_difficulty(difficulty);
+            assert_eq!(target.into_compact(), pool_target.into_compact());
+            if difficulty.is_power_of_two() {
+                assert_eq!(target, pool_target);
+            }
+        }
+
+        // fractional difficulty
+        let target = Target::from_difficulty_f64(0.5).unwrap();
+        assert_eq!(
+            target.into_inner(),
+            Target::default().into_inner() * uint::U256::from(2)
+        );
+        assert_eq!(target.get_difficulty_f64(), 0.5);
+
+        assert!(Target::from_difficulty_f64(0.0).is_err());
+        assert!(Target::from_difficulty_f64(-1.0).is_err());
+        assert!(Target::from_difficulty_f64(std::f64::NAN).is_err());
+        assert!(Target::from_difficulty_f64(std::f64::INFINITY).is_err());
+        // tiny difficulty saturates to the highest target
+        assert_eq!(
+            Target::from_difficulty_f64(1e-80).unwrap().into_inner(),
+            uint::U256::max_value()
+        );
+        assert_eq!(
+            Target::from(uint::U256::zero()).get_difficulty_f64(),
+            std::f64::INFINITY
+        );
+    }
+
+    #[test]
+    fn test_hash_difficulty() {
+        for block in TEST_BLOCKS.iter() {
+            let target = Target::from_compact(block.bits).unwrap();
+            let difficulty = block.hash.difficulty();
+
+            // block hash meets network target so its difficulty cannot be lower
+            assert!(difficulty >= target.get_difficulty_f64());
+            // and the hash meets target derived from its own difficulty
+            assert!(block
+                .hash
+                .meets(&Target::from_difficulty_f64(difficulty).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_difficulty_buckets() {
+        assert_eq!(DifficultyBuckets::bucket_idx(0.1), 0);
+        assert_eq!(DifficultyBuckets::bucket_idx(1.0), 0);
+        assert_eq!(DifficultyBuckets::bucket_idx(1.99), 0);
+        assert_eq!(DifficultyBuckets::bucket_idx(2.0), 1);
+        assert_eq!(DifficultyBuckets::bucket_idx(1023.0), 9);
+        assert_eq!(DifficultyBuckets::bucket_idx(1024.0), 10);
+        assert_eq!(DifficultyBuckets::bucket_difficulty(10), 1024.0);
+
+        let mut buckets = DifficultyBuckets::default();
+        assert_eq!(buckets.best_bucket_idx(), None);
+        for &difficulty in [0.5, 1.5, 3.0, 3.5, 1000.0].iter() {
+            buckets
+                .account(difficulty)
+                .expect("BUG: cannot account difficulty");
+        }
+        assert_eq!(buckets.counts(), &[2, 2, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(buckets.count_from(0), 5);
+        assert_eq!(buckets.count_from(1), 3);
+        assert_eq!(buckets.count_from(2), 1);
+        assert_eq!(buckets.count_from(10), 0);
+        assert_eq!(buckets.best_bucket_idx(), Some(9));
+
+        // block hash falls into bucket above the network difficulty
+        let block = &TEST_BLOCKS[0];
+        buckets
+            .account_hash(&block.hash)
+            .expect("BUG: cannot account block hash");
+        let network_difficulty = Target::from_compact(block.bits)
+            .unwrap()
+            .get_difficulty_f64();
+        assert!(
+            buckets.best_bucket_idx().unwrap() >= DifficultyBuckets::bucket_idx(network_difficulty)
+        );
+
+        // values without any bucket are rejected and don't change the histogram
+        let counts = buckets.counts().to_vec();
+        assert!(buckets.account(std::f64::INFINITY).is_err());
+        assert!(buckets.account(std::f64::NAN).is_err());
+        assert!(buckets.account(-1.0).is_err());
+        let zero_hash = DHash::from_slice(&[0u8; 32]).expect("BUG: cannot build zero hash");
+        assert!(buckets.account_hash(&zero_hash).is_err());
+        assert_eq!(buckets.counts(), &counts[..]);
     }
 
     #[test]
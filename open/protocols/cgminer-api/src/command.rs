@@ -24,16 +24,21 @@
 
 use crate::response;
 use crate::support::ValueExt as _;
-use crate::support::{MultiResponse, ResponseType, UnixTime, When};
+use crate::support::{Compatibility, MultiResponse, ResponseType, TimestampSplit, UnixTime, When};
+
+use ii_logging::macros::*;
 
 use serde_json as json;
 
 use ii_async_compat::futures::Future;
+use ii_async_compat::prelude::*;
 
 use std::collections::HashMap;
 use std::marker;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 
 /// List of all supported commands.
 const POOLS: &str = "pools";
@@ -54,14 +59,91 @@ const COIN: &str = "coin";
 const ASC_COUNT: &str = "asccount";
 const ASC: &str = "asc";
 const LCD: &str = "lcd";
+const API_STATS: &str = "apistats";
+const SUBSCRIBE: &str = "subscribe";
+const HELP: &str = "help";
 
 // List of all standard commands which can be optionally implemented.
 pub const DEVDETAILS: &str = "devdetails";
+pub const NOTIFY: &str = "notify";
 
 // List of all extended commands which have to be implemented externally.
 pub const TEMPCTRL: &str = "tempctrl";
 pub const TEMPS: &str = "temps";
 pub const FANS: &str = "fans";
+pub const CHIPS: &str = "chips";
+pub const EVENTS: &str = "events";
+pub const TASKS: &str = "tasks";
+pub const TUNE: &str = "tune";
+pub const PROFILE: &str = "profile";
+pub const PAUSE: &str = "pause";
+pub const RESUME: &str = "resume";
+pub const RESTART_CHAIN: &str = "restartchain";
+pub const IDENTIFY: &str = "identify";
+pub const IDENT: &str = "ident";
+pub const PIPELINE: &str = "pipeline";
+pub const LAST_SHUTDOWN: &str = "lastshutdown";
+pub const EDIT_POOL: &str = "editpool";
+pub const SHARES: &str = "shares";
+pub const SET_GROUPS: &str = "setgroups";
+pub const FEE: &str = "fee";
+pub const SELF_CHECK: &str = "selfcheck";
+pub const LOGS: &str = "logs";
+pub const LOG_FILTER: &str = "logfilter";
+pub const REJECTIONS: &str = "rejections";
+pub const ASIC_BOOST: &str = "asicboost";
+pub const NODES: &str = "nodes";
+
+/// Maximum time for handling of a single command before a timeout error is returned
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Maximum number of commands handled concurrently (other commands wait for a free slot)
+pub const DEFAULT_CONCURRENCY_LIMIT: usize = 8;
+/// Commands taking longer than this are reported as slow
+pub const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(1);
+/// Interval of subscription frames when the subscription doesn't specify it
+pub const DEFAULT_SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(5);
+/// The shortest allowed interval of subscription frames
+pub const MIN_SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(1);
+/// The longest allowed interval of subscription frames
+pub const MAX_SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(3600);
+/// Subscription ends after this time and the client has to subscribe again
+pub const SUBSCRIPTION_LIFETIME: Duration = Duration::from_secs(3600);
+/// Maximum number of subscriptions served at once (by all transports)
+pub const MAX_SUBSCRIPTIONS: usize = 16;
+
+/// Read-only commands which can be subscribed to. Any other command would be repeated with every
+/// frame of the subscription which is not acceptable for commands changing state of the miner
+/// (even parameter-less ones like `pause`) or with side effects (`selfcheck`).
+const SUBSCRIBABLE_COMMANDS: &[&str] = &[
+    POOLS,
+    DEVS,
+    EDEVS,
+    SUMMARY,
+    VERSION,
+    CONFIG,
+    STATS,
+    ESTATS,
+    COIN,
+    ASC_COUNT,
+    LCD,
+    API_STATS,
+    HELP,
+    DEVDETAILS,
+    NOTIFY,
+    TEMPCTRL,
+    TEMPS,
+    FANS,
+    CHIPS,
+    EVENTS,
+    TASKS,
+    IDENT,
+    PIPELINE,
+    LAST_SHUTDOWN,
+    SHARES,
+    FEE,
+    REJECTIONS,
+    NODES,
+];
 
 pub type Result<T> = std::result::Result<T, response::Error>;
 /// Type describing command table
@@ -112,6 +194,30 @@ impl Request {
     }
 }
 
+/// Periodic responses requested with `subscribe` command. The parameter of the command is
+/// a list of read-only commands joined with `+` optionally followed by interval in seconds
+/// (e.g. `summary+devs,2`).
+#[derive(Debug)]
+pub struct Subscription {
+    command: String,
+    pub interval: Duration,
+    created: Instant,
+    /// Slot of `MAX_SUBSCRIPTIONS` released when the subscription is dropped
+    _slot: tokio::sync::OwnedSemaphorePermit,
+}
+
+impl Subscription {
+    /// Request producing one frame of the subscription
+    pub fn request(&self) -> Request {
+        Request::new(json::json!({ "command": self.command }))
+    }
+
+    /// The subscription has exceeded `SUBSCRIPTION_LIFETIME` and it should be ended
+    pub fn is_expired(&self) -> bool {
+        self.created.elapsed() >= SUBSCRIPTION_LIFETIME
+    }
+}
+
 pub type AsyncHandler = Pin<Box<dyn Future<Output = Result<response::Dispatch>> + Send + 'static>>;
 
 pub type ParameterLessHandler = Box<dyn Fn() -> AsyncHandler + Send + Sync>;
@@ -125,6 +231,9 @@ pub enum HandlerType {
     Parameter(ParameterHandler),
     Version,
     Check,
+    ApiStats,
+    Subscribe,
+    Help,
 }
 
 impl HandlerType {
@@ -134,6 +243,9 @@ impl HandlerType {
             HandlerType::Parameter(_) => true,
             HandlerType::Version => false,
             HandlerType::Check => true,
+            HandlerType::ApiStats => false,
+            HandlerType::Subscribe => true,
+            HandlerType::Help => false,
         }
     }
 }
@@ -142,6 +254,10 @@ impl HandlerType {
 pub struct Descriptor {
     handler: HandlerType,
     parameter_check: Option<ParameterCheckHandler>,
+    /// Overrides default timeout of the command receiver
+    timeout: Option<Duration>,
+    /// Parameters of the command listed by `help` command (e.g. `chain,frequency,voltage`)
+    parameters: Option<&'static str>,
 }
 
 impl Descriptor {
@@ -152,9 +268,23 @@ impl Descriptor {
         Self {
             handler,
             parameter_check: parameter_check.into(),
+            timeout: None,
+            parameters: None,
         }
     }
 
+    /// Describe parameters of the command for `help` command
+    pub fn with_parameters(mut self, parameters: &'static str) -> Self {
+        self.parameters = Some(parameters);
+        self
+    }
+
+    /// Set timeout specific for this command
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     #[inline]
     pub fn has_parameters(&self) -> bool {
         self.handler.has_parameters()
@@ -194,17 +324,20 @@ macro_rules! command {
     };
 }
 
-/// Generates a map that associated a command name with its descriptor
+/// Generates a map that associated a command name with its descriptor. Description of
+/// parameters can follow the handler after a semicolon.
 #[macro_export]
 macro_rules! commands {
     () => (
         $crate::command::Map::new()
     );
-    ($(($name:ident: $type:ident$(($parameter:ident))? $(-> $handler:ident . $method:ident)?)),+) => {
+    ($(($name:ident: $type:ident$(($parameter:ident))? $(-> $handler:ident . $method:ident)?
+        $(; $parameters:literal)?)),+) => {
         {
             let mut map = $crate::command::Map::new();
             $(
-                let descriptor = command!($name: $type $(($parameter))? $(-> $handler . $method)?);
+                let descriptor = command!($name: $type $(($parameter))? $(-> $handler . $method)?)
+                    $(.with_parameters($parameters))?;
                 map.insert($name, descriptor);
             )*
             map
@@ -212,6 +345,35 @@ macro_rules! commands {
     }
 }
 
+/// Statistics of handling of one command
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub calls: u64,
+    /// Calls exceeding the slow command threshold (including timed out ones)
+    pub slow_calls: u64,
+    pub timeouts: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+impl Metrics {
+    fn account(&mut self, duration: Duration, slow: bool, timed_out: bool) {
+        self.calls += 1;
+        self.slow_calls += slow as u64;
+        self.timeouts += timed_out as u64;
+        self.total_duration += duration;
+        self.max_duration = self.max_duration.max(duration);
+    }
+
+    pub fn average_duration(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.total_duration / self.calls as u32
+        }
+    }
+}
+
 /// Generic command receiving and processing object that dispatches command handling
 /// user provided handler methods.
 pub struct Receiver<T = UnixTime> {
@@ -219,6 +381,21 @@ pub struct Receiver<T = UnixTime> {
     miner_signature: String,
     miner_version: String,
     description: String,
+    compatibility: Compatibility,
+    /// Default timeout for commands without their own timeout
+    timeout: Duration,
+    /// Commands taking longer than this are reported as slow
+    slow_threshold: Duration,
+    /// Limits number of handlers running at once so that a stuck backend cannot exhaust
+    /// resources of the whole miner
+    concurrency_limit: tokio::sync::Semaphore,
+    /// Slots of subscriptions which are served at once
+    subscription_limit: Arc<tokio::sync::Semaphore>,
+    metrics: StdMutex<HashMap<&'static str, Metrics>>,
+    /// Requests rejected by codec because they aren't valid JSON
+    malformed_requests: AtomicU64,
+    /// Requests rejected by codec because they exceed maximum frame size
+    oversized_requests: AtomicU64,
     _marker: marker::PhantomData<T>,
 }
 
@@ -260,21 +437,24 @@ where
             (DEVS: ParameterLess -> handler.handle_devs),
             (EDEVS: ParameterLess -> handler.handle_edevs),
             (SUMMARY: ParameterLess -> handler.handle_summary),
-            (SWITCH_POOL: Parameter(check_switch_pool) -> handler.handle_switch_pool),
+            (SWITCH_POOL: Parameter(check_switch_pool) -> handler.handle_switch_pool; "pool"),
             (CONFIG: ParameterLess -> handler.handle_config),
-            (ENABLE_POOL: Parameter(check_enable_pool) -> handler.handle_enable_pool),
-            (DISABLE_POOL: Parameter(check_disable_pool) -> handler.handle_disable_pool),
-            (ADD_POOL: Parameter(check_add_pool) -> handler.handle_add_pool),
-            (REMOVE_POOL: Parameter(check_remove_pool) -> handler.handle_remove_pool),
+            (ENABLE_POOL: Parameter(check_enable_pool) -> handler.handle_enable_pool; "pool"),
+            (DISABLE_POOL: Parameter(check_disable_pool) -> handler.handle_disable_pool; "pool"),
+            (ADD_POOL: Parameter(check_add_pool) -> handler.handle_add_pool; "url,user,password"),
+            (REMOVE_POOL: Parameter(check_remove_pool) -> handler.handle_remove_pool; "pool"),
             (STATS: ParameterLess -> handler.handle_stats),
             (ESTATS: ParameterLess -> handler.handle_estats),
             (COIN: ParameterLess -> handler.handle_coin),
             (ASC_COUNT: ParameterLess -> handler.handle_asc_count),
-            (ASC: Parameter(check_asc) -> handler.handle_asc),
+            (ASC: Parameter(check_asc) -> handler.handle_asc; "asc"),
             (LCD: ParameterLess -> handler.handle_lcd),
             // special built-in commands
             (VERSION: BuiltIn(Version)),
-            (CHECK: BuiltIn(Check))
+            (CHECK: BuiltIn(Check); "command"),
+            (API_STATS: BuiltIn(ApiStats)),
+            (SUBSCRIBE: BuiltIn(Subscribe); "commands,interval"),
+            (HELP: BuiltIn(Help))
         ];
 
         if let Some(custom_commands) = custom_commands.into() {
@@ -287,10 +467,82 @@ where
             miner_signature,
             miner_version,
             description,
+            compatibility: Default::default(),
+            timeout: DEFAULT_TIMEOUT,
+            slow_threshold: DEFAULT_SLOW_THRESHOLD,
+            concurrency_limit: tokio::sync::Semaphore::new(DEFAULT_CONCURRENCY_LIMIT),
+            subscription_limit: Arc::new(tokio::sync::Semaphore::new(MAX_SUBSCRIPTIONS)),
+            metrics: StdMutex::new(HashMap::new()),
+            malformed_requests: AtomicU64::new(0),
+            oversized_requests: AtomicU64::new(0),
             _marker: marker::PhantomData,
         }
     }
 
+    /// Set how strictly the responses follow the original CGMiner
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Set default timeout for all commands without their own timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set timeout of a particular `command`. Unknown commands are ignored.
+    pub fn with_command_timeout(mut self, command: &str, timeout: Duration) -> Self {
+        if let Some(descriptor) = self.commands.get_mut(command) {
+            descriptor.timeout = Some(timeout);
+        }
+        self
+    }
+
+    /// Set maximum number of commands handled at once
+    pub fn with_concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = tokio::sync::Semaphore::new(limit);
+        self
+    }
+
+    /// Set duration from which commands are reported as slow
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = threshold;
+        self
+    }
+
+    /// Snapshot of handling statistics of all commands that have been called at least once
+    pub fn metrics(&self) -> Vec<(&'static str, Metrics)> {
+        let mut metrics: Vec<_> = self
+            .metrics
+            .lock()
+            .expect("BUG: cannot lock command metrics")
+            .iter()
+            .map(|(command, metrics)| (*command, metrics.clone()))
+            .collect();
+        metrics.sort_by_key(|(command, _)| *command);
+        metrics
+    }
+
+    /// Account a request rejected by codec
+    pub fn account_frame_error(&self, frame_error: crate::FrameError) {
+        let counter = match frame_error {
+            crate::FrameError::Malformed => &self.malformed_requests,
+            crate::FrameError::Oversized => &self.oversized_requests,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of requests rejected by codec because they aren't valid JSON
+    pub fn malformed_requests(&self) -> u64 {
+        self.malformed_requests.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests rejected by codec because they exceed maximum frame size
+    pub fn oversized_requests(&self) -> u64 {
+        self.oversized_requests.load(Ordering::Relaxed)
+    }
+
     fn check_add_pool(_command: &str, parameter: &Option<&json::Value>) -> Result<()> {
         const ARG_COUNT: usize = 3;
         match parameter {
@@ -347,6 +599,170 @@ where
         })
     }
 
+    /// List all registered commands (including custom ones) with description of their parameters
+    fn handle_help(&self) -> Result<response::ext::Help> {
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by_key(|(command, _)| *command);
+        let list = commands
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (command, descriptor))| response::ext::CommandInfo {
+                idx: idx as i32,
+                command: command.to_string(),
+                parameter: descriptor.has_parameters().into(),
+                parameters: descriptor.parameters.unwrap_or_default().to_string(),
+            })
+            .collect();
+
+        Ok(response::ext::Help { list })
+    }
+
+    fn handle_api_stats(&self) -> Result<response::ext::ApiStats> {
+        let list = self
+            .metrics()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (command, metrics))| response::ext::CommandStats {
+                idx: idx as i32,
+                command: command.to_string(),
+                calls: metrics.calls,
+                slow_calls: metrics.slow_calls,
+                timeouts: metrics.timeouts,
+                average_duration: metrics.average_duration().as_secs_f64(),
+                max_duration: metrics.max_duration.as_secs_f64(),
+            })
+            .collect();
+
+        Ok(response::ext::ApiStats {
+            list,
+            malformed_requests: self.malformed_requests(),
+            oversized_requests: self.oversized_requests(),
+        })
+    }
+
+    fn parse_subscription(&self, parameter: Option<&json::Value>) -> Result<Subscription> {
+        let parameter = match parameter {
+            Some(json::Value::String(value)) => value.as_str(),
+            _ => Err(response::ErrorCode::MissingParameter(
+                "commands".to_string(),
+            ))?,
+        };
+        let mut args = parameter.splitn(2, super::PARAMETER_DELIMITER);
+        let command = args.next().unwrap_or_default();
+        let interval = match args.next() {
+            None => DEFAULT_SUBSCRIPTION_INTERVAL,
+            Some(value) => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|interval| {
+                    *interval >= MIN_SUBSCRIPTION_INTERVAL.as_secs_f64()
+                        && *interval <= MAX_SUBSCRIPTION_INTERVAL.as_secs_f64()
+                })
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| {
+                    response::ErrorCode::InvalidParameter("interval".to_string(), value.to_string())
+                })?,
+        };
+
+        let mut command_count = 0;
+        for name in command.split('+').filter(|name| !name.is_empty()) {
+            if !self.commands.contains_key(name) {
+                Err(response::ErrorCode::InvalidCommand)?;
+            }
+            if !SUBSCRIBABLE_COMMANDS.contains(&name) {
+                Err(response::ErrorCode::AccessDeniedCmd(name.to_string()))?;
+            }
+            command_count += 1;
+        }
+        if command_count == 0 {
+            Err(response::ErrorCode::MissingParameter(
+                "commands".to_string(),
+            ))?;
+        }
+
+        let slot = self
+            .subscription_limit
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| response::ErrorCode::TooManySubscriptions(MAX_SUBSCRIPTIONS))?;
+        Ok(Subscription {
+            command: command.to_string(),
+            interval,
+            created: Instant::now(),
+            _slot: slot,
+        })
+    }
+
+    /// Check whether `command_request` subscribes to periodic responses. Subscription can be
+    /// served only by a transport which keeps the connection open (see `crate::run`).
+    /// Returns: `None` for any other request or error response for invalid subscription
+    pub fn subscription(
+        &self,
+        command_request: &Request,
+    ) -> Option<std::result::Result<Subscription, ResponseType>> {
+        match command_request
+            .value
+            .get("command")
+            .and_then(json::Value::as_str)
+        {
+            Some(SUBSCRIBE) => Some(
+                self.parse_subscription(command_request.value.get("parameter"))
+                    .map_err(|error| self.get_single_response(error.into(), None)),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Runs `handler` of a `command` when a free slot is available. Waiting for the slot counts to
+    /// the command timeout so the whole command is always finished in time.
+    async fn run_handler(
+        &self,
+        command: &'static str,
+        timeout: Option<Duration>,
+        handler: AsyncHandler,
+    ) -> Result<response::Dispatch> {
+        let timeout = timeout.unwrap_or(self.timeout);
+        let start = Instant::now();
+        let result = async {
+            let _permit = self
+                .concurrency_limit
+                .acquire()
+                .await
+                .expect("BUG: command semaphore closed");
+            handler.await
+        }
+        .timeout(timeout)
+        .await;
+        let duration = start.elapsed();
+
+        let timed_out = result.is_err();
+        let slow = timed_out || duration >= self.slow_threshold;
+        if timed_out {
+            warn!(
+                "CGMiner API: command '{}' timed out after {:.3}s",
+                command,
+                duration.as_secs_f64()
+            );
+        } else if slow {
+            warn!(
+                "CGMiner API: slow command '{}' took {:.3}s",
+                command,
+                duration.as_secs_f64()
+            );
+        }
+        self.metrics
+            .lock()
+            .expect("BUG: cannot lock command metrics")
+            .entry(command)
+            .or_default()
+            .account(duration, slow, timed_out);
+
+        result.unwrap_or_else(|_| {
+            Err(response::ErrorCode::CommandTimeout(command.to_string()).into())
+        })
+    }
+
     /// Handles a single `command` with optional `parameter`. `multi_command` flag ensures that no
     /// command with parameters can be processed in batched mode.
     async fn handle_single(
@@ -355,8 +771,8 @@ where
         parameter: Option<&json::Value>,
         multi_command: bool,
     ) -> response::Dispatch {
-        let dispatch = match self.commands.get(command) {
-            Some(descriptor) => {
+        let dispatch = match self.commands.get_key_value(command) {
+            Some((name, descriptor)) => {
                 if multi_command && descriptor.has_parameters() {
                     Err(response::ErrorCode::AccessDeniedCmd(command.to_string()).into())
                 } else {
@@ -366,14 +782,28 @@ where
                         .map_or(Ok(()), |check| check(command, &parameter));
                     match check_result {
                         Ok(_) => match &descriptor.handler {
-                            HandlerType::ParameterLess(handle) => handle().await,
-                            HandlerType::Parameter(handle) => handle(parameter).await,
+                            HandlerType::ParameterLess(handle) => {
+                                self.run_handler(*name, descriptor.timeout, handle()).await
+                            }
+                            HandlerType::Parameter(handle) => {
+                                self.run_handler(*name, descriptor.timeout, handle(parameter))
+                                    .await
+                            }
                             HandlerType::Version => {
                                 self.handle_version().map(|response| response.into())
                             }
                             HandlerType::Check => {
                                 self.handle_check(parameter).map(|response| response.into())
                             }
+                            HandlerType::ApiStats => {
+                                self.handle_api_stats().map(|response| response.into())
+                            }
+                            HandlerType::Help => self.handle_help().map(|response| response.into()),
+                            // subscription has to be handled by the connection itself
+                            HandlerType::Subscribe => {
+                                Err(response::ErrorCode::AccessDeniedCmd(command.to_string())
+                                    .into())
+                            }
                         },
                         Err(response) => Err(response),
                     }
@@ -385,27 +815,52 @@ where
         dispatch.unwrap_or_else(|error| error.into())
     }
 
+    /// Response is timestamped with the request `timestamp` when it is specified
     #[inline]
-    fn get_single_response(&self, dispatch: response::Dispatch) -> ResponseType {
+    fn get_single_response(
+        &self,
+        dispatch: response::Dispatch,
+        timestamp: Option<&TimestampSplit>,
+    ) -> ResponseType {
         ResponseType::Single(dispatch.into_response(
-            T::when(),
+            timestamp.map_or_else(T::when, |timestamp| timestamp.when),
             &self.miner_signature,
             &self.description,
         ))
     }
 
     pub fn error_response(&self, error_code: response::ErrorCode) -> ResponseType {
-        self.get_single_response(error_code.into())
+        self.get_single_response(error_code.into(), None)
     }
 
     /// Handles a command request that can actually be a batched request of multiple commands
     pub async fn handle(&self, command_request: Request) -> ResponseType {
+        match self.compatibility {
+            Compatibility::Native => self.handle_request(command_request, None).await,
+            Compatibility::Legacy => {
+                let timestamp = TimestampSplit::now::<T>();
+                timestamp
+                    .scope(self.handle_request(command_request, Some(timestamp)))
+                    .await
+            }
+        }
+    }
+
+    async fn handle_request(
+        &self,
+        command_request: Request,
+        timestamp: Option<TimestampSplit>,
+    ) -> ResponseType {
+        let timestamp = timestamp.as_ref();
         let command = match command_request
             .value
             .get("command")
             .and_then(json::Value::as_str)
         {
-            None => return self.get_single_response(response::ErrorCode::MissingCommand.into()),
+            None => {
+                return self
+                    .get_single_response(response::ErrorCode::MissingCommand.into(), timestamp)
+            }
             Some(value) => value,
         };
         let commands: Vec<_> = command
@@ -415,15 +870,19 @@ where
         let parameter = command_request.value.get("parameter");
 
         if commands.len() == 0 {
-            self.get_single_response(response::ErrorCode::InvalidCommand.into())
+            self.get_single_response(response::ErrorCode::InvalidCommand.into(), timestamp)
         } else if commands.len() == 1 {
-            self.get_single_response(self.handle_single(command, parameter, false).await)
+            self.get_single_response(
+                self.handle_single(command, parameter, false).await,
+                timestamp,
+            )
         } else {
             let mut responses = MultiResponse::new();
             for command in commands {
-                if let ResponseType::Single(response) =
-                    self.get_single_response(self.handle_single(command, parameter, true).await)
-                {
+                if let ResponseType::Single(response) = self.get_single_response(
+                    self.handle_single(command, parameter, true).await,
+                    timestamp,
+                ) {
                     responses.add_response(command, response);
                 }
             }
@@ -23,8 +23,11 @@
 //! A generic CGMiner API server
 
 pub mod command;
+pub mod parameter;
 pub mod response;
 pub mod support;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 #[cfg(test)]
 mod test;
@@ -38,9 +41,12 @@ use futures::{SinkExt, StreamExt};
 use serde_json::Deserializer;
 use tokio_util::codec::{Decoder, Encoder};
 
+use std::error;
+use std::fmt;
 use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Re-export json because it is required in command handlers
 pub use serde_json as json;
@@ -56,11 +62,70 @@ pub const SIGNATURE_TAG: &str = "{SIGNATURE}";
 /// Default signature of CGMiner API
 pub const PARAMETER_DELIMITER: char = ',';
 
+/// Time for a client to send its request before the connection is dropped
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of a request. Real requests are tiny so anything bigger is treated as a flood.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Reason why `Codec` rejected a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The request is not a JSON object
+    Malformed,
+    /// The request exceeds maximum frame size
+    Oversized,
+}
+
+impl FrameError {
+    /// Extract frame error from I/O error returned by `Codec`
+    pub fn from_io_error(err: &io::Error) -> Option<Self> {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<Self>())
+            .copied()
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Malformed => write!(f, "Malformed JSON request"),
+            FrameError::Oversized => write!(f, "Request exceeds maximum frame size"),
+        }
+    }
+}
+
+impl error::Error for FrameError {}
+
+impl From<FrameError> for io::Error {
+    fn from(frame_error: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, frame_error)
+    }
+}
+
 /// Codec for the CGMiner API.
 /// The `Codec` decodes `Command`s and encodes `ResponseSet`s.
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Codec {
     encode_buf: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl Codec {
+    /// Set maximum size of a buffered request
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self {
+            encode_buf: vec![],
+            max_frame_size: MAX_FRAME_SIZE,
+        }
+    }
 }
 
 impl Decoder for Codec {
@@ -68,6 +133,15 @@ impl Decoder for Codec {
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Fast path for garbage (e.g. HTTP or binary probes of LAN scanners) which can never
+        // become a valid request so there is no reason to wait for more data
+        match src.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => {}
+            Some(_) => Err(FrameError::Malformed)?,
+            None if src.len() > self.max_frame_size => Err(FrameError::Oversized)?,
+            None => return Ok(None),
+        }
+
         let (res, offset) = {
             let mut stream = Deserializer::from_slice(&*src).into_iter();
             (stream.next(), stream.byte_offset())
@@ -79,26 +153,32 @@ impl Decoder for Codec {
 
                 if src.as_ref().iter().any(|byte| !byte.is_ascii_whitespace()) {
                     // There was a non-whitespace byte following the JSON
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Stray data following JSON",
-                    ))
+                    Err(FrameError::Malformed.into())
                 } else {
                     Ok(Some(command::Request::new(json)))
                 }
             }
-            Some(Err(err)) if err.is_eof() => Ok(None),
-            Some(Err(err)) => Err(err.into()),
+            Some(Err(err)) if err.is_eof() => {
+                if src.len() > self.max_frame_size {
+                    Err(FrameError::Oversized.into())
+                } else {
+                    Ok(None)
+                }
+            }
+            Some(Err(_)) => Err(FrameError::Malformed.into()),
             None => Ok(None),
         }
     }
 }
 
-impl Encoder for Codec {
-    type Item = support::ResponseType;
+impl Encoder<support::ResponseType> for Codec {
     type Error = io::Error;
 
-    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(
+        &mut self,
+        item: support::ResponseType,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
         self.encode_buf.clear();
         json::to_writer(&mut self.encode_buf, &item)?;
         dst.reserve(self.encode_buf.len() + 1);
@@ -123,13 +203,59 @@ impl ii_wire::Framing for Framing {
 /// wire-based connection type
 type Connection = ii_wire::Connection<Framing>;
 
-async fn handle_connection_task(mut conn: Connection, command_receiver: Arc<command::Receiver>) {
-    let response = match conn.next().await {
-        Some(Ok(command)) => command_receiver.handle(command).await,
-        Some(Err(err)) if err.kind() == io::ErrorKind::InvalidData => {
-            command_receiver.error_response(response::ErrorCode::InvalidJSON)
+/// Periodically send responses of subscribed commands until the client closes the connection,
+/// sends anything else or the subscription expires
+async fn handle_subscription(
+    conn: Connection,
+    command_receiver: Arc<command::Receiver>,
+    subscription: command::Subscription,
+) {
+    let (mut sink, mut stream) = conn.split();
+    let mut interval = tokio::time::interval(subscription.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if subscription.is_expired() {
+                    debug!("CGMiner API: subscription expired");
+                    return;
+                }
+                let response = command_receiver.handle(subscription.request()).await;
+                if let Err(e) = sink.send(response).await {
+                    debug!("CGMiner API: subscription closed ({})", e);
+                    return;
+                }
+            }
+            _ = stream.next() => return,
         }
-        _ => return, // We pretty much ignore I/O errors here
+    }
+}
+
+async fn handle_connection_task(conn: Connection, command_receiver: Arc<command::Receiver>) {
+    // Silent clients would otherwise keep their tasks (and sockets) forever
+    let mut conn = conn.with_idle_timeout(REQUEST_TIMEOUT);
+    let response = match conn.next().await {
+        Some(Ok(command)) => match command_receiver.subscription(&command) {
+            // subscribed client is expected to be silent so the idle timeout doesn't apply
+            Some(Ok(subscription)) => {
+                return handle_subscription(conn.into_inner(), command_receiver, subscription).await
+            }
+            Some(Err(response)) => response,
+            None => command_receiver.handle(command).await,
+        },
+        Some(Err(err)) => match FrameError::from_io_error(&err) {
+            Some(frame_error) => {
+                command_receiver.account_frame_error(frame_error);
+                match frame_error {
+                    FrameError::Malformed => {
+                        command_receiver.error_response(response::ErrorCode::InvalidJSON)
+                    }
+                    // Do not waste any more resources on flooding clients
+                    FrameError::Oversized => return,
+                }
+            }
+            None => return, // We pretty much ignore I/O errors here
+        },
+        None => return,
     };
 
     conn.send(response)
@@ -137,10 +263,14 @@ async fn handle_connection_task(mut conn: Connection, command_receiver: Arc<comm
         .unwrap_or_else(|e| warn!("CGMiner API: cannot send response ({})", e));
 }
 
-/// Start up an API server with a `command_receiver` object, listening on `listen_addr`
-pub async fn run(command_receiver: command::Receiver, listen_addr: SocketAddr) -> io::Result<()> {
+/// Start up an API server with a `command_receiver` object, listening on `listen_addr`.
+/// The receiver can be shared with other servers (e.g. `websocket`) when passed in `Arc`.
+pub async fn run(
+    command_receiver: impl Into<Arc<command::Receiver>>,
+    listen_addr: SocketAddr,
+) -> io::Result<()> {
     let mut server = ii_wire::Server::bind(&listen_addr)?;
-    let command_receiver = Arc::new(command_receiver);
+    let command_receiver = command_receiver.into();
 
     while let Some(conn) = server.next().await {
         if let Ok(conn) = conn {
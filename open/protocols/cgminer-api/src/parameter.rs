@@ -0,0 +1,162 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Typed extraction of command parameters
+
+use crate::command;
+use crate::response;
+
+use serde::de::DeserializeOwned;
+use serde_json as json;
+
+/// Parameters of a single command
+///
+/// CGMiner compatible clients pass parameters as a string with positional values delimited by
+/// `PARAMETER_DELIMITER` (e.g. `"0,650"`). Newer clients may pass a JSON object with named values
+/// (e.g. `{"chain": 0, "frequency": 650}`) or a JSON array with positional values. Handlers
+/// extract the values by position and name and don't need to care which form has been used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parameters {
+    Empty,
+    Positional(Vec<json::Value>),
+    Named(json::Map<String, json::Value>),
+}
+
+impl Parameters {
+    pub fn new(parameter: Option<&json::Value>) -> Self {
+        match parameter {
+            None | Some(json::Value::Null) => Self::Empty,
+            Some(json::Value::String(value)) if value.is_empty() => Self::Empty,
+            Some(json::Value::String(value)) => Self::Positional(
+                value
+                    .split(crate::PARAMETER_DELIMITER)
+                    .map(|value| json::Value::String(value.to_string()))
+                    .collect(),
+            ),
+            Some(json::Value::Array(values)) => Self::Positional(values.clone()),
+            Some(json::Value::Object(values)) => Self::Named(values.clone()),
+            Some(value) => Self::Positional(vec![value.clone()]),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::Empty
+    }
+
+    #[inline]
+    pub fn is_named(&self) -> bool {
+        match self {
+            Self::Named(_) => true,
+            _ => false,
+        }
+    }
+
+    fn lookup(&self, position: usize, name: &str) -> Option<&json::Value> {
+        match self {
+            Self::Empty => None,
+            Self::Positional(values) => values.get(position),
+            Self::Named(values) => values.get(name),
+        }
+    }
+
+    /// Positional values passed in a string are always strings so try to interpret them as
+    /// JSON first to support numbers and booleans
+    fn convert<T: DeserializeOwned>(value: &json::Value) -> Option<T> {
+        json::from_value(value.clone())
+            .ok()
+            .or_else(|| match value {
+                json::Value::String(value) => json::from_str(value).ok(),
+                _ => None,
+            })
+    }
+
+    /// Extract an optional parameter at `position` or with `name` depending on the form of
+    /// parameters
+    pub fn get_opt<T: DeserializeOwned>(
+        &self,
+        position: usize,
+        name: &str,
+    ) -> command::Result<Option<T>> {
+        match self.lookup(position, name) {
+            None => Ok(None),
+            Some(value) => Self::convert(value).map(Some).ok_or_else(|| {
+                response::ErrorCode::InvalidParameter(name.to_string(), value.to_string()).into()
+            }),
+        }
+    }
+
+    /// Extract a mandatory parameter at `position` or with `name` depending on the form of
+    /// parameters
+    pub fn get<T: DeserializeOwned>(&self, position: usize, name: &str) -> command::Result<T> {
+        self.get_opt(position, name)?
+            .ok_or_else(|| response::ErrorCode::MissingParameter(name.to_string()).into())
+    }
+}
+
+impl From<Option<&json::Value>> for Parameters {
+    fn from(parameter: Option<&json::Value>) -> Self {
+        Self::new(parameter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_positional_parameters() {
+        let value = json::json!("1,650.5,true,url");
+        let parameters = Parameters::new(Some(&value));
+
+        assert!(!parameters.is_named());
+        assert_eq!(parameters.get::<u32>(0, "idx").unwrap(), 1);
+        assert_eq!(parameters.get::<f64>(1, "frequency").unwrap(), 650.5);
+        assert!(parameters.get::<bool>(2, "enabled").unwrap());
+        assert_eq!(parameters.get::<String>(3, "url").unwrap(), "url");
+        assert_eq!(parameters.get_opt::<u32>(4, "missing").unwrap(), None);
+        assert!(parameters.get::<u32>(4, "missing").is_err());
+        assert!(parameters.get::<u32>(3, "url").is_err());
+    }
+
+    #[test]
+    fn test_named_parameters() {
+        let value = json::json!({"idx": 1, "frequency": 650.5, "url": "url"});
+        let parameters = Parameters::new(Some(&value));
+
+        assert!(parameters.is_named());
+        assert_eq!(parameters.get::<u32>(0, "idx").unwrap(), 1);
+        assert_eq!(parameters.get::<f64>(1, "frequency").unwrap(), 650.5);
+        assert_eq!(parameters.get::<String>(2, "url").unwrap(), "url");
+        assert_eq!(parameters.get_opt::<bool>(3, "enabled").unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_parameters() {
+        assert!(Parameters::new(None).is_empty());
+        assert!(Parameters::new(Some(&json::json!(""))).is_empty());
+        assert_eq!(
+            Parameters::new(Some(&json::json!(42))),
+            Parameters::Positional(vec![json::json!(42)])
+        );
+    }
+}
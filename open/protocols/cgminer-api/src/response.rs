@@ -39,6 +39,7 @@ pub type GigaHashes = MegaHashes;
 pub type TotalMegaHashes = f64;
 pub type Utility = f64;
 pub type Temperature = f64;
+pub type Milliseconds = f64;
 
 #[allow(dead_code)]
 /// CGMiner API Status indicator.
@@ -58,6 +59,16 @@ pub enum Bool {
     Y,
 }
 
+impl From<bool> for Bool {
+    fn from(value: bool) -> Self {
+        if value {
+            Bool::Y
+        } else {
+            Bool::N
+        }
+    }
+}
+
 impl<T> From<Option<T>> for Bool {
     fn from(value: Option<T>) -> Self {
         match value {
@@ -116,6 +127,7 @@ pub enum StatusCode {
     EnablePool = 47,
     DisablePool = 48,
     AddPool = 55,
+    Notify = 60,
     RemovePool = 68,
     DevDetails = 69,
     Stats = 70,
@@ -129,6 +141,36 @@ pub enum StatusCode {
     TempCtrl = 200,
     Temps = 201,
     Fans = 202,
+    Chips = 203,
+    Events = 204,
+    Tasks = 205,
+    Tune = 206,
+    Profiles = 207,
+    Pause = 208,
+    Resume = 209,
+    ApiStats = 210,
+    RestartChain = 211,
+    Identify = 212,
+    Ident = 213,
+    Pipeline = 214,
+    LastShutdown = 215,
+    EditPool = 216,
+    Shares = 217,
+    SetGroups = 218,
+    Fee = 219,
+    SelfCheck = 220,
+    Logs = 221,
+    LogFilter = 222,
+    Rejections = 223,
+    AsicBoost = 224,
+    Help = 225,
+    Nodes = 226,
+
+    // extended error status codes
+    MissingParameter = 250,
+    InvalidParameter = 251,
+    CommandTimeout = 252,
+    TooManySubscriptions = 253,
 
     // info status codes
     PoolAlreadyEnabled = 49,
@@ -201,6 +243,10 @@ pub enum ErrorCode {
     InvalidAddPoolDetails(String),
     MissingCheckCmd,
     InvalidAscId(i32, i32),
+    MissingParameter(String),
+    InvalidParameter(String, String),
+    CommandTimeout(String),
+    TooManySubscriptions(usize),
 }
 
 impl From<ErrorCode> for Dispatch {
@@ -209,6 +255,7 @@ impl From<ErrorCode> for Dispatch {
     }
 }
 
+#[derive(Debug)]
 pub struct Error {
     status: Status,
     code: StatusCodeType,
@@ -303,6 +350,22 @@ impl From<ErrorCode> for Error {
                     idx_requested, idx_last
                 ),
             ),
+            ErrorCode::MissingParameter(name) => (
+                StatusCode::MissingParameter,
+                format!("Missing parameter '{}'", name),
+            ),
+            ErrorCode::InvalidParameter(name, value) => (
+                StatusCode::InvalidParameter,
+                format!("Invalid parameter '{}' value {}", name, value),
+            ),
+            ErrorCode::CommandTimeout(name) => (
+                StatusCode::CommandTimeout,
+                format!("Command '{}' timed out", name),
+            ),
+            ErrorCode::TooManySubscriptions(limit) => (
+                StatusCode::TooManySubscriptions,
+                format!("Too many subscriptions (limit is {})", limit),
+            ),
         };
 
         Self {
@@ -413,6 +476,42 @@ pub struct Pool {
     // Follows attribute extensions
     #[serde(rename = "AsicBoost")]
     pub asic_boost: bool,
+    /// Accepted difficulty per minute
+    #[serde(rename = "Work Utility")]
+    pub work_utility: Utility,
+    /// Sum of difficulties of all valid shares within the window
+    #[serde(rename = "Share Difficulty 5m")]
+    pub share_difficulty_5m: Difficulty,
+    #[serde(rename = "Share Difficulty 15m")]
+    pub share_difficulty_15m: Difficulty,
+    #[serde(rename = "Share Difficulty 24h")]
+    pub share_difficulty_24h: Difficulty,
+    /// Sum of difficulties of shares accepted by the pool within the window
+    #[serde(rename = "Earned Work 5m")]
+    pub earned_work_5m: Difficulty,
+    #[serde(rename = "Earned Work 15m")]
+    pub earned_work_15m: Difficulty,
+    #[serde(rename = "Earned Work 24h")]
+    pub earned_work_24h: Difficulty,
+    /// Hashrate accepted by the pool relative to the expected hashrate (0 when unknown)
+    #[serde(rename = "Health Score")]
+    pub health_score: Percent,
+    /// Health score has been low for a sustained period
+    #[serde(rename = "Health Alert")]
+    pub health_alert: Bool,
+    /// Distribution of pool latency measured within the rolling window (0 when unknown)
+    #[serde(rename = "Latency Last")]
+    pub latency_last: Milliseconds,
+    #[serde(rename = "Latency Min")]
+    pub latency_min: Milliseconds,
+    #[serde(rename = "Latency Mean")]
+    pub latency_mean: Milliseconds,
+    #[serde(rename = "Latency Median")]
+    pub latency_median: Milliseconds,
+    #[serde(rename = "Latency P95")]
+    pub latency_p95: Milliseconds,
+    #[serde(rename = "Latency Max")]
+    pub latency_max: Milliseconds,
 }
 
 #[derive(Serialize, PartialEq, Clone, Debug)]
@@ -591,6 +690,11 @@ pub struct Summary {
     // Follows attribute extensions
     #[serde(rename = "MHS 24h")]
     pub mhs_24h: MegaHashes,
+    /// Found blocks including the ones from previous runs
+    #[serde(rename = "Total Found Blocks")]
+    pub total_found_blocks: u32,
+    #[serde(rename = "Last Found Block")]
+    pub last_found_block: Time,
 }
 
 impl From<Summary> for Dispatch {
@@ -750,6 +854,87 @@ impl From<RemovePool> for Dispatch {
     }
 }
 
+/// Reason of the last device failure as reported by `notify` command
+#[allow(dead_code)]
+#[derive(Serialize, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum NotifyReason {
+    None,
+    #[serde(rename = "Thread failed to init")]
+    ThreadFailInit,
+    #[serde(rename = "Thread got zero hashes")]
+    ThreadZeroHash,
+    #[serde(rename = "Thread failed to queue work")]
+    ThreadFailQueue,
+    #[serde(rename = "Device idle for 60s")]
+    DevSickIdle60,
+    #[serde(rename = "Device dead - idle for 600s")]
+    DevDeadIdle600,
+    #[serde(rename = "Device never started")]
+    DevNoStart,
+    #[serde(rename = "Device over heated")]
+    DevOverHeat,
+    #[serde(rename = "Device thermal cutoff")]
+    DevThermalCutoff,
+    #[serde(rename = "Comms error")]
+    DevCommsError,
+    #[serde(rename = "Device throttling")]
+    DevThrottle,
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct NotifyInfo {
+    #[serde(rename = "NOTIFY")]
+    pub idx: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(rename = "Last Well")]
+    pub last_well: Time,
+    #[serde(rename = "Last Not Well")]
+    pub last_not_well: Time,
+    #[serde(rename = "Reason Not Well")]
+    pub reason_not_well: NotifyReason,
+    #[serde(rename = "*Thread Fail Init")]
+    pub thread_fail_init: u32,
+    #[serde(rename = "*Thread Zero Hash")]
+    pub thread_zero_hash: u32,
+    #[serde(rename = "*Thread Fail Queue")]
+    pub thread_fail_queue: u32,
+    #[serde(rename = "*Dev Sick Idle 60s")]
+    pub dev_sick_idle_60s: u32,
+    #[serde(rename = "*Dev Dead Idle 600s")]
+    pub dev_dead_idle_600s: u32,
+    #[serde(rename = "*Dev Nostart")]
+    pub dev_nostart: u32,
+    #[serde(rename = "*Dev Over Heat")]
+    pub dev_over_heat: u32,
+    #[serde(rename = "*Dev Thermal Cutoff")]
+    pub dev_thermal_cutoff: u32,
+    #[serde(rename = "*Dev Comms Error")]
+    pub dev_comms_error: u32,
+    #[serde(rename = "*Dev Throttle")]
+    pub dev_throttle: u32,
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Notify {
+    pub list: Vec<NotifyInfo>,
+}
+
+impl From<Notify> for Dispatch {
+    fn from(notify: Notify) -> Self {
+        Dispatch::from_success(
+            StatusCode::Notify.into(),
+            "Notify".to_string(),
+            Some(Body {
+                name: "NOTIFY",
+                list: notify.list,
+            }),
+        )
+    }
+}
+
 #[derive(Serialize, PartialEq, Clone, Debug)]
 pub struct DevDetail<T> {
     #[serde(rename = "DEVDETAILS")]
@@ -843,6 +1028,10 @@ pub struct PoolStats {
 pub struct AscStats {
     #[serde(flatten)]
     pub header: StatsHeader,
+    /// Serial number of the device when it is known
+    #[serde(rename = "Serial")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
 }
 
 #[derive(Serialize, PartialEq, Clone, Debug)]
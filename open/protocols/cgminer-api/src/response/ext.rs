@@ -125,3 +125,906 @@ impl From<Fans> for Dispatch {
         )
     }
 }
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Chip<T> {
+    #[serde(rename = "CHIP")]
+    pub idx: i32,
+    /// ID of the device (hash chain) the chip belongs to
+    #[serde(rename = "ID")]
+    pub id: i32,
+    /// Position of the chip on the hash chain
+    #[serde(rename = "Chip")]
+    pub chip: i32,
+    #[serde(flatten)]
+    pub info: T,
+}
+
+pub struct Chips<T> {
+    pub list: Vec<Chip<T>>,
+}
+
+impl<T> From<Chips<T>> for Dispatch
+where
+    T: serde::Serialize,
+{
+    fn from(chips: Chips<T>) -> Self {
+        let chip_count = chips.list.len();
+        Dispatch::from_success(
+            StatusCode::Chips.into(),
+            format!("{} Chip(s)", chip_count),
+            Some(Body {
+                name: "CHIPS",
+                list: chips.list,
+            }),
+        )
+    }
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Event {
+    #[serde(rename = "EVENT")]
+    pub idx: i32,
+    #[serde(rename = "When")]
+    pub when: Time,
+    /// Short machine readable identifier of the event kind
+    #[serde(rename = "Code")]
+    pub code: String,
+    #[serde(rename = "Fault")]
+    pub fault: Bool,
+    #[serde(rename = "Msg")]
+    pub msg: String,
+}
+
+pub struct Events {
+    pub list: Vec<Event>,
+}
+
+impl From<Events> for Dispatch {
+    fn from(events: Events) -> Self {
+        let event_count = events.list.len();
+        Dispatch::from_success(
+            StatusCode::Events.into(),
+            format!("{} Event(s)", event_count),
+            Some(Body {
+                name: "EVENTS",
+                list: events.list,
+            }),
+        )
+    }
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Task {
+    #[serde(rename = "TASK")]
+    pub idx: i32,
+    /// Unique identifier of the task in the task registry
+    #[serde(rename = "ID")]
+    pub id: u64,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Alive")]
+    pub alive: Bool,
+    #[serde(rename = "Polls")]
+    pub polls: u64,
+    /// Seconds since the task has been spawned
+    #[serde(rename = "Elapsed")]
+    pub elapsed: Elapsed,
+    /// Seconds since the last activity of the task
+    #[serde(rename = "Idle")]
+    pub idle: Interval,
+}
+
+pub struct Tasks {
+    pub list: Vec<Task>,
+}
+
+impl From<Tasks> for Dispatch {
+    fn from(tasks: Tasks) -> Self {
+        let task_count = tasks.list.len();
+        Dispatch::from_success(
+            StatusCode::Tasks.into(),
+            format!("{} Task(s)", task_count),
+            Some(Body {
+                name: "TASKS",
+                list: tasks.list,
+            }),
+        )
+    }
+}
+
+/// Operating point requested for one hash chain
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct ChainTuning {
+    #[serde(rename = "TUNE")]
+    pub idx: i32,
+    #[serde(rename = "ID")]
+    pub id: i32,
+    /// Requested frequency in MHz (the hash chain approaches it gradually)
+    #[serde(rename = "Frequency")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    /// Requested voltage in volts
+    #[serde(rename = "Voltage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+}
+
+pub struct Tune {
+    pub list: Vec<ChainTuning>,
+}
+
+impl From<Tune> for Dispatch {
+    fn from(tune: Tune) -> Self {
+        let chain_count = tune.list.len();
+        Dispatch::from_success(
+            StatusCode::Tune.into(),
+            format!("Tuning requested for {} chain(s)", chain_count),
+            Some(Body {
+                name: "TUNE",
+                list: tune.list,
+            }),
+        )
+    }
+}
+
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Profile {
+    #[serde(rename = "PROFILE")]
+    pub idx: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// Comma separated days of week when the window starts
+    #[serde(rename = "Days")]
+    pub days: String,
+    #[serde(rename = "Start")]
+    pub start: String,
+    #[serde(rename = "End")]
+    pub end: String,
+    /// Frequency in MHz
+    #[serde(rename = "Frequency")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    /// Voltage in volts
+    #[serde(rename = "Voltage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+    #[serde(rename = "Paused")]
+    pub paused: Bool,
+    #[serde(rename = "Active")]
+    pub active: Bool,
+    /// The profile has been selected manually
+    #[serde(rename = "Override")]
+    pub override_: Bool,
+}
+
+pub struct Profiles {
+    pub list: Vec<Profile>,
+}
+
+impl From<Profiles> for Dispatch {
+    fn from(profiles: Profiles) -> Self {
+        let active = profiles
+            .list
+            .iter()
+            .find(|profile| profile.active == Bool::Y)
+            .map(|profile| format!("'{}'", profile.name))
+            .unwrap_or_else(|| "default".to_string());
+        Dispatch::from_success(
+            StatusCode::Profiles.into(),
+            format!("{} Profile(s), active {}", profiles.list.len(), active),
+            Some(Body {
+                name: "PROFILES",
+                list: profiles.list,
+            }),
+        )
+    }
+}
+
+pub struct Pause {
+    /// Mining has been paused by this command (and not before)
+    pub changed: bool,
+}
+
+impl From<Pause> for Dispatch {
+    fn from(pause: Pause) -> Self {
+        Dispatch::from_success::<()>(
+            StatusCode::Pause.into(),
+            if pause.changed {
+                "Mining paused".to_string()
+            } else {
+                "Mining already paused".to_string()
+            },
+            None,
+        )
+    }
+}
+
+pub struct Resume {
+    /// Mining has been resumed by this command (and not before)
+    pub changed: bool,
+}
+
+impl From<Resume> for Dispatch {
+    fn from(resume: Resume) -> Self {
+        Dispatch::from_success::<()>(
+            StatusCode::Resume.into(),
+            if resume.changed {
+                "Mining resumed".to_string()
+            } else {
+                "Mining not paused".to_string()
+            },
+            None,
+        )
+    }
+}
+
+/// Handling statistics of one API command
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct CommandStats {
+    #[serde(rename = "APISTATS")]
+    pub idx: i32,
+    #[serde(rename = "Command")]
+    pub command: String,
+    #[serde(rename = "Calls")]
+    pub calls: u64,
+    /// Calls exceeding the slow command threshold (including timed out ones)
+    #[serde(rename = "Slow Calls")]
+    pub slow_calls: u64,
+    #[serde(rename = "Timeouts")]
+    pub timeouts: u64,
+    /// Average duration of a call in seconds
+    #[serde(rename = "Average Duration")]
+    pub average_duration: Interval,
+    /// Longest duration of a call in seconds
+    #[serde(rename = "Max Duration")]
+    pub max_duration: Interval,
+}
+
+pub struct ApiStats {
+    pub list: Vec<CommandStats>,
+    /// Requests which weren't valid JSON
+    pub malformed_requests: u64,
+    /// Requests exceeding maximum frame size
+    pub oversized_requests: u64,
+}
+
+impl From<ApiStats> for Dispatch {
+    fn from(api_stats: ApiStats) -> Self {
+        let command_count = api_stats.list.len();
+        Dispatch::from_success(
+            StatusCode::ApiStats.into(),
+            format!(
+                "{} Command(s), {} Malformed Request(s), {} Oversized Request(s)",
+                command_count, api_stats.malformed_requests, api_stats.oversized_requests
+            ),
+            Some(Body {
+                name: "APISTATS",
+                list: api_stats.list,
+            }),
+        )
+    }
+}
+
+/// Command registered in the API
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct CommandInfo {
+    #[serde(rename = "HELP")]
+    pub idx: i32,
+    #[serde(rename = "Command")]
+    pub command: String,
+    /// Command accepts a parameter (it cannot be joined with other commands)
+    #[serde(rename = "Parameter")]
+    pub parameter: Bool,
+    /// Parameters delimited by commas (empty when they haven't been described)
+    #[serde(rename = "Parameters")]
+    pub parameters: String,
+}
+
+pub struct Help {
+    pub list: Vec<CommandInfo>,
+}
+
+impl From<Help> for Dispatch {
+    fn from(help: Help) -> Self {
+        let command_count = help.list.len();
+        Dispatch::from_success(
+            StatusCode::Help.into(),
+            format!("{} Command(s)", command_count),
+            Some(Body {
+                name: "HELP",
+                list: help.list,
+            }),
+        )
+    }
+}
+
+/// Progress of restart of one hash chain
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct ChainRestart {
+    #[serde(rename = "RESTARTCHAIN")]
+    pub idx: i32,
+    #[serde(rename = "ID")]
+    pub id: i32,
+    /// One of `Idle`, `Stopping`, `Starting`, `Done` or `Failed`
+    #[serde(rename = "Status")]
+    pub status: String,
+    /// The restart has been triggered by this command
+    #[serde(rename = "Triggered")]
+    pub triggered: Bool,
+    /// Number of restarts after the hash chain has been found broken by the monitor
+    #[serde(rename = "Broken Restarts")]
+    pub broken_restarts: u32,
+    /// Reason of the last failed restart
+    #[serde(rename = "Error")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub struct RestartChain {
+    pub list: Vec<ChainRestart>,
+}
+
+impl From<RestartChain> for Dispatch {
+    fn from(restart_chain: RestartChain) -> Self {
+        let triggered_count = restart_chain
+            .list
+            .iter()
+            .filter(|chain| chain.triggered == Bool::Y)
+            .count();
+        Dispatch::from_success(
+            StatusCode::RestartChain.into(),
+            format!("Restart triggered for {} chain(s)", triggered_count),
+            Some(Body {
+                name: "RESTARTCHAIN",
+                list: restart_chain.list,
+            }),
+        )
+    }
+}
+
+pub struct Identify {
+    /// Seconds the identification LED keeps blinking (zero when it has been turned off)
+    pub remaining: Interval,
+}
+
+impl From<Identify> for Dispatch {
+    fn from(identify: Identify) -> Self {
+        Dispatch::from_success::<()>(
+            StatusCode::Identify.into(),
+            if identify.remaining > 0.0 {
+                format!("Identifying for {:.0} s", identify.remaining)
+            } else {
+                "Identification stopped".to_string()
+            },
+            None,
+        )
+    }
+}
+
+/// Identity of the device used for its physical location
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct IdentInfo {
+    #[serde(rename = "Hostname")]
+    pub hostname: String,
+    #[serde(rename = "Model")]
+    pub model: String,
+    /// Serial number (hardware ID) of the control board
+    #[serde(rename = "Serial")]
+    pub serial: String,
+    /// MAC address of the network interface
+    #[serde(rename = "MAC")]
+    pub mac: String,
+    /// Comma separated serial numbers of hashboards
+    #[serde(rename = "Hashboard Serials")]
+    pub hashboard_serials: String,
+    /// Seconds the identification LED keeps blinking
+    #[serde(rename = "Identifying")]
+    pub identifying: Interval,
+}
+
+pub struct Ident {
+    pub info: IdentInfo,
+}
+
+impl From<Ident> for Dispatch {
+    fn from(ident: Ident) -> Self {
+        Dispatch::from_success(
+            StatusCode::Ident.into(),
+            format!("{} ident", crate::SIGNATURE_TAG),
+            Some(Body {
+                name: "IDENT",
+                list: vec![ident.info],
+            }),
+        )
+    }
+}
+
+/// Backpressure counters of the work pipeline between clients and backends
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct PipelineInfo {
+    #[serde(rename = "Generators")]
+    pub generators: u32,
+    /// Total seconds work generators waited for new work from clients
+    #[serde(rename = "Generator Wait")]
+    pub generator_wait: Interval,
+    #[serde(rename = "Generator Waits")]
+    pub generator_waits: u64,
+    /// Number of times backends had to wait for room in work FIFO
+    #[serde(rename = "TX FIFO Full")]
+    pub tx_fifo_full: u64,
+    /// Number of times solution FIFO has been full (solutions may have been lost)
+    #[serde(rename = "RX FIFO Overflow")]
+    pub rx_fifo_overflow: u64,
+    /// Solutions found by backends which haven't been taken by clients yet
+    #[serde(rename = "Pending Solutions")]
+    pub pending_solutions: u32,
+    #[serde(rename = "Max Pending Solutions")]
+    pub max_pending_solutions: u32,
+    /// Number of times pending solutions exceeded the saturation threshold
+    #[serde(rename = "Solution Channel Saturated")]
+    pub solution_channel_saturated: u64,
+    /// Works generated ahead of time which haven't been sent to hardware yet
+    #[serde(rename = "Prefetch Depth")]
+    pub prefetch_depth: u32,
+    /// Sum of adaptive depths of all prefetch buffers
+    #[serde(rename = "Prefetch Target Depth")]
+    pub prefetch_target_depth: u32,
+    /// Number of prefetched works discarded after job change
+    #[serde(rename = "Prefetch Stale")]
+    pub prefetch_stale: u64,
+    /// Number of times backends found prefetch buffer empty
+    #[serde(rename = "Prefetch Underruns")]
+    pub prefetch_underruns: u64,
+}
+
+pub struct Pipeline {
+    pub info: PipelineInfo,
+}
+
+impl From<Pipeline> for Dispatch {
+    fn from(pipeline: Pipeline) -> Self {
+        Dispatch::from_success(
+            StatusCode::Pipeline.into(),
+            format!("{} pipeline", crate::SIGNATURE_TAG),
+            Some(Body {
+                name: "PIPELINE",
+                list: vec![pipeline.info],
+            }),
+        )
+    }
+}
+
+/// Reason of the previous termination of the miner
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct ShutdownInfo {
+    #[serde(rename = "When")]
+    pub when: Time,
+    /// Short machine readable identifier of the reason (e.g. `thermal_shutdown` or `crash`)
+    #[serde(rename = "Reason")]
+    pub reason: String,
+    #[serde(rename = "Exit Code")]
+    pub exit_code: i32,
+    #[serde(rename = "Msg")]
+    pub msg: String,
+}
+
+pub struct LastShutdown {
+    /// Empty when no shutdown has been recorded yet
+    pub list: Vec<ShutdownInfo>,
+}
+
+impl From<LastShutdown> for Dispatch {
+    fn from(last_shutdown: LastShutdown) -> Self {
+        Dispatch::from_success(
+            StatusCode::LastShutdown.into(),
+            match last_shutdown.list.first() {
+                Some(info) => format!("Last shutdown: {}", info.reason),
+                None => "No shutdown recorded".to_string(),
+            },
+            Some(Body {
+                name: "LASTSHUTDOWN",
+                list: last_shutdown.list,
+            }),
+        )
+    }
+}
+
+pub struct EditPool {
+    pub idx: usize,
+    pub url: String,
+    /// The change has been written to the configuration file
+    pub persisted: bool,
+}
+
+impl From<EditPool> for Dispatch {
+    fn from(edit_pool: EditPool) -> Self {
+        Dispatch::from_success::<()>(
+            StatusCode::EditPool.into(),
+            format!(
+                "Edited pool {}:'{}'{}",
+                edit_pool.idx,
+                edit_pool.url,
+                if edit_pool.persisted {
+                    " and saved configuration"
+                } else {
+                    ""
+                }
+            ),
+            None,
+        )
+    }
+}
+
+/// Shares submitted to one pool which have been found by one hash chain
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct ShareAttribution {
+    #[serde(rename = "SHARES")]
+    pub idx: i32,
+    /// Index of the hash chain in `devs` response
+    #[serde(rename = "ASC")]
+    pub asc: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// Index of the pool in `pools` response
+    #[serde(rename = "POOL")]
+    pub pool: i32,
+    #[serde(rename = "URL")]
+    pub url: String,
+    #[serde(rename = "Accepted")]
+    pub accepted: u64,
+    #[serde(rename = "Rejected")]
+    pub rejected: u64,
+    #[serde(rename = "Difficulty Accepted")]
+    pub difficulty_accepted: f64,
+    #[serde(rename = "Difficulty Rejected")]
+    pub difficulty_rejected: f64,
+    /// Hashrate of shares accepted by the pool
+    #[serde(rename = "MHS 5m")]
+    pub mhs_5m: f64,
+    #[serde(rename = "MHS 15m")]
+    pub mhs_15m: f64,
+}
+
+pub struct Shares {
+    pub list: Vec<ShareAttribution>,
+}
+
+impl From<Shares> for Dispatch {
+    fn from(shares: Shares) -> Self {
+        let share_count = shares.list.len();
+        Dispatch::from_success(
+            StatusCode::Shares.into(),
+            format!("{} Share Attribution(s)", share_count),
+            Some(Body {
+                name: "SHARES",
+                list: shares.list,
+            }),
+        )
+    }
+}
+
+/// Result of replacement of the whole pool group configuration
+pub struct SetGroups {
+    pub groups: usize,
+    /// Pools which haven't been configured before
+    pub added: usize,
+    /// Pools kept connected from the previous configuration
+    pub kept: usize,
+    /// Pools which are no longer configured and are finishing their pending shares
+    pub removed: usize,
+}
+
+impl From<SetGroups> for Dispatch {
+    fn from(set_groups: SetGroups) -> Self {
+        Dispatch::from_success::<()>(
+            StatusCode::SetGroups.into(),
+            format!(
+                "Configured {} group(s) with {} added, {} kept and {} removed pool(s)",
+                set_groups.groups, set_groups.added, set_groups.kept, set_groups.removed
+            ),
+            None,
+        )
+    }
+}
+
+/// Pool receiving a fixed share of generated work as a fee
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct FeePool {
+    #[serde(rename = "FEE")]
+    pub idx: i32,
+    #[serde(rename = "URL")]
+    pub url: String,
+    #[serde(rename = "User")]
+    pub user: String,
+    #[serde(rename = "Status")]
+    pub status: PoolStatus,
+    /// Configured percentage of generated work
+    #[serde(rename = "Percent")]
+    pub percent: f64,
+    /// Percentage of work really generated for the pool since the last change of pool groups
+    #[serde(rename = "Generated Percent")]
+    pub generated_percent: f64,
+    #[serde(rename = "Works")]
+    pub works: u64,
+    #[serde(rename = "Accepted")]
+    pub accepted: u64,
+    #[serde(rename = "Rejected")]
+    pub rejected: u64,
+    #[serde(rename = "Difficulty Accepted")]
+    pub difficulty_accepted: f64,
+    #[serde(rename = "Difficulty Rejected")]
+    pub difficulty_rejected: f64,
+}
+
+pub struct Fee {
+    pub list: Vec<FeePool>,
+}
+
+impl From<Fee> for Dispatch {
+    fn from(fee: Fee) -> Self {
+        let msg = if fee.list.is_empty() {
+            "Fee is disabled".to_string()
+        } else {
+            format!("{} Fee Pool(s)", fee.list.len())
+        };
+        Dispatch::from_success(
+            StatusCode::Fee.into(),
+            msg,
+            Some(Body {
+                name: "FEE",
+                list: fee.list,
+            }),
+        )
+    }
+}
+
+/// Hardware self-check of one hashboard performed when the miner starts
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct SelfCheckInfo {
+    #[serde(rename = "SELFCHECK")]
+    pub idx: i32,
+    /// Index of the hashboard (hash chain)
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(rename = "Serial")]
+    pub serial: String,
+    /// One of `Disabled`, `Pending`, `OK` or `Failed`
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "Chips")]
+    pub chips: u32,
+    /// Zero when the number of chips is not known
+    #[serde(rename = "Expected Chips")]
+    pub expected_chips: u32,
+    /// Number of temperature sensors found on the hashboard
+    #[serde(rename = "Sensors")]
+    pub sensors: u32,
+    /// Version of voltage controller firmware, empty when it hasn't been read
+    #[serde(rename = "PIC Version")]
+    pub pic_version: String,
+    /// Version of FPGA bitstream, empty when it hasn't been read
+    #[serde(rename = "FPGA Version")]
+    pub fpga_version: String,
+    #[serde(rename = "Msg")]
+    pub msg: String,
+}
+
+pub struct SelfCheck {
+    /// Number of fans spinning when the self-check has been done
+    pub fans: usize,
+    pub list: Vec<SelfCheckInfo>,
+}
+
+impl From<SelfCheck> for Dispatch {
+    fn from(self_check: SelfCheck) -> Self {
+        let ok_count = self_check
+            .list
+            .iter()
+            .filter(|info| info.status == "OK")
+            .count();
+        Dispatch::from_success(
+            StatusCode::SelfCheck.into(),
+            format!(
+                "{}/{} hashboard(s) OK, {} fan(s) running",
+                ok_count,
+                self_check.list.len(),
+                self_check.fans
+            ),
+            Some(Body {
+                name: "SELFCHECK",
+                list: self_check.list,
+            }),
+        )
+    }
+}
+
+/// Log record captured in memory of the miner
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct LogRecord {
+    #[serde(rename = "LOG")]
+    pub idx: i32,
+    #[serde(rename = "When")]
+    pub when: Time,
+    #[serde(rename = "Level")]
+    pub level: String,
+    #[serde(rename = "Module")]
+    pub module: String,
+    #[serde(rename = "Msg")]
+    pub msg: String,
+}
+
+pub struct Logs {
+    /// Records ordered from the oldest one
+    pub list: Vec<LogRecord>,
+}
+
+impl From<Logs> for Dispatch {
+    fn from(logs: Logs) -> Self {
+        let record_count = logs.list.len();
+        Dispatch::from_success(
+            StatusCode::Logs.into(),
+            format!("{} Log Record(s)", record_count),
+            Some(Body {
+                name: "LOGS",
+                list: logs.list,
+            }),
+        )
+    }
+}
+
+/// Filter of log records captured in memory
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct LogFilter {
+    /// Levels of captured records in `RUST_LOG` format (e.g. `info,bosminer::client=debug`)
+    #[serde(rename = "Filter")]
+    pub filter: String,
+    /// Maximal number of captured records (0 means that logs are not captured)
+    #[serde(rename = "Capacity")]
+    pub capacity: u32,
+}
+
+impl From<LogFilter> for Dispatch {
+    fn from(log_filter: LogFilter) -> Self {
+        Dispatch::from_success(
+            StatusCode::LogFilter.into(),
+            format!("Log filter '{}'", log_filter.filter),
+            Some(Body {
+                name: "LOGFILTER",
+                list: vec![log_filter],
+            }),
+        )
+    }
+}
+
+/// Shares rejected by one pool split by reason of rejection
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct PoolRejections {
+    /// Index of the pool in `pools` response
+    #[serde(rename = "POOL")]
+    pub idx: i32,
+    #[serde(rename = "URL")]
+    pub url: String,
+    #[serde(rename = "Rejected")]
+    pub rejected: u64,
+    /// Share of a job which has been already replaced by a new block (network latency)
+    #[serde(rename = "Stale")]
+    pub stale: u64,
+    /// Share which doesn't meet the target of the job
+    #[serde(rename = "Low Difficulty")]
+    pub low_difficulty: u64,
+    #[serde(rename = "Duplicate")]
+    pub duplicate: u64,
+    #[serde(rename = "Job Not Found")]
+    pub job_not_found: u64,
+    /// Rejections with unrecognized reason
+    #[serde(rename = "Other")]
+    pub other: u64,
+}
+
+pub struct Rejections {
+    pub list: Vec<PoolRejections>,
+}
+
+impl From<Rejections> for Dispatch {
+    fn from(rejections: Rejections) -> Self {
+        let pool_count = rejections.list.len();
+        Dispatch::from_success(
+            StatusCode::Rejections.into(),
+            format!("{} Pool(s)", pool_count),
+            Some(Body {
+                name: "REJECTIONS",
+                list: rejections.list,
+            }),
+        )
+    }
+}
+
+/// Number of midstates (versions of block header) hashed in one work
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct AsicBoostInfo {
+    #[serde(rename = "Midstate Count")]
+    pub midstate_count: u32,
+    /// Set when the midstate count has been changed by the command
+    #[serde(rename = "Switched")]
+    pub switched: Bool,
+}
+
+pub struct AsicBoost {
+    pub info: AsicBoostInfo,
+}
+
+impl From<AsicBoost> for Dispatch {
+    fn from(asic_boost: AsicBoost) -> Self {
+        Dispatch::from_success(
+            StatusCode::AsicBoost.into(),
+            format!("{} midstate(s)", asic_boost.info.midstate_count),
+            Some(Body {
+                name: "ASICBOOST",
+                list: vec![asic_boost.info],
+            }),
+        )
+    }
+}
+
+/// Node of the work solver hierarchy of the backend (e.g. backend, hash chain manager, hash chain)
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Node {
+    /// Index of the node in pre-order traversal of the hierarchy
+    #[serde(rename = "NODE")]
+    pub idx: i32,
+    /// Work solver specific identifier (e.g. hash chain index)
+    #[serde(rename = "ID")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// Work hubs only route work to their child nodes
+    #[serde(rename = "Work Hub")]
+    pub work_hub: Bool,
+    /// Hashrate expected from the hardware (0 when unknown)
+    #[serde(rename = "Nominal MHS")]
+    pub nominal_mhs: MegaHashes,
+    #[serde(rename = "MHS 5s")]
+    pub mhs_5s: MegaHashes,
+    #[serde(rename = "MHS 1m")]
+    pub mhs_1m: MegaHashes,
+    #[serde(rename = "MHS 5m")]
+    pub mhs_5m: MegaHashes,
+    #[serde(rename = "MHS 15m")]
+    pub mhs_15m: MegaHashes,
+    #[serde(rename = "MHS 24h")]
+    pub mhs_24h: MegaHashes,
+    #[serde(rename = "Hardware Errors")]
+    pub hardware_errors: u64,
+    #[serde(rename = "Best Share")]
+    pub best_share: u64,
+    #[serde(rename = "Children")]
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Number of nodes in the subtree including this one
+    pub fn count(&self) -> usize {
+        1 + self.children.iter().map(Node::count).sum::<usize>()
+    }
+}
+
+pub struct Nodes {
+    /// Root nodes of the hierarchy (there is usually just one)
+    pub list: Vec<Node>,
+}
+
+impl From<Nodes> for Dispatch {
+    fn from(nodes: Nodes) -> Self {
+        let node_count: usize = nodes.list.iter().map(Node::count).sum();
+        Dispatch::from_success(
+            StatusCode::Nodes.into(),
+            format!("{} Node(s)", node_count),
+            Some(Body {
+                name: "NODES",
+                list: nodes.list,
+            }),
+        )
+    }
+}
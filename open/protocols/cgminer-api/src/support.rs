@@ -24,11 +24,13 @@
 
 use crate::response;
 
+use ii_async_compat::{futures::Future, tokio};
+
 use serde::{Serialize, Serializer};
 use serde_json as json;
 
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 
 pub trait When: Send + Sync {
     fn when() -> response::Time;
@@ -45,6 +47,64 @@ impl When for UnixTime {
     }
 }
 
+/// Determines how strictly responses follow the original CGMiner
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Compatibility {
+    /// Every response is timestamped when it is created
+    Native,
+    /// All responses of one request share a single timestamp and `Elapsed` attributes are
+    /// computed against it exactly like in the legacy CGMiner
+    Legacy,
+}
+
+impl Default for Compatibility {
+    fn default() -> Self {
+        Compatibility::Native
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_TIMESTAMP: TimestampSplit;
+}
+
+/// Instant of request processing split into the wall clock part rendered in `When` and the
+/// monotonic part used for computing `Elapsed` so that both refer to the same moment
+#[derive(Copy, Clone, Debug)]
+pub struct TimestampSplit {
+    pub when: response::Time,
+    pub instant: Instant,
+}
+
+impl TimestampSplit {
+    pub fn now<T: When>() -> Self {
+        Self {
+            when: T::when(),
+            instant: Instant::now(),
+        }
+    }
+
+    /// Timestamp of the request being processed in legacy compatibility mode or the current
+    /// time otherwise
+    pub fn current() -> Self {
+        REQUEST_TIMESTAMP
+            .try_with(|timestamp| *timestamp)
+            .unwrap_or_else(|_| Self::now::<UnixTime>())
+    }
+
+    /// Run `f` with the timestamp available through `TimestampSplit::current()`
+    pub(crate) async fn scope<F: Future>(self, f: F) -> F::Output {
+        REQUEST_TIMESTAMP.scope(self, f).await
+    }
+
+    /// Whole seconds elapsed from `start` to this timestamp
+    pub fn elapsed_since(&self, start: Instant) -> response::Elapsed {
+        self.instant
+            .checked_duration_since(start)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
+
 pub trait ValueExt {
     fn to_i32(&self) -> Option<i32>;
 
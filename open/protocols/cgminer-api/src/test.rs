@@ -22,27 +22,37 @@
 
 //! Tests for the CGMiner API module
 
-mod handler;
+pub mod handler;
 mod utils;
 
 use crate::command;
+use crate::command::{NOTIFY, PAUSE};
 use crate::commands;
+use crate::parameter::Parameters;
 use crate::response;
+use crate::support::{self, Compatibility};
+use crate::{Codec, FrameError};
 
 use utils::{assert_json_eq, codec_roundtrip};
 
-use ii_async_compat::tokio;
+use ii_async_compat::{bytes, tokio, tokio_util};
+use tokio_util::codec::Decoder;
+
+use bytes::BytesMut;
 
 use serde::Serialize;
 use serde_json as json;
 
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 #[repr(u32)]
 pub enum CustomStatusCode {
     CustomCommandOne = 1,
     CustomCommandTwo = 2,
+    CustomCommandThree = 3,
 
     // custom error codes
     MissingParameter = 10,
@@ -113,6 +123,31 @@ impl From<CustomCommandTwo> for response::Dispatch {
     }
 }
 
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct CustomCommandThree {
+    #[serde(rename = "Idx")]
+    pub idx: u32,
+    #[serde(rename = "Value")]
+    pub value: f64,
+}
+
+impl From<CustomCommandThree> for response::Dispatch {
+    fn from(custom_command: CustomCommandThree) -> Self {
+        response::Dispatch::from_custom_success(
+            CustomStatusCode::CustomCommandThree,
+            format!(
+                "{} custom command {} with parameters",
+                crate::SIGNATURE_TAG,
+                3
+            ),
+            Some(response::Body {
+                name: "CUSTOM_COMMAND_THREE",
+                list: vec![custom_command],
+            }),
+        )
+    }
+}
+
 struct TestCustomHandler;
 
 impl TestCustomHandler {
@@ -133,6 +168,45 @@ impl TestCustomHandler {
                 CustomCommandTwo { value }
             })
     }
+
+    async fn handle_command_three(
+        &self,
+        parameter: Option<&json::Value>,
+    ) -> command::Result<CustomCommandThree> {
+        let parameters = Parameters::new(parameter);
+        Ok(CustomCommandThree {
+            idx: parameters.get(0, "idx")?,
+            value: parameters.get_opt(1, "value")?.unwrap_or_default(),
+        })
+    }
+
+    async fn handle_stuck(&self) -> command::Result<CustomCommandOne> {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        self.handle_command_one().await
+    }
+
+    async fn handle_notify(&self) -> command::Result<response::Notify> {
+        Ok(response::Notify {
+            list: vec![response::NotifyInfo {
+                idx: 0,
+                name: "BC5".to_string(),
+                id: 0,
+                last_well: 10,
+                last_not_well: 5,
+                reason_not_well: response::NotifyReason::DevCommsError,
+                thread_fail_init: 0,
+                thread_zero_hash: 0,
+                thread_fail_queue: 0,
+                dev_sick_idle_60s: 0,
+                dev_dead_idle_600s: 0,
+                dev_nostart: 0,
+                dev_over_heat: 0,
+                dev_thermal_cutoff: 0,
+                dev_comms_error: 1,
+                dev_throttle: 0,
+            }],
+        })
+    }
 }
 
 #[tokio::test]
@@ -235,6 +309,49 @@ async fn test_single_custom_command() {
     assert_json_eq(&response, &expected);
 }
 
+#[tokio::test]
+async fn test_notify() {
+    let handler = Arc::new(TestCustomHandler);
+
+    let custom_commands = commands![
+        (NOTIFY: ParameterLess -> handler.handle_notify)
+    ];
+
+    let command: json::Value = json::json!({ "command": NOTIFY });
+
+    let response = codec_roundtrip(command, custom_commands).await;
+    let expected = json::json!({
+        "STATUS": [{
+            "STATUS": "S",
+            "When": 0,
+            "Code": 60,
+            "Msg": "Notify",
+            "Description": "TestMiner v1.0",
+        }],
+        "NOTIFY": [{
+            "NOTIFY": 0,
+            "Name": "BC5",
+            "ID": 0,
+            "Last Well": 10,
+            "Last Not Well": 5,
+            "Reason Not Well": "Comms error",
+            "*Thread Fail Init": 0,
+            "*Thread Zero Hash": 0,
+            "*Thread Fail Queue": 0,
+            "*Dev Sick Idle 60s": 0,
+            "*Dev Dead Idle 600s": 0,
+            "*Dev Nostart": 0,
+            "*Dev Over Heat": 0,
+            "*Dev Thermal Cutoff": 0,
+            "*Dev Comms Error": 1,
+            "*Dev Throttle": 0,
+        }],
+        "id": 1
+    });
+
+    assert_json_eq(&response, &expected);
+}
+
 #[tokio::test]
 async fn test_single_custom_command_with_parameter() {
     let handler = Arc::new(TestCustomHandler);
@@ -267,6 +384,63 @@ async fn test_single_custom_command_with_parameter() {
     assert_json_eq(&response, &expected);
 }
 
+#[tokio::test]
+async fn test_custom_command_with_parameters() {
+    let handler = Arc::new(TestCustomHandler);
+
+    const CUSTOM_COMMAND: &str = "custom_command";
+
+    let expected = json::json!({
+        "STATUS": [{
+            "STATUS": "S",
+            "When": 0,
+            "Code": 303,
+            "Msg": "TestMiner custom command 3 with parameters",
+            "Description": "TestMiner v1.0",
+        }],
+        "CUSTOM_COMMAND_THREE": [{
+            "Idx": 1,
+            "Value": 2.5,
+        }],
+        "id": 1
+    });
+
+    // CGMiner compatible parameters and named parameters produce the same result
+    for parameter in &[json::json!("1,2.5"), json::json!({"idx": 1, "value": 2.5})] {
+        let custom_commands = commands![
+            (CUSTOM_COMMAND: Parameter(None) -> handler.handle_command_three)
+        ];
+        let command: json::Value = json::json!({
+            "command": CUSTOM_COMMAND,
+            "parameter": parameter
+        });
+
+        let response = codec_roundtrip(command, custom_commands).await;
+        assert_json_eq(&response, &expected);
+    }
+
+    let custom_commands = commands![
+        (CUSTOM_COMMAND: Parameter(None) -> handler.handle_command_three)
+    ];
+    let command: json::Value = json::json!({
+        "command": CUSTOM_COMMAND,
+        "parameter": {"value": 2.5}
+    });
+
+    let response = codec_roundtrip(command, custom_commands).await;
+    let expected = json::json!({
+        "STATUS": [{
+            "STATUS": "E",
+            "When": 0,
+            "Code": 250,
+            "Msg": "Missing parameter 'idx'",
+            "Description": "TestMiner v1.0",
+        }],
+        "id": 1
+    });
+    assert_json_eq(&response, &expected);
+}
+
 #[tokio::test]
 async fn test_single_custom_command_error() {
     let handler = Arc::new(TestCustomHandler);
@@ -338,3 +512,287 @@ async fn test_multiple_custom_commands() {
 
     assert_json_eq(&response, &expected);
 }
+
+static COUNTING_TIME: AtomicU32 = AtomicU32::new(1);
+
+/// Every response gets a different time
+struct CountingTime;
+
+impl support::When for CountingTime {
+    fn when() -> response::Time {
+        COUNTING_TIME.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+async fn multiple_when(compatibility: Compatibility) -> Vec<json::Value> {
+    let command_receiver = command::Receiver::<CountingTime>::new(
+        handler::BasicTest,
+        "TestMiner".to_string(),
+        "v1.0".to_string(),
+        None,
+    )
+    .with_compatibility(compatibility);
+    let request = command::Request::new(json::json!({ "command": "version+config+summary" }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+
+    ["version", "config", "summary"]
+        .iter()
+        .map(|command| response[command][0]["STATUS"][0]["When"].clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_legacy_when() {
+    let when = multiple_when(Compatibility::Native).await;
+    assert_ne!(when[0], when[1]);
+    assert_ne!(when[1], when[2]);
+
+    // legacy CGMiner timestamps all responses of a request at once
+    let when = multiple_when(Compatibility::Legacy).await;
+    assert!(when[0].is_u64());
+    assert_eq!(when[0], when[1]);
+    assert_eq!(when[1], when[2]);
+}
+
+#[tokio::test]
+async fn test_timestamp_split_scope() {
+    let timestamp = support::TimestampSplit::now::<CountingTime>();
+    let current = timestamp
+        .scope(async { support::TimestampSplit::current() })
+        .await;
+    assert_eq!(current.when, timestamp.when);
+    assert_eq!(current.instant, timestamp.instant);
+    assert_eq!(timestamp.elapsed_since(timestamp.instant), 0);
+}
+
+#[tokio::test]
+async fn test_command_timeout() {
+    let handler = Arc::new(TestCustomHandler);
+
+    const STUCK_COMMAND: &str = "stuck";
+    let custom_commands = commands![
+        (STUCK_COMMAND: ParameterLess -> handler.handle_stuck)
+    ];
+    let command_receiver = command::Receiver::<CountingTime>::new(
+        handler::BasicTest,
+        "TestMiner".to_string(),
+        "v1.0".to_string(),
+        custom_commands,
+    )
+    .with_command_timeout(STUCK_COMMAND, Duration::from_millis(10));
+
+    let request = command::Request::new(json::json!({ "command": STUCK_COMMAND }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+    assert_eq!(response["STATUS"][0]["STATUS"], "E");
+    assert_eq!(response["STATUS"][0]["Code"], 252);
+    assert_eq!(response["STATUS"][0]["Msg"], "Command 'stuck' timed out");
+
+    // other commands are not affected by the stuck one
+    let request = command::Request::new(json::json!({ "command": "summary" }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+    assert_eq!(response["STATUS"][0]["STATUS"], "S");
+
+    let request = command::Request::new(json::json!({ "command": "apistats" }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+    let stats = &response["APISTATS"];
+    assert_eq!(stats[0]["Command"], STUCK_COMMAND);
+    assert_eq!(stats[0]["Calls"], 1);
+    assert_eq!(stats[0]["Slow Calls"], 1);
+    assert_eq!(stats[0]["Timeouts"], 1);
+    assert_eq!(stats[1]["Command"], "summary");
+    assert_eq!(stats[1]["Timeouts"], 0);
+}
+
+#[tokio::test]
+async fn test_subscription() {
+    let handler = Arc::new(TestCustomHandler);
+    // parameter-less command changing state of the miner
+    let custom_commands = commands![
+        (PAUSE: ParameterLess -> handler.handle_notify)
+    ];
+    let command_receiver = command::Receiver::<CountingTime>::new(
+        handler::BasicTest,
+        "TestMiner".to_string(),
+        "v1.0".to_string(),
+        custom_commands,
+    );
+    let subscription = |parameter: json::Value| {
+        command_receiver.subscription(&command::Request::new(json::json!({
+            "command": "subscribe",
+            "parameter": parameter,
+        })))
+    };
+    let error_code = |parameter: json::Value| {
+        let response = subscription(parameter)
+            .expect("BUG: subscription not recognized")
+            .expect_err("BUG: invalid subscription accepted");
+        json::to_value(response).unwrap()["STATUS"][0]["Code"].clone()
+    };
+
+    let request = command::Request::new(json::json!({ "command": "summary" }));
+    assert!(command_receiver.subscription(&request).is_none());
+
+    let summary = subscription(json::json!("summary+devs"))
+        .unwrap()
+        .expect("BUG: valid subscription rejected");
+    assert_eq!(summary.interval, command::DEFAULT_SUBSCRIPTION_INTERVAL);
+    let response = json::to_value(command_receiver.handle(summary.request()).await).unwrap();
+    assert_eq!(response["summary"][0]["STATUS"][0]["STATUS"], "S");
+    assert_eq!(response["devs"][0]["STATUS"][0]["STATUS"], "S");
+    drop(summary);
+
+    let summary = subscription(json::json!("summary,2.5"))
+        .unwrap()
+        .expect("BUG: valid subscription rejected");
+    assert_eq!(summary.interval, Duration::from_millis(2500));
+    drop(summary);
+
+    // missing commands
+    assert_eq!(error_code(json::json!(",5")), 250);
+    // interval out of range
+    assert_eq!(error_code(json::json!("summary,0.1")), 251);
+    assert_eq!(error_code(json::json!("summary,abc")), 251);
+    assert_eq!(error_code(json::json!("summary,1e300")), 251);
+    assert_eq!(error_code(json::json!("summary,inf")), 251);
+    // unknown command and commands which are not read-only
+    assert_eq!(error_code(json::json!("unknown")), 14);
+    assert_eq!(error_code(json::json!("summary+switchpool")), 45);
+    assert_eq!(error_code(json::json!("summary+pause")), 45);
+
+    // number of active subscriptions is limited
+    let mut subscriptions: Vec<_> = (0..command::MAX_SUBSCRIPTIONS)
+        .map(|_| {
+            subscription(json::json!("summary"))
+                .unwrap()
+                .expect("BUG: valid subscription rejected")
+        })
+        .collect();
+    assert_eq!(error_code(json::json!("summary")), 253);
+    subscriptions.pop();
+    assert!(subscription(json::json!("summary")).unwrap().is_ok());
+    drop(subscriptions);
+
+    // subscription cannot be handled without connection
+    let request = command::Request::new(json::json!({
+        "command": "subscribe",
+        "parameter": "summary",
+    }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+    assert_eq!(response["STATUS"][0]["Code"], 45);
+}
+
+#[test]
+fn test_codec_frame_errors() {
+    let mut codec = Codec::default().with_max_frame_size(32);
+    let mut decode = |data: &[u8]| {
+        codec
+            .decode(&mut BytesMut::from(data))
+            .map(|request| request.is_some())
+            .map_err(|e| FrameError::from_io_error(&e))
+    };
+
+    assert_eq!(decode(b"{\"command\":\"summary\"}"), Ok(true));
+    // incomplete request waits for more data
+    assert_eq!(decode(b"  {\"command\":"), Ok(false));
+    assert_eq!(decode(b"  "), Ok(false));
+
+    // garbage is rejected without waiting for more data
+    assert_eq!(decode(b"GET / HTTP/1.1"), Err(Some(FrameError::Malformed)));
+    assert_eq!(decode(b"{\"command\" 1"), Err(Some(FrameError::Malformed)));
+    assert_eq!(
+        decode(b"{\"command\":\"summary\"} x"),
+        Err(Some(FrameError::Malformed))
+    );
+
+    // incomplete requests cannot grow over maximum frame size
+    let request = format!("{{\"command\":\"{}", "x".repeat(32));
+    assert_eq!(decode(request.as_bytes()), Err(Some(FrameError::Oversized)));
+    assert_eq!(
+        decode(" ".repeat(64).as_bytes()),
+        Err(Some(FrameError::Oversized))
+    );
+}
+
+#[tokio::test]
+async fn test_frame_error_stats() {
+    let command_receiver = command::Receiver::<CountingTime>::new(
+        handler::BasicTest,
+        "TestMiner".to_string(),
+        "v1.0".to_string(),
+        None,
+    );
+    command_receiver.account_frame_error(FrameError::Malformed);
+    command_receiver.account_frame_error(FrameError::Malformed);
+    command_receiver.account_frame_error(FrameError::Oversized);
+    assert_eq!(command_receiver.malformed_requests(), 2);
+    assert_eq!(command_receiver.oversized_requests(), 1);
+
+    let request = command::Request::new(json::json!({ "command": "apistats" }));
+    let response = json::to_value(command_receiver.handle(request).await).unwrap();
+    assert_eq!(
+        response["STATUS"][0]["Msg"],
+        "0 Command(s), 2 Malformed Request(s), 1 Oversized Request(s)"
+    );
+}
+
+#[tokio::test]
+async fn test_check() {
+    let handler = Arc::new(TestCustomHandler);
+
+    const CUSTOM_COMMAND: &str = "custom_command";
+    let custom_commands = || {
+        commands![
+            (CUSTOM_COMMAND: Parameter(None) -> handler.handle_command_two)
+        ]
+    };
+
+    for (command, expected) in &[("version", "Y"), (CUSTOM_COMMAND, "Y"), ("unknown", "N")] {
+        let request = json::json!({
+            "command": "check",
+            "parameter": command,
+        });
+        let response = codec_roundtrip(request, custom_commands()).await;
+        assert_eq!(response["STATUS"][0]["Code"], 72);
+        assert_eq!(response["CHECK"][0]["Exists"], *expected);
+        assert_eq!(response["CHECK"][0]["Access"], *expected);
+    }
+}
+
+#[tokio::test]
+async fn test_help() {
+    let handler = Arc::new(TestCustomHandler);
+
+    const CUSTOM_COMMAND: &str = "custom_command";
+    let custom_commands = commands![
+        (CUSTOM_COMMAND: Parameter(None) -> handler.handle_command_two; "value")
+    ];
+
+    let command = json::json!({ "command": "help" });
+    let response = codec_roundtrip(command, custom_commands).await;
+    assert_eq!(response["STATUS"][0]["Code"], 225);
+
+    let list = response["HELP"].as_array().expect("missing help list");
+    let names: Vec<_> = list
+        .iter()
+        .map(|command| command["Command"].as_str().unwrap())
+        .collect();
+    let mut sorted_names = names.clone();
+    sorted_names.sort();
+    assert_eq!(names, sorted_names);
+    assert_eq!(
+        response["STATUS"][0]["Msg"],
+        format!("{} Command(s)", list.len())
+    );
+
+    let find = |name: &str| {
+        list.iter()
+            .find(|command| command["Command"] == name)
+            .unwrap_or_else(|| panic!("missing '{}' command", name))
+    };
+    assert_eq!(find(CUSTOM_COMMAND)["Parameter"], "Y");
+    assert_eq!(find(CUSTOM_COMMAND)["Parameters"], "value");
+    assert_eq!(find("addpool")["Parameters"], "url,user,password");
+    assert_eq!(find("summary")["Parameter"], "N");
+    assert_eq!(find("summary")["Parameters"], "");
+    assert_eq!(find("help")["Parameter"], "N");
+}
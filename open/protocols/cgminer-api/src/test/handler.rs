@@ -69,6 +69,21 @@ impl command::Handler for BasicTest {
                 current_block_height: 0,
                 current_block_version: 0,
                 asic_boost: false,
+                work_utility: 0.0,
+                share_difficulty_5m: 0.0,
+                share_difficulty_15m: 0.0,
+                share_difficulty_24h: 0.0,
+                earned_work_5m: 0.0,
+                earned_work_15m: 0.0,
+                earned_work_24h: 0.0,
+                health_score: 0.0,
+                health_alert: response::Bool::N,
+                latency_last: 0.0,
+                latency_min: 0.0,
+                latency_mean: 0.0,
+                latency_median: 0.0,
+                latency_p95: 0.0,
+                latency_max: 0.0,
             }],
         })
     }
@@ -144,6 +159,8 @@ impl command::Handler for BasicTest {
             pool_rejected_ratio: 0.0,
             pool_stale_ratio: 0.0,
             last_getwork: 0,
+            total_found_blocks: 0,
+            last_found_block: 0,
         })
     }
 
@@ -222,6 +239,7 @@ impl command::Handler for BasicTest {
                     max: 0.0,
                     min: 0.0,
                 },
+                serial: None,
             }],
             pool_stats: vec![response::PoolStats {
                 header: response::StatsHeader {
@@ -270,6 +288,7 @@ impl command::Handler for BasicTest {
                     max: 0.0,
                     min: 0.0,
                 },
+                serial: None,
             }],
             pool_stats: vec![],
         })
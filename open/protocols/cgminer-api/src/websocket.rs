@@ -0,0 +1,340 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! WebSocket bridge to the CGMiner API commands
+//!
+//! Every text message received from a client is handled as one API request and its response is
+//! sent back as a text message without the null terminator used by the TCP API. The connection
+//! stays open so a client (e.g. a browser based dashboard) can send any number of requests.
+//! Request for the `subscribe` command starts sending periodic responses of subscribed commands
+//! which continue until the connection is closed, another subscription replaces them or the
+//! subscription expires.
+//!
+//! Browsers let scripts of any web page open a WebSocket connection to the miner, so handshakes
+//! of pages from foreign origins are refused (see `is_origin_allowed()`).
+
+use ii_logging::macros::*;
+
+use crate::command;
+use crate::json;
+use crate::response;
+use crate::support::ResponseType;
+
+use ii_async_compat::{futures, tokio};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Active subscription of a WebSocket client
+type ActiveSubscription = (command::Subscription, time::Interval);
+
+/// Wait for the next period of active subscription and return its request. Never resolves when
+/// the client has no subscription (or it has just expired).
+async fn next_tick(subscription: &mut Option<ActiveSubscription>) -> command::Request {
+    if let Some((active_subscription, interval)) = subscription {
+        interval.tick().await;
+        if !active_subscription.is_expired() {
+            return active_subscription.request();
+        }
+        // the client has to subscribe again
+        subscription.take();
+    }
+    futures::future::pending().await
+}
+
+/// Host part of `authority` (`host[:port]`) or of origin (`scheme://host[:port]`)
+fn host_of(origin_or_authority: &str) -> &str {
+    let authority = origin_or_authority
+        .splitn(2, "://")
+        .last()
+        .expect("BUG: missing authority");
+    if let Some(ipv6_authority) = authority.strip_prefix('[') {
+        ipv6_authority.split(']').next().expect("BUG: missing host")
+    } else {
+        authority.split(':').next().expect("BUG: missing host")
+    }
+}
+
+/// Browsers attach `Origin` header to every WebSocket handshake. Without checking it any web
+/// page the operator visits could control the miner (cross-site WebSocket hijacking). Accepted
+/// are:
+/// - clients other than browsers which don't send the header at all
+/// - pages served by the miner itself, i.e. the origin has the same host as the handshake
+///   request addressed to the miner by its IP address (a host name could be rebound to the
+///   miner by a foreign DNS server)
+/// - explicitly allowed origins (e.g. `http://dashboard.local:8080`)
+fn is_origin_allowed(origin: Option<&str>, host: Option<&str>, allowed_origins: &[String]) -> bool {
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return true,
+    };
+    let is_served_by_miner = |host: &str| {
+        let host = host_of(host);
+        host.parse::<IpAddr>().is_ok() && host.eq_ignore_ascii_case(host_of(origin))
+    };
+    allowed_origins
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+        || host.map_or(false, is_served_by_miner)
+}
+
+/// Convert message from the client to API request
+fn parse_request(text: &str) -> Result<command::Request, response::ErrorCode> {
+    json::from_str(text)
+        .map(command::Request::new)
+        .map_err(|_| response::ErrorCode::InvalidJSON)
+}
+
+fn to_message(response: ResponseType) -> Message {
+    Message::Text(json::to_string(&response).expect("BUG: cannot serialize API response"))
+}
+
+async fn handle_request(
+    command_receiver: &command::Receiver,
+    subscription: &mut Option<ActiveSubscription>,
+    text: &str,
+) -> Option<ResponseType> {
+    let request = match parse_request(text) {
+        Ok(request) => request,
+        Err(error_code) => {
+            command_receiver.account_frame_error(crate::FrameError::Malformed);
+            return Some(command_receiver.error_response(error_code));
+        }
+    };
+    match command_receiver.subscription(&request) {
+        Some(Ok(new_subscription)) => {
+            // the first tick of new interval completes immediately
+            let interval = time::interval(new_subscription.interval);
+            subscription.replace((new_subscription, interval));
+            None
+        }
+        Some(Err(response)) => Some(response),
+        None => Some(command_receiver.handle(request).await),
+    }
+}
+
+async fn handle_connection_task(
+    stream: TcpStream,
+    command_receiver: Arc<command::Receiver>,
+    allowed_origins: Arc<Vec<String>>,
+) {
+    // Limit size of messages like the TCP API does
+    let config = WebSocketConfig {
+        max_message_size: Some(crate::MAX_FRAME_SIZE),
+        max_frame_size: Some(crate::MAX_FRAME_SIZE),
+        ..Default::default()
+    };
+    let check_origin = |request: &Request, response: Response| {
+        let header_str = |name| {
+            request
+                .headers()
+                .get(name)
+                .map(|value: &header::HeaderValue| value.to_str().unwrap_or_default())
+        };
+        let origin = header_str(header::ORIGIN);
+        if is_origin_allowed(origin, header_str(header::HOST), &allowed_origins) {
+            Ok(response)
+        } else {
+            info!(
+                "CGMiner API: refusing WebSocket connection from origin {}",
+                origin.unwrap_or_default()
+            );
+            let mut response = ErrorResponse::new(Some("Origin not allowed".to_string()));
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Err(response)
+        }
+    };
+    let ws_stream =
+        match tokio_tungstenite::accept_hdr_async_with_config(stream, check_origin, Some(config))
+            .await
+        {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                debug!("CGMiner API: WebSocket handshake failed ({})", e);
+                return;
+            }
+        };
+    let (mut sink, mut stream) = ws_stream.split();
+    let mut subscription = None;
+
+    loop {
+        let response = tokio::select! {
+            request = next_tick(&mut subscription) => Some(command_receiver.handle(request).await),
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    handle_request(&command_receiver, &mut subscription, &text).await
+                }
+                Some(Ok(Message::Binary(_))) => {
+                    command_receiver.account_frame_error(crate::FrameError::Malformed);
+                    Some(command_receiver.error_response(response::ErrorCode::InvalidJSON))
+                }
+                // Ping and pong frames are handled by the WebSocket implementation itself
+                Some(Ok(Message::Close(_))) | None => return,
+                Some(Ok(_)) => None,
+                Some(Err(tungstenite::Error::Capacity(_))) => {
+                    command_receiver.account_frame_error(crate::FrameError::Oversized);
+                    return;
+                }
+                Some(Err(e)) => {
+                    debug!("CGMiner API: WebSocket connection closed ({})", e);
+                    return;
+                }
+            },
+        };
+        if let Some(response) = response {
+            if let Err(e) = sink.send(to_message(response)).await {
+                debug!("CGMiner API: cannot send WebSocket response ({})", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Start up a WebSocket server with a `command_receiver` object, listening on `listen_addr`.
+/// Web pages from `allowed_origins` (besides pages served by the miner) can use the API.
+pub async fn run(
+    command_receiver: impl Into<Arc<command::Receiver>>,
+    listen_addr: SocketAddr,
+    allowed_origins: Vec<String>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(&listen_addr).await?;
+    let command_receiver = command_receiver.into();
+    let allowed_origins = Arc::new(allowed_origins);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection_task(
+                    stream,
+                    command_receiver.clone(),
+                    allowed_origins.clone(),
+                ));
+            }
+            Err(e) => warn!("CGMiner API: cannot accept WebSocket connection ({})", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::handler::BasicTest;
+
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    #[test]
+    fn test_origin_check() {
+        let allowed = vec!["http://dashboard.local:8080/".to_string()];
+        let miner = Some("10.0.0.5:4029");
+
+        assert!(is_origin_allowed(None, miner, &allowed));
+        assert!(is_origin_allowed(Some("http://10.0.0.5"), miner, &allowed));
+        assert!(is_origin_allowed(
+            Some("http://[fe80::1]:8080"),
+            Some("[fe80::1]:4029"),
+            &allowed
+        ));
+        assert!(is_origin_allowed(
+            Some("http://dashboard.local:8080"),
+            miner,
+            &allowed
+        ));
+
+        assert!(!is_origin_allowed(
+            Some("https://evil.example"),
+            miner,
+            &allowed
+        ));
+        assert!(!is_origin_allowed(
+            Some("http://dashboard.local"),
+            miner,
+            &allowed
+        ));
+        assert!(!is_origin_allowed(Some("null"), None, &allowed));
+        // DNS rebinding of foreign host name to the miner
+        assert!(!is_origin_allowed(
+            Some("http://evil.example"),
+            Some("evil.example:4029"),
+            &allowed
+        ));
+    }
+
+    /// Connect to a WebSocket API server from a page of `origin` and try a command
+    async fn run_version(origin: &str) -> tungstenite::Result<json::Value> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind test listener");
+        let addr = listener
+            .local_addr()
+            .expect("BUG: missing listener address");
+        let command_receiver = Arc::new(command::Receiver::new(
+            BasicTest,
+            "TestMiner".to_string(),
+            "v1.0".to_string(),
+            None,
+        ));
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("BUG: accept failed");
+            handle_connection_task(stream, command_receiver, Arc::new(vec![])).await;
+        });
+
+        let mut request = format!("ws://{}", addr).into_client_request()?;
+        request.headers_mut().insert(
+            header::ORIGIN,
+            origin.parse().expect("BUG: invalid origin header"),
+        );
+        let stream = TcpStream::connect(addr).await?;
+        let (mut ws_stream, _) = tokio_tungstenite::client_async(request, stream).await?;
+        ws_stream
+            .send(Message::Text(r#"{"command":"version"}"#.to_string()))
+            .await?;
+        let response = ws_stream.next().await.expect("BUG: connection closed")?;
+        Ok(json::from_str(response.to_text()?).expect("BUG: invalid response"))
+    }
+
+    #[tokio::test]
+    async fn test_upgrade() {
+        let response = run_version("http://127.0.0.1")
+            .await
+            .expect("BUG: page served by the miner refused");
+        assert_eq!(response["STATUS"][0]["STATUS"], "S");
+    }
+
+    #[tokio::test]
+    async fn test_foreign_origin_rejected() {
+        match run_version("https://evil.example").await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::FORBIDDEN)
+            }
+            result => panic!("BUG: foreign origin accepted: {:?}", result),
+        }
+    }
+}
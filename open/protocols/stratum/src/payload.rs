@@ -22,7 +22,7 @@
 
 //! Common module that provides helper functionality to handle payload of protocol frames
 
-use bytes::{buf::BufMutExt, BytesMut};
+use bytes::{BufMut, BytesMut};
 use std::fmt;
 
 use ii_async_compat::bytes;
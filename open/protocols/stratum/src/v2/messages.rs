@@ -83,12 +83,26 @@ pub enum MessageType {
     SetGroupChannel = 0x26,
 }
 
+/// `SetupConnection` flags for the mining protocol: downstream requests standard jobs only
+pub const SETUP_CONNECTION_REQUIRES_STANDARD_JOBS: u32 = 1 << 0;
+/// `SetupConnection` flags for the mining protocol: downstream wants to select its own work
+pub const SETUP_CONNECTION_REQUIRES_WORK_SELECTION: u32 = 1 << 1;
+/// `SetupConnection` flags for the mining protocol: downstream needs to roll the version bits
+pub const SETUP_CONNECTION_REQUIRES_VERSION_ROLLING: u32 = 1 << 2;
+
+/// `SetupConnectionSuccess` flags for the mining protocol: upstream does not accept any
+/// changes to the block version field
+pub const SETUP_CONNECTION_SUCCESS_REQUIRES_FIXED_VERSION: u32 = 1 << 0;
+/// `SetupConnectionSuccess` flags for the mining protocol: upstream accepts extended channels
+/// only
+pub const SETUP_CONNECTION_SUCCESS_REQUIRES_EXTENDED_CHANNELS: u32 = 1 << 1;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SetupConnection {
     pub protocol: u8,
     pub min_version: u16,
     pub max_version: u16,
-    /// TODO: specify an enum for flags
+    /// See `SETUP_CONNECTION_*` constants
     pub flags: u32,
     pub endpoint_host: Str0_255,
     pub endpoint_port: u16,
@@ -98,7 +112,7 @@ pub struct SetupConnection {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SetupConnectionSuccess {
     pub used_version: u16,
-    /// TODO: specify an enum for flags
+    /// See `SETUP_CONNECTION_SUCCESS_*` constants
     pub flags: u32,
 }
 
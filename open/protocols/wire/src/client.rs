@@ -33,6 +33,8 @@ use tokio::time;
 use ii_async_compat::prelude::*;
 use thiserror::Error;
 
+use crate::keepalive::TcpKeepalive;
+
 #[derive(Error, PartialEq, Eq, Debug)]
 pub struct AddressParseError;
 
@@ -62,9 +64,38 @@ impl Address {
         (self.0.as_str(), self.1)
     }
 
+    /// Default limit for a connection attempt to a single resolved socket address
+    pub const DEFAULT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
     /// Create a `TcpStream` connected to this address
     pub async fn connect(&self) -> io::Result<TcpStream> {
-        TcpStream::connect(self.as_ref()).await
+        self.connect_timeout(Self::DEFAULT_ATTEMPT_TIMEOUT).await
+    }
+
+    /// Create a `TcpStream` connected to this address. The hostname is resolved on every call
+    /// so that changes in DNS records are picked up on reconnection. All resolved socket
+    /// addresses are tried in order, each of them for at most `attempt_timeout`, and the
+    /// error of the last attempt is returned when none of them accepts the connection.
+    pub async fn connect_timeout(&self, attempt_timeout: Duration) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for socket_addr in tokio::net::lookup_host(self.as_ref()).await? {
+            match time::timeout(attempt_timeout, TcpStream::connect(socket_addr)).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => {
+                    last_error = Some(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("Connection to {} timed out", socket_addr),
+                    ))
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot resolve any address for {}", self),
+            )
+        }))
     }
 }
 
@@ -237,6 +268,10 @@ pub struct Client {
     addr: Address,
     /// Backoff strategy trait object
     backoff: Box<dyn Backoff>,
+    /// Limit for connecting to a single socket address the `addr` resolves to
+    attempt_timeout: Duration,
+    /// Keepalive enabled on established connections
+    tcp_keepalive: Option<TcpKeepalive>,
     /// When connection attempt fails, current time (Instant) and a backoff Duration
     /// are saved here, this is used by next() to compute delay time before attempting
     /// connection when called next time.
@@ -264,6 +299,8 @@ impl Client {
         Self {
             addr,
             backoff: Box::new(backoff),
+            attempt_timeout: Address::DEFAULT_ATTEMPT_TIMEOUT,
+            tcp_keepalive: Some(TcpKeepalive::default()),
             next_delay: None,
             retries: 0,
             start_time: None,
@@ -278,18 +315,31 @@ impl Client {
         self.backoff = Box::new(backoff);
     }
 
+    pub fn set_attempt_timeout(&mut self, attempt_timeout: Duration) {
+        self.attempt_timeout = attempt_timeout;
+    }
+
+    /// Set keepalive of established connections, `None` disables keepalive
+    pub fn set_tcp_keepalive(&mut self, tcp_keepalive: Option<TcpKeepalive>) {
+        self.tcp_keepalive = tcp_keepalive;
+    }
+
     pub async fn next(&mut self) -> Result<TcpStream, AttemptError> {
         self.start_time.get_or_insert(Instant::now());
 
         if let Some((when, delay)) = self.next_delay.take() {
             let since_last_attempt = Instant::now().duration_since(when);
             if delay > since_last_attempt {
-                time::delay_for(delay - since_last_attempt).await;
+                time::sleep(delay - since_last_attempt).await;
             }
         }
 
-        match self.addr.connect().await {
+        match self.addr.connect_timeout(self.attempt_timeout).await {
             Ok(conn) => {
+                if let Some(tcp_keepalive) = self.tcp_keepalive.as_ref() {
+                    // the connection is still usable without keepalive
+                    let _ = tcp_keepalive.apply(&conn);
+                }
                 self.backoff.reset();
                 self.retries = 0;
                 self.start_time = None;
@@ -327,4 +377,30 @@ mod tests {
         assert_eq!(Address::from_str(":"), Err(AddressParseError));
         assert_eq!(Address::from_str(":123"), Err(AddressParseError));
     }
+
+    #[tokio::test]
+    async fn wire_address_connect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind test listener");
+        let port = listener
+            .local_addr()
+            .expect("BUG: missing listener address")
+            .port();
+
+        // 'localhost' may resolve to an IPv6 address first which has to be skipped
+        for host in &["127.0.0.1", "localhost"] {
+            let stream = Address(host.to_string(), port)
+                .connect_timeout(Duration::from_secs(1))
+                .await
+                .expect("BUG: cannot connect to test listener");
+            assert_eq!(
+                stream
+                    .peer_addr()
+                    .expect("BUG: missing peer address")
+                    .port(),
+                port
+            );
+        }
+    }
 }
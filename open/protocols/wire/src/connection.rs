@@ -31,6 +31,7 @@ use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio_util::codec::Framed;
 
 use crate::framing::Framing;
+use crate::keepalive::IdleTimeout;
 
 #[pin_project]
 #[derive(Debug)]
@@ -69,6 +70,11 @@ impl<F: Framing> Connection<F> {
     pub fn into_inner(self) -> Framed<TcpStream, F::Codec> {
         self.framed_stream
     }
+
+    /// Fail receiving when the peer doesn't send anything for `timeout`
+    pub fn with_idle_timeout(self, timeout: std::time::Duration) -> IdleTimeout<Self> {
+        IdleTimeout::new(self, timeout)
+    }
 }
 
 impl<F: Framing> From<TcpStream> for Connection<F> {
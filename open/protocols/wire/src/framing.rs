@@ -31,7 +31,7 @@ pub trait Framing: 'static {
     /// Receive message type
     type Rx: Send + Sync;
     type Error: From<IOError> + failure::Fail;
-    type Codec: Encoder<Item = Self::Tx, Error = Self::Error>
+    type Codec: Encoder<Self::Tx, Error = Self::Error>
         + Decoder<Item = Self::Rx, Error = Self::Error>
         + Default
         + Unpin
@@ -0,0 +1,307 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Detection of dead peers. Half-open connections (e.g. after NAT mapping of the connection has
+//! expired) never deliver an error by themselves so they are detected on three levels:
+//! - `TcpKeepalive` lets the kernel probe the peer while the connection is idle
+//! - `Heartbeat` schedules application-level pings for protocols which can pair a request with
+//!   its response
+//! - `IdleTimeout` ends a stream which hasn't received any frame for a given time
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ii_async_compat::prelude::*;
+use pin_project::pin_project;
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant, Sleep};
+
+/// Parameters of TCP keepalive probes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TcpKeepalive {
+    /// How long the connection has to be idle before the first probe is sent
+    pub time: Duration,
+    /// Interval between unanswered probes
+    pub interval: Duration,
+    /// Number of unanswered probes after which the connection is dropped
+    pub retries: u32,
+}
+
+impl TcpKeepalive {
+    /// Enable keepalive probes on `stream`
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let keepalive = socket2::TcpKeepalive::new().with_time(self.time);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let keepalive = keepalive
+            .with_interval(self.interval)
+            .with_retries(self.retries);
+        socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+    }
+
+    /// Time after which an idle connection to dead peer is detected
+    pub fn detection_time(&self) -> Duration {
+        self.time + self.interval * self.retries
+    }
+}
+
+impl Default for TcpKeepalive {
+    /// Detect dead peer in 1 minute which is well below usual NAT timeouts
+    fn default() -> Self {
+        Self {
+            time: Duration::from_secs(30),
+            interval: Duration::from_secs(10),
+            retries: 3,
+        }
+    }
+}
+
+/// Application-level ping. The owner of a connection sends a ping whenever `tick()` resolves
+/// and reports every frame received from the peer with `received()`. Any frame counts as an
+/// answer so the ping can be an arbitrary request the peer responds to (even with an error).
+#[derive(Debug)]
+pub struct Heartbeat {
+    /// How long the connection has to be idle before a ping is sent
+    interval: Duration,
+    /// Time for the peer to answer the ping
+    timeout: Duration,
+    last_received: Instant,
+    /// Time when the pending ping has been sent
+    ping_sent: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_received: Instant::now(),
+            ping_sent: None,
+        }
+    }
+
+    /// Account a frame received from the peer
+    pub fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.ping_sent = None;
+    }
+
+    /// Resolves when a ping should be sent. Fails with `io::ErrorKind::TimedOut` when the peer
+    /// hasn't answered the previous ping in time. The future can be dropped at any time (e.g. in
+    /// a `select!` loop) without losing track of the pending ping.
+    pub async fn tick(&mut self) -> io::Result<()> {
+        match self.ping_sent {
+            None => {
+                time::sleep_until(self.last_received + self.interval).await;
+                self.ping_sent = Some(Instant::now());
+                Ok(())
+            }
+            Some(ping_sent) => {
+                time::sleep_until(ping_sent + self.timeout).await;
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Peer hasn't answered ping for {:?}", self.timeout),
+                ))
+            }
+        }
+    }
+}
+
+/// Stream adapter which fails with `io::ErrorKind::TimedOut` when the inner stream doesn't
+/// produce any item for `timeout`. Protocols with periodic messages (or requests which are
+/// expected right after connecting) can use it to recycle connections to dead peers.
+#[pin_project]
+#[derive(Debug)]
+pub struct IdleTimeout<S> {
+    #[pin]
+    inner: S,
+    timeout: Duration,
+    /// The sleep is boxed so that the adapter is `Unpin` whenever the inner stream is
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<S> IdleTimeout<S> {
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: Box::pin(time::sleep(timeout)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, T, E> Stream for IdleTimeout<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    E: From<io::Error>,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        match this.inner.poll_next(cx) {
+            Poll::Ready(item) => {
+                this.deadline.as_mut().reset(Instant::now() + *this.timeout);
+                Poll::Ready(item)
+            }
+            Poll::Pending => match this.deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    // restart the timeout in case the user keeps polling the stream
+                    this.deadline.as_mut().reset(Instant::now() + *this.timeout);
+                    Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("No data received from peer for {:?}", this.timeout),
+                    )
+                    .into())))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl<S, I> Sink<I> for IdleTimeout<S>
+where
+    S: Sink<I>,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        self.project().inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_timeout() {
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<u32, io::Error>>();
+        let mut stream = IdleTimeout::new(receiver, Duration::from_millis(50));
+
+        sender.unbounded_send(Ok(1)).expect("BUG: send failed");
+        assert_eq!(
+            stream
+                .next()
+                .await
+                .expect("BUG: stream ended")
+                .expect("BUG: unexpected error"),
+            1
+        );
+
+        // nothing is sent so the stream times out
+        let error = stream
+            .next()
+            .await
+            .expect("BUG: stream ended")
+            .expect_err("BUG: missing timeout");
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        // the stream is usable after the timeout
+        sender.unbounded_send(Ok(2)).expect("BUG: send failed");
+        assert_eq!(
+            stream
+                .next()
+                .await
+                .expect("BUG: stream ended")
+                .expect("BUG: unexpected error"),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn heartbeat() {
+        let mut heartbeat = Heartbeat::new(Duration::from_millis(50), Duration::from_millis(50));
+
+        // idle connection is pinged and the answer postpones the next ping
+        heartbeat
+            .tick()
+            .await
+            .expect("BUG: unexpected ping timeout");
+        heartbeat.received();
+        let start = Instant::now();
+        heartbeat
+            .tick()
+            .await
+            .expect("BUG: unexpected ping timeout");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // dropping the tick doesn't forget the pending ping
+        assert!(heartbeat
+            .tick()
+            .timeout(Duration::from_millis(10))
+            .await
+            .is_err());
+        let error = heartbeat
+            .tick()
+            .await
+            .expect_err("BUG: missing ping timeout");
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn tcp_keepalive() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind test listener");
+        let stream = TcpStream::connect(
+            listener
+                .local_addr()
+                .expect("BUG: missing listener address"),
+        )
+        .await
+        .expect("BUG: cannot connect to test listener");
+
+        TcpKeepalive::default()
+            .apply(&stream)
+            .expect("BUG: cannot set keepalive");
+        assert!(socket2::SockRef::from(&stream)
+            .keepalive()
+            .expect("BUG: cannot get keepalive"));
+    }
+}
@@ -34,3 +34,6 @@ pub use client::*;
 
 mod framing;
 pub use framing::*;
+
+mod keepalive;
+pub use keepalive::*;
@@ -29,19 +29,33 @@ use ii_async_compat::prelude::*;
 use pin_project::pin_project;
 use tokio::net::{TcpListener, TcpStream};
 
+use crate::keepalive::TcpKeepalive;
+
 #[pin_project]
 #[derive(Debug)]
 pub struct Server {
     #[pin]
     tcp: TcpListener,
+    /// Keepalive enabled on accepted connections
+    tcp_keepalive: Option<TcpKeepalive>,
 }
 
 impl Server {
     pub fn bind<A: StdToSocketAddrs>(addr: A) -> std::io::Result<Self> {
         let tcp = StdTcpListener::bind(addr)?;
+        // Tokio requires the listener to be in nonblocking mode
+        tcp.set_nonblocking(true)?;
         let tcp = TcpListener::from_std(tcp)?;
 
-        Ok(Server { tcp })
+        Ok(Server {
+            tcp,
+            tcp_keepalive: Some(TcpKeepalive::default()),
+        })
+    }
+
+    /// Set keepalive of accepted connections, `None` disables keepalive
+    pub fn set_tcp_keepalive(&mut self, tcp_keepalive: Option<TcpKeepalive>) {
+        self.tcp_keepalive = tcp_keepalive;
     }
 }
 
@@ -49,8 +63,16 @@ impl Stream for Server {
     type Item = std::io::Result<TcpStream>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let mut tcp = self.project().tcp;
-
-        Pin::new(&mut tcp.incoming()).poll_next(cx)
+        let this = self.project();
+        let tcp_keepalive = this.tcp_keepalive;
+        this.tcp.poll_accept(cx).map(|result| {
+            Some(result.map(|(stream, _)| {
+                if let Some(tcp_keepalive) = tcp_keepalive.as_ref() {
+                    // the connection is still usable without keepalive
+                    let _ = tcp_keepalive.apply(&stream);
+                }
+                stream
+            }))
+        })
     }
 }
@@ -125,8 +125,8 @@ impl From<io::Error> for Error {
     }
 }
 
-impl From<tokio::time::Elapsed> for Error {
-    fn from(e: tokio::time::Elapsed) -> Self {
+impl From<tokio::time::error::Elapsed> for Error {
+    fn from(e: tokio::time::error::Elapsed) -> Self {
         let msg = e.to_string();
         Self {
             inner: e.context(ErrorKind::Io(msg)),
@@ -70,16 +70,26 @@ impl SeqId {
 pub struct V2ToV1TranslationOptions {
     /// Try to send `extranonce.subscribe` during handshake
     pub try_enable_xnsub: bool,
+    /// Value of extra nonce 2 used in coinbase of all jobs. Translations which share the same
+    /// extra nonce 1 (e.g. multiple miners behind a proxy with one subscription) have to use
+    /// unique values so that they don't mine duplicate work.
+    pub extra_nonce2: u32,
 }
 
 impl Default for V2ToV1TranslationOptions {
     fn default() -> Self {
         Self {
             try_enable_xnsub: false,
+            extra_nonce2: Self::DEFAULT_EXTRA_NONCE2,
         }
     }
 }
 
+impl V2ToV1TranslationOptions {
+    /// Extra nonce 2 encodes the channel ID by default
+    pub const DEFAULT_EXTRA_NONCE2: u32 = V2ToV1Translation::CHANNEL_ID;
+}
+
 /// States of the Translation setup
 #[derive(PartialEq, Debug)]
 enum V2ToV1TranslationState {
@@ -461,6 +471,13 @@ impl V2ToV1Translation {
 
         self.v1_extra_nonce1 = Some(subscribe_result.extra_nonce_1().clone());
         self.v1_extra_nonce2_size = subscribe_result.extra_nonce_2_size().clone();
+        if !Self::fits_extra_nonce2(self.options.extra_nonce2, self.v1_extra_nonce2_size) {
+            self.abort_open_channel("Upstream extra nonce 2 is too small");
+            Err(crate::error::ErrorKind::General(format!(
+                "Extra nonce 2 {} doesn't fit into {} bytes provided by upstream",
+                self.options.extra_nonce2, self.v1_extra_nonce2_size
+            )))?;
+        }
 
         // In order to finalize the opening procedure we need 3 items: authorization,
         // subscription and difficulty
@@ -628,7 +645,7 @@ impl V2ToV1Translation {
     }
 
     /// Iterates the merkle branches and calculates block merkle root using the extra nonce 1.
-    /// Extra nonce 2 encodes the channel ID.
+    /// Extra nonce 2 is taken from translation options (see `extra_nonce2_bytes`).
     /// TODO review, whether a Result has to be returned as missing enonce1 would be considered a bug
     fn calculate_merkle_root(
         &mut self,
@@ -645,10 +662,7 @@ impl V2ToV1Translation {
             );
             coin_base.extend_from_slice(payload.coin_base_1());
             coin_base.extend_from_slice(v1_extra_nonce1.0.as_ref());
-            coin_base.extend_from_slice(
-                Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, self.v1_extra_nonce2_size)
-                    .as_ref(),
-            );
+            coin_base.extend_from_slice(self.extra_nonce2_bytes().as_ref());
             coin_base.extend_from_slice(payload.coin_base_2());
 
             let mut engine = sha256d::Hash::engine();
@@ -710,24 +724,37 @@ impl V2ToV1Translation {
         })
     }
 
-    /// Converts specified `channel_id` into extra nonce 2 with a specified
+    /// Extra nonce 2 of the channel serialized to the size requested by upstream
+    #[inline]
+    fn extra_nonce2_bytes(&self) -> BytesMut {
+        Self::u32_to_extra_nonce2_bytes(self.options.extra_nonce2, self.v1_extra_nonce2_size)
+    }
+
+    /// Check that `value` can be stored in extra nonce 2 of `v1_extra_nonce2_size` bytes
+    #[inline]
+    fn fits_extra_nonce2(value: u32, v1_extra_nonce2_size: usize) -> bool {
+        v1_extra_nonce2_size >= size_of::<u32>()
+            || value < 1u32.wrapping_shl(8 * v1_extra_nonce2_size as u32)
+    }
+
+    /// Converts specified `value` into extra nonce 2 with a specified
     /// `v1_extra_nonce2_size`
     /// TODO review the implementation 'how to efficiently render a u32 into a byte array'
     #[inline]
-    fn channel_to_extra_nonce2_bytes(channel_id: u32, v1_extra_nonce2_size: usize) -> BytesMut {
+    fn u32_to_extra_nonce2_bytes(value: u32, v1_extra_nonce2_size: usize) -> BytesMut {
         let mut extra_nonce2: BytesMut = BytesMut::with_capacity(v1_extra_nonce2_size);
 
-        let channel_id_bytes = u32::to_le_bytes(channel_id);
+        let value_bytes = u32::to_le_bytes(value);
         if v1_extra_nonce2_size < size_of::<u32>() {
             // TODO: what to do when server deliberately sends small extranonce?
-            if channel_id >= 1u32.wrapping_shl(8 * v1_extra_nonce2_size as u32) {
-                error!("BUG: channel_id doesn't fit into extranonce");
+            if !Self::fits_extra_nonce2(value, v1_extra_nonce2_size) {
+                error!("BUG: value doesn't fit into extranonce");
             }
-            // Write just part of channel_id
-            extra_nonce2.extend_from_slice(&channel_id_bytes[0..v1_extra_nonce2_size]);
+            // Write just part of value
+            extra_nonce2.extend_from_slice(&value_bytes[0..v1_extra_nonce2_size]);
         } else {
-            // Write full 32-bits of channel id and pad the rest
-            extra_nonce2.extend_from_slice(&channel_id_bytes);
+            // Write full 32-bits of value and pad the rest
+            extra_nonce2.extend_from_slice(&value_bytes);
             let padding = v1_extra_nonce2_size - size_of::<u32>();
             extra_nonce2.extend_from_slice(&vec![0; padding]);
         }
@@ -939,6 +966,12 @@ impl v1::Handler for V2ToV1Translation {
         //   https://en.bitcoin.it/wiki/Stratum_mining_protocol#mining.set_extranonce
         self.v1_extra_nonce1 = Some(payload.extra_nonce_1().clone());
         self.v1_extra_nonce2_size = payload.extra_nonce_2_size().clone();
+        if !Self::fits_extra_nonce2(self.options.extra_nonce2, self.v1_extra_nonce2_size) {
+            warn!(
+                "Extra nonce 2 {} doesn't fit into {} bytes, work may be duplicated",
+                self.options.extra_nonce2, self.v1_extra_nonce2_size
+            );
+        }
     }
 
     /// Composes a new mining job and sends it downstream
@@ -1174,7 +1207,7 @@ impl v2::Handler for V2ToV1Translation {
             .expect("Missing channel details");
         // TODO this is only here as we want to prevent locking up 'self' into multiple closures
         // and causing borrow checker complains
-        let v1_extra_nonce2_size = self.v1_extra_nonce2_size;
+        let extra_nonce2 = self.extra_nonce2_bytes();
 
         // Check job ID validity
         let v1_submit_template = self
@@ -1193,8 +1226,7 @@ impl v2::Handler for V2ToV1Translation {
                 let submit = v1::messages::Submit::new(
                     v2_channel_details.user.to_string(),
                     v1_submit_template.job_id.clone(),
-                    Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, v1_extra_nonce2_size)
-                        .as_ref(),
+                    extra_nonce2.as_ref(),
                     payload.ntime,
                     payload.nonce,
                     // ensure the version bits in the template follow BIP320
@@ -184,3 +184,24 @@ fn test_diff_1_bitcoin_target() {
         V2ToV1Translation::DIFF1_TARGET
     );
 }
+
+#[test]
+fn test_extra_nonce2_bytes() {
+    // (value, extra nonce 2 size, expected bytes)
+    let cases: [(u32, usize, &[u8]); 5] = [
+        (0, 4, &[0, 0, 0, 0]),
+        (3, 8, &[3, 0, 0, 0, 0, 0, 0, 0]),
+        (0x0201, 2, &[1, 2]),
+        (0x04030201, 4, &[1, 2, 3, 4]),
+        (0, 0, &[]),
+    ];
+    for (value, size, expected) in cases.iter() {
+        assert!(V2ToV1Translation::fits_extra_nonce2(*value, *size));
+        assert_eq!(
+            V2ToV1Translation::u32_to_extra_nonce2_bytes(*value, *size).as_ref(),
+            *expected
+        );
+    }
+    assert!(!V2ToV1Translation::fits_extra_nonce2(1, 0));
+    assert!(!V2ToV1Translation::fits_extra_nonce2(0x100, 1));
+}
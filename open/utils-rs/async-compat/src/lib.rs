@@ -24,31 +24,39 @@
 pub use bytes;
 pub use futures;
 pub use tokio;
+pub use tokio_stream;
 pub use tokio_util;
 
+pub mod task;
+
 /// A general async prelude.
 ///
-/// Re-exports `futures::prelude::*`, along with `tokio`, `tokio_util`
-/// and `FutureExt` (custom extensions).
+/// Re-exports `futures::prelude::*`, along with `tokio`, `tokio_stream`,
+/// `tokio_util` and `FutureExt` (custom extensions).
 pub mod prelude {
-    pub use super::{bytes, futures, tokio, tokio_util, FutureExt as _};
+    pub use super::{bytes, futures, tokio, tokio_stream, tokio_util, FutureExt as _};
 
     pub use futures::prelude::*;
-    pub use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
-    pub use tokio::prelude::*;
+    pub use tokio::io::{
+        AsyncBufRead, AsyncBufReadExt as _, AsyncRead, AsyncReadExt as _, AsyncWrite,
+        AsyncWriteExt as _,
+    };
 
     pub use stream_cancel::{StreamExt as _, Tripwire};
 }
 
 pub use stream_cancel::{self, Tripwire};
 
+use std::any::Any;
+use std::cell::Cell;
 use std::error::Error as StdError;
 use std::fmt;
-use std::panic::{self, PanicInfo};
+use std::panic::{self, AssertUnwindSafe, PanicInfo};
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Once;
 use std::sync::{Arc, Mutex};
+use std::task::Poll;
 use std::time::Duration;
 
 use futures::prelude::*;
@@ -57,6 +65,11 @@ use tokio::sync::{mpsc, oneshot};
 use tokio::task::{JoinError, JoinHandle};
 use tokio::{signal, time};
 
+thread_local! {
+    /// Number of `catch_panic` futures being polled on this thread
+    static CATCHING_PANIC: Cell<usize> = Cell::new(0);
+}
+
 /// This registers a customized panic hook with the stdlib.
 /// The customized panic hook does the same thing as the default
 /// panic handling - ie. it prints out the panic information
@@ -64,7 +77,8 @@ use tokio::{signal, time};
 ///
 /// This means that a panic in Tokio threadpool worker thread
 /// will bring down the whole program as if the panic
-/// occured on the main thread.
+/// occured on the main thread. The only exception are panics
+/// inside futures wrapped with `catch_panic()`.
 ///
 /// This function can be called any number of times,
 /// but the hook will be set only on the first call.
@@ -77,13 +91,33 @@ pub fn setup_panic_handling() {
 
         let our_hook = move |pi: &PanicInfo| {
             default_hook(pi);
-            process::abort();
+            if CATCHING_PANIC.with(|depth| depth.get()) == 0 {
+                process::abort();
+            }
         };
 
         panic::set_hook(Box::new(our_hook));
     });
 }
 
+/// Run `future` and return the panic payload when it panics instead of
+/// bringing down the whole program (see `setup_panic_handling()`).
+/// The panicked future is dropped.
+pub async fn catch_panic<F: Future>(future: F) -> Result<F::Output, Box<dyn Any + Send>> {
+    let mut future = Box::pin(future);
+    future::poll_fn(move |cx| {
+        CATCHING_PANIC.with(|depth| depth.set(depth.get() + 1));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx)));
+        CATCHING_PANIC.with(|depth| depth.set(depth.get() - 1));
+        match result {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    })
+    .await
+}
+
 /// An extension trait for `Future` goodies,
 /// currently this only entails the `timeout()` function.
 pub trait FutureExt: Future {
@@ -100,31 +134,10 @@ pub trait FutureExt: Future {
 
 impl<F: Future> FutureExt for F {}
 
-/// Wrapper for `select!` macro from `futures`.
-/// The reason for this is that the macro needs to be told
-/// to look for futures at `::ii_async_compat::futures` rather than `::futures`.
-#[macro_export]
-macro_rules! select {
-    ($($tokens:tt)*) => {
-        futures::inner_macro::select! {
-            futures_crate_path(::ii_async_compat::futures)
-            $( $tokens )*
-        }
-    }
-}
-
-/// Wrapper for `join!` macro from `futures`.
-/// The reason for this is that the macro needs to be told
-/// to look for futures at `::ii_async_compat::futures` rather than `::futures`.
-#[macro_export]
-macro_rules! join {
-    ($($tokens:tt)*) => {
-        futures::inner_macro::join! {
-            futures_crate_path(::ii_async_compat::futures)
-            $( $tokens )*
-        }
-    }
-}
+// Since futures 0.3.5 the `select!` and `join!` macros resolve their
+// dependencies through `$crate`, so they can be re-exported directly
+// and don't need to be told where to look for `futures`.
+pub use futures::{join, select};
 
 /// Internal, used to signal termination via `trigger`
 /// and notify `Tasks` when that happens.
@@ -253,7 +266,7 @@ impl HaltHandle {
 
     /// Spawn a new task. `f` is a function that takes
     /// a `Tripwire` and returns a `Future` to be spawned.
-    /// `Tripwire` can be passed to `StreamExt::take_until`
+    /// `Tripwire` can be passed to `StreamExt::take_until_if`
     /// to make a stream stop generating items when
     /// `halt()` is called on the `HaltHandle`.
     pub fn spawn<FT, FN>(&self, f: FN)
@@ -271,6 +284,21 @@ impl HaltHandle {
         let _ = self.tasks_tx.send(TaskMsg::Task(task));
     }
 
+    /// Same as `spawn()` but the task is registered under `name` in the task registry
+    /// (see `task::registry()`)
+    pub fn spawn_named<FT, FN>(&self, name: impl Into<String>, f: FN)
+    where
+        FT: Future<Output = ()> + Send + 'static,
+        FN: FnOnce(Tripwire) -> FT,
+    {
+        let name = name.into();
+        let ft = task::instrument(name, f(self.tripwire.clone()));
+        let task = tokio::spawn(ft);
+
+        // See `spawn()` for why errors are ignored
+        let _ = self.tasks_tx.send(TaskMsg::Task(task));
+    }
+
     /// Tells the handle that all tasks were spawned
     pub fn ready(&self) {
         // Send a Ready message. join() uses this to tell
@@ -301,9 +329,10 @@ impl HaltHandle {
         FT: Future + Send + 'static,
         FN: FnOnce(Arc<Self>) -> FT,
     {
-        if !self
+        if self
             .ctrlc_task_spawned
-            .compare_and_swap(false, true, Ordering::SeqCst)
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
         {
             let ft = f(self);
             tokio::spawn(async move {
@@ -339,7 +368,7 @@ impl HaltHandle {
         // ready() pushes a ready message, TaskMsg::Ready, to this channel.
         // Here we collect all the task join handles until we reach the ready message.
         let mut handles = vec![];
-        while let Some(task_msg) = tasks.tasks_rx.next().await {
+        while let Some(task_msg) = tasks.tasks_rx.recv().await {
             match task_msg {
                 TaskMsg::Task(handle) => handles.push(handle),
                 TaskMsg::Ready => break,
@@ -373,7 +402,8 @@ mod test {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
 
-    use tokio::{stream, time};
+    use tokio::time;
+    use tokio_stream as stream;
 
     #[tokio::test]
     async fn test_timeout() {
@@ -387,9 +417,23 @@ mod test {
         future.await.expect_err("Timeout expected");
     }
 
+    #[tokio::test]
+    async fn test_catch_panic() {
+        assert_eq!(catch_panic(async { 1 }).await.expect("unexpected panic"), 1);
+
+        let payload = catch_panic(async {
+            if true {
+                panic!("expected panic");
+            }
+        })
+        .await
+        .expect_err("panic expected");
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"expected panic"));
+    }
+
     /// Wait indefinitely on a stream with a `Tripwire` for cancellation.
     async fn forever_stream(tripwire: Tripwire) {
-        let mut stream = stream::pending::<()>().take_until(tripwire);
+        let mut stream = stream::pending::<()>().take_until_if(tripwire);
 
         // The pending stream never actually yields a value,
         // ie. next() resolves to None only in the canelled case,
@@ -437,9 +481,29 @@ mod test {
         handle.join(None).await.expect("join() failed");
     }
 
+    // Named tasks are visible in the task registry until they are halted
+    #[tokio::test]
+    async fn test_halthandle_named() {
+        let handle = HaltHandle::new();
+        handle.spawn_named("test halthandle", |tripwire| forever_stream(tripwire));
+
+        let is_alive = || {
+            task::registry()
+                .snapshot()
+                .iter()
+                .any(|task| task.name == "test halthandle" && task.alive)
+        };
+        assert!(is_alive());
+
+        handle.ready();
+        handle.halt();
+        handle.join(None).await.expect("join() failed");
+        assert!(!is_alive());
+    }
+
     // Test that spawn() / halt() / join() is not racy when ready()
     // is used appropriately.
-    #[tokio::test(threaded_scheduler)]
+    #[tokio::test(flavor = "multi_thread")]
     async fn test_halthandle_race() {
         const NUM_TASKS: usize = 10;
 
@@ -456,7 +520,7 @@ mod test {
 
             tokio::spawn(async move {
                 // Delay a bit so that join() happens sooner than spawns
-                time::delay_for(Duration::from_millis(100)).await;
+                time::sleep(Duration::from_millis(100)).await;
 
                 // Spawn a couple of tasks on the handle
                 for _ in 0..NUM_TASKS {
@@ -491,7 +555,7 @@ mod test {
                 forever_stream(tripwire).await;
 
                 // Delay cleanup on purpose here
-                time::delay_for(Duration::from_secs(9001)).await;
+                time::sleep(Duration::from_secs(9001)).await;
             }
         });
 
@@ -0,0 +1,257 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Instrumentation of spawned tasks. Tasks spawned with a name are kept in a global registry
+//! together with their liveness and time of last activity (the last time the task was polled)
+//! so that stuck tasks can be diagnosed in the field.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use once_cell::sync::Lazy;
+use tokio::task::JoinHandle;
+
+/// Number of finished tasks kept in the registry to keep tasks that ended unexpectedly visible
+pub const FINISHED_TASKS_KEPT: usize = 32;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// Internal, shared between the registry and the instrumented future
+#[derive(Debug)]
+struct Info {
+    id: u64,
+    name: String,
+    spawned: Instant,
+    /// Milliseconds since `spawned` when the task was polled for the last time
+    last_poll: AtomicU64,
+    polls: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Info {
+    fn touch(&self) {
+        self.last_poll
+            .store(self.spawned.elapsed().as_millis() as u64, Ordering::Relaxed);
+        self.polls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    fn is_alive(&self) -> bool {
+        !self.finished.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self, now: Instant) -> Snapshot {
+        let age = now.saturating_duration_since(self.spawned);
+        let last_poll = Duration::from_millis(self.last_poll.load(Ordering::Relaxed));
+        Snapshot {
+            id: self.id,
+            name: self.name.clone(),
+            alive: self.is_alive(),
+            polls: self.polls.load(Ordering::Relaxed),
+            age,
+            idle: age.checked_sub(last_poll).unwrap_or_default(),
+        }
+    }
+}
+
+/// State of one task at the time the registry was queried
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Unique identifier which distinguishes tasks with the same name
+    pub id: u64,
+    pub name: String,
+    /// The task has neither completed nor been dropped
+    pub alive: bool,
+    /// Number of times the task has been polled
+    pub polls: u64,
+    /// Time since the task has been spawned
+    pub age: Duration,
+    /// Time since the task has been polled for the last time
+    pub idle: Duration,
+}
+
+/// Registry of all named tasks
+#[derive(Debug)]
+pub struct Registry {
+    next_id: AtomicU64,
+    tasks: Mutex<Vec<Arc<Info>>>,
+}
+
+impl Registry {
+    fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn register(&self, name: String) -> Arc<Info> {
+        let info = Arc::new(Info {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            name,
+            spawned: Instant::now(),
+            last_poll: AtomicU64::new(0),
+            polls: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        });
+
+        let mut tasks = self.tasks.lock().unwrap();
+        // Forget the oldest finished tasks
+        let finished = tasks.iter().filter(|info| !info.is_alive()).count();
+        let mut excess = finished.saturating_sub(FINISHED_TASKS_KEPT);
+        tasks.retain(|info| {
+            if excess > 0 && !info.is_alive() {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+        tasks.push(info.clone());
+        info
+    }
+
+    /// Take a snapshot of all registered tasks in order they were spawned
+    pub fn snapshot(&self) -> Vec<Snapshot> {
+        let now = Instant::now();
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|info| info.snapshot(now))
+            .collect()
+    }
+}
+
+/// Global registry of named tasks
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Future wrapper which reports activity of the inner future to the registry
+struct Instrumented<F> {
+    future: Pin<Box<F>>,
+    info: Arc<Info>,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.info.touch();
+        let result = self.future.as_mut().poll(cx);
+        if result.is_ready() {
+            self.info.finish();
+        }
+        result
+    }
+}
+
+impl<F> Drop for Instrumented<F> {
+    fn drop(&mut self) {
+        // The task may also be cancelled before it completes
+        self.info.finish();
+    }
+}
+
+/// Register the future under `name` in the global registry and track its activity
+pub fn instrument<F>(name: impl Into<String>, future: F) -> impl Future<Output = F::Output>
+where
+    F: Future,
+{
+    Instrumented {
+        future: Box::pin(future),
+        info: registry().register(name.into()),
+    }
+}
+
+/// Spawn a task which is registered under `name` in the global registry
+pub fn spawn_named<F>(name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let name = name.into();
+    tokio::spawn(instrument(name, future))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(name: &str) -> Snapshot {
+        registry()
+            .snapshot()
+            .into_iter()
+            .find(|task| task.name == name)
+            .expect("BUG: task not registered")
+    }
+
+    #[tokio::test]
+    async fn test_task_liveness() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = spawn_named("test liveness", async move {
+            let _ = rx.await;
+        });
+        tokio::task::yield_now().await;
+
+        let task = find("test liveness");
+        assert!(task.alive);
+        assert!(task.polls >= 1);
+
+        tx.send(()).unwrap();
+        handle.await.unwrap();
+        let task = find("test liveness");
+        assert!(!task.alive);
+        assert!(task.polls >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_task_cancelled() {
+        let handle = spawn_named("test cancelled", future::pending::<()>());
+        handle.abort();
+        let _ = handle.await;
+        assert!(!find("test cancelled").alive);
+    }
+
+    #[test]
+    fn test_finished_tasks_pruned() {
+        let registry = Registry::new();
+        for _ in 0..FINISHED_TASKS_KEPT * 2 {
+            registry.register("finished".into()).finish();
+        }
+        let _alive = registry.register("alive".into());
+
+        let tasks = registry.snapshot();
+        assert_eq!(tasks.len(), FINISHED_TASKS_KEPT + 1);
+        // the most recent tasks are kept
+        assert_eq!(tasks[0].id, FINISHED_TASKS_KEPT as u64);
+        assert!(tasks.last().unwrap().alive);
+    }
+}
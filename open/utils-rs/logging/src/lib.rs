@@ -27,6 +27,7 @@
 //! - Configuration of the global instance
 //! - Logging macros that operate on the shared instance
 //! - Flushing of logs on application exit
+//! - Capture of recent log records in memory (see `ring`)
 //!
 //! It also re-exports `slog` - this is a way to provide common `slog`
 //! dependency.
@@ -59,7 +60,7 @@ use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard};
 
 use lazy_static::lazy_static;
-use slog::{o, Discard, Drain, FilterLevel, Logger};
+use slog::{o, Discard, Drain, Duplicate, FilterLevel, Logger};
 use slog_async::{Async, AsyncGuard};
 use slog_envlogger::EnvLogger;
 use slog_term;
@@ -69,6 +70,8 @@ use slog_term;
 pub use slog;
 pub use slog::Level;
 
+pub mod ring;
+
 /// Logging target configuration: Where to log
 #[derive(Clone, Debug)]
 pub enum LoggingTarget {
@@ -93,10 +96,13 @@ pub struct LoggingConfig {
     /// Channel size for the asynchronous drain, increasing the channel size prevents
     /// the drain to drop messages in case of logging bursts
     pub drain_channel_size: usize,
+    /// Number of recent records captured in `RING_BUFFER` (0 disables the capture)
+    pub ring_buffer_size: usize,
 }
 
 impl LoggingConfig {
     pub const ASYNC_LOGGER_DRAIN_CHANNEL_SIZE: usize = 128;
+    pub const DEFAULT_RING_BUFFER_SIZE: usize = 1000;
     /// Logging configuration suitable for test harness,
     /// doesn't pollute terminal, logs to `test-log.txt` in system tmp location.
     pub fn for_testing() -> Self {
@@ -104,6 +110,7 @@ impl LoggingConfig {
             target: LoggingTarget::File(env::temp_dir().join("test-log.txt")),
             level: Level::Trace,
             drain_channel_size: Self::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+            ring_buffer_size: 0,
         }
     }
 
@@ -120,6 +127,7 @@ impl LoggingConfig {
                 Level::Info
             },
             drain_channel_size,
+            ring_buffer_size: Self::DEFAULT_RING_BUFFER_SIZE,
         }
     }
 
@@ -129,6 +137,7 @@ impl LoggingConfig {
             target: LoggingTarget::None,
             level: Level::Error,
             drain_channel_size: Self::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+            ring_buffer_size: 0,
         }
     }
 }
@@ -279,9 +288,17 @@ impl GuardedLogger {
         D: Drain<Ok = (), Err = E> + Send + 'static,
     {
         let drain = get_envlogger_drain(drain, config.level);
-        let (drain, guard) = Async::new(drain.fuse())
-            .chan_size(config.drain_channel_size)
-            .build_with_guard();
+        // records are captured in the ring buffer regardless of `RUST_LOG` filter
+        let (drain, guard) = if config.ring_buffer_size > 0 {
+            RING_BUFFER.set_capacity(config.ring_buffer_size);
+            Async::new(Duplicate::new(drain, ring::RingDrain).fuse())
+                .chan_size(config.drain_channel_size)
+                .build_with_guard()
+        } else {
+            Async::new(drain.fuse())
+                .chan_size(config.drain_channel_size)
+                .build_with_guard()
+        };
         Self {
             logger: Logger::root(drain.fuse(), o!()),
             guard: Mutex::new(FlushGuard(Some(guard))),
@@ -327,6 +344,9 @@ impl Deref for GuardedLogger {
 lazy_static! {
     static ref LOGGER_CONFIG: Mutex<Option<LoggingConfig>> = Mutex::new(Some(LoggingConfig::default()));
 
+    /// Recent log records of the global logger, its capacity is set by `LoggingConfig`
+    pub static ref RING_BUFFER: ring::Buffer = ring::Buffer::new(0);
+
     /// Static global reference to the logger that will be accessible from all crates
     pub static ref LOGGER: GuardedLogger = {
         // Take the configuration data
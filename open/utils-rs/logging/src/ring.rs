@@ -0,0 +1,267 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! In-memory capture of the most recent log records so that they can be fetched from a running
+//! program (e.g. via API) without access to its log files. The records are captured
+//! independently of `RUST_LOG` filtering with per-module filter that can be changed at runtime.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+use slog::{Drain, Level, Never, OwnedKVList};
+
+/// Captured log record
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub time: SystemTime,
+    pub level: Level,
+    /// Module path where the record has been logged
+    pub module: String,
+    pub message: String,
+}
+
+/// Minimal levels of captured records for individual modules
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    /// Level of modules without their own filter
+    default: Level,
+    /// Module path prefixes with their levels
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    pub fn new(default: Level) -> Self {
+        Self {
+            default,
+            modules: vec![],
+        }
+    }
+
+    /// Parse filter in the same format as `RUST_LOG`, e.g. `info,bosminer::client=debug`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parse_level =
+            |level: &str| Level::from_str(level).map_err(|_| format!("invalid level '{}'", level));
+
+        let mut filter = Self::new(Level::Info);
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let module = parts.next().expect("BUG: missing directive").trim();
+            match parts.next() {
+                Some(level) => filter.set_module(module, parse_level(level.trim())?),
+                None => filter.default = parse_level(module)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Set level of `module` and all its submodules
+    pub fn set_module(&mut self, module: &str, level: Level) {
+        match self.modules.iter_mut().find(|(prefix, _)| prefix == module) {
+            Some((_, current)) => *current = level,
+            None => self.modules.push((module.to_string(), level)),
+        }
+    }
+
+    /// Level of the most specific filter matching `module`
+    fn level(&self, module: &str) -> Level {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| {
+                module.starts_with(prefix.as_str())
+                    && (module.len() == prefix.len() || module[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    pub fn is_enabled(&self, module: &str, level: Level) -> bool {
+        level.is_at_least(self.level(module))
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in self.modules.iter() {
+            write!(f, ",{}={}", module, level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer with the last captured records
+pub struct Buffer {
+    records: Mutex<VecDeque<Record>>,
+    /// Maximal number of records, nothing is captured when it's 0
+    capacity: Mutex<usize>,
+    filter: RwLock<Filter>,
+}
+
+impl Buffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: Mutex::new(capacity),
+            filter: RwLock::new(Filter::new(Level::Info)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        *self
+            .capacity
+            .lock()
+            .expect("BUG: lock ring buffer capacity")
+    }
+
+    /// Change the number of kept records (the oldest records are dropped when it's decreased)
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut records = self.records.lock().expect("BUG: lock ring buffer");
+        *self
+            .capacity
+            .lock()
+            .expect("BUG: lock ring buffer capacity") = capacity;
+        while records.len() > capacity {
+            records.pop_front();
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+            .read()
+            .expect("BUG: lock ring buffer filter")
+            .clone()
+    }
+
+    pub fn set_filter(&self, filter: Filter) {
+        *self.filter.write().expect("BUG: lock ring buffer filter") = filter;
+    }
+
+    /// Capture the record when it passes the filter
+    pub fn push(&self, record: Record) {
+        if !self
+            .filter
+            .read()
+            .expect("BUG: lock ring buffer filter")
+            .is_enabled(&record.module, record.level)
+        {
+            return;
+        }
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().expect("BUG: lock ring buffer");
+        if records.len() >= capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// The last `count` records (or all of them) ordered from the oldest one
+    pub fn recent(&self, count: Option<usize>) -> Vec<Record> {
+        let records = self.records.lock().expect("BUG: lock ring buffer");
+        let skip = count
+            .map(|count| records.len().saturating_sub(count))
+            .unwrap_or(0);
+        records.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// Drain capturing records into the global `RING_BUFFER`
+pub struct RingDrain;
+
+impl Drain for RingDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &slog::Record, _values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        crate::RING_BUFFER.push(Record {
+            time: SystemTime::now(),
+            level: record.level(),
+            module: record.module().to_string(),
+            message: record.msg().to_string(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(module: &str, level: Level, message: &str) -> Record {
+        Record {
+            time: SystemTime::now(),
+            level,
+            module: module.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, bosminer=info ,bosminer::client=trace").unwrap();
+        assert_eq!(
+            filter.to_string(),
+            "warn,bosminer=info,bosminer::client=trace"
+        );
+        assert!(filter.is_enabled("bosminer::client::stratum_v2", Level::Trace));
+        assert!(!filter.is_enabled("bosminer::hub", Level::Debug));
+        assert!(filter.is_enabled("bosminer", Level::Info));
+        // prefix has to match whole module name
+        assert!(!filter.is_enabled("bosminer_am1_s9", Level::Info));
+        assert!(filter.is_enabled("bosminer_am1_s9", Level::Warning));
+
+        assert!(Filter::parse("bosminer=loud").is_err());
+        assert_eq!(Filter::parse("").unwrap(), Filter::new(Level::Info));
+    }
+
+    #[test]
+    fn test_buffer() {
+        let buffer = Buffer::new(3);
+        buffer.set_filter(Filter::parse("info,noisy=error").unwrap());
+        for i in 0..5 {
+            buffer.push(record("app", Level::Info, &i.to_string()));
+        }
+        buffer.push(record("app", Level::Debug, "filtered"));
+        buffer.push(record("noisy::module", Level::Info, "filtered"));
+
+        let messages = |records: Vec<Record>| {
+            records
+                .into_iter()
+                .map(|record| record.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(buffer.recent(None)), vec!["2", "3", "4"]);
+        assert_eq!(messages(buffer.recent(Some(1))), vec!["4"]);
+
+        buffer.set_capacity(2);
+        assert_eq!(messages(buffer.recent(None)), vec!["3", "4"]);
+        buffer.set_capacity(0);
+        buffer.push(record("app", Level::Info, "5"));
+        assert!(buffer.recent(None).is_empty());
+    }
+}
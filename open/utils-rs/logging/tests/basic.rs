@@ -47,6 +47,7 @@ fn test_logging_basic() {
         target: LoggingTarget::File(temp_file.path().into()),
         level: Level::Trace,
         drain_channel_size: LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+        ring_buffer_size: 0,
     };
 
     // Setup logger
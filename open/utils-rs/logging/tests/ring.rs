@@ -0,0 +1,61 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Test of capturing log records into the ring buffer.
+//!
+//! **Warning**: Each logging test needs to be in a separate files
+//! due to global LOGGER initialization
+
+use std::env;
+
+use ii_logging::macros::*;
+use ii_logging::ring::Filter;
+use ii_logging::{self, Level, LoggingConfig, LoggingTarget, LOGGER, RING_BUFFER};
+
+#[test]
+fn test_logging_ring() {
+    // records are captured regardless of RUST_LOG
+    env::set_var("RUST_LOG", "error");
+
+    let config = LoggingConfig {
+        target: LoggingTarget::Stderr,
+        level: Level::Error,
+        drain_channel_size: LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+        ring_buffer_size: 2,
+    };
+    ii_logging::set_logger_config(config);
+    let flush_guard = LOGGER.take_guard();
+    RING_BUFFER.set_filter(Filter::parse("info,ring=debug").expect("invalid filter"));
+
+    for i in 0..3 {
+        debug!("record {}", i);
+    }
+    trace!("filtered");
+    drop(flush_guard);
+
+    let messages: Vec<_> = RING_BUFFER
+        .recent(None)
+        .into_iter()
+        .map(|record| record.message)
+        .collect();
+    assert_eq!(messages, vec!["record 1", "record 2"]);
+}
@@ -20,80 +20,332 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+//! Generate register access crate from SVD description(s) in build script. The generated code is
+//! placed into `src/` which is recreated only when any input changes.
+//!
+//! Multiple SVD files (e.g. register maps of different FPGA bitstream variants) are generated into
+//! separate modules, each of them optionally gated by a cargo feature.
+
+use std::collections::hash_map::DefaultHasher;
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::process::Command;
 
 // source dir. will be removed and recreated with generated source files
 const SRC_DIR: &'static str = "src";
 
-pub fn run(input_path: String) -> std::io::Result<()> {
-    let current_dir = env::current_dir()?;
+/// File in source dir. with fingerprint of inputs the code has been generated from
+const FINGERPRINT_FILE: &'static str = ".svd-build-fingerprint";
 
-    // clear up existing src/
-    if Path::new(SRC_DIR).is_dir() {
-        fs::remove_dir_all(SRC_DIR).map_err(|err| {
-            std::io::Error::new(
-                err.kind(),
-                format!("removing {}: {}", SRC_DIR, err.description()),
-            )
-        })?;
+/// SVD file generated into its own module
+#[derive(Debug, Clone, Hash)]
+pub struct Input {
+    /// Path to SVD file
+    pub path: String,
+    /// Name of the generated module
+    pub module: String,
+    /// Cargo feature which enables the module, the module is always compiled when it's `None`
+    pub feature: Option<String>,
+}
+
+impl Input {
+    pub fn new(path: &str, module: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            module: module.to_string(),
+            feature: None,
+        }
+    }
+
+    pub fn with_feature(mut self, feature: &str) -> Self {
+        self.feature = Some(feature.to_string());
+        self
     }
+}
 
-    fs::create_dir(SRC_DIR).map_err(|err| {
+fn other_error(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, message)
+}
+
+fn read_input(input_path: &str) -> std::io::Result<String> {
+    fs::read_to_string(input_path).map_err(|err| {
         std::io::Error::new(
             err.kind(),
-            format!("recreating {}: {}", SRC_DIR, err.description()),
+            format!("reading {}: {}", input_path, err.description()),
         )
-    })?;
+    })
+}
 
-    // create code as single line blob
-    let input = fs::read_to_string(&input_path).map_err(|err| {
+/// Fingerprint of inputs including layout of generated modules
+fn fingerprint<T: Hash>(layout: &T, contents: &[String]) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    layout.hash(&mut hasher);
+    contents.hash(&mut hasher);
+    format!("{:016x}\n", hasher.finish())
+}
+
+/// Code generated from the same inputs is already present in `src_dir`
+fn is_up_to_date(src_dir: &Path, fingerprint: &str) -> bool {
+    src_dir.join("lib.rs").is_file()
+        && fs::read_to_string(src_dir.join(FINGERPRINT_FILE))
+            .map(|stored| stored == fingerprint)
+            .unwrap_or(false)
+}
+
+fn recreate_dir(dir: &Path) -> std::io::Result<()> {
+    // clear up existing directory
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("removing {}: {}", dir.display(), err.description()),
+            )
+        })?;
+    }
+
+    fs::create_dir(dir).map_err(|err| {
         std::io::Error::new(
             err.kind(),
-            format!("reading {}: {}", input_path, err.description()),
+            format!("recreating {}: {}", dir.display(), err.description()),
         )
-    })?;
+    })
+}
 
+/// Generate code from SVD `input` and split it to files in `dir` (the root file is `lib.rs`)
+fn generate(input_path: &str, input: &str, dir: &Path) -> std::io::Result<()> {
     // NOTE: svd2rust panics on most failures anyways
-    let out = svd2rust::generate(input.as_str(), svd2rust::Target::None, false).map_err(|err| {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("generating code from {}: {:?}", input_path, err),
-        )
-    })?;
+    let out = svd2rust::generate(input, svd2rust::Target::None, false)
+        .map_err(|err| other_error(format!("generating code from {}: {:?}", input_path, err)))?;
 
     // split code blob to files
-    form::create_directory_structure(Path::new(&current_dir).join(SRC_DIR), out.lib_rs).map_err(
-        |err| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("formating code in {}: {}", SRC_DIR, err),
-            )
-        },
-    )?;
+    form::create_directory_structure(dir, out.lib_rs)
+        .map_err(|err| other_error(format!("formating code in {}: {}", dir.display(), err)))
+}
 
-    // reformat
+fn rustfmt(path: &Path) -> std::io::Result<()> {
+    // submodules are formatted as well
     let out = Command::new("rustfmt")
-        .arg(Path::new(&current_dir).join(SRC_DIR).join("lib.rs"))
+        .arg(path)
         .status()
-        .map_err(|err| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("reformating files in {}: {}", SRC_DIR, err),
-            )
-        })?;
+        .map_err(|err| other_error(format!("reformating files in {}: {}", SRC_DIR, err)))?;
 
     if !out.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "rustfmt failed",
-        ));
+        return Err(other_error("rustfmt failed".to_string()));
     }
+    Ok(())
+}
 
-    // rebuild lib.rs only if fpga-io.xml is changed
-    print!("cargo:rerun-if-changed={}", input_path);
+/// Generate code for one SVD file directly into the crate root
+pub fn run(input_path: String) -> std::io::Result<()> {
+    let current_dir = env::current_dir()?;
+    let src_dir = current_dir.join(SRC_DIR);
+
+    // rebuild lib.rs only if the input is changed
+    println!("cargo:rerun-if-changed={}", input_path);
+
+    // create code as single line blob
+    let input = read_input(&input_path)?;
+    let fingerprint = fingerprint(&input_path, &[input.clone()]);
+    if is_up_to_date(&src_dir, &fingerprint) {
+        return Ok(());
+    }
+
+    recreate_dir(&src_dir)?;
+    generate(&input_path, &input, &src_dir)?;
+    rustfmt(&src_dir.join("lib.rs"))?;
+    fs::write(src_dir.join(FINGERPRINT_FILE), fingerprint)
+}
+
+/// Root of the crate which declares generated modules. It takes over crate-level attributes
+/// which are stripped from the modules (see `relocate_code()`).
+fn render_lib_rs(inputs: &[Input]) -> String {
+    let mut lib_rs = String::from(
+        "//! Generated by svd-build, do not edit\n\
+         #![no_std]\n\
+         #![allow(non_camel_case_types)]\n",
+    );
+    for input in inputs {
+        lib_rs.push('\n');
+        if let Some(feature) = input.feature.as_ref() {
+            lib_rs.push_str(&format!("#[cfg(feature = \"{}\")]\n", feature));
+        }
+        lib_rs.push_str(&format!("pub mod {};\n", input.module));
+    }
+    lib_rs
+}
+
+/// Code generated by svd2rust assumes it is the root of the crate: it starts with crate-level
+/// inner attributes and refers to its items (including the `generic` module with `R`, `W`,
+/// `Reg`, ...) by absolute `crate::` paths. Inner attributes are dropped from the root of the
+/// `module` (`is_root`) and the paths are redirected into the module.
+fn relocate_code(code: &str, module: &str, is_root: bool) -> String {
+    let module_path = format!("crate::{}::", module);
+    code.lines()
+        .filter(|line| !(is_root && line.trim_start().starts_with("#![")))
+        .map(|line| line.replace("crate::", &module_path))
+        .fold(String::new(), |mut code, line| {
+            code.push_str(&line);
+            code.push('\n');
+            code
+        })
+}
+
+/// Relocate all files generated into `dir` which is (a subdirectory of) `module_dir`
+fn relocate_dir(dir: &Path, module_dir: &Path, module: &str) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            relocate_dir(&path, module_dir, module)?;
+        } else if path
+            .extension()
+            .map_or(false, |extension| extension == "rs")
+        {
+            let is_root = path == module_dir.join("mod.rs");
+            let code = fs::read_to_string(&path)?;
+            fs::write(&path, relocate_code(&code, module, is_root))?;
+        }
+    }
     Ok(())
 }
+
+/// Generate code for multiple SVD files, each of them into its own (feature-gated) module.
+///
+/// NOTE: every module contains its own `Peripherals` singleton, so modules of variants that are
+/// never used together are usually gated by mutually exclusive features.
+pub fn run_multiple(inputs: Vec<Input>) -> std::io::Result<()> {
+    let current_dir = env::current_dir()?;
+
+    for input in inputs.iter() {
+        println!("cargo:rerun-if-changed={}", input.path);
+    }
+
+    generate_multiple(&current_dir.join(SRC_DIR), &inputs)
+}
+
+fn generate_multiple(src_dir: &Path, inputs: &[Input]) -> std::io::Result<()> {
+    let contents = inputs
+        .iter()
+        .map(|input| read_input(&input.path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let fingerprint = fingerprint(&inputs, &contents);
+    if is_up_to_date(src_dir, &fingerprint) {
+        return Ok(());
+    }
+
+    recreate_dir(src_dir)?;
+    for (input, content) in inputs.iter().zip(contents.iter()) {
+        let module_dir = src_dir.join(&input.module);
+        fs::create_dir(&module_dir)?;
+        generate(&input.path, content, &module_dir)?;
+        // generated root of the module is not the root of the crate
+        fs::rename(module_dir.join("lib.rs"), module_dir.join("mod.rs"))?;
+    }
+    fs::write(src_dir.join("lib.rs"), render_lib_rs(&inputs))?;
+    // absolute paths are rewritten in formatted code and formatted again as they get longer
+    rustfmt(&src_dir.join("lib.rs"))?;
+    for input in inputs.iter() {
+        let module_dir = src_dir.join(&input.module);
+        relocate_dir(&module_dir, &module_dir, &input.module)?;
+    }
+    rustfmt(&src_dir.join("lib.rs"))?;
+    fs::write(src_dir.join(FINGERPRINT_FILE), fingerprint)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_lib_rs() {
+        let inputs = vec![
+            Input::new("s9.svd", "s9").with_feature("s9"),
+            Input::new("common.svd", "common"),
+        ];
+        assert_eq!(
+            render_lib_rs(&inputs),
+            "//! Generated by svd-build, do not edit\n#![no_std]\n\
+             #![allow(non_camel_case_types)]\n\n\
+             #[cfg(feature = \"s9\")]\npub mod s9;\n\n\
+             pub mod common;\n"
+        );
+    }
+
+    #[test]
+    fn test_relocate_code() {
+        let code = "#![doc = \"S9 [here]\"]\n#![no_std]\nuse generic::*;\n\
+                    pub type R = crate::R<u32, super::CTRL>;\n";
+        assert_eq!(
+            relocate_code(code, "s9", true),
+            "use generic::*;\npub type R = crate::s9::R<u32, super::CTRL>;\n"
+        );
+        // only the root of the module contains crate-level attributes
+        assert_eq!(
+            relocate_code(
+                "#![allow(unused)]\nimpl crate::Readable for CTRL {}\n",
+                "s9",
+                false
+            ),
+            "#![allow(unused)]\nimpl crate::s9::Readable for CTRL {}\n"
+        );
+    }
+
+    /// Generate the S9 register map twice (as two modules of one crate) and check the result
+    /// with cargo
+    #[test]
+    fn test_generate_multiple_compiles() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let svd = manifest_dir
+            .join("../../hw/zynq-io-am1-s9/fpga-io/map.svd")
+            .to_string_lossy()
+            .to_string();
+        let crate_dir = env::temp_dir().join(format!("svd-build-test-{}", std::process::id()));
+        recreate_dir(&crate_dir).expect("BUG: cannot create test crate");
+        fs::write(
+            crate_dir.join("Cargo.toml"),
+            "[package]\nname = \"svd-build-test\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n\
+             [dependencies]\nbare-metal = \"0.2.0\"\nvcell = \"0.1.0\"\n\n\
+             [features]\nsecond = []\n\n[workspace]\n",
+        )
+        .expect("BUG: cannot write test manifest");
+
+        let inputs = vec![
+            Input::new(&svd, "first"),
+            Input::new(&svd, "second").with_feature("second"),
+        ];
+        generate_multiple(&crate_dir.join(SRC_DIR), &inputs).expect("BUG: generating failed");
+        let mod_rs = fs::read_to_string(crate_dir.join(SRC_DIR).join("first/mod.rs"))
+            .expect("BUG: missing module root");
+        assert!(!mod_rs.contains("#!["));
+
+        let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .args(&["check", "--quiet", "--all-features", "--manifest-path"])
+            .arg(crate_dir.join("Cargo.toml"))
+            .status()
+            .expect("BUG: cannot run cargo");
+        fs::remove_dir_all(&crate_dir).expect("BUG: cannot remove test crate");
+        assert!(status.success(), "generated code doesn't compile");
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let inputs = vec![Input::new("s9.svd", "s9")];
+        let content = vec!["<device/>".to_string()];
+        assert_eq!(
+            fingerprint(&inputs, &content),
+            fingerprint(&inputs, &content)
+        );
+        assert_ne!(
+            fingerprint(&inputs, &content),
+            fingerprint(&inputs, &["<device></device>".to_string()])
+        );
+        let gated = vec![Input::new("s9.svd", "s9").with_feature("s9")];
+        assert_ne!(
+            fingerprint(&inputs, &content),
+            fingerprint(&gated, &content)
+        );
+    }
+}
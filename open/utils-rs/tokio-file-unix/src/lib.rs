@@ -11,12 +11,15 @@
 //! `futures::Stream`.
 
 use bytes::{BufMut, BytesMut};
-use std::cell::RefCell;
+use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::{fs, io};
 
 use ii_async_compat::prelude::*;
-use tokio::io::PollEvented;
+use tokio::io::unix::AsyncFd;
+use tokio::io::ReadBuf;
 use tokio_util::codec::{Decoder, Encoder};
 
 unsafe fn dupe_file_from_fd(old_fd: RawFd) -> io::Result<fs::File> {
@@ -110,8 +113,8 @@ impl<'a> io::Write for StdFile<io::StderrLock<'a>> {
     }
 }
 
-/// Used to wrap file-like objects so they can be used with
-/// `tokio_core::reactor::PollEvented`.
+/// Used to wrap file-like objects so they can be registered with the tokio
+/// reactor via `File::into_io`.
 ///
 /// Normally, you should use `File::new_nb` rather than `File::raw_new` unless
 /// the underlying file descriptor has already been set to nonblocking mode.
@@ -121,11 +124,6 @@ impl<'a> io::Write for StdFile<io::StderrLock<'a>> {
 /// Wrapping regular files has no effect because they do not support
 /// nonblocking mode.
 ///
-/// ```ignore
-/// impl Evented for File<std::fs::File>;
-/// impl Evented for File<impl AsRawFd>;
-/// ```
-///
 /// ## Example: unsafe creation from raw file descriptor
 ///
 /// To unsafely create `File<F>` from a raw file descriptor `fd`, you can do
@@ -148,18 +146,11 @@ impl<'a> io::Write for StdFile<io::StderrLock<'a>> {
 #[derive(Debug)]
 pub struct File<F> {
     file: F,
-    evented: RefCell<Option<mio::Registration>>,
 }
 
 impl<F: AsRawFd> File<F> {
-    /// Wraps a file-like object so it can be used with
-    /// `tokio_core::reactor::PollEvented`, and also *enables nonblocking
-    /// mode* on the underlying file descriptor.
-    ///
-    /// ```ignore
-    /// fn new_nb(std::fs::File) -> Result<impl Evented + Read + Write>;
-    /// fn new_nb(impl AsRawFd) -> Result<impl Evented>;
-    /// ```
+    /// Wraps a file-like object so it can be converted into `AsyncFile`, and
+    /// also *enables nonblocking mode* on the underlying file descriptor.
     pub fn new_nb(file: F) -> io::Result<Self> {
         let file = File::raw_new(file);
         file.set_nonblocking(true)?;
@@ -205,16 +196,31 @@ impl<F: AsRawFd> File<F> {
         }
     }
 
-    /// Converts into a pollable object that supports `tokio_io::AsyncRead`
-    /// and `tokio_io::AsyncWrite`, making it suitable for `tokio_io::io::*`.
+    /// Converts into a pollable object that supports `tokio::io::AsyncRead`
+    /// and `tokio::io::AsyncWrite`, making it suitable for `tokio::io::*`.
+    ///
+    /// Must be called from within the context of a tokio runtime.
     ///
     /// ```ignore
-    /// fn into_io(File<std::fs::File>, &Handle) -> Result<impl AsyncRead + AsyncWrite>;
-    /// fn into_io(File<impl AsRawFd + Read>, &Handle) -> Result<impl AsyncRead>;
-    /// fn into_io(File<impl AsRawFd + Write>, &Handle) -> Result<impl AsyncWrite>;
+    /// fn into_io(File<std::fs::File>) -> Result<impl AsyncRead + AsyncWrite>;
+    /// fn into_io(File<impl AsRawFd + Read>) -> Result<impl AsyncRead>;
+    /// fn into_io(File<impl AsRawFd + Write>) -> Result<impl AsyncWrite>;
     /// ```
-    pub fn into_io(self) -> io::Result<PollEvented<Self>> {
-        PollEvented::new(self)
+    pub fn into_io(self) -> io::Result<AsyncFile<F>> {
+        match AsyncFd::try_new(self) {
+            Ok(file) => Ok(AsyncFile::Evented(file)),
+            Err(e) => match e.into_parts() {
+                // this is a workaround for regular files, which are not
+                // supported by epoll; they would instead cause EPERM upon
+                // registration. Regular files never block, so they are used
+                // directly without the reactor.
+                (file, ref e) if e.raw_os_error() == Some(libc::EPERM) => {
+                    file.set_nonblocking(false)?;
+                    Ok(AsyncFile::Regular(file))
+                }
+                (_, e) => Err(e),
+            },
+        }
     }
 }
 
@@ -224,10 +230,7 @@ impl<F> File<F> {
     /// you are certain that the underlying file descriptor is already in
     /// nonblocking mode.
     pub fn raw_new(file: F) -> Self {
-        File {
-            file: file,
-            evented: Default::default(),
-        }
+        File { file: file }
     }
 }
 
@@ -237,55 +240,6 @@ impl<F: AsRawFd> AsRawFd for File<F> {
     }
 }
 
-impl<F: AsRawFd> mio::Evented for File<F> {
-    fn register(
-        &self,
-        poll: &mio::Poll,
-        token: mio::Token,
-        interest: mio::Ready,
-        opts: mio::PollOpt,
-    ) -> io::Result<()> {
-        match mio::unix::EventedFd(&self.as_raw_fd()).register(poll, token, interest, opts) {
-            // this is a workaround for regular files, which are not supported
-            // by epoll; they would instead cause EPERM upon registration
-            Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => {
-                self.set_nonblocking(false)?;
-                // workaround: PollEvented/IoToken always starts off in the
-                // "not ready" state so we have to use a real Evented object
-                // to set its readiness state
-                let (r, s) = mio::Registration::new2();
-                r.register(poll, token, interest, opts)?;
-                s.set_readiness(mio::Ready::readable() | mio::Ready::writable())?;
-                *self.evented.borrow_mut() = Some(r);
-                Ok(())
-            }
-            e => e,
-        }
-    }
-
-    fn reregister(
-        &self,
-        poll: &mio::Poll,
-        token: mio::Token,
-        interest: mio::Ready,
-        opts: mio::PollOpt,
-    ) -> io::Result<()> {
-        match &*self.evented.borrow() {
-            &None => {
-                mio::unix::EventedFd(&self.as_raw_fd()).reregister(poll, token, interest, opts)
-            }
-            &Some(ref r) => r.reregister(poll, token, interest, opts),
-        }
-    }
-
-    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
-        match &*self.evented.borrow() {
-            &None => mio::unix::EventedFd(&self.as_raw_fd()).deregister(poll),
-            &Some(ref r) => mio::Evented::deregister(r, poll),
-        }
-    }
-}
-
 impl<F: io::Read> io::Read for File<F> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.file.read(buf)
@@ -308,6 +262,94 @@ impl<F: io::Seek> io::Seek for File<F> {
     }
 }
 
+/// Asynchronous counterpart of `File` that is returned by `File::into_io`.
+///
+/// ```ignore
+/// impl AsyncRead + AsyncWrite for AsyncFile<std::fs::File>;
+/// impl AsyncRead for AsyncFile<impl AsRawFd + Read>;
+/// impl AsyncWrite for AsyncFile<impl AsRawFd + Write>;
+/// ```
+#[derive(Debug)]
+pub enum AsyncFile<F: AsRawFd> {
+    /// File registered with the tokio reactor
+    Evented(AsyncFd<File<F>>),
+    /// Regular file that does not support nonblocking mode
+    Regular(File<F>),
+}
+
+impl<F: AsRawFd> AsyncFile<F> {
+    /// Returns a shared reference to the underlying file
+    pub fn get_ref(&self) -> &File<F> {
+        match self {
+            AsyncFile::Evented(file) => file.get_ref(),
+            AsyncFile::Regular(file) => file,
+        }
+    }
+
+    /// Returns a mutable reference to the underlying file
+    pub fn get_mut(&mut self) -> &mut File<F> {
+        match self {
+            AsyncFile::Evented(file) => file.get_mut(),
+            AsyncFile::Regular(file) => file,
+        }
+    }
+}
+
+impl<F: AsRawFd + Read + Unpin> AsyncRead for AsyncFile<F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncFile::Evented(file) => loop {
+                let mut guard = futures::ready!(file.poll_read_ready_mut(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                match guard.try_io(|file| file.get_mut().read(unfilled)) {
+                    Ok(result) => {
+                        buf.advance(result?);
+                        return Poll::Ready(Ok(()));
+                    }
+                    // readiness has been cleared, wait for another event
+                    Err(_would_block) => continue,
+                }
+            },
+            AsyncFile::Regular(file) => {
+                let n = file.read(buf.initialize_unfilled())?;
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl<F: AsRawFd + Write + Unpin> AsyncWrite for AsyncFile<F> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AsyncFile::Evented(file) => loop {
+                let mut guard = futures::ready!(file.poll_write_ready_mut(cx))?;
+                match guard.try_io(|file| file.get_mut().write(buf)) {
+                    Ok(result) => return Poll::Ready(result),
+                    Err(_would_block) => continue,
+                }
+            },
+            AsyncFile::Regular(file) => Poll::Ready(file.write(buf)),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().get_mut().flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
 /// A `Codec` that splits the stream into frames divided by a given delimiter
 /// byte.  All frames except possibly the last one contain the delimiter byte
 /// as the last element (this behavior differs from `tokio_io::io::lines`).
@@ -342,11 +384,10 @@ impl<D: Into<u8> + Clone> Decoder for DelimCodec<D> {
     }
 }
 
-impl<D: Into<u8> + Clone> Encoder for DelimCodec<D> {
-    type Item = Vec<u8>;
+impl<D: Into<u8> + Clone> Encoder<Vec<u8>> for DelimCodec<D> {
     type Error = io::Error;
 
-    fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: Vec<u8>, buf: &mut BytesMut) -> Result<(), Self::Error> {
         buf.extend(msg);
         buf.put_u8(self.0.clone().into());
         Ok(())